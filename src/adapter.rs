@@ -0,0 +1,182 @@
+/// Which backends and GPU a `State` is created on.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AdapterOptions {
+    /// Backends to choose from; None for the platform's usual ones.
+    pub backends: Option<wgpu::Backends>,
+    /// An index into `list_adapters`, or part of an adapter's name in any
+    /// case. None leaves the choice to wgpu and `power_preference`, as
+    /// does the web, where adapters cannot be listed.
+    pub adapter: Option<String>,
+    pub power_preference: wgpu::PowerPreference,
+    /// A directory to record a wgpu API trace of the device into, for
+    /// replaying a bug with wgpu's player. Only builds with the `trace`
+    /// feature can, and only on native.
+    pub trace: Option<std::path::PathBuf>,
+}
+
+impl AdapterOptions {
+    /// Reads `--backend <names>`, `--adapter <index or name>`,
+    /// `--low-power` and `--trace <dir>` from command-line `args`. Backend
+    /// names are comma separated, from vulkan, dx12, metal, gl and webgpu.
+    pub fn from_args(args: &[String]) -> anyhow::Result<Self> {
+        let value = |flag: &str| {
+            args.iter()
+                .position(|arg| arg == flag)
+                .and_then(|i| args.get(i + 1))
+        };
+        let backends = match value("--backend") {
+            Some(names) => {
+                let mut backends = wgpu::Backends::empty();
+                for name in names.split(',') {
+                    let backend = wgpu::Backends::from_comma_list(name);
+                    if backend.is_empty() {
+                        anyhow::bail!(
+                            "Unknown backend {:?}; expected vulkan, dx12, metal, gl or webgpu",
+                            name.trim()
+                        );
+                    }
+                    backends |= backend;
+                }
+                Some(backends)
+            }
+            None => None,
+        };
+        let trace = value("--trace").map(std::path::PathBuf::from);
+        if trace.is_some() && !cfg!(all(feature = "trace", not(target_arch = "wasm32"))) {
+            anyhow::bail!("--trace needs a native build with the trace feature");
+        }
+        Ok(Self {
+            backends,
+            adapter: value("--adapter").cloned(),
+            power_preference: if args.iter().any(|arg| arg == "--low-power") {
+                wgpu::PowerPreference::LowPower
+            } else {
+                wgpu::PowerPreference::default()
+            },
+            trace,
+        })
+    }
+
+    /// The backends a windowed `State` picks from.
+    pub fn window_backends(&self) -> wgpu::Backends {
+        #[cfg(not(target_arch = "wasm32"))]
+        let default = wgpu::Backends::PRIMARY;
+        #[cfg(target_arch = "wasm32")]
+        let default = wgpu::Backends::BROWSER_WEBGPU | wgpu::Backends::GL;
+        self.backends.unwrap_or(default)
+    }
+
+    /// What the device records its API calls to.
+    pub(crate) fn device_trace(&self) -> anyhow::Result<wgpu::Trace> {
+        #[cfg(all(feature = "trace", not(target_arch = "wasm32")))]
+        if let Some(directory) = &self.trace {
+            // wgpu only creates the trace file, not the directory. It also
+            // logs that tracing was removed and does nothing, which is
+            // stale: wgpu-core still records it.
+            std::fs::create_dir_all(directory)?;
+            return Ok(wgpu::Trace::Directory(directory.clone()));
+        }
+        Ok(wgpu::Trace::Off)
+    }
+
+    /// The backends a headless `State` picks from.
+    pub fn headless_backends(&self) -> wgpu::Backends {
+        self.backends.unwrap_or(wgpu::Backends::all())
+    }
+
+    /// The adapter these options pick from `instance`, able to present to
+    /// `surface` if there is one.
+    pub(crate) async fn request_adapter(
+        &self,
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface<'_>>,
+    ) -> anyhow::Result<wgpu::Adapter> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(wanted) = &self.adapter {
+            // The instance itself is limited to the backends in use.
+            let adapters = instance.enumerate_adapters(wgpu::Backends::all());
+            let adapter = match wanted.parse::<usize>() {
+                Ok(index) => adapters.into_iter().nth(index),
+                Err(_) => {
+                    let wanted = wanted.to_lowercase();
+                    adapters
+                        .into_iter()
+                        .find(|adapter| adapter.get_info().name.to_lowercase().contains(&wanted))
+                }
+            };
+            let Some(adapter) = adapter else {
+                anyhow::bail!("No adapter matches {:?}; see --list-adapters", wanted);
+            };
+            if let Some(surface) = surface
+                && !adapter.is_surface_supported(surface)
+            {
+                anyhow::bail!(
+                    "{} cannot present to the window",
+                    describe(&adapter.get_info())
+                );
+            }
+            log::info!("Using {}", describe(&adapter.get_info()));
+            return Ok(adapter);
+        }
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: self.power_preference,
+                compatible_surface: surface,
+                force_fallback_adapter: false,
+            })
+            .await?;
+        log::info!("Using {}", describe(&adapter.get_info()));
+        Ok(adapter)
+    }
+}
+
+/// Every adapter on `backends`, in the order `AdapterOptions::adapter`
+/// indexes them when picking from the same backends.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn list_adapters(backends: wgpu::Backends) -> Vec<wgpu::AdapterInfo> {
+    wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends,
+        ..Default::default()
+    })
+    .enumerate_adapters(backends)
+    .iter()
+    .map(wgpu::Adapter::get_info)
+    .collect()
+}
+
+/// An adapter's name, backend and kind on one line.
+pub fn describe(info: &wgpu::AdapterInfo) -> String {
+    format!("{} ({:?}, {:?})", info.name, info.backend, info.device_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backends(names: &str) -> anyhow::Result<Option<wgpu::Backends>> {
+        let args = ["--backend", names].map(String::from);
+        AdapterOptions::from_args(&args).map(|options| options.backends)
+    }
+
+    #[test]
+    fn backend_names_are_comma_separated() {
+        assert_eq!(backends("vulkan").unwrap(), Some(wgpu::Backends::VULKAN));
+        assert_eq!(
+            backends("vulkan, gl").unwrap(),
+            Some(wgpu::Backends::VULKAN | wgpu::Backends::GL)
+        );
+        assert_eq!(AdapterOptions::from_args(&[]).unwrap().backends, None);
+    }
+
+    #[test]
+    fn unknown_backend_names_are_errors() {
+        assert_eq!(
+            backends("vulkan,vulcan").unwrap_err().to_string(),
+            "Unknown backend \"vulcan\"; expected vulkan, dx12, metal, gl or webgpu"
+        );
+        assert_eq!(
+            backends("gl,").unwrap_err().to_string(),
+            "Unknown backend \"\"; expected vulkan, dx12, metal, gl or webgpu"
+        );
+    }
+}
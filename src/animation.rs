@@ -0,0 +1,754 @@
+use cgmath::{InnerSpace, SquareMatrix, VectorSpace};
+use wgpu::util::DeviceExt;
+
+/// The joints and weights of one skinned vertex, bound alongside
+/// `ModelVertex` as its own buffer. Weights sum to one.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinVertex {
+    /// Indices into the joint palette, not into the skin's own joints.
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+impl SkinVertex {
+    /// Follows a single palette entry, as for rigid meshes in an animated
+    /// model.
+    pub fn rigid(entry: u32) -> Self {
+        Self {
+            joints: [entry, 0, 0, 0],
+            weights: [1.0, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+// Locations 5 to 11 are taken by `InstanceRaw`.
+crate::impl_vertex!(SkinVertex, Vertex {
+    joints => 12: Uint32x4,
+    weights => 13: Float32x4,
+});
+
+/// Translation, rotation and scale of a joint relative to its parent.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub translation: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.translation)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// `t` of the way from `self` to `other`.
+    pub fn blend(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: nlerp_short(self.rotation, other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+
+    /// `self` moved by `weight` times the change from `reference` to
+    /// `target`, as an additive layer does.
+    pub fn add(&self, reference: &Transform, target: &Transform, weight: f32) -> Transform {
+        let identity = cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0);
+        let rotation = nlerp_short(
+            identity,
+            target.rotation * reference.rotation.conjugate(),
+            weight,
+        );
+        let ratio = |target: f32, reference: f32| {
+            if reference.abs() > f32::EPSILON {
+                1.0 + (target / reference - 1.0) * weight
+            } else {
+                1.0
+            }
+        };
+        Transform {
+            translation: self.translation + (target.translation - reference.translation) * weight,
+            rotation: (rotation * self.rotation).normalize(),
+            scale: cgmath::Vector3::new(
+                self.scale.x * ratio(target.scale.x, reference.scale.x),
+                self.scale.y * ratio(target.scale.y, reference.scale.y),
+                self.scale.z * ratio(target.scale.z, reference.scale.z),
+            ),
+        }
+    }
+}
+
+/// Normalized lerp between rotations, taking the short way round.
+fn nlerp_short(
+    from: cgmath::Quaternion<f32>,
+    mut to: cgmath::Quaternion<f32>,
+    t: f32,
+) -> cgmath::Quaternion<f32> {
+    if from.dot(to) < 0.0 {
+        to = -to;
+    }
+    from.nlerp(to, t).normalize()
+}
+
+#[derive(Debug, Clone)]
+pub struct Joint {
+    pub name: String,
+    /// Always before this joint in `Skeleton::joints`.
+    pub parent: Option<usize>,
+    pub rest: Transform,
+}
+
+/// One matrix of the palette the vertex shader skins with.
+#[derive(Debug, Copy, Clone)]
+pub struct PaletteEntry {
+    pub joint: usize,
+    /// Takes a vertex from the mesh's space into the joint's, as it was when
+    /// the mesh was bound.
+    pub inverse_bind: cgmath::Matrix4<f32>,
+}
+
+/// The node hierarchy of an animated model.
+///
+/// The palette holds one entry per joint of every skin, followed by one per
+/// rigid mesh that animations can move; `SkinVertex::joints` index it.
+#[derive(Debug, Clone, Default)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    pub palette: Vec<PaletteEntry>,
+}
+
+impl Skeleton {
+    pub fn find_joint(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|joint| joint.name == name)
+    }
+
+    pub fn rest_pose(&self) -> Vec<Transform> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+
+    /// Each joint's transform relative to the model, for a local `pose`.
+    pub fn world_transforms(&self, pose: &[Transform]) -> Vec<cgmath::Matrix4<f32>> {
+        let mut world: Vec<cgmath::Matrix4<f32>> = Vec::with_capacity(self.joints.len());
+        for (joint, local) in self.joints.iter().zip(pose) {
+            let local = local.matrix();
+            world.push(match joint.parent {
+                Some(parent) => world[parent] * local,
+                None => local,
+            });
+        }
+        world
+    }
+
+    /// The matrices to upload to a `JointPalette` for `pose`.
+    pub fn palette(&self, pose: &[Transform]) -> Vec<cgmath::Matrix4<f32>> {
+        let world = self.world_transforms(pose);
+        self.palette
+            .iter()
+            .map(|entry| world[entry.joint] * entry.inverse_bind)
+            .collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+}
+
+/// Keyframe values for one joint property, one per entry of `Channel::times`.
+#[derive(Debug, Clone)]
+pub enum Keyframes {
+    Translation(Vec<cgmath::Vector3<f32>>),
+    Rotation(Vec<cgmath::Quaternion<f32>>),
+    Scale(Vec<cgmath::Vector3<f32>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub joint: usize,
+    pub interpolation: Interpolation,
+    /// In seconds, ascending.
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+impl Channel {
+    /// The keyframes around `time` and how far it is between them; holds
+    /// the first and last values outside the keyed range.
+    fn keys(&self, time: f32) -> (usize, usize, f32) {
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return (0, 0, 0.0);
+        }
+        if next == self.times.len() {
+            return (next - 1, next - 1, 0.0);
+        }
+        let (start, end) = (self.times[next - 1], self.times[next]);
+        let t = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => (time - start) / (end - start).max(f32::EPSILON),
+        };
+        (next - 1, next, t)
+    }
+
+    fn apply(&self, time: f32, transform: &mut Transform) {
+        if self.times.is_empty() {
+            return;
+        }
+        let (a, b, t) = self.keys(time);
+        match &self.keyframes {
+            Keyframes::Translation(values) => {
+                transform.translation = values[a].lerp(values[b], t);
+            }
+            Keyframes::Rotation(values) => {
+                // glTF keys rotations to be slerped; it takes the short way.
+                transform.rotation = values[a].slerp(values[b], t);
+            }
+            Keyframes::Scale(values) => {
+                transform.scale = values[a].lerp(values[b], t);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    /// Time of the last keyframe of any channel.
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    /// Overwrites the joints this clip animates in `pose` with their values
+    /// at `time`; the others keep theirs.
+    pub fn sample(&self, time: f32, pose: &mut [Transform]) {
+        for channel in &self.channels {
+            if let Some(transform) = pose.get_mut(channel.joint) {
+                channel.apply(time, transform);
+            }
+        }
+    }
+}
+
+/// A clip and how far into it a track of `AnimationPlayer` is.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Track {
+    pub clip: usize,
+    /// Seconds into the clip.
+    pub time: f32,
+}
+
+impl Track {
+    fn new(clip: usize) -> Self {
+        Self { clip, time: 0.0 }
+    }
+
+    /// Moves on by `dt` seconds; false once a clip that does not loop has
+    /// reached its end.
+    fn advance(&mut self, dt: f32, clips: &[AnimationClip], looping: bool) -> bool {
+        let Some(clip) = clips.get(self.clip) else {
+            return false;
+        };
+        self.time += dt;
+        if clip.duration <= 0.0 {
+            self.time = 0.0;
+        } else if looping {
+            self.time = self.time.rem_euclid(clip.duration);
+        } else if !(0.0..clip.duration).contains(&self.time) {
+            self.time = self.time.clamp(0.0, clip.duration);
+            return false;
+        }
+        true
+    }
+
+    /// `pose` overwritten with this track's clip where it animates.
+    fn sample(&self, clips: &[AnimationClip], pose: &mut [Transform]) {
+        if let Some(clip) = clips.get(self.clip) {
+            clip.sample(self.time, pose);
+        }
+    }
+}
+
+/// A clip whose change from its first frame is added on top of the pose,
+/// such as breathing over a walk.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdditiveLayer {
+    pub track: Track,
+    pub weight: f32,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Crossfade {
+    from: Track,
+    elapsed: f32,
+    duration: f32,
+}
+
+/// Plays a model's clips: one main clip, optionally blended with a second
+/// at a fixed weight, crossfading when the main clip changes, with any
+/// number of additive layers on top.
+#[derive(Debug, Clone)]
+pub struct AnimationPlayer {
+    current: Option<Track>,
+    blend: Option<(Track, f32)>,
+    fade: Option<Crossfade>,
+    layers: Vec<AdditiveLayer>,
+    speed: f32,
+    playing: bool,
+    looping: bool,
+}
+
+impl Default for AnimationPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnimationPlayer {
+    /// Stopped, with no clip selected; looping at normal speed once played.
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            blend: None,
+            fade: None,
+            layers: Vec::new(),
+            speed: 1.0,
+            playing: false,
+            looping: true,
+        }
+    }
+
+    /// Selects `clip` and plays it from the start, cutting off any
+    /// crossfade.
+    pub fn play(&mut self, clip: usize) {
+        self.current = Some(Track::new(clip));
+        self.fade = None;
+        self.playing = true;
+    }
+
+    /// Plays `clip` from the start, fading from the current pose to it over
+    /// `duration` seconds. Without a clip playing, or with no duration, this
+    /// is `play`. A crossfade started during another fades from the clip
+    /// that was fading in.
+    pub fn crossfade_to(&mut self, clip: usize, duration: f32) {
+        let from = self.current;
+        self.play(clip);
+        if let Some(from) = from.filter(|_| duration > 0.0) {
+            self.fade = Some(Crossfade {
+                from,
+                elapsed: 0.0,
+                duration,
+            });
+        }
+    }
+
+    /// How far the crossfade is to the current clip, from 0 to 1, while
+    /// one is running.
+    pub fn crossfade_progress(&self) -> Option<f32> {
+        self.fade
+            .map(|fade| (fade.elapsed / fade.duration).clamp(0.0, 1.0))
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn resume(&mut self) {
+        self.playing = self.current.is_some();
+    }
+
+    /// Deselects the clip, returning the model to its rest pose. The blend
+    /// and layers stay set up for the next clip.
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.fade = None;
+        self.playing = false;
+    }
+
+    pub fn clip(&self) -> Option<usize> {
+        self.current.map(|track| track.clip)
+    }
+
+    /// Seconds into the current clip.
+    pub fn time(&self) -> f32 {
+        self.current.map_or(0.0, |track| track.time)
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        if let Some(track) = &mut self.current {
+            track.time = time.max(0.0);
+        }
+    }
+
+    /// Mixes `clip` into the current one, `weight` of the way towards it;
+    /// both advance together. `None` removes the blend.
+    pub fn set_blend(&mut self, blend: Option<(usize, f32)>) {
+        self.blend = blend.map(|(clip, weight)| match self.blend {
+            Some((track, _)) if track.clip == clip => (track, weight),
+            _ => (Track::new(clip), weight),
+        });
+    }
+
+    /// The blended clip and its weight.
+    pub fn blend(&self) -> Option<(usize, f32)> {
+        self.blend.map(|(track, weight)| (track.clip, weight))
+    }
+
+    /// Starts `clip` as an additive layer at `weight`; returns its index
+    /// for `set_layer_weight` and `remove_layer`.
+    pub fn add_layer(&mut self, clip: usize, weight: f32) -> usize {
+        self.layers.push(AdditiveLayer {
+            track: Track::new(clip),
+            weight,
+        });
+        self.layers.len() - 1
+    }
+
+    pub fn set_layer_weight(&mut self, index: usize, weight: f32) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.weight = weight;
+        }
+    }
+
+    /// Removes a layer; those after it move down an index.
+    pub fn remove_layer(&mut self, index: usize) {
+        if index < self.layers.len() {
+            self.layers.remove(index);
+        }
+    }
+
+    pub fn layers(&self) -> &[AdditiveLayer] {
+        &self.layers
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn looping(&self) -> bool {
+        self.looping
+    }
+
+    /// A clip that does not loop stops on its last frame. Applies to every
+    /// track, but only the main clip ending stops the player.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    /// Moves every track on by `dt` seconds.
+    pub fn advance(&mut self, dt: f32, clips: &[AnimationClip]) {
+        if !self.playing || self.current.is_none() {
+            return;
+        }
+        let dt = dt * self.speed;
+        let looping = self.looping;
+        if let Some(track) = &mut self.current
+            && !track.advance(dt, clips, looping)
+        {
+            self.playing = false;
+        }
+        if let Some((track, _)) = &mut self.blend {
+            track.advance(dt, clips, looping);
+        }
+        for layer in &mut self.layers {
+            layer.track.advance(dt, clips, looping);
+        }
+        if let Some(fade) = &mut self.fade {
+            fade.from.advance(dt, clips, looping);
+            fade.elapsed += dt.abs();
+            if fade.elapsed >= fade.duration {
+                self.fade = None;
+            }
+        }
+    }
+
+    /// The local transform of every joint of `skeleton` at the playhead.
+    pub fn pose(&self, skeleton: &Skeleton, clips: &[AnimationClip]) -> Vec<Transform> {
+        let rest = skeleton.rest_pose();
+        let Some(current) = self.current else {
+            return rest;
+        };
+        let mut pose = self.blended(current, &rest, clips);
+        if let Some(fade) = self.fade {
+            let t = (fade.elapsed / fade.duration).clamp(0.0, 1.0);
+            let from = self.blended(fade.from, &rest, clips);
+            for (to, from) in pose.iter_mut().zip(&from) {
+                *to = from.blend(to, t);
+            }
+        }
+        for layer in &self.layers {
+            let Some(clip) = clips.get(layer.track.clip) else {
+                continue;
+            };
+            let mut reference = rest.clone();
+            clip.sample(0.0, &mut reference);
+            let mut target = rest.clone();
+            clip.sample(layer.track.time, &mut target);
+            for ((transform, reference), target) in pose.iter_mut().zip(&reference).zip(&target) {
+                *transform = transform.add(reference, target, layer.weight);
+            }
+        }
+        pose
+    }
+
+    /// `track` sampled over `rest` and mixed with the blend clip.
+    fn blended(&self, track: Track, rest: &[Transform], clips: &[AnimationClip]) -> Vec<Transform> {
+        let mut pose = rest.to_vec();
+        track.sample(clips, &mut pose);
+        if let Some((blend, weight)) = self.blend {
+            let mut other = rest.to_vec();
+            blend.sample(clips, &mut other);
+            for (transform, other) in pose.iter_mut().zip(&other) {
+                *transform = transform.blend(other, weight);
+            }
+        }
+        pose
+    }
+}
+
+/// Storage buffer of skinning matrices a model's vertex shaders read,
+/// rewritten every frame.
+///
+/// Models without a skeleton get a single identity matrix, so the bind
+/// groups that hold the buffer look the same either way.
+pub struct JointPalette {
+    buffer: wgpu::Buffer,
+    len: usize,
+    skinned: bool,
+}
+
+impl JointPalette {
+    pub fn new(device: &wgpu::Device, skeleton: Option<&Skeleton>) -> Self {
+        let matrices = match skeleton {
+            Some(skeleton) if !skeleton.palette.is_empty() => {
+                skeleton.palette(&skeleton.rest_pose())
+            }
+            _ => vec![cgmath::Matrix4::identity()],
+        };
+        let raw = matrices
+            .iter()
+            .map(|&m| m.into())
+            .collect::<Vec<[[f32; 4]; 4]>>();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("JointPalette::buffer"),
+            contents: bytemuck::cast_slice(&raw),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            buffer,
+            len: raw.len(),
+            skinned: skeleton.is_some(),
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether this was built for a skeleton, and so whether pipelines
+    /// should be compiled with `SKINNED`.
+    pub fn is_skinned(&self) -> bool {
+        self.skinned
+    }
+
+    /// Uploads `matrices`, ignoring any past the palette's length.
+    pub fn write(&self, queue: &wgpu::Queue, matrices: &[cgmath::Matrix4<f32>]) {
+        let raw = matrices
+            .iter()
+            .take(self.len)
+            .map(|&m| m.into())
+            .collect::<Vec<[[f32; 4]; 4]>>();
+        if !raw.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&raw));
+        }
+    }
+
+    /// Layout entry for the palette in a vertex stage bind group.
+    pub fn layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+
+    /// Defines for shaders skinning against this palette.
+    pub fn shader_defs(&self) -> crate::preprocess::ShaderDefs {
+        let defs = crate::preprocess::ShaderDefs::new();
+        if self.skinned {
+            defs.with_flag("SKINNED")
+        } else {
+            defs
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, One, Quaternion, Rotation3, Vector3};
+
+    use super::*;
+
+    fn channel(interpolation: Interpolation, keyframes: Keyframes) -> Channel {
+        Channel {
+            joint: 0,
+            interpolation,
+            times: vec![1.0, 2.0, 4.0],
+            keyframes,
+        }
+    }
+
+    fn translations() -> Keyframes {
+        Keyframes::Translation(vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(2.0, 4.0, 0.0),
+            Vector3::new(2.0, 0.0, 8.0),
+        ])
+    }
+
+    fn translation(channel: &Channel, time: f32) -> Vector3<f32> {
+        let mut transform = Transform::default();
+        channel.apply(time, &mut transform);
+        transform.translation
+    }
+
+    fn rotation(channel: &Channel, time: f32) -> Quaternion<f32> {
+        let mut transform = Transform::default();
+        channel.apply(time, &mut transform);
+        transform.rotation
+    }
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    /// The angle between two rotations, in degrees.
+    fn angle(a: Quaternion<f32>, b: Quaternion<f32>) -> f32 {
+        let difference = a.conjugate() * b;
+        (2.0 * difference.v.magnitude().atan2(difference.s.abs())).to_degrees()
+    }
+
+    #[test]
+    fn step_holds_each_keyframe_until_the_next() {
+        let channel = channel(Interpolation::Step, translations());
+        assert_near(translation(&channel, 1.0), Vector3::new(0.0, 0.0, 0.0));
+        assert_near(translation(&channel, 1.99), Vector3::new(0.0, 0.0, 0.0));
+        assert_near(translation(&channel, 2.0), Vector3::new(2.0, 4.0, 0.0));
+        assert_near(translation(&channel, 3.5), Vector3::new(2.0, 4.0, 0.0));
+        assert_near(translation(&channel, 4.0), Vector3::new(2.0, 0.0, 8.0));
+    }
+
+    #[test]
+    fn linear_interpolates_between_keyframes() {
+        let translate = channel(Interpolation::Linear, translations());
+        assert_near(translation(&translate, 2.0), Vector3::new(2.0, 4.0, 0.0));
+        assert_near(translation(&translate, 1.25), Vector3::new(0.5, 1.0, 0.0));
+        assert_near(translation(&translate, 3.0), Vector3::new(2.0, 2.0, 4.0));
+
+        let scales = Keyframes::Scale(vec![
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(3.0, 1.0, 1.0),
+            Vector3::new(3.0, 1.0, 1.0),
+        ]);
+        let scale = channel(Interpolation::Linear, scales);
+        let mut transform = Transform::default();
+        scale.apply(1.5, &mut transform);
+        assert_near(transform.scale, Vector3::new(2.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn holds_the_ends_outside_the_keyed_range() {
+        for interpolation in [Interpolation::Step, Interpolation::Linear] {
+            let channel = channel(interpolation, translations());
+            assert_near(translation(&channel, -5.0), Vector3::new(0.0, 0.0, 0.0));
+            assert_near(translation(&channel, 0.5), Vector3::new(0.0, 0.0, 0.0));
+            assert_near(translation(&channel, 4.5), Vector3::new(2.0, 0.0, 8.0));
+            assert_near(translation(&channel, 100.0), Vector3::new(2.0, 0.0, 8.0));
+        }
+    }
+
+    #[test]
+    fn slerps_rotations_at_a_constant_rate() {
+        let quarter = Quaternion::from_angle_y(Deg(90.0));
+        let channel = channel(
+            Interpolation::Linear,
+            Keyframes::Rotation(vec![Quaternion::one(), quarter, Quaternion::one()]),
+        );
+        let identity = Quaternion::one();
+        assert!(angle(rotation(&channel, 1.0), identity) < 1e-2);
+        assert!(angle(rotation(&channel, 2.0), quarter) < 1e-2);
+        // A normalized lerp would fall short of 22.5 degrees here.
+        assert!((angle(rotation(&channel, 1.25), identity) - 22.5).abs() < 1e-2);
+        assert!((angle(rotation(&channel, 1.5), identity) - 45.0).abs() < 1e-2);
+        assert!((angle(rotation(&channel, 3.0), identity) - 45.0).abs() < 1e-2);
+        assert!(angle(rotation(&channel, 10.0), identity) < 1e-2);
+    }
+
+    #[test]
+    fn slerp_takes_the_short_way_round() {
+        // The same rotation as the first, with the opposite sign.
+        let first = Quaternion::from_angle_y(Deg(10.0));
+        let second = -Quaternion::from_angle_y(Deg(30.0));
+        let channel = channel(
+            Interpolation::Linear,
+            Keyframes::Rotation(vec![first, second, second]),
+        );
+        let halfway = rotation(&channel, 1.5);
+        assert!(angle(halfway, Quaternion::from_angle_y(Deg(20.0))) < 1e-2);
+    }
+
+    #[test]
+    fn clips_only_overwrite_the_joints_they_animate() {
+        let clip = AnimationClip {
+            name: "move".to_owned(),
+            duration: 4.0,
+            channels: vec![Channel {
+                joint: 1,
+                ..channel(Interpolation::Linear, translations())
+            }],
+        };
+        let rest = Transform {
+            translation: Vector3::new(7.0, 7.0, 7.0),
+            ..Transform::default()
+        };
+        let mut pose = vec![rest; 2];
+        clip.sample(3.0, &mut pose);
+        assert_eq!(pose[0], rest);
+        assert_near(pose[1].translation, Vector3::new(2.0, 2.0, 4.0));
+        assert_eq!(pose[1].rotation, rest.rotation);
+    }
+}
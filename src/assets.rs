@@ -0,0 +1,304 @@
+use std::future::Future;
+use std::sync::mpsc;
+
+use crate::color::ColorSpace;
+use crate::mesh::primitives;
+use crate::{model, resources, texture};
+
+/// A texture `Assets::load_texture` started loading.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TextureHandle(pub usize);
+
+/// A model `Assets::load_model` started loading.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ModelHandle(pub usize);
+
+/// Either kind of handle, as `Assets::poll` reports them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AssetHandle {
+    Texture(TextureHandle),
+    Model(ModelHandle),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetStatus {
+    Loading,
+    Loaded,
+    /// The load's error, logged when it arrived. The placeholder stays.
+    Failed(String),
+}
+
+// A load's file and, once it arrives, what came of it.
+struct Entry<T, K> {
+    file_name: String,
+    kind: K,
+    // Everything the last load read, such as a model's materials and
+    // textures, for `reload_changed`.
+    files: Vec<String>,
+    value: Option<T>,
+    status: AssetStatus,
+}
+
+impl<T, K> Entry<T, K> {
+    fn new(file_name: &str, kind: K) -> Self {
+        Self {
+            file_name: file_name.to_string(),
+            kind,
+            files: vec![file_name.to_string()],
+            value: None,
+            status: AssetStatus::Loading,
+        }
+    }
+
+    fn finish(&mut self, result: anyhow::Result<T>, files: Vec<String>) {
+        // Only native loads know what they read.
+        if !files.is_empty() {
+            self.files = files;
+        }
+        match result {
+            Ok(value) => {
+                log::info!("Loaded {}", self.file_name);
+                self.value = Some(value);
+                self.status = AssetStatus::Loaded;
+            }
+            Err(e) => {
+                log::error!("Unable to load {}: {:#}", self.file_name, e);
+                self.status = AssetStatus::Failed(format!("{:#}", e));
+            }
+        }
+    }
+}
+
+enum Loaded {
+    Texture(TextureHandle, anyhow::Result<texture::Texture>),
+    Model(ModelHandle, anyhow::Result<model::Model>),
+}
+
+/// Textures and models from `res` loaded without blocking the frame.
+///
+/// Each load returns a handle at once and runs on a thread of its own, or
+/// as a task on the web, reading, decoding and uploading there through the
+/// shared device and queue. `poll` takes in the finished ones; until then,
+/// and for good if the load fails, a handle resolves to a placeholder: a
+/// gray texture, or a gray unit cube with bounds for culling and picking.
+///
+/// `reload_changed` loads assets again under the same handles, which keep
+/// resolving to the previous version until the new one arrives, and keep
+/// it if the new one fails.
+pub struct Assets {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    material_layout: wgpu::BindGroupLayout,
+    placeholder_texture: texture::Texture,
+    placeholder_model: model::Model,
+    textures: Vec<Entry<texture::Texture, ColorSpace>>,
+    models: Vec<Entry<model::Model, ()>>,
+    // Each load with the files it read.
+    sender: mpsc::Sender<(Loaded, Vec<String>)>,
+    receiver: mpsc::Receiver<(Loaded, Vec<String>)>,
+}
+
+impl Assets {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material_layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let placeholder_texture =
+            texture::Texture::from_color(device, queue, [128, 128, 128, 255], "placeholder")?;
+        let material = model::Material::new(
+            device,
+            "placeholder",
+            model::MaterialTextures::new(device, queue, placeholder_texture.clone())?,
+            model::MaterialFactors::default(),
+            material_layout,
+        );
+        let placeholder_model = primitives::cube(1.0).into_model(device, "placeholder", material);
+        let (sender, receiver) = mpsc::channel();
+        Ok(Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            material_layout: material_layout.clone(),
+            placeholder_texture,
+            placeholder_model,
+            textures: Vec::new(),
+            models: Vec::new(),
+            sender,
+            receiver,
+        })
+    }
+
+    /// Starts loading a color texture, `res/<file_name>`.
+    pub fn load_texture(&mut self, file_name: &str) -> TextureHandle {
+        self.start_texture(file_name, ColorSpace::Srgb)
+    }
+
+    /// Starts loading a tangent-space normal map, kept linear.
+    pub fn load_normal_texture(&mut self, file_name: &str) -> TextureHandle {
+        self.start_texture(file_name, ColorSpace::Linear)
+    }
+
+    /// Starts loading any texture tagged with the space its texels are in:
+    /// `Linear` for roughness, occlusion and other data maps.
+    pub fn load_texture_in(&mut self, file_name: &str, color_space: ColorSpace) -> TextureHandle {
+        self.start_texture(file_name, color_space)
+    }
+
+    /// Starts loading an OBJ or glTF model with its materials.
+    pub fn load_model(&mut self, file_name: &str) -> ModelHandle {
+        let handle = ModelHandle(self.models.len());
+        self.models.push(Entry::new(file_name, ()));
+        self.spawn_model(handle);
+        handle
+    }
+
+    fn start_texture(&mut self, file_name: &str, color_space: ColorSpace) -> TextureHandle {
+        let handle = TextureHandle(self.textures.len());
+        self.textures.push(Entry::new(file_name, color_space));
+        self.spawn_texture(handle);
+        handle
+    }
+
+    fn spawn_texture(&self, handle: TextureHandle) {
+        let entry = &self.textures[handle.0];
+        let color_space = entry.kind;
+        let (file_name, device, queue, _) = self.load_context(&entry.file_name);
+        spawn(self.sender.clone(), move || async move {
+            let result = resources::load_texture_in(&file_name, color_space, &device, &queue).await;
+            Loaded::Texture(handle, result)
+        });
+    }
+
+    fn spawn_model(&self, handle: ModelHandle) {
+        let (file_name, device, queue, layout) =
+            self.load_context(&self.models[handle.0].file_name);
+        spawn(self.sender.clone(), move || async move {
+            let result = resources::load_model(
+                &file_name,
+                &device,
+                &queue,
+                &layout,
+                model::ImportOptions::default(),
+            )
+            .await;
+            Loaded::Model(handle, result)
+        });
+    }
+
+    // What a load needs to own to run away from `self`.
+    fn load_context(
+        &self,
+        file_name: &str,
+    ) -> (String, wgpu::Device, wgpu::Queue, wgpu::BindGroupLayout) {
+        (
+            file_name.to_string(),
+            self.device.clone(),
+            self.queue.clone(),
+            self.material_layout.clone(),
+        )
+    }
+
+    /// Takes in the loads that finished since the last call, returning
+    /// their handles, whether they loaded or failed.
+    pub fn poll(&mut self) -> Vec<AssetHandle> {
+        let mut finished = Vec::new();
+        for (loaded, files) in self.receiver.try_iter() {
+            match loaded {
+                Loaded::Texture(handle, result) => {
+                    self.textures[handle.0].finish(result, files);
+                    finished.push(AssetHandle::Texture(handle));
+                }
+                Loaded::Model(handle, result) => {
+                    self.models[handle.0].finish(result, files);
+                    finished.push(AssetHandle::Model(handle));
+                }
+            }
+        }
+        finished
+    }
+
+    /// The texture, or the placeholder while it loads or if it failed.
+    pub fn texture(&self, handle: TextureHandle) -> &texture::Texture {
+        self.textures
+            .get(handle.0)
+            .and_then(|entry| entry.value.as_ref())
+            .unwrap_or(&self.placeholder_texture)
+    }
+
+    /// The model, or the placeholder cube while it loads or if it failed.
+    pub fn model(&self, handle: ModelHandle) -> &model::Model {
+        self.models
+            .get(handle.0)
+            .and_then(|entry| entry.value.as_ref())
+            .unwrap_or(&self.placeholder_model)
+    }
+
+    /// None for handles from another `Assets`.
+    pub fn status(&self, handle: AssetHandle) -> Option<&AssetStatus> {
+        match handle {
+            AssetHandle::Texture(handle) => self.textures.get(handle.0).map(|entry| &entry.status),
+            AssetHandle::Model(handle) => self.models.get(handle.0).map(|entry| &entry.status),
+        }
+    }
+
+    /// Whether any load is still running.
+    pub fn is_loading(&self) -> bool {
+        let loading = |status: &AssetStatus| *status == AssetStatus::Loading;
+        self.textures.iter().any(|entry| loading(&entry.status))
+            || self.models.iter().any(|entry| loading(&entry.status))
+    }
+
+    /// Loads again each asset that read any of `files`, named as under
+    /// `res`, such as the textures a changed material points models at.
+    pub fn reload_changed(&mut self, files: &[String]) {
+        let stale = |files_read: &[String]| files.iter().any(|file| files_read.contains(file));
+        for i in 0..self.textures.len() {
+            if stale(&self.textures[i].files) {
+                self.textures[i].status = AssetStatus::Loading;
+                self.spawn_texture(TextureHandle(i));
+            }
+        }
+        for i in 0..self.models.len() {
+            if stale(&self.models[i].files) {
+                self.models[i].status = AssetStatus::Loading;
+                self.spawn_model(ModelHandle(i));
+            }
+        }
+    }
+
+    /// Loads everything `old` was asked for again onto this device, in the
+    /// same order so `old`'s handles stay valid here.
+    pub(crate) fn reload(&mut self, old: &Assets) {
+        for entry in &old.textures {
+            self.start_texture(&entry.file_name, entry.kind);
+        }
+        for entry in &old.models {
+            self.load_model(&entry.file_name);
+        }
+    }
+}
+
+// `load` makes the future on the thread that runs it, so nothing it holds
+// across an await needs to be `Send`. The thread is the load's alone, so
+// the files read on it are the load's.
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn<F: Future<Output = Loaded>>(
+    sender: mpsc::Sender<(Loaded, Vec<String>)>,
+    load: impl FnOnce() -> F + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        let _ = sender.send(resources::record_reads(|| pollster::block_on(load())));
+    });
+}
+
+// The browser runs the fetch and decode on the page's thread, between
+// frames.
+#[cfg(target_arch = "wasm32")]
+fn spawn<F: Future<Output = Loaded> + 'static>(
+    sender: mpsc::Sender<(Loaded, Vec<String>)>,
+    load: impl FnOnce() -> F + 'static,
+) {
+    wasm_bindgen_futures::spawn_local(async move {
+        let _ = sender.send((load().await, Vec::new()));
+    });
+}
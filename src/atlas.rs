@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+
+use crate::texture;
+
+/// Where one named image sits in an atlas, in texels from the top-left.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AtlasRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The named regions of an atlas image, packed here or read from a
+/// pre-baked atlas description.
+#[derive(Debug, Clone, Default)]
+pub struct AtlasLayout {
+    /// The atlas image, relative to the description, if it names one.
+    pub image: Option<String>,
+    pub regions: HashMap<String, AtlasRegion>,
+}
+
+impl AtlasLayout {
+    /// Reads the JSON written by TexturePacker and most tools copying it,
+    /// with `frames` either an object keyed by name or an array of frames
+    /// carrying a `filename`. Rotated frames are not supported.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let root: serde_json::Value = serde_json::from_str(json)?;
+        let frames: Vec<(&str, &serde_json::Value)> = match &root["frames"] {
+            serde_json::Value::Object(frames) => frames
+                .iter()
+                .map(|(name, frame)| (name.as_str(), frame))
+                .collect(),
+            serde_json::Value::Array(frames) => frames
+                .iter()
+                .map(|frame| {
+                    let name = frame["filename"]
+                        .as_str()
+                        .context("atlas frame has no filename")?;
+                    Ok((name, frame))
+                })
+                .collect::<anyhow::Result<_>>()?,
+            _ => anyhow::bail!("atlas has no frames"),
+        };
+
+        let mut regions = HashMap::with_capacity(frames.len());
+        for (name, frame) in frames {
+            if frame["rotated"].as_bool() == Some(true) {
+                anyhow::bail!("atlas frame {} is rotated", name);
+            }
+            let rect = &frame["frame"];
+            let field = |key: &str| {
+                rect[key]
+                    .as_u64()
+                    .and_then(|value| u32::try_from(value).ok())
+                    .with_context(|| format!("atlas frame {} has no {}", name, key))
+            };
+            let region = AtlasRegion {
+                x: field("x")?,
+                y: field("y")?,
+                width: field("w")?,
+                height: field("h")?,
+            };
+            if regions.insert(name.to_string(), region).is_some() {
+                anyhow::bail!("atlas frame {} appears twice", name);
+            }
+        }
+
+        Ok(Self {
+            image: root["meta"]["image"].as_str().map(str::to_string),
+            regions,
+        })
+    }
+}
+
+/// Packs images into one atlas, so everything drawn from them can share a
+/// texture and a batch.
+///
+/// Images are placed on shelves, tallest first, starting from the smallest
+/// power-of-two size their area allows and doubling the shorter side until
+/// they fit. Each is surrounded by `padding` texels copied from its own
+/// edges, which keeps filtering and the first few mip levels from picking
+/// up its neighbours.
+#[derive(Debug, Clone)]
+pub struct TextureAtlasBuilder {
+    images: Vec<(String, image::RgbaImage)>,
+    padding: u32,
+}
+
+impl Default for TextureAtlasBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextureAtlasBuilder {
+    pub fn new() -> Self {
+        Self {
+            images: Vec::new(),
+            padding: 2,
+        }
+    }
+
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, image: &image::DynamicImage) {
+        self.images.push((name.into(), image.to_rgba8()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    /// Lays the images out in an atlas no wider or taller than `max_size`.
+    pub fn pack(self, max_size: u32) -> anyhow::Result<(image::RgbaImage, AtlasLayout)> {
+        let mut names = std::collections::HashSet::new();
+        if let Some((name, _)) = self.images.iter().find(|(name, _)| !names.insert(name)) {
+            anyhow::bail!("atlas image {} added twice", name);
+        }
+        if let Some((name, _)) = self
+            .images
+            .iter()
+            .find(|(_, image)| image.width() == 0 || image.height() == 0)
+        {
+            anyhow::bail!("atlas image {} is empty", name);
+        }
+
+        let padding = self.padding;
+        let padded =
+            |image: &image::RgbaImage| (image.width() + padding * 2, image.height() + padding * 2);
+        let mut order = (0..self.images.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.images[i].1.height()));
+
+        let area = self
+            .images
+            .iter()
+            .map(|(_, image)| {
+                let (width, height) = padded(image);
+                width as u64 * height as u64
+            })
+            .sum::<u64>();
+        let widest = self
+            .images
+            .iter()
+            .map(|(_, image)| padded(image).0)
+            .max()
+            .unwrap_or(1);
+        let tallest = self
+            .images
+            .iter()
+            .map(|(_, image)| padded(image).1)
+            .max()
+            .unwrap_or(1);
+        let mut width = ((area as f64).sqrt().ceil() as u32)
+            .max(widest)
+            .next_power_of_two();
+        let mut height = (area.div_ceil(width as u64) as u32)
+            .max(tallest)
+            .next_power_of_two();
+        let positions = loop {
+            if width > max_size || height > max_size {
+                anyhow::bail!(
+                    "{} images do not fit in a {}x{} atlas",
+                    self.images.len(),
+                    max_size,
+                    max_size
+                );
+            }
+            if let Some(positions) = Self::shelve(&order, &self.images, padding, width, height) {
+                break positions;
+            }
+            if height < width {
+                height *= 2;
+            } else {
+                width *= 2;
+            }
+        };
+
+        let mut atlas = image::RgbaImage::new(width, height);
+        let mut regions = HashMap::with_capacity(self.images.len());
+        for ((name, image), (x, y)) in self.images.into_iter().zip(positions) {
+            let (padded_width, padded_height) = padded(&image);
+            for py in 0..padded_height {
+                for px in 0..padded_width {
+                    let sx = px.saturating_sub(padding).min(image.width() - 1);
+                    let sy = py.saturating_sub(padding).min(image.height() - 1);
+                    atlas.put_pixel(x - padding + px, y - padding + py, *image.get_pixel(sx, sy));
+                }
+            }
+            regions.insert(
+                name,
+                AtlasRegion {
+                    x,
+                    y,
+                    width: image.width(),
+                    height: image.height(),
+                },
+            );
+        }
+        Ok((
+            atlas,
+            AtlasLayout {
+                image: None,
+                regions,
+            },
+        ))
+    }
+
+    // Top-left corner of each image, inside its padding, in `images` order;
+    // None when they overflow `width` by `height`.
+    fn shelve(
+        order: &[usize],
+        images: &[(String, image::RgbaImage)],
+        padding: u32,
+        width: u32,
+        height: u32,
+    ) -> Option<Vec<(u32, u32)>> {
+        let mut positions = vec![(0, 0); images.len()];
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        for &i in order {
+            let image = &images[i].1;
+            let padded_width = image.width() + padding * 2;
+            let padded_height = image.height() + padding * 2;
+            if x + padded_width > width {
+                x = 0;
+                y += shelf_height;
+                shelf_height = 0;
+            }
+            if padded_width > width || y + padded_height > height {
+                return None;
+            }
+            positions[i] = (x + padding, y + padding);
+            x += padded_width;
+            shelf_height = shelf_height.max(padded_height);
+        }
+        Some(positions)
+    }
+
+    /// Packs the images and uploads the atlas.
+    pub fn build(
+        self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+    ) -> anyhow::Result<TextureAtlas> {
+        let (image, layout) = self.pack(device.limits().max_texture_dimension_2d)?;
+        TextureAtlas::new(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(image),
+            layout,
+            label,
+        )
+    }
+}
+
+/// One texture holding many named images, looked up as UV rects for
+/// sprites and other screen-space drawing.
+pub struct TextureAtlas {
+    texture: texture::Texture,
+    // Kept to upload again on a new device.
+    image: image::DynamicImage,
+    size: [u32; 2],
+    regions: HashMap<String, AtlasRegion>,
+}
+
+impl TextureAtlas {
+    /// Uploads an atlas image whose regions were packed or baked already.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &image::DynamicImage,
+        layout: AtlasLayout,
+        label: &str,
+    ) -> anyhow::Result<Self> {
+        let size = [image.width(), image.height()];
+        if let Some((name, _)) = layout.regions.iter().find(|(_, region)| {
+            region.x + region.width > size[0] || region.y + region.height > size[1]
+        }) {
+            anyhow::bail!(
+                "atlas region {} lies outside the {}x{} image",
+                name,
+                size[0],
+                size[1]
+            );
+        }
+        let texture = texture::Texture::from_image(device, queue, image, Some(label))?;
+        Ok(Self {
+            texture,
+            image: image.clone(),
+            size,
+            regions: layout.regions,
+        })
+    }
+
+    pub fn texture(&self) -> &texture::Texture {
+        &self.texture
+    }
+
+    /// The image the texture was uploaded from.
+    pub fn image(&self) -> &image::DynamicImage {
+        &self.image
+    }
+
+    /// Width and height in texels.
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    pub fn region(&self, name: &str) -> Option<AtlasRegion> {
+        self.regions.get(name).copied()
+    }
+
+    /// `name`'s region as `[u0, v0, u1, v1]`, ready for
+    /// `Sprite::with_uv_rect`.
+    pub fn uv_rect(&self, name: &str) -> Option<[f32; 4]> {
+        let region = self.region(name)?;
+        let [width, height] = self.size.map(|side| side as f32);
+        Some([
+            region.x as f32 / width,
+            region.y as f32 / height,
+            (region.x + region.width) as f32 / width,
+            (region.y + region.height) as f32 / height,
+        ])
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.regions.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, shade: u8) -> image::DynamicImage {
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            width,
+            height,
+            image::Rgba([shade, shade, shade, 255]),
+        ))
+    }
+
+    #[test]
+    fn packed_regions_stay_apart_and_inside_the_atlas() {
+        let padding = 2;
+        let mut builder = TextureAtlasBuilder::new().with_padding(padding);
+        let sizes = [
+            (30, 12),
+            (7, 40),
+            (16, 16),
+            (1, 1),
+            (50, 3),
+            (9, 9),
+            (20, 33),
+        ];
+        for (i, &(width, height)) in sizes.iter().enumerate() {
+            builder.add(format!("image {}", i), &solid(width, height, i as u8 * 30));
+        }
+        let (atlas, layout) = builder.pack(512).unwrap();
+        assert_eq!(layout.regions.len(), sizes.len());
+
+        // Each region with the padding copied around it.
+        let padded = |region: &AtlasRegion| {
+            (
+                region.x - padding,
+                region.y - padding,
+                region.x + region.width + padding,
+                region.y + region.height + padding,
+            )
+        };
+        let regions = layout.regions.iter().collect::<Vec<_>>();
+        for (i, (name, region)) in regions.iter().enumerate() {
+            let index = name["image ".len()..].parse::<usize>().unwrap();
+            assert_eq!((region.width, region.height), sizes[index]);
+            assert!(region.x >= padding && region.y >= padding);
+            let (_, _, right, bottom) = padded(region);
+            assert!(right <= atlas.width() && bottom <= atlas.height());
+            let shade = index as u8 * 30;
+            assert_eq!(
+                atlas.get_pixel(region.x, region.y).0,
+                [shade, shade, shade, 255]
+            );
+
+            for (other_name, other) in &regions[i + 1..] {
+                let (left, top, right, bottom) = padded(region);
+                let (other_left, other_top, other_right, other_bottom) = padded(other);
+                let apart = right <= other_left
+                    || other_right <= left
+                    || bottom <= other_top
+                    || other_bottom <= top;
+                assert!(apart, "{} overlaps {}", name, other_name);
+            }
+        }
+    }
+
+    #[test]
+    fn an_image_too_large_for_the_atlas_is_an_error() {
+        let mut builder = TextureAtlasBuilder::new();
+        builder.add("small", &solid(4, 4, 0));
+        builder.add("huge", &solid(300, 10, 0));
+        assert!(builder.pack(256).is_err());
+
+        // Only its padding makes this one too large.
+        let mut builder = TextureAtlasBuilder::new().with_padding(1);
+        builder.add("edge", &solid(256, 4, 0));
+        assert!(builder.pack(256).is_err());
+    }
+
+    #[test]
+    fn duplicate_or_empty_images_are_errors() {
+        let mut builder = TextureAtlasBuilder::new();
+        builder.add("twice", &solid(2, 2, 0));
+        builder.add("twice", &solid(2, 2, 0));
+        assert!(builder.pack(64).is_err());
+
+        let mut builder = TextureAtlasBuilder::new();
+        builder.add("empty", &solid(0, 2, 0));
+        assert!(builder.pack(64).is_err());
+    }
+}
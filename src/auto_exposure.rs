@@ -0,0 +1,257 @@
+use wgpu::util::DeviceExt;
+
+use crate::preprocess::{self, ShaderDefs};
+
+const WORKGROUP_SIZE: u32 = 16;
+const BINS: u64 = 256;
+
+/// How the exposure follows the scene's brightness.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AutoExposureSettings {
+    pub enabled: bool,
+    /// The darkest and brightest average luminance it adapts to, in stops:
+    /// scenes past them stay under or over exposed.
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    /// How quickly it adapts: each second closes all but `e^-speed` of
+    /// the gap to the scene's exposure.
+    pub speed: f32,
+    /// What the average luminance is exposed to, middle grey by default.
+    pub key: f32,
+    /// Shares of the darkest and brightest pixels left out of the average,
+    /// so a few lamps or deep shadows don't swing it.
+    pub low_percentile: f32,
+    pub high_percentile: f32,
+}
+
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_log_luminance: -8.0,
+            max_log_luminance: 6.0,
+            speed: 1.5,
+            key: 0.18,
+            low_percentile: 0.5,
+            high_percentile: 0.05,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AutoExposureUniform {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    delta_time: f32,
+    speed: f32,
+    key: f32,
+    low_percentile: f32,
+    high_percentile: f32,
+    _padding: u32,
+}
+
+/// Eye adaptation: a histogram of the HDR target's luminance each frame,
+/// and an exposure eased towards the one that puts its average at
+/// `AutoExposureSettings::key`. The exposure lives in
+/// `HdrPipeline::adapted_exposure`, which the tonemapper multiplies its own
+/// with, so that stays as an exposure compensation.
+pub struct AutoExposure {
+    settings: AutoExposureSettings,
+    uniform_buffer: wgpu::Buffer,
+    histogram: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    histogram_pipeline: wgpu::ComputePipeline,
+    adapt_pipeline: wgpu::ComputePipeline,
+}
+
+impl AutoExposure {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let settings = AutoExposureSettings::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("AutoExposure::uniform_buffer"),
+            contents: bytemuck::bytes_of(&Self::uniform(settings, 0.0)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let histogram = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("AutoExposure::histogram"),
+            size: BINS * size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty,
+            count: None,
+        };
+        let storage = |binding| {
+            entry(
+                binding,
+                wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            )
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("AutoExposure::layout"),
+            entries: &[
+                entry(
+                    0,
+                    wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                ),
+                entry(
+                    1,
+                    wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                ),
+                storage(2),
+                storage(3),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("AutoExposure::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let [histogram_pipeline, adapt_pipeline] = Self::create_pipelines(device, &pipeline_layout);
+
+        Self {
+            settings,
+            uniform_buffer,
+            histogram,
+            layout,
+            pipeline_layout,
+            histogram_pipeline,
+            adapt_pipeline,
+        }
+    }
+
+    // The histogram and adapt pipelines, in that order.
+    fn create_pipelines(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+    ) -> [wgpu::ComputePipeline; 2] {
+        let shader = preprocess::builtin_module(
+            device,
+            "AutoExposure::shader",
+            "auto_exposure.wgsl",
+            &ShaderDefs::new(),
+        );
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let histogram_pipeline = pipeline("AutoExposure::histogram_pipeline", "cs_histogram");
+        let adapt_pipeline = pipeline("AutoExposure::adapt_pipeline", "cs_adapt");
+        [histogram_pipeline, adapt_pipeline]
+    }
+
+    /// Compiles the pipelines again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        [self.histogram_pipeline, self.adapt_pipeline] =
+            Self::create_pipelines(device, &self.pipeline_layout);
+    }
+
+    fn uniform(settings: AutoExposureSettings, delta_time: f32) -> AutoExposureUniform {
+        AutoExposureUniform {
+            min_log_luminance: settings.min_log_luminance,
+            log_luminance_range: (settings.max_log_luminance - settings.min_log_luminance)
+                .max(f32::EPSILON),
+            delta_time,
+            speed: settings.speed,
+            key: settings.key,
+            low_percentile: settings.low_percentile,
+            high_percentile: settings.high_percentile,
+            _padding: 0,
+        }
+    }
+
+    pub fn settings(&self) -> AutoExposureSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: AutoExposureSettings) {
+        self.settings = settings;
+    }
+
+    /// Sets how far the next `render` adapts: the frame took `delta_time`
+    /// seconds.
+    pub fn update(&self, queue: &wgpu::Queue, delta_time: f32) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&Self::uniform(self.settings, delta_time)),
+        );
+    }
+
+    /// Adapts `exposure` to the `width` by `height` HDR target `hdr`, when
+    /// enabled.
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr: &wgpu::TextureView,
+        exposure: &wgpu::Buffer,
+        [width, height]: [u32; 2],
+    ) {
+        if !self.settings.enabled {
+            return;
+        }
+        // The HDR target is replaced on resize, and one bind group a frame
+        // is cheaper than tracking when.
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("AutoExposure::bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.histogram.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: exposure.as_entire_binding(),
+                },
+            ],
+        });
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("AutoExposure::pass"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.set_pipeline(&self.histogram_pipeline);
+        pass.dispatch_workgroups(
+            width.div_ceil(WORKGROUP_SIZE),
+            height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+        pass.set_pipeline(&self.adapt_pipeline);
+        pass.dispatch_workgroups(1, 1, 1);
+    }
+}
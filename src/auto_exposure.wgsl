@@ -0,0 +1,99 @@
+// Adapts the exposure the tonemapper applies to how bright the HDR target
+// is: `cs_histogram` bins its pixels by log luminance, then `cs_adapt`
+// averages the bins and eases the exposure towards that average's.
+
+struct AutoExposureUniform {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // Seconds since the last frame.
+    delta_time: f32,
+    speed: f32,
+    key: f32,
+    // Shares of the darkest and brightest pixels the average leaves out.
+    low_percentile: f32,
+    high_percentile: f32,
+}
+
+const BINS: u32 = 256u;
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> settings: AutoExposureUniform;
+@group(0) @binding(2)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+// The exposure the tonemapper multiplies its own with.
+@group(0) @binding(3)
+var<storage, read_write> exposure: f32;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+var<workgroup> counts: array<u32, 256>;
+
+// Bin 0 holds the black pixels, which say nothing of how bright the scene
+// is; the rest split the log luminance range evenly.
+fn bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if (luminance < 1e-5) {
+        return 0u;
+    }
+    let t = (log2(luminance) - settings.min_log_luminance) / settings.log_luminance_range;
+    return u32(clamp(t, 0.0, 1.0) * f32(BINS - 2u)) + 1u;
+}
+
+@compute @workgroup_size(16, 16)
+fn cs_histogram(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    atomicStore(&local_bins[index], 0u);
+    workgroupBarrier();
+
+    let size = textureDimensions(t_hdr);
+    if (id.x < size.x && id.y < size.y) {
+        let color = textureLoad(t_hdr, vec2<i32>(id.xy), 0).rgb;
+        atomicAdd(&local_bins[bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    let count = atomicLoad(&local_bins[index]);
+    if (count > 0u) {
+        atomicAdd(&histogram[index], count);
+    }
+}
+
+@compute @workgroup_size(256)
+fn cs_adapt(@builtin(local_invocation_index) index: u32) {
+    // Cleared on the way out, for the next frame's `cs_histogram`.
+    counts[index] = atomicExchange(&histogram[index], 0u);
+    workgroupBarrier();
+    if (index != 0u) {
+        return;
+    }
+
+    var total = 0u;
+    for (var i = 1u; i < BINS; i += 1u) {
+        total += counts[i];
+    }
+    let low = f32(total) * settings.low_percentile;
+    let high = f32(total) * (1.0 - settings.high_percentile);
+    var seen = 0.0;
+    var kept = 0.0;
+    var sum = 0.0;
+    for (var i = 1u; i < BINS; i += 1u) {
+        let count = f32(counts[i]);
+        let inside = max(min(seen + count, high) - max(seen, low), 0.0);
+        let t = (f32(i) - 0.5) / f32(BINS - 2u);
+        sum += inside * (settings.min_log_luminance + t * settings.log_luminance_range);
+        kept += inside;
+        seen += count;
+    }
+    if (kept <= 0.0) {
+        return;
+    }
+
+    // In stops, so brightening and darkening by as much take as long.
+    let target_stops = log2(settings.key) - sum / kept;
+    let stops = log2(max(exposure, 1e-6));
+    let blend = 1.0 - exp(-settings.delta_time * settings.speed);
+    exposure = exp2(mix(stops, target_stops, blend));
+}
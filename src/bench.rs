@@ -0,0 +1,281 @@
+use std::path::PathBuf;
+
+use serde_json::{Value, json};
+use web_time::Instant;
+
+use crate::state::State;
+
+/// How a `Benchmark` report is written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// A summary object, with every measured frame's time alongside.
+    Json,
+    /// `metric,value` rows of the summary, for spreadsheets and diffs.
+    Csv,
+}
+
+impl ReportFormat {
+    /// CSV for paths ending in `.csv`, and otherwise JSON.
+    pub fn from_path(path: &std::path::Path) -> Self {
+        let is_csv = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+        if is_csv { Self::Csv } else { Self::Json }
+    }
+}
+
+/// Times a fixed run of frames and reports on it, to compare machines and
+/// changes against each other.
+///
+/// `start` steps every update by `FRAME_TIME` and plays the state's camera
+/// path from the top, again each time it ends, so every run sees the same
+/// frames however fast they draw; without a path the camera stays put.
+/// The first `WARMUP_FRAMES`, which compile pipelines and fill caches, go
+/// unmeasured. After that `record` takes each frame's wall time, draw
+/// calls and triangles, and every pass's GPU time as the readbacks
+/// arrive, until `frames` have been measured.
+pub struct Benchmark {
+    frames: u32,
+    report_path: PathBuf,
+    warmup: u32,
+    last: Option<Instant>,
+    frame_ms: Vec<f32>,
+    draw_calls: Vec<u64>,
+    triangles: Vec<u64>,
+    // Each pass's times, in the order they were first seen.
+    passes: Vec<(&'static str, Vec<f32>)>,
+    // The GPU profiler's frame last taken, so none is counted twice.
+    gpu_frame: u64,
+    adapter: String,
+    render_path: String,
+    size: (u32, u32),
+}
+
+impl Benchmark {
+    /// Seconds every update steps by while benchmarking.
+    pub const FRAME_TIME: f32 = 1.0 / 60.0;
+    pub const WARMUP_FRAMES: u32 = 10;
+
+    /// Measures `frames` frames, then writes the report to `report_path`
+    /// in the format its extension picks.
+    pub fn new(frames: u32, report_path: impl Into<PathBuf>) -> Self {
+        Self {
+            frames: frames.max(1),
+            report_path: report_path.into(),
+            warmup: Self::WARMUP_FRAMES,
+            last: None,
+            frame_ms: Vec::new(),
+            draw_calls: Vec::new(),
+            triangles: Vec::new(),
+            passes: Vec::new(),
+            gpu_frame: 0,
+            adapter: String::new(),
+            render_path: String::new(),
+            size: (0, 0),
+        }
+    }
+
+    /// Sets `state` up to be measured, turning off vertical sync where the
+    /// surface allows.
+    pub fn start(&mut self, state: &mut State) {
+        state.set_fixed_frame_time(Some(Self::FRAME_TIME));
+        let modes = state.present_modes();
+        if let Some(&mode) = modes.iter().find(|&&mode| mode != wgpu::PresentMode::Fifo)
+            && let Err(e) = state.set_present_mode(mode)
+        {
+            log::warn!("Benchmarking with vertical sync: {:#}", e);
+        }
+        if state.camera_path().keyframes.is_empty() {
+            log::warn!("No camera path to benchmark along; the camera stays put");
+        }
+        state.play_camera_path();
+        self.adapter = crate::adapter::describe(state.adapter_info());
+        self.render_path = format!("{:?}", state.render_path());
+        self.size = state.size();
+        log::info!(
+            "Benchmarking {} frames on {} after {} to warm up",
+            self.frames,
+            self.adapter,
+            self.warmup
+        );
+    }
+
+    /// Takes the frame `state` just rendered; true once every frame has
+    /// been measured.
+    pub fn record(&mut self, state: &mut State) -> bool {
+        if !state.is_playing_camera_path() {
+            state.play_camera_path();
+        }
+        let now = Instant::now();
+        let last = self.last.replace(now);
+        if self.warmup > 0 {
+            self.warmup -= 1;
+            return false;
+        }
+        if let Some(last) = last {
+            self.frame_ms.push((now - last).as_secs_f32() * 1000.0);
+            let timing = state.frame_timing();
+            self.draw_calls.push(timing.draw_calls() as u64);
+            self.triangles.push(timing.triangles());
+            if let Some(gpu) = timing.gpu()
+                && gpu.timings_frame() > self.gpu_frame
+            {
+                self.gpu_frame = gpu.timings_frame();
+                for &(label, ms) in gpu.timings() {
+                    match self.passes.iter_mut().find(|(name, _)| *name == label) {
+                        Some((_, times)) => times.push(ms),
+                        None => self.passes.push((label, vec![ms])),
+                    }
+                }
+            }
+        }
+        self.is_finished()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame_ms.len() >= self.frames as usize
+    }
+
+    /// Frames measured so far.
+    pub fn measured(&self) -> usize {
+        self.frame_ms.len()
+    }
+
+    pub fn report_path(&self) -> &std::path::Path {
+        &self.report_path
+    }
+
+    /// The report on the frames measured so far.
+    pub fn report(&self) -> Value {
+        let gpu_ms: serde_json::Map<String, Value> = self
+            .passes
+            .iter()
+            .map(|(label, times)| (label.to_string(), json!(average(times))))
+            .collect();
+        let frame_ms = average(&self.frame_ms);
+        json!({
+            "adapter": self.adapter,
+            "render_path": self.render_path,
+            "width": self.size.0,
+            "height": self.size.1,
+            "frames": self.frame_ms.len(),
+            "fps": if frame_ms > 0.0 { 1000.0 / frame_ms } else { 0.0 },
+            "frame_ms": {
+                "average": frame_ms,
+                "min": self.frame_ms.iter().copied().reduce(f32::min).unwrap_or(0.0),
+                "max": self.frame_ms.iter().copied().reduce(f32::max).unwrap_or(0.0),
+                "p50": percentile(&self.frame_ms, 50.0),
+                "p90": percentile(&self.frame_ms, 90.0),
+                "p95": percentile(&self.frame_ms, 95.0),
+                "p99": percentile(&self.frame_ms, 99.0),
+            },
+            "gpu_ms": gpu_ms,
+            "draw_calls": average_count(&self.draw_calls),
+            "triangles": average_count(&self.triangles),
+            "frame_times_ms": self.frame_ms,
+        })
+    }
+
+    /// `report` as `metric,value` rows, leaving out the frame times.
+    pub fn to_csv(&self) -> String {
+        let report = self.report();
+        let mut out = String::from("metric,value\n");
+        for (key, value) in report.as_object().into_iter().flatten() {
+            match value {
+                Value::Object(fields) => {
+                    for (field, value) in fields {
+                        out.push_str(&format!("{}.{},{}\n", key, csv_field(field), value));
+                    }
+                }
+                Value::Array(_) => {}
+                Value::String(text) => out.push_str(&format!("{},{}\n", key, csv_field(text))),
+                _ => out.push_str(&format!("{},{}\n", key, value)),
+            }
+        }
+        out
+    }
+
+    /// Writes the report to `report_path`.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let contents = match ReportFormat::from_path(&self.report_path) {
+            ReportFormat::Json => {
+                serde_json::to_string_pretty(&self.report()).expect("a Value always serializes")
+            }
+            ReportFormat::Csv => self.to_csv(),
+        };
+        std::fs::write(&self.report_path, contents).map_err(|e| {
+            anyhow::anyhow!("Unable to write {}: {}", self.report_path.display(), e)
+        })?;
+        log::info!(
+            "Wrote a report on {} frames to {}",
+            self.frame_ms.len(),
+            self.report_path.display()
+        );
+        Ok(())
+    }
+}
+
+fn average(values: &[f32]) -> f32 {
+    values.iter().sum::<f32>() / values.len().max(1) as f32
+}
+
+fn average_count(values: &[u64]) -> f64 {
+    values.iter().sum::<u64>() as f64 / values.len().max(1) as f64
+}
+
+// The nearest-rank percentile; 0 without values.
+fn percentile(values: &[f32], percent: f32) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let rank = (percent / 100.0 * sorted.len() as f32).ceil() as usize;
+    sorted.get(rank.saturating_sub(1)).copied().unwrap_or(0.0)
+}
+
+// `text` quoted if it would otherwise split the row.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_takes_the_nearest_rank() {
+        let even = [4.0, 1.0, 3.0, 2.0];
+        assert_eq!(percentile(&even, 50.0), 2.0);
+        assert_eq!(percentile(&even, 51.0), 3.0);
+        assert_eq!(percentile(&even, 99.0), 4.0);
+        let odd = [5.0, 3.0, 1.0, 4.0, 2.0];
+        assert_eq!(percentile(&odd, 50.0), 3.0);
+        assert_eq!(percentile(&odd, 90.0), 5.0);
+        assert_eq!(percentile(&odd, 0.0), 1.0);
+        assert_eq!(percentile(&odd, 100.0), 5.0);
+    }
+
+    #[test]
+    fn percentile_of_one_or_no_values() {
+        for percent in [0.0, 50.0, 99.0, 100.0] {
+            assert_eq!(percentile(&[], percent), 0.0);
+            assert_eq!(percentile(&[7.5], percent), 7.5);
+        }
+    }
+
+    #[test]
+    fn csv_field_quotes_only_what_would_split_the_row() {
+        assert_eq!(
+            csv_field("llvmpipe (LLVM 19.1.7)"),
+            "llvmpipe (LLVM 19.1.7)"
+        );
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("Vulkan, Gl"), "\"Vulkan, Gl\"");
+        assert_eq!(csv_field("a \"quoted\" name"), "\"a \"\"quoted\"\" name\"");
+        assert_eq!(csv_field("\"a\",\"b\""), "\"\"\"a\"\",\"\"b\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}
@@ -0,0 +1,383 @@
+use std::ops::Range;
+
+use cgmath::MetricSpace;
+
+use crate::post::taa::Taa;
+use crate::preprocess::{ShaderDefs, ShaderLibrary};
+use crate::texture;
+use crate::vertex::Vertex;
+
+/// How a billboard turns to face the camera.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum BillboardMode {
+    /// Faces the camera fully, for particles and markers.
+    #[default]
+    Spherical,
+    /// Turns about world up only, for impostors of upright things like
+    /// distant trees.
+    Cylindrical,
+}
+
+/// A texture registered with a `BillboardRenderer`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BillboardTextureId(pub usize);
+
+impl BillboardTextureId {
+    /// Plain white, so the tint alone colors the quad.
+    pub const WHITE: Self = Self(0);
+}
+
+/// A textured quad centered on `position`, `size` world units across.
+#[derive(Debug, Copy, Clone)]
+pub struct Billboard {
+    pub position: cgmath::Point3<f32>,
+    pub size: [f32; 2],
+    /// Multiplies the texture, alpha included.
+    pub tint: [f32; 4],
+    pub texture: BillboardTextureId,
+    pub mode: BillboardMode,
+}
+
+impl Billboard {
+    pub fn new(position: cgmath::Point3<f32>, size: [f32; 2]) -> Self {
+        Self {
+            position,
+            size,
+            tint: [1.0; 4],
+            texture: BillboardTextureId::WHITE,
+            mode: BillboardMode::Spherical,
+        }
+    }
+
+    pub fn with_tint(mut self, tint: [f32; 4]) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    pub fn with_texture(mut self, texture: BillboardTextureId) -> Self {
+        self.texture = texture;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: BillboardMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    fn to_raw(self) -> BillboardRaw {
+        BillboardRaw {
+            position: self.position.into(),
+            mode: self.mode as u32,
+            size: self.size,
+            tint: self.tint,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BillboardRaw {
+    position: [f32; 3],
+    mode: u32,
+    size: [f32; 2],
+    tint: [f32; 4],
+}
+
+crate::impl_vertex!(BillboardRaw, Instance {
+    position => 0: Float32x3,
+    mode => 1: Uint32,
+    size => 2: Float32x2,
+    tint => 3: Float32x4,
+});
+
+/// Camera-facing quads drawn blended over the scene after the transparent
+/// meshes.
+///
+/// Billboards are kept between frames like `InstanceBuffer`'s instances.
+/// `upload` sorts them back to front and draws runs sharing a texture as
+/// one instanced draw, so a particle system should keep to few textures.
+/// They test depth without writing it, and leave velocity alone.
+pub struct BillboardRenderer {
+    billboards: Vec<Billboard>,
+    textures: Vec<wgpu::BindGroup>,
+    texture_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    reverse_z: bool,
+    pipeline: wgpu::RenderPipeline,
+    // `pipeline` for a reversed depth buffer.
+    reverse_z_pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    // Runs of the sorted instances sharing a texture.
+    batches: Vec<(BillboardTextureId, Range<u32>)>,
+}
+
+impl BillboardRenderer {
+    const MIN_CAPACITY: usize = 64;
+
+    /// `camera_layout` is the scene's camera group, of which only the
+    /// `CameraUniform` is read.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shaders: &ShaderLibrary,
+        camera_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<Self> {
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("BillboardRenderer::texture_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("BillboardRenderer::layout"),
+            bind_group_layouts: &[&texture_layout, camera_layout],
+            push_constant_ranges: &[],
+        });
+        let [pipeline, reverse_z_pipeline] =
+            Self::create_pipelines(device, shaders, &pipeline_layout, color_format)?;
+
+        let mut renderer = Self {
+            billboards: Vec::new(),
+            textures: Vec::new(),
+            texture_layout,
+            pipeline_layout,
+            color_format,
+            reverse_z: false,
+            pipeline,
+            reverse_z_pipeline,
+            buffer: Self::create_buffer(device, Self::MIN_CAPACITY),
+            capacity: Self::MIN_CAPACITY,
+            batches: Vec::new(),
+        };
+        let white =
+            texture::Texture::from_color(device, queue, [255; 4], "BillboardRenderer::white")?;
+        renderer.add_texture(device, &white);
+        Ok(renderer)
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+        pipeline_layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<[wgpu::RenderPipeline; 2]> {
+        let shader = shaders.create_module(
+            device,
+            "BillboardRenderer::shader",
+            "billboard.wgsl",
+            &ShaderDefs::new(),
+        )?;
+        let create_pipeline = |label, depth_compare| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[BillboardRaw::desc()],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[
+                        Some(wgpu::ColorTargetState {
+                            format: color_format,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: Taa::VELOCITY_FORMAT,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::empty(),
+                        }),
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleStrip,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        Ok([
+            create_pipeline(
+                "BillboardRenderer::pipeline",
+                wgpu::CompareFunction::LessEqual,
+            ),
+            create_pipeline(
+                "BillboardRenderer::reverse_z_pipeline",
+                wgpu::CompareFunction::GreaterEqual,
+            ),
+        ])
+    }
+
+    /// Compiles the pipelines again from `shaders`' current sources.
+    pub(crate) fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+    ) -> anyhow::Result<()> {
+        [self.pipeline, self.reverse_z_pipeline] =
+            Self::create_pipelines(device, shaders, &self.pipeline_layout, self.color_format)?;
+        Ok(())
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("BillboardRenderer::instances"),
+            size: (capacity * size_of::<BillboardRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Makes `texture` available to billboards; alpha is blended, so
+    /// straight (not premultiplied) alpha is expected.
+    pub fn add_texture(
+        &mut self,
+        device: &wgpu::Device,
+        texture: &texture::Texture,
+    ) -> BillboardTextureId {
+        self.textures
+            .push(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("BillboardRenderer::texture"),
+                layout: &self.texture_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    },
+                ],
+            }));
+        BillboardTextureId(self.textures.len() - 1)
+    }
+
+    /// Tests depth against a buffer drawn with the camera's `reverse_z`.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+    }
+
+    pub fn billboards(&self) -> &[Billboard] {
+        &self.billboards
+    }
+
+    pub fn len(&self) -> usize {
+        self.billboards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.billboards.is_empty()
+    }
+
+    pub fn push(&mut self, billboard: Billboard) -> usize {
+        self.billboards.push(billboard);
+        self.billboards.len() - 1
+    }
+
+    pub fn set(&mut self, index: usize, billboard: Billboard) {
+        self.billboards[index] = billboard;
+    }
+
+    pub fn remove(&mut self, index: usize) -> Billboard {
+        self.billboards.remove(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.billboards.clear();
+    }
+
+    /// Sorts the billboards back to front from `eye` and writes them out.
+    /// Billboards with a texture that was never added are skipped.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, eye: cgmath::Point3<f32>) {
+        let mut sorted = self
+            .billboards
+            .iter()
+            .filter(|billboard| billboard.texture.0 < self.textures.len())
+            .map(|billboard| (eye.distance2(billboard.position), billboard))
+            .collect::<Vec<_>>();
+        sorted.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        self.batches.clear();
+        for (i, (_, billboard)) in sorted.iter().enumerate() {
+            let i = i as u32;
+            match self.batches.last_mut() {
+                Some((texture, range)) if *texture == billboard.texture => range.end = i + 1,
+                _ => self.batches.push((billboard.texture, i..i + 1)),
+            }
+        }
+        if sorted.is_empty() {
+            return;
+        }
+
+        if sorted.len() > self.capacity {
+            self.capacity = sorted.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        let raw = sorted
+            .iter()
+            .map(|(_, billboard)| billboard.to_raw())
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&raw));
+    }
+
+    /// Draw calls `render` makes for what was last uploaded.
+    pub fn draw_calls(&self) -> usize {
+        self.batches.len()
+    }
+
+    /// Draws into a pass over the HDR, velocity and depth targets.
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.batches.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(if self.reverse_z {
+            &self.reverse_z_pipeline
+        } else {
+            &self.pipeline
+        });
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        for (texture, instances) in &self.batches {
+            render_pass.set_bind_group(0, &self.textures[texture.0], &[]);
+            render_pass.draw(0..4, instances.clone());
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// Camera-facing quads for billboard.rs, one instance per billboard.
+#include "include/camera.wgsl"
+
+@group(0) @binding(0)
+var t_billboard: texture_2d<f32>;
+@group(0) @binding(1)
+var s_billboard: sampler;
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Matches `BillboardMode` in billboard.rs.
+const SPHERICAL: u32 = 0u;
+
+struct BillboardInput {
+    @location(0) position: vec3<f32>,
+    @location(1) mode: u32,
+    @location(2) size: vec2<f32>,
+    @location(3) tint: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) tint: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, billboard: BillboardInput) -> VertexOutput {
+    // A triangle strip over the unit square.
+    let corner = vec2<f32>(f32(index & 1u), f32(index >> 1u));
+    // The view matrix's rows are the camera axes in world space.
+    var right = vec3<f32>(camera.view[0].x, camera.view[1].x, camera.view[2].x);
+    var up = vec3<f32>(camera.view[0].y, camera.view[1].y, camera.view[2].y);
+    if billboard.mode != SPHERICAL {
+        // Turns about world up only, so trees stay upright.
+        up = vec3<f32>(0.0, 1.0, 0.0);
+        let flat_right = vec3<f32>(right.x, 0.0, right.z);
+        if dot(flat_right, flat_right) > 1e-8 {
+            right = normalize(flat_right);
+        }
+    }
+    let offset = (corner - 0.5) * billboard.size;
+    let world_position = billboard.position + right * offset.x + up * offset.y;
+
+    var out: VertexOutput;
+    let clip = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.clip_position = vec4<f32>(clip.xy + camera.jitter * clip.w, clip.zw);
+    out.uv = vec2<f32>(corner.x, 1.0 - corner.y);
+    out.tint = billboard.tint;
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    // The velocity target is masked off; what shows through keeps its motion.
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let color = textureSample(t_billboard, s_billboard, in.uv) * in.tint;
+    if color.a < 0.01 {
+        discard;
+    }
+    var out: FragmentOutput;
+    out.color = color;
+    out.velocity = vec2<f32>(0.0);
+    return out;
+}
@@ -0,0 +1,309 @@
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+use crate::uniforms::DynamicUniformBuffer;
+
+const WORKGROUP_SIZE: u32 = 8;
+const MAX_KAWASE_PASSES: u32 = 8;
+// Taps a Gaussian pass reads on each side, at most.
+const MAX_RADIUS: u32 = 16;
+
+/// How `Blur` spreads each texel out.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlurKernel {
+    /// A horizontal then a vertical pass, reaching three `sigma`s (in
+    /// texels, at most 16) either side.
+    Gaussian { sigma: f32 },
+    /// `passes` of four bilinear taps, each reaching a texel further out
+    /// than the last: a cheap, wide approximation of a Gaussian. At most
+    /// eight, and rounded up to an even count so the last lands in the
+    /// output.
+    Kawase { passes: u32 },
+}
+
+impl BlurKernel {
+    fn passes(self) -> u32 {
+        match self {
+            Self::Gaussian { .. } => 2,
+            Self::Kawase { passes } => passes.clamp(2, MAX_KAWASE_PASSES).next_multiple_of(2),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BlurUniform {
+    axis: [i32; 2],
+    offset: f32,
+    sigma: f32,
+    radius: i32,
+    _padding: [u32; 3],
+}
+
+/// A separable Gaussian or Kawase blur in compute passes, for any single
+/// mip of a texture in `format`, which must be both filterable and a
+/// storage format: `Rgba8Unorm` or `Rgba16Float`.
+///
+/// Each pass reads the last one's result and writes the next, alternating
+/// between a scratch texture and the output, so the input is only read
+/// once and may be the output itself. Each effect keeps its own `Blur`,
+/// since the kernel is uploaded with `set_kernel` rather than per `run`.
+///
+/// SSAO, depth of field and variance shadow maps blur through it. Bloom
+/// keeps its own downsample/upsample mip pyramid.
+pub struct Blur {
+    kernel: BlurKernel,
+    format: wgpu::TextureFormat,
+    uniforms: DynamicUniformBuffer<BlurUniform>,
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    sampler: wgpu::Sampler,
+    gaussian_pipeline: wgpu::ComputePipeline,
+    kawase_pipeline: wgpu::ComputePipeline,
+}
+
+impl Blur {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        kernel: BlurKernel,
+    ) -> Self {
+        let uniforms =
+            DynamicUniformBuffer::new(device, "Blur::uniforms", MAX_KAWASE_PASSES as usize);
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty,
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Blur::layout"),
+            entries: &[
+                DynamicUniformBuffer::<BlurUniform>::layout_entry(0, wgpu::ShaderStages::COMPUTE),
+                entry(
+                    1,
+                    wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                ),
+                entry(
+                    2,
+                    wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                ),
+                entry(
+                    3,
+                    wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                ),
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Blur::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blur::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let [gaussian_pipeline, kawase_pipeline] =
+            Self::create_pipelines(device, &pipeline_layout, format);
+
+        let mut blur = Self {
+            kernel,
+            format,
+            uniforms,
+            layout,
+            pipeline_layout,
+            sampler,
+            gaussian_pipeline,
+            kawase_pipeline,
+        };
+        blur.set_kernel(device, queue, kernel);
+        blur
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+    ) -> [wgpu::ComputePipeline; 2] {
+        let storage_format = match format {
+            wgpu::TextureFormat::Rgba8Unorm => "rgba8unorm",
+            wgpu::TextureFormat::Rgba16Float => "rgba16float",
+            _ => panic!("Can't blur {:?} textures", format),
+        };
+        let shader = preprocess::builtin_module(
+            device,
+            "Blur::shader",
+            "blur.wgsl",
+            &ShaderDefs::new().with("FORMAT", storage_format),
+        );
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        [
+            pipeline("Blur::gaussian_pipeline", "cs_gaussian"),
+            pipeline("Blur::kawase_pipeline", "cs_kawase"),
+        ]
+    }
+
+    /// Compiles the pipelines again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        [self.gaussian_pipeline, self.kawase_pipeline] =
+            Self::create_pipelines(device, &self.pipeline_layout, self.format);
+    }
+
+    /// A target `run` can write and blur through: sampled, stored to and
+    /// drawn into, in `format`.
+    pub fn create_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> texture::Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    pub fn kernel(&self) -> BlurKernel {
+        self.kernel
+    }
+
+    /// Uploads each of `kernel`'s passes' uniforms, for the `run`s after.
+    pub fn set_kernel(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, kernel: BlurKernel) {
+        self.kernel = kernel;
+        self.uniforms.clear();
+        for pass in 0..kernel.passes() {
+            let uniform = match kernel {
+                BlurKernel::Gaussian { sigma } => {
+                    let sigma = sigma.max(0.1);
+                    BlurUniform {
+                        axis: if pass == 0 { [1, 0] } else { [0, 1] },
+                        offset: 0.0,
+                        sigma,
+                        radius: ((sigma * 3.0).ceil() as u32).min(MAX_RADIUS) as i32,
+                        _padding: [0; 3],
+                    }
+                }
+                BlurKernel::Kawase { .. } => BlurUniform {
+                    axis: [0, 0],
+                    offset: pass as f32,
+                    sigma: 0.0,
+                    radius: 0,
+                    _padding: [0; 3],
+                },
+            };
+            self.uniforms.push(&uniform);
+        }
+        self.uniforms.upload(device, queue);
+    }
+
+    /// Blurs `input` into `output` through `scratch`: views of a single
+    /// mip each, all `width` by `height`, the last two made storable as by
+    /// `create_target`.
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        scratch: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+        [width, height]: [u32; 2],
+    ) {
+        let pipeline = match self.kernel {
+            BlurKernel::Gaussian { .. } => &self.gaussian_pipeline,
+            BlurKernel::Kawase { .. } => &self.kawase_pipeline,
+        };
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Blur::pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(pipeline);
+        for index in 0..self.kernel.passes() {
+            let source = match index {
+                0 => input,
+                _ if index % 2 == 1 => scratch,
+                _ => output,
+            };
+            let destination = if index % 2 == 0 { scratch } else { output };
+            // The views are the caller's, and differ between effects and
+            // across resizes, so the bind groups are built per run.
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Blur::bind_group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: self.uniforms.binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(destination),
+                    },
+                ],
+            });
+            pass.set_bind_group(0, &bind_group, &[self.uniforms.offset(index as usize)]);
+            pass.dispatch_workgroups(
+                width.div_ceil(WORKGROUP_SIZE),
+                height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+    }
+}
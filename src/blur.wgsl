@@ -0,0 +1,56 @@
+// Blurs one mip into another of the same size: `cs_gaussian` along one
+// axis at a time, `cs_kawase` with four bilinear taps at a pass's corners.
+// `Blur` chains the passes through a scratch texture.
+
+struct BlurUniform {
+    // The axis a Gaussian pass runs along, in texels.
+    axis: vec2<i32>,
+    // How far out a Kawase pass's corner taps are, in texels.
+    offset: f32,
+    sigma: f32,
+    radius: i32,
+}
+
+@group(0) @binding(0)
+var<uniform> blur: BlurUniform;
+@group(0) @binding(1)
+var t_input: texture_2d<f32>;
+@group(0) @binding(2)
+var s_input: sampler;
+@group(0) @binding(3)
+var output: texture_storage_2d<FORMAT, write>;
+
+@compute @workgroup_size(8, 8)
+fn cs_gaussian(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let last = vec2<i32>(textureDimensions(t_input)) - 1;
+    let falloff = -0.5 / (blur.sigma * blur.sigma);
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var i = -blur.radius; i <= blur.radius; i += 1) {
+        let weight = exp(f32(i * i) * falloff);
+        let texel = clamp(vec2<i32>(id.xy) + blur.axis * i, vec2<i32>(0), last);
+        sum += textureLoad(t_input, texel, 0) * weight;
+        total += weight;
+    }
+    textureStore(output, id.xy, sum / total);
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_kawase(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    // Half a texel off each corner, so every tap averages four of them.
+    let corner = (blur.offset + 0.5) / vec2<f32>(textureDimensions(t_input));
+    let sum = textureSampleLevel(t_input, s_input, uv + vec2<f32>(-corner.x, -corner.y), 0.0)
+        + textureSampleLevel(t_input, s_input, uv + vec2<f32>(corner.x, -corner.y), 0.0)
+        + textureSampleLevel(t_input, s_input, uv + vec2<f32>(-corner.x, corner.y), 0.0)
+        + textureSampleLevel(t_input, s_input, uv + vec2<f32>(corner.x, corner.y), 0.0);
+    textureStore(output, id.xy, sum * 0.25);
+}
@@ -0,0 +1,239 @@
+use crate::flythrough::{self, CameraKeyframe};
+
+/// Where bookmarks are saved as they are set, and loaded from at startup.
+pub const DEFAULT_PATH: &str = "camera_bookmarks.toml";
+
+/// How many slots there are, numbered from 1 like the keys that set them.
+pub const SLOTS: usize = 9;
+
+/// Camera poses saved to numbered slots, to come back to exactly, as when
+/// comparing a rendering change from the same viewpoint.
+///
+/// Saved as TOML: a `transition_seconds` key, then one `[[bookmark]]` table
+/// per set slot with its `slot` number, `eye` and `target` arrays and a
+/// `fovy`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraBookmarks {
+    slots: [Option<CameraKeyframe>; SLOTS],
+    /// How long a smooth recall takes.
+    pub transition_seconds: f32,
+}
+
+impl Default for CameraBookmarks {
+    fn default() -> Self {
+        Self {
+            slots: [None; SLOTS],
+            transition_seconds: 1.0,
+        }
+    }
+}
+
+impl CameraBookmarks {
+    /// The pose in `slot`, 1 to 9, if one was saved there.
+    pub fn get(&self, slot: usize) -> Option<CameraKeyframe> {
+        self.slots.get(slot.checked_sub(1)?).copied().flatten()
+    }
+
+    /// Saves `keyframe` to `slot`, 1 to 9; out of range does nothing.
+    pub fn set(&mut self, slot: usize, keyframe: CameraKeyframe) {
+        if let Some(entry) = slot.checked_sub(1).and_then(|i| self.slots.get_mut(i)) {
+            *entry = Some(keyframe);
+        }
+    }
+
+    pub fn clear(&mut self, slot: usize) {
+        if let Some(entry) = slot.checked_sub(1).and_then(|i| self.slots.get_mut(i)) {
+            *entry = None;
+        }
+    }
+
+    /// Each set slot's number and pose, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, CameraKeyframe)> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, keyframe)| Some((i + 1, (*keyframe)?)))
+    }
+
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let document: toml_edit::DocumentMut = source.parse()?;
+        let mut bookmarks = Self::default();
+        if let Some(item) = document.get("transition_seconds") {
+            bookmarks.transition_seconds = flythrough::number(item)
+                .filter(|&seconds| seconds >= 0.0)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("transition_seconds should be a number, 0 or more")
+                })?;
+        }
+        let Some(tables) = document.get("bookmark") else {
+            return Ok(bookmarks);
+        };
+        let Some(tables) = tables.as_array_of_tables() else {
+            anyhow::bail!("bookmark should be [[bookmark]] tables");
+        };
+        for (i, table) in tables.iter().enumerate() {
+            let slot = table
+                .get("slot")
+                .and_then(toml_edit::Item::as_integer)
+                .filter(|slot| (1..=SLOTS as i64).contains(slot))
+                .ok_or_else(|| anyhow::anyhow!("bookmark {}: slot should be 1 to {}", i, SLOTS))?;
+            let keyframe = CameraKeyframe::from_toml(table, &format!("bookmark {}", i))?;
+            bookmarks.set(slot as usize, keyframe);
+        }
+        Ok(bookmarks)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Unable to read {}: {}", path.display(), e))?;
+        Self::parse(&source)
+            .map_err(|e| anyhow::anyhow!("Unable to read {}: {:#}", path.display(), e))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_toml())?;
+        Ok(())
+    }
+
+    /// The file `parse` reads back as `self`.
+    pub fn to_toml(&self) -> String {
+        let mut out = format!("transition_seconds = {:?}\n", self.transition_seconds);
+        for (slot, keyframe) in self.iter() {
+            out.push_str(&format!(
+                "\n[[bookmark]]\nslot = {}\n{}",
+                slot,
+                keyframe.toml_fields()
+            ));
+        }
+        out
+    }
+}
+
+/// A smooth recall under way, easing in and out between two poses.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct BookmarkTransition {
+    from: CameraKeyframe,
+    to: CameraKeyframe,
+    seconds: f32,
+    elapsed: f32,
+}
+
+impl BookmarkTransition {
+    pub(crate) fn new(from: CameraKeyframe, to: CameraKeyframe, seconds: f32) -> Self {
+        Self {
+            from,
+            to,
+            seconds,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Moves on by `dt` seconds: where the camera is then, and whether the
+    /// transition has further to go.
+    pub(crate) fn advance(&mut self, dt: f32) -> (CameraKeyframe, bool) {
+        self.elapsed += dt;
+        if self.elapsed >= self.seconds {
+            return (self.to, false);
+        }
+        let t = self.elapsed / self.seconds;
+        let eased = t * t * (3.0 - 2.0 * t);
+        (self.from.lerp(&self.to, eased), true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Point3;
+
+    use super::*;
+
+    fn keyframe(x: f32, fovy: f32) -> CameraKeyframe {
+        CameraKeyframe {
+            eye: Point3::new(x, 1.0, 2.0),
+            target: Point3::new(0.0, 0.0, 0.0),
+            fovy,
+        }
+    }
+
+    fn bookmark(slot: i64) -> String {
+        format!(
+            "[[bookmark]]\nslot = {}\neye = [0, 1, 2]\ntarget = [0, 0, 0]\nfovy = 45\n",
+            slot
+        )
+    }
+
+    #[test]
+    fn slots_out_of_range_are_ignored() {
+        let mut bookmarks = CameraBookmarks::default();
+        bookmarks.set(0, keyframe(1.0, 45.0));
+        bookmarks.set(SLOTS + 1, keyframe(1.0, 45.0));
+        assert_eq!(bookmarks, CameraBookmarks::default());
+        assert_eq!(bookmarks.get(0), None);
+        assert_eq!(bookmarks.get(SLOTS + 1), None);
+
+        bookmarks.set(1, keyframe(1.0, 45.0));
+        bookmarks.set(SLOTS, keyframe(2.0, 45.0));
+        assert_eq!(bookmarks.get(1), Some(keyframe(1.0, 45.0)));
+        assert_eq!(bookmarks.get(SLOTS), Some(keyframe(2.0, 45.0)));
+        bookmarks.clear(0);
+        bookmarks.clear(SLOTS + 1);
+        assert_eq!(bookmarks.iter().count(), 2);
+    }
+
+    #[test]
+    fn parse_rejects_slots_out_of_range() {
+        for slot in [0, SLOTS as i64 + 1, -1] {
+            let error = CameraBookmarks::parse(&bookmark(slot)).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("bookmark 0: slot should be 1 to {}", SLOTS)
+            );
+        }
+        let parsed = CameraBookmarks::parse(&(bookmark(1) + &bookmark(SLOTS as i64))).unwrap();
+        assert_eq!(
+            parsed.iter().map(|(slot, _)| slot).collect::<Vec<_>>(),
+            [1, SLOTS]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_negative_transitions() {
+        let error = CameraBookmarks::parse("transition_seconds = -1\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "transition_seconds should be a number, 0 or more"
+        );
+    }
+
+    #[test]
+    fn transitions_ease_between_their_endpoints() {
+        let (from, to) = (keyframe(0.0, 30.0), keyframe(10.0, 60.0));
+        let mut transition = BookmarkTransition::new(from, to, 1.0);
+        let (start, going) = transition.advance(0.0);
+        assert_eq!(start, from);
+        assert!(going);
+        // Slower than linear out of the start, through the middle at half
+        // way, and slower again into the end.
+        let (early, _) = transition.advance(0.1);
+        assert!(early.eye.x > 0.0 && early.eye.x < 1.0, "{:?}", early);
+        let (middle, _) = transition.advance(0.4);
+        assert!((middle.eye.x - 5.0).abs() < 1e-4, "{:?}", middle);
+        assert!((middle.fovy - 45.0).abs() < 1e-4, "{:?}", middle);
+        let (late, going) = transition.advance(0.4);
+        assert!(late.eye.x > 9.0 && late.eye.x < 10.0, "{:?}", late);
+        assert!(going);
+        let (end, going) = transition.advance(0.1);
+        assert_eq!(end, to);
+        assert!(!going);
+    }
+
+    #[test]
+    fn zero_second_transitions_end_at_once() {
+        let (from, to) = (keyframe(0.0, 30.0), keyframe(10.0, 60.0));
+        let mut transition = BookmarkTransition::new(from, to, 0.0);
+        assert_eq!(transition.advance(0.0), (to, false));
+    }
+}
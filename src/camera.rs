@@ -1,5 +1,7 @@
-use cgmath::{Rotation, Rotation3};
-use winit::keyboard::KeyCode;
+use cgmath::{Angle, Rotation, Rotation3};
+
+use crate::input::{Action, GamepadState};
+use crate::texture;
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_cols(
@@ -9,185 +11,329 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_co
     cgmath::Vector4::new(0.0, 0.0, 0.5, 1.0),
 );
 
+/// Takes wgpu's 0..1 depth to 1..0, putting the near plane at 1 and the far
+/// plane at 0, where a float depth buffer's precision is.
+#[rustfmt::skip]
+pub const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_cols(
+    cgmath::Vector4::new(1.0, 0.0, 0.0, 0.0),
+    cgmath::Vector4::new(0.0, 1.0, 0.0, 0.0),
+    cgmath::Vector4::new(0.0, 0.0, -1.0, 0.0),
+    cgmath::Vector4::new(0.0, 0.0, 1.0, 1.0),
+);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    /// Vertical field of view in degrees.
+    Perspective { fovy: f32 },
+    /// Height of the view volume in world units; width follows the aspect ratio.
+    Orthographic { height: f32 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
     pub up: cgmath::Vector3<f32>,
     pub aspect: f32,
-    pub fovy: f32,
+    pub projection: Projection,
     pub znear: f32,
     pub zfar: f32,
+    /// Projects the near plane to depth 1 and the far plane to 0, which
+    /// spreads `Depth32Float` evenly over huge `znear`..`zfar` ranges. The
+    /// depth tests have to agree, so `State::set_reverse_z` sets this on
+    /// every camera it draws with.
+    pub reverse_z: bool,
 }
 
 impl Camera {
-    fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        // Build the view matrix.
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
+    pub fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
+    /// Projection for an arbitrary depth range, e.g. a shadow cascade slice.
+    pub fn build_projection_matrix(&self, znear: f32, zfar: f32) -> cgmath::Matrix4<f32> {
+        let proj = match self.projection {
+            Projection::Perspective { fovy } => {
+                cgmath::perspective(cgmath::Deg(fovy), self.aspect, znear, zfar)
+            }
+            Projection::Orthographic { height } => {
+                let half_h = height * 0.5;
+                let half_w = half_h * self.aspect;
+                cgmath::ortho(-half_w, half_w, -half_h, half_h, znear, zfar)
+            }
+        };
+        if self.reverse_z {
+            REVERSE_Z_MATRIX * OPENGL_TO_WGPU_MATRIX * proj
+        } else {
+            OPENGL_TO_WGPU_MATRIX * proj
+        }
+    }
+
+    /// Depth of the near plane: 0, or 1 with `reverse_z`.
+    pub fn near_depth(&self) -> f32 {
+        if self.reverse_z { 1.0 } else { 0.0 }
+    }
+
+    /// Depth of the far plane, which depth buffers are cleared to.
+    pub fn far_depth(&self) -> f32 {
+        texture::Texture::far_depth(self.reverse_z)
+    }
 
-        // Build the projection matrix.
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+    pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        self.build_projection_matrix(self.znear, self.zfar) * self.build_view_matrix()
+    }
+
+    /// Partway from `self` at 0 to `other` at 1, for drawing between two
+    /// simulation steps. The field of view or height only blends when
+    /// both use the same kind of projection; otherwise, and for the rest,
+    /// `other` wins.
+    pub fn lerp(&self, other: &Camera, t: f32) -> Camera {
+        use cgmath::VectorSpace;
 
-        // Return the combined matrix.
-        OPENGL_TO_WGPU_MATRIX * proj * view
+        let projection = match (self.projection, other.projection) {
+            (Projection::Perspective { fovy: from }, Projection::Perspective { fovy: to }) => {
+                Projection::Perspective {
+                    fovy: from + (to - from) * t,
+                }
+            }
+            (
+                Projection::Orthographic { height: from },
+                Projection::Orthographic { height: to },
+            ) => Projection::Orthographic {
+                height: from + (to - from) * t,
+            },
+            (_, projection) => projection,
+        };
+        Camera {
+            eye: self.eye + (other.eye - self.eye) * t,
+            target: self.target + (other.target - self.target) * t,
+            up: self.up.lerp(other.up, t),
+            projection,
+            ..*other
+        }
+    }
+
+    /// Switches between perspective and orthographic, keeping the object at
+    /// `target` roughly the same size on screen.
+    pub fn toggle_projection(&mut self) {
+        use cgmath::MetricSpace;
+
+        let distance = self.eye.distance(self.target).max(self.znear);
+        self.projection = match self.projection {
+            Projection::Perspective { fovy } => Projection::Orthographic {
+                height: 2.0 * distance * cgmath::Deg(fovy * 0.5).tan(),
+            },
+            Projection::Orthographic { height } => Projection::Perspective {
+                fovy: cgmath::Deg::from(cgmath::Rad((height * 0.5 / distance).atan())).0 * 2.0,
+            },
+        };
     }
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
+    pub view_position: [f32; 4],
+    pub view: [[f32; 4]; 4],
     pub view_proj: [[f32; 4]; 4],
+    /// Takes NDC back to world space, for passes that rebuild positions
+    /// from depth.
+    pub inv_view_proj: [[f32; 4]; 4],
+    /// Last frame's `view_proj`, for screen-space velocity.
+    pub prev_view_proj: [[f32; 4]; 4],
+    /// Sub-pixel offset in NDC added after projection; zero unless TAA is
+    /// running.
+    pub jitter: [f32; 2],
+    /// 1 when the camera has `reverse_z`.
+    pub reverse_z: u32,
+    _padding: u32,
+    /// Forward materials discard fragments at `p` with
+    /// `dot(clip_plane, (p, 1)) < 0`; zero keeps everything.
+    pub clip_plane: [f32; 4],
 }
 
 impl CameraUniform {
     pub fn new() -> Self {
         use cgmath::SquareMatrix;
         Self {
+            view_position: [0.0; 4],
+            view: cgmath::Matrix4::identity().into(),
             view_proj: cgmath::Matrix4::identity().into(),
+            inv_view_proj: cgmath::Matrix4::identity().into(),
+            prev_view_proj: cgmath::Matrix4::identity().into(),
+            jitter: [0.0; 2],
+            reverse_z: 0,
+            _padding: 0,
+            clip_plane: [0.0; 4],
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into();
+        use cgmath::SquareMatrix;
+
+        self.view_position = camera.eye.to_homogeneous().into();
+        self.view = camera.build_view_matrix().into();
+        self.reverse_z = camera.reverse_z as u32;
+        let view_proj = camera.build_view_projection_matrix();
+        self.prev_view_proj = self.view_proj;
+        self.view_proj = view_proj.into();
+        self.inv_view_proj = view_proj
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity)
+            .into();
     }
 }
 
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How much of its motion `CameraController` carries from one update to
+/// the next, each in 0..1: zero starts and stops at once, and the closer
+/// to one, the longer it takes to get up to speed and to coast to a halt.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct CameraDamping {
+    /// For moving, in every direction.
+    pub movement: f32,
+    /// For turning, which also smooths out mouse look.
+    pub rotation: f32,
+}
+
 pub struct CameraController {
     speed: f32,
-    is_forward_pressed: bool,
-    is_backward_pressed: bool,
-    is_left_pressed: bool,
-    is_right_pressed: bool,
-    is_left_rotate_pressed: bool,
-    is_right_rotate_pressed: bool,
-    is_up_rotate_pressed: bool,
-    is_down_rotate_pressed: bool,
-    is_up_pressed: bool,
-    is_down_pressed: bool,
+    damping: CameraDamping,
+    // Per update, along the camera's forward, right and up axes.
+    velocity: cgmath::Vector3<f32>,
+    // Yaw and pitch per update, in degrees.
+    angular_velocity: cgmath::Vector2<f32>,
+    // Indexed by `Action as usize`.
+    held: [bool; Action::ALL.len()],
+    // Updates' worth of each action left over from the mouse, spent on
+    // the next update.
+    nudges: [f32; Action::ALL.len()],
+    gamepad: GamepadState,
 }
 
 impl CameraController {
     pub fn new(speed: f32) -> Self {
         Self {
             speed,
-            is_forward_pressed: false,
-            is_backward_pressed: false,
-            is_left_pressed: false,
-            is_right_pressed: false,
-            is_left_rotate_pressed: false,
-            is_right_rotate_pressed: false,
-            is_up_rotate_pressed: false,
-            is_down_rotate_pressed: false,
-            is_up_pressed: false,
-            is_down_pressed: false,
+            damping: CameraDamping::default(),
+            velocity: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            angular_velocity: cgmath::Vector2::new(0.0, 0.0),
+            held: [false; Action::ALL.len()],
+            nudges: [0.0; Action::ALL.len()],
+            gamepad: GamepadState::default(),
         }
     }
 
-    pub(crate) fn handle_key(&mut self, code: KeyCode, is_pressed: bool) -> bool {
-        match code {
-            KeyCode::KeyW => {
-                self.is_forward_pressed = is_pressed;
-                true
-            }
-            KeyCode::KeyS => {
-                self.is_backward_pressed = is_pressed;
-                true
-            }
-            KeyCode::KeyA => {
-                self.is_left_pressed = is_pressed;
-                true
-            }
-            KeyCode::KeyD => {
-                self.is_right_pressed = is_pressed;
-                true
-            }
-            KeyCode::ArrowLeft => {
-                self.is_left_rotate_pressed = is_pressed;
-                true
-            }
-            KeyCode::ArrowRight => {
-                self.is_right_rotate_pressed = is_pressed;
-                true
-            }
-            KeyCode::ArrowUp => {
-                self.is_up_rotate_pressed = is_pressed;
-                true
-            }
-            KeyCode::ArrowDown => {
-                self.is_down_rotate_pressed = is_pressed;
-                true
-            }
-            KeyCode::KeyE => {
-                self.is_up_pressed = is_pressed;
-                true
-            }
-            KeyCode::KeyQ => {
-                self.is_down_pressed = is_pressed;
-                true
-            }
-            _ => false,
-        }
+    pub fn speed(&self) -> f32 {
+        self.speed
     }
 
-    pub(crate) fn update_camera(&self, camera: &mut Camera) {
-        use cgmath::InnerSpace;
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
 
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
-        let forward_mag = forward.magnitude();
+    pub fn damping(&self) -> CameraDamping {
+        self.damping
+    }
 
-        let speed_limit = 0.05;
+    /// Clamps both factors short of one, where the camera would never
+    /// change speed at all.
+    pub fn set_damping(&mut self, damping: CameraDamping) {
+        self.damping = CameraDamping {
+            movement: damping.movement.clamp(0.0, 0.99),
+            rotation: damping.rotation.clamp(0.0, 0.99),
+        };
+    }
 
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed * speed_limit;
-            camera.target += forward_norm * self.speed * speed_limit;
-        }
-        if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed * speed_limit;
-            camera.target -= forward_norm * self.speed * speed_limit;
-        }
+    /// Drops whatever motion is left, as when something else moves the
+    /// camera.
+    pub fn stop(&mut self) {
+        self.velocity = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        self.angular_velocity = cgmath::Vector2::new(0.0, 0.0);
+    }
 
-        if self.is_left_pressed {
-            let right = forward_norm.cross(camera.up);
-            camera.eye -= right * self.speed * speed_limit;
-            camera.target -= right * self.speed * speed_limit;
-        }
-        if self.is_right_pressed {
-            let right = forward_norm.cross(camera.up);
-            camera.eye += right * self.speed * speed_limit;
-            camera.target += right * self.speed * speed_limit;
-        }
+    /// Starts or stops `action` every update until told otherwise.
+    pub(crate) fn handle_action(&mut self, action: Action, is_pressed: bool) {
+        self.held[action as usize] = is_pressed;
+    }
 
-        if self.is_left_rotate_pressed {
-            let rotation = cgmath::Quaternion::from_axis_angle(camera.up, cgmath::Deg(self.speed));
-            let new_forward = rotation.rotate_vector(forward);
-            camera.target = camera.eye + new_forward;
-        }
-        if self.is_right_rotate_pressed {
-            let rotation = cgmath::Quaternion::from_axis_angle(camera.up, cgmath::Deg(-self.speed));
-            let new_forward = rotation.rotate_vector(forward);
-            camera.target = camera.eye + new_forward;
-        }
+    /// Does `action` on the next update as if held for `updates` updates.
+    pub(crate) fn nudge(&mut self, action: Action, updates: f32) {
+        self.nudges[action as usize] += updates;
+    }
+
+    /// Where the gamepad's sticks and triggers are now; they stay there
+    /// until the next call.
+    pub(crate) fn set_gamepad(&mut self, gamepad: GamepadState) {
+        self.gamepad = gamepad;
+    }
+
+    /// Stops every held action, as when the bindings change under them.
+    pub(crate) fn release_all(&mut self) {
+        self.held = [false; Action::ALL.len()];
+    }
 
+    pub(crate) fn update_camera(&mut self, camera: &mut Camera) {
+        use cgmath::InnerSpace;
+
+        let amount = |action: Action| {
+            self.held[action as usize] as u8 as f32
+                + self.nudges[action as usize]
+                + self.gamepad.amount(action)
+        };
+        let speed = self.speed * self.gamepad.speed_scale();
+        let forward = camera.target - camera.eye;
+        let forward_norm = forward.normalize();
+        let forward_mag = forward.magnitude();
         let right = forward_norm.cross(camera.up);
 
-        if self.is_up_rotate_pressed {
-            let rotation = cgmath::Quaternion::from_axis_angle(right, cgmath::Deg(self.speed));
-            let new_forward = rotation.rotate_vector(forward);
-            camera.target = camera.eye + new_forward;
+        let step = speed * 0.05;
+        let mut forward_step = amount(Action::MoveBackward) * -step;
+        if forward_mag > self.speed {
+            forward_step += amount(Action::MoveForward) * step;
         }
-        if self.is_down_rotate_pressed {
-            let rotation = cgmath::Quaternion::from_axis_angle(right, cgmath::Deg(-self.speed));
-            let new_forward = rotation.rotate_vector(forward);
-            camera.target = camera.eye + new_forward;
+        let wanted = cgmath::Vector3::new(
+            forward_step,
+            (amount(Action::MoveRight) - amount(Action::MoveLeft)) * step,
+            (amount(Action::MoveUp) - amount(Action::MoveDown)) * step,
+        );
+        self.velocity = damp(wanted, self.velocity, self.damping.movement);
+        // Coasting forward stops at the target too.
+        if forward_mag <= self.speed {
+            self.velocity.x = self.velocity.x.min(0.0);
         }
+        let offset =
+            forward_norm * self.velocity.x + right * self.velocity.y + camera.up * self.velocity.z;
+        camera.eye += offset;
+        camera.target += offset;
 
-        if self.is_up_pressed {
-            camera.eye += camera.up * self.speed * speed_limit;
-            camera.target += camera.up * self.speed * speed_limit;
-        }
-        if self.is_down_pressed {
-            camera.eye -= camera.up * self.speed * speed_limit;
-            camera.target -= camera.up * self.speed * speed_limit;
+        let wanted = cgmath::Vector2::new(
+            amount(Action::RotateLeft) - amount(Action::RotateRight),
+            amount(Action::RotateUp) - amount(Action::RotateDown),
+        ) * speed;
+        self.angular_velocity = damp(wanted, self.angular_velocity, self.damping.rotation);
+        let cgmath::Vector2 { x: yaw, y: pitch } = self.angular_velocity;
+        if yaw != 0.0 || pitch != 0.0 {
+            let rotation = cgmath::Quaternion::from_axis_angle(right, cgmath::Deg(pitch))
+                * cgmath::Quaternion::from_axis_angle(camera.up, cgmath::Deg(yaw));
+            camera.target = camera.eye + rotation.rotate_vector(forward);
         }
+        self.nudges = [0.0; Action::ALL.len()];
+    }
+}
+
+/// `velocity` eased towards `wanted`, keeping `damping` of the difference,
+/// and snapped to it once close enough that the rest wouldn't show.
+fn damp<V: cgmath::InnerSpace<Scalar = f32>>(wanted: V, velocity: V, damping: f32) -> V {
+    let velocity = wanted + (velocity - wanted) * damping;
+    if (velocity - wanted).magnitude2() < 1e-10 {
+        wanted
+    } else {
+        velocity
     }
 }
@@ -0,0 +1,190 @@
+use cgmath::{InnerSpace, Rotation, Rotation3};
+
+use crate::camera::{Camera, Projection};
+
+/// A timed shake, started with `CameraEffects::shake`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Shake {
+    /// Largest turn away from where the camera looks, in degrees.
+    pub amplitude: f32,
+    /// How often the shake changes direction, roughly, per second.
+    pub frequency: f32,
+    /// How fast the amplitude dies down: it falls to about a third every
+    /// `1 / decay` seconds.
+    pub decay: f32,
+    /// Seconds until it stops altogether.
+    pub duration: f32,
+}
+
+impl Default for Shake {
+    fn default() -> Self {
+        Self {
+            amplitude: 2.0,
+            frequency: 12.0,
+            decay: 3.0,
+            duration: 1.0,
+        }
+    }
+}
+
+/// Game-feel effects applied over the camera the controller drives, for
+/// what is drawn only: the controller, and anything reading
+/// `State::camera`, never sees them.
+///
+/// Shakes turn the view by Perlin noise, a separate channel for yaw, pitch
+/// and roll, and several can run at once. Recoil kicks add up and spring
+/// back at `recoil_recovery` per second. The field of view eases towards
+/// `fov_offset` degrees over the camera's own at `fov_smoothing` per
+/// second. Everything advances with `update` and is applied by `apply`,
+/// which `State` does every frame before uploading the camera.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraEffects {
+    /// How fast recoil springs back, per second.
+    pub recoil_recovery: f32,
+    /// How fast the field of view follows `set_fov_offset`, per second.
+    pub fov_smoothing: f32,
+    // Each shake and how long it has run.
+    shakes: Vec<(Shake, f32)>,
+    // Where the noise is sampled, in seconds since the first update.
+    time: f32,
+    // Pitch and yaw in degrees.
+    recoil: cgmath::Vector2<f32>,
+    // Where the field of view offset is on its way to `fov_offset`.
+    fov_current: f32,
+    fov_offset: f32,
+}
+
+impl Default for CameraEffects {
+    fn default() -> Self {
+        Self {
+            recoil_recovery: 8.0,
+            fov_smoothing: 6.0,
+            shakes: Vec::new(),
+            time: 0.0,
+            recoil: cgmath::Vector2::new(0.0, 0.0),
+            fov_current: 0.0,
+            fov_offset: 0.0,
+        }
+    }
+}
+
+impl CameraEffects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts `shake` alongside any already running.
+    pub fn shake(&mut self, shake: Shake) {
+        self.shakes.push((shake, 0.0));
+    }
+
+    /// Kicks the view up by `pitch` and left by `yaw` degrees, on top of
+    /// any recoil not yet recovered from.
+    pub fn kick(&mut self, pitch: f32, yaw: f32) {
+        self.recoil += cgmath::Vector2::new(pitch, yaw);
+    }
+
+    /// Where the field of view is heading, in degrees over the camera's.
+    pub fn fov_offset(&self) -> f32 {
+        self.fov_offset
+    }
+
+    /// Eases the field of view to `degrees` over the camera's, as for a
+    /// sprint or a zoom.
+    pub fn set_fov_offset(&mut self, degrees: f32) {
+        self.fov_offset = degrees;
+    }
+
+    /// Whether `apply` would change anything.
+    pub fn is_active(&self) -> bool {
+        !self.shakes.is_empty()
+            || self.recoil.magnitude2() > 1e-8
+            || self.fov_current != 0.0
+            || self.fov_offset != 0.0
+    }
+
+    /// Stops every shake and recoil and drops the field of view offset at
+    /// once.
+    pub fn clear(&mut self) {
+        self.shakes.clear();
+        self.recoil = cgmath::Vector2::new(0.0, 0.0);
+        self.fov_current = 0.0;
+        self.fov_offset = 0.0;
+    }
+
+    /// Moves the effects on by `dt` seconds.
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+        for (_, elapsed) in &mut self.shakes {
+            *elapsed += dt;
+        }
+        self.shakes
+            .retain(|(shake, elapsed)| *elapsed < shake.duration);
+        self.recoil *= (-self.recoil_recovery * dt).exp();
+        let t = 1.0 - (-self.fov_smoothing * dt).exp();
+        self.fov_current += (self.fov_offset - self.fov_current) * t;
+        // Settled, rather than creeping closer forever.
+        if (self.fov_offset - self.fov_current).abs() < 1e-3 {
+            self.fov_current = self.fov_offset;
+        }
+    }
+
+    /// `camera` as the effects leave it.
+    pub fn apply(&self, camera: &Camera) -> Camera {
+        let mut camera = *camera;
+        if let Projection::Perspective { fovy } = &mut camera.projection {
+            *fovy = (*fovy + self.fov_current).clamp(1.0, 179.0);
+        }
+
+        let [mut yaw, mut pitch, mut roll] = [0.0f32; 3];
+        for (shake, elapsed) in &self.shakes {
+            let strength = shake.amplitude * (-shake.decay * elapsed).exp();
+            let x = self.time * shake.frequency;
+            yaw += perlin(x, 0) * strength;
+            pitch += perlin(x, 1) * strength;
+            roll += perlin(x, 2) * strength;
+        }
+        pitch += self.recoil.x;
+        yaw += self.recoil.y;
+        if yaw == 0.0 && pitch == 0.0 && roll == 0.0 {
+            return camera;
+        }
+
+        let forward = camera.target - camera.eye;
+        let direction = forward.normalize();
+        let right = direction.cross(camera.up).normalize();
+        // Looking straight along `up`, or nowhere at all.
+        if !right.magnitude2().is_finite() {
+            return camera;
+        }
+        let up = right.cross(direction);
+        let rotation = cgmath::Quaternion::from_axis_angle(up, cgmath::Deg(yaw))
+            * cgmath::Quaternion::from_axis_angle(right, cgmath::Deg(pitch))
+            * cgmath::Quaternion::from_axis_angle(direction, cgmath::Deg(roll));
+        camera.target = camera.eye + rotation.rotate_vector(forward);
+        camera.up = rotation.rotate_vector(up);
+        camera
+    }
+}
+
+/// One-dimensional Perlin noise in -1..1, zero at every whole `x`, with a
+/// different sequence for every `seed`.
+fn perlin(x: f32, seed: u32) -> f32 {
+    let cell = x.floor();
+    let t = x - cell;
+    let gradient = |i: i32| {
+        // An integer hash of the lattice point, mapped to a slope in -1..1.
+        let mut h = (i as u32).wrapping_mul(0x9e37_79b9) ^ seed.wrapping_mul(0x85eb_ca6b);
+        h ^= h >> 16;
+        h = h.wrapping_mul(0x7feb_352d);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x846c_a68b);
+        h ^= h >> 16;
+        h as f32 / u32::MAX as f32 * 2.0 - 1.0
+    };
+    let a = gradient(cell as i32) * t;
+    let b = gradient(cell as i32 + 1) * (t - 1.0);
+    let fade = t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    // Each half reaches at most a half, so doubling fills -1..1.
+    (a + (b - a) * fade) * 2.0
+}
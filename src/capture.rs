@@ -0,0 +1,166 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::color;
+
+/// A texture copied into a mappable buffer, waiting to be read back.
+///
+/// `new` records the copy; once the encoder is submitted, `map` starts the
+/// readback and `is_ready` reports when it has finished, mapped or not, so
+/// `into_image` can run without blocking. `wait` does both in one blocking
+/// call.
+pub struct FrameCapture {
+    buffer: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    mapping: bool,
+    // How the mapping went, once it has.
+    mapped: Arc<parking_lot::Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+}
+
+impl FrameCapture {
+    /// Records a copy of `texture`, which needs `COPY_SRC` usage and an
+    /// 8-bit RGBA/BGRA or `Rgba16Float` format.
+    pub fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> anyhow::Result<Self> {
+        let format = texture.format();
+        let bytes_per_pixel = match format {
+            wgpu::TextureFormat::Rgba8Unorm
+            | wgpu::TextureFormat::Rgba8UnormSrgb
+            | wgpu::TextureFormat::Bgra8Unorm
+            | wgpu::TextureFormat::Bgra8UnormSrgb => 4,
+            wgpu::TextureFormat::Rgba16Float => 8,
+            _ => anyhow::bail!("Cannot capture {:?} textures", format),
+        };
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            anyhow::bail!("Captured textures need COPY_SRC usage");
+        }
+
+        let width = texture.width();
+        let height = texture.height();
+        // Buffer rows must be padded to the copy alignment.
+        let padded_bytes_per_row =
+            (width * bytes_per_pixel).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("FrameCapture"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        Ok(Self {
+            buffer,
+            format,
+            width,
+            height,
+            padded_bytes_per_row,
+            mapping: false,
+            mapped: Arc::default(),
+        })
+    }
+
+    /// Starts mapping the buffer; call after submitting the copy.
+    pub fn map(&mut self) {
+        if self.mapping {
+            return;
+        }
+        self.mapping = true;
+        let mapped = self.mapped.clone();
+        self.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *mapped.lock() = Some(result);
+            });
+    }
+
+    /// Whether the readback has finished, successfully or not; either way
+    /// `into_image` then has the outcome.
+    pub fn is_ready(&self) -> bool {
+        self.mapped.lock().is_some()
+    }
+
+    /// Maps the buffer and blocks until the copy has finished.
+    pub fn wait(mut self, device: &wgpu::Device) -> anyhow::Result<image::RgbaImage> {
+        self.map();
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        self.into_image()
+    }
+
+    /// Unpads and converts the mapped rows to 8-bit RGBA. Float targets are
+    /// clamped to 0..1 without tonemapping and encoded to sRGB, as an
+    /// `Rgba8UnormSrgb` target stores them.
+    ///
+    /// Fails if the buffer couldn't be mapped, or hasn't been yet.
+    pub fn into_image(self) -> anyhow::Result<image::RgbaImage> {
+        match self.mapped.lock().clone() {
+            Some(Ok(())) => {}
+            Some(Err(e)) => anyhow::bail!("Frame capture buffer could not be mapped: {}", e),
+            None => anyhow::bail!("Frame capture read before it was ready"),
+        }
+        let mut pixels = Vec::with_capacity((self.width * self.height * 4) as usize);
+        {
+            let data = self.buffer.slice(..).get_mapped_range();
+            for row in data.chunks_exact(self.padded_bytes_per_row as usize) {
+                match self.format {
+                    wgpu::TextureFormat::Rgba16Float => {
+                        let row: &[half::f16] =
+                            bytemuck::cast_slice(&row[..(self.width * 8) as usize]);
+                        for rgba in row.chunks_exact(4) {
+                            let [r, g, b, a] =
+                                [0, 1, 2, 3].map(|i| rgba[i].to_f32().clamp(0.0, 1.0));
+                            let [r, g, b] = [r, g, b].map(color::linear_to_srgb);
+                            pixels.extend([r, g, b, a].map(|c| (c * 255.0 + 0.5) as u8));
+                        }
+                    }
+                    wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => {
+                        for bgra in row[..(self.width * 4) as usize].chunks_exact(4) {
+                            pixels.extend([bgra[2], bgra[1], bgra[0], bgra[3]]);
+                        }
+                    }
+                    _ => pixels.extend_from_slice(&row[..(self.width * 4) as usize]),
+                }
+            }
+        }
+        self.buffer.unmap();
+        Ok(image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .expect("capture holds width * height pixels"))
+    }
+}
+
+/// Encodes and writes `image` to `path` off the render thread, logging the
+/// outcome.
+pub fn save_png(image: image::RgbaImage, path: PathBuf) {
+    let save = move || match image.save_with_format(&path, image::ImageFormat::Png) {
+        Ok(()) => log::info!("Saved capture to {}", path.display()),
+        Err(e) => log::error!("Unable to save {}: {}", path.display(), e),
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    std::thread::spawn(save);
+    // There is no filesystem or thread to hand this to on the web.
+    #[cfg(target_arch = "wasm32")]
+    {
+        drop(save);
+        log::warn!("Saving captures is not supported on the web");
+    }
+}
@@ -0,0 +1,187 @@
+use crate::camera::Camera;
+use crate::preprocess;
+
+/// Screen tiles across and down, and depth slices, in the cluster grid.
+pub const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+/// Lights past this many in one cluster are dropped from it.
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 63;
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClusterUniform {
+    inv_proj: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    grid: [u32; 3],
+    enabled: u32,
+    tile_size: [f32; 2],
+    screen_size: [f32; 2],
+    z_near: f32,
+    z_far: f32,
+    _padding: [u32; 2],
+}
+
+/// Compute pass that bins point lights into a screen-space cluster grid so
+/// shading only walks the lights that can reach each fragment.
+///
+/// The grid is read through the camera group. While disabled the pass is
+/// skipped and shaders fall back to looping over every light.
+pub struct LightClusters {
+    enabled: bool,
+    uniform_buffer: wgpu::Buffer,
+    cluster_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl LightClusters {
+    pub fn new(device: &wgpu::Device, light_layout: &wgpu::BindGroupLayout, enabled: bool) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster Uniform Buffer"),
+            size: size_of::<ClusterUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let [x, y, z] = CLUSTER_GRID;
+        // Each cluster is a light count followed by its light indices.
+        let cluster_size = (1 + MAX_LIGHTS_PER_CLUSTER) * size_of::<u32>() as u32;
+        let cluster_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster Storage Buffer"),
+            size: (x * y * z * cluster_size) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("LightClusters::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("LightClusters::bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: cluster_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("LightClusters::pipeline_layout"),
+            bind_group_layouts: &[&layout, light_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout);
+
+        Self {
+            enabled,
+            uniform_buffer,
+            cluster_buffer,
+            bind_group,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+    ) -> wgpu::ComputePipeline {
+        let shader = preprocess::builtin_module(
+            device,
+            "LightClusters::shader",
+            "cluster.wgsl",
+            &preprocess::ShaderDefs::new(),
+        );
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("LightClusters::pipeline"),
+            layout: Some(pipeline_layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        })
+    }
+
+    /// Compiles the pipeline again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(device, &self.pipeline_layout);
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+
+    pub fn cluster_buffer(&self) -> &wgpu::Buffer {
+        &self.cluster_buffer
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera, width: u32, height: u32) {
+        use cgmath::SquareMatrix;
+
+        let proj = camera.build_projection_matrix(camera.znear, camera.zfar);
+        let inv_proj = proj.invert().unwrap_or_else(cgmath::Matrix4::identity);
+        let [x, y, _] = CLUSTER_GRID;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[ClusterUniform {
+                inv_proj: inv_proj.into(),
+                view: camera.build_view_matrix().into(),
+                grid: CLUSTER_GRID,
+                enabled: self.enabled as u32,
+                tile_size: [width.div_ceil(x) as f32, height.div_ceil(y) as f32],
+                screen_size: [width as f32, height as f32],
+                z_near: camera.znear,
+                z_far: camera.zfar,
+                _padding: [0; 2],
+            }]),
+        );
+    }
+
+    pub fn dispatch(&self, encoder: &mut wgpu::CommandEncoder, light_bind_group: &wgpu::BindGroup) {
+        if !self.enabled {
+            return;
+        }
+        let [x, y, z] = CLUSTER_GRID;
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("LightClusters::pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_bind_group(1, light_bind_group, &[]);
+        pass.dispatch_workgroups((x * y * z).div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
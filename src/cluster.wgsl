@@ -0,0 +1,79 @@
+// Bins point lights into a froxel grid: screen tiles in x/y, depth slices
+// spaced logarithmically in view space. One invocation per cluster.
+#include "include/lights.wgsl"
+
+@group(0) @binding(0)
+var<uniform> cluster_grid: ClusterUniform;
+@group(0) @binding(1)
+var<storage, read_write> clusters: array<Cluster>;
+
+@group(1) @binding(0)
+var<uniform> lighting: Lighting;
+@group(1) @binding(1)
+var<storage, read> lights: array<Light>;
+
+// View-space point on the ray through `ndc` at view depth `depth`; works for
+// both perspective and orthographic projections. Both points are taken
+// short of the planes in NDC because with a huge far/near ratio the far
+// plane itself unprojects to infinity in f32, and this way it doesn't
+// matter which end of the range it's at.
+fn point_at_depth(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let near = cluster_grid.inv_proj * vec4<f32>(ndc, 0.25, 1.0);
+    let far = cluster_grid.inv_proj * vec4<f32>(ndc, 0.75, 1.0);
+    let a = near.xyz / near.w;
+    let b = far.xyz / far.w;
+    return mix(a, b, (-depth - a.z) / (b.z - a.z));
+}
+
+fn slice_depth(slice: u32) -> f32 {
+    let t = f32(slice) / f32(cluster_grid.grid.z);
+    return cluster_grid.z_near * pow(cluster_grid.z_far / cluster_grid.z_near, t);
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let grid = cluster_grid.grid;
+    let index = id.x;
+    if index >= grid.x * grid.y * grid.z {
+        return;
+    }
+    let tile = vec2<u32>(index % grid.x, (index / grid.x) % grid.y);
+    let slice = index / (grid.x * grid.y);
+
+    // Pixel rows grow downwards while NDC y grows upwards.
+    let pixel_min = vec2<f32>(tile) * cluster_grid.tile_size;
+    let pixel_max = pixel_min + cluster_grid.tile_size;
+    let ndc_min = vec2<f32>(pixel_min.x, pixel_max.y) / cluster_grid.screen_size
+        * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    let ndc_max = vec2<f32>(pixel_max.x, pixel_min.y) / cluster_grid.screen_size
+        * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+
+    var aabb_min = vec3<f32>(3.4e38);
+    var aabb_max = vec3<f32>(-3.4e38);
+    for (var i = 0u; i < 2u; i += 1u) {
+        let depth = slice_depth(slice + i);
+        for (var corner = 0u; corner < 4u; corner += 1u) {
+            let ndc = vec2<f32>(
+                select(ndc_min.x, ndc_max.x, (corner & 1u) != 0u),
+                select(ndc_min.y, ndc_max.y, (corner & 2u) != 0u),
+            );
+            let p = point_at_depth(ndc, depth);
+            aabb_min = min(aabb_min, p);
+            aabb_max = max(aabb_max, p);
+        }
+    }
+
+    var count = 0u;
+    for (var i = 0u; i < lighting.light_count && count < MAX_LIGHTS_PER_CLUSTER; i += 1u) {
+        let light = lights[i];
+        let center = (cluster_grid.view * vec4<f32>(light.position, 1.0)).xyz;
+        let closest = clamp(center, aabb_min, aabb_max);
+        let offset = center - closest;
+        let range = light_range(light);
+        if dot(offset, offset) <= range * range {
+            clusters[index].lights[count] = i;
+            count += 1u;
+        }
+    }
+    clusters[index].count = count;
+}
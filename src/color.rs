@@ -0,0 +1,78 @@
+//! Where colors change encoding.
+//!
+//! Everything the renderer shades, blends and filters is linear: lights,
+//! material factors, sprite and text colors and every HDR target. Color
+//! textures are stored sRGB-encoded and decoded by the sampler; data
+//! textures (normals, metallic-roughness, occlusion, splat maps) are
+//! stored as they are. The only encode is the last write, into an sRGB
+//! surface or surface view, which the hardware does itself.
+
+/// How a texture's texels are encoded, picked when it's loaded.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// Colors authored for display, such as albedo and emissive maps,
+    /// decoded to linear as they are sampled.
+    #[default]
+    Srgb,
+    /// Data that isn't a color, such as normal, roughness and occlusion
+    /// maps, sampled exactly as stored.
+    Linear,
+}
+
+impl ColorSpace {
+    /// The RGBA8 format images in this space are uploaded as.
+    pub fn rgba8_format(self) -> wgpu::TextureFormat {
+        match self {
+            Self::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            Self::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The surface format to configure out of those a surface offers, and the
+/// sRGB view to draw through when it's linear.
+///
+/// Eight-bit sRGB formats come first, then eight-bit linear ones, which
+/// browser canvases only offer, drawn through their sRGB views. Anything
+/// else, such as a 10-bit or float surface, has no sRGB view, so the last
+/// write would go out unencoded and look dark; it's only used when the
+/// surface offers nothing better.
+pub fn surface_format(
+    formats: &[wgpu::TextureFormat],
+) -> (wgpu::TextureFormat, Option<wgpu::TextureFormat>) {
+    use wgpu::TextureFormat as F;
+    const PREFERRED: [wgpu::TextureFormat; 4] = [
+        F::Bgra8UnormSrgb,
+        F::Rgba8UnormSrgb,
+        F::Bgra8Unorm,
+        F::Rgba8Unorm,
+    ];
+    let format = PREFERRED
+        .into_iter()
+        .find(|format| formats.contains(format))
+        .unwrap_or_else(|| {
+            log::warn!(
+                "No 8-bit surface format in {:?}; colors won't be sRGB-encoded",
+                formats
+            );
+            formats[0]
+        });
+    let view_format = Some(format.add_srgb_suffix()).filter(|view| *view != format);
+    (format, view_format)
+}
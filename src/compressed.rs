@@ -0,0 +1,377 @@
+use std::io::Read;
+
+use anyhow::Context;
+
+/// Device features that let block-compressed textures be sampled as they
+/// are. `State` requests whichever of them the adapter has; formats left
+/// out are decoded to RGBA8 on upload instead.
+pub const FEATURES: wgpu::Features = wgpu::Features::TEXTURE_COMPRESSION_BC
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ASTC)
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ASTC_HDR);
+
+const KTX2_MAGIC: &[u8] = &[
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+const DDS_MAGIC: &[u8] = b"DDS ";
+
+// texture2ddecoder's signature: blocks, width, height, BGRA output.
+type BlockDecoder = fn(&[u8], usize, usize, &mut [u32]) -> Result<(), &'static str>;
+
+// Legacy DDS FourCCs for BC4 and BC5 that ddsfile has no D3DFormat for.
+const FOURCC_ATI1: u32 = u32::from_le_bytes(*b"ATI1");
+const FOURCC_BC4U: u32 = u32::from_le_bytes(*b"BC4U");
+const FOURCC_ATI2: u32 = u32::from_le_bytes(*b"ATI2");
+const FOURCC_BC5U: u32 = u32::from_le_bytes(*b"BC5U");
+
+/// A 2D texture and its stored mip chain, read from a KTX2 or DDS file in
+/// the GPU format it was baked to.
+#[derive(Debug, Clone)]
+pub struct CompressedImage {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Mip levels, largest first, each a tightly packed grid of blocks.
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    /// Whether `bytes` start like a KTX2 or DDS file rather than an image
+    /// the `image` crate reads.
+    pub fn is_container(bytes: &[u8]) -> bool {
+        bytes.starts_with(KTX2_MAGIC) || bytes.starts_with(DDS_MAGIC)
+    }
+
+    /// Reads a KTX2 or DDS file. `srgb` picks the color space for legacy
+    /// DDS formats that do not record one; KTX2 and DX10 DDS files always
+    /// name theirs.
+    pub fn from_bytes(bytes: &[u8], srgb: bool) -> anyhow::Result<Self> {
+        if bytes.starts_with(KTX2_MAGIC) {
+            Self::from_ktx2(bytes)
+        } else if bytes.starts_with(DDS_MAGIC) {
+            Self::from_dds(bytes, srgb)
+        } else {
+            anyhow::bail!("not a KTX2 or DDS file")
+        }
+    }
+
+    /// Reads a KTX2 file, undoing zstd supercompression. Basis Universal
+    /// and zlib supercompressed files are not supported.
+    pub fn from_ktx2(bytes: &[u8]) -> anyhow::Result<Self> {
+        let reader = ktx2::Reader::new(bytes)?;
+        let header = reader.header();
+        if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count != 1 {
+            anyhow::bail!("only single 2D KTX2 textures are supported");
+        }
+        let format = header
+            .format
+            .context("KTX2 textures needing transcoding are not supported")?;
+        let format = ktx2_format(format)
+            .with_context(|| format!("KTX2 format {:?} is not supported", format))?;
+
+        let levels = reader
+            .levels()
+            .map(|level| match header.supercompression_scheme {
+                None => Ok(level.data.to_vec()),
+                Some(ktx2::SupercompressionScheme::Zstandard) => {
+                    let mut data = Vec::with_capacity(level.uncompressed_byte_length as usize);
+                    ruzstd::decoding::StreamingDecoder::new(level.data)?.read_to_end(&mut data)?;
+                    Ok(data)
+                }
+                Some(scheme) => {
+                    anyhow::bail!("KTX2 supercompression {:?} is not supported", scheme)
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Self::new(
+            format,
+            header.pixel_width,
+            header.pixel_height.max(1),
+            levels,
+        )
+    }
+
+    /// Reads a DDS file holding one 2D texture, with or without a DX10
+    /// header.
+    pub fn from_dds(bytes: &[u8], srgb: bool) -> anyhow::Result<Self> {
+        let dds = ddsfile::Dds::read(bytes)?;
+        if dds.get_num_array_layers() > 1
+            || dds.get_depth() > 1
+            || dds
+                .header
+                .caps2
+                .intersects(ddsfile::Caps2::CUBEMAP | ddsfile::Caps2::VOLUME)
+        {
+            anyhow::bail!("only single 2D DDS textures are supported");
+        }
+        let format = match dds.get_dxgi_format() {
+            Some(format) => dxgi_format(format)
+                .with_context(|| format!("DDS format {:?} is not supported", format))?,
+            None => legacy_dds_format(&dds, srgb).context("DDS format is not supported")?,
+        };
+
+        let (width, height) = (dds.get_width(), dds.get_height());
+        let mut data = dds.get_data(0)?;
+        let mut levels = Vec::new();
+        for level in 0..dds.get_num_mipmap_levels().max(1) {
+            let size = level_size(format, width, height, level);
+            if data.len() < size {
+                anyhow::bail!("DDS file ends inside mip level {}", level);
+            }
+            let (level_data, rest) = data.split_at(size);
+            levels.push(level_data.to_vec());
+            data = rest;
+        }
+        Self::new(format, width, height, levels)
+    }
+
+    fn new(
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        levels: Vec<Vec<u8>>,
+    ) -> anyhow::Result<Self> {
+        if levels.is_empty() {
+            anyhow::bail!("texture has no mip levels");
+        }
+        for (level, data) in levels.iter().enumerate() {
+            if data.len() < level_size(format, width, height, level as u32) {
+                anyhow::bail!(
+                    "mip level {} is too short for a {}x{} {:?} texture",
+                    level,
+                    width,
+                    height,
+                    format
+                );
+            }
+        }
+        Ok(Self {
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    pub fn level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    /// Width and height of mip `level` in texels.
+    pub fn level_dimensions(&self, level: u32) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    /// Whether `device` can sample the stored format directly. Block
+    /// formats also need the top level to be whole blocks.
+    pub fn is_supported(&self, device: &wgpu::Device) -> bool {
+        let (block_width, block_height) = self.format.block_dimensions();
+        device.features().contains(self.format.required_features())
+            && self.width.is_multiple_of(block_width)
+            && self.height.is_multiple_of(block_height)
+    }
+
+    /// The format `decode_rgba8` produces, keeping the color space.
+    pub fn fallback_format(&self) -> wgpu::TextureFormat {
+        if self.format.is_srgb() {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        }
+    }
+
+    /// Expands every stored level to RGBA8, for devices that cannot sample
+    /// the stored format. BC6H is clamped to [0, 1] on the way, and signed
+    /// BC4/BC5 and HDR ASTC cannot be decoded.
+    pub fn decode_rgba8(&self) -> anyhow::Result<Vec<image::RgbaImage>> {
+        use wgpu::TextureFormat as F;
+
+        let decode: BlockDecoder = match self.format {
+            F::Rgba8Unorm | F::Rgba8UnormSrgb => {
+                return self
+                    .levels
+                    .iter()
+                    .enumerate()
+                    .map(|(level, data)| {
+                        let (width, height) = self.level_dimensions(level as u32);
+                        let size = (width * height * 4) as usize;
+                        image::RgbaImage::from_raw(width, height, data[..size].to_vec())
+                            .context("RGBA8 level has the wrong size")
+                    })
+                    .collect();
+            }
+            F::Bc1RgbaUnorm | F::Bc1RgbaUnormSrgb => texture2ddecoder::decode_bc1a,
+            F::Bc2RgbaUnorm | F::Bc2RgbaUnormSrgb => texture2ddecoder::decode_bc2,
+            F::Bc3RgbaUnorm | F::Bc3RgbaUnormSrgb => texture2ddecoder::decode_bc3,
+            F::Bc4RUnorm => texture2ddecoder::decode_bc4,
+            F::Bc5RgUnorm => texture2ddecoder::decode_bc5,
+            F::Bc6hRgbUfloat => texture2ddecoder::decode_bc6_unsigned,
+            F::Bc6hRgbFloat => texture2ddecoder::decode_bc6_signed,
+            F::Bc7RgbaUnorm | F::Bc7RgbaUnormSrgb => texture2ddecoder::decode_bc7,
+            F::Astc {
+                channel: wgpu::AstcChannel::Unorm | wgpu::AstcChannel::UnormSrgb,
+                ..
+            } => {
+                let (block_width, block_height) = self.format.block_dimensions();
+                return self.decode_with(|data, width, height, pixels| {
+                    texture2ddecoder::decode_astc(
+                        data,
+                        width,
+                        height,
+                        block_width as usize,
+                        block_height as usize,
+                        pixels,
+                    )
+                });
+            }
+            format => anyhow::bail!("{:?} cannot be decoded on the CPU", format),
+        };
+        self.decode_with(decode)
+    }
+
+    fn decode_with(
+        &self,
+        decode: impl Fn(&[u8], usize, usize, &mut [u32]) -> Result<(), &'static str>,
+    ) -> anyhow::Result<Vec<image::RgbaImage>> {
+        self.levels
+            .iter()
+            .enumerate()
+            .map(|(level, data)| {
+                let (width, height) = self.level_dimensions(level as u32);
+                let mut pixels = vec![0u32; (width * height) as usize];
+                decode(data, width as usize, height as usize, &mut pixels)
+                    .map_err(|e| anyhow::anyhow!("decoding {:?}: {}", self.format, e))?;
+                // The decoder packs BGRA into little-endian words.
+                let rgba = pixels
+                    .into_iter()
+                    .flat_map(|pixel| {
+                        let [b, g, r, a] = pixel.to_le_bytes();
+                        [r, g, b, a]
+                    })
+                    .collect();
+                image::RgbaImage::from_raw(width, height, rgba)
+                    .context("decoded level has the wrong size")
+            })
+            .collect()
+    }
+}
+
+/// Bytes in mip `level` of a `width` by `height` texture, padded out to
+/// whole blocks.
+fn level_size(format: wgpu::TextureFormat, width: u32, height: u32, level: u32) -> usize {
+    let (block_width, block_height) = format.block_dimensions();
+    let blocks_x = (width >> level).max(1).div_ceil(block_width);
+    let blocks_y = (height >> level).max(1).div_ceil(block_height);
+    let block_size = format.block_copy_size(None).unwrap_or(4);
+    (blocks_x * blocks_y * block_size) as usize
+}
+
+fn ktx2_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    use ktx2::Format as K;
+    use wgpu::TextureFormat as F;
+
+    Some(match format {
+        K::R8G8B8A8_UNORM => F::Rgba8Unorm,
+        K::R8G8B8A8_SRGB => F::Rgba8UnormSrgb,
+        K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => F::Bc1RgbaUnorm,
+        K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => F::Bc1RgbaUnormSrgb,
+        K::BC2_UNORM_BLOCK => F::Bc2RgbaUnorm,
+        K::BC2_SRGB_BLOCK => F::Bc2RgbaUnormSrgb,
+        K::BC3_UNORM_BLOCK => F::Bc3RgbaUnorm,
+        K::BC3_SRGB_BLOCK => F::Bc3RgbaUnormSrgb,
+        K::BC4_UNORM_BLOCK => F::Bc4RUnorm,
+        K::BC4_SNORM_BLOCK => F::Bc4RSnorm,
+        K::BC5_UNORM_BLOCK => F::Bc5RgUnorm,
+        K::BC5_SNORM_BLOCK => F::Bc5RgSnorm,
+        K::BC6H_UFLOAT_BLOCK => F::Bc6hRgbUfloat,
+        K::BC6H_SFLOAT_BLOCK => F::Bc6hRgbFloat,
+        K::BC7_UNORM_BLOCK => F::Bc7RgbaUnorm,
+        K::BC7_SRGB_BLOCK => F::Bc7RgbaUnormSrgb,
+        _ => return astc_format(format),
+    })
+}
+
+fn astc_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    use wgpu::AstcBlock as B;
+
+    // Each block size has UNORM and SRGB codes next to each other, and an
+    // SFLOAT code in a separate extension range, all in the same order.
+    const BLOCKS: [B; 14] = [
+        B::B4x4,
+        B::B5x4,
+        B::B5x5,
+        B::B6x5,
+        B::B6x6,
+        B::B8x5,
+        B::B8x6,
+        B::B8x8,
+        B::B10x5,
+        B::B10x6,
+        B::B10x8,
+        B::B10x10,
+        B::B12x10,
+        B::B12x12,
+    ];
+    let value = format.value();
+    let unorm = ktx2::Format::ASTC_4x4_UNORM_BLOCK.value();
+    let sfloat = ktx2::Format::ASTC_4x4_SFLOAT_BLOCK.value();
+    let (block, channel) = if (unorm..unorm + 28).contains(&value) {
+        let offset = value - unorm;
+        let channel = if offset.is_multiple_of(2) {
+            wgpu::AstcChannel::Unorm
+        } else {
+            wgpu::AstcChannel::UnormSrgb
+        };
+        (BLOCKS[offset as usize / 2], channel)
+    } else if (sfloat..sfloat + 14).contains(&value) {
+        (BLOCKS[(value - sfloat) as usize], wgpu::AstcChannel::Hdr)
+    } else {
+        return None;
+    };
+    Some(wgpu::TextureFormat::Astc { block, channel })
+}
+
+fn dxgi_format(format: ddsfile::DxgiFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::DxgiFormat as D;
+    use wgpu::TextureFormat as F;
+
+    Some(match format {
+        D::R8G8B8A8_UNorm => F::Rgba8Unorm,
+        D::R8G8B8A8_UNorm_sRGB => F::Rgba8UnormSrgb,
+        D::BC1_UNorm => F::Bc1RgbaUnorm,
+        D::BC1_UNorm_sRGB => F::Bc1RgbaUnormSrgb,
+        D::BC2_UNorm => F::Bc2RgbaUnorm,
+        D::BC2_UNorm_sRGB => F::Bc2RgbaUnormSrgb,
+        D::BC3_UNorm => F::Bc3RgbaUnorm,
+        D::BC3_UNorm_sRGB => F::Bc3RgbaUnormSrgb,
+        D::BC4_UNorm => F::Bc4RUnorm,
+        D::BC4_SNorm => F::Bc4RSnorm,
+        D::BC5_UNorm => F::Bc5RgUnorm,
+        D::BC5_SNorm => F::Bc5RgSnorm,
+        D::BC6H_UF16 => F::Bc6hRgbUfloat,
+        D::BC6H_SF16 => F::Bc6hRgbFloat,
+        D::BC7_UNorm => F::Bc7RgbaUnorm,
+        D::BC7_UNorm_sRGB => F::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+fn legacy_dds_format(dds: &ddsfile::Dds, srgb: bool) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat as F;
+
+    let pick = |linear, srgb_format| if srgb { srgb_format } else { linear };
+    match dds.get_d3d_format() {
+        Some(ddsfile::D3DFormat::DXT1) => return Some(pick(F::Bc1RgbaUnorm, F::Bc1RgbaUnormSrgb)),
+        Some(ddsfile::D3DFormat::DXT2 | ddsfile::D3DFormat::DXT3) => {
+            return Some(pick(F::Bc2RgbaUnorm, F::Bc2RgbaUnormSrgb));
+        }
+        Some(ddsfile::D3DFormat::DXT4 | ddsfile::D3DFormat::DXT5) => {
+            return Some(pick(F::Bc3RgbaUnorm, F::Bc3RgbaUnormSrgb));
+        }
+        _ => {}
+    }
+    match dds.header.spf.fourcc.as_ref()?.0 {
+        FOURCC_ATI1 | FOURCC_BC4U => Some(F::Bc4RUnorm),
+        FOURCC_ATI2 | FOURCC_BC5U => Some(F::Bc5RgUnorm),
+        _ => None,
+    }
+}
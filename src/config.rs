@@ -0,0 +1,873 @@
+use winit::keyboard::KeyCode;
+
+use crate::camera::{CameraDamping, Projection};
+use crate::deferred::RenderPath;
+use crate::input::{self, Action, Input, InputMap};
+use crate::msaa::Msaa;
+use crate::post::Fxaa;
+use crate::state::State;
+
+/// Where `run` reads the configuration from unless told otherwise with
+/// `--config <path>`, and writes it back on exit.
+pub const DEFAULT_PATH: &str = "config.toml";
+
+/// Startup settings, read from a TOML file whose tables and keys mirror
+/// these structs: `[window]`, `[camera]`, `[render]`, `[assets]`, `[keys]`
+/// and `[input]`. Anything a file leaves out, or a missing file, takes the
+/// default; a table or key it doesn't know, as from a typo, is an error.
+///
+/// A parsed config keeps the file it came from, so that `to_toml` only
+/// changes the values that differ and leaves the comments, key order and
+/// spacing alone.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub window: WindowConfig,
+    pub camera: CameraConfig,
+    pub render: RenderConfig,
+    pub assets: AssetConfig,
+    pub keys: KeyBindings,
+    /// Each action's name, such as `move_forward`, to a list of the inputs
+    /// that trigger it, named as `Input::parse` reads them.
+    pub input: InputMap,
+    document: Document,
+}
+
+// The file a `Config` was parsed from, which doesn't count towards
+// whether two are equal.
+#[derive(Debug, Clone, Default)]
+struct Document(toml_edit::DocumentMut);
+
+impl PartialEq for Document {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WindowConfig {
+    /// Inner size in physical pixels while windowed.
+    pub width: u32,
+    pub height: u32,
+    /// Borderless fullscreen on the window's monitor.
+    pub fullscreen: bool,
+    /// Presents with `Fifo`; without it, with `Mailbox` or `Immediate`
+    /// where the surface has either.
+    pub vsync: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            fullscreen: false,
+            vsync: true,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CameraConfig {
+    /// World units per update while a movement key is held.
+    pub speed: f32,
+    /// Vertical field of view in degrees.
+    pub fov: f32,
+    /// `CameraDamping`'s factors, as `movement_damping` and
+    /// `rotation_damping`.
+    pub damping: CameraDamping,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            speed: 0.1,
+            fov: 45.0,
+            damping: CameraDamping::default(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderConfig {
+    /// "forward", "forward-plus" or "deferred"; `--deferred` and
+    /// `--forward-plus` take precedence.
+    pub path: RenderPath,
+    pub taa: bool,
+    pub fxaa: bool,
+    /// Reversed depth, for scenes seen from far away; see
+    /// `State::set_reverse_z`.
+    pub reverse_z: bool,
+    /// Samples a pixel, 1 for no MSAA or `Msaa::SAMPLE_COUNT`; see
+    /// `State::set_msaa_samples`. Ignored where `State::msaa_available`
+    /// isn't.
+    pub msaa: u32,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            path: RenderPath::default(),
+            taa: true,
+            fxaa: true,
+            reverse_z: false,
+            msaa: 1,
+        }
+    }
+}
+
+/// Files under `res` loaded once the state is up.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AssetConfig {
+    /// An equirectangular HDR image for the lighting and skybox.
+    pub environment: Option<String>,
+    /// A TrueType or OpenType font for text.
+    pub font: Option<String>,
+    /// A heightmap image to build terrain from.
+    pub terrain: Option<String>,
+}
+
+/// Which key triggers each of `State`'s shortcuts, named as in
+/// `winit::keyboard::KeyCode`, or by the bare letter or digit.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct KeyBindings {
+    pub exit: KeyCode,
+    pub debug_ui: KeyCode,
+    pub stats: KeyCode,
+    pub screenshot: KeyCode,
+    pub debugger_capture: KeyCode,
+    pub projection: KeyCode,
+    pub grid: KeyCode,
+    pub cascades: KeyCode,
+    pub present_mode: KeyCode,
+    /// Held with Alt.
+    pub fullscreen: KeyCode,
+    /// Adds where the camera is to the camera path.
+    pub keyframe: KeyCode,
+    /// Starts or stops flying the camera path.
+    pub flythrough: KeyCode,
+    /// Steps the debug view through the intermediate textures. Held with
+    /// Shift, switches it between fullscreen and picture-in-picture.
+    pub debug_view: KeyCode,
+    /// Turns the editor on or off. Held with Shift, steps its gizmo
+    /// through translate, rotate and scale.
+    pub editor: KeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            exit: KeyCode::Escape,
+            debug_ui: KeyCode::F1,
+            stats: KeyCode::F3,
+            screenshot: KeyCode::F12,
+            debugger_capture: KeyCode::F11,
+            projection: KeyCode::KeyP,
+            grid: KeyCode::KeyG,
+            cascades: KeyCode::KeyC,
+            present_mode: KeyCode::KeyV,
+            fullscreen: KeyCode::Enter,
+            keyframe: KeyCode::KeyK,
+            flythrough: KeyCode::KeyL,
+            debug_view: KeyCode::KeyT,
+            editor: KeyCode::Tab,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let document: toml_edit::DocumentMut = source.parse()?;
+        let defaults = Self::default();
+        for (name, item) in document.iter() {
+            if TABLES.contains(&name) {
+                continue;
+            }
+            if item.is_table_like() {
+                anyhow::bail!("Unknown table {}", name);
+            }
+            anyhow::bail!("Unknown key {}; keys go in one of the tables", name);
+        }
+        let table = |name| Table {
+            name,
+            item: document.get(name),
+            read: Default::default(),
+        };
+
+        let window = table("window");
+        let camera = table("camera");
+        let render = table("render");
+        let assets = table("assets");
+        let keys = table("keys");
+        let input_table = table("input");
+        let mut input = defaults.input.clone();
+        for action in Action::ALL {
+            if let Some(inputs) = input_table.inputs(action.name())? {
+                input.bind(action, inputs);
+            }
+        }
+        let key = |name, default| keys.key(name).map(|key| key.unwrap_or(default));
+        let config = Self {
+            window: WindowConfig {
+                width: window.integer("width")?.unwrap_or(defaults.window.width),
+                height: window.integer("height")?.unwrap_or(defaults.window.height),
+                fullscreen: window
+                    .bool("fullscreen")?
+                    .unwrap_or(defaults.window.fullscreen),
+                vsync: window.bool("vsync")?.unwrap_or(defaults.window.vsync),
+            },
+            camera: CameraConfig {
+                speed: camera.float("speed")?.unwrap_or(defaults.camera.speed),
+                fov: camera.degrees("fov")?.unwrap_or(defaults.camera.fov),
+                damping: CameraDamping {
+                    movement: camera
+                        .fraction("movement_damping")?
+                        .unwrap_or(defaults.camera.damping.movement),
+                    rotation: camera
+                        .fraction("rotation_damping")?
+                        .unwrap_or(defaults.camera.damping.rotation),
+                },
+            },
+            render: RenderConfig {
+                path: match render.string("path")? {
+                    None => defaults.render.path,
+                    Some("forward") => RenderPath::Forward,
+                    Some("forward-plus") => RenderPath::ForwardPlus,
+                    Some("deferred") => RenderPath::Deferred,
+                    Some(path) => anyhow::bail!("Unknown render.path {:?}", path),
+                },
+                taa: render.bool("taa")?.unwrap_or(defaults.render.taa),
+                fxaa: render.bool("fxaa")?.unwrap_or(defaults.render.fxaa),
+                reverse_z: render
+                    .bool("reverse_z")?
+                    .unwrap_or(defaults.render.reverse_z),
+                msaa: render.samples("msaa")?.unwrap_or(defaults.render.msaa),
+            },
+            assets: AssetConfig {
+                environment: assets.string("environment")?.map(str::to_string),
+                font: assets.string("font")?.map(str::to_string),
+                terrain: assets.string("terrain")?.map(str::to_string),
+            },
+            keys: KeyBindings {
+                exit: key("exit", defaults.keys.exit)?,
+                debug_ui: key("debug_ui", defaults.keys.debug_ui)?,
+                stats: key("stats", defaults.keys.stats)?,
+                screenshot: key("screenshot", defaults.keys.screenshot)?,
+                debugger_capture: key("debugger_capture", defaults.keys.debugger_capture)?,
+                projection: key("projection", defaults.keys.projection)?,
+                grid: key("grid", defaults.keys.grid)?,
+                cascades: key("cascades", defaults.keys.cascades)?,
+                present_mode: key("present_mode", defaults.keys.present_mode)?,
+                fullscreen: key("fullscreen", defaults.keys.fullscreen)?,
+                keyframe: key("keyframe", defaults.keys.keyframe)?,
+                flythrough: key("flythrough", defaults.keys.flythrough)?,
+                debug_view: key("debug_view", defaults.keys.debug_view)?,
+                editor: key("editor", defaults.keys.editor)?,
+            },
+            input,
+            document: Document(document.clone()),
+        };
+        for table in [&window, &camera, &render, &assets, &keys, &input_table] {
+            table.check_unknown_keys()?;
+        }
+        Ok(config)
+    }
+
+    /// The defaults when there is no file at `path`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(source) => Self::parse(&source)
+                .map_err(|e| anyhow::anyhow!("Unable to read {}: {:#}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_toml())?;
+        Ok(())
+    }
+
+    /// The file `parse` reads back as `self`: the one it was parsed from,
+    /// if any, with the values that differ replaced in place and the
+    /// missing tables and keys added at the end.
+    pub fn to_toml(&self) -> String {
+        use toml_edit::value;
+
+        let mut document = self.document.0.clone();
+        let mut table = |name: &str, entries: Vec<(&str, toml_edit::Item)>| {
+            if !document.contains_key(name) {
+                let mut table = toml_edit::Table::new();
+                if !document.is_empty() {
+                    table.decor_mut().set_prefix("\n");
+                }
+                document.insert(name, toml_edit::Item::Table(table));
+            }
+            let Some(table) = document
+                .get_mut(name)
+                .and_then(toml_edit::Item::as_table_like_mut)
+            else {
+                return;
+            };
+            // Keys it has no value for, like an unset asset, go.
+            let stale = table
+                .iter()
+                .map(|(key, _)| key.to_string())
+                .filter(|key| !entries.iter().any(|(name, _)| name == key))
+                .collect::<Vec<_>>();
+            for key in stale {
+                table.remove(&key);
+            }
+            for (key, item) in entries {
+                set(table, key, item);
+            }
+        };
+        table(
+            "window",
+            vec![
+                ("width", value(self.window.width as i64)),
+                ("height", value(self.window.height as i64)),
+                ("fullscreen", value(self.window.fullscreen)),
+                ("vsync", value(self.window.vsync)),
+            ],
+        );
+        table(
+            "camera",
+            vec![
+                ("speed", float(self.camera.speed)),
+                ("fov", float(self.camera.fov)),
+                ("movement_damping", float(self.camera.damping.movement)),
+                ("rotation_damping", float(self.camera.damping.rotation)),
+            ],
+        );
+        let path = match self.render.path {
+            RenderPath::Forward => "forward",
+            RenderPath::ForwardPlus => "forward-plus",
+            RenderPath::Deferred => "deferred",
+        };
+        table(
+            "render",
+            vec![
+                ("path", value(path)),
+                ("taa", value(self.render.taa)),
+                ("fxaa", value(self.render.fxaa)),
+                ("reverse_z", value(self.render.reverse_z)),
+                ("msaa", value(self.render.msaa as i64)),
+            ],
+        );
+        table(
+            "assets",
+            [
+                ("environment", &self.assets.environment),
+                ("font", &self.assets.font),
+                ("terrain", &self.assets.terrain),
+            ]
+            .into_iter()
+            .filter_map(|(name, file)| Some((name, value(file.as_deref()?))))
+            .collect(),
+        );
+        let keys = &self.keys;
+        table(
+            "keys",
+            [
+                ("exit", keys.exit),
+                ("debug_ui", keys.debug_ui),
+                ("stats", keys.stats),
+                ("screenshot", keys.screenshot),
+                ("debugger_capture", keys.debugger_capture),
+                ("projection", keys.projection),
+                ("grid", keys.grid),
+                ("cascades", keys.cascades),
+                ("present_mode", keys.present_mode),
+                ("fullscreen", keys.fullscreen),
+                ("keyframe", keys.keyframe),
+                ("flythrough", keys.flythrough),
+                ("debug_view", keys.debug_view),
+                ("editor", keys.editor),
+            ]
+            .into_iter()
+            .map(|(name, key)| (name, value(format!("{:?}", key))))
+            .collect(),
+        );
+        table(
+            "input",
+            Action::ALL
+                .into_iter()
+                .map(|action| {
+                    let inputs = self.input.bindings(action).iter().map(Input::to_string);
+                    (action.name(), value(inputs.collect::<toml_edit::Array>()))
+                })
+                .collect(),
+        );
+        document.to_string()
+    }
+
+    /// Sets up a newly created `state` as configured, loading the assets.
+    /// Ones that fail to load are logged and skipped. The window size and
+    /// fullscreen mode are left to whoever creates the window.
+    pub async fn apply(&self, state: &mut State) {
+        state.camera_controller_mut().set_speed(self.camera.speed);
+        state
+            .camera_controller_mut()
+            .set_damping(self.camera.damping);
+        if let Projection::Perspective { fovy } = &mut state.camera_mut().projection {
+            *fovy = self.camera.fov;
+        }
+        let present_mode = if self.window.vsync {
+            Some(wgpu::PresentMode::Fifo)
+        } else {
+            state
+                .present_modes()
+                .into_iter()
+                .find(|&mode| mode != wgpu::PresentMode::Fifo)
+        };
+        if let Some(mode) = present_mode
+            && let Err(e) = state.set_present_mode(mode)
+        {
+            log::warn!("{}", e);
+        }
+        let mut taa = state.taa_settings();
+        taa.enabled = self.render.taa;
+        state.set_taa_settings(taa);
+        if let Some(fxaa) = state.post_effect_mut::<Fxaa>() {
+            fxaa.enabled = self.render.fxaa;
+        }
+        state.set_reverse_z(self.render.reverse_z);
+        if state.msaa_available()
+            && let Err(e) = state.set_msaa_samples(self.render.msaa)
+        {
+            log::warn!("{}", e);
+        }
+        state.set_key_bindings(self.keys);
+        state.set_input_map(self.input.clone());
+
+        if let Some(file) = &self.assets.environment
+            && let Err(e) = state.load_environment(file).await
+        {
+            log::error!("Unable to load environment {}: {:#}", file, e);
+        }
+        if let Some(file) = &self.assets.font
+            && let Err(e) = state.load_font(file).await
+        {
+            log::error!("Unable to load font {}: {:#}", file, e);
+        }
+        if let Some(file) = &self.assets.terrain
+            && let Err(e) = state.load_terrain(file, Default::default()).await
+        {
+            log::error!("Unable to load terrain {}: {:#}", file, e);
+        }
+    }
+
+    /// Takes in what may have changed while `state` ran: the window size
+    /// and fullscreen mode, vsync, the camera speed, damping and field of
+    /// view, whether TAA, FXAA and reversed depth are on, and the MSAA
+    /// samples where MSAA is available.
+    pub fn update_from(&mut self, state: &mut State) {
+        if let Some(window) = state.window() {
+            self.window.fullscreen = window.fullscreen().is_some();
+            if !self.window.fullscreen {
+                let size = window.inner_size();
+                self.window.width = size.width;
+                self.window.height = size.height;
+            }
+        }
+        if !state.present_modes().is_empty() {
+            self.window.vsync = state.present_mode() == wgpu::PresentMode::Fifo;
+        }
+        self.camera.speed = state.camera_controller().speed();
+        self.camera.damping = state.camera_controller().damping();
+        if let Projection::Perspective { fovy } = state.camera().projection {
+            self.camera.fov = fovy;
+        }
+        self.render.taa = state.taa_settings().enabled;
+        if let Some(fxaa) = state.post_effect_mut::<Fxaa>() {
+            self.render.fxaa = fxaa.enabled;
+        }
+        self.render.reverse_z = state.reverse_z();
+        if state.msaa_available() {
+            self.render.msaa = state.msaa_samples();
+        }
+        self.keys = state.key_bindings();
+        self.input = state.input_map().clone();
+    }
+}
+
+// Sets `key` to `item`, unless it already reads as the same, keeping the
+// comments and spacing around the old value.
+fn set(table: &mut dyn toml_edit::TableLike, key: &str, item: toml_edit::Item) {
+    let (Some(old), Some(mut new)) = (
+        table.get_mut(key).and_then(toml_edit::Item::as_value_mut),
+        item.as_value().cloned(),
+    ) else {
+        table.insert(key, item);
+        return;
+    };
+    if !same(old, &new) {
+        *new.decor_mut() = old.decor().clone();
+        *old = new;
+    }
+}
+
+// Whether `a` and `b` read as the same, however they are written: `70`
+// and `70.0` are, as are lists of the same items spaced differently.
+fn same(a: &toml_edit::Value, b: &toml_edit::Value) -> bool {
+    use toml_edit::Value;
+
+    let number = |value: &Value| {
+        value
+            .as_float()
+            .or_else(|| value.as_integer().map(|value| value as f64))
+    };
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| same(a, b))
+        }
+        _ => number(a).is_some() && number(a) == number(b),
+    }
+}
+
+// `value` as the shortest decimal that reads back as it, rather than the
+// digits widening it to an `f64` leaves.
+fn float(value: f32) -> toml_edit::Item {
+    toml_edit::value(value.to_string().parse::<f64>().unwrap_or(value as f64))
+}
+
+/// The tables a file may have.
+const TABLES: &[&str] = &["window", "camera", "render", "assets", "keys", "input"];
+
+/// One of the file's tables, which may be missing.
+struct Table<'a> {
+    name: &'a str,
+    item: Option<&'a toml_edit::Item>,
+    // Every key looked up so far, there or not.
+    read: std::cell::RefCell<Vec<String>>,
+}
+
+impl<'a> Table<'a> {
+    fn value<T>(
+        &self,
+        key: &str,
+        expected: &str,
+        read: impl FnOnce(&'a toml_edit::Item) -> Option<T>,
+    ) -> anyhow::Result<Option<T>> {
+        self.read.borrow_mut().push(key.to_string());
+        let Some(item) = self.item.and_then(|table| table.get(key)) else {
+            return Ok(None);
+        };
+        match read(item) {
+            Some(value) => Ok(Some(value)),
+            None => match item.as_value() {
+                Some(value) => anyhow::bail!(
+                    "{}.{} should be {}, not {}",
+                    self.name,
+                    key,
+                    expected,
+                    value.to_string().trim()
+                ),
+                None => anyhow::bail!(
+                    "{}.{} should be {}, not a {}",
+                    self.name,
+                    key,
+                    expected,
+                    item.type_name()
+                ),
+            },
+        }
+    }
+
+    /// Fails on the first key in the table that nothing has read, once
+    /// everything has been; and if it's there but isn't a table.
+    fn check_unknown_keys(&self) -> anyhow::Result<()> {
+        let Some(item) = self.item else {
+            return Ok(());
+        };
+        let Some(table) = item.as_table_like() else {
+            anyhow::bail!("{} should be a table, not {}", self.name, item.type_name());
+        };
+        let read = self.read.borrow();
+        match table
+            .iter()
+            .find(|(key, _)| !read.iter().any(|read| read == key))
+        {
+            Some((key, _)) => anyhow::bail!("Unknown key {}.{}", self.name, key),
+            None => Ok(()),
+        }
+    }
+
+    fn integer(&self, key: &str) -> anyhow::Result<Option<u32>> {
+        self.value(key, "a positive integer", |item| {
+            item.as_integer()
+                .and_then(|value| value.try_into().ok())
+                .filter(|&value| value > 0)
+        })
+    }
+
+    /// A finite number `valid` accepts, integer or not.
+    fn number(
+        &self,
+        key: &str,
+        expected: &str,
+        valid: impl FnOnce(f32) -> bool,
+    ) -> anyhow::Result<Option<f32>> {
+        self.value(key, expected, |item| {
+            item.as_float()
+                .or_else(|| item.as_integer().map(|value| value as f64))
+                .map(|value| value as f32)
+                .filter(|&value| value.is_finite() && valid(value))
+        })
+    }
+
+    fn float(&self, key: &str) -> anyhow::Result<Option<f32>> {
+        self.number(key, "a positive number", |value| value > 0.0)
+    }
+
+    /// A field of view, wider than nothing and narrower than a half turn.
+    fn degrees(&self, key: &str) -> anyhow::Result<Option<f32>> {
+        self.number(key, "between 0 and 180 degrees", |value| {
+            value > 0.0 && value < 180.0
+        })
+    }
+
+    /// A damping factor, from 0 up to but not including 1.
+    fn fraction(&self, key: &str) -> anyhow::Result<Option<f32>> {
+        self.number(key, "at least 0 and less than 1", |value| {
+            (0.0..1.0).contains(&value)
+        })
+    }
+
+    /// A sample count MSAA takes.
+    fn samples(&self, key: &str) -> anyhow::Result<Option<u32>> {
+        let expected = format!("1 or {}", Msaa::SAMPLE_COUNT);
+        self.value(key, &expected, |item| {
+            item.as_integer()
+                .filter(|&samples| samples == 1 || samples == Msaa::SAMPLE_COUNT as i64)
+                .map(|samples| samples as u32)
+        })
+    }
+
+    fn bool(&self, key: &str) -> anyhow::Result<Option<bool>> {
+        self.value(key, "true or false", toml_edit::Item::as_bool)
+    }
+
+    fn string(&self, key: &str) -> anyhow::Result<Option<&'a str>> {
+        self.value(key, "a string", toml_edit::Item::as_str)
+    }
+
+    fn key(&self, key: &str) -> anyhow::Result<Option<KeyCode>> {
+        match self.string(key)? {
+            Some(name) => match input::parse_key(name) {
+                Some(code) => Ok(Some(code)),
+                None => anyhow::bail!("{}.{} names no known key: {:?}", self.name, key, name),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// A list of input names, or just one.
+    fn inputs(&self, key: &str) -> anyhow::Result<Option<Vec<Input>>> {
+        let names = self.value(key, "a list of input names", |item| match item.as_str() {
+            Some(name) => Some(vec![name]),
+            None => item.as_array()?.iter().map(|name| name.as_str()).collect(),
+        })?;
+        let Some(names) = names else {
+            return Ok(None);
+        };
+        names
+            .into_iter()
+            .map(|name| match Input::parse(name) {
+                Some(input) => Ok(input),
+                None => anyhow::bail!("{}.{} names no known input: {:?}", self.name, key, name),
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        format!("{:#}", Config::parse(source).unwrap_err())
+    }
+
+    #[test]
+    fn missing_tables_and_keys_take_the_defaults() {
+        let config = Config::parse("[camera]\nfov = 70\n\n[keys]\nexit = \"Q\"\n").unwrap();
+        let defaults = Config::default();
+        assert_eq!(config.camera.fov, 70.0);
+        assert_eq!(config.camera.speed, defaults.camera.speed);
+        assert_eq!(config.keys.exit, KeyCode::KeyQ);
+        assert_eq!(config.keys.stats, defaults.keys.stats);
+        assert_eq!(config.window, defaults.window);
+        assert_eq!(config.input, defaults.input);
+        assert_eq!(Config::parse("").unwrap(), defaults);
+    }
+
+    #[test]
+    fn unknown_tables_and_keys_are_errors() {
+        assert_eq!(error("[windows]\nwidth = 640\n"), "Unknown table windows");
+        assert_eq!(
+            error("msaa = 4\n"),
+            "Unknown key msaa; keys go in one of the tables"
+        );
+        assert_eq!(error("[msaa]\nsamples = 4\n"), "Unknown table msaa");
+        assert_eq!(error("[window]\nwidht = 640\n"), "Unknown key window.widht");
+        assert_eq!(
+            error("[input]\nmove_forwards = [\"KeyW\"]\n"),
+            "Unknown key input.move_forwards"
+        );
+        assert_eq!(
+            error("window = 3\n"),
+            "window should be a table, not integer"
+        );
+    }
+
+    #[test]
+    fn wrong_types_name_the_key_and_the_value() {
+        assert_eq!(
+            error("[window]\nwidth = \"wide\"\n"),
+            "window.width should be a positive integer, not \"wide\""
+        );
+        assert_eq!(
+            error("[window]\nheight = -1\n"),
+            "window.height should be a positive integer, not -1"
+        );
+        assert_eq!(
+            error("[window]\nvsync = 1\n"),
+            "window.vsync should be true or false, not 1"
+        );
+        assert_eq!(
+            error("[camera]\nspeed = \"fast\"\n"),
+            "camera.speed should be a positive number, not \"fast\""
+        );
+        assert_eq!(
+            error("[assets]\nfont = [\"a.ttf\"]\n"),
+            "assets.font should be a string, not [\"a.ttf\"]"
+        );
+        assert_eq!(
+            error("[input]\nmove_up = 3\n"),
+            "input.move_up should be a list of input names, not 3"
+        );
+    }
+
+    #[test]
+    fn msaa_takes_one_or_the_sample_count() {
+        assert_eq!(Config::default().render.msaa, 1);
+        let config = Config::parse("[render]\nmsaa = 4\n").unwrap();
+        assert_eq!(config.render.msaa, Msaa::SAMPLE_COUNT);
+        assert_eq!(
+            Config::parse(&config.to_toml()).unwrap().render.msaa,
+            Msaa::SAMPLE_COUNT
+        );
+        assert_eq!(
+            error("[render]\nmsaa = 3\n"),
+            "render.msaa should be 1 or 4, not 3"
+        );
+    }
+
+    #[test]
+    fn zero_window_sizes_are_errors() {
+        assert_eq!(
+            error("[window]\nwidth = 0\n"),
+            "window.width should be a positive integer, not 0"
+        );
+    }
+
+    #[test]
+    fn out_of_range_numbers_are_errors() {
+        for speed in ["inf", "-inf", "nan", "0", "-0.5"] {
+            assert_eq!(
+                error(&format!("[camera]\nspeed = {}\n", speed)),
+                format!("camera.speed should be a positive number, not {}", speed)
+            );
+        }
+        for fov in ["0", "180", "-45", "nan"] {
+            assert_eq!(
+                error(&format!("[camera]\nfov = {}\n", fov)),
+                format!(
+                    "camera.fov should be between 0 and 180 degrees, not {}",
+                    fov
+                )
+            );
+        }
+        assert_eq!(
+            error("[camera]\nmovement_damping = 1\n"),
+            "camera.movement_damping should be at least 0 and less than 1, not 1"
+        );
+        assert_eq!(
+            error("[camera]\nrotation_damping = -0.1\n"),
+            "camera.rotation_damping should be at least 0 and less than 1, not -0.1"
+        );
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        assert_eq!(
+            error("[render]\npath = \"sideways\"\n"),
+            "Unknown render.path \"sideways\""
+        );
+        assert_eq!(
+            error("[keys]\nexit = \"Hyper\"\n"),
+            "keys.exit names no known key: \"Hyper\""
+        );
+        assert_eq!(
+            error("[input]\nmove_up = [\"KeyE\", \"Nope\"]\n"),
+            "input.move_up names no known input: \"Nope\""
+        );
+    }
+
+    #[test]
+    fn to_toml_writes_strings_and_floats_toml_reads_back() {
+        let mut config = Config::default();
+        config.assets.font = Some("tab\there \"quoted\" \\ \u{1} é.ttf".to_string());
+        config.camera.speed = 3.3;
+        config.camera.fov = 0.1;
+        let toml = config.to_toml();
+        assert!(toml.contains("speed = 3.3\n"), "{}", toml);
+        assert!(toml.contains("fov = 0.1\n"), "{}", toml);
+        assert_eq!(Config::parse(&toml).unwrap(), config);
+    }
+
+    #[test]
+    fn to_toml_keeps_the_parsed_files_layout() {
+        let source = "# Mine.\n\
+            [camera]\n\
+            fov   = 70 # Wide.\n\n\
+            [window]\n\
+            # Not too big.\n\
+            height = 480\n\
+            width = 640\n\n\
+            [assets]\n\
+            font = \"a.ttf\"\n";
+        let mut config = Config::parse(source).unwrap();
+        config.window.width = 1024;
+        config.assets.font = None;
+        let toml = config.to_toml();
+        assert!(
+            toml.starts_with(
+                "# Mine.\n\
+                [camera]\n\
+                fov   = 70 # Wide.\n"
+            ),
+            "{}",
+            toml
+        );
+        assert!(
+            toml.contains(
+                "[window]\n\
+                # Not too big.\n\
+                height = 480\n\
+                width = 1024\n"
+            ),
+            "{}",
+            toml
+        );
+        assert!(!toml.contains("font"), "{}", toml);
+        assert_eq!(Config::parse(&toml).unwrap(), config);
+    }
+}
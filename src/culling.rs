@@ -0,0 +1,524 @@
+use cgmath::{EuclideanSpace, InnerSpace, MetricSpace};
+
+use crate::camera::Camera;
+use crate::gpu_culling::GpuCulling;
+use crate::instance::{InstanceBuffer, InstanceRaw};
+use crate::lod::LodInstances;
+use crate::material_arrays::MaterialArrays;
+use crate::model::{self, Bounds, DrawModel};
+
+/// Points `p` with `dot(normal, p) + d >= 0` are on the inner side.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Plane {
+    pub normal: cgmath::Vector3<f32>,
+    pub d: f32,
+}
+
+impl Plane {
+    /// Normalizes `(a, b, c, d)` so `signed_distance` is in world units.
+    pub fn new(plane: cgmath::Vector4<f32>) -> Self {
+        let length = plane.truncate().magnitude().max(f32::EPSILON);
+        Self {
+            normal: plane.truncate() / length,
+            d: plane.w / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: cgmath::Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) + self.d
+    }
+}
+
+/// The six planes bounding what a camera sees, facing inwards, in the order
+/// left, right, bottom, top, near, far.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    pub fn from_view_proj(camera: &Camera) -> Self {
+        Self::from_matrix(camera.build_view_projection_matrix())
+    }
+
+    /// Extracts the planes from the rows of a world-to-clip matrix using
+    /// wgpu's clip space, where depth runs from 0 to 1.
+    pub fn from_matrix(view_proj: cgmath::Matrix4<f32>) -> Self {
+        use cgmath::Matrix;
+
+        let m = view_proj.transpose();
+        let (x, y, z, w) = (m.x, m.y, m.z, m.w);
+        Self {
+            planes: [w + x, w - x, w + y, w - y, z, w - z].map(Plane::new),
+        }
+    }
+
+    pub fn contains_point(&self, point: cgmath::Point3<f32>) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    pub fn intersects_sphere(&self, center: cgmath::Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(center) >= -radius)
+    }
+
+    /// Conservative: a large box just outside a corner of the frustum can
+    /// still pass, but a box that is partly inside never fails.
+    pub fn intersects_aabb(&self, bounds: &Bounds) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane's normal.
+            let corner = cgmath::Point3::new(
+                if plane.normal.x >= 0.0 {
+                    bounds.max.x
+                } else {
+                    bounds.min.x
+                },
+                if plane.normal.y >= 0.0 {
+                    bounds.max.y
+                } else {
+                    bounds.min.y
+                },
+                if plane.normal.z >= 0.0 {
+                    bounds.max.z
+                } else {
+                    bounds.min.z
+                },
+            );
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
+}
+
+/// For each mesh of `model`, whether any of `instances` places its bounds
+/// inside `frustum`. Meshes without one can skip their draw call.
+pub fn visible_meshes(
+    frustum: &Frustum,
+    model: &model::Model,
+    instances: &[InstanceRaw],
+) -> Vec<bool> {
+    model
+        .meshes
+        .iter()
+        .map(|mesh| {
+            instances.iter().any(|instance| {
+                let world = mesh.bounds.transformed(&instance.model.into());
+                frustum.intersects_aabb(&world)
+            })
+        })
+        .collect()
+}
+
+/// One instance of one mesh, drawn on its own so blended surfaces can be
+/// ordered.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SortedDraw {
+    pub mesh: usize,
+    pub instance: u32,
+    /// From `eye` to the center of the instance's bounds.
+    pub distance: f32,
+}
+
+/// Every instance of the meshes flagged in `meshes`, farthest from `eye`
+/// first, leaving out those outside `frustum` when one is given.
+///
+/// Sorting by bounds keeps whole meshes apart but not the triangles of one
+/// mesh, so a single mesh folding over itself can still blend out of order.
+pub fn back_to_front(
+    eye: cgmath::Point3<f32>,
+    frustum: Option<&Frustum>,
+    model: &model::Model,
+    instances: &[InstanceRaw],
+    meshes: &[bool],
+) -> Vec<SortedDraw> {
+    let mut draws = Vec::new();
+    for (i, mesh) in model.meshes.iter().enumerate() {
+        if !meshes.get(i).copied().unwrap_or(false) {
+            continue;
+        }
+        for (instance, raw) in instances.iter().enumerate() {
+            let world = mesh.bounds.transformed(&raw.model.into());
+            if frustum.is_some_and(|frustum| !frustum.intersects_aabb(&world)) {
+                continue;
+            }
+            let center = world.center();
+            draws.push(SortedDraw {
+                mesh: i,
+                instance: instance as u32,
+                distance: eye.distance(center),
+            });
+        }
+    }
+    draws.sort_by(|a, b| b.distance.total_cmp(&a.distance));
+    draws
+}
+
+/// The instance buffer range `DrawInstances` draws from, and GPU
+/// culling's draws with whether they're batched, when it is running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceSource {
+    buffer: wgpu::Buffer,
+    size: wgpu::BufferSize,
+    draws: Option<(wgpu::Buffer, bool)>,
+}
+
+/// What a camera pass draws: the instances, or the survivors of
+/// `GpuCulling` when it is running, or of `LodInstances` at their levels
+/// of detail when they are on, for the meshes not culled on the CPU.
+#[derive(Clone, Copy)]
+pub struct DrawInstances<'a> {
+    instances: &'a InstanceBuffer,
+    gpu_culling: Option<&'a GpuCulling>,
+    lods: Option<&'a LodInstances>,
+    visible_meshes: Option<&'a [bool]>,
+    texture_arrays: Option<&'a MaterialArrays>,
+}
+
+impl<'a> DrawInstances<'a> {
+    /// Reads from `gpu_culling` when it is ready.
+    pub fn new(instances: &'a InstanceBuffer, gpu_culling: Option<&'a GpuCulling>) -> Self {
+        Self {
+            instances,
+            gpu_culling: gpu_culling.filter(|culling| culling.is_ready()),
+            lods: None,
+            visible_meshes: None,
+            texture_arrays: None,
+        }
+    }
+
+    /// Reads from `lods` instead of the instances or `GpuCulling` when it
+    /// is enabled, as it culls to the view itself.
+    pub fn with_lods(mut self, lods: &'a LodInstances) -> Self {
+        if lods.is_enabled() {
+            self.gpu_culling = None;
+            self.lods = Some(lods);
+        }
+        self
+    }
+
+    /// Has `draw_model` read every material from `arrays`, for a pipeline
+    /// built for `PipelineKey::texture_arrays`, so meshes of different
+    /// materials go in one multi-draw. Only taken when batched, as only
+    /// `GpuCulling`'s merged buffers carry the vertices' material IDs.
+    pub fn with_texture_arrays(mut self, arrays: &'a MaterialArrays) -> Self {
+        if self.is_batched() {
+            self.texture_arrays = Some(arrays);
+        }
+        self
+    }
+
+    /// Skips the meshes whose flag, as from `visible_meshes`, is false.
+    pub fn with_visible_meshes(mut self, visible_meshes: &'a [bool]) -> Self {
+        self.visible_meshes = Some(visible_meshes);
+        self
+    }
+
+    /// Whether the draws go through `GpuCulling`'s merged buffers, as runs
+    /// of multi-draws.
+    pub fn is_batched(&self) -> bool {
+        self.batched().is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self.lods {
+            Some(lods) => lods.is_empty(),
+            None => self.instances.is_empty(),
+        }
+    }
+
+    pub fn is_visible(&self, index: usize) -> bool {
+        self.visible_meshes
+            .is_none_or(|visible| visible.get(index).copied().unwrap_or(true))
+    }
+
+    /// What these draw from besides the meshes, for telling when draws
+    /// recorded from them have gone stale; None when drawing from
+    /// `LodInstances`, whose instances move between levels every update.
+    pub fn source(&self) -> Option<InstanceSource> {
+        if self.lods.is_some() {
+            return None;
+        }
+        let slice = self.slice();
+        Some(InstanceSource {
+            buffer: slice.buffer().clone(),
+            size: slice.size(),
+            draws: self
+                .gpu_culling
+                .map(|culling| (culling.draws_buffer().clone(), culling.is_batched())),
+        })
+    }
+
+    /// The vertex buffer to bind at slot 1; only valid when not empty.
+    pub fn slice(&self) -> wgpu::BufferSlice<'a> {
+        if let Some(lods) = self.lods {
+            return lods.slice();
+        }
+        match self.gpu_culling {
+            Some(culling) => culling
+                .culled_buffer()
+                .slice(..(self.instances.len() * size_of::<InstanceRaw>()) as wgpu::BufferAddress),
+            None => self.instances.slice(),
+        }
+    }
+
+    // The levels of detail to draw and the instances at each, when drawing
+    // from `LodInstances`.
+    fn levels(&self) -> impl Iterator<Item = (usize, std::ops::Range<u32>)> + 'a {
+        self.lods
+            .into_iter()
+            .flat_map(|lods| lods.ranges().iter().cloned().enumerate())
+            .filter(|(_, range)| !range.is_empty())
+    }
+
+    // `gpu_culling` when its draws are batched.
+    fn batched(&self) -> Option<&'a GpuCulling> {
+        self.gpu_culling.filter(|culling| culling.is_batched())
+    }
+
+    // `runs` of `culling`'s draw order.
+    fn runs(&self, culling: &GpuCulling, key: impl Fn(usize) -> usize) -> Vec<(usize, u32)> {
+        runs(culling.draw_order(), |mesh| self.is_visible(mesh), key)
+    }
+
+    /// Draws the `index`th mesh of the model with whatever is bound, unless
+    /// it was culled, into a pass or a render bundle.
+    pub fn draw_mesh<'p>(
+        &self,
+        pass: &mut impl wgpu::util::RenderEncoder<'p>,
+        index: usize,
+        mesh: &'p model::Mesh,
+    ) where
+        'a: 'p,
+    {
+        if !self.is_visible(index) {
+            return;
+        }
+        if let Some(culling) = self.batched() {
+            culling.set_merged_buffers(pass);
+            pass.draw_indexed_indirect(culling.draws_buffer(), culling.draw_offset(index));
+            return;
+        }
+        mesh.set_buffers(pass);
+        if self.lods.is_some() {
+            for (level, instances) in self.levels() {
+                pass.draw_indexed(mesh.indices(level), 0, instances);
+            }
+            return;
+        }
+        match self.gpu_culling {
+            Some(culling) => {
+                pass.draw_indexed_indirect(culling.draws_buffer(), culling.draw_offset(index))
+            }
+            None => pass.draw_indexed(0..mesh.num_elements, 0, 0..self.instances.len() as u32),
+        }
+    }
+
+    /// `draw_mesh` for every mesh of `model`, as a few multi-draws when
+    /// `GpuCulling` batches them.
+    pub fn draw_meshes<'p>(&self, pass: &mut wgpu::RenderPass<'p>, model: &'p model::Model)
+    where
+        'a: 'p,
+    {
+        let Some(culling) = self.batched() else {
+            for (i, mesh) in model.meshes.iter().enumerate() {
+                self.draw_mesh(pass, i, mesh);
+            }
+            return;
+        };
+        culling.set_merged_buffers(pass);
+        for (mesh, count) in self.runs(culling, |_| 0) {
+            pass.multi_draw_indexed_indirect(
+                culling.draws_buffer(),
+                culling.draw_offset(mesh),
+                count,
+            );
+        }
+    }
+
+    /// `DrawModel::draw_model_instanced` over these instances.
+    pub fn draw_model<'p>(
+        &self,
+        pass: &mut wgpu::RenderPass<'p>,
+        model: &'p model::Model,
+        camera_bind_group: &'p wgpu::BindGroup,
+        light_bind_group: &'p wgpu::BindGroup,
+    ) where
+        'a: 'p,
+    {
+        if let (Some(culling), Some(arrays)) = (self.batched(), self.texture_arrays) {
+            culling.set_merged_buffers(pass);
+            pass.set_bind_group(0, arrays.bind_group(), &[]);
+            pass.set_bind_group(1, camera_bind_group, &[]);
+            pass.set_bind_group(2, light_bind_group, &[]);
+            for (mesh, count) in self.runs(culling, |_| 0) {
+                pass.multi_draw_indexed_indirect(
+                    culling.draws_buffer(),
+                    culling.draw_offset(mesh),
+                    count,
+                );
+            }
+            return;
+        }
+        if let Some(culling) = self.batched() {
+            culling.set_merged_buffers(pass);
+            for (mesh, count) in self.runs(culling, |mesh| model.meshes[mesh].material) {
+                let material = &model.materials[model.meshes[mesh].material];
+                pass.set_bind_group(0, &material.bind_group, &[]);
+                pass.set_bind_group(1, camera_bind_group, &[]);
+                pass.set_bind_group(2, light_bind_group, &[]);
+                pass.multi_draw_indexed_indirect(
+                    culling.draws_buffer(),
+                    culling.draw_offset(mesh),
+                    count,
+                );
+            }
+            return;
+        }
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            if !self.is_visible(i) {
+                continue;
+            }
+            let material = &model.materials[mesh.material];
+            if self.lods.is_some() {
+                for (level, instances) in self.levels() {
+                    pass.draw_mesh_lod(
+                        mesh,
+                        material,
+                        level,
+                        instances,
+                        camera_bind_group,
+                        light_bind_group,
+                    );
+                }
+                continue;
+            }
+            match self.gpu_culling {
+                Some(culling) => pass.draw_mesh_indirect(
+                    mesh,
+                    material,
+                    culling.draws_buffer(),
+                    culling.draw_offset(i),
+                    camera_bind_group,
+                    light_bind_group,
+                ),
+                None => pass.draw_mesh_instanced(
+                    mesh,
+                    material,
+                    0..self.instances.len() as u32,
+                    camera_bind_group,
+                    light_bind_group,
+                ),
+            }
+        }
+    }
+}
+
+// Runs of visible meshes with their draws next to each other in a draws
+// buffer holding them in `draw_order`, as each run's first mesh and how
+// many there are, split too between meshes of a different `key`.
+fn runs(
+    draw_order: &[usize],
+    is_visible: impl Fn(usize) -> bool,
+    key: impl Fn(usize) -> usize,
+) -> Vec<(usize, u32)> {
+    let mut runs: Vec<(usize, u32)> = Vec::new();
+    let mut last = None;
+    for (slot, &mesh) in draw_order.iter().enumerate() {
+        if !is_visible(mesh) {
+            continue;
+        }
+        match (runs.last_mut(), last) {
+            (Some(run), Some((previous, previous_key)))
+                if previous + 1 == slot && previous_key == key(mesh) =>
+            {
+                run.1 += 1
+            }
+            _ => runs.push((mesh, 1)),
+        }
+        last = Some((slot, key(mesh)));
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Projection;
+
+    // Looking down -z from 5 units out, seeing 1 to 10 units ahead.
+    fn frustum(reverse_z: bool) -> Frustum {
+        Frustum::from_view_proj(&Camera {
+            eye: (0.0, 0.0, 5.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 1.0,
+            projection: Projection::Perspective { fovy: 60.0 },
+            znear: 1.0,
+            zfar: 10.0,
+            reverse_z,
+        })
+    }
+
+    fn cube(center: [f32; 3], half: f32) -> Bounds {
+        let center = cgmath::Point3::from(center);
+        let half = cgmath::Vector3::new(half, half, half);
+        Bounds {
+            min: center - half,
+            max: center + half,
+        }
+    }
+
+    #[test]
+    fn aabbs_inside_outside_and_straddling() {
+        for reverse_z in [false, true] {
+            let frustum = frustum(reverse_z);
+            assert!(frustum.intersects_aabb(&cube([0.0, 0.0, 0.0], 0.5)));
+            // Behind the eye, past the far plane and off to either side.
+            assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, 8.0], 0.5)));
+            assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, -8.0], 0.5)));
+            assert!(!frustum.intersects_aabb(&cube([20.0, 0.0, 0.0], 0.5)));
+            assert!(!frustum.intersects_aabb(&cube([0.0, -20.0, 0.0], 0.5)));
+            // Across the near plane, the far plane and a side.
+            assert!(frustum.intersects_aabb(&cube([0.0, 0.0, 4.0], 0.5)));
+            assert!(frustum.intersects_aabb(&cube([0.0, 0.0, -5.0], 0.5)));
+            assert!(frustum.intersects_aabb(&cube([3.0, 0.0, 0.0], 0.5)));
+            // Enclosing the whole frustum.
+            assert!(frustum.intersects_aabb(&cube([0.0, 0.0, 0.0], 50.0)));
+        }
+    }
+
+    #[test]
+    fn points_and_spheres_match_the_planes() {
+        let frustum = frustum(false);
+        assert!(frustum.contains_point((0.0, 0.0, 0.0).into()));
+        assert!(!frustum.contains_point((0.0, 0.0, 4.5).into()));
+        assert!(frustum.intersects_sphere((0.0, 0.0, 4.5).into(), 1.0));
+        assert!(!frustum.intersects_sphere((0.0, 0.0, -8.0).into(), 2.0));
+    }
+
+    #[test]
+    fn runs_join_neighbouring_draws_of_one_key() {
+        let all = |_| true;
+        assert_eq!(runs(&[0, 1, 2, 3], all, |_| 0), [(0, 4)]);
+        // The draws are in slot order, which need not be mesh order.
+        assert_eq!(runs(&[2, 0, 3, 1], all, |_| 0), [(2, 4)]);
+        assert_eq!(runs(&[], all, |_| 0), []);
+    }
+
+    #[test]
+    fn runs_split_between_keys() {
+        let materials = [0, 0, 1, 1, 0];
+        let split = runs(&[0, 1, 2, 3, 4], |_| true, |mesh| materials[mesh]);
+        assert_eq!(split, [(0, 2), (2, 2), (4, 1)]);
+    }
+
+    #[test]
+    fn runs_split_around_hidden_meshes() {
+        let visible = [true, true, false, true, false, false, true];
+        let split = runs(&[0, 1, 2, 3, 4, 5, 6], |mesh| visible[mesh], |_| 0);
+        assert_eq!(split, [(0, 2), (3, 1), (6, 1)]);
+        assert_eq!(runs(&[0, 1], |_| false, |_| 0), []);
+    }
+}
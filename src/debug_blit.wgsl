@@ -0,0 +1,82 @@
+// Shows one of the renderer's textures over a whole window, read texel by
+// texel so unfilterable formats work too. Depth textures are bound as
+// unfilterable floats, as in deferred.wgsl, with depth in red.
+
+@group(0) @binding(0)
+var t_texture: texture_2d<f32>;
+
+// Bound instead of `t_texture` for `fs_layer_depth`, since WebGL can't view
+// a single layer of an array as a 2D texture.
+@group(0) @binding(1)
+var t_layers: texture_2d_array<f32>;
+// The layer in `x`, padded to the 16 bytes WebGL needs of a uniform.
+@group(0) @binding(2)
+var<uniform> layer: vec4<u32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+fn texel(uv: vec2<f32>) -> vec4<f32> {
+    let size = textureDimensions(t_texture);
+    let pixel = min(vec2<u32>(uv * vec2<f32>(size)), size - 1u);
+    return textureLoad(t_texture, pixel, 0);
+}
+
+// Inverse of `octahedral_encode` in shader.wgsl.
+fn octahedral_decode(e: vec2<f32>) -> vec3<f32> {
+    var n = vec3<f32>(e, 1.0 - abs(e.x) - abs(e.y));
+    let t = max(-n.z, 0.0);
+    n.x += select(t, -t, n.x >= 0.0);
+    n.y += select(t, -t, n.y >= 0.0);
+    return normalize(n);
+}
+
+@fragment
+fn fs_color(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(texel(in.uv).rgb, 1.0);
+}
+
+@fragment
+fn fs_red(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(vec3<f32>(texel(in.uv).r), 1.0);
+}
+
+// World-space normals from the G-buffer, mapped from [-1, 1] to [0, 1].
+@fragment
+fn fs_normal(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = octahedral_decode(texel(in.uv).xy);
+    return vec4<f32>(normal * 0.5 + 0.5, 1.0);
+}
+
+// Perspective depth crowds towards 1; the power spreads it back out so
+// nearby surfaces are dark and distant ones bright.
+@fragment
+fn fs_perspective_depth(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(vec3<f32>(pow(texel(in.uv).r, 512.0)), 1.0);
+}
+
+// Reversed depth crowds towards 0 instead, so it's flipped first.
+@fragment
+fn fs_reversed_depth(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(vec3<f32>(pow(1.0 - texel(in.uv).r, 512.0)), 1.0);
+}
+
+// Orthographic depth, such as the shadow cascades', is already linear.
+@fragment
+fn fs_layer_depth(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = textureDimensions(t_layers);
+    let pixel = min(vec2<u32>(in.uv * vec2<f32>(size)), size - 1u);
+    return vec4<f32>(vec3<f32>(textureLoad(t_layers, pixel, layer.x, 0).r), 1.0);
+}
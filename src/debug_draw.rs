@@ -0,0 +1,378 @@
+use cgmath::prelude::*;
+
+use crate::model::Bounds;
+use crate::post::taa::Taa;
+use crate::preprocess::{ShaderDefs, ShaderLibrary};
+use crate::texture;
+use crate::vertex::Vertex;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+crate::impl_vertex!(LineVertex, Vertex {
+    position => 0: Float32x3,
+    color => 1: Float32x4,
+});
+
+/// Immediate-mode lines drawn over the scene, for seeing bounds, lights
+/// and the like.
+///
+/// Everything added is drawn by the next render and then cleared, so
+/// callers add their shapes again every frame. Colors are linear and go
+/// through tonemapping with the scene; alpha blends. Lines hide behind
+/// what is in front of them unless `set_depth_test(false)`, or unless
+/// added through `overlay`.
+pub struct DebugDraw {
+    vertices: Vec<LineVertex>,
+    // Drawn after `vertices`, over everything.
+    overlay: Vec<LineVertex>,
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_test: bool,
+    reverse_z: bool,
+    pipeline: wgpu::RenderPipeline,
+    // `pipeline` for a reversed depth buffer.
+    reverse_z_pipeline: wgpu::RenderPipeline,
+    overlay_pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    // Vertices in `buffer` as of the last `upload`, the overlay's last.
+    uploaded: u32,
+    uploaded_overlay: u32,
+}
+
+impl DebugDraw {
+    const MIN_CAPACITY: usize = 1024;
+    const CIRCLE_SEGMENTS: usize = 32;
+
+    /// `camera_layout` is the scene's camera group, of which only the
+    /// `CameraUniform` is read.
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+        camera_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<Self> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("DebugDraw::layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let [pipeline, reverse_z_pipeline, overlay_pipeline] =
+            Self::create_pipelines(device, shaders, &pipeline_layout, color_format)?;
+
+        Ok(Self {
+            vertices: Vec::new(),
+            overlay: Vec::new(),
+            pipeline_layout,
+            color_format,
+            depth_test: true,
+            reverse_z: false,
+            pipeline,
+            reverse_z_pipeline,
+            overlay_pipeline,
+            buffer: Self::create_buffer(device, Self::MIN_CAPACITY),
+            capacity: Self::MIN_CAPACITY,
+            uploaded: 0,
+            uploaded_overlay: 0,
+        })
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+        pipeline_layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<[wgpu::RenderPipeline; 3]> {
+        let shader = shaders.create_module(
+            device,
+            "DebugDraw::shader",
+            "debug_draw.wgsl",
+            &ShaderDefs::new(),
+        )?;
+        let create_pipeline = |label, depth_compare| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[LineVertex::desc()],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[
+                        Some(wgpu::ColorTargetState {
+                            format: color_format,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: Taa::VELOCITY_FORMAT,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::empty(),
+                        }),
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        Ok([
+            create_pipeline("DebugDraw::pipeline", wgpu::CompareFunction::LessEqual),
+            create_pipeline(
+                "DebugDraw::reverse_z_pipeline",
+                wgpu::CompareFunction::GreaterEqual,
+            ),
+            create_pipeline("DebugDraw::overlay_pipeline", wgpu::CompareFunction::Always),
+        ])
+    }
+
+    /// Compiles the pipelines again from `shaders`' current sources.
+    pub(crate) fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+    ) -> anyhow::Result<()> {
+        [
+            self.pipeline,
+            self.reverse_z_pipeline,
+            self.overlay_pipeline,
+        ] = Self::create_pipelines(device, shaders, &self.pipeline_layout, self.color_format)?;
+        Ok(())
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("DebugDraw::vertices"),
+            size: (capacity * size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn depth_test(&self) -> bool {
+        self.depth_test
+    }
+
+    pub fn set_depth_test(&mut self, depth_test: bool) {
+        self.depth_test = depth_test;
+    }
+
+    /// Tests depth against a buffer drawn with the camera's `reverse_z`.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+    }
+
+    /// Lines added since the last clear.
+    pub fn len(&self) -> usize {
+        (self.vertices.len() + self.overlay.len()) / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty() && self.overlay.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.overlay.clear();
+    }
+
+    /// Adds the shapes `draw` adds so they show over everything, whatever
+    /// `depth_test` says, as handles to grab should.
+    pub fn overlay(&mut self, draw: impl FnOnce(&mut Self)) {
+        let start = self.vertices.len();
+        draw(self);
+        let added = self.vertices.drain(start..).collect::<Vec<_>>();
+        self.overlay.extend(added);
+    }
+
+    pub fn line(&mut self, a: cgmath::Point3<f32>, b: cgmath::Point3<f32>, color: [f32; 4]) {
+        self.vertices.extend([
+            LineVertex {
+                position: a.into(),
+                color,
+            },
+            LineVertex {
+                position: b.into(),
+                color,
+            },
+        ]);
+    }
+
+    /// The twelve edges of `bounds`.
+    pub fn wire_aabb(&mut self, bounds: &Bounds, color: [f32; 4]) {
+        let corner = |i: usize| {
+            cgmath::Point3::new(
+                if i & 1 == 0 {
+                    bounds.min.x
+                } else {
+                    bounds.max.x
+                },
+                if i & 2 == 0 {
+                    bounds.min.y
+                } else {
+                    bounds.max.y
+                },
+                if i & 4 == 0 {
+                    bounds.min.z
+                } else {
+                    bounds.max.z
+                },
+            )
+        };
+        self.box_edges(corner, color);
+    }
+
+    /// Circles around the three axes through `center`.
+    pub fn wire_sphere(&mut self, center: cgmath::Point3<f32>, radius: f32, color: [f32; 4]) {
+        let axes = [
+            (cgmath::Vector3::unit_x(), cgmath::Vector3::unit_y()),
+            (cgmath::Vector3::unit_y(), cgmath::Vector3::unit_z()),
+            (cgmath::Vector3::unit_z(), cgmath::Vector3::unit_x()),
+        ];
+        for (u, v) in axes {
+            self.circle(center, [u, v], radius, color);
+        }
+    }
+
+    /// The circle around `center` in the plane of `axes`, two
+    /// perpendicular unit vectors.
+    pub fn circle(
+        &mut self,
+        center: cgmath::Point3<f32>,
+        [u, v]: [cgmath::Vector3<f32>; 2],
+        radius: f32,
+        color: [f32; 4],
+    ) {
+        let point = |i: usize| {
+            let angle = i as f32 / Self::CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+            center + (u * angle.cos() + v * angle.sin()) * radius
+        };
+        for i in 0..Self::CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    /// The x, y and z axes of `transform`, `length` long before it scales
+    /// them, in red, green and blue.
+    pub fn axes(&mut self, transform: &cgmath::Matrix4<f32>, length: f32) {
+        let origin = transform.transform_point(cgmath::Point3::origin());
+        let axes = [
+            (cgmath::Vector3::unit_x(), [1.0, 0.0, 0.0, 1.0]),
+            (cgmath::Vector3::unit_y(), [0.0, 1.0, 0.0, 1.0]),
+            (cgmath::Vector3::unit_z(), [0.0, 0.0, 1.0, 1.0]),
+        ];
+        for (axis, color) in axes {
+            let tip = transform.transform_point(cgmath::Point3::from_vec(axis * length));
+            self.line(origin, tip, color);
+        }
+    }
+
+    /// The edges of the volume `view_proj` maps to clip space, such as a
+    /// camera's or a shadow cascade's. Skipped if it cannot be inverted.
+    pub fn frustum(&mut self, view_proj: &cgmath::Matrix4<f32>, color: [f32; 4]) {
+        let Some(inverse) = view_proj.invert() else {
+            return;
+        };
+        let corner = |i: usize| {
+            let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+            let z = if i & 4 == 0 { 0.0 } else { 1.0 };
+            cgmath::Point3::from_homogeneous(inverse * cgmath::Vector4::new(x, y, z, 1.0))
+        };
+        self.box_edges(corner, color);
+    }
+
+    // The edges between eight corners numbered by which of x, y and z they
+    // are at the far end of, one bit each.
+    fn box_edges(&mut self, corner: impl Fn(usize) -> cgmath::Point3<f32>, color: [f32; 4]) {
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    /// Writes out the lines added so far, growing the buffer as needed.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.uploaded = self.vertices.len() as u32;
+        self.uploaded_overlay = self.overlay.len() as u32;
+        let len = self.vertices.len() + self.overlay.len();
+        if len == 0 {
+            return;
+        }
+        if len > self.capacity {
+            self.capacity = len.next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        for (start, vertices) in [(0, &self.vertices), (self.vertices.len(), &self.overlay)] {
+            if !vertices.is_empty() {
+                let offset = (start * size_of::<LineVertex>()) as wgpu::BufferAddress;
+                queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(vertices));
+            }
+        }
+    }
+
+    /// Draw calls `render` makes for what was last uploaded.
+    pub fn draw_calls(&self) -> usize {
+        (self.uploaded > 0) as usize + (self.uploaded_overlay > 0) as usize
+    }
+
+    /// Draws into a pass over the HDR, velocity and depth targets.
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.draw_calls() == 0 {
+            return;
+        }
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        if self.uploaded > 0 {
+            render_pass.set_pipeline(if !self.depth_test {
+                &self.overlay_pipeline
+            } else if self.reverse_z {
+                &self.reverse_z_pipeline
+            } else {
+                &self.pipeline
+            });
+            render_pass.draw(0..self.uploaded, 0..1);
+        }
+        if self.uploaded_overlay > 0 {
+            render_pass.set_pipeline(&self.overlay_pipeline);
+            render_pass.draw(self.uploaded..self.uploaded + self.uploaded_overlay, 0..1);
+        }
+    }
+}
+
+/// Scene features `State` outlines with its `DebugDraw` every frame.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DebugShapes {
+    /// The world bounds of every mesh instance, as culling tests them.
+    pub bounds: bool,
+    /// A small sphere at each point light.
+    pub lights: bool,
+}
@@ -0,0 +1,38 @@
+// Colored lines for debug_draw.rs.
+#include "include/camera.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(vertex: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    let clip = camera.view_proj * vec4<f32>(vertex.position, 1.0);
+    out.clip_position = vec4<f32>(clip.xy + camera.jitter * clip.w, clip.zw);
+    out.color = vertex.color;
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    // Masked off, like billboards.
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+    out.color = in.color;
+    out.velocity = vec2<f32>(0.0);
+    return out;
+}
@@ -0,0 +1,1069 @@
+use winit::event::WindowEvent;
+use winit::window::{Fullscreen, Window};
+
+use crate::bookmarks;
+use crate::camera_effects::Shake;
+use crate::editor::GizmoMode;
+use crate::flythrough::CameraPath;
+use crate::hdr::{ColorView, Tonemap};
+use crate::lod::LodMetric;
+use crate::msaa::Msaa;
+use crate::picking::PickMode;
+use crate::post::{ColorGrading, DepthOfField};
+use crate::secondary_window::DebugTexture;
+use crate::shadow::ShadowFilter;
+use crate::state::State;
+use crate::timing::FrameTiming;
+use crate::viewport::{DebugView, View, ViewportRect};
+
+/// How long picking another clip in the animation panel fades for.
+const CROSSFADE_SECONDS: f32 = 0.3;
+
+/// A section of the debug window, drawn every frame while it is open.
+///
+/// Closures taking `(&mut egui::Ui, &mut State)` implement this, so most
+/// panels are a single `State::add_debug_ui` call.
+pub trait DebugUi {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut State);
+}
+
+impl<F: FnMut(&mut egui::Ui, &mut State)> DebugUi for F {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut State) {
+        self(ui, state)
+    }
+}
+
+/// The built-in panel: camera speed, exposure, lighting and effect toggles.
+pub struct SceneControls;
+
+impl DebugUi for SceneControls {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut State) {
+        egui::CollapsingHeader::new("Camera")
+            .default_open(true)
+            .show(ui, |ui| {
+                let mut speed = state.camera_controller().speed();
+                if ui
+                    .add(egui::Slider::new(&mut speed, 0.01..=1.0).text("speed"))
+                    .changed()
+                {
+                    state.camera_controller_mut().set_speed(speed);
+                }
+                let mut damping = state.camera_controller().damping();
+                let mut changed = ui
+                    .add(
+                        egui::Slider::new(&mut damping.movement, 0.0..=0.99)
+                            .text("movement damping"),
+                    )
+                    .changed();
+                changed |= ui
+                    .add(
+                        egui::Slider::new(&mut damping.rotation, 0.0..=0.99)
+                            .text("rotation damping"),
+                    )
+                    .changed();
+                if changed {
+                    state.camera_controller_mut().set_damping(damping);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("shake").clicked() {
+                        state.camera_effects_mut().shake(Shake::default());
+                    }
+                    if ui.button("recoil").clicked() {
+                        state.camera_effects_mut().kick(3.0, 0.0);
+                    }
+                    let mut zoomed = state.camera_effects().fov_offset() != 0.0;
+                    if ui.checkbox(&mut zoomed, "zoom").changed() {
+                        state
+                            .camera_effects_mut()
+                            .set_fov_offset(if zoomed { -20.0 } else { 0.0 });
+                    }
+                });
+                let mut reverse_z = state.reverse_z();
+                if ui.checkbox(&mut reverse_z, "reverse Z").changed() {
+                    state.set_reverse_z(reverse_z);
+                }
+            });
+
+        egui::CollapsingHeader::new("Camera Path").show(ui, |ui| {
+            ui.label(format!(
+                "{} keyframes, {:.1} s",
+                state.camera_path().keyframes.len(),
+                state.camera_path().duration()
+            ));
+            ui.horizontal(|ui| {
+                if ui.button("add keyframe").clicked() {
+                    state.add_camera_keyframe();
+                }
+                if ui.button("clear").clicked() {
+                    state.set_camera_path(CameraPath {
+                        keyframes: Vec::new(),
+                        ..state.camera_path().clone()
+                    });
+                }
+                if state.is_playing_camera_path() {
+                    if ui.button("stop").clicked() {
+                        state.stop_camera_path();
+                    }
+                } else if ui.button("play").clicked() {
+                    state.play_camera_path();
+                }
+            });
+            ui.add(
+                egui::Slider::new(&mut state.camera_path_mut().segment_seconds, 0.1..=10.0)
+                    .text("seconds per keyframe"),
+            );
+            ui.checkbox(&mut state.camera_path_mut().looping, "loop");
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.horizontal(|ui| {
+                use crate::flythrough;
+
+                if ui
+                    .button(format!("save {}", flythrough::DEFAULT_PATH))
+                    .clicked()
+                {
+                    match state.camera_path().save(flythrough::DEFAULT_PATH) {
+                        Ok(()) => log::info!("Saved {}", flythrough::DEFAULT_PATH),
+                        Err(e) => {
+                            log::error!("Unable to save {}: {:#}", flythrough::DEFAULT_PATH, e)
+                        }
+                    }
+                }
+                if ui.button("load").clicked() {
+                    match CameraPath::load(flythrough::DEFAULT_PATH) {
+                        Ok(path) => state.set_camera_path(path),
+                        Err(e) => log::error!("{:#}", e),
+                    }
+                }
+            });
+        });
+
+        egui::CollapsingHeader::new("Camera Bookmarks").show(ui, |ui| {
+            ui.label("Ctrl+1..9 saves, 1..9 eases back, Shift+1..9 jumps");
+            ui.add(
+                egui::Slider::new(
+                    &mut state.camera_bookmarks_mut().transition_seconds,
+                    0.0..=5.0,
+                )
+                .text("transition seconds"),
+            );
+            let slots: Vec<usize> = state
+                .camera_bookmarks()
+                .iter()
+                .map(|(slot, _)| slot)
+                .collect();
+            ui.horizontal_wrapped(|ui| {
+                for slot in 1..=bookmarks::SLOTS {
+                    let saved = slots.contains(&slot);
+                    let button = ui.add_enabled(saved, egui::Button::new(slot.to_string()));
+                    if button.clicked() {
+                        state.recall_camera_bookmark(slot, true);
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                let slot = (1..=bookmarks::SLOTS).find(|slot| !slots.contains(slot));
+                if let Some(slot) = slot
+                    && ui.button(format!("save to {}", slot)).clicked()
+                {
+                    state.save_camera_bookmark(slot);
+                }
+                if ui.button("clear").clicked() {
+                    for slot in 1..=bookmarks::SLOTS {
+                        state.camera_bookmarks_mut().clear(slot);
+                    }
+                }
+            });
+        });
+
+        #[cfg(not(target_arch = "wasm32"))]
+        egui::CollapsingHeader::new("Scene File").show(ui, |ui| {
+            use crate::scene_file::{self, Scene};
+
+            ui.label(match (state.model_file(), state.environment_file()) {
+                (Some(model), Some(environment)) => format!("{} in {}", model, environment),
+                (Some(model), None) => model.to_string(),
+                (None, _) => "model built in code, saved without it".to_string(),
+            });
+            ui.horizontal(|ui| {
+                if ui
+                    .button(format!("save {}", scene_file::DEFAULT_PATH))
+                    .clicked()
+                {
+                    match Scene::from_state(state).save(scene_file::DEFAULT_PATH) {
+                        Ok(()) => log::info!("Saved {}", scene_file::DEFAULT_PATH),
+                        Err(e) => {
+                            log::error!("Unable to save {}: {:#}", scene_file::DEFAULT_PATH, e)
+                        }
+                    }
+                }
+                if ui.button("load").clicked() {
+                    let result = Scene::load(scene_file::DEFAULT_PATH)
+                        .and_then(|scene| pollster::block_on(scene.apply(state)));
+                    if let Err(e) = result {
+                        log::error!("Unable to load {}: {:#}", scene_file::DEFAULT_PATH, e);
+                    }
+                }
+            });
+        });
+
+        egui::CollapsingHeader::new("Views").show(ui, |ui| {
+            let mut remove = None;
+            for (i, view) in state.views().enumerate() {
+                ui.horizontal(|ui| {
+                    let rect = view.rect;
+                    ui.label(format!(
+                        "{}: {:.0}% x {:.0}% at ({:.2}, {:.2}){}",
+                        i,
+                        rect.width * 100.0,
+                        rect.height * 100.0,
+                        rect.x,
+                        rect.y,
+                        if view.camera.is_none() {
+                            ", main camera"
+                        } else {
+                            ""
+                        }
+                    ));
+                    if ui.button("remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                state.remove_view(i);
+            }
+            ui.horizontal(|ui| {
+                // Straight down on whatever the main camera looks at.
+                if ui.button("add minimap").clicked() {
+                    let main = *state.camera();
+                    let camera = crate::camera::Camera {
+                        eye: main.target + cgmath::Vector3::new(0.0, 20.0, 0.01),
+                        ..main
+                    };
+                    let view = View {
+                        camera: Some(camera),
+                        rect: ViewportRect::new(0.75, 0.0, 0.25, 0.25),
+                    };
+                    if let Err(e) = state.add_view(view) {
+                        log::error!("Unable to add view: {:#}", e);
+                    }
+                }
+                // The main camera on the left, and where it is now on the
+                // right.
+                if ui.button("add split screen").clicked() {
+                    let views = [
+                        View {
+                            camera: None,
+                            rect: ViewportRect::LEFT_HALF,
+                        },
+                        View {
+                            camera: Some(*state.camera()),
+                            rect: ViewportRect::RIGHT_HALF,
+                        },
+                    ];
+                    for view in views {
+                        if let Err(e) = state.add_view(view) {
+                            log::error!("Unable to add view: {:#}", e);
+                        }
+                    }
+                }
+            });
+        });
+
+        egui::CollapsingHeader::new("Debug view").show(ui, |ui| {
+            let current = state.debug_view();
+            let mut texture = current.map(|view| view.texture);
+            let label = |texture: Option<DebugTexture>| {
+                texture.map_or_else(|| "none".to_string(), |texture| texture.to_string())
+            };
+            egui::ComboBox::from_label("texture")
+                .selected_text(label(texture))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut texture, None, "none");
+                    for option in state.debug_textures() {
+                        ui.selectable_value(&mut texture, Some(option), option.to_string());
+                    }
+                });
+            let mut inset = current.is_some_and(|view| view.rect != ViewportRect::FULL);
+            ui.checkbox(&mut inset, "picture-in-picture");
+            let rect = if inset {
+                ViewportRect::INSET
+            } else {
+                ViewportRect::FULL
+            };
+            let view = texture.map(|texture| DebugView { texture, rect });
+            if view != current {
+                state.set_debug_view(view);
+            }
+        });
+
+        if state.window().is_some() {
+            egui::CollapsingHeader::new("Display")
+                .default_open(true)
+                .show(ui, |ui| {
+                    let mut mode = state.present_mode();
+                    egui::ComboBox::from_label("present mode")
+                        .selected_text(format!("{:?}", mode))
+                        .show_ui(ui, |ui| {
+                            for option in state.present_modes() {
+                                ui.selectable_value(&mut mode, option, format!("{:?}", option));
+                            }
+                        });
+                    if mode != state.present_mode()
+                        && let Err(e) = state.set_present_mode(mode)
+                    {
+                        log::error!("{}", e);
+                    }
+                    let mut fullscreen = state.fullscreen();
+                    egui::ComboBox::from_label("fullscreen")
+                        .selected_text(fullscreen_label(&fullscreen))
+                        .show_ui(ui, |ui| {
+                            // Listed only while open, as asking can be slow.
+                            let options = [None, Some(Fullscreen::Borderless(None))]
+                                .into_iter()
+                                .chain(
+                                    state
+                                        .video_modes()
+                                        .into_iter()
+                                        .map(|mode| Some(Fullscreen::Exclusive(mode))),
+                                );
+                            for option in options {
+                                let label = fullscreen_label(&option);
+                                ui.selectable_value(&mut fullscreen, option, label);
+                            }
+                        });
+                    if fullscreen != state.fullscreen() {
+                        state.set_fullscreen(fullscreen);
+                    }
+                });
+        }
+
+        egui::CollapsingHeader::new("Tonemapping")
+            .default_open(true)
+            .show(ui, |ui| {
+                let mut exposure = state.exposure();
+                if ui
+                    .add(
+                        egui::Slider::new(&mut exposure, 0.1..=8.0)
+                            .logarithmic(true)
+                            .text("exposure"),
+                    )
+                    .changed()
+                {
+                    state.set_exposure(exposure);
+                }
+                let mut tonemap = state.tonemap();
+                egui::ComboBox::from_label("curve")
+                    .selected_text(format!("{:?}", tonemap))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut tonemap, Tonemap::Aces, "Aces");
+                        ui.selectable_value(&mut tonemap, Tonemap::Reinhard, "Reinhard");
+                    });
+                if tonemap != state.tonemap() {
+                    state.set_tonemap(tonemap);
+                }
+                let mut color_view = state.color_view();
+                egui::ComboBox::from_label("view")
+                    .selected_text(format!("{:?}", color_view))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut color_view, ColorView::Final, "Final");
+                        ui.selectable_value(
+                            &mut color_view,
+                            ColorView::Untonemapped,
+                            "Untonemapped",
+                        );
+                        ui.selectable_value(&mut color_view, ColorView::Unencoded, "Unencoded");
+                        ui.selectable_value(
+                            &mut color_view,
+                            ColorView::DoubleEncoded,
+                            "DoubleEncoded",
+                        );
+                    });
+                if color_view != state.color_view() {
+                    state.set_color_view(color_view);
+                }
+                ui.label(format!("output: {:?}", state.output_format()));
+            });
+
+        egui::CollapsingHeader::new("Lighting")
+            .default_open(true)
+            .show(ui, |ui| {
+                let mut ambient = state.ambient();
+                if ui
+                    .add(egui::Slider::new(&mut ambient, 0.0..=2.0).text("ambient"))
+                    .changed()
+                {
+                    state.set_ambient(ambient);
+                }
+                let mut sun = *state.directional_light();
+                let mut changed = ui
+                    .add(egui::Slider::new(&mut sun.intensity, 0.0..=10.0).text("sun intensity"))
+                    .changed();
+                changed |= ui.color_edit_button_rgb(&mut sun.color).changed();
+                if changed {
+                    state.set_directional_light(sun);
+                }
+                let mut shadows = state.shadows_enabled();
+                if ui.checkbox(&mut shadows, "shadows").changed() {
+                    state.set_shadows_enabled(shadows);
+                }
+                let mut variance = state.shadow_filter() == ShadowFilter::Variance;
+                if ui.checkbox(&mut variance, "variance shadows").changed() {
+                    state.set_shadow_filter(if variance {
+                        ShadowFilter::Variance
+                    } else {
+                        ShadowFilter::Pcf
+                    });
+                }
+            });
+
+        egui::CollapsingHeader::new("Sky")
+            .default_open(false)
+            .show(ui, |ui| {
+                let mut sky = state.sky_settings();
+                let mut changed = ui.checkbox(&mut sky.enabled, "day/night cycle").changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut sky.time_of_day, 0.0..=24.0).text("time of day"))
+                    .changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut sky.speed, 0.0..=2.0).text("hours per second"))
+                    .changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut sky.turbidity, 1.7..=10.0).text("turbidity"))
+                    .changed();
+                if changed {
+                    state.set_sky_settings(sky);
+                }
+            });
+
+        egui::CollapsingHeader::new("Fog")
+            .default_open(false)
+            .show(ui, |ui| {
+                let mut fog = state.fog_settings();
+                let mut changed = ui.checkbox(&mut fog.enabled, "enabled").changed();
+                changed |= ui.color_edit_button_rgb(&mut fog.color).changed();
+                changed |= ui
+                    .add(
+                        egui::Slider::new(&mut fog.density, 0.0..=0.5)
+                            .logarithmic(true)
+                            .text("density"),
+                    )
+                    .changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut fog.start, 0.0..=200.0).text("start"))
+                    .changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut fog.end, 1.0..=500.0).text("end"))
+                    .changed();
+                changed |= ui
+                    .add(
+                        egui::Slider::new(&mut fog.height_falloff, 0.0..=1.0)
+                            .text("height falloff"),
+                    )
+                    .changed();
+                if changed {
+                    state.set_fog_settings(fog);
+                }
+            });
+
+        egui::CollapsingHeader::new("Sampling")
+            .default_open(false)
+            .show(ui, |ui| {
+                let mut sampler = state.sampler_settings();
+                let mut filter = sampler.min_filter;
+                egui::ComboBox::from_label("filter")
+                    .selected_text(format!("{:?}", filter))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut filter, wgpu::FilterMode::Linear, "Linear");
+                        ui.selectable_value(&mut filter, wgpu::FilterMode::Nearest, "Nearest");
+                    });
+                sampler.mag_filter = filter;
+                sampler.min_filter = filter;
+                sampler.mipmap_filter = filter;
+                let mut address_mode = sampler.address_mode_u;
+                egui::ComboBox::from_label("address mode")
+                    .selected_text(format!("{:?}", address_mode))
+                    .show_ui(ui, |ui| {
+                        for mode in [
+                            wgpu::AddressMode::Repeat,
+                            wgpu::AddressMode::MirrorRepeat,
+                            wgpu::AddressMode::ClampToEdge,
+                        ] {
+                            ui.selectable_value(&mut address_mode, mode, format!("{:?}", mode));
+                        }
+                    });
+                sampler.address_mode_u = address_mode;
+                sampler.address_mode_v = address_mode;
+                ui.add_enabled(
+                    filter == wgpu::FilterMode::Linear,
+                    egui::Slider::new(&mut sampler.anisotropy_clamp, 1..=16).text("anisotropy"),
+                );
+                ui.add(egui::Slider::new(&mut sampler.lod_bias, -2.0..=2.0).text("LOD bias"));
+                if sampler != state.sampler_settings() {
+                    state.set_sampler_settings(sampler);
+                }
+            });
+
+        egui::CollapsingHeader::new("Debug Draw")
+            .default_open(false)
+            .show(ui, |ui| {
+                let mut shapes = state.debug_shapes();
+                ui.checkbox(&mut shapes.bounds, "instance bounds");
+                ui.checkbox(&mut shapes.lights, "point lights");
+                if shapes != state.debug_shapes() {
+                    state.set_debug_shapes(shapes);
+                }
+                let mut grid = state.grid_settings();
+                ui.checkbox(&mut grid.enabled, "grid (G)");
+                ui.add_enabled(
+                    grid.enabled,
+                    egui::Slider::new(&mut grid.cell_size, 0.1..=10.0)
+                        .logarithmic(true)
+                        .text("cell size"),
+                );
+                ui.add_enabled(
+                    grid.enabled,
+                    egui::Slider::new(&mut grid.fade_distance, 5.0..=500.0)
+                        .logarithmic(true)
+                        .text("fade distance"),
+                );
+                if grid != state.grid_settings() {
+                    state.set_grid_settings(grid);
+                }
+                let mut depth_test = state.debug_draw().depth_test();
+                if ui.checkbox(&mut depth_test, "depth test").changed() {
+                    state.debug_draw_mut().set_depth_test(depth_test);
+                }
+            });
+
+        egui::CollapsingHeader::new("Selection")
+            .default_open(false)
+            .show(ui, |ui| {
+                let mut mode = state.pick_mode();
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut mode, PickMode::Bounds, "Bounds");
+                    ui.selectable_value(&mut mode, PickMode::Triangles, "Triangles");
+                    ui.selectable_value(&mut mode, PickMode::IdBuffer, "ID buffer");
+                });
+                if mode != state.pick_mode() {
+                    state.set_pick_mode(mode);
+                }
+                let mut outline = state.outline_settings();
+                ui.horizontal(|ui| {
+                    ui.color_edit_button_rgba_unmultiplied(&mut outline.color);
+                    ui.add(egui::Slider::new(&mut outline.width, 1.0..=10.0).text("outline"));
+                });
+                if outline != state.outline_settings() {
+                    state.set_outline_settings(outline);
+                }
+                match state.selection() {
+                    Some(hit) => {
+                        ui.label(format!("mesh {} instance {}", hit.mesh, hit.instance));
+                        if let Some(entity) = hit.entity {
+                            ui.label(format!("entity {:?}", entity));
+                        }
+                        ui.label(format!(
+                            "at ({:.2}, {:.2}, {:.2}), {:.2} away",
+                            hit.position.x, hit.position.y, hit.position.z, hit.distance
+                        ));
+                        if ui.button("Clear").clicked() {
+                            state.set_selection(None);
+                        }
+                    }
+                    None if state.pick_pending() => {
+                        ui.label("Picking...");
+                    }
+                    None => {
+                        ui.label("Click the scene to pick");
+                    }
+                }
+            });
+
+        egui::CollapsingHeader::new("Editor")
+            .default_open(false)
+            .show(ui, |ui| {
+                let mut editor = state.editor_settings();
+                ui.checkbox(&mut editor.enabled, "gizmo (Tab)");
+                ui.horizontal(|ui| {
+                    for mode in GizmoMode::ALL {
+                        ui.selectable_value(&mut editor.mode, mode, mode.to_string());
+                    }
+                });
+                if editor != state.editor_settings() {
+                    state.set_editor_settings(editor);
+                }
+                let Some(mut instance) = state.selected_instance() else {
+                    ui.label("Click the scene to pick something to edit");
+                    return;
+                };
+                let mut changed = false;
+                let mut vector = |ui: &mut egui::Ui, label, value: &mut [f32; 3], speed| {
+                    ui.horizontal(|ui| {
+                        for v in value.iter_mut() {
+                            changed |= ui.add(egui::DragValue::new(v).speed(speed)).changed();
+                        }
+                        ui.label(label);
+                    });
+                };
+                let mut position = instance.position.into();
+                vector(ui, "position", &mut position, 0.05);
+                let euler = cgmath::Euler::from(instance.rotation);
+                let mut rotation =
+                    [euler.x, euler.y, euler.z].map(|angle| cgmath::Deg::from(angle).0);
+                vector(ui, "rotation", &mut rotation, 1.0);
+                let mut scale = instance.scale.into();
+                vector(ui, "scale", &mut scale, 0.01);
+                if changed {
+                    instance.position = position.into();
+                    let [x, y, z] = rotation.map(cgmath::Deg);
+                    instance.rotation = cgmath::Euler::new(x, y, z).into();
+                    instance.scale = scale.into();
+                    state.update_selected_instance(instance);
+                }
+
+                if let Some(index) = state.selected_material() {
+                    let material = &state.model_materials()[index];
+                    ui.label(format!("material {}", material.name));
+                    let mut factors = *material.factors();
+                    ui.horizontal(|ui| {
+                        ui.color_edit_button_rgba_unmultiplied(&mut factors.base_color);
+                        ui.label("base color");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.color_edit_button_rgb(&mut factors.emissive);
+                        ui.label("emissive");
+                        ui.add(
+                            egui::DragValue::new(&mut factors.emissive_strength)
+                                .speed(0.1)
+                                .range(0.0..=f32::MAX),
+                        );
+                        ui.label("strength");
+                    });
+                    ui.add(egui::Slider::new(&mut factors.metallic, 0.0..=1.0).text("metallic"));
+                    ui.add(egui::Slider::new(&mut factors.roughness, 0.0..=1.0).text("roughness"));
+                    if factors != *material.factors()
+                        && let Err(e) = state.set_material_factors(index, factors)
+                    {
+                        log::error!("{:#}", e);
+                    }
+                }
+
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    use crate::scene_file::{self, Scene};
+
+                    if ui
+                        .button(format!("save {}", scene_file::DEFAULT_PATH))
+                        .clicked()
+                    {
+                        match Scene::from_state(state).save(scene_file::DEFAULT_PATH) {
+                            Ok(()) => log::info!("Saved {}", scene_file::DEFAULT_PATH),
+                            Err(e) => {
+                                log::error!("Unable to save {}: {:#}", scene_file::DEFAULT_PATH, e)
+                            }
+                        }
+                    }
+                }
+            });
+
+        egui::CollapsingHeader::new("Effects")
+            .default_open(true)
+            .show(ui, |ui| {
+                let mut bloom = state.bloom_settings();
+                let mut changed = ui
+                    .add(egui::Slider::new(&mut bloom.intensity, 0.0..=1.0).text("bloom"))
+                    .changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut bloom.threshold, 0.0..=4.0).text("threshold"))
+                    .changed();
+                if changed {
+                    state.set_bloom_settings(bloom);
+                }
+
+                let mut exposure = state.auto_exposure_settings();
+                let mut changed = ui
+                    .checkbox(&mut exposure.enabled, "auto exposure")
+                    .changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut exposure.speed, 0.1..=10.0).text("speed"))
+                    .changed();
+                if changed {
+                    state.set_auto_exposure_settings(exposure);
+                }
+
+                let mut ssao = state.ssao_settings();
+                let mut changed = ui.checkbox(&mut ssao.enabled, "SSAO").changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut ssao.radius, 0.05..=2.0).text("radius"))
+                    .changed();
+                if changed {
+                    state.set_ssao_settings(ssao);
+                }
+
+                let mut ssr = state.ssr_settings();
+                let mut changed = ui.checkbox(&mut ssr.enabled, "reflections").changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut ssr.max_roughness, 0.0..=1.0).text("max roughness"))
+                    .changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut ssr.thickness, 0.05..=2.0).text("thickness"))
+                    .changed();
+                if changed {
+                    state.set_ssr_settings(ssr);
+                }
+
+                let mut volume = state.volumetric_settings();
+                let mut changed = ui.checkbox(&mut volume.enabled, "light shafts").changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut volume.density, 0.0..=0.2).text("density"))
+                    .changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut volume.anisotropy, -0.9..=0.9).text("anisotropy"))
+                    .changed();
+                if changed {
+                    state.set_volumetric_settings(volume);
+                }
+
+                let mut taa = state.taa_settings();
+                let mut changed = ui.checkbox(&mut taa.enabled, "TAA").changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut taa.blend, 0.02..=1.0).text("blend"))
+                    .changed();
+                if changed {
+                    state.set_taa_settings(taa);
+                }
+
+                let mut msaa = state.msaa_samples() > 1;
+                if ui
+                    .add_enabled(
+                        state.msaa_available(),
+                        egui::Checkbox::new(&mut msaa, "MSAA"),
+                    )
+                    .changed()
+                    && let Err(e) =
+                        state.set_msaa_samples(if msaa { Msaa::SAMPLE_COUNT } else { 1 })
+                {
+                    log::error!("{}", e);
+                }
+
+                if let Some(dof) = state.post_effect_mut::<DepthOfField>() {
+                    ui.checkbox(&mut dof.enabled, "depth of field");
+                    ui.checkbox(&mut dof.settings.autofocus, "autofocus");
+                    ui.add_enabled(
+                        !dof.settings.autofocus,
+                        egui::Slider::new(&mut dof.settings.focus_distance, 0.1..=100.0)
+                            .logarithmic(true)
+                            .text("focus distance"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut dof.settings.aperture, 0.0..=2.0).text("aperture"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut dof.settings.max_radius, 0.0..=16.0)
+                            .text("max blur"),
+                    );
+                }
+
+                if let Some(grading) = state.post_effect_mut::<ColorGrading>()
+                    && grading.lut().is_some()
+                {
+                    ui.checkbox(&mut grading.enabled, "color grading");
+                    ui.add(
+                        egui::Slider::new(&mut grading.settings.intensity, 0.0..=1.0)
+                            .text("intensity"),
+                    );
+                }
+
+                let mut culling = state.gpu_culling_enabled();
+                if ui.checkbox(&mut culling, "GPU culling").changed() {
+                    state.set_gpu_culling_enabled(culling);
+                }
+                let mut occlusion = state.occlusion_culling_enabled();
+                if ui.checkbox(&mut occlusion, "occlusion culling").changed() {
+                    state.set_occlusion_culling_enabled(occlusion);
+                }
+                let mut culling = state.cpu_culling_enabled();
+                if ui.checkbox(&mut culling, "CPU culling").changed() {
+                    state.set_cpu_culling_enabled(culling);
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    let mut parallel = state.parallel_encoding_enabled();
+                    if ui.checkbox(&mut parallel, "parallel encoding").changed() {
+                        state.set_parallel_encoding_enabled(parallel);
+                    }
+                }
+                let mut bundles = state.static_bundles_enabled();
+                ui.horizontal(|ui| {
+                    if ui.checkbox(&mut bundles, "static bundles").changed() {
+                        state.set_static_bundles_enabled(bundles);
+                    }
+                    if bundles {
+                        ui.label(format!("recorded {}x", state.static_bundle_recordings()));
+                    }
+                });
+                let mut arrays = state.texture_arrays_enabled();
+                if ui.checkbox(&mut arrays, "texture arrays").changed()
+                    && let Err(e) = state.set_texture_arrays_enabled(arrays)
+                {
+                    log::error!("Unable to build texture array pipelines: {:#}", e);
+                }
+
+                let mut lod = state.lod_settings();
+                ui.checkbox(&mut lod.enabled, "LOD");
+                ui.horizontal(|ui| {
+                    for metric in LodMetric::ALL {
+                        ui.selectable_value(&mut lod.metric, metric, metric.to_string());
+                    }
+                });
+                match lod.metric {
+                    LodMetric::ScreenSize => ui.add(
+                        egui::Slider::new(&mut lod.screen_size, 10.0..=2000.0)
+                            .logarithmic(true)
+                            .text("full detail pixels"),
+                    ),
+                    LodMetric::Distance => ui.add(
+                        egui::Slider::new(&mut lod.distance, 1.0..=200.0)
+                            .logarithmic(true)
+                            .text("full detail distance"),
+                    ),
+                };
+                if lod != state.lod_settings() {
+                    state.set_lod_settings(lod);
+                }
+                let counts = state.lod_counts();
+                if !counts.is_empty() {
+                    let counts = counts
+                        .iter()
+                        .map(|count| count.to_string())
+                        .collect::<Vec<_>>();
+                    ui.label(format!("instances per level: {}", counts.join(" / ")));
+                }
+            });
+
+        if state.animations().is_empty() {
+            return;
+        }
+        egui::CollapsingHeader::new("Animation")
+            .default_open(true)
+            .show(ui, |ui| {
+                let names = state
+                    .animations()
+                    .iter()
+                    .map(|clip| clip.name.clone())
+                    .collect::<Vec<_>>();
+                let player = state.animation_player_mut();
+                let selected = player.clip();
+                egui::ComboBox::from_label("clip")
+                    .selected_text(selected.map_or("none", |clip| names[clip].as_str()))
+                    .show_ui(ui, |ui| {
+                        for (i, name) in names.iter().enumerate() {
+                            if ui.selectable_label(selected == Some(i), name).clicked() {
+                                player.crossfade_to(i, CROSSFADE_SECONDS);
+                            }
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    if player.is_playing() {
+                        if ui.button("pause").clicked() {
+                            player.pause();
+                        }
+                    } else if ui.button("play").clicked() {
+                        match player.clip() {
+                            Some(_) => player.resume(),
+                            None => player.play(0),
+                        }
+                    }
+                    if ui.button("stop").clicked() {
+                        player.stop();
+                    }
+                });
+                let mut looping = player.looping();
+                if ui.checkbox(&mut looping, "loop").changed() {
+                    player.set_looping(looping);
+                }
+                let mut speed = player.speed();
+                if ui
+                    .add(egui::Slider::new(&mut speed, 0.0..=2.0).text("speed"))
+                    .changed()
+                {
+                    player.set_speed(speed);
+                }
+            });
+    }
+}
+
+fn fullscreen_label(fullscreen: &Option<Fullscreen>) -> String {
+    match fullscreen {
+        None => "windowed".to_string(),
+        Some(Fullscreen::Borderless(_)) => "borderless".to_string(),
+        Some(Fullscreen::Exclusive(mode)) => format!(
+            "{}x{} @ {:.0} Hz",
+            mode.size().width,
+            mode.size().height,
+            mode.refresh_rate_millihertz() as f32 / 1000.0
+        ),
+    }
+}
+
+/// Frame rate, CPU and GPU times and draw calls in the top-right corner.
+pub(crate) fn stats_overlay(ctx: &egui::Context, timing: &FrameTiming) {
+    egui::Area::new(egui::Id::new("frame_stats"))
+        .anchor(egui::Align2::RIGHT_TOP, [-8.0, 8.0])
+        .interactable(false)
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.monospace(format!(
+                    "{:6.1} fps  {:6.2} ms",
+                    timing.fps(),
+                    timing.frame_time_ms()
+                ));
+                ui.monospace(format!("cpu     {:6.2} ms", timing.cpu_time_ms()));
+                match timing.gpu() {
+                    Some(gpu) => {
+                        for (label, ms) in gpu.timings() {
+                            ui.monospace(format!("{:<8}{:6.2} ms", label, ms));
+                        }
+                        ui.monospace(format!("gpu     {:6.2} ms", gpu.total_ms()));
+                    }
+                    None => {
+                        ui.monospace("gpu     n/a");
+                    }
+                }
+                ui.monospace(format!("draws   {:6}", timing.draw_calls()));
+                ui.monospace(format!(
+                    "upload  {:6.1} KiB",
+                    timing.upload_bytes() as f32 / 1024.0
+                ));
+            });
+        });
+}
+
+/// egui context, winit input bridge and renderer behind the debug window.
+///
+/// The stats overlay shares the same egui frame but takes no input.
+/// Panels are taken out while they run so they can borrow the whole
+/// `State`; `State::render` drives that and then calls `render` here.
+pub(crate) struct DebugOverlay {
+    pub(crate) visible: bool,
+    pub(crate) stats_visible: bool,
+    pub(crate) panels: Vec<Box<dyn DebugUi>>,
+    context: egui::Context,
+    // None without a window; headless frames get synthetic input.
+    input: Option<egui_winit::State>,
+    renderer: egui_wgpu::Renderer,
+}
+
+impl DebugOverlay {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        window: Option<&Window>,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let context = egui::Context::default();
+        let input = window.map(|window| {
+            egui_winit::State::new(
+                context.clone(),
+                egui::ViewportId::ROOT,
+                window,
+                Some(window.scale_factor() as f32),
+                None,
+                Some(device.limits().max_texture_dimension_2d as usize),
+            )
+        });
+        let renderer =
+            egui_wgpu::Renderer::new(device, output_format, egui_wgpu::RendererOptions::default());
+        Self {
+            visible: false,
+            stats_visible: false,
+            panels: vec![Box::new(SceneControls)],
+            context,
+            input,
+            renderer,
+        }
+    }
+
+    /// Forwards `event` to egui; true when the UI used it and the scene
+    /// should not see it.
+    pub(crate) fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        match &mut self.input {
+            Some(input) if self.visible => input.on_window_event(window, event).consumed,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn context(&self) -> egui::Context {
+        self.context.clone()
+    }
+
+    /// Input gathered since the last frame, or just the screen size of a
+    /// `size` pixel output when there is no window.
+    pub(crate) fn take_input(&mut self, window: Option<&Window>, size: [u32; 2]) -> egui::RawInput {
+        match (&mut self.input, window) {
+            (Some(input), Some(window)) => input.take_egui_input(window),
+            _ => egui::RawInput {
+                screen_rect: Some(egui::Rect::from_min_size(
+                    egui::Pos2::ZERO,
+                    egui::vec2(size[0] as f32, size[1] as f32),
+                )),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Uploads and draws a finished egui frame over `output`, which is
+    /// `size` pixels.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        window: Option<&Window>,
+        output: &wgpu::TextureView,
+        size: [u32; 2],
+        frame: egui::FullOutput,
+    ) {
+        if let (Some(input), Some(window)) = (&mut self.input, window) {
+            input.handle_platform_output(window, frame.platform_output);
+        }
+        let paint_jobs = self
+            .context
+            .tessellate(frame.shapes, frame.pixels_per_point);
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: size,
+            pixels_per_point: frame.pixels_per_point,
+        };
+
+        for (id, delta) in &frame.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        // Extra command buffers only come from paint callbacks, which the
+        // panels never add.
+        let _ = self
+            .renderer
+            .update_buffers(device, queue, encoder, &paint_jobs, &screen);
+        {
+            let pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Debug UI Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            self.renderer
+                .render(&mut pass.forget_lifetime(), &paint_jobs, &screen);
+        }
+        for id in &frame.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use std::ops::Range;
+
+use cgmath::SquareMatrix;
+
+use crate::animation::Transform;
+use crate::deferred::Deferred;
+use crate::preprocess::{ShaderDefs, ShaderLibrary};
+use crate::texture;
+use crate::vertex::Vertex;
+
+/// Index of a texture added with `Decals::add_texture`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct DecalTextureId(pub usize);
+
+impl DecalTextureId {
+    /// Plain white with no normal map, so the tint alone colors the
+    /// surface; enough for road markings.
+    pub const WHITE: Self = Self(0);
+}
+
+/// A texture pressed onto whatever lies inside a box: give an entity one
+/// along with a `Transform`, which places a unit cube centered on its
+/// origin. The decal projects down the box's local -Y, with the texture's u
+/// along X and v along Z, so an unrotated decal lies on the ground.
+///
+/// Decals are blended into the G-buffer, so only the deferred path draws
+/// them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Decal {
+    pub texture: DecalTextureId,
+    /// Multiplies the texture, alpha included.
+    pub color: [f32; 4],
+    /// Cosine of the angle between a surface and the box's Y axis past
+    /// which the decal fades out, as it would smear across surfaces seen
+    /// edge-on from the projection.
+    pub angle_fade: f32,
+}
+
+impl Default for Decal {
+    fn default() -> Self {
+        Self {
+            texture: DecalTextureId::WHITE,
+            color: [1.0; 4],
+            angle_fade: 0.3,
+        }
+    }
+}
+
+impl Decal {
+    pub fn with_texture(mut self, texture: DecalTextureId) -> Self {
+        self.texture = texture;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    // None for a box flattened to nothing, which covers no surface.
+    fn to_raw(self, transform: &Transform) -> Option<DecalRaw> {
+        let model = transform.matrix();
+        Some(DecalRaw {
+            model: model.into(),
+            inverse: model.invert()?.into(),
+            color: self.color,
+            params: [self.angle_fade, 0.0, 0.0, 0.0],
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DecalRaw {
+    model: [[f32; 4]; 4],
+    inverse: [[f32; 4]; 4],
+    color: [f32; 4],
+    params: [f32; 4],
+}
+
+crate::impl_vertex!(DecalRaw, Instance {
+    model => 0..=3: Float32x4,
+    inverse => 4..=7: Float32x4,
+    color => 8: Float32x4,
+    params => 9: Float32x4,
+});
+
+struct DecalTexture {
+    bind_group: wgpu::BindGroup,
+    // Whether it has a normal map, and so is drawn by the pipeline that
+    // writes normals.
+    normals: bool,
+}
+
+/// Draws the scene's `Decal` entities into the G-buffer's albedo and,
+/// for those with a normal map, normal targets, between the geometry that
+/// fills it and the lighting that reads it.
+pub struct Decals {
+    textures: Vec<DecalTexture>,
+    texture_layout: wgpu::BindGroupLayout,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    sampler: wgpu::Sampler,
+    flat_normal: texture::Texture,
+    pipeline_layout: wgpu::PipelineLayout,
+    // Albedo only, then albedo and normals.
+    pipelines: [wgpu::RenderPipeline; 2],
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    batches: Vec<(DecalTextureId, Range<u32>)>,
+}
+
+impl Decals {
+    const MIN_CAPACITY: usize = 16;
+
+    /// `camera_buffer` holds the scene's `CameraUniform`, and the decals
+    /// find the surfaces they cover in `depth_texture`, which `resize`
+    /// replaces.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shaders: &ShaderLibrary,
+        depth_texture: &texture::Texture,
+        camera_buffer: &wgpu::Buffer,
+    ) -> anyhow::Result<Self> {
+        let texture_entry = |binding, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Decals::layout"),
+            entries: &[
+                texture_entry(0, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Decals::texture_layout"),
+            entries: &[
+                texture_entry(0, true),
+                texture_entry(1, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Decals::pipeline_layout"),
+            bind_group_layouts: &[&layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        let pipelines = Self::create_pipelines(device, shaders, &pipeline_layout)?;
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Decals::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = Self::create_bind_group(device, &layout, depth_texture, camera_buffer);
+        let mut decals = Self {
+            textures: Vec::new(),
+            texture_layout,
+            layout,
+            bind_group,
+            sampler,
+            flat_normal: texture::Texture::flat_normal(device, queue)?,
+            pipeline_layout,
+            pipelines,
+            buffer: Self::create_buffer(device, Self::MIN_CAPACITY),
+            capacity: Self::MIN_CAPACITY,
+            batches: Vec::new(),
+        };
+        let white = texture::Texture::from_color(device, queue, [255; 4], "Decals::white")?;
+        decals.add_texture(device, &white, None);
+        Ok(decals)
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+        pipeline_layout: &wgpu::PipelineLayout,
+    ) -> anyhow::Result<[wgpu::RenderPipeline; 2]> {
+        let shader =
+            shaders.create_module(device, "Decals::shader", "decal.wgsl", &ShaderDefs::new())?;
+        // The G-buffer's alpha holds occlusion, which decals leave alone.
+        let pipeline = |label, normal_writes| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[DecalRaw::desc()],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[
+                        Some(wgpu::ColorTargetState {
+                            format: Deferred::ALBEDO_FORMAT,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::COLOR,
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: Deferred::NORMAL_FORMAT,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: normal_writes,
+                        }),
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                // Back faces, so a camera inside the box still draws it.
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Front),
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        Ok([
+            pipeline("Decals::albedo_pipeline", wgpu::ColorWrites::empty()),
+            pipeline(
+                "Decals::normal_pipeline",
+                wgpu::ColorWrites::RED | wgpu::ColorWrites::GREEN,
+            ),
+        ])
+    }
+
+    /// Compiles the pipelines again from `shaders`' current sources.
+    pub(crate) fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+    ) -> anyhow::Result<()> {
+        self.pipelines = Self::create_pipelines(device, shaders, &self.pipeline_layout)?;
+        Ok(())
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        depth_texture: &texture::Texture,
+        camera_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Decals::bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Decals::instances"),
+            size: (capacity * size_of::<DecalRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        depth_texture: &texture::Texture,
+        camera_buffer: &wgpu::Buffer,
+    ) {
+        self.bind_group =
+            Self::create_bind_group(device, &self.layout, depth_texture, camera_buffer);
+    }
+
+    /// Makes `albedo`, with straight alpha, and the tangent-space `normal`
+    /// map, if it has one, available to decals. Without a normal map the
+    /// surface keeps its own normals.
+    pub fn add_texture(
+        &mut self,
+        device: &wgpu::Device,
+        albedo: &texture::Texture,
+        normal: Option<&texture::Texture>,
+    ) -> DecalTextureId {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Decals::texture"),
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&albedo.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &normal.unwrap_or(&self.flat_normal).view,
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        self.textures.push(DecalTexture {
+            bind_group,
+            normals: normal.is_some(),
+        });
+        DecalTextureId(self.textures.len() - 1)
+    }
+
+    /// Writes out `decals` for the next `render`, in order within each
+    /// texture, so where decals of one texture overlap the later one is on
+    /// top. Decals with a texture that was never added are skipped.
+    pub fn upload<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        decals: impl IntoIterator<Item = (&'a Decal, &'a Transform)>,
+    ) {
+        let mut sorted = decals
+            .into_iter()
+            .filter(|(decal, _)| decal.texture.0 < self.textures.len())
+            .filter_map(|(decal, transform)| Some((decal.texture, decal.to_raw(transform)?)))
+            .collect::<Vec<_>>();
+        sorted.sort_by_key(|(texture, _)| texture.0);
+
+        self.batches.clear();
+        for (i, (texture, _)) in sorted.iter().enumerate() {
+            let i = i as u32;
+            match self.batches.last_mut() {
+                Some((batch, range)) if batch == texture => range.end = i + 1,
+                _ => self.batches.push((*texture, i..i + 1)),
+            }
+        }
+        if sorted.is_empty() {
+            return;
+        }
+
+        if sorted.len() > self.capacity {
+            self.capacity = sorted.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        let raw = sorted.iter().map(|(_, raw)| *raw).collect::<Vec<_>>();
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&raw));
+    }
+
+    /// Draw calls `render` makes for what was last uploaded.
+    pub fn draw_calls(&self) -> usize {
+        self.batches.len()
+    }
+
+    /// Blends the decals into the G-buffer's `albedo` and `normal` targets.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        albedo: &wgpu::TextureView,
+        normal: &wgpu::TextureView,
+    ) {
+        if self.batches.is_empty() {
+            return;
+        }
+        let attachment = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })
+        };
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Decals::pass"),
+            color_attachments: &[attachment(albedo), attachment(normal)],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.buffer.slice(..));
+        for (texture, range) in &self.batches {
+            let texture = &self.textures[texture.0];
+            pass.set_pipeline(&self.pipelines[texture.normals as usize]);
+            pass.set_bind_group(1, &texture.bind_group, &[]);
+            pass.draw(0..36, range.clone());
+        }
+    }
+}
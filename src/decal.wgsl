@@ -0,0 +1,151 @@
+#include "include/camera.wgsl"
+
+// Boxes pressed onto the G-buffer: each fragment of a box's back faces
+// finds the scene surface it covers from the depth buffer, and blends the
+// decal in where that surface lies inside the box.
+
+@group(0) @binding(0)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> camera: CameraUniform;
+
+@group(1) @binding(0)
+var t_albedo: texture_2d<f32>;
+@group(1) @binding(1)
+var t_normal: texture_2d<f32>;
+@group(1) @binding(2)
+var s_decal: sampler;
+
+struct DecalInput {
+    @location(0) model_0: vec4<f32>,
+    @location(1) model_1: vec4<f32>,
+    @location(2) model_2: vec4<f32>,
+    @location(3) model_3: vec4<f32>,
+    @location(4) inverse_0: vec4<f32>,
+    @location(5) inverse_1: vec4<f32>,
+    @location(6) inverse_2: vec4<f32>,
+    @location(7) inverse_3: vec4<f32>,
+    @location(8) color: vec4<f32>,
+    // The angle fade's cosine in x.
+    @location(9) params: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) inverse_0: vec4<f32>,
+    @location(1) @interpolate(flat) inverse_1: vec4<f32>,
+    @location(2) @interpolate(flat) inverse_2: vec4<f32>,
+    @location(3) @interpolate(flat) inverse_3: vec4<f32>,
+    @location(4) @interpolate(flat) color: vec4<f32>,
+    @location(5) @interpolate(flat) params: vec4<f32>,
+    // The box's X and Y axes in world space, to build the tangent frame
+    // normal maps are in.
+    @location(6) @interpolate(flat) axis_x: vec3<f32>,
+    @location(7) @interpolate(flat) axis_y: vec3<f32>,
+}
+
+// The unit box's triangles, each corner's bits giving its x, y and z.
+const CORNERS: array<u32, 36> = array<u32, 36>(
+    0u, 2u, 1u, 1u, 2u, 3u,
+    4u, 5u, 6u, 5u, 7u, 6u,
+    0u, 1u, 4u, 1u, 5u, 4u,
+    2u, 6u, 3u, 3u, 6u, 7u,
+    0u, 4u, 2u, 2u, 4u, 6u,
+    1u, 3u, 5u, 3u, 7u, 5u,
+);
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, decal: DecalInput) -> VertexOutput {
+    let corner = CORNERS[index];
+    let local = vec3<f32>(
+        f32(corner & 1u),
+        f32((corner >> 1u) & 1u),
+        f32((corner >> 2u) & 1u),
+    ) - 0.5;
+    let model = mat4x4<f32>(decal.model_0, decal.model_1, decal.model_2, decal.model_3);
+    let clip = camera.view_proj * model * vec4<f32>(local, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(clip.xy + camera.jitter * clip.w, clip.zw);
+    out.inverse_0 = decal.inverse_0;
+    out.inverse_1 = decal.inverse_1;
+    out.inverse_2 = decal.inverse_2;
+    out.inverse_3 = decal.inverse_3;
+    out.color = decal.color;
+    out.params = decal.params;
+    out.axis_x = normalize(decal.model_0.xyz);
+    out.axis_y = normalize(decal.model_1.xyz);
+    return out;
+}
+
+// Where the depth buffer puts the surface at `pixel`.
+fn world_position(pixel: vec2<i32>) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    // Depth was rasterized with the TAA jitter, so take it back out.
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0) - camera.jitter;
+    let world = camera.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// Maps a unit vector onto the [-1, 1] square, like `octahedral_encode` in
+// shader.wgsl.
+fn octahedral_encode(n: vec3<f32>) -> vec2<f32> {
+    let p = n.xy / (abs(n.x) + abs(n.y) + abs(n.z));
+    if n.z >= 0.0 {
+        return p;
+    }
+    return (1.0 - abs(p.yx)) * select(vec2<f32>(-1.0), vec2<f32>(1.0), p >= vec2<f32>(0.0));
+}
+
+struct DecalOutput {
+    @location(0) albedo: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> DecalOutput {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let size = vec2<i32>(textureDimensions(t_depth));
+    let inverse = mat4x4<f32>(in.inverse_0, in.inverse_1, in.inverse_2, in.inverse_3);
+    let position = world_position(pixel);
+    let local = (inverse * vec4<f32>(position, 1.0)).xyz;
+    if is_far_depth(textureLoad(t_depth, pixel, 0).r) || any(abs(local) > vec3<f32>(0.5)) {
+        discard;
+    }
+
+    // The neighbours give the surface's slope, for its normal and for the
+    // texture's mip level; those on the far side at the target's edges.
+    let toward = select(vec2<i32>(1), vec2<i32>(-1), pixel + 1 >= size);
+    let right = world_position(pixel + vec2<i32>(toward.x, 0));
+    let below = world_position(pixel + vec2<i32>(0, toward.y));
+    let ddx_world = (right - position) * f32(toward.x);
+    let ddy_world = (below - position) * f32(toward.y);
+    let surface_normal = normalize(cross(ddy_world, ddx_world));
+    let facing = dot(surface_normal, in.axis_y);
+    let fade = smoothstep(in.params.x, min(in.params.x + 0.2, 1.0), facing);
+
+    let uv = vec2<f32>(local.x + 0.5, local.z + 0.5);
+    let ddx_uv = (inverse * vec4<f32>(ddx_world, 0.0)).xz;
+    let ddy_uv = (inverse * vec4<f32>(ddy_world, 0.0)).xz;
+    let albedo = textureSampleGrad(t_albedo, s_decal, uv, ddx_uv, ddy_uv) * in.color;
+    let alpha = albedo.a * fade;
+    if alpha <= 0.0 {
+        discard;
+    }
+
+    // Texture u runs along the box's X and v along its Z, on a surface
+    // facing up its Y; normal maps point green up the image, against v.
+    let tangent = normalize(in.axis_x - surface_normal * dot(in.axis_x, surface_normal));
+    let bitangent = cross(surface_normal, tangent);
+    let mapped = textureSampleGrad(t_normal, s_decal, uv, ddx_uv, ddy_uv).xyz * 2.0 - 1.0;
+    let normal = normalize(
+        tangent * mapped.x + bitangent * mapped.y + surface_normal * mapped.z,
+    );
+
+    var out: DecalOutput;
+    out.albedo = vec4<f32>(albedo.rgb, alpha);
+    out.normal = vec4<f32>(octahedral_encode(normal), 0.0, alpha);
+    return out;
+}
@@ -0,0 +1,370 @@
+use crate::culling::DrawInstances;
+use crate::decal::Decals;
+use crate::model;
+use crate::pipeline_cache::PipelineCache;
+use crate::post::taa::Taa;
+use crate::{post, texture};
+
+/// Which pipeline lights the scene; chosen once when `State` is created.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum RenderPath {
+    /// Materials are lit as they are drawn.
+    #[default]
+    Forward,
+    /// Forward shading that only walks the lights binned into each
+    /// fragment's cluster by `LightClusters`.
+    ForwardPlus,
+    /// Materials are written to a G-buffer and lit in one fullscreen pass,
+    /// so lighting cost no longer scales with overdraw.
+    Deferred,
+}
+
+/// The lighting pipeline built from one permutation of the scene shaders.
+#[derive(Clone)]
+pub struct DeferredPipelines {
+    lighting: wgpu::RenderPipeline,
+}
+
+/// G-buffer targets plus the pipeline that resolves them.
+///
+/// G-buffer pipelines belong to the materials drawn into it, built with
+/// `create_gbuffer_pipeline`; they share the forward vertex stage, so they
+/// also match depth laid down by the SSAO prepass. The lighting pass swaps
+/// the material group for the G-buffer and keeps the camera, light and
+/// shadow groups.
+pub struct Deferred {
+    albedo: texture::Texture,
+    normal: texture::Texture,
+    material: texture::Texture,
+    emissive: texture::Texture,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    lighting_layout: wgpu::PipelineLayout,
+    output_format: wgpu::TextureFormat,
+    pipelines: DeferredPipelines,
+}
+
+impl Deferred {
+    pub(crate) const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    pub(crate) const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+    const MATERIAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    const EMISSIVE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// `scene_bind_group_layouts` are the camera, light and shadow groups
+    /// of the scene pipelines, and `lighting_source` is deferred.wgsl,
+    /// preprocessed with the scene defines.
+    pub fn new(
+        device: &wgpu::Device,
+        lighting_source: &str,
+        scene_bind_group_layouts: [&wgpu::BindGroupLayout; 3],
+        depth_texture: &texture::Texture,
+        output_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Deferred::layout"),
+            entries: &[entry(0), entry(1), entry(2), entry(3), entry(4)],
+        });
+
+        let [camera_layout, light_layout, shadow_layout] = scene_bind_group_layouts;
+        let lighting_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Deferred::lighting_layout"),
+            bind_group_layouts: &[&layout, camera_layout, light_layout, shadow_layout],
+            push_constant_ranges: &[],
+        });
+        let pipelines =
+            Self::build_pipelines(device, &lighting_layout, lighting_source, output_format);
+
+        let (albedo, normal, material, emissive) = Self::create_targets(device, width, height);
+        let bind_group = Self::create_bind_group(
+            device,
+            &layout,
+            [&albedo, &normal, &material, &emissive, depth_texture],
+        );
+
+        Self {
+            albedo,
+            normal,
+            material,
+            emissive,
+            layout,
+            bind_group,
+            lighting_layout,
+            output_format,
+            pipelines,
+        }
+    }
+
+    /// A pipeline drawing materials into the G-buffer with `scene_shader`,
+    /// a module of shader.wgsl whose `layout` starts with the material group,
+    /// from meshes laid out as `vertex_buffers`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_gbuffer_pipeline(
+        device: &wgpu::Device,
+        cache: &PipelineCache,
+        label: &str,
+        layout: &wgpu::PipelineLayout,
+        scene_shader: &wgpu::ShaderModule,
+        vertex_buffers: &[wgpu::VertexBufferLayout],
+        cull_mode: Option<wgpu::Face>,
+        depth_compare: wgpu::CompareFunction,
+    ) -> wgpu::RenderPipeline {
+        let target = |format| {
+            Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+        };
+        cache.render_pipeline(
+            device,
+            &wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: scene_shader,
+                    entry_point: Some("vs_main"),
+                    buffers: vertex_buffers,
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: scene_shader,
+                    entry_point: Some("fs_gbuffer"),
+                    targets: &[
+                        target(Self::ALBEDO_FORMAT),
+                        target(Self::NORMAL_FORMAT),
+                        target(Self::MATERIAL_FORMAT),
+                        target(Self::EMISSIVE_FORMAT),
+                        target(Taa::VELOCITY_FORMAT),
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            },
+        )
+    }
+
+    fn build_pipelines(
+        device: &wgpu::Device,
+        lighting_layout: &wgpu::PipelineLayout,
+        lighting_source: &str,
+        output_format: wgpu::TextureFormat,
+    ) -> DeferredPipelines {
+        let lighting_shader =
+            post::create_shader(device, "Deferred::lighting_shader", lighting_source);
+        let lighting = post::create_pipeline(
+            device,
+            "Deferred::lighting_pipeline",
+            lighting_layout,
+            &lighting_shader,
+            "fs_lighting",
+            output_format,
+            None,
+        );
+        DeferredPipelines { lighting }
+    }
+
+    /// Builds the lighting pipeline for another permutation of the scene
+    /// shaders, as `new` does for the first; `set_pipelines` switches to it.
+    pub fn create_pipelines(
+        &self,
+        device: &wgpu::Device,
+        lighting_source: &str,
+    ) -> DeferredPipelines {
+        Self::build_pipelines(
+            device,
+            &self.lighting_layout,
+            lighting_source,
+            self.output_format,
+        )
+    }
+
+    pub fn pipelines(&self) -> &DeferredPipelines {
+        &self.pipelines
+    }
+
+    pub fn set_pipelines(&mut self, pipelines: DeferredPipelines) {
+        self.pipelines = pipelines;
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (
+        texture::Texture,
+        texture::Texture,
+        texture::Texture,
+        texture::Texture,
+    ) {
+        let target = |format, label| {
+            texture::Texture::create_render_target(device, width, height, format, label)
+        };
+        (
+            target(Self::ALBEDO_FORMAT, "Deferred::albedo"),
+            target(Self::NORMAL_FORMAT, "Deferred::normal"),
+            target(Self::MATERIAL_FORMAT, "Deferred::material"),
+            target(Self::EMISSIVE_FORMAT, "Deferred::emissive"),
+        )
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        textures: [&texture::Texture; 5],
+    ) -> wgpu::BindGroup {
+        let entries = textures
+            .iter()
+            .enumerate()
+            .map(|(binding, texture)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            })
+            .collect::<Vec<_>>();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Deferred::bind_group"),
+            layout,
+            entries: &entries,
+        })
+    }
+
+    /// The albedo, normal, material and emissive targets.
+    pub fn gbuffer(&self) -> [&texture::Texture; 4] {
+        [&self.albedo, &self.normal, &self.material, &self.emissive]
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        depth_texture: &texture::Texture,
+        width: u32,
+        height: u32,
+    ) {
+        (self.albedo, self.normal, self.material, self.emissive) =
+            Self::create_targets(device, width, height);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            [
+                &self.albedo,
+                &self.normal,
+                &self.material,
+                &self.emissive,
+                depth_texture,
+            ],
+        );
+    }
+
+    /// Fills the G-buffer and lights it into `output`. `depth_load` follows
+    /// the forward pass: load when a prepass already wrote depth.
+    ///
+    /// `gbuffer_draws` pairs each material's G-buffer pipeline with the
+    /// meshes drawn with it, and `decals` are blended over what they
+    /// leave before it is lit. `scene_bind_groups` are the camera, light and
+    /// shadow groups, and `velocity` receives screen-space motion like the
+    /// forward pass.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        gbuffer_draws: &[(&wgpu::RenderPipeline, DrawInstances)],
+        depth_texture: &texture::Texture,
+        depth_load: wgpu::LoadOp<f32>,
+        decals: &Decals,
+        scene_bind_groups: [&wgpu::BindGroup; 3],
+        velocity: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        let [camera_bind_group, light_bind_group, shadow_bind_group] = scene_bind_groups;
+        {
+            let attachment = |view| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })
+            };
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Deferred::gbuffer"),
+                color_attachments: &[
+                    attachment(&self.albedo.view),
+                    attachment(&self.normal.view),
+                    attachment(&self.material.view),
+                    attachment(&self.emissive.view),
+                    attachment(velocity),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: depth_load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            for (pipeline, instances) in gbuffer_draws {
+                if instances.is_empty() {
+                    continue;
+                }
+                pass.set_vertex_buffer(1, instances.slice());
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(3, shadow_bind_group, &[]);
+                instances.draw_model(&mut pass, model, camera_bind_group, light_bind_group);
+            }
+        }
+        decals.render(encoder, &self.albedo.view, &self.normal.view);
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Deferred::lighting"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipelines.lighting);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_bind_group(1, camera_bind_group, &[]);
+        pass.set_bind_group(2, light_bind_group, &[]);
+        pass.set_bind_group(3, shadow_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
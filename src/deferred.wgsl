@@ -0,0 +1,57 @@
+#include "lighting.wgsl"
+
+// Composed after fullscreen.wgsl. Every G-buffer target is read with
+// textureLoad at the pixel being lit, so none need a sampler.
+@group(0) @binding(0)
+var t_albedo: texture_2d<f32>;
+@group(0) @binding(1)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(2)
+var t_material: texture_2d<f32>;
+@group(0) @binding(3)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(4)
+var t_depth: texture_2d<f32>;
+
+// Pixels left at the far plane show the clear color; the skybox is drawn
+// over them afterwards.
+const CLEAR_COLOR: vec3<f32> = vec3<f32>(0.1, 0.2, 0.3);
+
+// Inverse of `octahedral_encode` in shader.wgsl.
+fn octahedral_decode(e: vec2<f32>) -> vec3<f32> {
+    var n = vec3<f32>(e, 1.0 - abs(e.x) - abs(e.y));
+    let t = max(-n.z, 0.0);
+    n.x += select(t, -t, n.x >= 0.0);
+    n.y += select(t, -t, n.y >= 0.0);
+    return normalize(n);
+}
+
+@fragment
+fn fs_lighting(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    if is_far_depth(depth) {
+        return vec4<f32>(CLEAR_COLOR, 1.0);
+    }
+
+    // Depth was rasterized with the TAA jitter, so take it back out.
+    let ndc_xy = vec2<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0) - camera.jitter;
+    let ndc = vec4<f32>(ndc_xy, depth, 1.0);
+    let world = camera.inv_view_proj * ndc;
+    let world_position = world.xyz / world.w;
+
+    let albedo = textureLoad(t_albedo, pixel, 0);
+    let material = textureLoad(t_material, pixel, 0);
+    var surface: Surface;
+    surface.albedo = albedo.rgb;
+    surface.normal = octahedral_decode(textureLoad(t_normal, pixel, 0).xy);
+    surface.view_dir = normalize(camera.view_pos.xyz - world_position);
+    surface.metallic = material.r;
+    surface.roughness = material.g;
+    surface.f0 = mix(vec3<f32>(0.04), surface.albedo, surface.metallic);
+    surface.occlusion = albedo.a;
+    surface.emissive = textureLoad(t_emissive, pixel, 0).rgb;
+
+    let lit = shade(surface, world_position, in.clip_position.xy);
+    return vec4<f32>(apply_fog(lit, world_position), 1.0);
+}
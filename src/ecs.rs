@@ -0,0 +1,360 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// Handle to an entity of a `World`. Ids of despawned entities are reused
+/// with a new generation, so stale handles never alias a new entity.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+/// Draws an entity with a `Transform` as an instance of the scene model,
+/// every one of its meshes with their own materials.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mesh {
+    /// Whether it's drawn into the shadow maps.
+    pub cast_shadows: bool,
+}
+
+impl Default for Mesh {
+    fn default() -> Self {
+        Self { cast_shadows: true }
+    }
+}
+
+/// What a `Mesh` entity changes about the scene model's materials for
+/// itself alone; without one, it's drawn as they are.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Material {
+    /// Multiplies each material's base color, alpha included.
+    pub base_color: [f32; 4],
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+        }
+    }
+}
+
+/// Marks the entity whose `Camera` the scene is rendered from.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ActiveCamera;
+
+trait Storage {
+    fn remove(&mut self, index: usize, tick: u64);
+    fn changed(&self) -> u64;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+// One slot per entity index, each with the tick it last changed at.
+struct Column<T> {
+    values: Vec<Option<T>>,
+    ticks: Vec<u64>,
+    changed: u64,
+    // The last tick a value was added to an empty slot or removed.
+    added_or_removed: u64,
+}
+
+impl<T> Column<T> {
+    // Returns the value it replaces.
+    fn set(&mut self, index: usize, value: Option<T>, tick: u64) -> Option<T> {
+        if self.values.len() <= index {
+            self.values.resize_with(index + 1, || None);
+            self.ticks.resize(index + 1, 0);
+        }
+        if self.values[index].is_some() != value.is_some() {
+            self.added_or_removed = tick;
+        }
+        self.ticks[index] = tick;
+        self.changed = tick;
+        std::mem::replace(&mut self.values[index], value)
+    }
+}
+
+impl<T: 'static> Storage for Column<T> {
+    fn remove(&mut self, index: usize, tick: u64) {
+        if self.values.get(index).is_some_and(Option::is_some) {
+            self.set(index, None, tick);
+        }
+    }
+
+    fn changed(&self) -> u64 {
+        self.changed
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Entities and their components, stored by type.
+///
+/// Any `'static` type can be a component; an entity holds at most one of
+/// each. Every mutable access to a component counts as a change to it,
+/// which is what `changed_since` reports for a type and `changed` for each
+/// entity, so systems can skip work when nothing they read has been
+/// touched.
+#[derive(Default)]
+pub struct World {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+    storages: HashMap<TypeId, Box<dyn Storage>>,
+    tick: u64,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                self.alive[index as usize] = true;
+                Entity {
+                    index,
+                    generation: self.generations[index as usize],
+                }
+            }
+            None => {
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    /// Removes `entity` and all its components; false if it was already
+    /// gone.
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.contains(entity) {
+            return false;
+        }
+        let tick = self.next_tick();
+        let index = entity.index as usize;
+        for storage in self.storages.values_mut() {
+            storage.remove(index, tick);
+        }
+        self.alive[index] = false;
+        self.generations[index] += 1;
+        self.free.push(entity.index);
+        true
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        let index = entity.index as usize;
+        self.alive.get(index).copied().unwrap_or(false)
+            && self.generations[index] == entity.generation
+    }
+
+    /// Entities alive right now.
+    pub fn len(&self) -> usize {
+        self.alive.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// The number of changes made so far; pass it to `changed_since` later.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Whether any `T` was inserted, removed or borrowed mutably after
+    /// `tick`.
+    pub fn changed_since<T: 'static>(&self, tick: u64) -> bool {
+        self.storages
+            .get(&TypeId::of::<T>())
+            .is_some_and(|storage| storage.changed() > tick)
+    }
+
+    /// Whether an entity gained or lost a `T` after `tick`, despawning
+    /// included; replacing one with `insert` doesn't count.
+    pub fn added_or_removed_since<T: 'static>(&self, tick: u64) -> bool {
+        self.column::<T>()
+            .is_some_and(|column| column.added_or_removed > tick)
+    }
+
+    /// Every living entity whose `T` was inserted, removed or borrowed
+    /// mutably after `tick`, with the `T` it has now, if any.
+    pub fn changed<T: 'static>(&self, tick: u64) -> impl Iterator<Item = (Entity, Option<&T>)> {
+        self.column::<T>()
+            .filter(move |column| column.changed > tick)
+            .into_iter()
+            .flat_map(|column| column.values.iter().zip(&column.ticks).enumerate())
+            .filter(move |&(index, (_, &changed))| changed > tick && self.alive[index])
+            .map(|(index, (value, _))| (self.entity(index), value.as_ref()))
+    }
+
+    fn column<T: 'static>(&self) -> Option<&Column<T>> {
+        self.storages
+            .get(&TypeId::of::<T>())
+            .and_then(|storage| storage.as_any().downcast_ref())
+    }
+
+    // With the tick the caller changes it at.
+    fn column_mut<T: 'static>(&mut self) -> (&mut Column<T>, u64) {
+        let tick = self.next_tick();
+        let column = self
+            .storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| {
+                Box::new(Column::<T> {
+                    values: Vec::new(),
+                    ticks: Vec::new(),
+                    changed: 0,
+                    added_or_removed: 0,
+                })
+            })
+            .as_any_mut()
+            .downcast_mut::<Column<T>>()
+            .unwrap();
+        (column, tick)
+    }
+
+    /// Adds or replaces `entity`'s `T`; ignored for a despawned entity.
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) {
+        if !self.contains(entity) {
+            return;
+        }
+        let (column, tick) = self.column_mut::<T>();
+        column.set(entity.index as usize, Some(component), tick);
+    }
+
+    pub fn remove<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        if !self.has::<T>(entity) {
+            return None;
+        }
+        let (column, tick) = self.column_mut::<T>();
+        column.set(entity.index as usize, None, tick)
+    }
+
+    pub fn get<T: 'static>(&self, entity: Entity) -> Option<&T> {
+        if !self.contains(entity) {
+            return None;
+        }
+        self.column::<T>()?
+            .values
+            .get(entity.index as usize)?
+            .as_ref()
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
+        if !self.has::<T>(entity) {
+            return None;
+        }
+        let (column, tick) = self.column_mut::<T>();
+        let index = entity.index as usize;
+        column.ticks[index] = tick;
+        column.changed = tick;
+        column.values[index].as_mut()
+    }
+
+    pub fn has<T: 'static>(&self, entity: Entity) -> bool {
+        self.get::<T>(entity).is_some()
+    }
+
+    fn entity(&self, index: usize) -> Entity {
+        Entity {
+            index: index as u32,
+            generation: self.generations[index],
+        }
+    }
+
+    /// Every entity with a `T`, in spawn-slot order.
+    pub fn query<T: 'static>(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.column::<T>()
+            .into_iter()
+            .flat_map(|column| column.values.iter().enumerate())
+            .filter_map(|(index, value)| Some((self.entity(index), value.as_ref()?)))
+    }
+
+    /// `query` with mutable access; counts as a change to `T` even if
+    /// nothing is written.
+    pub fn query_mut<T: 'static>(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        let tick = self.next_tick();
+        let generations = &self.generations;
+        let mut column = self
+            .storages
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|storage| storage.as_any_mut().downcast_mut::<Column<T>>());
+        if let Some(column) = column.as_deref_mut() {
+            column.changed = tick;
+        }
+        column
+            .into_iter()
+            .flat_map(|column| column.values.iter_mut().zip(&mut column.ticks).enumerate())
+            .filter_map(move |(index, (value, changed))| {
+                let entity = Entity {
+                    index: index as u32,
+                    generation: generations[index],
+                };
+                let value = value.as_mut()?;
+                *changed = tick;
+                Some((entity, value))
+            })
+    }
+
+    /// Every entity with both an `A` and a `B`.
+    pub fn query2<A: 'static, B: 'static>(&self) -> impl Iterator<Item = (Entity, &A, &B)> {
+        self.query::<A>()
+            .filter_map(|(entity, a)| Some((entity, a, self.get::<B>(entity)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_yields_only_entities_touched_after_the_tick() {
+        let mut world = World::new();
+        let a = world.spawn();
+        let b = world.spawn();
+        world.insert(a, 1u32);
+        world.insert(b, 2u32);
+        let tick = world.tick();
+        *world.get_mut::<u32>(b).unwrap() = 3;
+        let changed = world.changed::<u32>(tick).collect::<Vec<_>>();
+        assert_eq!(changed, [(b, Some(&3))]);
+        assert!(!world.added_or_removed_since::<u32>(tick));
+    }
+
+    #[test]
+    fn removing_and_despawning_count_as_added_or_removed() {
+        let mut world = World::new();
+        let a = world.spawn();
+        let b = world.spawn();
+        world.insert(a, 1u32);
+        world.insert(b, 2u32);
+        let tick = world.tick();
+        world.insert(a, 4u32);
+        assert!(!world.added_or_removed_since::<u32>(tick));
+        assert_eq!(world.remove::<u32>(a), Some(4));
+        assert!(world.added_or_removed_since::<u32>(tick));
+        assert_eq!(world.changed::<u32>(tick).collect::<Vec<_>>(), [(a, None)]);
+
+        let tick = world.tick();
+        world.despawn(b);
+        assert!(world.added_or_removed_since::<u32>(tick));
+        assert_eq!(world.changed::<u32>(tick).count(), 0);
+    }
+}
@@ -0,0 +1,251 @@
+use cgmath::prelude::*;
+
+use crate::debug_draw::DebugDraw;
+use crate::instance::Instance;
+use crate::model::Bounds;
+use crate::picking::Ray;
+
+/// What dragging the editor's gizmo does to the selected instance.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum GizmoMode {
+    /// Moves it along a world axis.
+    #[default]
+    Translate,
+    /// Turns it around a world axis.
+    Rotate,
+    /// Stretches it along one of its own axes.
+    Scale,
+}
+
+impl GizmoMode {
+    pub const ALL: [Self; 3] = [Self::Translate, Self::Rotate, Self::Scale];
+
+    /// The mode after this one in `ALL`, wrapping around.
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&mode| mode == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+impl std::fmt::Display for GizmoMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Translate => write!(f, "translate"),
+            Self::Rotate => write!(f, "rotate"),
+            Self::Scale => write!(f, "scale"),
+        }
+    }
+}
+
+/// Turns the viewer into an editor: the selected instance shows a gizmo
+/// whose handles drag it around, and clicks elsewhere still pick.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct EditorSettings {
+    pub enabled: bool,
+    pub mode: GizmoMode,
+}
+
+const AXES: [cgmath::Vector3<f32>; 3] = [
+    cgmath::Vector3::new(1.0, 0.0, 0.0),
+    cgmath::Vector3::new(0.0, 1.0, 0.0),
+    cgmath::Vector3::new(0.0, 0.0, 1.0),
+];
+const COLORS: [[f32; 4]; 3] = [
+    [1.0, 0.2, 0.2, 1.0],
+    [0.2, 1.0, 0.2, 1.0],
+    [0.3, 0.4, 1.0, 1.0],
+];
+// The handle dragged or under the cursor.
+const HIGHLIGHT: [f32; 4] = [1.0, 1.0, 0.2, 1.0];
+
+/// Handles along, or around, the three world axes through an instance:
+/// arrows to translate, rings to rotate and boxed ends to scale.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Gizmo {
+    mode: GizmoMode,
+    center: cgmath::Point3<f32>,
+    // The length of the arrows and the radius of the rings.
+    size: f32,
+}
+
+impl Gizmo {
+    // As a fraction of the distance from the eye, so the gizmo covers about
+    // the same part of the view however far away it is.
+    const SIZE: f32 = 0.2;
+    // How near a ray must pass a handle to grab it, as a fraction of `size`.
+    const GRAB_DISTANCE: f32 = 0.08;
+
+    /// Handles at least reaching out of a sphere of `radius` around
+    /// `center`, so the instance there doesn't hide them.
+    pub fn new(
+        mode: GizmoMode,
+        center: cgmath::Point3<f32>,
+        radius: f32,
+        eye: cgmath::Point3<f32>,
+    ) -> Self {
+        let size = (center.distance(eye) * Self::SIZE).max(radius * 1.3);
+        Self {
+            mode,
+            center,
+            size: size.max(1e-3),
+        }
+    }
+
+    /// The axis of the handle `ray` passes nearest, if near enough to
+    /// grab.
+    pub fn handle(&self, ray: &Ray) -> Option<usize> {
+        let reach = self.size * Self::GRAB_DISTANCE;
+        (0..AXES.len())
+            .filter_map(|axis| {
+                let miss = match self.mode {
+                    GizmoMode::Translate | GizmoMode::Scale => {
+                        let (along, miss) = self.closest_on_axis(ray, axis)?;
+                        (0.0..=self.size * 1.1).contains(&along).then_some(miss)?
+                    }
+                    GizmoMode::Rotate => {
+                        let point = self.on_plane(ray, axis)?;
+                        (point.distance(self.center) - self.size).abs()
+                    }
+                };
+                (miss <= reach).then_some((axis, miss))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(axis, _)| axis)
+    }
+
+    /// Starts dragging `axis`'s handle where `ray` grabs it, to change
+    /// `instance`.
+    pub fn drag(&self, axis: usize, ray: &Ray, instance: Instance) -> Option<GizmoDrag> {
+        let grab = match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                Grab::Along(self.closest_on_axis(ray, axis)?.0)
+            }
+            GizmoMode::Rotate => Grab::Around(self.on_plane(ray, axis)? - self.center),
+        };
+        Some(GizmoDrag {
+            gizmo: *self,
+            axis,
+            start: instance,
+            grab,
+        })
+    }
+
+    /// Adds the handles, `highlighted`'s standing out, over everything
+    /// else.
+    pub fn draw(&self, debug_draw: &mut DebugDraw, highlighted: Option<usize>) {
+        debug_draw.overlay(|debug_draw| {
+            for axis in 0..AXES.len() {
+                let color = if highlighted == Some(axis) {
+                    HIGHLIGHT
+                } else {
+                    COLORS[axis]
+                };
+                let direction = AXES[axis];
+                let others = [AXES[(axis + 1) % 3], AXES[(axis + 2) % 3]];
+                let tip = self.center + direction * self.size;
+                match self.mode {
+                    GizmoMode::Translate => {
+                        debug_draw.line(self.center, tip, color);
+                        let base = tip - direction * self.size * 0.2;
+                        for side in others {
+                            for sign in [-1.0, 1.0] {
+                                let barb = base + side * sign * self.size * 0.07;
+                                debug_draw.line(tip, barb, color);
+                            }
+                        }
+                    }
+                    GizmoMode::Rotate => debug_draw.circle(self.center, others, self.size, color),
+                    GizmoMode::Scale => {
+                        debug_draw.line(self.center, tip, color);
+                        let half = cgmath::Vector3::from_value(self.size * 0.06);
+                        let bounds = Bounds {
+                            min: tip - half,
+                            max: tip + half,
+                        };
+                        debug_draw.wire_aabb(&bounds, color);
+                    }
+                }
+            }
+        });
+    }
+
+    // How far along `axis` from the center the point nearest `ray` is, and
+    // how far from the ray. None if the ray runs along the axis, or only
+    // gets nearest behind its origin.
+    fn closest_on_axis(&self, ray: &Ray, axis: usize) -> Option<(f32, f32)> {
+        let direction = AXES[axis];
+        let to_center = self.center - ray.origin;
+        let cos = direction.dot(ray.direction);
+        let sin_squared = 1.0 - cos * cos;
+        if sin_squared < 1e-6 {
+            return None;
+        }
+        let along = (cos * ray.direction.dot(to_center) - direction.dot(to_center)) / sin_squared;
+        let distance = ray.direction.dot(to_center) + cos * along;
+        if distance < 0.0 {
+            return None;
+        }
+        let miss = (self.center + direction * along).distance(ray.at(distance));
+        Some((along, miss))
+    }
+
+    // Where `ray` crosses the plane through the center across `axis`.
+    fn on_plane(&self, ray: &Ray, axis: usize) -> Option<cgmath::Point3<f32>> {
+        let normal = AXES[axis];
+        let facing = ray.direction.dot(normal);
+        if facing.abs() < 1e-4 {
+            return None;
+        }
+        let distance = (self.center - ray.origin).dot(normal) / facing;
+        (distance >= 0.0).then(|| ray.at(distance))
+    }
+}
+
+// Where a drag grabbed its handle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Grab {
+    // This far along the axis from the center.
+    Along(f32),
+    // This way from the center, in the plane across the axis.
+    Around(cgmath::Vector3<f32>),
+}
+
+/// A gizmo handle held down, from the instance as it was when grabbed.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct GizmoDrag {
+    gizmo: Gizmo,
+    axis: usize,
+    start: Instance,
+    grab: Grab,
+}
+
+impl GizmoDrag {
+    pub fn axis(&self) -> usize {
+        self.axis
+    }
+
+    /// The instance with the handle moved to where `ray` points. None
+    /// while the ray misses what the handle moves along.
+    pub fn update(&self, ray: &Ray) -> Option<Instance> {
+        let axis = AXES[self.axis];
+        let mut instance = self.start;
+        match self.grab {
+            Grab::Along(start) => {
+                let (along, _) = self.gizmo.closest_on_axis(ray, self.axis)?;
+                if self.gizmo.mode == GizmoMode::Translate {
+                    instance.position += axis * (along - start);
+                } else {
+                    let factor = (along / start.max(1e-3)).max(0.01);
+                    instance.scale[self.axis] *= factor;
+                }
+            }
+            Grab::Around(start) => {
+                let now = self.gizmo.on_plane(ray, self.axis)? - self.gizmo.center;
+                let angle = axis.dot(start.cross(now)).atan2(start.dot(now));
+                let turn = cgmath::Quaternion::from_axis_angle(axis, cgmath::Rad(angle));
+                instance.rotation = (turn * self.start.rotation).normalize();
+            }
+        }
+        Some(instance)
+    }
+}
@@ -0,0 +1,380 @@
+use wgpu::util::DeviceExt;
+
+use crate::preprocess::{self, ShaderDefs};
+use crate::skybox::Skybox;
+use crate::{resources, texture};
+
+const IRRADIANCE_SIZE: u32 = 32;
+const IRRADIANCE_SAMPLES: u32 = 512;
+pub(crate) const PREFILTERED_SIZE: u32 = 128;
+pub(crate) const PREFILTERED_MIPS: u32 = 5;
+const PREFILTERED_SAMPLES: u32 = 256;
+const BRDF_LUT_SIZE: u32 = 128;
+const BRDF_LUT_SAMPLES: u32 = 512;
+/// Face size used when converting equirectangular images.
+pub const CUBE_SIZE: u32 = 512;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BakeUniform {
+    face: u32,
+    roughness: f32,
+    sample_count: u32,
+    _padding: u32,
+}
+
+struct BakePass<'a> {
+    pipeline: &'a wgpu::RenderPipeline,
+    view: wgpu::TextureView,
+    uniform: BakeUniform,
+}
+
+/// The bake pipelines, which read a source cube.
+struct Baker {
+    bind_group_layout: wgpu::BindGroupLayout,
+    irradiance_pipeline: wgpu::RenderPipeline,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    brdf_lut_pipeline: wgpu::RenderPipeline,
+}
+
+impl Baker {
+    fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(size_of::<BakeUniform>() as u64),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("environment_bake_bind_group_layout"),
+        });
+        let shader =
+            preprocess::builtin_module(device, "IBL Shader", "ibl.wgsl", &ShaderDefs::new());
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("IBL Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point, format: wgpu::TextureFormat| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(format.into())],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let irradiance_pipeline = create_pipeline("fs_irradiance", Environment::CUBE_FORMAT);
+        let prefilter_pipeline = create_pipeline("fs_prefilter", Environment::CUBE_FORMAT);
+        let brdf_lut_pipeline = create_pipeline("fs_brdf_lut", Environment::LUT_FORMAT);
+
+        Self {
+            bind_group_layout,
+            irradiance_pipeline,
+            prefilter_pipeline,
+            brdf_lut_pipeline,
+        }
+    }
+
+    /// A pass per mip of each face of `target`'s six layers from
+    /// `first_layer` on, each rougher than the last.
+    fn prefilter_passes<'a>(
+        &'a self,
+        target: &'a wgpu::Texture,
+        first_layer: u32,
+    ) -> impl Iterator<Item = BakePass<'a>> {
+        (0..6).flat_map(move |face| {
+            (0..PREFILTERED_MIPS).map(move |mip| {
+                let roughness = mip as f32 / (PREFILTERED_MIPS - 1) as f32;
+                BakePass {
+                    pipeline: &self.prefilter_pipeline,
+                    view: face_view(target, mip, first_layer + face),
+                    uniform: bake_uniform(face, roughness, PREFILTERED_SAMPLES),
+                }
+            })
+        })
+    }
+
+    /// Draws `passes` from `cube`, and submits them.
+    fn run(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cube: &texture::Texture,
+        passes: &[BakePass],
+    ) {
+        // One uniform slot per pass, selected with a dynamic offset.
+        let stride = (size_of::<BakeUniform>() as u32)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment);
+        let mut contents = vec![0u8; (stride as usize) * passes.len()];
+        for (i, pass) in passes.iter().enumerate() {
+            let offset = i * stride as usize;
+            contents[offset..offset + size_of::<BakeUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&pass.uniform));
+        }
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Bake Buffer"),
+            contents: &contents,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &uniform_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(size_of::<BakeUniform>() as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&cube.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&cube.sampler),
+                },
+            ],
+            label: Some("environment_bake_bind_group"),
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Bake Encoder"),
+        });
+        for (i, pass) in passes.iter().enumerate() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Environment Bake Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &pass.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(pass.pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[i as u32 * stride]);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
+
+fn face_view(texture: &wgpu::Texture, mip: u32, layer: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("environment_face_view"),
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        base_array_layer: layer,
+        array_layer_count: Some(1),
+        ..Default::default()
+    })
+}
+
+fn bake_uniform(face: u32, roughness: f32, sample_count: u32) -> BakeUniform {
+    BakeUniform {
+        face,
+        roughness,
+        sample_count,
+        _padding: 0,
+    }
+}
+
+/// Image-based lighting derived from an environment cube: diffuse
+/// irradiance, a specular mip chain indexed by roughness and the BRDF LUT
+/// for the split-sum approximation.
+pub struct Environment {
+    pub cube: texture::Texture,
+    pub irradiance: texture::Texture,
+    pub prefiltered: texture::Texture,
+    pub brdf_lut: texture::Texture,
+}
+
+impl Environment {
+    const CUBE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const LUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+    /// Bakes the lighting textures for `cube` on the GPU.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, cube: texture::Texture) -> Self {
+        let irradiance = Self::create_target(
+            device,
+            IRRADIANCE_SIZE,
+            1,
+            6,
+            Self::CUBE_FORMAT,
+            "environment_irradiance",
+        );
+        let prefiltered = Self::create_target(
+            device,
+            PREFILTERED_SIZE,
+            PREFILTERED_MIPS,
+            6,
+            Self::CUBE_FORMAT,
+            "environment_prefiltered",
+        );
+        let brdf_lut = Self::create_target(
+            device,
+            BRDF_LUT_SIZE,
+            1,
+            1,
+            Self::LUT_FORMAT,
+            "environment_brdf_lut",
+        );
+
+        let baker = Baker::new(device);
+        let mut passes = Vec::new();
+        for face in 0..6 {
+            passes.push(BakePass {
+                pipeline: &baker.irradiance_pipeline,
+                view: face_view(&irradiance.texture, 0, face),
+                uniform: bake_uniform(face, 0.0, IRRADIANCE_SAMPLES),
+            });
+        }
+        passes.extend(baker.prefilter_passes(&prefiltered.texture, 0));
+        passes.push(BakePass {
+            pipeline: &baker.brdf_lut_pipeline,
+            view: face_view(&brdf_lut.texture, 0, 0),
+            uniform: bake_uniform(0, 0.0, BRDF_LUT_SAMPLES),
+        });
+        baker.run(device, queue, &cube, &passes);
+
+        Self {
+            cube,
+            irradiance,
+            prefiltered,
+            brdf_lut,
+        }
+    }
+
+    /// Bakes `cube`'s specular mip chain, as `prefiltered` holds it, into
+    /// the six layers of `target` from `first_layer` on. `target` must be
+    /// `PREFILTERED_SIZE` square with `PREFILTERED_MIPS` mips.
+    pub(crate) fn prefilter(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cube: &texture::Texture,
+        target: &wgpu::Texture,
+        first_layer: u32,
+    ) {
+        let baker = Baker::new(device);
+        let passes: Vec<_> = baker.prefilter_passes(target, first_layer).collect();
+        baker.run(device, queue, cube, &passes);
+    }
+
+    /// Loads an equirectangular HDR image and bakes its lighting.
+    pub async fn from_hdr(
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<Self> {
+        let data = resources::load_binary(file_name).await?;
+        let image = image::load_from_memory(&data)?.to_rgb32f();
+        let cube = Skybox::cube_from_equirectangular(device, queue, &image, CUBE_SIZE, file_name);
+        Ok(Self::new(device, queue, cube))
+    }
+
+    /// Sky gradient matching `Skybox::gradient`, used as the default.
+    pub fn gradient(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    ) -> Self {
+        let cube = Skybox::gradient_cube(device, queue, zenith, horizon, ground);
+        Self::new(device, queue, cube)
+    }
+
+    fn create_target(
+        device: &wgpu::Device,
+        size: u32,
+        mip_level_count: u32,
+        layers: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> texture::Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(if layers == 6 {
+                wgpu::TextureViewDimension::Cube
+            } else {
+                wgpu::TextureViewDimension::D2
+            }),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+}
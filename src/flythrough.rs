@@ -0,0 +1,347 @@
+use crate::camera::{Camera, Projection};
+
+/// Where the debug UI saves and loads the camera path.
+pub const DEFAULT_PATH: &str = "camera_path.toml";
+
+/// One stop on a camera path.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CameraKeyframe {
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    /// Vertical field of view in degrees.
+    pub fovy: f32,
+}
+
+impl CameraKeyframe {
+    /// Where `camera` is. An orthographic camera records the field of view
+    /// `Camera::toggle_projection` would give it.
+    pub fn from_camera(camera: &Camera) -> Self {
+        let fovy = match camera.projection {
+            Projection::Perspective { fovy } => fovy,
+            Projection::Orthographic { .. } => {
+                let mut perspective = *camera;
+                perspective.toggle_projection();
+                match perspective.projection {
+                    Projection::Perspective { fovy } => fovy,
+                    Projection::Orthographic { .. } => unreachable!(),
+                }
+            }
+        };
+        Self {
+            eye: camera.eye,
+            target: camera.target,
+            fovy,
+        }
+    }
+
+    /// Moves `camera` here, switching it to perspective.
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.eye;
+        camera.target = self.target;
+        camera.projection = Projection::Perspective { fovy: self.fovy };
+    }
+
+    /// Straight from `self` at 0 to `other` at 1.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            eye: self.eye + (other.eye - self.eye) * t,
+            target: self.target + (other.target - self.target) * t,
+            fovy: self.fovy + (other.fovy - self.fovy) * t,
+        }
+    }
+
+    /// Reads the `eye` and `target` arrays and `fovy` out of `table`, which
+    /// errors call `name`.
+    pub(crate) fn from_toml(table: &toml_edit::Table, name: &str) -> anyhow::Result<Self> {
+        let point = |key| {
+            let point = table
+                .get(key)
+                .and_then(toml_edit::Item::as_array)
+                .filter(|array| array.len() == 3)
+                .and_then(|array| {
+                    array
+                        .iter()
+                        .map(|value| {
+                            value
+                                .as_float()
+                                .or_else(|| value.as_integer().map(|value| value as f64))
+                                .map(|value| value as f32)
+                        })
+                        .collect::<Option<Vec<_>>>()
+                });
+            match point.as_deref() {
+                Some(&[x, y, z]) => Ok(cgmath::Point3::new(x, y, z)),
+                _ => anyhow::bail!("{}: {} should be three numbers", name, key),
+            }
+        };
+        Ok(Self {
+            eye: point("eye")?,
+            target: point("target")?,
+            fovy: table
+                .get("fovy")
+                .and_then(number)
+                .ok_or_else(|| anyhow::anyhow!("{}: fovy should be a number", name))?,
+        })
+    }
+
+    /// The lines of a table `from_toml` reads back as `self`.
+    pub(crate) fn toml_fields(&self) -> String {
+        let point = |p: cgmath::Point3<f32>| format!("[{:?}, {:?}, {:?}]", p.x, p.y, p.z);
+        format!(
+            "eye = {}\ntarget = {}\nfovy = {:?}\n",
+            point(self.eye),
+            point(self.target),
+            self.fovy
+        )
+    }
+}
+
+/// Keyframes a flythrough passes through at an even pace, joined by a
+/// Catmull-Rom spline so it never stops or turns sharply at one.
+///
+/// Saved as TOML: a `segment_seconds` key and, for a looping path,
+/// `looping = true`, then one `[[keyframe]]` table per keyframe with `eye`
+/// and `target` arrays and a `fovy`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraPath {
+    pub keyframes: Vec<CameraKeyframe>,
+    /// Seconds from each keyframe to the next.
+    pub segment_seconds: f32,
+    /// Whether the path goes on from its last keyframe back to its first,
+    /// playing until stopped.
+    pub looping: bool,
+}
+
+impl Default for CameraPath {
+    fn default() -> Self {
+        Self {
+            keyframes: Vec::new(),
+            segment_seconds: 2.0,
+            looping: false,
+        }
+    }
+}
+
+impl CameraPath {
+    /// How long playing the whole path takes, once round for a looping
+    /// one.
+    pub fn duration(&self) -> f32 {
+        let segments = if self.looping {
+            self.keyframes.len()
+        } else {
+            self.keyframes.len().saturating_sub(1)
+        };
+        segments as f32 * self.segment_seconds
+    }
+
+    /// Where the camera is `time` seconds in, held at either end or, for a
+    /// looping path, wrapped around. None without keyframes.
+    pub fn sample(&self, time: f32) -> Option<CameraKeyframe> {
+        let count = self.keyframes.len();
+        let last = count.checked_sub(1)?;
+        let position = time / self.segment_seconds.max(f32::EPSILON);
+        let (position, segments) = if self.looping {
+            (position.rem_euclid(count as f32), count)
+        } else {
+            (position.clamp(0.0, last as f32), last)
+        };
+        let segment = (position as usize).min(segments.saturating_sub(1));
+        let t = position - segment as f32;
+        // A looping path wraps around; the ends of others are repeated so
+        // the spline reaches them.
+        let keyframe = |i: isize| {
+            let i = if self.looping {
+                i.rem_euclid(count as isize)
+            } else {
+                i.clamp(0, last as isize)
+            };
+            self.keyframes[i as usize]
+        };
+        let [k0, k1, k2, k3] = [-1, 0, 1, 2].map(|offset| keyframe(segment as isize + offset));
+
+        let point = |p: [cgmath::Point3<f32>; 4]| {
+            use cgmath::EuclideanSpace;
+
+            let [p0, p1, p2, p3] = p.map(|p| p.to_vec());
+            cgmath::Point3::from_vec(catmull_rom([p0, p1, p2, p3], t))
+        };
+        Some(CameraKeyframe {
+            eye: point([k0.eye, k1.eye, k2.eye, k3.eye]),
+            target: point([k0.target, k1.target, k2.target, k3.target]),
+            fovy: catmull_rom([k0.fovy, k1.fovy, k2.fovy, k3.fovy], t),
+        })
+    }
+
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let document: toml_edit::DocumentMut = source.parse()?;
+        let mut path = Self::default();
+        if let Some(item) = document.get("segment_seconds") {
+            path.segment_seconds = number(item)
+                .filter(|&seconds| seconds > 0.0)
+                .ok_or_else(|| anyhow::anyhow!("segment_seconds should be a positive number"))?;
+        }
+        if let Some(item) = document.get("looping") {
+            path.looping = item
+                .as_bool()
+                .ok_or_else(|| anyhow::anyhow!("looping should be true or false"))?;
+        }
+        let Some(keyframes) = document.get("keyframe") else {
+            return Ok(path);
+        };
+        let Some(keyframes) = keyframes.as_array_of_tables() else {
+            anyhow::bail!("keyframe should be [[keyframe]] tables");
+        };
+        for (i, table) in keyframes.iter().enumerate() {
+            path.keyframes.push(CameraKeyframe::from_toml(
+                table,
+                &format!("keyframe {}", i),
+            )?);
+        }
+        Ok(path)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Unable to read {}: {}", path.display(), e))?;
+        Self::parse(&source)
+            .map_err(|e| anyhow::anyhow!("Unable to read {}: {:#}", path.display(), e))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_toml())?;
+        Ok(())
+    }
+
+    /// The file `parse` reads back as `self`.
+    pub fn to_toml(&self) -> String {
+        let mut out = format!("segment_seconds = {:?}\n", self.segment_seconds);
+        if self.looping {
+            out.push_str("looping = true\n");
+        }
+        for keyframe in &self.keyframes {
+            out.push_str(&format!("\n[[keyframe]]\n{}", keyframe.toml_fields()));
+        }
+        out
+    }
+}
+
+pub(crate) fn number(item: &toml_edit::Item) -> Option<f32> {
+    item.as_float()
+        .or_else(|| item.as_integer().map(|value| value as f64))
+        .map(|value| value as f32)
+}
+
+/// The uniform Catmull-Rom spline through `p1` at 0 and `p2` at 1.
+fn catmull_rom<V>(p: [V; 4], t: f32) -> V
+where
+    V: Copy
+        + std::ops::Add<Output = V>
+        + std::ops::Sub<Output = V>
+        + std::ops::Mul<f32, Output = V>,
+{
+    let [p0, p1, p2, p3] = p;
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{MetricSpace, Point3};
+
+    use super::*;
+
+    fn path(looping: bool) -> CameraPath {
+        let keyframe = |x: f32, y: f32, fovy: f32| CameraKeyframe {
+            eye: Point3::new(x, y, 5.0),
+            target: Point3::new(x, 0.0, 0.0),
+            fovy,
+        };
+        CameraPath {
+            keyframes: vec![
+                keyframe(0.0, 1.0, 45.0),
+                keyframe(4.0, 3.0, 60.0),
+                keyframe(5.0, -2.0, 30.0),
+                keyframe(-3.0, 0.0, 50.0),
+            ],
+            segment_seconds: 2.0,
+            looping,
+        }
+    }
+
+    fn assert_near(a: CameraKeyframe, b: CameraKeyframe) {
+        assert!(a.eye.distance(b.eye) < 1e-4, "{:?} != {:?}", a, b);
+        assert!(a.target.distance(b.target) < 1e-4, "{:?} != {:?}", a, b);
+        assert!((a.fovy - b.fovy).abs() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn passes_through_every_keyframe() {
+        for looping in [false, true] {
+            let path = path(looping);
+            for (i, &keyframe) in path.keyframes.iter().enumerate() {
+                assert_near(path.sample(i as f32 * 2.0).unwrap(), keyframe);
+            }
+        }
+    }
+
+    #[test]
+    fn holds_at_either_end() {
+        let path = path(false);
+        assert_eq!(path.duration(), 6.0);
+        assert_near(path.sample(-3.0).unwrap(), path.keyframes[0]);
+        assert_near(path.sample(6.0).unwrap(), path.keyframes[3]);
+        assert_near(path.sample(100.0).unwrap(), path.keyframes[3]);
+        // In between keyframes it is at neither.
+        let between = path.sample(3.0).unwrap();
+        assert!(between.eye.distance(path.keyframes[1].eye) > 0.1);
+        assert!(between.eye.distance(path.keyframes[2].eye) > 0.1);
+    }
+
+    #[test]
+    fn loops_back_to_the_start() {
+        let path = path(true);
+        assert_eq!(path.duration(), 8.0);
+        assert_near(path.sample(8.0).unwrap(), path.keyframes[0]);
+        assert_near(path.sample(-2.0).unwrap(), path.keyframes[3]);
+        for time in [0.5, 3.3, 7.0] {
+            assert_near(path.sample(time + 8.0).unwrap(), path.sample(time).unwrap());
+            assert_near(
+                path.sample(time - 16.0).unwrap(),
+                path.sample(time).unwrap(),
+            );
+        }
+        // The last segment heads back towards the first keyframe.
+        let closing = path.sample(7.0).unwrap();
+        assert!(closing.eye.x > path.keyframes[3].eye.x);
+        assert!(closing.eye.x < path.keyframes[0].eye.x);
+    }
+
+    #[test]
+    fn short_paths() {
+        let mut path = path(false);
+        path.keyframes.truncate(1);
+        assert_eq!(path.duration(), 0.0);
+        assert_near(path.sample(1.0).unwrap(), path.keyframes[0]);
+        path.keyframes.clear();
+        assert_eq!(path.sample(0.0), None);
+        path.looping = true;
+        assert_eq!(path.sample(0.0), None);
+    }
+
+    #[test]
+    fn toml_round_trips() {
+        for looping in [false, true] {
+            let path = path(looping);
+            assert_eq!(CameraPath::parse(&path.to_toml()).unwrap(), path);
+        }
+        assert!(CameraPath::parse("looping = 1\n").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! Per-frame `tracing` spans, and the wgpu validation errors raised inside
+//! them.
+//!
+//! `State` opens a span around each phase of a frame: `update`, `encode`,
+//! `submit` and `present`, each with the frame's number as its `frame`
+//! field. Each is a wgpu error scope as well, so a validation error becomes
+//! an error event inside the span of the phase it came from, and is
+//! counted, rather than panicking in the device's uncaptured error handler.
+//! A phase's time is a trace event inside its span as it closes.
+//!
+//! Everything is under the `wgpu_test::frame` target. A `tracing`
+//! subscriber sees the spans and events as such; without one they're
+//! logged, so `RUST_LOG=wgpu_test::frame=trace` shows every phase's time.
+//!
+//! `start_file` also writes them as `key=value` lines to `frames.log` in a
+//! directory (`--frame-log <dir>`), for attaching to bug reports. That's
+//! this crate's record of its own frames, not a wgpu API trace: builds
+//! with the `trace` feature record one of those with `--trace <dir>`; see
+//! `AdapterOptions::trace`.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use web_time::Instant;
+
+const TARGET: &str = "wgpu_test::frame";
+
+// Counts errors and writes lines to the file, if there is one, from spans
+// and from the futures popping their error scopes.
+#[derive(Default)]
+struct Sink {
+    errors: AtomicU32,
+    #[cfg(not(target_arch = "wasm32"))]
+    file: parking_lot::Mutex<Option<std::io::BufWriter<std::fs::File>>>,
+}
+
+impl Sink {
+    #[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+    fn write(&self, line: std::fmt::Arguments) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            use std::io::Write;
+
+            let mut file = self.file.lock();
+            if let Some(writer) = &mut *file
+                && let Err(e) = writeln!(writer, "{}", line)
+            {
+                log::error!("Closing the frame log: {}", e);
+                *file = None;
+            }
+        }
+    }
+}
+
+/// Numbers frames and opens their spans; see the module docs.
+pub struct FrameTrace {
+    device: wgpu::Device,
+    frame: u64,
+    sink: Arc<Sink>,
+}
+
+impl FrameTrace {
+    pub(crate) fn new(device: &wgpu::Device) -> Self {
+        Self {
+            device: device.clone(),
+            frame: 0,
+            sink: Arc::default(),
+        }
+    }
+
+    /// Keeps counting, and writing to any file, from `old` onto `device`,
+    /// for a recreated device.
+    pub(crate) fn carry_over(&mut self, old: FrameTrace) {
+        self.frame = old.frame;
+        self.sink = old.sink;
+    }
+
+    /// The frame being traced, counting from 1 at the first `begin_frame`.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Validation errors caught so far, including those raised on any
+    /// device before a `recreate_device`.
+    pub fn error_count(&self) -> u32 {
+        self.sink.errors.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Enters `phase`'s span, left and closed when it's dropped. Spans must
+    /// close in the reverse of the order they opened, as error scopes nest.
+    pub(crate) fn span(&self, phase: Phase) -> Span {
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let span = match phase {
+            Phase::Update => tracing::trace_span!(target: TARGET, "update", frame = self.frame),
+            Phase::Encode => tracing::trace_span!(target: TARGET, "encode", frame = self.frame),
+            Phase::Submit => tracing::trace_span!(target: TARGET, "submit", frame = self.frame),
+            Phase::Present => tracing::trace_span!(target: TARGET, "present", frame = self.frame),
+        };
+        Span {
+            phase,
+            frame: self.frame,
+            start: Instant::now(),
+            span: Some(span.entered()),
+            device: self.device.clone(),
+            sink: self.sink.clone(),
+        }
+    }
+
+    /// Starts writing every span and error to `frames.log` in `directory`,
+    /// created if need be, replacing any file written before.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_file(&self, directory: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)?;
+        let path = directory.join("frames.log");
+        let file = std::fs::File::create(&path)
+            .map_err(|e| anyhow::anyhow!("Unable to create {}: {}", path.display(), e))?;
+        *self.sink.file.lock() = Some(std::io::BufWriter::new(file));
+        log::info!("Logging frames to {}", path.display());
+        Ok(())
+    }
+
+    /// Writes out what the file has buffered.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn flush(&self) {
+        use std::io::Write;
+
+        if let Some(file) = &mut *self.sink.file.lock()
+            && let Err(e) = file.flush()
+        {
+            log::error!("Unable to write the frame log: {}", e);
+        }
+    }
+}
+
+/// The parts of a frame `FrameTrace` spans.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Phase {
+    Update,
+    Encode,
+    Submit,
+    Present,
+}
+
+impl Phase {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Update => "update",
+            Self::Encode => "encode",
+            Self::Submit => "submit",
+            Self::Present => "present",
+        }
+    }
+}
+
+/// One phase of a frame, entered from `FrameTrace::span` until dropped.
+pub(crate) struct Span {
+    phase: Phase,
+    frame: u64,
+    start: Instant,
+    // Only taken in `drop`.
+    span: Option<tracing::span::EnteredSpan>,
+    device: wgpu::Device,
+    sink: Arc<Sink>,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let ms = self.start.elapsed().as_secs_f64() * 1000.0;
+        tracing::trace!(target: TARGET, frame = self.frame, span = self.phase.name(), ms);
+        let span = self
+            .span
+            .take()
+            .map_or_else(tracing::Span::none, |span| span.exit());
+        self.sink.write(format_args!(
+            "frame={} span={} ms={:.3}",
+            self.frame,
+            self.phase.name(),
+            ms
+        ));
+
+        let scope = self.device.pop_error_scope();
+        let (frame, name, sink) = (self.frame, self.phase.name(), self.sink.clone());
+        let report = async move {
+            if let Some(error) = scope.await {
+                sink.errors.fetch_add(1, Ordering::Relaxed);
+                tracing::error!(target: TARGET, parent: &span, frame, span = name, %error);
+                sink.write(format_args!(
+                    "frame={} span={} error={:?}",
+                    frame,
+                    name,
+                    error.to_string()
+                ));
+            }
+        };
+        // Native scopes are resolved as they're popped; the browser's come
+        // back some time later, so the span stays open for the error.
+        #[cfg(not(target_arch = "wasm32"))]
+        pollster::block_on(report);
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(report);
+    }
+}
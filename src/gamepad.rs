@@ -0,0 +1,51 @@
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+
+use crate::input::GamepadState;
+
+/// The gamepads gilrs can see. Whichever one was used last steers the
+/// camera, starting with the first one connected.
+pub struct Gamepads {
+    gilrs: Gilrs,
+    active: Option<GamepadId>,
+}
+
+impl Gamepads {
+    pub fn new() -> anyhow::Result<Self> {
+        // The error can hold a dummy context, which isn't `Send`.
+        let gilrs = Gilrs::new().map_err(|e| anyhow::anyhow!("Unable to open gamepads: {}", e))?;
+        let active = gilrs.gamepads().next().map(|(id, _)| id);
+        Ok(Self { gilrs, active })
+    }
+
+    /// Takes in the events since the last call and returns where the
+    /// active gamepad's sticks and triggers are now, or the default when
+    /// none is connected.
+    pub fn poll(&mut self) -> GamepadState {
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::Disconnected => {
+                    if self.active == Some(event.id) {
+                        self.active = self.gilrs.gamepads().next().map(|(id, _)| id);
+                    }
+                }
+                _ => self.active = Some(event.id),
+            }
+        }
+        let Some(gamepad) = self.active.map(|id| self.gilrs.gamepad(id)) else {
+            return GamepadState::default();
+        };
+        let trigger = |button| gamepad.button_data(button).map_or(0.0, |data| data.value());
+        GamepadState {
+            left_stick: [
+                gamepad.value(Axis::LeftStickX),
+                gamepad.value(Axis::LeftStickY),
+            ],
+            right_stick: [
+                gamepad.value(Axis::RightStickX),
+                gamepad.value(Axis::RightStickY),
+            ],
+            left_trigger: trigger(Button::LeftTrigger2),
+            right_trigger: trigger(Button::RightTrigger2),
+        }
+    }
+}
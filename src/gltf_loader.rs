@@ -0,0 +1,643 @@
+use alloc::format;
+use base64::Engine;
+use cgmath::{InnerSpace, Matrix, SquareMatrix};
+use std::collections::HashSet;
+use wgpu::util::DeviceExt;
+
+use crate::animation::{self, SkinVertex};
+use crate::material::MaterialKindId;
+use crate::mesh::optimize;
+use crate::{lod, model, resources, texture};
+
+const DATA_URI_PREFIX: &str = "data:";
+
+// glTF URIs are relative to the file that references them.
+pub(crate) fn resolve_uri(base: &str, uri: &str) -> String {
+    match base.rfind('/') {
+        Some(i) => format!("{}/{}", &base[..i], uri),
+        None => uri.to_string(),
+    }
+}
+
+async fn load_uri(base: &str, uri: &str) -> anyhow::Result<Vec<u8>> {
+    if let Some(rest) = uri.strip_prefix(DATA_URI_PREFIX) {
+        let (_, data) = rest
+            .split_once(";base64,")
+            .ok_or_else(|| anyhow::anyhow!("unsupported data uri in {}", base))?;
+        return Ok(base64::engine::general_purpose::STANDARD.decode(data)?);
+    }
+    resources::load_binary(&resolve_uri(base, uri)).await
+}
+
+pub async fn load_gltf(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    options: model::ImportOptions,
+) -> anyhow::Result<model::Model> {
+    let bytes = resources::load_binary(file_name).await?;
+    let gltf::Gltf { document, mut blob } = gltf::Gltf::from_slice(&bytes)?;
+
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => blob
+                .take()
+                .ok_or_else(|| anyhow::anyhow!("{} has no binary chunk", file_name))?,
+            gltf::buffer::Source::Uri(uri) => load_uri(file_name, uri).await?,
+        };
+        buffers.push(data);
+    }
+
+    let mut images = Vec::new();
+    for image in document.images() {
+        let data = match image.source() {
+            gltf::image::Source::View { view, .. } => {
+                let buffer = &buffers[view.buffer().index()];
+                buffer[view.offset()..view.offset() + view.length()].to_vec()
+            }
+            gltf::image::Source::Uri { uri, .. } => load_uri(file_name, uri).await?,
+        };
+        images.push(image::load_from_memory(&data)?);
+    }
+
+    let mut materials = Vec::new();
+    for gltf_material in document.materials() {
+        let pbr = gltf_material.pbr_metallic_roughness();
+        let name = gltf_material.name().unwrap_or(file_name);
+        let diffuse_texture = match pbr.base_color_texture() {
+            Some(info) => texture::Texture::from_image(
+                device,
+                queue,
+                &images[info.texture().source().index()],
+                Some(name),
+            )?,
+            None => texture::Texture::from_color(device, queue, [255; 4], name)?,
+        };
+        let mut textures = model::MaterialTextures::new(device, queue, diffuse_texture)?;
+        let image = |texture: gltf::Texture| &images[texture.source().index()];
+        if let Some(info) = gltf_material.normal_texture() {
+            textures.normal = texture::Texture::normal_from_image(
+                device,
+                queue,
+                image(info.texture()),
+                Some(name),
+            )?;
+        }
+        if let Some(info) = pbr.metallic_roughness_texture() {
+            textures.metallic_roughness = texture::Texture::linear_from_image(
+                device,
+                queue,
+                image(info.texture()),
+                Some(name),
+            )?;
+        }
+        if let Some(info) = gltf_material.occlusion_texture() {
+            textures.occlusion = texture::Texture::linear_from_image(
+                device,
+                queue,
+                image(info.texture()),
+                Some(name),
+            )?;
+        }
+        if let Some(info) = gltf_material.emissive_texture() {
+            textures.emissive =
+                texture::Texture::from_image(device, queue, image(info.texture()), Some(name))?;
+        }
+
+        let factors = model::MaterialFactors {
+            base_color: pbr.base_color_factor(),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            emissive: gltf_material.emissive_factor(),
+            emissive_strength: gltf_material.emissive_strength().unwrap_or(1.0),
+            occlusion_strength: gltf_material
+                .occlusion_texture()
+                .map_or(1.0, |info| info.strength()),
+            normal_scale: gltf_material
+                .normal_texture()
+                .map_or(1.0, |info| info.scale()),
+        };
+        // Masked materials have no alpha test yet and draw opaque.
+        let kind = match gltf_material.alpha_mode() {
+            gltf::material::AlphaMode::Blend => MaterialKindId::TRANSPARENT,
+            _ => MaterialKindId::STANDARD,
+        };
+        let material =
+            model::Material::new(device, name, textures, factors, layout).with_kind(kind);
+        materials.push(material);
+    }
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| anyhow::anyhow!("{} contains no scenes", file_name))?;
+
+    // Every node becomes a joint, parents first, so animations can move
+    // any of them.
+    let mut joints = Vec::new();
+    let mut node_joints = vec![None; document.nodes().len()];
+    let mut nodes = Vec::new();
+    let mut stack = scene
+        .nodes()
+        .map(|node| (node, None, cgmath::Matrix4::identity()))
+        .collect::<Vec<_>>();
+    while let Some((node, parent, parent_world)) = stack.pop() {
+        let world = parent_world * cgmath::Matrix4::from(node.transform().matrix());
+        let joint = joints.len();
+        let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+        joints.push(animation::Joint {
+            name: node
+                .name()
+                .map_or_else(|| format!("node {}", node.index()), str::to_string),
+            parent,
+            rest: animation::Transform {
+                translation: translation.into(),
+                rotation: cgmath::Quaternion::new(w, x, y, z),
+                scale: scale.into(),
+            },
+        });
+        node_joints[node.index()] = Some(joint);
+        for child in node.children() {
+            stack.push((child, Some(joint), world));
+        }
+        nodes.push((node, joint, world));
+    }
+
+    let animated = document.skins().len() > 0 || document.animations().len() > 0;
+    let mut skeleton = animation::Skeleton {
+        joints,
+        palette: Vec::new(),
+    };
+    let rest_world = skeleton.world_transforms(&skeleton.rest_pose());
+    let mut skin_bases = Vec::new();
+    for skin in document.skins() {
+        skin_bases.push(skeleton.palette.len() as u32);
+        let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+        let inverse_binds = reader
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.collect::<Vec<_>>())
+            .unwrap_or_default();
+        for (i, joint) in skin.joints().enumerate() {
+            let joint = node_joints[joint.index()]
+                .ok_or_else(|| anyhow::anyhow!("{}: skin joint outside the scene", file_name))?;
+            skeleton.palette.push(animation::PaletteEntry {
+                joint,
+                inverse_bind: inverse_binds
+                    .get(i)
+                    .map_or_else(cgmath::Matrix4::identity, |&m| m.into()),
+            });
+        }
+    }
+
+    // Nodes standing in for others at coarser levels of detail, drawn
+    // only through them.
+    let lod_nodes = document
+        .nodes()
+        .flat_map(|node| lod_ids(&node))
+        .collect::<HashSet<_>>();
+    let mut meshes = Vec::new();
+    for (node, joint, world) in nodes {
+        let Some(mesh) = node.mesh() else {
+            continue;
+        };
+        if lod_nodes.contains(&node.index()) {
+            continue;
+        }
+        // A level's node replaces this one, so it goes where this one is.
+        let parent_world = cgmath::Matrix4::from(node.transform().matrix())
+            .invert()
+            .map_or(world, |inverse_local| world * inverse_local);
+        let levels = lod_ids(&node)
+            .into_iter()
+            .filter_map(|id| document.nodes().nth(id))
+            .map(|level| {
+                let world = parent_world * cgmath::Matrix4::from(level.transform().matrix());
+                (level.mesh(), world)
+            })
+            .collect::<Vec<_>>();
+        // Animated vertices stay in their node's space and reach the model's
+        // through the palette instead.
+        let (skinning, world) = if !animated {
+            (None, world)
+        } else if let Some(skin) = node.skin() {
+            let base = skin_bases[skin.index()];
+            (Some(Skinning::Skin { base }), cgmath::Matrix4::identity())
+        } else {
+            skeleton.palette.push(animation::PaletteEntry {
+                joint,
+                inverse_bind: cgmath::Matrix4::identity(),
+            });
+            let entry = skeleton.palette.len() as u32 - 1;
+            (Some(Skinning::Rigid { entry }), cgmath::Matrix4::identity())
+        };
+        let rest_palette = skeleton
+            .palette
+            .iter()
+            .map(|entry| rest_world[entry.joint] * entry.inverse_bind)
+            .collect::<Vec<_>>();
+        for (i, primitive) in mesh.primitives().enumerate() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!("{}: skipping non-triangle primitive", file_name);
+                continue;
+            }
+            // Matched to each level's primitives by index; a level without
+            // a mesh or the primitive leaves it out.
+            let coarser = levels
+                .iter()
+                .map(|(mesh, world)| {
+                    let primitive = mesh
+                        .as_ref()
+                        .and_then(|mesh| mesh.primitives().nth(i))
+                        .filter(|primitive| primitive.mode() == gltf::mesh::Mode::Triangles);
+                    (primitive, *world)
+                })
+                .collect::<Vec<_>>();
+            let material = match primitive.material().index() {
+                Some(index) => index,
+                None => default_material(device, queue, layout, &mut materials)?,
+            };
+            meshes.push(load_primitive(
+                device,
+                file_name,
+                &mesh,
+                &primitive,
+                &coarser,
+                &buffers,
+                world,
+                material,
+                options,
+                skinning
+                    .as_ref()
+                    .map(|skinning| (skinning, rest_palette.as_slice())),
+            )?);
+        }
+    }
+
+    let animations = document
+        .animations()
+        .map(|animation| load_animation(file_name, &animation, &buffers, &node_joints))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(model::Model {
+        meshes,
+        materials,
+        skeleton: animated.then_some(skeleton),
+        animations,
+        vertex_encoding: options.vertex_encoding,
+    })
+}
+
+/// The nodes `node` names as its coarser levels of detail, coarsest last,
+/// with the `MSFT_lod` extension.
+fn lod_ids(node: &gltf::Node) -> Vec<usize> {
+    node.extension_value("MSFT_lod")
+        .and_then(|lod| lod.get("ids"))
+        .and_then(|ids| ids.as_array())
+        .map(|ids| {
+            ids.iter()
+                .filter_map(|id| id.as_u64())
+                .map(|id| id as usize)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// How the vertices of a primitive in an animated model follow the joint
+/// palette.
+enum Skinning {
+    /// The primitive's joints index the skin whose entries start at `base`.
+    Skin { base: u32 },
+    /// Every vertex follows the one entry of the primitive's node.
+    Rigid { entry: u32 },
+}
+
+fn load_animation(
+    file_name: &str,
+    animation: &gltf::Animation,
+    buffers: &[Vec<u8>],
+    node_joints: &[Option<usize>],
+) -> anyhow::Result<animation::AnimationClip> {
+    use gltf::animation::util::ReadOutputs;
+
+    let name = animation.name().map_or_else(
+        || format!("animation {}", animation.index()),
+        str::to_string,
+    );
+    let mut channels = Vec::new();
+    for channel in animation.channels() {
+        let Some(joint) = node_joints[channel.target().node().index()] else {
+            continue;
+        };
+        let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+        let times = reader
+            .read_inputs()
+            .ok_or_else(|| anyhow::anyhow!("{}: {} has a channel without times", file_name, name))?
+            .collect::<Vec<_>>();
+        let (interpolation, cubic) = match channel.sampler().interpolation() {
+            gltf::animation::Interpolation::Step => (animation::Interpolation::Step, false),
+            gltf::animation::Interpolation::Linear => (animation::Interpolation::Linear, false),
+            // Keyed as in-tangent, value, out-tangent; the tangents are
+            // dropped and the values interpolated linearly.
+            gltf::animation::Interpolation::CubicSpline => (animation::Interpolation::Linear, true),
+        };
+        let keyframes = match reader.read_outputs() {
+            Some(ReadOutputs::Translations(t)) => {
+                animation::Keyframes::Translation(keyed_values(t.map(cgmath::Vector3::from), cubic))
+            }
+            Some(ReadOutputs::Rotations(r)) => animation::Keyframes::Rotation(keyed_values(
+                r.into_f32()
+                    .map(|[x, y, z, w]| cgmath::Quaternion::new(w, x, y, z)),
+                cubic,
+            )),
+            Some(ReadOutputs::Scales(s)) => {
+                animation::Keyframes::Scale(keyed_values(s.map(cgmath::Vector3::from), cubic))
+            }
+            Some(ReadOutputs::MorphTargetWeights(_)) => {
+                log::warn!(
+                    "{}: {} animates morph targets, which are not supported",
+                    file_name,
+                    name
+                );
+                continue;
+            }
+            None => anyhow::bail!("{}: {} has a channel without values", file_name, name),
+        };
+        let keyframe_count = match &keyframes {
+            animation::Keyframes::Translation(v) | animation::Keyframes::Scale(v) => v.len(),
+            animation::Keyframes::Rotation(v) => v.len(),
+        };
+        if keyframe_count != times.len() {
+            anyhow::bail!(
+                "{}: {} has a channel with mismatched keyframes",
+                file_name,
+                name
+            );
+        }
+        channels.push(animation::Channel {
+            joint,
+            interpolation,
+            times,
+            keyframes,
+        });
+    }
+    let duration = channels
+        .iter()
+        .filter_map(|channel| channel.times.last().copied())
+        .fold(0.0, f32::max);
+    Ok(animation::AnimationClip {
+        name,
+        duration,
+        channels,
+    })
+}
+
+fn default_material(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    materials: &mut Vec<model::Material>,
+) -> anyhow::Result<usize> {
+    const NAME: &str = "gltf default material";
+    if let Some(index) = materials.iter().position(|m| m.name == NAME) {
+        return Ok(index);
+    }
+    let diffuse_texture = texture::Texture::from_color(device, queue, [255; 4], NAME)?;
+    let textures = model::MaterialTextures::new(device, queue, diffuse_texture)?;
+    // glTF's default material is fully metallic and rough; a dielectric reads
+    // better for untextured meshes.
+    let factors = model::MaterialFactors {
+        metallic: 0.0,
+        ..Default::default()
+    };
+    materials.push(model::Material::new(
+        device, NAME, textures, factors, layout,
+    ));
+    Ok(materials.len() - 1)
+}
+
+/// The values of a sampler's keyframes; cubic spline samplers interleave
+/// them with tangents.
+fn keyed_values<T>(values: impl Iterator<Item = T>, cubic: bool) -> Vec<T> {
+    if cubic {
+        values.skip(1).step_by(3).collect()
+    } else {
+        values.collect()
+    }
+}
+
+/// `coarser` holds the primitive of each of its authored levels of
+/// detail, None where a level leaves it out, with the world transform of
+/// the level's node. `skinning` comes with the palette at rest, which
+/// places the bounds.
+#[allow(clippy::too_many_arguments)]
+fn load_primitive(
+    device: &wgpu::Device,
+    file_name: &str,
+    mesh: &gltf::Mesh,
+    primitive: &gltf::Primitive,
+    coarser: &[(Option<gltf::Primitive>, cgmath::Matrix4<f32>)],
+    buffers: &[Vec<u8>],
+    world: cgmath::Matrix4<f32>,
+    material: usize,
+    options: model::ImportOptions,
+    skinning: Option<(&Skinning, &[cgmath::Matrix4<f32>])>,
+) -> anyhow::Result<model::Mesh> {
+    let (mut vertices, indices, mut skin) = read_primitive(
+        file_name,
+        primitive,
+        buffers,
+        world,
+        options,
+        skinning.map(|(skinning, _)| skinning),
+    )?;
+    let bounds = match skinning {
+        Some((_, rest_palette)) => {
+            model::Bounds::from_points(vertices.iter().zip(&skin).map(|(vertex, skin)| {
+                let skinning = (0..4)
+                    .map(|i| rest_palette[skin.joints[i] as usize] * skin.weights[i])
+                    .fold(cgmath::Matrix4::from_scale(0.0), |sum, m| sum + m);
+                (skinning * cgmath::Vector3::from(vertex.position).extend(1.0))
+                    .truncate()
+                    .into()
+            }))
+        }
+        None => model::Bounds::from_points(vertices.iter().map(|v| v.position)),
+    };
+
+    let geometry = skinning
+        .is_none()
+        .then(|| model::MeshGeometry::new(&vertices, &indices));
+
+    let (all_indices, lods) = if !options.lods {
+        (indices.clone(), Vec::new())
+    } else if coarser.is_empty() {
+        lod::generate(&vertices, &indices)
+    } else {
+        // Each level's vertices go after those before it, in the same
+        // buffers, so that its indices draw with the mesh's.
+        let mut all_indices = indices.clone();
+        let mut lods = Vec::new();
+        for (primitive, world) in coarser {
+            let start = all_indices.len() as u32;
+            if let Some(primitive) = primitive {
+                let (level_vertices, level_indices, level_skin) = read_primitive(
+                    file_name,
+                    primitive,
+                    buffers,
+                    *world,
+                    options,
+                    skinning.map(|(skinning, _)| skinning),
+                )?;
+                let base = vertices.len() as u32;
+                all_indices.extend(level_indices.iter().map(|index| base + index));
+                vertices.extend(level_vertices);
+                skin.extend(level_skin);
+            }
+            lods.push(start..all_indices.len() as u32);
+        }
+        (all_indices, lods)
+    };
+
+    let name = mesh.name().unwrap_or(file_name);
+    let vertex_buffer = options.vertex_encoding.create_vertex_buffer(
+        device,
+        &format!("{:?} Vertex Buffer", name),
+        &vertices,
+    );
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Index Buffer", name)),
+        contents: bytemuck::cast_slice(&all_indices),
+        usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
+    });
+
+    Ok(model::Mesh {
+        name: name.to_string(),
+        vertex_buffer,
+        index_buffer,
+        num_elements: indices.len() as u32,
+        material,
+        bounds,
+        skin_buffer: skinning.is_some().then(|| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Skin Buffer", name)),
+                contents: bytemuck::cast_slice(&skin),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+            })
+        }),
+        geometry,
+        lods,
+    })
+}
+
+/// The vertices, indices and, if `skinning`, skin vertices of `primitive`,
+/// moved into the model's space by `world`.
+fn read_primitive(
+    file_name: &str,
+    primitive: &gltf::Primitive,
+    buffers: &[Vec<u8>],
+    world: cgmath::Matrix4<f32>,
+    options: model::ImportOptions,
+    skinning: Option<&Skinning>,
+) -> anyhow::Result<(Vec<model::ModelVertex>, Vec<u32>, Vec<SkinVertex>)> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let positions = reader
+        .read_positions()
+        .ok_or_else(|| anyhow::anyhow!("{}: primitive without positions", file_name))?
+        .collect::<Vec<_>>();
+    let normals = reader
+        .read_normals()
+        .map(|n| n.collect::<Vec<_>>())
+        .unwrap_or_default();
+    let tangents = reader
+        .read_tangents()
+        .map(|t| t.collect::<Vec<_>>())
+        .unwrap_or_default();
+    let tex_coords = reader
+        .read_tex_coords(0)
+        .map(|t| t.into_f32().collect::<Vec<_>>())
+        .unwrap_or_default();
+    let mut indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+        None => (0..positions.len() as u32).collect(),
+    };
+
+    // Node transforms are baked into the vertices so the mesh renders with the
+    // same instance transform as OBJ meshes.
+    let normal_matrix =
+        cgmath::Matrix3::from_cols(world.x.truncate(), world.y.truncate(), world.z.truncate())
+            .invert()
+            .map(|m| m.transpose())
+            .unwrap_or_else(cgmath::Matrix3::identity);
+
+    let tangent_matrix =
+        cgmath::Matrix3::from_cols(world.x.truncate(), world.y.truncate(), world.z.truncate());
+
+    let mut vertices = positions
+        .iter()
+        .enumerate()
+        .map(|(i, position)| {
+            let position = world * cgmath::Vector3::from(*position).extend(1.0);
+            let normal = normals
+                .get(i)
+                .map(|n| (normal_matrix * cgmath::Vector3::from(*n)).normalize())
+                .unwrap_or(cgmath::Vector3::new(0.0, 0.0, 0.0));
+            // glTF tangents carry the bitangent sign in w.
+            let (tangent, bitangent) = match tangents.get(i) {
+                Some(&[x, y, z, w]) if normal.magnitude2() > 0.0 => {
+                    let tangent = (tangent_matrix * cgmath::Vector3::new(x, y, z)).normalize();
+                    (tangent.into(), (normal.cross(tangent) * w).into())
+                }
+                _ => ([0.0; 3], [0.0; 3]),
+            };
+            model::ModelVertex {
+                position: position.truncate().into(),
+                tex_coords: tex_coords.get(i).copied().unwrap_or([0.0; 2]),
+                normal: normal.into(),
+                tangent,
+                bitangent,
+            }
+        })
+        .collect::<Vec<_>>();
+    if tangents.is_empty() {
+        model::compute_tangents(&mut vertices, &indices);
+    }
+
+    let mut skin = match skinning {
+        Some(Skinning::Skin { base }) => {
+            let joints = reader.read_joints(0).ok_or_else(|| {
+                anyhow::anyhow!("{}: skinned primitive without joints", file_name)
+            })?;
+            let weights = reader.read_weights(0).ok_or_else(|| {
+                anyhow::anyhow!("{}: skinned primitive without weights", file_name)
+            })?;
+            joints
+                .into_u16()
+                .zip(weights.into_f32())
+                .map(|(joints, weights)| {
+                    let total = weights.iter().sum::<f32>();
+                    if total <= 0.0 {
+                        return SkinVertex::rigid(base + joints[0] as u32);
+                    }
+                    SkinVertex {
+                        joints: joints.map(|joint| base + joint as u32),
+                        weights: weights.map(|weight| weight / total),
+                    }
+                })
+                .collect::<Vec<_>>()
+        }
+        Some(&Skinning::Rigid { entry }) => vec![SkinVertex::rigid(entry); vertices.len()],
+        None => Vec::new(),
+    };
+    if skinning.is_some() && skin.len() != vertices.len() {
+        anyhow::bail!("{}: primitive with mismatched joints", file_name);
+    }
+    if options.optimize {
+        let remap = optimize::optimize(&mut vertices, &mut indices);
+        if skinning.is_some() {
+            skin = optimize::remap_vertices(&skin, &remap);
+        }
+    }
+    Ok((vertices, indices, skin))
+}
@@ -0,0 +1,601 @@
+use cgmath::{EuclideanSpace, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use crate::animation::SkinVertex;
+use crate::camera::Camera;
+use crate::culling::Frustum;
+use crate::hiz::DepthPyramid;
+use crate::instance::{InstanceBuffer, InstanceRaw};
+use crate::model;
+use crate::preprocess;
+use crate::texture;
+
+const WORKGROUP_SIZE: u32 = 64;
+const DRAW_ARGS_SIZE: wgpu::BufferAddress =
+    size_of::<wgpu::util::DrawIndexedIndirectArgs>() as wgpu::BufferAddress;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    sphere: [f32; 4],
+    instance_count: u32,
+    mesh_count: u32,
+    _padding: [u32; 2],
+    occlusion_view_proj: [[f32; 4]; 4],
+    // Zero when the pyramid has nothing to test against.
+    occlusion: u32,
+    _occlusion_padding: [u32; 3],
+}
+
+/// Compute pass that culls instances against the camera frustum on the GPU.
+///
+/// Each instance's bounding sphere, taken from the model's bounds, is tested
+/// against the frustum planes; survivors are packed into a separate instance
+/// buffer and counted straight into one `draw_indexed_indirect` per mesh,
+/// so the CPU never reads the result back. Shadow passes still draw every
+/// instance, since casters outside the view can shade what is inside it.
+///
+/// With occlusion culling on, instances are also tested against a
+/// `DepthPyramid` of the last frame's depth, skipping those behind what
+/// was drawn then. Being a frame late, something coming out from behind a
+/// wall as the camera moves can show up a frame after it should.
+///
+/// Where multi-draw indirect runs natively, the model's meshes are also
+/// copied into one set of buffers, so each material's meshes draw in one
+/// `multi_draw_indexed_indirect`; see `is_batched`.
+pub struct GpuCulling {
+    enabled: bool,
+    occlusion: bool,
+    // Whether the device draws a multi-draw as one call, rather than
+    // looping over the draws itself.
+    multi_draw: bool,
+    merged: Option<MergedMeshes>,
+    // The meshes in the order of their draws, each material's together and
+    // those of each material kind next to each other.
+    order: Vec<usize>,
+    // Where each mesh's draw is in that order.
+    slots: Vec<u32>,
+    pyramid: DepthPyramid,
+    // Nearest, to read the pyramid texel by texel.
+    pyramid_sampler: wgpu::Sampler,
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    uniform_buffer: wgpu::Buffer,
+    culled_buffer: wgpu::Buffer,
+    draws_buffer: wgpu::Buffer,
+    // Draws with zero instances, copied over `draws_buffer` every dispatch.
+    draws_template: wgpu::Buffer,
+    // What the bind group was built from, to tell when it must be rebuilt.
+    source: Option<(wgpu::Buffer, usize, wgpu::Texture)>,
+    bind_group: Option<wgpu::BindGroup>,
+    instance_count: u32,
+    mesh_count: u32,
+    cull_pipeline: wgpu::ComputePipeline,
+    draws_pipeline: wgpu::ComputePipeline,
+}
+
+impl GpuCulling {
+    pub fn new(device: &wgpu::Device, enabled: bool) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuCulling::uniform_buffer"),
+            size: size_of::<CullUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GpuCulling::layout"),
+            entries: &[
+                entry(0, wgpu::BufferBindingType::Uniform),
+                entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(2, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(3, wgpu::BufferBindingType::Storage { read_only: false }),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GpuCulling::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let [cull_pipeline, draws_pipeline] = Self::create_pipelines(device, &pipeline_layout);
+
+        let (draws_buffer, draws_template) = Self::create_draws_buffers(device, 1);
+        Self {
+            enabled,
+            occlusion: false,
+            multi_draw: device
+                .features()
+                .contains(wgpu::Features::MULTI_DRAW_INDIRECT_COUNT),
+            merged: None,
+            order: Vec::new(),
+            slots: Vec::new(),
+            pyramid: DepthPyramid::new(device),
+            pyramid_sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("GpuCulling::pyramid_sampler"),
+                ..Default::default()
+            }),
+            layout,
+            pipeline_layout,
+            uniform_buffer,
+            culled_buffer: Self::create_culled_buffer(device, 1),
+            draws_buffer,
+            draws_template,
+            source: None,
+            bind_group: None,
+            instance_count: 0,
+            mesh_count: 0,
+            cull_pipeline,
+            draws_pipeline,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+    ) -> [wgpu::ComputePipeline; 2] {
+        let shader = preprocess::builtin_module(
+            device,
+            "GpuCulling::shader",
+            "gpu_culling.wgsl",
+            &preprocess::ShaderDefs::new(),
+        );
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        [
+            pipeline("GpuCulling::cull_pipeline", "cs_cull"),
+            pipeline("GpuCulling::draws_pipeline", "cs_write_draws"),
+        ]
+    }
+
+    /// Compiles the pipelines again from the current shader sources,
+    /// along with the depth pyramid's.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        [self.cull_pipeline, self.draws_pipeline] =
+            Self::create_pipelines(device, &self.pipeline_layout);
+        self.pyramid.rebuild_pipelines(device);
+    }
+
+    fn create_culled_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuCulling::culled_buffer"),
+            size: (capacity * size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_draws_buffers(device: &wgpu::Device, meshes: usize) -> (wgpu::Buffer, wgpu::Buffer) {
+        let size = meshes.max(1) as wgpu::BufferAddress * DRAW_ARGS_SIZE;
+        let draws = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuCulling::draws_buffer"),
+            size,
+            // COPY_SRC so the counts can be read back when debugging.
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let template = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuCulling::draws_template"),
+            size,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        (draws, template)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn occlusion_enabled(&self) -> bool {
+        self.occlusion
+    }
+
+    pub fn set_occlusion_enabled(&mut self, enabled: bool) {
+        self.occlusion = enabled;
+        if !enabled {
+            self.pyramid.clear();
+        }
+    }
+
+    /// Instances that survived the last dispatch, packed from the start.
+    pub fn culled_buffer(&self) -> &wgpu::Buffer {
+        &self.culled_buffer
+    }
+
+    /// One `DrawIndexedIndirectArgs` per mesh of the model last passed to
+    /// `update`, in `draw_order`.
+    pub fn draws_buffer(&self) -> &wgpu::Buffer {
+        &self.draws_buffer
+    }
+
+    /// Offset of the `index`th mesh's draw in `draws_buffer`.
+    pub fn draw_offset(&self, index: usize) -> wgpu::BufferAddress {
+        let slot = self.slots.get(index).copied().unwrap_or(index as u32);
+        slot as wgpu::BufferAddress * DRAW_ARGS_SIZE
+    }
+
+    /// The meshes in the order of their draws in `draws_buffer`, those of
+    /// each material next to each other, and each material kind's.
+    pub fn draw_order(&self) -> &[usize] {
+        &self.order
+    }
+
+    /// Whether the draws are into merged buffers, bound with
+    /// `set_merged_buffers` in place of the meshes' own, so that runs of
+    /// them can be drawn with one `multi_draw_indexed_indirect`.
+    pub fn is_batched(&self) -> bool {
+        self.is_ready() && self.merged.is_some()
+    }
+
+    /// Binds the merged vertex buffers at slots 0 and 2, the vertices'
+    /// material IDs after them for `MaterialArrays::vertex_buffers`, and
+    /// the index buffer, when batched.
+    pub fn set_merged_buffers<'a>(&'a self, pass: &mut impl wgpu::util::RenderEncoder<'a>) {
+        let Some(merged) = &self.merged else {
+            return;
+        };
+        pass.set_vertex_buffer(0, merged.vertex_buffer.slice(..));
+        let mut slot = 2;
+        if let Some(skin_buffer) = &merged.skin_buffer {
+            pass.set_vertex_buffer(slot, skin_buffer.slice(..));
+            slot += 1;
+        }
+        pass.set_vertex_buffer(slot, merged.material_buffer.slice(..));
+        pass.set_index_buffer(merged.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+    }
+
+    /// Whether the buffers hold results to draw from: enabled, and updated
+    /// at least once.
+    pub fn is_ready(&self) -> bool {
+        self.enabled && self.bind_group.is_some()
+    }
+
+    /// Resizes the buffers for `instances` and `model` and uploads the
+    /// frustum; call after `instances` has been uploaded.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        model: &model::Model,
+        instances: &InstanceBuffer,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let capacity = instances.capacity();
+        let source_changed = self
+            .source
+            .as_ref()
+            .is_none_or(|(buffer, culled, _)| buffer != instances.buffer() || *culled != capacity);
+        if source_changed {
+            self.culled_buffer = Self::create_culled_buffer(device, capacity);
+            self.bind_group = None;
+        }
+        if self
+            .source
+            .as_ref()
+            .is_none_or(|(_, _, pyramid)| pyramid != self.pyramid.texture())
+        {
+            self.bind_group = None;
+        }
+        self.source = Some((
+            instances.buffer().clone(),
+            capacity,
+            self.pyramid.texture().clone(),
+        ));
+        if model.meshes.len() != self.mesh_count as usize {
+            (self.draws_buffer, self.draws_template) =
+                Self::create_draws_buffers(device, model.meshes.len());
+            self.mesh_count = model.meshes.len() as u32;
+            self.bind_group = None;
+        }
+        if self.bind_group.is_none() {
+            self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("GpuCulling::bind_group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: self.uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: instances.buffer().as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.culled_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: self.draws_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(self.pyramid.view()),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::Sampler(&self.pyramid_sampler),
+                    },
+                ],
+            }));
+        }
+
+        if self.multi_draw
+            && self
+                .merged
+                .as_ref()
+                .is_none_or(|merged| !merged.is_of(model))
+        {
+            self.merged = MergedMeshes::new(device, queue, model);
+        }
+        self.order = (0..model.meshes.len()).collect();
+        self.order.sort_by_key(|&mesh| {
+            let material = model.meshes[mesh].material;
+            (model.materials[material].kind, material)
+        });
+        self.slots = vec![0; model.meshes.len()];
+        for (slot, &mesh) in self.order.iter().enumerate() {
+            self.slots[mesh] = slot as u32;
+        }
+
+        let draws = self
+            .order
+            .iter()
+            .flat_map(|&index| {
+                let mesh = &model.meshes[index];
+                let (first_index, base_vertex) = self
+                    .merged
+                    .as_ref()
+                    .map_or((0, 0), |merged| merged.offsets[index]);
+                wgpu::util::DrawIndexedIndirectArgs {
+                    index_count: mesh.num_elements,
+                    instance_count: 0,
+                    first_index,
+                    base_vertex,
+                    first_instance: 0,
+                }
+                .as_bytes()
+                .to_vec()
+            })
+            .collect::<Vec<_>>();
+        if !draws.is_empty() {
+            queue.write_buffer(&self.draws_template, 0, &draws);
+        }
+
+        let bounds = model.bounds();
+        let center = bounds.center().to_vec();
+        let occlusion_view_proj = self.pyramid.view_proj();
+        let occlusion = self.occlusion && occlusion_view_proj.is_some();
+        let occlusion_view_proj = occlusion_view_proj.unwrap_or(cgmath::Matrix4::identity());
+        self.instance_count = instances.len() as u32;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[CullUniform {
+                planes: Frustum::from_view_proj(camera)
+                    .planes
+                    .map(|plane| plane.normal.extend(plane.d).into()),
+                sphere: center.extend(bounds.radius()).into(),
+                instance_count: self.instance_count,
+                mesh_count: self.mesh_count,
+                _padding: [0; 2],
+                occlusion_view_proj: occlusion_view_proj.into(),
+                occlusion: occlusion as u32,
+                _occlusion_padding: [0; 3],
+            }]),
+        );
+    }
+
+    pub fn dispatch(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(bind_group) = self.bind_group.as_ref().filter(|_| self.enabled) else {
+            return;
+        };
+        encoder.copy_buffer_to_buffer(&self.draws_template, 0, &self.draws_buffer, 0, None);
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("GpuCulling::pass"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.set_pipeline(&self.cull_pipeline);
+        pass.dispatch_workgroups(self.instance_count.div_ceil(WORKGROUP_SIZE).max(1), 1, 1);
+        pass.set_pipeline(&self.draws_pipeline);
+        pass.dispatch_workgroups(self.mesh_count.div_ceil(WORKGROUP_SIZE).max(1), 1, 1);
+    }
+
+    /// Builds the depth pyramid the next dispatch tests against from
+    /// `depth`, as drawn from `camera`, when occlusion culling is on.
+    pub fn build_depth_pyramid(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        depth: &texture::Texture,
+        camera: &Camera,
+    ) {
+        if self.enabled && self.occlusion {
+            self.pyramid.build(device, encoder, depth, camera);
+        } else {
+            self.pyramid.clear();
+        }
+    }
+}
+
+/// The meshes of a model copied one after another into shared buffers,
+/// next to their own, which the other passes still draw from.
+struct MergedMeshes {
+    // The meshes' own vertex buffers and materials, to tell when the model
+    // is replaced or remapped.
+    sources: Vec<(wgpu::Buffer, usize)>,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    skin_buffer: Option<wgpu::Buffer>,
+    // Each vertex's mesh's material.
+    material_buffer: wgpu::Buffer,
+    // Each mesh's first index and base vertex in them.
+    offsets: Vec<(u32, i32)>,
+}
+
+impl MergedMeshes {
+    /// Copies `model`'s meshes on the GPU; None for an empty model, or one
+    /// where only some meshes are skinned.
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue, model: &model::Model) -> Option<Self> {
+        let skinned = model.meshes.first()?.skin_buffer.is_some();
+        if model
+            .meshes
+            .iter()
+            .any(|mesh| mesh.skin_buffer.is_some() != skinned)
+        {
+            return None;
+        }
+        let vertex_size = model.vertex_encoding.vertex_size();
+        let skin_size = size_of::<SkinVertex>() as wgpu::BufferAddress;
+        let index_size = size_of::<u32>() as wgpu::BufferAddress;
+        let vertices: wgpu::BufferAddress = model
+            .meshes
+            .iter()
+            .map(|mesh| mesh.vertex_buffer.size() / vertex_size)
+            .sum();
+        let indices: wgpu::BufferAddress = model
+            .meshes
+            .iter()
+            .map(|mesh| mesh.index_buffer.size() / index_size)
+            .sum();
+        let create = |label, size: wgpu::BufferAddress, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size.max(1).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT),
+                usage: usage | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let vertex_buffer = create(
+            "MergedMeshes::vertex_buffer",
+            vertices * vertex_size,
+            wgpu::BufferUsages::VERTEX,
+        );
+        let index_buffer = create(
+            "MergedMeshes::index_buffer",
+            indices * index_size,
+            wgpu::BufferUsages::INDEX,
+        );
+        let skin_buffer = skinned.then(|| {
+            create(
+                "MergedMeshes::skin_buffer",
+                vertices * skin_size,
+                wgpu::BufferUsages::VERTEX,
+            )
+        });
+
+        let materials = model
+            .meshes
+            .iter()
+            .flat_map(|mesh| {
+                let count = mesh.vertex_buffer.size() / vertex_size;
+                std::iter::repeat_n(mesh.material as u32, count as usize)
+            })
+            .collect::<Vec<_>>();
+        let material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("MergedMeshes::material_buffer"),
+            contents: bytemuck::cast_slice(&materials),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("MergedMeshes::encoder"),
+        });
+        let mut offsets = Vec::with_capacity(model.meshes.len());
+        let (mut vertex, mut index) = (0, 0);
+        for mesh in &model.meshes {
+            offsets.push((index as u32, vertex as i32));
+            let copy =
+                |encoder: &mut wgpu::CommandEncoder, source: &wgpu::Buffer, target, offset| {
+                    encoder.copy_buffer_to_buffer(source, 0, target, offset, source.size());
+                };
+            copy(
+                &mut encoder,
+                &mesh.vertex_buffer,
+                &vertex_buffer,
+                vertex * vertex_size,
+            );
+            copy(
+                &mut encoder,
+                &mesh.index_buffer,
+                &index_buffer,
+                index * index_size,
+            );
+            if let (Some(source), Some(target)) = (&mesh.skin_buffer, &skin_buffer) {
+                copy(&mut encoder, source, target, vertex * skin_size);
+            }
+            vertex += mesh.vertex_buffer.size() / vertex_size;
+            index += mesh.index_buffer.size() / index_size;
+        }
+        queue.submit([encoder.finish()]);
+        Some(Self {
+            sources: model
+                .meshes
+                .iter()
+                .map(|mesh| (mesh.vertex_buffer.clone(), mesh.material))
+                .collect(),
+            vertex_buffer,
+            index_buffer,
+            skin_buffer,
+            material_buffer,
+            offsets,
+        })
+    }
+
+    fn is_of(&self, model: &model::Model) -> bool {
+        self.sources
+            .iter()
+            .map(|(buffer, material)| (buffer, *material))
+            .eq(model
+                .meshes
+                .iter()
+                .map(|mesh| (&mesh.vertex_buffer, mesh.material)))
+    }
+}
@@ -0,0 +1,143 @@
+// Tests each instance's bounding sphere against the camera frustum, and
+// against last frame's depth pyramid when occlusion culling is on, and
+// packs survivors into `culled`, then writes one indexed indirect draw per
+// mesh that draws all of them.
+
+struct CullUniform {
+    // World-space planes with their normals pointing inwards.
+    planes: array<vec4<f32>, 6>,
+    // Model-space bounding sphere: center in xyz, radius in w.
+    sphere: vec4<f32>,
+    instance_count: u32,
+    mesh_count: u32,
+    // What the depth pyramid was drawn with.
+    occlusion_view_proj: mat4x4<f32>,
+    // Zero when there is no pyramid to test against.
+    occlusion: u32,
+}
+
+// Matches `wgpu::util::DrawIndexedIndirectArgs`.
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+// `InstanceRaw` as plain floats.
+const INSTANCE_FLOATS: u32 = 32u;
+
+@group(0) @binding(0)
+var<uniform> cull: CullUniform;
+@group(0) @binding(1)
+var<storage, read> instances: array<f32>;
+@group(0) @binding(2)
+var<storage, read_write> culled: array<f32>;
+@group(0) @binding(3)
+var<storage, read_write> draws: array<DrawArgs>;
+// The farthest depth under each texel, over ever larger texels.
+@group(0) @binding(4)
+var pyramid: texture_2d<f32>;
+@group(0) @binding(5)
+var pyramid_sampler: sampler;
+
+fn model_column(instance: u32, column: u32) -> vec3<f32> {
+    let base = instance * INSTANCE_FLOATS + column * 4u;
+    return vec3<f32>(instances[base], instances[base + 1u], instances[base + 2u]);
+}
+
+// Sampled at the texel's center rather than loaded: llvmpipe reads zeros
+// when invocations load from different mips at once.
+fn pyramid_texel(texel: vec2<u32>, size: vec2<u32>, mip: i32) -> f32 {
+    let uv = (vec2<f32>(texel) + 0.5) / vec2<f32>(size);
+    return textureSampleLevel(pyramid, pyramid_sampler, uv, f32(mip)).r;
+}
+
+// Whether everything in the box around the sphere was behind what last
+// frame drew over it.
+fn occluded(center: vec3<f32>, radius: f32) -> bool {
+    if cull.occlusion == 0u {
+        return false;
+    }
+    var lo = vec3<f32>(1.0e9);
+    var hi = vec3<f32>(-1.0e9);
+    for (var i = 0u; i < 8u; i += 1u) {
+        let corner = center + radius * vec3<f32>(
+            select(-1.0, 1.0, (i & 1u) != 0u),
+            select(-1.0, 1.0, (i & 2u) != 0u),
+            select(-1.0, 1.0, (i & 4u) != 0u),
+        );
+        let clip = cull.occlusion_view_proj * vec4<f32>(corner, 1.0);
+        // Reaching behind the eye, it could cover anything.
+        if clip.w <= 0.0 {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        lo = min(lo, ndc);
+        hi = max(hi, ndc);
+    }
+    if lo.z <= 0.0 {
+        return false;
+    }
+
+    let size = vec2<f32>(textureDimensions(pyramid, 0));
+    // A texel wider on each side for the TAA jitter.
+    let margin = 1.0 / size;
+    let uv_min = clamp(vec2<f32>(lo.x, -hi.y) * 0.5 + 0.5 - margin, vec2<f32>(0.0), vec2<f32>(1.0));
+    let uv_max = clamp(vec2<f32>(hi.x, -lo.y) * 0.5 + 0.5 + margin, vec2<f32>(0.0), vec2<f32>(1.0));
+    // The mip where the box is at most a texel across, so it spans at most
+    // two texels each way and four samples cover it.
+    let texels = max((uv_max - uv_min) * size, vec2<f32>(1.0));
+    let mip = min(i32(ceil(log2(max(texels.x, texels.y)))), i32(textureNumLevels(pyramid)) - 1);
+    let mip_size = vec2<u32>(textureDimensions(pyramid, mip));
+    let a = min(vec2<u32>(uv_min * vec2<f32>(mip_size)), mip_size - 1u);
+    let b = min(vec2<u32>(uv_max * vec2<f32>(mip_size)), mip_size - 1u);
+    let farthest = max(
+        max(pyramid_texel(a, mip_size, mip), pyramid_texel(vec2<u32>(b.x, a.y), mip_size, mip)),
+        max(pyramid_texel(vec2<u32>(a.x, b.y), mip_size, mip), pyramid_texel(b, mip_size, mip)),
+    );
+    return lo.z > farthest;
+}
+
+@compute @workgroup_size(64)
+fn cs_cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let instance = id.x;
+    if instance >= cull.instance_count {
+        return;
+    }
+    let x = model_column(instance, 0u);
+    let y = model_column(instance, 1u);
+    let z = model_column(instance, 2u);
+    let translation = model_column(instance, 3u);
+    let center = x * cull.sphere.x + y * cull.sphere.y + z * cull.sphere.z + translation;
+    // The largest axis scale bounds the sphere under non-uniform scaling.
+    let scale = sqrt(max(dot(x, x), max(dot(y, y), dot(z, z))));
+    let radius = cull.sphere.w * scale;
+    for (var i = 0u; i < 6u; i += 1u) {
+        let plane = cull.planes[i];
+        if dot(plane.xyz, center) + plane.w < -radius {
+            return;
+        }
+    }
+    if occluded(center, radius) {
+        return;
+    }
+
+    let slot = atomicAdd(&draws[0].instance_count, 1u);
+    let src = instance * INSTANCE_FLOATS;
+    let dst = slot * INSTANCE_FLOATS;
+    for (var i = 0u; i < INSTANCE_FLOATS; i += 1u) {
+        culled[dst + i] = instances[src + i];
+    }
+}
+
+// Runs after `cs_cull`, once the first draw holds the final count.
+@compute @workgroup_size(64)
+fn cs_write_draws(@builtin(global_invocation_id) id: vec3<u32>) {
+    let mesh = id.x;
+    if mesh == 0u || mesh >= cull.mesh_count {
+        return;
+    }
+    atomicStore(&draws[mesh].instance_count, atomicLoad(&draws[0].instance_count));
+}
@@ -0,0 +1,234 @@
+use wgpu::util::DeviceExt;
+
+use crate::post::taa::Taa;
+use crate::preprocess::{ShaderDefs, ShaderLibrary};
+use crate::texture;
+
+/// How the ground grid looks, and whether it is drawn at all.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GridSettings {
+    pub enabled: bool,
+    /// World units between minor lines; every tenth line is brighter.
+    pub cell_size: f32,
+    /// Distance from the camera by which the lines have faded out.
+    pub fade_distance: f32,
+    /// Linear color of the lines off the axes.
+    pub color: [f32; 3],
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cell_size: 1.0,
+            fade_distance: 60.0,
+            color: [0.5, 0.5, 0.5],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridUniform {
+    color: [f32; 3],
+    cell_size: f32,
+    fade_distance: f32,
+    _padding: [f32; 3],
+}
+
+impl GridUniform {
+    fn new(settings: &GridSettings) -> Self {
+        Self {
+            color: settings.color,
+            cell_size: settings.cell_size.max(1e-3),
+            fade_distance: settings.fade_distance,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// An endless grid on the y = 0 plane, with the x axis in red and z in
+/// blue, for getting one's bearings in an empty scene.
+///
+/// It is shaded per pixel from one screen-covering triangle, so it needs
+/// no geometry and reaches the horizon. It blends over the opaque scene,
+/// testing depth without writing it.
+pub struct Grid {
+    settings: GridSettings,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    reverse_z: bool,
+    pipeline: wgpu::RenderPipeline,
+    // `pipeline` for a reversed depth buffer.
+    reverse_z_pipeline: wgpu::RenderPipeline,
+}
+
+impl Grid {
+    /// `camera_layout` is the scene's camera group, of which only the
+    /// `CameraUniform` is read.
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+        camera_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<Self> {
+        let settings = GridSettings::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid::uniform"),
+            contents: bytemuck::bytes_of(&GridUniform::new(&settings)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Grid::bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Grid::bind_group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid::layout"),
+            bind_group_layouts: &[camera_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let [pipeline, reverse_z_pipeline] =
+            Self::create_pipelines(device, shaders, &pipeline_layout, color_format)?;
+
+        Ok(Self {
+            settings,
+            uniform_buffer,
+            bind_group,
+            pipeline_layout,
+            color_format,
+            reverse_z: false,
+            pipeline,
+            reverse_z_pipeline,
+        })
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+        pipeline_layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<[wgpu::RenderPipeline; 2]> {
+        let shader =
+            shaders.create_module(device, "Grid::shader", "grid.wgsl", &ShaderDefs::new())?;
+        let create_pipeline = |label, depth_compare| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[
+                        Some(wgpu::ColorTargetState {
+                            format: color_format,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: Taa::VELOCITY_FORMAT,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::empty(),
+                        }),
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        Ok([
+            create_pipeline("Grid::pipeline", wgpu::CompareFunction::LessEqual),
+            create_pipeline(
+                "Grid::reverse_z_pipeline",
+                wgpu::CompareFunction::GreaterEqual,
+            ),
+        ])
+    }
+
+    /// Compiles the pipelines again from `shaders`' current sources.
+    pub(crate) fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+    ) -> anyhow::Result<()> {
+        [self.pipeline, self.reverse_z_pipeline] =
+            Self::create_pipelines(device, shaders, &self.pipeline_layout, self.color_format)?;
+        Ok(())
+    }
+
+    pub fn settings(&self) -> GridSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: GridSettings) {
+        self.settings = settings;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&GridUniform::new(&settings)),
+        );
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.settings.enabled
+    }
+
+    /// Tests depth against a buffer drawn with the camera's `reverse_z`.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+    }
+
+    /// Draws into a pass over the HDR, velocity and depth targets, if
+    /// enabled.
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if !self.settings.enabled {
+            return;
+        }
+        render_pass.set_pipeline(if self.reverse_z {
+            &self.reverse_z_pipeline
+        } else {
+            &self.pipeline
+        });
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
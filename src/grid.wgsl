@@ -0,0 +1,94 @@
+// The infinite ground grid for grid.rs, drawn as one screen-covering
+// triangle that finds where each pixel's view ray meets y = 0.
+#include "include/camera.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Matches `GridUniform` in grid.rs.
+struct GridUniform {
+    color: vec3<f32>,
+    cell_size: f32,
+    fade_distance: f32,
+}
+@group(1) @binding(0)
+var<uniform> grid: GridUniform;
+
+// Every how many cells a line is drawn brighter.
+const MAJOR_EVERY: f32 = 10.0;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+// Coverage of the lines `spacing` apart through `coord`, antialiased over
+// about a pixel.
+fn lines(coord: vec2<f32>, spacing: f32) -> f32 {
+    let scaled = coord / spacing;
+    let width = fwidth(scaled);
+    let distance = abs(fract(scaled - 0.5) - 0.5) / max(width, vec2<f32>(1e-6));
+    return 1.0 - min(min(distance.x, distance.y), 1.0);
+}
+
+struct FragmentOutput {
+    @builtin(frag_depth) depth: f32,
+    @location(0) color: vec4<f32>,
+    // Masked off, like billboards.
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    // The depth buffer was drawn jittered; the unjittered matrices need the
+    // offset taken back out. Two depths short of the far plane unproject
+    // cleanly however distant it is, as for the skybox.
+    let ndc = in.ndc - camera.jitter;
+    let near = camera.inv_view_proj * vec4<f32>(ndc, near_depth(), 1.0);
+    let inside = camera.inv_view_proj * vec4<f32>(ndc, 0.5, 1.0);
+    let origin = near.xyz / near.w;
+    let direction = inside.xyz / inside.w - origin;
+    let t = -origin.y / direction.y;
+    let position = origin + direction * t;
+    let clip = camera.view_proj * vec4<f32>(position, 1.0);
+    let depth = clip.z / clip.w;
+    // Only discarded at the end, as derivatives need every pixel of a quad.
+    let on_plane = abs(direction.y) > 1e-8 && t > 0.0 && depth >= 0.0 && depth <= 1.0;
+
+    let coord = position.xz;
+    let minor = lines(coord, grid.cell_size);
+    let major = lines(coord, grid.cell_size * MAJOR_EVERY);
+    var color = grid.color;
+    var alpha = max(minor * 0.5, major);
+    // Red along the x axis and blue along z, as in the axis helper.
+    let width = fwidth(coord);
+    if abs(coord.y) < width.y {
+        color = vec3<f32>(1.0, 0.1, 0.1);
+        alpha = 1.0;
+    } else if abs(coord.x) < width.x {
+        color = vec3<f32>(0.1, 0.1, 1.0);
+        alpha = 1.0;
+    }
+    let fade = 1.0 - smoothstep(0.0, grid.fade_distance, distance(position, camera.view_pos.xyz));
+    alpha *= fade;
+    if !on_plane || alpha < 0.01 {
+        discard;
+    }
+
+    var out: FragmentOutput;
+    out.depth = depth;
+    out.color = vec4<f32>(color, alpha);
+    out.velocity = vec2<f32>(0.0);
+    return out;
+}
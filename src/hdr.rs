@@ -0,0 +1,387 @@
+use wgpu::util::DeviceExt;
+
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+/// Curve used to map HDR radiance into the displayable range.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Tonemap {
+    #[default]
+    Aces,
+    Reinhard,
+}
+
+/// What the tonemap pass writes, to see where color goes wrong: the curve
+/// and the sRGB encode can each be skipped or doubled up.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ColorView {
+    #[default]
+    Final,
+    /// Exposed but clamped rather than tonemapped.
+    Untonemapped,
+    /// As if the output were never sRGB-encoded: too dark and contrasty.
+    Unencoded,
+    /// As if the output were sRGB-encoded twice: washed out.
+    DoubleEncoded,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    exposure: f32,
+    mode: u32,
+    view: u32,
+    _padding: u32,
+}
+
+/// Format the final passes render surface views as: the sRGB view format
+/// when the surface itself is linear, as browser canvases are.
+pub fn output_format(config: &wgpu::SurfaceConfiguration) -> wgpu::TextureFormat {
+    config
+        .view_formats
+        .first()
+        .copied()
+        .unwrap_or(config.format)
+}
+
+/// Offscreen `Rgba16Float` target the scene renders into, resolved to the
+/// surface by a fullscreen tonemap pass.
+pub struct HdrPipeline {
+    texture: texture::Texture,
+    exposure: f32,
+    tonemap: Tonemap,
+    color_view: ColorView,
+    uniform_buffer: wgpu::Buffer,
+    adapted_exposure: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    output_format: wgpu::TextureFormat,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl HdrPipeline {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let texture = texture::Texture::create_render_target(
+            device,
+            config.width,
+            config.height,
+            Self::FORMAT,
+            "Hdr::texture",
+        );
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap Buffer"),
+            contents: bytemuck::cast_slice(&[TonemapUniform {
+                exposure: 1.0,
+                mode: Tonemap::default() as u32,
+                view: ColorView::default() as u32,
+                _padding: 0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let adapted_exposure = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Hdr::adapted_exposure"),
+            contents: bytemuck::bytes_of(&1.0f32),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Hdr::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = Self::create_bind_group(
+            device,
+            &layout,
+            &texture,
+            &uniform_buffer,
+            &adapted_exposure,
+        );
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Hdr Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, output_format(config));
+
+        Self {
+            texture,
+            exposure: 1.0,
+            tonemap: Tonemap::default(),
+            color_view: ColorView::default(),
+            uniform_buffer,
+            adapted_exposure,
+            layout,
+            bind_group,
+            pipeline_layout,
+            output_format: output_format(config),
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        output_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader =
+            preprocess::builtin_module(device, "Hdr Shader", "hdr.wgsl", &ShaderDefs::new());
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Hdr Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: output_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Compiles the pipeline again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(device, &self.pipeline_layout, self.output_format);
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &texture::Texture,
+        uniform_buffer: &wgpu::Buffer,
+        adapted_exposure: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Hdr::bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: adapted_exposure.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.texture = texture::Texture::create_render_target(
+            device,
+            width,
+            height,
+            Self::FORMAT,
+            "Hdr::texture",
+        );
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            &self.texture,
+            &self.uniform_buffer,
+            &self.adapted_exposure,
+        );
+    }
+
+    pub fn texture(&self) -> &texture::Texture {
+        &self.texture
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        Self::FORMAT
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    pub fn tonemap(&self) -> Tonemap {
+        self.tonemap
+    }
+
+    pub fn color_view(&self) -> ColorView {
+        self.color_view
+    }
+
+    pub fn set_exposure(&mut self, queue: &wgpu::Queue, exposure: f32) {
+        self.exposure = exposure;
+        self.write_uniform(queue);
+    }
+
+    /// The exposure `AutoExposure` adapts, which `exposure` is multiplied
+    /// with; 1 while it's off.
+    pub fn adapted_exposure(&self) -> &wgpu::Buffer {
+        &self.adapted_exposure
+    }
+
+    /// Puts the adapted exposure back to 1.
+    pub fn reset_adapted_exposure(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.adapted_exposure, 0, bytemuck::bytes_of(&1.0f32));
+    }
+
+    pub fn set_tonemap(&mut self, queue: &wgpu::Queue, tonemap: Tonemap) {
+        self.tonemap = tonemap;
+        self.write_uniform(queue);
+    }
+
+    pub fn set_color_view(&mut self, queue: &wgpu::Queue, color_view: ColorView) {
+        self.color_view = color_view;
+        self.write_uniform(queue);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[TonemapUniform {
+                exposure: self.exposure,
+                mode: self.tonemap as u32,
+                view: self.color_view as u32,
+                _padding: 0,
+            }]),
+        );
+    }
+
+    /// Tonemaps the HDR texture into `output`.
+    pub fn process(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Hdr::process"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    /// A bind group for `composite` to tonemap `texture` with, at the
+    /// same exposure and curve as the HDR texture.
+    pub fn bind_texture(
+        &self,
+        device: &wgpu::Device,
+        texture: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        Self::create_bind_group(
+            device,
+            &self.layout,
+            texture,
+            &self.uniform_buffer,
+            &self.adapted_exposure,
+        )
+    }
+
+    /// Tonemaps the texture `bind_group` was made for into the `size`
+    /// pixels of `output` at `origin`, leaving the rest as it was.
+    pub fn composite(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+        origin: [u32; 2],
+        size: [u32; 2],
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Hdr::composite"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_viewport(
+            origin[0] as f32,
+            origin[1] as f32,
+            size[0] as f32,
+            size[1] as f32,
+            0.0,
+            1.0,
+        );
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,66 @@
+#include "include/color.wgsl"
+
+struct TonemapUniform {
+    exposure: f32,
+    mode: u32,
+    // Matches `ColorView` in hdr.rs.
+    view: u32,
+}
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var s_hdr: sampler;
+@group(0) @binding(2)
+var<uniform> tonemap: TonemapUniform;
+// What auto_exposure.wgsl adapted the exposure to, 1 while it's off.
+@group(0) @binding(3)
+var<storage, read> adapted_exposure: f32;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard(x: vec3<f32>) -> vec3<f32> {
+    return x / (1.0 + x);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_hdr, s_hdr, in.uv);
+    let color = hdr.rgb * tonemap.exposure * adapted_exposure;
+    var mapped: vec3<f32>;
+    switch tonemap.mode {
+        case 1u: { mapped = reinhard(color); }
+        default: { mapped = aces(color); }
+    }
+    // The output is sRGB, so it stays linear and the hardware encodes it;
+    // the debug views undo or repeat that encode.
+    switch tonemap.view {
+        case 1u: { mapped = saturate(color); }
+        case 2u: { mapped = srgb_to_linear(mapped); }
+        case 3u: { mapped = linear_to_srgb(mapped); }
+        default: {}
+    }
+    return vec4<f32>(mapped, hdr.a);
+}
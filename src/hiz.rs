@@ -0,0 +1,259 @@
+use crate::camera::Camera;
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+const WORKGROUP_SIZE: u32 = 8;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+/// The depth buffer reduced to a chain of mips, each texel holding the
+/// farthest depth of the ones it covers in full resolution, so a few loads
+/// tell whether anything drawn could be in front of a box on screen.
+///
+/// Built by `GpuCulling` after the opaque pass, which tests the next
+/// frame's instances against it along with the view it was drawn from.
+pub struct DepthPyramid {
+    texture: wgpu::Texture,
+    // Every mip, for the culling pass.
+    view: wgpu::TextureView,
+    // One mip each, to write them one after another.
+    mip_views: Vec<wgpu::TextureView>,
+    copy_layout: wgpu::BindGroupLayout,
+    reduce_layout: wgpu::BindGroupLayout,
+    copy_pipeline: wgpu::ComputePipeline,
+    // `copy_pipeline` for depth drawn with `reverse_z`.
+    reversed_copy_pipeline: wgpu::ComputePipeline,
+    reduce_pipeline: wgpu::ComputePipeline,
+    // What the depth was drawn with, once the pyramid has been built.
+    view_proj: Option<cgmath::Matrix4<f32>>,
+}
+
+impl DepthPyramid {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty,
+            count: None,
+        };
+        let storage = |binding, access| {
+            entry(
+                binding,
+                wgpu::BindingType::StorageTexture {
+                    access,
+                    format: FORMAT,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+            )
+        };
+        let copy_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("DepthPyramid::copy_layout"),
+            entries: &[
+                storage(0, wgpu::StorageTextureAccess::WriteOnly),
+                entry(
+                    1,
+                    wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                ),
+            ],
+        });
+        let reduce_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("DepthPyramid::reduce_layout"),
+            entries: &[
+                storage(0, wgpu::StorageTextureAccess::WriteOnly),
+                storage(2, wgpu::StorageTextureAccess::ReadOnly),
+            ],
+        });
+
+        let [copy_pipeline, reversed_copy_pipeline, reduce_pipeline] =
+            Self::create_pipelines(device, &copy_layout, &reduce_layout);
+
+        let (texture, view, mip_views) = Self::create_texture(device, 1, 1);
+        Self {
+            texture,
+            view,
+            mip_views,
+            copy_layout,
+            reduce_layout,
+            copy_pipeline,
+            reversed_copy_pipeline,
+            reduce_pipeline,
+            view_proj: None,
+        }
+    }
+
+    // The copy, reversed copy and reduce pipelines, in that order.
+    fn create_pipelines(
+        device: &wgpu::Device,
+        copy_layout: &wgpu::BindGroupLayout,
+        reduce_layout: &wgpu::BindGroupLayout,
+    ) -> [wgpu::ComputePipeline; 3] {
+        let shader = preprocess::builtin_module(
+            device,
+            "DepthPyramid::shader",
+            "hiz.wgsl",
+            &ShaderDefs::new(),
+        );
+        let pipeline = |label, layout, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let copy_pipeline = pipeline("DepthPyramid::copy_pipeline", copy_layout, "cs_copy");
+        let reversed_copy_pipeline = pipeline(
+            "DepthPyramid::reversed_copy_pipeline",
+            copy_layout,
+            "cs_copy_reversed",
+        );
+        let reduce_pipeline = pipeline("DepthPyramid::reduce_pipeline", reduce_layout, "cs_reduce");
+        [copy_pipeline, reversed_copy_pipeline, reduce_pipeline]
+    }
+
+    /// Compiles the pipelines again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        [
+            self.copy_pipeline,
+            self.reversed_copy_pipeline,
+            self.reduce_pipeline,
+        ] = Self::create_pipelines(device, &self.copy_layout, &self.reduce_layout);
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::Texture, wgpu::TextureView, Vec<wgpu::TextureView>) {
+        let mip_level_count = width.max(height).max(1).ilog2() + 1;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("DepthPyramid::texture"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("DepthPyramid::view"),
+            ..Default::default()
+        });
+        let mip_views = (0..mip_level_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("DepthPyramid::mip_view"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        (texture, view, mip_views)
+    }
+
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    /// All the mips, for a `texture_2d<f32>` binding.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// The view-projection matrix the depth was drawn with, or None until
+    /// the pyramid is built, and again after `clear`. Depth drawn with
+    /// `reverse_z` is stored the usual way round, and this matrix is too.
+    pub fn view_proj(&self) -> Option<cgmath::Matrix4<f32>> {
+        self.view_proj
+    }
+
+    /// Forgets what was built, so nothing is tested against stale depth.
+    pub fn clear(&mut self) {
+        self.view_proj = None;
+    }
+
+    /// Reduces `depth`, as drawn from `camera`, into the pyramid, resizing
+    /// it to match first if needed.
+    pub fn build(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        depth: &texture::Texture,
+        camera: &Camera,
+    ) {
+        let (width, height) = (depth.texture.width(), depth.texture.height());
+        if (self.texture.width(), self.texture.height()) != (width, height) {
+            (self.texture, self.view, self.mip_views) = Self::create_texture(device, width, height);
+        }
+        // Made every build, since the depth texture is replaced on resize.
+        let bind_group = |layout, target, (binding, previous)| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("DepthPyramid::bind_group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(target),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding,
+                        resource: wgpu::BindingResource::TextureView(previous),
+                    },
+                ],
+            })
+        };
+        let copy = bind_group(&self.copy_layout, &self.mip_views[0], (1, &depth.view));
+        let reduces = self
+            .mip_views
+            .windows(2)
+            .map(|mips| bind_group(&self.reduce_layout, &mips[1], (2, &mips[0])))
+            .collect::<Vec<_>>();
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("DepthPyramid::pass"),
+            timestamp_writes: None,
+        });
+        let dispatch = |pass: &mut wgpu::ComputePass<'_>, mip: u32| {
+            let width = (width >> mip).max(1);
+            let height = (height >> mip).max(1);
+            pass.dispatch_workgroups(
+                width.div_ceil(WORKGROUP_SIZE),
+                height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        };
+        pass.set_pipeline(if camera.reverse_z {
+            &self.reversed_copy_pipeline
+        } else {
+            &self.copy_pipeline
+        });
+        pass.set_bind_group(0, &copy, &[]);
+        dispatch(&mut pass, 0);
+        pass.set_pipeline(&self.reduce_pipeline);
+        for (mip, bind_group) in reduces.iter().enumerate() {
+            pass.set_bind_group(0, bind_group, &[]);
+            dispatch(&mut pass, mip as u32 + 1);
+        }
+        let camera = Camera {
+            reverse_z: false,
+            ..*camera
+        };
+        self.view_proj = Some(camera.build_view_projection_matrix());
+    }
+}
@@ -0,0 +1,54 @@
+// Builds the depth pyramid: mip 0 copies the depth buffer, and each mip
+// after it holds the farthest depth of the texels it covers in the one
+// before.
+
+@group(0) @binding(0)
+var target_mip: texture_storage_2d<r32float, write>;
+// Bound as a plain float texture: GL can't `textureLoad` depth textures.
+@group(0) @binding(1)
+var depth: texture_2d<f32>;
+// The mip before, as a storage texture too: sampling one mip of the
+// pyramid would, on GL, leave the others unwritable.
+@group(0) @binding(2)
+var previous: texture_storage_2d<r32float, read>;
+
+@compute @workgroup_size(8, 8)
+fn cs_copy(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(target_mip);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    textureStore(target_mip, id.xy, vec4<f32>(textureLoad(depth, id.xy, 0).r, 0.0, 0.0, 1.0));
+}
+
+// `cs_copy` for a reversed depth buffer, flipped back so the rest of the
+// pyramid and the culling pass only know one convention.
+@compute @workgroup_size(8, 8)
+fn cs_copy_reversed(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(target_mip);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let reversed = textureLoad(depth, id.xy, 0).r;
+    textureStore(target_mip, id.xy, vec4<f32>(1.0 - reversed, 0.0, 0.0, 1.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_reduce(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(target_mip);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let last = textureDimensions(previous);
+    // Halving an odd size rounds down, so each texel takes in a third row
+    // or column to still cover all of the screen it stands for.
+    let extra = last % 2u;
+    var farthest = 0.0;
+    for (var y = 0u; y <= 1u + extra.y; y += 1u) {
+        for (var x = 0u; x <= 1u + extra.x; x += 1u) {
+            let texel = min(id.xy * 2u + vec2<u32>(x, y), last - 1u);
+            farthest = max(farthest, textureLoad(previous, texel).r);
+        }
+    }
+    textureStore(target_mip, id.xy, vec4<f32>(farthest, 0.0, 0.0, 1.0));
+}
@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use notify::Watcher;
+
+/// Watches the WGSL sources in this crate's `src` directory.
+///
+/// Shaders are compiled into the binary with `include_str!`; while this
+/// runs, edited files are read back from disk instead so pipelines can be
+/// rebuilt without restarting.
+pub struct ShaderWatcher {
+    directory: WatchedDirectory,
+}
+
+impl ShaderWatcher {
+    /// Fails when the sources are not on this machine, as for an installed
+    /// binary.
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            directory: WatchedDirectory::new(Path::new(env!("CARGO_MANIFEST_DIR")).join("src"))?,
+        })
+    }
+
+    /// WGSL files modified since the last call, relative to `src` with `/`
+    /// separators, e.g. `shader.wgsl` or `post/taa.wgsl`.
+    pub fn changed(&self) -> Vec<String> {
+        self.directory.changed(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "wgsl")
+        })
+    }
+
+    /// Current contents of `name`, as returned by `changed`.
+    pub fn read(&self, name: &str) -> std::io::Result<String> {
+        std::fs::read_to_string(self.directory.root.join(name))
+    }
+}
+
+/// Watches this crate's `res` directory, where textures and models are
+/// edited.
+///
+/// The build copies `res` next to the binary, where the loaders read it;
+/// `changed` copies each edited file over its build copy the same way, so
+/// loading it again picks up the edit.
+pub struct AssetWatcher {
+    directory: WatchedDirectory,
+    copy: PathBuf,
+}
+
+impl AssetWatcher {
+    /// Fails when the sources are not on this machine, as for an installed
+    /// binary.
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            directory: WatchedDirectory::new(Path::new(env!("CARGO_MANIFEST_DIR")).join("res"))?,
+            copy: Path::new(env!("OUT_DIR")).join("res"),
+        })
+    }
+
+    /// Files modified since the last call, relative to `res` with `/`
+    /// separators as the loaders name them, each copied over its build copy
+    /// first. Ones that fail to copy are logged and left out.
+    pub fn changed(&self) -> Vec<String> {
+        let mut changed = self.directory.changed(Path::is_file);
+        changed.retain(|name| {
+            let copy = self.copy.join(name);
+            let copied = copy
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|()| std::fs::copy(self.directory.root.join(name), &copy));
+            copied
+                .inspect_err(|e| log::error!("Unable to copy {} for reloading: {}", name, e))
+                .is_ok()
+        });
+        changed
+    }
+}
+
+struct WatchedDirectory {
+    root: PathBuf,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    // Dropping the watcher stops the events.
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl WatchedDirectory {
+    fn new(root: PathBuf) -> anyhow::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(&root, notify::RecursiveMode::Recursive)?;
+        Ok(Self {
+            root,
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    // Paths modified or created since the last call that pass `wanted`,
+    // relative to `root` with `/` separators.
+    fn changed(&self, wanted: impl Fn(&Path) -> bool) -> Vec<String> {
+        let mut changed = Vec::new();
+        for event in self.events.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    log::warn!("File watcher error: {}", e);
+                    continue;
+                }
+            };
+            if !matches!(
+                event.kind,
+                notify::EventKind::Modify(_) | notify::EventKind::Create(_)
+            ) {
+                continue;
+            }
+            for path in event.paths {
+                if !wanted(&path) {
+                    continue;
+                }
+                let Ok(relative) = path.strip_prefix(&self.root) else {
+                    continue;
+                };
+                let name = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if !changed.contains(&name) {
+                    changed.push(name);
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// Runs `build` inside a validation error scope. Returns None, logging the
+/// error, if anything it created was invalid, so callers can keep the
+/// objects they had.
+pub fn try_build<T>(device: &wgpu::Device, label: &str, build: impl FnOnce() -> T) -> Option<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let built = build();
+    match pollster::block_on(device.pop_error_scope()) {
+        None => Some(built),
+        Some(error) => {
+            log::error!("Keeping the previous {}: {}", label, error);
+            None
+        }
+    }
+}
@@ -0,0 +1,137 @@
+struct BakeUniform {
+    face: u32,
+    roughness: f32,
+    sample_count: u32,
+}
+@group(0) @binding(0)
+var<uniform> bake: BakeUniform;
+@group(0) @binding(1)
+var t_source: texture_cube<f32>;
+@group(0) @binding(2)
+var s_source: sampler;
+
+const PI: f32 = 3.14159265359;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Face coordinates in -1..1, with y pointing down the image.
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = vec2<f32>(ndc.x, -ndc.y);
+    return out;
+}
+
+// Matches `skybox::cube_face_direction`.
+fn face_direction(face: u32, u: f32, v: f32) -> vec3<f32> {
+    switch face {
+        case 0u: { return normalize(vec3<f32>(1.0, -v, -u)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -v, u)); }
+        case 2u: { return normalize(vec3<f32>(u, 1.0, v)); }
+        case 3u: { return normalize(vec3<f32>(u, -1.0, -v)); }
+        case 4u: { return normalize(vec3<f32>(u, -v, 1.0)); }
+        default: { return normalize(vec3<f32>(-u, -v, -1.0)); }
+    }
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Rotates tangent-space `v` (z up) into the hemisphere around `n`.
+fn to_world(v: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    var up = vec3<f32>(0.0, 0.0, 1.0);
+    if abs(n.z) > 0.999 {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let t = normalize(cross(up, n));
+    let b = cross(n, t);
+    return t * v.x + b * v.y + n * v.z;
+}
+
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return to_world(vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+}
+
+// Cosine-weighted samples, so the plain average is irradiance / pi.
+@fragment
+fn fs_irradiance(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = face_direction(bake.face, in.uv.x, in.uv.y);
+    var sum = vec3<f32>(0.0);
+    for (var i = 0u; i < bake.sample_count; i += 1u) {
+        let xi = hammersley(i, bake.sample_count);
+        let phi = 2.0 * PI * xi.x;
+        let cos_theta = sqrt(1.0 - xi.y);
+        let sin_theta = sqrt(xi.y);
+        let dir = to_world(vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+        sum += textureSampleLevel(t_source, s_source, dir, 0.0).rgb;
+    }
+    return vec4<f32>(sum / f32(bake.sample_count), 1.0);
+}
+
+// Split-sum prefilter with the usual n = v = r assumption.
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = face_direction(bake.face, in.uv.x, in.uv.y);
+    if bake.roughness <= 0.0 {
+        return vec4<f32>(textureSampleLevel(t_source, s_source, n, 0.0).rgb, 1.0);
+    }
+
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < bake.sample_count; i += 1u) {
+        let h = importance_sample_ggx(hammersley(i, bake.sample_count), n, bake.roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            sum += textureSampleLevel(t_source, s_source, l, 0.0).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(sum / max(weight, 1e-4), 1.0);
+}
+
+fn geometry_schlick_ggx(n_dot_x: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+// Scale and bias applied to F0, indexed by (n.v, roughness).
+@fragment
+fn fs_brdf_lut(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coords = in.uv * 0.5 + 0.5;
+    let n_dot_v = max(coords.x, 1e-3);
+    let roughness = coords.y;
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let n = vec3<f32>(0.0, 0.0, 1.0);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < bake.sample_count; i += 1u) {
+        let h = importance_sample_ggx(hammersley(i, bake.sample_count), n, roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        if n_dot_l > 0.0 {
+            let n_dot_h = max(h.z, 0.0);
+            let v_dot_h = max(dot(v, h), 0.0);
+            let g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            let g_vis = g * v_dot_h / max(n_dot_h * n_dot_v, 1e-4);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+    let count = f32(bake.sample_count);
+    return vec4<f32>(scale / count, bias / count, 0.0, 1.0);
+}
@@ -0,0 +1,31 @@
+// Matches `CameraUniform` in camera.rs.
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+    // Sub-pixel TAA offset in NDC, applied after `view_proj`.
+    jitter: vec2<f32>,
+    // 1 with reverse Z, where the near plane is at depth 1 and the far at 0.
+    reverse_z: u32,
+    // Forward materials discard what is behind it; zero keeps everything.
+    clip_plane: vec4<f32>,
+}
+
+// These read the including shader's `camera`.
+
+// Depth of the near plane, which unprojects cleanly however far away the
+// far plane is.
+fn near_depth() -> f32 {
+    return select(0.0, 1.0, camera.reverse_z != 0u);
+}
+
+// Whether `depth` is still the far plane's, which the depth buffer was
+// cleared to, so nothing was drawn there.
+fn is_far_depth(depth: f32) -> bool {
+    if camera.reverse_z != 0u {
+        return depth <= 0.0;
+    }
+    return depth >= 1.0;
+}
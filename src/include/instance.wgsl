@@ -0,0 +1,41 @@
+// Matches `InstanceRaw::desc`; passes that only need the model matrix can
+// ignore the rest. Location 15, past the skinning and material ID inputs,
+// is the color.
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    // Padded as a `mat3x3`'s columns are, the last padding holding the
+    // level-of-detail fade.
+    @location(9) normal_matrix_0: vec4<f32>,
+    @location(10) normal_matrix_1: vec4<f32>,
+    @location(11) normal_matrix_2: vec4<f32>,
+    @location(15) color: vec4<f32>,
+}
+
+fn instance_normal_matrix(instance: InstanceInput) -> mat3x3<f32> {
+    return mat3x3<f32>(
+        instance.normal_matrix_0.xyz,
+        instance.normal_matrix_1.xyz,
+        instance.normal_matrix_2.xyz,
+    );
+}
+
+// `InstanceRaw::fade`, 1 unless `LodInstances` is crossfading the instance
+// between two levels of detail.
+fn instance_fade(instance: InstanceInput) -> f32 {
+    return instance.normal_matrix_2.w;
+}
+
+// Whether the fragment at `position`, in pixels, of an instance drawn with
+// `fade` is left out of its crossfade. The pixels a negative fade keeps
+// are the ones `1 + fade` drops, so the two levels never overlap.
+fn lod_dithered(position: vec2<f32>, fade: f32) -> bool {
+    // Interleaved gradient noise, a threshold per pixel in [0, 1).
+    let threshold = fract(52.9829189 * fract(dot(floor(position), vec2<f32>(0.06711056, 0.00583715))));
+    if fade < 0.0 {
+        return threshold < 1.0 + fade;
+    }
+    return threshold >= fade;
+}
@@ -0,0 +1,42 @@
+// Point lights and the clusters they are binned into. Bindings are left to
+// the including shader.
+
+struct Light {
+    position: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+}
+struct Lighting {
+    light_count: u32,
+    ambient: f32,
+    fog_enabled: u32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    fog_start: f32,
+    fog_end: f32,
+    fog_height_falloff: f32,
+}
+
+struct ClusterUniform {
+    inv_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    grid: vec3<u32>,
+    enabled: u32,
+    tile_size: vec2<f32>,
+    screen_size: vec2<f32>,
+    z_near: f32,
+    z_far: f32,
+}
+struct Cluster {
+    count: u32,
+    lights: array<u32, MAX_LIGHTS_PER_CLUSTER>,
+}
+
+// Radiance below this is cut off, giving every light a finite range that
+// clustering can cull against.
+const LIGHT_CUTOFF: f32 = 0.005;
+
+fn light_range(light: Light) -> f32 {
+    let peak = light.intensity * max(light.color.r, max(light.color.g, light.color.b));
+    return sqrt(max(peak, 0.0) / LIGHT_CUTOFF);
+}
@@ -0,0 +1,133 @@
+// The directional light's cascaded shadow map and the point lights' cube
+// maps. Define SHADOW_GROUP to where the including pass binds
+// `ShadowMap::bind_group` first.
+
+// Fraction of each cascade, at its far end, that fades into the next one.
+const CASCADE_BLEND: f32 = 0.1;
+
+struct ShadowUniform {
+    cascades: array<mat4x4<f32>, CASCADE_COUNT>,
+    splits: vec4<f32>,
+    direction: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+    debug_cascades: u32,
+    // Non-zero to read `t_shadow_moments` instead of comparing depths.
+    variance: u32,
+}
+@group(SHADOW_GROUP) @binding(0)
+var<uniform> shadow: ShadowUniform;
+@group(SHADOW_GROUP) @binding(1)
+var t_shadow: texture_depth_2d_array;
+@group(SHADOW_GROUP) @binding(2)
+var s_shadow: sampler_comparison;
+
+struct PointShadowUniform {
+    count: u32,
+    far: f32,
+    bias: f32,
+}
+@group(SHADOW_GROUP) @binding(3)
+var<uniform> point_shadows: PointShadowUniform;
+@group(SHADOW_GROUP) @binding(4)
+var t_point_shadow: texture_depth_cube_array;
+// Each cascade's depth and depth squared, blurred, for `ShadowFilter::Variance`.
+@group(SHADOW_GROUP) @binding(5)
+var t_shadow_moments: texture_2d_array<f32>;
+@group(SHADOW_GROUP) @binding(6)
+var s_shadow_moments: sampler;
+
+// Variance below which the moments are taken as noise.
+const MIN_VARIANCE: f32 = 1e-5;
+// How much of the lit share to cut off, so that light doesn't show through
+// a caster in front of another.
+const LIGHT_BLEED_CUTOFF: f32 = 0.3;
+
+// Chebyshev's upper bound on the share of a blurred texel at most `depth`
+// away; 1.0 means fully lit.
+fn sample_moments(cascade: u32, uv: vec2<f32>, depth: f32) -> f32 {
+    let moments = textureSampleLevel(t_shadow_moments, s_shadow_moments, uv, cascade, 0.0).xy;
+    if depth <= moments.x {
+        return 1.0;
+    }
+    let variance = max(moments.y - moments.x * moments.x, MIN_VARIANCE);
+    let d = depth - moments.x;
+    let lit = variance / (variance + d * d);
+    return clamp((lit - LIGHT_BLEED_CUTOFF) / (1.0 - LIGHT_BLEED_CUTOFF), 0.0, 1.0);
+}
+
+// 3x3 PCF in one cascade, or a tap of its moments; 1.0 means fully lit.
+fn sample_cascade(cascade: u32, world_position: vec3<f32>) -> f32 {
+    let light_space = shadow.cascades[cascade] * vec4<f32>(world_position, 1.0);
+    let ndc = light_space.xyz / light_space.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    if shadow.variance != 0u {
+        return sample_moments(cascade, uv, ndc.z);
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_shadow));
+    var lit = 0.0;
+    for (var x = -1; x <= 1; x += 1) {
+        for (var y = -1; y <= 1; y += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, cascade, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// One tap in one cascade, for passes sampling too many points for PCF.
+fn sample_cascade_once(cascade: u32, world_position: vec3<f32>) -> f32 {
+    let light_space = shadow.cascades[cascade] * vec4<f32>(world_position, 1.0);
+    let ndc = light_space.xyz / light_space.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    if shadow.variance != 0u {
+        return sample_moments(cascade, uv, ndc.z);
+    }
+    return textureSampleCompareLevel(t_shadow, s_shadow, uv, cascade, ndc.z);
+}
+
+fn cascade_index(view_depth: f32) -> u32 {
+    for (var i = 0u; i < CASCADE_COUNT; i += 1u) {
+        if view_depth < shadow.splits[i] {
+            return i;
+        }
+    }
+    return CASCADE_COUNT;
+}
+
+// Without ENABLE_SHADOWS every light is treated as unoccluded and the
+// shadow maps are never sampled.
+fn shadow_factor(world_position: vec3<f32>, view_depth: f32) -> f32 {
+#ifndef ENABLE_SHADOWS
+    return 1.0;
+#else
+    let cascade = cascade_index(view_depth);
+    if cascade >= CASCADE_COUNT {
+        return 1.0;
+    }
+
+    let lit = sample_cascade(cascade, world_position);
+    let far = shadow.splits[cascade];
+    var near = 0.0;
+    if cascade > 0u {
+        near = shadow.splits[cascade - 1u];
+    }
+    let blend_start = far - (far - near) * CASCADE_BLEND;
+    if view_depth <= blend_start {
+        return lit;
+    }
+
+    var next = 1.0;
+    if cascade + 1u < CASCADE_COUNT {
+        next = sample_cascade(cascade + 1u, world_position);
+    }
+    return mix(lit, next, (view_depth - blend_start) / (far - blend_start));
+#endif
+}
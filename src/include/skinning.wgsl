@@ -0,0 +1,29 @@
+// Skinning against a `JointPalette`, compiled in with SKINNED. Define
+// SKIN_GROUP and SKIN_BINDING to where the including pass binds the
+// palette first.
+#ifdef SKINNED
+// Matches `SkinVertex`.
+struct SkinInput {
+    @location(12) joints: vec4<u32>,
+    @location(13) weights: vec4<f32>,
+}
+
+@group(SKIN_GROUP) @binding(SKIN_BINDING)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+fn skin_matrix(skin: SkinInput) -> mat4x4<f32> {
+    return joint_matrices[skin.joints.x] * skin.weights.x
+        + joint_matrices[skin.joints.y] * skin.weights.y
+        + joint_matrices[skin.joints.z] * skin.weights.z
+        + joint_matrices[skin.joints.w] * skin.weights.w;
+}
+
+fn skin_position(skinning: mat4x4<f32>, position: vec3<f32>) -> vec3<f32> {
+    return (skinning * vec4<f32>(position, 1.0)).xyz;
+}
+
+// Exact for rotations and uniform scale, which is what rigs use.
+fn skin_direction(skinning: mat4x4<f32>, direction: vec3<f32>) -> vec3<f32> {
+    return mat3x3<f32>(skinning[0].xyz, skinning[1].xyz, skinning[2].xyz) * direction;
+}
+#endif
@@ -0,0 +1,58 @@
+// Group 0 for terrain.rs: up to four tiling layers blended by a splat map
+// stretched once over the whole terrain.
+
+@group(0) @binding(0)
+var t_splat: texture_2d<f32>;
+@group(0) @binding(1)
+var s_splat: sampler;
+@group(0) @binding(2)
+var t_layer_0: texture_2d<f32>;
+@group(0) @binding(3)
+var t_layer_1: texture_2d<f32>;
+@group(0) @binding(4)
+var t_layer_2: texture_2d<f32>;
+@group(0) @binding(5)
+var t_layer_3: texture_2d<f32>;
+@group(0) @binding(6)
+var s_layer: sampler;
+
+struct TerrainUniform {
+    layer_roughness: vec4<f32>,
+    // Layer repeats across the terrain.
+    tiling: f32,
+}
+@group(0) @binding(7)
+var<uniform> terrain: TerrainUniform;
+
+// Splat weights normalized to sum to one; all zero falls back to layer 0.
+fn splat_weights(in: VertexOutput) -> vec4<f32> {
+    let weights = textureSample(t_splat, s_splat, in.tex_coords);
+    let total = dot(weights, vec4<f32>(1.0));
+    if total < 1e-4 {
+        return vec4<f32>(1.0, 0.0, 0.0, 0.0);
+    }
+    return weights / total;
+}
+
+fn base_color(in: VertexOutput) -> vec4<f32> {
+    let weights = splat_weights(in);
+    let uv = in.tex_coords * terrain.tiling;
+    let color = textureSample(t_layer_0, s_layer, uv) * weights.r
+        + textureSample(t_layer_1, s_layer, uv) * weights.g
+        + textureSample(t_layer_2, s_layer, uv) * weights.b
+        + textureSample(t_layer_3, s_layer, uv) * weights.a;
+    return vec4<f32>(color.rgb, 1.0);
+}
+
+fn material_surface(in: VertexOutput) -> Surface {
+    var surface: Surface;
+    surface.albedo = base_color(in).rgb;
+    surface.normal = normalize(in.world_normal);
+    surface.view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    surface.metallic = 0.0;
+    surface.roughness = clamp(dot(splat_weights(in), terrain.layer_roughness), 0.04, 1.0);
+    surface.f0 = vec3<f32>(0.04);
+    surface.occlusion = 1.0;
+    surface.emissive = vec3<f32>(0.0);
+    return surface;
+}
@@ -0,0 +1,57 @@
+// Model vertices in either `model::VertexEncoding`: a `QuantizedVertex`
+// with QUANTIZED, otherwise a `ModelVertex`. Passes that only need the
+// position can read it as a vec3 at location 0 from both.
+#ifdef QUANTIZED
+struct ModelVertexInput {
+    // Half floats, with the tangent frame's handedness in w.
+    @location(0) position: vec4<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    // The normal, then the tangent, octahedral-encoded.
+    @location(2) normal_tangent: vec4<f32>,
+}
+#else
+struct ModelVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+}
+#endif
+
+struct ModelAttributes {
+    position: vec3<f32>,
+    tex_coords: vec2<f32>,
+    normal: vec3<f32>,
+    tangent: vec3<f32>,
+    bitangent: vec3<f32>,
+}
+
+// Inverse of `octahedral_encode` in model.rs, from unorm components.
+fn unpack_octahedral(packed: vec2<f32>) -> vec3<f32> {
+    let e = packed * 2.0 - 1.0;
+    var n = vec3<f32>(e, 1.0 - abs(e.x) - abs(e.y));
+    let t = max(-n.z, 0.0);
+    n.x += select(t, -t, n.x >= 0.0);
+    n.y += select(t, -t, n.y >= 0.0);
+    return normalize(n);
+}
+
+fn model_attributes(model: ModelVertexInput) -> ModelAttributes {
+    var attributes: ModelAttributes;
+    attributes.tex_coords = model.tex_coords;
+#ifdef QUANTIZED
+    let handedness = model.position.w;
+    attributes.position = model.position.xyz;
+    attributes.normal = unpack_octahedral(model.normal_tangent.xy);
+    // A handedness of 0 marks a vertex without a tangent.
+    attributes.tangent = unpack_octahedral(model.normal_tangent.zw) * abs(handedness);
+    attributes.bitangent = cross(attributes.normal, attributes.tangent) * handedness;
+#else
+    attributes.position = model.position;
+    attributes.normal = model.normal;
+    attributes.tangent = model.tangent;
+    attributes.bitangent = model.bitangent;
+#endif
+    return attributes;
+}
@@ -0,0 +1,456 @@
+use std::fmt;
+
+use winit::event::{MouseButton, MouseScrollDelta, TouchPhase};
+use winit::keyboard::KeyCode;
+
+/// How many updates of a held key one line of mouse wheel is worth.
+const WHEEL_UPDATES: f32 = 10.0;
+/// How many updates of a held key one pixel of cursor movement is worth.
+const CURSOR_UPDATES: f32 = 1.0;
+/// Pixels of a touchpad's scroll taken as one line of mouse wheel.
+const PIXELS_PER_LINE: f32 = 20.0;
+/// How many updates of a held key one pixel of one-finger drag turns the
+/// camera by.
+const TOUCH_ROTATE_UPDATES: f32 = 1.0;
+/// How many updates of a held key one pixel of two-finger drag moves the
+/// camera by.
+const TOUCH_PAN_UPDATES: f32 = 1.0;
+/// How many updates of a held key spreading two fingers one pixel further
+/// apart moves the camera forward by.
+const TOUCH_PINCH_UPDATES: f32 = 2.0;
+
+/// Something the camera controller can be told to do, whatever input
+/// asks for it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    RotateLeft,
+    RotateRight,
+    RotateUp,
+    RotateDown,
+}
+
+impl Action {
+    /// Every action, in the order `Action as usize` indexes them.
+    pub const ALL: [Action; 10] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::RotateUp,
+        Action::RotateDown,
+    ];
+
+    /// The action's key in a config file's `[input]` table.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveForward => "move_forward",
+            Action::MoveBackward => "move_backward",
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::MoveUp => "move_up",
+            Action::MoveDown => "move_down",
+            Action::RotateLeft => "rotate_left",
+            Action::RotateRight => "rotate_right",
+            Action::RotateUp => "rotate_up",
+            Action::RotateDown => "rotate_down",
+        }
+    }
+}
+
+/// One direction of the mouse wheel or cursor. Unlike keys and buttons
+/// these are never held; each movement nudges its actions once, by how
+/// far it went.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Axis {
+    WheelUp,
+    WheelDown,
+    CursorLeft,
+    CursorRight,
+    CursorUp,
+    CursorDown,
+}
+
+impl Axis {
+    const ALL: [Axis; 6] = [
+        Axis::WheelUp,
+        Axis::WheelDown,
+        Axis::CursorLeft,
+        Axis::CursorRight,
+        Axis::CursorUp,
+        Axis::CursorDown,
+    ];
+
+    /// The nudges a wheel `delta` gives, in updates of a held key.
+    pub(crate) fn wheel(delta: MouseScrollDelta) -> [(Axis, f32); 2] {
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, y) => y,
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+        };
+        [
+            (Axis::WheelUp, lines.max(0.0) * WHEEL_UPDATES),
+            (Axis::WheelDown, (-lines).max(0.0) * WHEEL_UPDATES),
+        ]
+    }
+
+    /// The nudges moving the cursor by `delta` pixels gives, in updates of
+    /// a held key.
+    pub(crate) fn cursor(delta: [f32; 2]) -> [(Axis, f32); 4] {
+        let [x, y] = delta.map(|side| side * CURSOR_UPDATES);
+        [
+            (Axis::CursorLeft, (-x).max(0.0)),
+            (Axis::CursorRight, x.max(0.0)),
+            (Axis::CursorUp, (-y).max(0.0)),
+            (Axis::CursorDown, y.max(0.0)),
+        ]
+    }
+}
+
+/// A physical input an action can be bound to. Keys are by position, so
+/// the defaults sit in the same place on any layout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Axis(Axis),
+}
+
+impl Input {
+    /// `KeyCode` names such as "KeyW" or "ArrowLeft", or a bare letter or
+    /// digit; "MouseLeft", "MouseRight", "MouseMiddle", "MouseBack" or
+    /// "MouseForward", or "Mouse" and the number of another button; or an
+    /// `Axis` name such as "WheelUp".
+    pub fn parse(name: &str) -> Option<Self> {
+        let button = match name {
+            "MouseLeft" => Some(MouseButton::Left),
+            "MouseRight" => Some(MouseButton::Right),
+            "MouseMiddle" => Some(MouseButton::Middle),
+            "MouseBack" => Some(MouseButton::Back),
+            "MouseForward" => Some(MouseButton::Forward),
+            _ => name
+                .strip_prefix("Mouse")
+                .and_then(|button| button.parse().ok())
+                .map(MouseButton::Other),
+        };
+        button
+            .map(Input::Mouse)
+            .or_else(|| {
+                Axis::ALL
+                    .into_iter()
+                    .find(|axis| format!("{:?}", axis) == name)
+                    .map(Input::Axis)
+            })
+            .or_else(|| parse_key(name).map(Input::Key))
+    }
+}
+
+/// The name `Input::parse` reads back.
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Key(code) => write!(f, "{:?}", code),
+            Input::Mouse(MouseButton::Other(button)) => write!(f, "Mouse{}", button),
+            Input::Mouse(button) => write!(f, "Mouse{:?}", button),
+            Input::Axis(axis) => write!(f, "{:?}", axis),
+        }
+    }
+}
+
+/// Turns touches into camera nudges: dragging one finger turns the view
+/// as if grabbing the scene, dragging two pans it the same way, and
+/// pinching them dollies in and out.
+#[derive(Debug, Default)]
+pub(crate) struct TouchGestures {
+    // Fingers down, by id, with where each was last seen.
+    touches: Vec<(u64, [f32; 2])>,
+}
+
+impl TouchGestures {
+    /// The actions finger `id` reaching `phase` at `location` nudges, and
+    /// by how many updates' worth.
+    pub(crate) fn handle(
+        &mut self,
+        id: u64,
+        phase: TouchPhase,
+        location: [f32; 2],
+    ) -> Vec<(Action, f32)> {
+        let index = self.touches.iter().position(|&(touch, _)| touch == id);
+        match (phase, index) {
+            (TouchPhase::Started, _) => {
+                if let Some(index) = index {
+                    self.touches.remove(index);
+                }
+                self.touches.push((id, location));
+                Vec::new()
+            }
+            (TouchPhase::Moved, Some(index)) => {
+                let before = self.touches.clone();
+                self.touches[index].1 = location;
+                match (before.as_slice(), self.touches.as_slice()) {
+                    ([(_, from)], [(_, to)]) => {
+                        let [x, y] = [to[0] - from[0], to[1] - from[1]]
+                            .map(|side| side * TOUCH_ROTATE_UPDATES);
+                        [
+                            split(x, Action::RotateRight, Action::RotateLeft),
+                            split(y, Action::RotateDown, Action::RotateUp),
+                        ]
+                        .into_iter()
+                        .flatten()
+                        .collect()
+                    }
+                    ([(_, a), (_, b)], [(_, c), (_, d)]) => {
+                        let centre =
+                            |a: &[f32; 2], b: &[f32; 2]| [(a[0] + b[0]) * 0.5, (a[1] + b[1]) * 0.5];
+                        let spread = |a: &[f32; 2], b: &[f32; 2]| (a[0] - b[0]).hypot(a[1] - b[1]);
+                        let [from, to] = [centre(a, b), centre(c, d)];
+                        let [x, y] =
+                            [to[0] - from[0], to[1] - from[1]].map(|side| side * TOUCH_PAN_UPDATES);
+                        let pinch = (spread(c, d) - spread(a, b)) * TOUCH_PINCH_UPDATES;
+                        [
+                            split(x, Action::MoveRight, Action::MoveLeft),
+                            split(y, Action::MoveDown, Action::MoveUp),
+                            split(pinch, Action::MoveBackward, Action::MoveForward),
+                        ]
+                        .into_iter()
+                        .flatten()
+                        .collect()
+                    }
+                    // Three or more fingers do nothing.
+                    _ => Vec::new(),
+                }
+            }
+            (TouchPhase::Ended | TouchPhase::Cancelled, Some(index)) => {
+                self.touches.remove(index);
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// `amount` as a nudge to `negative` if below zero or `positive` if above.
+fn split(amount: f32, negative: Action, positive: Action) -> Option<(Action, f32)> {
+    match amount {
+        amount if amount < 0.0 => Some((negative, -amount)),
+        amount if amount > 0.0 => Some((positive, amount)),
+        _ => None,
+    }
+}
+
+/// Stick deflection below which a gamepad's stick counts as centred, so
+/// a worn stick doesn't drift the camera.
+const STICK_DEAD_ZONE: f32 = 0.15;
+/// How much faster a fully pulled right trigger moves the camera.
+const MAX_SPEED_UP: f32 = 4.0;
+/// How much slower a fully pulled left trigger moves the camera.
+const MAX_SLOW_DOWN: f32 = 4.0;
+
+/// Where a gamepad's sticks and triggers are, as `gamepad::Gamepads`
+/// polls them with the `gamepad` feature. The left stick moves the
+/// camera and the right one turns it, on top of whatever the keyboard
+/// and mouse are doing; the right trigger speeds both up and the left
+/// one slows them down.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct GamepadState {
+    /// X right and Y up, each from -1 to 1.
+    pub left_stick: [f32; 2],
+    pub right_stick: [f32; 2],
+    /// From 0 released to 1 fully pulled.
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+}
+
+impl GamepadState {
+    /// How far the sticks hold `action` down, from 0 to 1.
+    pub(crate) fn amount(&self, action: Action) -> f32 {
+        let [left_x, left_y] = dead_zone(self.left_stick);
+        let [right_x, right_y] = dead_zone(self.right_stick);
+        match action {
+            Action::MoveForward => left_y.max(0.0),
+            Action::MoveBackward => (-left_y).max(0.0),
+            Action::MoveLeft => (-left_x).max(0.0),
+            Action::MoveRight => left_x.max(0.0),
+            Action::MoveUp | Action::MoveDown => 0.0,
+            Action::RotateLeft => (-right_x).max(0.0),
+            Action::RotateRight => right_x.max(0.0),
+            Action::RotateUp => right_y.max(0.0),
+            Action::RotateDown => (-right_y).max(0.0),
+        }
+    }
+
+    /// What the triggers multiply the controller's speed by.
+    pub(crate) fn speed_scale(&self) -> f32 {
+        let faster = 1.0 + (MAX_SPEED_UP - 1.0) * self.right_trigger.clamp(0.0, 1.0);
+        let slower = 1.0 + (MAX_SLOW_DOWN - 1.0) * self.left_trigger.clamp(0.0, 1.0);
+        faster / slower
+    }
+}
+
+/// `stick` with the dead zone cut out and the rest stretched back to the
+/// full range, keeping its direction.
+fn dead_zone(stick: [f32; 2]) -> [f32; 2] {
+    let [x, y] = stick;
+    let length = x.hypot(y);
+    if length <= STICK_DEAD_ZONE {
+        return [0.0; 2];
+    }
+    let scale = ((length - STICK_DEAD_ZONE) / (1.0 - STICK_DEAD_ZONE)).min(1.0) / length;
+    [x * scale, y * scale]
+}
+
+/// Which inputs trigger each action. Any number of inputs may share an
+/// action, and one input may trigger several.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputMap {
+    // Indexed by `Action as usize`.
+    bindings: [Vec<Input>; Action::ALL.len()],
+}
+
+impl Default for InputMap {
+    /// WASD to move, E and Q to rise and sink, and the arrow keys to turn.
+    fn default() -> Self {
+        let mut map = Self {
+            bindings: Default::default(),
+        };
+        for (action, key) in [
+            (Action::MoveForward, KeyCode::KeyW),
+            (Action::MoveBackward, KeyCode::KeyS),
+            (Action::MoveLeft, KeyCode::KeyA),
+            (Action::MoveRight, KeyCode::KeyD),
+            (Action::MoveUp, KeyCode::KeyE),
+            (Action::MoveDown, KeyCode::KeyQ),
+            (Action::RotateLeft, KeyCode::ArrowLeft),
+            (Action::RotateRight, KeyCode::ArrowRight),
+            (Action::RotateUp, KeyCode::ArrowUp),
+            (Action::RotateDown, KeyCode::ArrowDown),
+        ] {
+            map.bind(action, vec![Input::Key(key)]);
+        }
+        map
+    }
+}
+
+impl InputMap {
+    pub fn bindings(&self, action: Action) -> &[Input] {
+        &self.bindings[action as usize]
+    }
+
+    /// Replaces whatever triggered `action` with `inputs`.
+    pub fn bind(&mut self, action: Action, inputs: Vec<Input>) {
+        self.bindings[action as usize] = inputs;
+    }
+
+    /// The actions `input` triggers.
+    pub fn actions(&self, input: Input) -> impl Iterator<Item = Action> + '_ {
+        Action::ALL
+            .into_iter()
+            .filter(move |&action| self.bindings(action).contains(&input))
+    }
+}
+
+/// Keys that can be bound, matched by their `Debug` names.
+const BINDABLE_KEYS: &[KeyCode] = &[
+    KeyCode::KeyA,
+    KeyCode::KeyB,
+    KeyCode::KeyC,
+    KeyCode::KeyD,
+    KeyCode::KeyE,
+    KeyCode::KeyF,
+    KeyCode::KeyG,
+    KeyCode::KeyH,
+    KeyCode::KeyI,
+    KeyCode::KeyJ,
+    KeyCode::KeyK,
+    KeyCode::KeyL,
+    KeyCode::KeyM,
+    KeyCode::KeyN,
+    KeyCode::KeyO,
+    KeyCode::KeyP,
+    KeyCode::KeyQ,
+    KeyCode::KeyR,
+    KeyCode::KeyS,
+    KeyCode::KeyT,
+    KeyCode::KeyU,
+    KeyCode::KeyV,
+    KeyCode::KeyW,
+    KeyCode::KeyX,
+    KeyCode::KeyY,
+    KeyCode::KeyZ,
+    KeyCode::Digit0,
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::Escape,
+    KeyCode::Enter,
+    KeyCode::Space,
+    KeyCode::Tab,
+    KeyCode::Backspace,
+    KeyCode::Backquote,
+    KeyCode::Minus,
+    KeyCode::Equal,
+    KeyCode::BracketLeft,
+    KeyCode::BracketRight,
+    KeyCode::Backslash,
+    KeyCode::Semicolon,
+    KeyCode::Quote,
+    KeyCode::Comma,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Insert,
+    KeyCode::Delete,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::ArrowUp,
+    KeyCode::ArrowDown,
+    KeyCode::ArrowLeft,
+    KeyCode::ArrowRight,
+    KeyCode::ShiftLeft,
+    KeyCode::ShiftRight,
+    KeyCode::ControlLeft,
+    KeyCode::ControlRight,
+    KeyCode::AltLeft,
+    KeyCode::AltRight,
+];
+
+/// `KeyCode` names such as "KeyG", "F1" or "Escape", or a bare letter or
+/// digit.
+pub(crate) fn parse_key(name: &str) -> Option<KeyCode> {
+    let name = match name.len() {
+        1 if name.as_bytes()[0].is_ascii_alphabetic() => format!("Key{}", name.to_uppercase()),
+        1 if name.as_bytes()[0].is_ascii_digit() => format!("Digit{}", name),
+        _ => name.to_string(),
+    };
+    BINDABLE_KEYS
+        .iter()
+        .copied()
+        .find(|key| format!("{:?}", key) == name)
+}
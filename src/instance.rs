@@ -1,55 +1,250 @@
-use std::mem;
+use std::ops::Range;
 
+use crate::animation::Transform;
+use crate::staging::UploadBelt;
+
+#[derive(Debug, Copy, Clone)]
 pub struct Instance {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     pub model: [[f32; 4]; 4],
+    /// The normal matrix's columns, padded as WGSL lays out a `mat3x3`.
+    /// The last one's padding is the instance's `fade`.
+    pub normal: [[f32; 4]; 3],
+    /// Multiplies the base color of each material the instance is drawn
+    /// with, as an `ecs::Material` asks.
+    pub color: [f32; 4],
 }
 
 impl Instance {
+    pub fn new(position: cgmath::Vector3<f32>, rotation: cgmath::Quaternion<f32>) -> Self {
+        Self {
+            position,
+            rotation,
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
-        let model =
-            cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation);
-        InstanceRaw {
+        let model = cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
+        // Inverse-transpose of rotation * scale, which for a pure rotation is
+        // the rotation itself, leaving only the reciprocal scale.
+        let inv_scale = cgmath::Matrix3::new(
+            1.0 / self.scale.x,
+            0.0,
+            0.0,
+            0.0,
+            1.0 / self.scale.y,
+            0.0,
+            0.0,
+            0.0,
+            1.0 / self.scale.z,
+        );
+        let normal = cgmath::Matrix3::from(self.rotation) * inv_scale;
+        let mut raw = InstanceRaw {
             model: model.into(),
-        }
+            normal: [normal.x, normal.y, normal.z].map(|column| column.extend(0.0).into()),
+            color: [1.0; 4],
+        };
+        raw.set_fade(1.0);
+        raw
     }
 }
 
 impl InstanceRaw {
-    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
-
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: (mem::size_of::<[f32; 4]>() * 2) as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: (mem::size_of::<[f32; 4]>() * 3) as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-            ],
+    /// How much of the instance `LodInstances` draws at a level of detail
+    /// it is crossfading to or from, as the fraction of its pixels kept by
+    /// include/instance.wgsl's `lod_dithered`; 1 draws all of them. A
+    /// negative fade `f - 1` keeps exactly the pixels `f` drops, so the
+    /// two levels of a crossfade fill the instance between them.
+    pub fn fade(&self) -> f32 {
+        self.normal[2][3]
+    }
+
+    pub fn set_fade(&mut self, fade: f32) {
+        self.normal[2][3] = fade;
+    }
+}
+
+impl From<Transform> for Instance {
+    fn from(transform: Transform) -> Self {
+        Self {
+            position: transform.translation,
+            rotation: transform.rotation,
+            scale: transform.scale,
+        }
+    }
+}
+
+impl From<Instance> for Transform {
+    fn from(instance: Instance) -> Self {
+        Self {
+            translation: instance.position,
+            rotation: instance.rotation,
+            scale: instance.scale,
+        }
+    }
+}
+
+crate::impl_vertex!(InstanceRaw, Instance {
+    model => 5..=8: Float32x4,
+    normal => 9..=11: Float32x4,
+    color => 15: Float32x4,
+});
+
+/// CPU-side instance list mirrored into a growable GPU vertex buffer.
+///
+/// Edits only mark a dirty range; nothing touches the GPU until `upload`,
+/// which writes just that range (or reallocates when capacity runs out).
+pub struct InstanceBuffer {
+    instances: Vec<Instance>,
+    raw: Vec<InstanceRaw>,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    dirty: Option<Range<usize>>,
+}
+
+impl InstanceBuffer {
+    const MIN_CAPACITY: usize = 64;
+
+    pub fn new(device: &wgpu::Device, instances: Vec<Instance>) -> Self {
+        let raw = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let capacity = raw.len().next_power_of_two().max(Self::MIN_CAPACITY);
+        let buffer = Self::create_buffer(device, capacity);
+        let dirty = if raw.is_empty() {
+            None
+        } else {
+            Some(0..raw.len())
+        };
+
+        Self {
+            instances,
+            raw,
+            buffer,
+            capacity,
+            dirty,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            // Storage so `GpuCulling` can read it.
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    /// What `upload` writes, one per instance.
+    pub fn raw(&self) -> &[InstanceRaw] {
+        &self.raw
+    }
+
+    pub fn push(&mut self, instance: Instance) -> usize {
+        let index = self.instances.len();
+        self.raw.push(instance.to_raw());
+        self.instances.push(instance);
+        self.mark_dirty(index..index + 1);
+        index
+    }
+
+    pub fn extend(&mut self, instances: impl IntoIterator<Item = Instance>) -> Range<usize> {
+        let start = self.instances.len();
+        for instance in instances {
+            self.raw.push(instance.to_raw());
+            self.instances.push(instance);
+        }
+        let end = self.instances.len();
+        if end > start {
+            self.mark_dirty(start..end);
         }
+        start..end
+    }
+
+    /// Moves the instance at `index`, keeping its color.
+    pub fn set(&mut self, index: usize, instance: Instance) {
+        self.raw[index] = InstanceRaw {
+            color: self.raw[index].color,
+            ..instance.to_raw()
+        };
+        self.instances[index] = instance;
+        self.mark_dirty(index..index + 1);
+    }
+
+    /// Sets what the instance at `index` multiplies base colors by, white
+    /// until changed.
+    pub fn set_color(&mut self, index: usize, color: [f32; 4]) {
+        self.raw[index].color = color;
+        self.mark_dirty(index..index + 1);
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.raw.clear();
+        self.dirty = None;
+    }
+
+    /// Flushes pending edits, growing the GPU buffer if it is too small.
+    pub fn upload(&mut self, device: &wgpu::Device, uploads: &mut UploadBelt) {
+        if self.raw.len() > self.capacity {
+            self.capacity = self.raw.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+            self.dirty = Some(0..self.raw.len());
+        }
+
+        if let Some(dirty) = self.dirty.take() {
+            let offset = (dirty.start * size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+            uploads.write(
+                device,
+                &self.buffer,
+                offset,
+                bytemuck::cast_slice(&self.raw[dirty]),
+            );
+        }
+    }
+
+    /// Instances the GPU buffer holds before it has to grow.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// The occupied part of the buffer; only valid when not empty.
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        let size = (self.raw.len() * size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+        self.buffer.slice(..size)
     }
 }
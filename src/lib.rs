@@ -1,420 +1,434 @@
 extern crate alloc;
 
-mod camera;
-mod instance;
-mod model;
-mod resources;
-mod texture;
-
-use bytemuck::{Pod, Zeroable};
-use cgmath::prelude::*;
+pub mod adapter;
+pub mod animation;
+pub mod assets;
+pub mod atlas;
+pub mod auto_exposure;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bench;
+pub mod billboard;
+pub mod blur;
+pub mod bookmarks;
+pub mod camera;
+pub mod camera_effects;
+pub mod capture;
+pub mod cluster;
+pub mod color;
+pub mod compressed;
+pub mod config;
+pub mod culling;
+pub mod debug_draw;
+pub mod debug_ui;
+pub mod decal;
+pub mod deferred;
+pub mod ecs;
+pub mod editor;
+pub mod environment;
+pub mod flythrough;
+pub mod frame_trace;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
+pub mod gltf_loader;
+pub mod gpu_culling;
+pub mod grid;
+pub mod hdr;
+pub mod hiz;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod hot_reload;
+pub mod input;
+pub mod instance;
+pub mod light;
+pub mod lod;
+pub mod material;
+pub mod material_arrays;
+pub mod mesh;
+pub mod model;
+pub mod msaa;
+pub mod oit;
+pub mod outline;
+pub mod parallel;
+pub mod picking;
+pub mod pipeline_cache;
+pub mod post;
+pub mod preprocess;
+pub mod probe;
+pub mod profiler;
+pub mod render_target;
+pub mod replay;
+pub mod resources;
+#[cfg(feature = "physics")]
+pub mod rigid_body;
+pub mod scene;
+pub mod scene_file;
+pub mod secondary_window;
+pub mod shadow;
+pub mod sky;
+pub mod skybox;
+pub mod sprite;
+pub mod ssao;
+pub mod ssr;
+pub mod staging;
+pub mod state;
+pub mod static_bundles;
+pub mod terrain;
+pub mod text;
+pub mod texture;
+pub mod timing;
+pub mod uniforms;
+pub mod vertex;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod video;
+pub mod viewport;
+pub mod volumetric;
+pub mod water;
+
 use std::sync::Arc;
-use wgpu::util::DeviceExt;
 use winit::{
     application::ApplicationHandler,
     event::*,
     event_loop::{ActiveEventLoop, EventLoop},
-    keyboard::{KeyCode, PhysicalKey},
-    window::Window,
+    keyboard::PhysicalKey,
+    window::{Fullscreen, Window},
 };
 
-use crate::camera::{Camera, CameraController, CameraUniform};
-use crate::instance::{Instance, InstanceRaw};
-use crate::model::Vertex;
-use crate::model::{DrawModel, ModelVertex};
+pub use crate::adapter::AdapterOptions;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::bench::Benchmark;
+pub use crate::bookmarks::CameraBookmarks;
+pub use crate::config::Config;
+pub use crate::deferred::RenderPath;
+pub use crate::flythrough::CameraPath;
+pub use crate::replay::InputRecording;
+pub use crate::scene_file::Scene;
+pub use crate::secondary_window::{DebugTexture, WindowContent};
+pub use crate::state::State;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::video::{VideoCapture, VideoOutput};
+
+use crate::replay::InputEvent;
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 use winit::dpi::PhysicalSize;
 
-// This will store the state of our game
-pub struct State {
-    surface: wgpu::Surface<'static>,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
-    is_surface_configured: bool,
-    render_pipeline: wgpu::RenderPipeline,
-    diffuse_bind_group: wgpu::BindGroup,
-    diffuse_texture: texture::Texture,
-    camera: Camera,
-    camera_uniform: CameraUniform,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
-    camera_controller: CameraController,
-    instances: Vec<Instance>,
-    instance_buffer: wgpu::Buffer,
-    depth_texture: texture::Texture,
-    obj_model: model::Model,
-    window: Arc<Window>,
+pub struct App {
+    #[cfg(target_arch = "wasm32")]
+    proxy: Option<winit::event_loop::EventLoopProxy<State>>,
+    state: Option<State>,
+    render_path: RenderPath,
+    adapter_options: AdapterOptions,
+    config: Config,
+    flythrough: Option<CameraPath>,
+    scene: Option<Scene>,
+    // How many boxes `rigid_body::drop_stack` drops, if any.
+    #[cfg(feature = "physics")]
+    dropped_stack: Option<usize>,
+    // None if gilrs couldn't be set up.
+    #[cfg(feature = "gamepad")]
+    gamepads: Option<gamepad::Gamepads>,
+    replay: Option<InputRecording>,
+    // Where `config` is written back on exit, if anywhere.
+    #[cfg(not(target_arch = "wasm32"))]
+    config_path: Option<std::path::PathBuf>,
+    // Where the session's input is saved on exit, if recording.
+    #[cfg(not(target_arch = "wasm32"))]
+    record_path: Option<std::path::PathBuf>,
+    // Where each frame's spans and errors are written, if anywhere.
+    #[cfg(not(target_arch = "wasm32"))]
+    frame_log_directory: Option<std::path::PathBuf>,
+    // Measuring the frames, and reporting on them on exit, if benchmarking.
+    #[cfg(not(target_arch = "wasm32"))]
+    benchmark: Option<Benchmark>,
+    #[cfg(not(target_arch = "wasm32"))]
+    video: Option<VideoCapture>,
+    // What each window opened beside the main one shows.
+    #[cfg(not(target_arch = "wasm32"))]
+    windows: Vec<WindowContent>,
 }
 
-const NUM_INSTANCES_PER_ROW: u32 = 10;
-const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(
-    NUM_INSTANCES_PER_ROW as f32 * 0.5,
-    0.0,
-    NUM_INSTANCES_PER_ROW as f32 * 0.5,
-);
-
-impl State {
-    // We don't need this to be async right now,
-    // but we will in the next tutorial
-    pub async fn new(window: Arc<Window>) -> anyhow::Result<Self> {
-        let size = window.inner_size();
+// The browser's needs an event loop to send the state through.
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+impl App {
+    pub fn new(#[cfg(target_arch = "wasm32")] event_loop: &EventLoop<State>) -> Self {
+        #[cfg(target_arch = "wasm32")]
+        let proxy = Some(event_loop.create_proxy());
+        Self {
+            state: None,
+            render_path: RenderPath::default(),
+            adapter_options: AdapterOptions::default(),
+            config: Config::default(),
+            flythrough: None,
+            scene: None,
+            #[cfg(feature = "physics")]
+            dropped_stack: None,
+            #[cfg(feature = "gamepad")]
+            gamepads: gamepad::Gamepads::new()
+                .inspect_err(|e| log::error!("{:#}", e))
+                .ok(),
+            replay: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            config_path: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            record_path: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            frame_log_directory: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            benchmark: None,
             #[cfg(not(target_arch = "wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
+            video: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            windows: Vec::new(),
             #[cfg(target_arch = "wasm32")]
-            backends: wgpu::Backends::GL,
-            ..Default::default()
-        });
-        let surface = instance.create_surface(window.clone())?;
-
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await?;
-
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::empty(),
-                experimental_features: wgpu::ExperimentalFeatures::disabled(),
-                required_limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
-                },
-                memory_hints: Default::default(),
-                trace: wgpu::Trace::Off,
-            })
-            .await?;
-        let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .find(|f| f.is_srgb())
-            .copied()
-            .unwrap_or(surface_caps.formats[0]);
-
-        const SPACE_BETWEEN: f32 = 3.0;
-        let instances = (0..NUM_INSTANCES_PER_ROW)
-            .flat_map(|z| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-
-                    let position = cgmath::Vector3 { x, y: 0.0, z };
-                    let rotation = if position.is_zero() {
-                        cgmath::Quaternion::from_axis_angle(
-                            cgmath::Vector3::unit_z(),
-                            cgmath::Deg(0.0),
-                        )
-                    } else {
-                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                    };
-                    Instance { position, rotation }
-                })
-            })
-            .collect::<Vec<_>>();
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
-            desired_maximum_frame_latency: 2,
-        };
+            proxy,
+        }
+    }
 
-        let diffuse_bytes = include_bytes!("happy-tree.png");
-        let diffuse_texture =
-            texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "happy-tree.png").unwrap();
-
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
-
-        let camera = Camera {
-            eye: (0.0, 1.0, 2.0).into(),
-            target: (0.0, 0.0, 0.0).into(),
-            up: cgmath::Vector3::unit_y(),
-            aspect: config.width as f32 / config.height as f32,
-            fovy: 45.0,
-            znear: 0.01,
-            zfar: 100000.0,
-        };
-        let mut camera_uniform = camera::CameraUniform::new();
-        camera_uniform.update_view_proj(&camera);
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        });
-
-        let camera_controller = CameraController::new(0.1);
-
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
-        });
-
-        let obj_model =
-            resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
-                .await
-                .unwrap();
-
-        Ok(Self {
-            surface,
-            device,
-            queue,
-            config,
-            is_surface_configured: false,
-            render_pipeline,
-            diffuse_bind_group,
-            diffuse_texture,
-            camera,
-            camera_uniform,
-            camera_buffer,
-            camera_bind_group,
-            camera_controller,
-            instances,
-            instance_buffer,
-            depth_texture,
-            obj_model,
-            window,
-        })
+    pub fn with_render_path(mut self, render_path: RenderPath) -> Self {
+        self.render_path = render_path;
+        self
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
-        if width > 0 && height > 0 {
-            self.config.width = width;
-            self.config.height = height;
-            self.surface.configure(&self.device, &self.config);
-            self.is_surface_configured = true;
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
-        }
+    pub fn with_adapter_options(mut self, adapter_options: AdapterOptions) -> Self {
+        self.adapter_options = adapter_options;
+        self
     }
 
-    pub fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
-        self.camera_uniform.update_view_proj(&self.camera);
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
+    /// Sizes the window and sets up the state from `config`. Its render
+    /// path is only a default; `with_render_path` decides.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.window.request_redraw();
+    /// Flies the camera along `path` as soon as the state is up.
+    pub fn with_flythrough(mut self, path: CameraPath) -> Self {
+        self.flythrough = Some(path);
+        self
+    }
 
-        if !self.is_surface_configured {
-            return Ok(());
-        }
+    /// Sets the state up as `scene` describes, over what the config did.
+    pub fn with_scene(mut self, scene: Scene) -> Self {
+        self.scene = Some(scene);
+        self
+    }
 
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                    depth_slice: None,
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
+    /// Drops a stack of `count` boxes onto a floor once the state is up,
+    /// replacing the instances.
+    #[cfg(feature = "physics")]
+    pub fn with_dropped_stack(mut self, count: usize) -> Self {
+        self.dropped_stack = Some(count);
+        self
+    }
 
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+    /// Replays `recording` from the first frame instead of taking live
+    /// input until it runs out.
+    pub fn with_replay(mut self, recording: InputRecording) -> Self {
+        self.replay = Some(recording);
+        self
+    }
 
-            render_pass.draw_model_instanced(
-                &self.obj_model,
-                0..self.instances.len() as u32,
-                &self.camera_bind_group,
-            );
-        }
+    /// Records the session's input and saves it to `path` on exit.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn record_to(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.record_path = Some(path.into());
+        self
+    }
+
+    /// Writes each frame's spans and validation errors to `frames.log` in
+    /// `directory`, for a bug report. This is not a wgpu API trace, which
+    /// is `AdapterOptions::trace`; see `frame_trace`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn log_frames_to(mut self, directory: impl Into<std::path::PathBuf>) -> Self {
+        self.frame_log_directory = Some(directory.into());
+        self
+    }
+
+    /// Runs `benchmark` over the frames after setup, exiting once it has
+    /// measured them all and writing its report on exit.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_benchmark(mut self, benchmark: Benchmark) -> Self {
+        self.benchmark = Some(benchmark);
+        self
+    }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    /// Writes every frame out to `video` from the first one on.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_video_capture(mut self, video: VideoCapture) -> Self {
+        self.video = Some(video);
+        self
+    }
+
+    /// Opens another window beside the main one, showing `content`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_window(mut self, content: WindowContent) -> Self {
+        self.windows.push(content);
+        self
+    }
 
+    /// Hands a newly created `state` everything the app was set up with.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn prepare(&mut self, state: &mut State) {
+        if let Some(directory) = &self.frame_log_directory
+            && let Err(e) = state.frame_trace().start_file(directory)
+        {
+            log::error!("Unable to log frames: {:#}", e);
+        }
+        pollster::block_on(self.config.apply(state));
+        if let Some(scene) = self.scene.take()
+            && let Err(e) = pollster::block_on(scene.apply(state))
+        {
+            log::error!("Unable to set up the scene: {:#}", e);
+        }
+        #[cfg(feature = "physics")]
+        if let Some(count) = self.dropped_stack.take() {
+            rigid_body::drop_stack(state, count);
+        }
+        if std::path::Path::new(bookmarks::DEFAULT_PATH).exists() {
+            match CameraBookmarks::load(bookmarks::DEFAULT_PATH) {
+                Ok(bookmarks) => state.set_camera_bookmarks(bookmarks),
+                Err(e) => log::error!("{:#}", e),
+            }
+        }
+        if let Some(path) = self.flythrough.take() {
+            state.set_camera_path(path);
+            state.play_camera_path();
+        }
+        if let Some(recording) = self.replay.take() {
+            state.start_replay(recording);
+        }
+        if self.record_path.is_some() {
+            state.start_recording();
+        }
+        if let Some(video) = self.video.take() {
+            state.start_video_capture(video);
+        }
+        if let Some(benchmark) = &mut self.benchmark {
+            benchmark.start(state);
+        }
+    }
+
+    /// Renders `frames` frames without a window, at the configured size
+    /// and with everything else the app was set up with, and writes each
+    /// to `directory` as `frame-NNNN.png`, or only to the video capture if
+    /// there is one.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run_headless(mut self, frames: u32, directory: &str) -> anyhow::Result<()> {
+        let mut state = pollster::block_on(State::headless_with_adapter(
+            self.config.window.width,
+            self.config.window.height,
+            self.render_path,
+            self.adapter_options.clone(),
+        ))?;
+        self.prepare(&mut state);
+        let write_frames = !state.is_capturing_video();
+        if write_frames {
+            std::fs::create_dir_all(directory)?;
+        }
+        for frame in 0..frames {
+            state.frame_timing_mut().begin_frame();
+            state.update();
+            if !write_frames {
+                state.render()?;
+                state.frame_timing_mut().end_frame();
+                continue;
+            }
+            let image = state.render_to_image()?;
+            state.frame_timing_mut().end_frame();
+            let path = std::path::Path::new(directory).join(format!("frame-{:04}.png", frame));
+            image.save(&path)?;
+            log::info!("Wrote {}", path.display());
+        }
+        state.stop_video_capture();
         Ok(())
     }
 
-    fn handle_key(&mut self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
-        match (code, is_pressed) {
-            (KeyCode::Escape, true) => event_loop.exit(),
-            _ => {
-                self.camera_controller.handle_key(code, is_pressed);
+    /// Runs the benchmark without a window, at the configured size, and
+    /// writes its report. Each frame waits for the GPU, so its time covers
+    /// the drawing as well as the CPU's part.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run_benchmark_headless(mut self) -> anyhow::Result<()> {
+        let mut state = pollster::block_on(State::headless_with_adapter(
+            self.config.window.width,
+            self.config.window.height,
+            self.render_path,
+            self.adapter_options.clone(),
+        ))?;
+        self.prepare(&mut state);
+        let mut benchmark = self
+            .benchmark
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("run_benchmark_headless needs with_benchmark"))?;
+        loop {
+            state.frame_timing_mut().begin_frame();
+            state.update();
+            state.render()?;
+            state.wait_for_gpu();
+            state.frame_timing_mut().end_frame();
+            if benchmark.record(&mut state) {
+                break;
             }
         }
+        benchmark.save()
     }
-}
 
-pub struct App {
-    #[cfg(target_arch = "wasm32")]
-    proxy: Option<winit::event_loop::EventLoopProxy<State>>,
-    state: Option<State>,
-}
+    /// Writes the config back to `path` on exit if anything it covers
+    /// changed while running.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_config_on_exit(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Swaps the state for one on a new device, exiting if none can be
+    /// made, and applies the config to it again, with the settings it
+    /// covers as they were on the old one. On the web the new state arrives
+    /// later as a user event.
+    fn recreate_device(
+        &mut self,
+        #[cfg_attr(target_arch = "wasm32", allow(unused_variables))] event_loop: &ActiveEventLoop,
+    ) {
+        let Some(mut state) = self.state.take() else {
+            return;
+        };
+        log::warn!("Recreating the device");
+        let mut config = self.config.clone();
+        config.update_from(&mut state);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        match pollster::block_on(state.recreate_device()) {
+            Ok(mut state) => {
+                pollster::block_on(config.apply(&mut state));
+                // Only `render` asks for the next frame, and the lost frame
+                // never got there.
+                if let Some(window) = state.window() {
+                    window.request_redraw();
+                }
+                self.state = Some(state);
+            }
+            Err(e) => {
+                log::error!("Unable to recreate the device: {:#}", e);
+                event_loop.exit();
+            }
+        }
 
-impl App {
-    pub fn new(#[cfg(target_arch = "wasm32")] event_loop: &EventLoop<State>) -> Self {
         #[cfg(target_arch = "wasm32")]
-        let proxy = Some(event_loop.create_proxy());
-        Self {
-            state: None,
-            #[cfg(target_arch = "wasm32")]
-            proxy,
+        if let Some(proxy) = self.proxy.clone() {
+            wasm_bindgen_futures::spawn_local(async move {
+                match state.recreate_device().await {
+                    Ok(mut state) => {
+                        config.apply(&mut state).await;
+                        assert!(proxy.send_event(state).is_ok());
+                    }
+                    Err(e) => log::error!("Unable to recreate the device: {:#}", e),
+                }
+            });
         }
     }
 }
@@ -424,7 +438,16 @@ impl ApplicationHandler<State> for App {
         #[allow(unused_mut)]
         let mut window_attributes = Window::default_attributes()
             .with_title("wgpu + winit example")
-            .with_inner_size(PhysicalSize::new(800, 600))
+            .with_inner_size(PhysicalSize::new(
+                self.config.window.width,
+                self.config.window.height,
+            ))
+            .with_fullscreen(
+                self.config
+                    .window
+                    .fullscreen
+                    .then_some(Fullscreen::Borderless(None)),
+            )
             .with_resizable(false);
 
         #[cfg(target_arch = "wasm32")]
@@ -447,38 +470,129 @@ impl ApplicationHandler<State> for App {
         {
             // If we are not on web we can use pollster to
             // await the
-            self.state = Some(pollster::block_on(State::new(window)).unwrap());
+            let mut state = match pollster::block_on(State::with_adapter(
+                window,
+                self.render_path,
+                self.adapter_options.clone(),
+            )) {
+                Ok(state) => state,
+                Err(e) => {
+                    log::error!("{:#}", e);
+                    event_loop.exit();
+                    return;
+                }
+            };
+            self.prepare(&mut state);
+            for &content in &self.windows {
+                let name = match content {
+                    WindowContent::Scene(_) => "scene".to_string(),
+                    WindowContent::Debug(texture) => texture.to_string(),
+                };
+                let attributes = Window::default_attributes()
+                    .with_title(format!("wgpu + winit example: {}", name))
+                    .with_inner_size(PhysicalSize::new(640, 360));
+                let added = event_loop
+                    .create_window(attributes)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|window| state.add_window(Arc::new(window), content));
+                if let Err(e) = added {
+                    log::error!("Unable to open the {} window: {:#}", name, e);
+                }
+            }
+            self.state = Some(state);
         }
 
         #[cfg(target_arch = "wasm32")]
         {
             // Run the future asynchronously and use the
-            // proxy to send the results to the event loop
-            if let Some(proxy) = self.proxy.take() {
+            // proxy to send the results to the event loop. It is kept for
+            // `recreate_device`.
+            if let Some(proxy) = self.proxy.clone() {
+                let render_path = self.render_path;
+                let adapter_options = self.adapter_options.clone();
+                let config = self.config.clone();
+                let flythrough = self.flythrough.take();
+                let scene = self.scene.take();
+                #[cfg(feature = "physics")]
+                let dropped_stack = self.dropped_stack.take();
+                let replay = self.replay.take();
                 wasm_bindgen_futures::spawn_local(async move {
-                    assert!(
-                        proxy
-                            .send_event(
-                                State::new(window)
-                                    .await
-                                    .expect("Unable to create canvas!!!")
-                            )
-                            .is_ok()
-                    )
+                    let mut state =
+                        match State::with_adapter(window, render_path, adapter_options).await {
+                            Ok(state) => state,
+                            Err(e) => {
+                                log::error!("{:#}", e);
+                                return;
+                            }
+                        };
+                    config.apply(&mut state).await;
+                    if let Some(scene) = scene
+                        && let Err(e) = scene.apply(&mut state).await
+                    {
+                        log::error!("Unable to set up the scene: {:#}", e);
+                    }
+                    #[cfg(feature = "physics")]
+                    if let Some(count) = dropped_stack {
+                        rigid_body::drop_stack(&mut state, count);
+                    }
+                    if let Some(path) = flythrough {
+                        state.set_camera_path(path);
+                        state.play_camera_path();
+                    }
+                    if let Some(recording) = replay {
+                        state.start_replay(recording);
+                    }
+                    assert!(proxy.send_event(state).is_ok())
                 });
             }
         }
     }
 
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(state) = &mut self.state {
+            state.stop_video_capture();
+        }
+        // Closing the window early still reports on what was measured.
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(benchmark) = self.benchmark.take()
+            && benchmark.measured() > 0
+            && let Err(e) = benchmark.save()
+        {
+            log::error!("{:#}", e);
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let (Some(path), Some(state)) = (&self.record_path, &mut self.state)
+            && let Some(recording) = state.stop_recording()
+        {
+            match recording.save(path) {
+                Ok(()) => log::info!(
+                    "Saved {} frames of input to {}",
+                    recording.frames.len(),
+                    path.display()
+                ),
+                Err(e) => log::error!("Unable to save {}: {:#}", path.display(), e),
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let (Some(path), Some(state)) = (&self.config_path, &mut self.state) {
+            let mut config = self.config.clone();
+            config.update_from(state);
+            if config != self.config {
+                match config.save(path) {
+                    Ok(()) => log::info!("Saved {}", path.display()),
+                    Err(e) => log::error!("Unable to save {}: {:#}", path.display(), e),
+                }
+            }
+        }
+    }
+
     #[allow(unused_mut)]
     fn user_event(&mut self, _event_loop: &ActiveEventLoop, mut event: State) {
         #[cfg(target_arch = "wasm32")]
-        {
-            event.window.request_redraw();
-            event.resize(
-                event.window.inner_size().width,
-                event.window.inner_size().height,
-            );
+        if let Some(window) = event.window().cloned() {
+            window.request_redraw();
+            event.resize(window.inner_size().width, window.inner_size().height);
         }
         self.state = Some(event);
     }
@@ -486,7 +600,7 @@ impl ApplicationHandler<State> for App {
     fn window_event(
         &mut self,
         event_loop: &ActiveEventLoop,
-        _window_id: winit::window::WindowId,
+        window_id: winit::window::WindowId,
         event: WindowEvent,
     ) {
         let state = match &mut self.state {
@@ -494,17 +608,60 @@ impl ApplicationHandler<State> for App {
             None => return,
         };
 
+        // The other windows are drawn along with the main one and take no
+        // input.
+        if state.window_content(window_id).is_some() {
+            match event {
+                WindowEvent::CloseRequested => {
+                    state.remove_window(window_id);
+                }
+                WindowEvent::Resized(size) => {
+                    state.resize_window(window_id, size.width, size.height)
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        if state.handle_window_event(&event) {
+            return;
+        }
+
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
             WindowEvent::Resized(size) => state.resize(size.width, size.height),
             WindowEvent::RedrawRequested => {
+                if state.is_device_lost() {
+                    self.recreate_device(event_loop);
+                    return;
+                }
+                state.frame_timing_mut().begin_frame();
+                #[cfg(feature = "gamepad")]
+                if let Some(gamepads) = &mut self.gamepads {
+                    state.set_gamepad(gamepads.poll());
+                }
                 state.update();
-                match state.render() {
+                let result = state.render();
+                state.frame_timing_mut().end_frame();
+                match result {
+                    #[cfg(not(target_arch = "wasm32"))]
+                    Ok(_) => {
+                        if let Some(benchmark) = &mut self.benchmark
+                            && benchmark.record(state)
+                        {
+                            event_loop.exit();
+                        }
+                    }
+                    #[cfg(target_arch = "wasm32")]
                     Ok(_) => {}
                     Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        let size = state.window.inner_size();
-                        state.resize(size.width, size.height);
+                        if let Some(size) = state.window().map(|window| window.inner_size()) {
+                            state.resize(size.width, size.height);
+                        }
                     }
+                    Err(wgpu::SurfaceError::OutOfMemory) => self.recreate_device(event_loop),
+                    // The next frame tries again.
+                    Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timed out"),
                     Err(e) => {
                         log::error!("Unable to render {}", e);
                     }
@@ -518,9 +675,40 @@ impl ApplicationHandler<State> for App {
                         ..
                     },
                 ..
-            } => state.handle_key(event_loop, code, key_state.is_pressed()),
+            } => state.handle_input_event(InputEvent::Key {
+                code,
+                pressed: key_state.is_pressed(),
+            }),
+            WindowEvent::CursorMoved { position, .. } => {
+                state.handle_input_event(InputEvent::CursorMoved {
+                    x: position.x,
+                    y: position.y,
+                })
+            }
+            WindowEvent::MouseInput {
+                state: button_state,
+                button,
+                ..
+            } => state.handle_input_event(InputEvent::MouseButton {
+                button,
+                pressed: button_state.is_pressed(),
+            }),
+            WindowEvent::MouseWheel { delta, .. } => {
+                state.handle_input_event(InputEvent::Wheel(delta))
+            }
+            WindowEvent::Touch(touch) => state.handle_input_event(InputEvent::Touch {
+                id: touch.id,
+                phase: touch.phase,
+                x: touch.location.x,
+                y: touch.location.y,
+            }),
             _ => {}
         }
+        if let Some(state) = &self.state
+            && state.exit_requested()
+        {
+            event_loop.exit();
+        }
     }
 }
 
@@ -534,12 +722,132 @@ pub fn run() -> anyhow::Result<()> {
         console_log::init_with_level(log::Level::Info).unwrap_throw();
     }
 
-    let event_loop = EventLoop::with_user_event().build()?;
-    let mut app = App::new(
-        #[cfg(target_arch = "wasm32")]
-        &event_loop,
-    );
-    event_loop.run_app(&mut app)?;
+    let args: Vec<String> = std::env::args().collect();
+    #[cfg(not(target_arch = "wasm32"))]
+    let value = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|i| args.get(i + 1))
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let config_path = value("--config").map_or(config::DEFAULT_PATH, |path| path.as_str());
+    #[cfg(not(target_arch = "wasm32"))]
+    let config = Config::load(config_path)?;
+    #[cfg(target_arch = "wasm32")]
+    let config = Config::default();
+    let render_path = args
+        .iter()
+        .find_map(|arg| match arg.as_str() {
+            "--deferred" => Some(RenderPath::Deferred),
+            "--forward-plus" => Some(RenderPath::ForwardPlus),
+            _ => None,
+        })
+        .unwrap_or(config.render.path);
+    let adapter_options = AdapterOptions::from_args(&args)?;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.iter().any(|arg| arg == "--list-adapters") {
+        let backends = if args.iter().any(|arg| arg == "--headless") {
+            adapter_options.headless_backends()
+        } else {
+            adapter_options.window_backends()
+        };
+        for (i, info) in adapter::list_adapters(backends).iter().enumerate() {
+            println!("{}: {}", i, adapter::describe(info));
+        }
+        return Ok(());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let mut app = App::new()
+            .with_render_path(render_path)
+            .with_adapter_options(adapter_options)
+            .with_config(config);
+        if let Some(path) = value("--scene") {
+            app = app.with_scene(Scene::load(path)?);
+        }
+        #[cfg(feature = "physics")]
+        if let Some(count) = value("--drop-stack") {
+            app = app.with_dropped_stack(count.parse()?);
+        }
+        if let Some(path) = value("--flythrough") {
+            app = app.with_flythrough(CameraPath::load(path)?);
+        }
+        if let Some(directory) = value("--frame-log") {
+            app = app.log_frames_to(directory);
+        }
+        let replay = value("--replay").map(InputRecording::load).transpose()?;
+        let replay_frames = replay
+            .as_ref()
+            .map(|recording| recording.frames.len() as u32);
+        if let Some(recording) = replay {
+            app = app.with_replay(recording);
+        }
+        for name in args
+            .windows(2)
+            .filter(|pair| pair[0] == "--window")
+            .map(|pair| &pair[1])
+        {
+            let texture = DebugTexture::parse(name).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No debug texture {:?}; expected albedo, normal, material, emissive, \
+                     depth, occlusion, shadow<cascade> or bloom<level>",
+                    name
+                )
+            })?;
+            app = app.with_window(WindowContent::Debug(texture));
+        }
+        if let Some(path) = value("--video") {
+            let fps = match value("--fps") {
+                Some(fps) => fps.parse()?,
+                None => 60,
+            };
+            app = app.with_video_capture(VideoCapture::new(VideoOutput::from_path(path), fps)?);
+        }
+
+        if let Some(report) = value("--bench") {
+            let frames = match value("--frames") {
+                Some(frames) => frames.parse()?,
+                None => 600,
+            };
+            if value("--flythrough").is_none()
+                && std::path::Path::new(flythrough::DEFAULT_PATH).exists()
+            {
+                app = app.with_flythrough(CameraPath::load(flythrough::DEFAULT_PATH)?);
+            }
+            app = app.with_benchmark(Benchmark::new(frames, report));
+            if args.iter().any(|arg| arg == "--headless") {
+                return app.run_benchmark_headless();
+            }
+        }
+        if args.iter().any(|arg| arg == "--headless") {
+            let frames = match (value("--frames"), replay_frames) {
+                (Some(frames), _) => frames.parse()?,
+                (None, Some(frames)) => frames,
+                (None, None) => 1,
+            };
+            let directory = value("--output").map_or("headless", |dir| dir.as_str());
+            return app.run_headless(frames, directory);
+        }
+
+        app = app.save_config_on_exit(config_path);
+        if let Some(path) = value("--record") {
+            app = app.record_to(path);
+        }
+        let event_loop = EventLoop::with_user_event().build()?;
+        event_loop.run_app(&mut app)?;
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let event_loop = EventLoop::with_user_event().build()?;
+        let mut app = App::new(&event_loop)
+            .with_render_path(render_path)
+            .with_adapter_options(adapter_options)
+            .with_config(config);
+        event_loop.run_app(&mut app)?;
+    }
 
     Ok(())
 }
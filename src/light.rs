@@ -0,0 +1,278 @@
+use wgpu::util::DeviceExt;
+
+use crate::staging::UploadBelt;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    pub position: [f32; 3],
+    pub intensity: f32,
+    pub color: [f32; 3],
+    _padding: u32,
+}
+
+impl LightUniform {
+    pub fn new(position: [f32; 3], color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            position,
+            intensity,
+            color,
+            _padding: 0,
+        }
+    }
+}
+
+/// Exponential fog over the lit scene, thinning with height above y = 0.
+#[derive(Debug, Copy, Clone)]
+pub struct FogSettings {
+    pub enabled: bool,
+    pub color: [f32; 3],
+    /// Extinction per world unit at y = 0.
+    pub density: f32,
+    /// Distance from the eye before which no fog builds up.
+    pub start: f32,
+    /// Distance at which the fog turns opaque whatever its density, so
+    /// geometry fades out before the far plane cuts it off.
+    pub end: f32,
+    /// How quickly the density falls off with height; zero keeps it even.
+    pub height_falloff: f32,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            color: [0.5, 0.6, 0.7],
+            density: 0.02,
+            start: 0.0,
+            end: 100.0,
+            height_falloff: 0.1,
+        }
+    }
+}
+
+/// Scene-wide lighting values shared by every light in the storage buffer.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightingUniform {
+    pub light_count: u32,
+    pub ambient: f32,
+    pub fog_enabled: u32,
+    _padding: u32,
+    pub fog_color: [f32; 3],
+    pub fog_density: f32,
+    pub fog_start: f32,
+    pub fog_end: f32,
+    pub fog_height_falloff: f32,
+    _fog_padding: u32,
+}
+
+impl LightingUniform {
+    pub fn new(light_count: u32, ambient: f32, fog: &FogSettings) -> Self {
+        Self {
+            light_count,
+            ambient,
+            fog_enabled: fog.enabled as u32,
+            _padding: 0,
+            fog_color: fog.color,
+            fog_density: fog.density,
+            fog_start: fog.start,
+            fog_end: fog.end,
+            fog_height_falloff: fog.height_falloff,
+            _fog_padding: 0,
+        }
+    }
+}
+
+pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX
+                    | wgpu::ShaderStages::FRAGMENT
+                    | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::VERTEX
+                    | wgpu::ShaderStages::FRAGMENT
+                    | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+        label: Some("light_bind_group_layout"),
+    })
+}
+
+/// Point lights mirrored into a growable storage buffer.
+///
+/// Growing the storage buffer invalidates the bind group, so it is rebuilt
+/// in `upload` together with the new buffer.
+pub struct LightBuffer {
+    lights: Vec<LightUniform>,
+    ambient: f32,
+    fog: FogSettings,
+    capacity: usize,
+    lighting_buffer: wgpu::Buffer,
+    storage_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    dirty: bool,
+}
+
+impl LightBuffer {
+    const MIN_CAPACITY: usize = 16;
+
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, ambient: f32) -> Self {
+        let fog = FogSettings::default();
+        let lighting_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Lighting Buffer"),
+            contents: bytemuck::cast_slice(&[LightingUniform::new(0, ambient, &fog)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let storage_buffer = Self::create_storage_buffer(device, Self::MIN_CAPACITY);
+        let bind_group = Self::create_bind_group(device, layout, &lighting_buffer, &storage_buffer);
+
+        Self {
+            lights: Vec::new(),
+            ambient,
+            fog,
+            capacity: Self::MIN_CAPACITY,
+            lighting_buffer,
+            storage_buffer,
+            bind_group,
+            dirty: true,
+        }
+    }
+
+    fn create_storage_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Storage Buffer"),
+            size: (capacity * size_of::<LightUniform>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        lighting_buffer: &wgpu::Buffer,
+        storage_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: lighting_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: storage_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("light_bind_group"),
+        })
+    }
+
+    pub fn lights(&self) -> &[LightUniform] {
+        &self.lights
+    }
+
+    pub fn push(&mut self, light: LightUniform) -> usize {
+        self.lights.push(light);
+        self.dirty = true;
+        self.lights.len() - 1
+    }
+
+    pub fn set(&mut self, index: usize, light: LightUniform) {
+        self.lights[index] = light;
+        self.dirty = true;
+    }
+
+    pub fn remove(&mut self, index: usize) -> LightUniform {
+        self.dirty = true;
+        self.lights.remove(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.lights.clear();
+        self.dirty = true;
+    }
+
+    pub fn ambient(&self) -> f32 {
+        self.ambient
+    }
+
+    pub fn set_ambient(&mut self, ambient: f32) {
+        self.ambient = ambient;
+        self.dirty = true;
+    }
+
+    pub fn fog(&self) -> FogSettings {
+        self.fog
+    }
+
+    pub fn set_fog(&mut self, fog: FogSettings) {
+        self.fog = fog;
+        self.dirty = true;
+    }
+
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        uploads: &mut UploadBelt,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        if self.lights.len() > self.capacity {
+            self.capacity = self.lights.len().next_power_of_two();
+            self.storage_buffer = Self::create_storage_buffer(device, self.capacity);
+            self.bind_group = Self::create_bind_group(
+                device,
+                layout,
+                &self.lighting_buffer,
+                &self.storage_buffer,
+            );
+        }
+
+        uploads.write(
+            device,
+            &self.lighting_buffer,
+            0,
+            bytemuck::cast_slice(&[LightingUniform::new(
+                self.lights.len() as u32,
+                self.ambient,
+                &self.fog,
+            )]),
+        );
+        if !self.lights.is_empty() {
+            uploads.write(
+                device,
+                &self.storage_buffer,
+                0,
+                bytemuck::cast_slice(&self.lights),
+            );
+        }
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
@@ -0,0 +1,348 @@
+#include "include/camera.wgsl"
+#include "include/lights.wgsl"
+#define SHADOW_GROUP 3
+#include "include/shadows.wgsl"
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(1) @binding(1)
+var t_irradiance: texture_cube<f32>;
+@group(1) @binding(2)
+var t_prefiltered: texture_cube<f32>;
+@group(1) @binding(3)
+var s_environment: sampler;
+@group(1) @binding(4)
+var t_brdf_lut: texture_2d<f32>;
+@group(1) @binding(5)
+var s_brdf_lut: sampler;
+@group(1) @binding(6)
+var t_ssao: texture_2d<f32>;
+
+// Lights binned per cluster by cluster.wgsl; only read when enabled.
+@group(1) @binding(7)
+var<uniform> cluster_grid: ClusterUniform;
+@group(1) @binding(8)
+var<storage, read> clusters: array<Cluster>;
+
+// The previous frame, for screen-space reflections; see ssr.rs.
+struct ReflectionUniform {
+    enabled: u32,
+    steps: u32,
+    max_distance: f32,
+    thickness: f32,
+    max_roughness: f32,
+    edge_fade: f32,
+    // Where the previous frame was seen from.
+    eye: vec3<f32>,
+}
+@group(1) @binding(10)
+var t_reflection_color: texture_2d<f32>;
+// World positions less `reflections.eye`, with zero in w where there's
+// only sky.
+@group(1) @binding(11)
+var t_reflection_position: texture_2d<f32>;
+@group(1) @binding(12)
+var<uniform> reflections: ReflectionUniform;
+
+// Captures of the scene around points in it, for the surfaces near them to
+// reflect; see probe.rs.
+struct ReflectionProbe {
+    position: vec3<f32>,
+    radius: f32,
+    blend_distance: f32,
+}
+struct ReflectionProbeUniform {
+    count: u32,
+    probes: array<ReflectionProbe, 8>,
+}
+@group(1) @binding(13)
+var t_reflection_probes: texture_cube_array<f32>;
+@group(1) @binding(14)
+var<uniform> reflection_probes: ReflectionProbeUniform;
+
+@group(2) @binding(0)
+var<uniform> lighting: Lighting;
+@group(2) @binding(1)
+var<storage, read> lights: array<Light>;
+
+const PI: f32 = 3.14159265359;
+
+// Point shadow cubes store distance / far from each light.
+fn point_shadow_factor(light_index: u32, light_position: vec3<f32>, world_position: vec3<f32>) -> f32 {
+#ifndef ENABLE_SHADOWS
+    return 1.0;
+#else
+    if light_index >= point_shadows.count {
+        return 1.0;
+    }
+    let to_fragment = world_position - light_position;
+    let depth = length(to_fragment) / point_shadows.far - point_shadows.bias;
+    return textureSampleCompareLevel(t_point_shadow, s_shadow, to_fragment, light_index, depth);
+#endif
+}
+
+fn cascade_debug_color(view_depth: f32) -> vec3<f32> {
+    switch cascade_index(view_depth) {
+        case 0u: { return vec3<f32>(1.0, 0.3, 0.3); }
+        case 1u: { return vec3<f32>(0.3, 1.0, 0.3); }
+        case 2u: { return vec3<f32>(0.3, 0.3, 1.0); }
+        case 3u: { return vec3<f32>(1.0, 1.0, 0.3); }
+        default: { return vec3<f32>(1.0); }
+    }
+}
+
+// Trowbridge-Reitz GGX normal distribution.
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denom = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denom * denom);
+}
+
+// Smith's method with the Schlick-GGX approximation for direct lighting.
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let ggx_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let ggx_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return ggx_v * ggx_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let f90 = max(vec3<f32>(1.0 - roughness), f0);
+    return f0 + (f90 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+struct Surface {
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    view_dir: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    f0: vec3<f32>,
+    // Material occlusion only; SSAO is applied in `shade`.
+    occlusion: f32,
+    emissive: vec3<f32>,
+}
+
+// Cook-Torrance specular plus Lambertian diffuse for one light direction,
+// already multiplied by n.l.
+fn brdf(surface: Surface, light_dir: vec3<f32>) -> vec3<f32> {
+    let half_dir = normalize(surface.view_dir + light_dir);
+    let n_dot_l = max(dot(surface.normal, light_dir), 0.0);
+    let n_dot_v = max(dot(surface.normal, surface.view_dir), 1e-4);
+    let n_dot_h = max(dot(surface.normal, half_dir), 0.0);
+
+    let d = distribution_ggx(n_dot_h, surface.roughness);
+    let g = geometry_smith(n_dot_v, n_dot_l, surface.roughness);
+    let f = fresnel_schlick(max(dot(half_dir, surface.view_dir), 0.0), surface.f0);
+
+    let specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 1e-4);
+    let k_d = (vec3<f32>(1.0) - f) * (1.0 - surface.metallic);
+    return (k_d * surface.albedo / PI + specular) * n_dot_l;
+}
+
+// The split-sum BRDF's scale for light reflected off `surface`, from the
+// environment or the screen.
+fn specular_weight(surface: Surface) -> vec3<f32> {
+    let n_dot_v = max(dot(surface.normal, surface.view_dir), 1e-4);
+    let f = fresnel_schlick_roughness(n_dot_v, surface.f0, surface.roughness);
+    let brdf = textureSampleLevel(t_brdf_lut, s_brdf_lut, vec2<f32>(n_dot_v, surface.roughness), 0.0).rg;
+    return f * brdf.x + brdf.y;
+}
+
+// The prefiltered environment towards `direction` from `world_position`:
+// the reflection probes whose radius it's in, nearer ones weighing more,
+// over the baked environment past them.
+fn environment_reflection(world_position: vec3<f32>, direction: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let lod = roughness * f32(textureNumLevels(t_prefiltered) - 1u);
+    let environment = textureSampleLevel(t_prefiltered, s_environment, direction, lod).rgb;
+    var sum = vec3<f32>(0.0);
+    var total = 0.0;
+    var coverage = 0.0;
+    for (var i = 0u; i < reflection_probes.count; i += 1u) {
+        let probe = reflection_probes.probes[i];
+        let reach = length(world_position - probe.position);
+        let fade = clamp((probe.radius - reach) / max(probe.blend_distance, 1e-4), 0.0, 1.0);
+        if fade <= 0.0 {
+            continue;
+        }
+        let weight = fade / max(reach, 0.1);
+        sum += textureSampleLevel(t_reflection_probes, s_environment, direction, i, lod).rgb * weight;
+        total += weight;
+        coverage = max(coverage, fade);
+    }
+    if total <= 0.0 {
+        return environment;
+    }
+    return mix(environment, sum / total, coverage);
+}
+
+// Split-sum image-based lighting from the baked environment and probes,
+// with only `specular_share` of its reflection where the screen's stands
+// in for it.
+fn ambient_light(surface: Surface, world_position: vec3<f32>, specular_share: f32) -> vec3<f32> {
+    let n_dot_v = max(dot(surface.normal, surface.view_dir), 1e-4);
+    let f = fresnel_schlick_roughness(n_dot_v, surface.f0, surface.roughness);
+    let k_d = (vec3<f32>(1.0) - f) * (1.0 - surface.metallic);
+
+    let irradiance = textureSampleLevel(t_irradiance, s_environment, surface.normal, 0.0).rgb;
+    let reflected = reflect(-surface.view_dir, surface.normal);
+    let prefiltered = environment_reflection(world_position, reflected, surface.roughness);
+
+    return k_d * irradiance * surface.albedo + prefiltered * specular_weight(surface) * specular_share;
+}
+
+// Where `world_position` was in the previous frame's image, in uv, and
+// its depth there.
+fn previous_screen(world_position: vec3<f32>) -> vec3<f32> {
+    let clip = camera.prev_view_proj * vec4<f32>(world_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    return vec3<f32>(ndc.xy * vec2<f32>(0.5, -0.5) + 0.5, ndc.z);
+}
+
+// Whether `world_position`, at `screen`, is behind what the previous frame
+// saw there by less than `thickness`.
+fn behind_previous(world_position: vec3<f32>, screen: vec3<f32>, thickness: f32) -> bool {
+    let size = vec2<f32>(textureDimensions(t_reflection_position));
+    let scene = textureLoad(t_reflection_position, vec2<i32>(screen.xy * size), 0);
+    let scene_position = scene.xyz + reflections.eye;
+    return scene.w > 0.0 && screen.z >= previous_screen(scene_position).z
+        && distance(world_position, scene_position) < thickness;
+}
+
+// What the previous frame saw along the ray reflected off `surface`, with
+// how much to trust it in a: none where the ray leaves the screen or hits
+// nothing within `max_distance`, and less towards either.
+fn screen_space_reflection(surface: Surface, world_position: vec3<f32>) -> vec4<f32> {
+    if reflections.enabled == 0u || surface.roughness > reflections.max_roughness {
+        return vec4<f32>(0.0);
+    }
+    let direction = reflect(-surface.view_dir, surface.normal);
+    // Off the surface a little, so it doesn't find itself.
+    let origin = world_position + surface.normal * 0.01;
+    let step = reflections.max_distance / f32(reflections.steps);
+    // A step's length thicker, so long steps don't pass thin things.
+    let thickness = reflections.thickness + step;
+    var near = 0.0;
+    var far = -1.0;
+    for (var i = 1u; i <= reflections.steps; i += 1u) {
+        let t = step * f32(i);
+        let position = origin + direction * t;
+        let screen = previous_screen(position);
+        if any(screen < vec3<f32>(0.0)) || any(screen > vec3<f32>(1.0)) {
+            break;
+        }
+        if behind_previous(position, screen, thickness) {
+            far = t;
+            break;
+        }
+        near = t;
+    }
+    if far < 0.0 {
+        return vec4<f32>(0.0);
+    }
+    // Halves the last step a few times for where the ray passed behind.
+    for (var i = 0; i < 5; i += 1) {
+        let middle = (near + far) * 0.5;
+        let position = origin + direction * middle;
+        if behind_previous(position, previous_screen(position), thickness) {
+            far = middle;
+        } else {
+            near = middle;
+        }
+    }
+
+    let screen = previous_screen(origin + direction * far);
+    let color = textureSampleLevel(t_reflection_color, s_environment, screen.xy, 0.0).rgb;
+    let edge = min(min(screen.x, 1.0 - screen.x), min(screen.y, 1.0 - screen.y));
+    var confidence = saturate(edge / reflections.edge_fade);
+    confidence *= 1.0 - far / reflections.max_distance;
+    confidence *= 1.0 - smoothstep(0.5, 1.0, surface.roughness / reflections.max_roughness);
+    // Rays back towards the camera would see the backs of things, which the
+    // frame never drew.
+    confidence *= 1.0 - smoothstep(0.25, 0.75, dot(direction, surface.view_dir));
+    return vec4<f32>(color, confidence);
+}
+
+// Inverse-square falloff, windowed to reach zero at `light_range`.
+fn point_light(surface: Surface, index: u32, world_position: vec3<f32>) -> vec3<f32> {
+    let light = lights[index];
+    let to_light = light.position - world_position;
+    let distance2 = max(dot(to_light, to_light), 1e-4);
+    let range = light_range(light);
+    let falloff = clamp(1.0 - distance2 * distance2 / (range * range * range * range), 0.0, 1.0);
+    let radiance = light.color * light.intensity * falloff * falloff / distance2;
+    let lit = point_shadow_factor(index, light.position, world_position);
+    return brdf(surface, normalize(to_light)) * radiance * lit;
+}
+
+fn cluster_index(pixel: vec2<f32>, view_depth: f32) -> u32 {
+    let grid = cluster_grid.grid;
+    let tile = min(vec2<u32>(pixel / cluster_grid.tile_size), grid.xy - 1u);
+    let depth_ratio = log(max(view_depth, cluster_grid.z_near) / cluster_grid.z_near)
+        / log(cluster_grid.z_far / cluster_grid.z_near);
+    let slice = min(u32(depth_ratio * f32(grid.z)), grid.z - 1u);
+    return tile.x + grid.x * (tile.y + grid.y * slice);
+}
+
+// Total light leaving `surface` towards the eye. `pixel` is the framebuffer
+// position, used to look up screen-space occlusion.
+fn shade(surface: Surface, world_position: vec3<f32>, pixel: vec2<f32>) -> vec3<f32> {
+    let view_depth = -(camera.view * vec4<f32>(world_position, 1.0)).z;
+    var result = vec3<f32>(0.0);
+    if cluster_grid.enabled != 0u {
+        let cluster = cluster_index(pixel, view_depth);
+        for (var i = 0u; i < clusters[cluster].count; i += 1u) {
+            result += point_light(surface, clusters[cluster].lights[i], world_position);
+        }
+    } else {
+        for (var i = 0u; i < lighting.light_count; i += 1u) {
+            result += point_light(surface, i, world_position);
+        }
+    }
+
+    let sun_dir = -normalize(shadow.direction);
+    result += brdf(surface, sun_dir) * shadow.color * shadow.intensity
+        * shadow_factor(world_position, view_depth);
+
+    let ssao = textureLoad(t_ssao, vec2<i32>(pixel), 0).r;
+    let reflection = screen_space_reflection(surface, world_position);
+    result += ambient_light(surface, world_position, 1.0 - reflection.a) * lighting.ambient * surface.occlusion * ssao;
+    // Light the screen already shows, so the ambient scale leaves it be.
+    result += reflection.rgb * reflection.a * specular_weight(surface) * surface.occlusion * ssao;
+    result += surface.emissive;
+    if shadow.debug_cascades != 0u {
+        result *= cascade_debug_color(view_depth);
+    }
+    return result;
+}
+
+// Blends `color`, seen at `world_position`, into the fog. The density
+// integrates exactly along the view ray beyond `fog_start`, falling off
+// exponentially with height; `fog_end` forces full fog regardless.
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    if lighting.fog_enabled == 0u {
+        return color;
+    }
+    let eye = camera.view_pos.xyz;
+    let distance = length(world_position - eye);
+    let travelled = max(distance - lighting.fog_start, 0.0);
+    let entry = eye + (world_position - eye) * (1.0 - travelled / max(distance, 1e-4));
+
+    let falloff = lighting.fog_height_falloff;
+    let climb = falloff * (world_position.y - entry.y);
+    // (1 - e^-x) / x, kept finite for level rays.
+    var average = 1.0;
+    if abs(climb) > 1e-4 {
+        average = (1.0 - exp(-climb)) / climb;
+    }
+    let depth = lighting.fog_density * exp(-falloff * entry.y) * travelled * average;
+    let ramp = saturate((distance - lighting.fog_start) / max(lighting.fog_end - lighting.fog_start, 1e-4));
+    let fog = max(1.0 - exp(-depth), ramp);
+    return mix(color, lighting.fog_color, fog);
+}
@@ -0,0 +1,341 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use cgmath::prelude::*;
+
+use crate::camera::{Camera, Projection};
+use crate::culling::Frustum;
+use crate::instance::InstanceRaw;
+use crate::model::{self, Bounds, ModelVertex};
+use crate::staging::UploadBelt;
+
+/// What picks the level of detail an instance is drawn at.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum LodMetric {
+    /// How tall its bounds are on screen.
+    #[default]
+    ScreenSize,
+    /// How far its bounds are from `Camera::eye`.
+    Distance,
+}
+
+impl LodMetric {
+    pub const ALL: [Self; 2] = [Self::ScreenSize, Self::Distance];
+}
+
+impl std::fmt::Display for LodMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ScreenSize => write!(f, "screen size"),
+            Self::Distance => write!(f, "distance"),
+        }
+    }
+}
+
+/// When instances of the scene model switch to its meshes' coarser
+/// levels of detail. Each level is for half the screen size, or twice the
+/// distance, of the one before.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LodSettings {
+    pub enabled: bool,
+    pub metric: LodMetric,
+    /// Pixels tall an instance must be to get the full meshes.
+    pub screen_size: f32,
+    /// How far from the eye an instance gets the full meshes.
+    pub distance: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            metric: LodMetric::default(),
+            screen_size: 300.0,
+            distance: 10.0,
+        }
+    }
+}
+
+// Meshes with fewer triangles aren't worth coarser levels.
+const MIN_TRIANGLES: usize = 256;
+// The grid each coarser level is clustered on, in cells along the mesh's
+// longest side.
+const CELLS: [u32; 3] = [32, 16, 8];
+// How far across a switch, in levels, an instance is drawn at both levels,
+// dithered from one into the other, rather than popping between them.
+const CROSSFADE: f32 = 0.2;
+
+/// The indices of `indices`'s triangles followed by those of coarser
+/// levels of detail, and where each coarser level is in them. The levels
+/// use the same vertices, so they fit in the mesh's index buffer after the
+/// full mesh.
+pub fn generate(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<u32>, Vec<Range<u32>>) {
+    let mut all = indices.to_vec();
+    let mut lods = Vec::new();
+    if indices.len() / 3 < MIN_TRIANGLES {
+        return (all, lods);
+    }
+    let mut previous = indices.len();
+    for cells in CELLS {
+        let level = decimate(vertices, indices, cells);
+        if level.is_empty() {
+            break;
+        }
+        // Not worth a level unless it drops a quarter of the triangles.
+        if level.len() * 4 > previous * 3 {
+            continue;
+        }
+        let start = all.len() as u32;
+        lods.push(start..start + level.len() as u32);
+        previous = level.len();
+        all.extend(level);
+    }
+    (all, lods)
+}
+
+/// `indices` with the vertices in each cell of a grid `cells` wide across
+/// the mesh's longest side merged into the one nearest their average, and
+/// without the triangles that collapse or end up doubled.
+pub fn decimate(vertices: &[ModelVertex], indices: &[u32], cells: u32) -> Vec<u32> {
+    let bounds = Bounds::from_points(vertices.iter().map(|vertex| vertex.position));
+    let size = bounds.max - bounds.min;
+    let cell = size.x.max(size.y).max(size.z) / cells.max(1) as f32;
+    if cell <= 0.0 {
+        return indices.to_vec();
+    }
+    let key = |vertex: &ModelVertex| {
+        let offset = (cgmath::Point3::from(vertex.position) - bounds.min) / cell;
+        [offset.x, offset.y, offset.z].map(|x| x.floor() as i32)
+    };
+
+    let mut sums = HashMap::<[i32; 3], (cgmath::Vector3<f32>, f32)>::new();
+    for &index in indices {
+        let vertex = &vertices[index as usize];
+        let sum = sums
+            .entry(key(vertex))
+            .or_insert((cgmath::Vector3::zero(), 0.0));
+        sum.0 += cgmath::Vector3::from(vertex.position);
+        sum.1 += 1.0;
+    }
+    let mut nearest = HashMap::<[i32; 3], (u32, f32)>::new();
+    for &index in indices {
+        let vertex = &vertices[index as usize];
+        let cell = key(vertex);
+        let (sum, count) = sums[&cell];
+        let distance = (cgmath::Vector3::from(vertex.position) - sum / count).magnitude2();
+        let best = nearest.entry(cell).or_insert((index, distance));
+        if distance < best.1 {
+            *best = (index, distance);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut decimated = Vec::new();
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| nearest[&key(&vertices[triangle[i] as usize])].0);
+        if a == b || b == c || c == a {
+            continue;
+        }
+        // The same triangle starting from another corner is still the same.
+        let first = if a < b && a < c {
+            [a, b, c]
+        } else if b < c {
+            [b, c, a]
+        } else {
+            [c, a, b]
+        };
+        if seen.insert(first) {
+            decimated.extend(first);
+        }
+    }
+    decimated
+}
+
+// The levels of detail, out of `count`, to draw an instance `detail`
+// levels down at, each with its `InstanceRaw::fade`: one, or two
+// crossfading within `CROSSFADE` of a switch.
+fn levels(detail: f32, count: usize) -> Vec<(usize, f32)> {
+    let coarsest = count.saturating_sub(1);
+    let switch = detail.round();
+    // Through how much of the crossfade into the level after `switch`.
+    let progress = (detail - switch) / CROSSFADE + 0.5;
+    if switch >= 0.0 && (switch as usize) < coarsest && (0.0..1.0).contains(&progress) {
+        let from = switch as usize;
+        return vec![(from, progress - 1.0), (from + 1, progress)];
+    }
+    vec![((detail.ceil().max(0.0) as usize).min(coarsest), 1.0)]
+}
+
+/// The scene model's instances inside the view, grouped by the level of
+/// detail each is drawn at, in a vertex buffer for slot 1.
+///
+/// Levels are picked for whole instances from the bounds of all the
+/// model's meshes, so the meshes of one instance switch together; a mesh
+/// with fewer levels draws its coarsest for the rest. Near a switch an
+/// instance is in both levels' groups, with complementary
+/// `InstanceRaw::fade`s.
+pub struct LodInstances {
+    settings: LodSettings,
+    raw: Vec<InstanceRaw>,
+    ranges: Vec<Range<u32>>,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+}
+
+impl LodInstances {
+    const MIN_CAPACITY: usize = 64;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            settings: LodSettings::default(),
+            raw: Vec::new(),
+            ranges: Vec::new(),
+            buffer: Self::create_buffer(device, Self::MIN_CAPACITY),
+            capacity: Self::MIN_CAPACITY,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("LOD Instance Buffer"),
+            size: (capacity * size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn settings(&self) -> LodSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: LodSettings) {
+        self.settings = settings;
+    }
+
+    /// Whether passes should draw from here rather than the instances.
+    pub fn is_enabled(&self) -> bool {
+        self.settings.enabled
+    }
+
+    /// Picks the level of each of `instances` seen from `camera`, onto a
+    /// target `height` pixels tall, and uploads them grouped by level.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        uploads: &mut UploadBelt,
+        camera: &Camera,
+        height: u32,
+        model: &model::Model,
+        instances: &[InstanceRaw],
+    ) {
+        if !self.settings.enabled {
+            return;
+        }
+        let count = 1 + model
+            .meshes
+            .iter()
+            .map(|mesh| mesh.lods.len())
+            .max()
+            .unwrap_or(0);
+        let bounds = model.bounds();
+        let frustum = Frustum::from_view_proj(camera);
+        let mut by_level = vec![Vec::new(); count];
+        for raw in instances {
+            let world = bounds.transformed(&raw.model.into());
+            if !frustum.intersects_aabb(&world) {
+                continue;
+            }
+            let detail = self.detail(camera, height, &world);
+            for (level, fade) in levels(detail, count) {
+                let mut raw = *raw;
+                raw.set_fade(fade);
+                by_level[level].push(raw);
+            }
+        }
+
+        self.raw.clear();
+        self.ranges.clear();
+        for level in by_level {
+            let start = self.raw.len() as u32;
+            self.raw.extend(level);
+            self.ranges.push(start..self.raw.len() as u32);
+        }
+        if self.raw.len() > self.capacity {
+            self.capacity = self.raw.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        uploads.write(device, &self.buffer, 0, bytemuck::cast_slice(&self.raw));
+    }
+
+    // How many levels down `world` should be drawn, as a fraction: level
+    // `n` is right from just above `n - 1` up to `n`.
+    fn detail(&self, camera: &Camera, height: u32, world: &Bounds) -> f32 {
+        let radius = world.radius();
+        let distance = camera.eye.distance(world.center()).max(camera.znear);
+        match self.settings.metric {
+            LodMetric::ScreenSize => {
+                let view_height = match camera.projection {
+                    Projection::Perspective { fovy } => {
+                        2.0 * distance * (cgmath::Deg(fovy) / 2.0).tan()
+                    }
+                    Projection::Orthographic { height } => height,
+                };
+                let pixels = 2.0 * radius / view_height * height as f32;
+                (self.settings.screen_size / pixels.max(1e-3)).log2()
+            }
+            LodMetric::Distance => (distance / self.settings.distance.max(1e-3)).log2(),
+        }
+    }
+
+    /// The instances drawn at each level, as ranges of `slice`.
+    pub fn ranges(&self) -> &[Range<u32>] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// The vertex buffer to bind at slot 1; only valid when not empty.
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        let size = (self.raw.len() * size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+        self.buffer.slice(..size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_switch_at_whole_details_away_from_a_crossfade() {
+        assert_eq!(levels(-3.0, 4), vec![(0, 1.0)]);
+        assert_eq!(levels(0.5, 4), vec![(1, 1.0)]);
+        assert_eq!(levels(1.5, 4), vec![(2, 1.0)]);
+        // Past the coarsest level there is nothing to fade into.
+        assert_eq!(levels(3.0, 4), vec![(3, 1.0)]);
+        assert_eq!(levels(10.0, 4), vec![(3, 1.0)]);
+        assert_eq!(levels(2.0, 1), vec![(0, 1.0)]);
+    }
+
+    #[test]
+    fn crossfades_are_complementary_and_continuous() {
+        let [(from, fade_out), (to, fade_in)] = levels(1.0, 4)[..] else {
+            panic!("expected a crossfade");
+        };
+        assert_eq!((from, to), (1, 2));
+        // Halfway, each level keeps the pixels the other drops.
+        assert!((fade_in - 0.5).abs() < 1e-6);
+        assert!((fade_out - (fade_in - 1.0)).abs() < 1e-6);
+
+        // The edges of the crossfade meet the levels on either side.
+        let start = levels(1.0 - CROSSFADE / 2.0 + 1e-4, 4);
+        assert_eq!(start[0].0, 1);
+        assert!(start[0].1 < -0.99);
+        assert!(start[1].1 < 0.01);
+        let end = levels(1.0 + CROSSFADE / 2.0 - 1e-4, 4);
+        assert_eq!(end[1].0, 2);
+        assert!(end[0].1 > -0.01);
+        assert!(end[1].1 > 0.99);
+    }
+}
@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+
+use crate::deferred::Deferred;
+use crate::material_arrays::MaterialArrays;
+use crate::model;
+use crate::oit::Oit;
+use crate::pipeline_cache::PipelineCache;
+use crate::post::taa::Taa;
+use crate::preprocess::{ShaderDefs, ShaderLibrary};
+use crate::texture;
+
+/// A kind of material registered with a `MaterialRegistry`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MaterialKindId(pub usize);
+
+impl MaterialKindId {
+    /// Lit and opaque; what loaders give every material by default.
+    pub const STANDARD: Self = Self(0);
+    /// Base color and emission only, ignoring the lights.
+    pub const UNLIT: Self = Self(1);
+    /// Lit, blended over what is behind it by base color alpha.
+    pub const TRANSPARENT: Self = Self(2);
+    /// `TRANSPARENT`, but blended order-independently instead of sorted.
+    pub const WEIGHTED_BLENDED: Self = Self(3);
+}
+
+/// Which pass a material pipeline draws in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MaterialPass {
+    /// Shaded as drawn, into the HDR and velocity targets.
+    Forward,
+    /// Written to the deferred path's G-buffer for the lighting pass.
+    GBuffer,
+    /// Accumulated into `Oit`'s targets and composited over the HDR target,
+    /// on either path.
+    WeightedBlended,
+}
+
+/// What a material pipeline is built for: the material kind, the vertex
+/// layout (`model::vertex_buffers(skinned, vertex_encoding)`) and the pass.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub material: MaterialKindId,
+    pub skinned: bool,
+    pub vertex_encoding: model::VertexEncoding,
+    pub pass: MaterialPass,
+    /// Reads every material from `MaterialArrays` by the vertices' material
+    /// IDs, in place of the kind's own group 0.
+    pub texture_arrays: bool,
+    /// Samples a pixel of the targets: 1, or `Msaa::SAMPLE_COUNT` for
+    /// opaque forward pipelines drawing into `Msaa`'s.
+    pub samples: u32,
+}
+
+/// How meshes of one kind of material are drawn.
+///
+/// Every kind uses shader.wgsl, compiled with the scene's defines plus its
+/// own, behind its own bind group layout in group 0.
+pub trait MaterialKind {
+    fn name(&self) -> &str;
+
+    /// Group 0 of this kind's pipelines; the bind groups of the
+    /// `model::Material`s using it must be built against it.
+    fn bind_group_layout(&self) -> &wgpu::BindGroupLayout;
+
+    fn shader_defs(&self) -> ShaderDefs {
+        ShaderDefs::new()
+    }
+
+    /// Blending for the color target; `None` for opaque materials.
+    fn blend(&self) -> Option<wgpu::BlendState> {
+        None
+    }
+
+    fn cull_mode(&self) -> Option<wgpu::Face> {
+        Some(wgpu::Face::Back)
+    }
+
+    /// Whether the deferred path writes it to the G-buffer. Others are
+    /// drawn forward after the lighting pass.
+    fn deferred(&self) -> bool {
+        self.blend().is_none()
+    }
+
+    /// Transparent materials are drawn after the skybox, one instance at a
+    /// time from back to front. They leave depth and velocity alone and
+    /// stay out of the SSAO prepass.
+    fn is_transparent(&self) -> bool {
+        self.blend().is_some()
+    }
+
+    /// Whether a transparent kind goes through weighted-blended OIT, for
+    /// surfaces that interpenetrate or overlap too much to sort. Its
+    /// `blend` then only marks it transparent.
+    fn order_independent(&self) -> bool {
+        false
+    }
+
+    /// Whether it can be drawn from `MaterialArrays`, which only kinds
+    /// with `model::create_material_bind_group_layout`'s textures can.
+    fn texture_arrays(&self) -> bool {
+        false
+    }
+}
+
+/// The built-in kinds: shader.wgsl's surface with a choice of lighting,
+/// blending and culling.
+pub struct SurfaceMaterial {
+    name: String,
+    layout: wgpu::BindGroupLayout,
+    defs: ShaderDefs,
+    blend: Option<wgpu::BlendState>,
+    cull_mode: Option<wgpu::Face>,
+    order_independent: bool,
+}
+
+impl SurfaceMaterial {
+    /// `layout` is `model::create_material_bind_group_layout`'s.
+    pub fn standard(layout: &wgpu::BindGroupLayout) -> Self {
+        Self {
+            name: "standard".to_string(),
+            layout: layout.clone(),
+            defs: ShaderDefs::new(),
+            blend: None,
+            cull_mode: Some(wgpu::Face::Back),
+            order_independent: false,
+        }
+    }
+
+    pub fn unlit(layout: &wgpu::BindGroupLayout) -> Self {
+        Self {
+            name: "unlit".to_string(),
+            defs: ShaderDefs::new().with_flag("UNLIT"),
+            ..Self::standard(layout)
+        }
+    }
+
+    /// Drawn from both sides, since the back faces show through.
+    pub fn transparent(layout: &wgpu::BindGroupLayout) -> Self {
+        Self {
+            name: "transparent".to_string(),
+            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            cull_mode: None,
+            ..Self::standard(layout)
+        }
+    }
+
+    pub fn weighted_blended(layout: &wgpu::BindGroupLayout) -> Self {
+        Self {
+            name: "weighted_blended".to_string(),
+            order_independent: true,
+            ..Self::transparent(layout)
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn with_define(mut self, name: &str, value: impl ToString) -> Self {
+        self.defs.set(name, value);
+        self
+    }
+
+    pub fn with_blend(mut self, blend: Option<wgpu::BlendState>) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn with_cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn with_order_independent(mut self, order_independent: bool) -> Self {
+        self.order_independent = order_independent;
+        self
+    }
+}
+
+impl MaterialKind for SurfaceMaterial {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    fn shader_defs(&self) -> ShaderDefs {
+        self.defs.clone()
+    }
+
+    fn blend(&self) -> Option<wgpu::BlendState> {
+        self.blend
+    }
+
+    fn cull_mode(&self) -> Option<wgpu::Face> {
+        self.cull_mode
+    }
+
+    fn deferred(&self) -> bool {
+        self.blend.is_none() && !self.defs.contains("UNLIT")
+    }
+
+    fn order_independent(&self) -> bool {
+        self.order_independent
+    }
+
+    fn texture_arrays(&self) -> bool {
+        true
+    }
+}
+
+/// What building a material pipeline needs besides the material.
+pub struct PipelineContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub shaders: &'a ShaderLibrary,
+    /// Where the modules, layout and pipeline come from, so kinds and
+    /// passes built alike share them.
+    pub cache: &'a PipelineCache,
+    pub scene_defs: &'a ShaderDefs,
+    /// The camera, light and shadow groups, after the material's own.
+    pub scene_bind_group_layouts: [&'a wgpu::BindGroupLayout; 3],
+    /// Group 0 in place of the kind's for `PipelineKey::texture_arrays`.
+    pub texture_array_layout: &'a wgpu::BindGroupLayout,
+    pub color_format: wgpu::TextureFormat,
+}
+
+/// The material kinds meshes can use, and the pipelines built for them
+/// with the current scene defines.
+pub struct MaterialRegistry {
+    kinds: Vec<Box<dyn MaterialKind>>,
+    pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>,
+}
+
+impl MaterialRegistry {
+    /// Registers the built-in kinds under their `MaterialKindId` constants,
+    /// all using `layout`.
+    pub fn new(layout: &wgpu::BindGroupLayout) -> Self {
+        let mut registry = Self {
+            kinds: Vec::new(),
+            pipelines: HashMap::new(),
+        };
+        registry.register(SurfaceMaterial::standard(layout));
+        registry.register(SurfaceMaterial::unlit(layout));
+        registry.register(SurfaceMaterial::transparent(layout));
+        registry.register(SurfaceMaterial::weighted_blended(layout));
+        registry
+    }
+
+    pub fn register(&mut self, kind: impl MaterialKind + 'static) -> MaterialKindId {
+        self.kinds.push(Box::new(kind));
+        MaterialKindId(self.kinds.len() - 1)
+    }
+
+    pub fn get(&self, id: MaterialKindId) -> Option<&dyn MaterialKind> {
+        self.kinds.get(id.0).map(|kind| kind.as_ref())
+    }
+
+    pub fn find(&self, name: &str) -> Option<MaterialKindId> {
+        self.kinds
+            .iter()
+            .position(|kind| kind.name() == name)
+            .map(MaterialKindId)
+    }
+
+    pub fn len(&self) -> usize {
+        self.kinds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+    /// The pass `id` is drawn in on the forward or deferred path.
+    pub fn pass(&self, id: MaterialKindId, deferred_path: bool) -> MaterialPass {
+        match self.get(id) {
+            Some(kind) if kind.is_transparent() && kind.order_independent() => {
+                MaterialPass::WeightedBlended
+            }
+            Some(kind) if deferred_path && kind.deferred() => MaterialPass::GBuffer,
+            _ => MaterialPass::Forward,
+        }
+    }
+
+    pub fn pipeline(&self, key: &PipelineKey) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(key)
+    }
+
+    /// The `PipelineKey::texture_arrays` pipeline built alongside
+    /// `pipeline`, if there is one.
+    pub fn texture_array_pipeline(
+        &self,
+        pipeline: &wgpu::RenderPipeline,
+    ) -> Option<&wgpu::RenderPipeline> {
+        let (key, _) = self
+            .pipelines
+            .iter()
+            .find(|(key, other)| !key.texture_arrays && *other == pipeline)?;
+        self.pipelines.get(&PipelineKey {
+            texture_arrays: true,
+            ..*key
+        })
+    }
+
+    pub fn pipelines(&self) -> &HashMap<PipelineKey, wgpu::RenderPipeline> {
+        &self.pipelines
+    }
+
+    /// Replaces every pipeline, as after the scene defines change.
+    pub fn set_pipelines(&mut self, pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>) {
+        self.pipelines = pipelines;
+    }
+
+    pub fn insert_pipeline(&mut self, key: PipelineKey, pipeline: wgpu::RenderPipeline) {
+        self.pipelines.insert(key, pipeline);
+    }
+
+    /// The pipeline for `key`, compiled unless `context.cache` has it,
+    /// without storing it in the registry.
+    pub fn create_pipeline(
+        &self,
+        key: &PipelineKey,
+        context: &PipelineContext,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        let kind = self
+            .get(key.material)
+            .with_context(|| format!("no material kind {}", key.material.0))?;
+        if key.pass == MaterialPass::GBuffer && !kind.deferred() {
+            anyhow::bail!("{} materials are not drawn into the G-buffer", kind.name());
+        }
+        if key.pass == MaterialPass::WeightedBlended && !kind.order_independent() {
+            anyhow::bail!(
+                "{} materials are not blended order-independently",
+                kind.name()
+            );
+        }
+        if key.samples > 1 && (key.pass != MaterialPass::Forward || kind.is_transparent()) {
+            anyhow::bail!("only opaque forward pipelines are multisampled");
+        }
+        if key.texture_arrays && (!kind.texture_arrays() || kind.is_transparent()) {
+            anyhow::bail!(
+                "{} materials are not drawn from texture arrays",
+                kind.name()
+            );
+        }
+        let mut defs = context.scene_defs.clone();
+        // The key, not the scene, says whether the meshes are skinned and
+        // how their vertices are stored.
+        if key.skinned {
+            defs.set("SKINNED", "");
+        } else {
+            defs.remove("SKINNED");
+        }
+        defs.remove("QUANTIZED");
+        let mut defs = key.vertex_encoding.shader_defs(defs);
+        for (name, value) in kind.shader_defs().iter() {
+            defs.set(name, value);
+        }
+        let (material_layout, vertex_buffers) = if key.texture_arrays {
+            defs.set("TEXTURE_ARRAYS", "");
+            (
+                context.texture_array_layout,
+                MaterialArrays::vertex_buffers(key.skinned, key.vertex_encoding),
+            )
+        } else {
+            (
+                kind.bind_group_layout(),
+                model::vertex_buffers(key.skinned, key.vertex_encoding),
+            )
+        };
+        let device = context.device;
+        let cache = context.cache;
+        let depth_compare = texture::Texture::depth_compare(
+            wgpu::CompareFunction::LessEqual,
+            context.scene_defs.contains("REVERSE_Z"),
+        );
+        let label = format!("MaterialRegistry::{}", kind.name());
+        let shader = cache.shader_module(device, context.shaders, &label, "shader.wgsl", &defs)?;
+        let [camera_layout, light_layout, shadow_layout] = context.scene_bind_group_layouts;
+        let layout = cache.pipeline_layout(
+            device,
+            &label,
+            &[material_layout, camera_layout, light_layout, shadow_layout],
+        );
+        Ok(match key.pass {
+            MaterialPass::Forward => create_forward_pipeline(
+                device,
+                cache,
+                &label,
+                &layout,
+                &shader,
+                context.color_format,
+                &vertex_buffers,
+                kind,
+                depth_compare,
+                key.samples,
+            ),
+            MaterialPass::GBuffer => Deferred::create_gbuffer_pipeline(
+                device,
+                cache,
+                &label,
+                &layout,
+                &shader,
+                &vertex_buffers,
+                kind.cull_mode(),
+                depth_compare,
+            ),
+            MaterialPass::WeightedBlended => Oit::create_accumulate_pipeline(
+                device,
+                cache,
+                &label,
+                &layout,
+                &shader,
+                &vertex_buffers,
+                kind.cull_mode(),
+                depth_compare,
+            ),
+        })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_forward_pipeline(
+    device: &wgpu::Device,
+    cache: &PipelineCache,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    vertex_buffers: &[wgpu::VertexBufferLayout],
+    kind: &dyn MaterialKind,
+    depth_compare: wgpu::CompareFunction,
+    samples: u32,
+) -> wgpu::RenderPipeline {
+    let transparent = kind.is_transparent();
+    cache.render_pipeline(
+        device,
+        &wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: vertex_buffers,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &[
+                    Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(kind.blend().unwrap_or(wgpu::BlendState::REPLACE)),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
+                    // What shows through a transparent surface keeps its motion.
+                    Some(wgpu::ColorTargetState {
+                        format: Taa::VELOCITY_FORMAT,
+                        blend: None,
+                        write_mask: if transparent {
+                            wgpu::ColorWrites::empty()
+                        } else {
+                            wgpu::ColorWrites::ALL
+                        },
+                    }),
+                ],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: kind.cull_mode(),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: !transparent,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        },
+    )
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+
+use wgpu::util::DeviceExt;
+
+use crate::model::{self, MaterialUniform};
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+// Layers are no bigger than this on a side, however big the textures.
+const MAX_SIZE: u32 = 2048;
+const SLOTS: usize = 5;
+
+/// The scene model's material textures packed into a 2D texture array per
+/// slot, a layer per material, and their factors into a storage buffer,
+/// all behind one bind group. Meshes drawn with it pick their material by
+/// the ID `vertex_buffers` adds to each vertex, so meshes of different
+/// materials can go in one draw.
+///
+/// Each slot's layers take the size of its largest texture, up to
+/// `MAX_SIZE`, and the others are stretched to fit. That needs the
+/// textures of a slot to share a format that can be rendered to, and
+/// since there's one sampler, the materials to be sampled alike.
+pub struct MaterialArrays {
+    // What the arrays hold, to tell when they need packing again.
+    sources: Vec<[wgpu::Texture; SLOTS]>,
+    sampler_settings: texture::SamplerSettings,
+    factor_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl MaterialArrays {
+    /// Slot `i`'s array at binding `2 * i` and the sampler after it, as in
+    /// `model::create_material_bind_group_layout`, with the factors of
+    /// every material at binding 10.
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let mut entries = Vec::with_capacity(SLOTS * 2 + 1);
+        for slot in 0..SLOTS as u32 {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: slot * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: slot * 2 + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 10,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("MaterialArrays::layout"),
+            entries: &entries,
+        })
+    }
+
+    /// `model::vertex_buffers` followed by the material ID of each vertex,
+    /// at location 14.
+    pub fn vertex_buffers(
+        skinned: bool,
+        vertex_encoding: model::VertexEncoding,
+    ) -> Vec<wgpu::VertexBufferLayout<'static>> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![14 => Uint32];
+        let mut buffers = model::vertex_buffers(skinned, vertex_encoding);
+        buffers.push(wgpu::VertexBufferLayout {
+            array_stride: size_of::<u32>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        });
+        buffers
+    }
+
+    /// Packs `model`'s materials with a bind group against `layout`, one
+    /// of `create_bind_group_layout`'s; None when there are none, or they
+    /// can't share arrays.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        model: &model::Model,
+    ) -> Option<Self> {
+        let first = model.materials.first()?;
+        let sampler_settings = *first.sampler_settings();
+        let layers = Self::layer_count(model.materials.len());
+        if layers > device.limits().max_texture_array_layers
+            || model
+                .materials
+                .iter()
+                .any(|material| *material.sampler_settings() != sampler_settings)
+        {
+            return None;
+        }
+        let mut formats = Vec::with_capacity(SLOTS);
+        for slot in 0..SLOTS {
+            let format = first.textures.slots()[slot].texture.format();
+            let renderable = format
+                .guaranteed_format_features(device.features())
+                .allowed_usages
+                .contains(wgpu::TextureUsages::RENDER_ATTACHMENT);
+            if !renderable
+                || model
+                    .materials
+                    .iter()
+                    .any(|material| material.textures.slots()[slot].texture.format() != format)
+            {
+                return None;
+            }
+            formats.push(format);
+        }
+
+        let shader = preprocess::builtin_module(
+            device,
+            "MaterialArrays::shader",
+            "material_arrays.wgsl",
+            &ShaderDefs::new(),
+        );
+        let blit_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("MaterialArrays::blit_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("MaterialArrays::blit_pipeline_layout"),
+            bind_group_layouts: &[&blit_layout],
+            push_constant_ranges: &[],
+        });
+        let mut pipelines = HashMap::new();
+        for &format in &formats {
+            pipelines.entry(format).or_insert_with(|| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("MaterialArrays::blit_pipeline"),
+                    layout: Some(&pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: Some("vs_main"),
+                        buffers: &[],
+                        compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: Some("fs_main"),
+                        targets: &[Some(format.into())],
+                        compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    }),
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                    multiview: None,
+                    cache: None,
+                })
+            });
+        }
+        // Clamped, as repeating would blend the far edge into the near one
+        // when stretching.
+        let blit_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("MaterialArrays::blit_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("MaterialArrays::encoder"),
+        });
+        let mut views = Vec::with_capacity(SLOTS);
+        for (slot, &format) in formats.iter().enumerate() {
+            let textures = model
+                .materials
+                .iter()
+                .map(|material| material.textures.slots()[slot])
+                .collect::<Vec<_>>();
+            let width = textures.iter().map(|t| t.texture.width()).max()?;
+            let height = textures.iter().map(|t| t.texture.height()).max()?;
+            let size = wgpu::Extent3d {
+                width: width.min(MAX_SIZE),
+                height: height.min(MAX_SIZE),
+                depth_or_array_layers: layers,
+            };
+            let mip_level_count = texture::Texture::mip_level_count(size.width, size.height);
+            let array = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("MaterialArrays::array"),
+                size,
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            });
+            for (layer, source) in textures.iter().enumerate() {
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("MaterialArrays::blit_bind_group"),
+                    layout: &blit_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&source.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&blit_sampler),
+                        },
+                    ],
+                });
+                for mip in 0..mip_level_count {
+                    let target = array.create_view(&wgpu::TextureViewDescriptor {
+                        label: Some("MaterialArrays::target"),
+                        dimension: Some(wgpu::TextureViewDimension::D2),
+                        base_mip_level: mip,
+                        mip_level_count: Some(1),
+                        base_array_layer: layer as u32,
+                        array_layer_count: Some(1),
+                        ..Default::default()
+                    });
+                    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("MaterialArrays::blit_pass"),
+                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                            view: &target,
+                            resolve_target: None,
+                            depth_slice: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                                store: wgpu::StoreOp::Store,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                        occlusion_query_set: None,
+                        timestamp_writes: None,
+                    });
+                    pass.set_pipeline(&pipelines[&format]);
+                    pass.set_bind_group(0, &bind_group, &[]);
+                    pass.draw(0..3, 0..1);
+                }
+            }
+            views.push(array.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("MaterialArrays::view"),
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            }));
+        }
+        queue.submit([encoder.finish()]);
+
+        let factor_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("MaterialArrays::factor_buffer"),
+            contents: bytemuck::cast_slice(&Self::factors(model)),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&sampler_settings.descriptor());
+        let mut entries = Vec::with_capacity(SLOTS * 2 + 1);
+        for (slot, view) in views.iter().enumerate() {
+            entries.push(wgpu::BindGroupEntry {
+                binding: slot as u32 * 2,
+                resource: wgpu::BindingResource::TextureView(view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: slot as u32 * 2 + 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            });
+        }
+        entries.push(wgpu::BindGroupEntry {
+            binding: 10,
+            resource: factor_buffer.as_entire_binding(),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("MaterialArrays::bind_group"),
+            layout,
+            entries: &entries,
+        });
+
+        Some(Self {
+            sources: Self::sources(model),
+            sampler_settings,
+            factor_buffer,
+            bind_group,
+        })
+    }
+
+    fn sources(model: &model::Model) -> Vec<[wgpu::Texture; SLOTS]> {
+        model
+            .materials
+            .iter()
+            .map(|material| material.textures.slots().map(|slot| slot.texture.clone()))
+            .collect()
+    }
+
+    fn factors(model: &model::Model) -> Vec<MaterialUniform> {
+        model
+            .materials
+            .iter()
+            .map(|material| material.uniform())
+            .collect()
+    }
+
+    /// Layers for `materials` materials, with spares where GL would take
+    /// the count for a plain 2D texture's or a cube map's and bind it so.
+    fn layer_count(materials: usize) -> u32 {
+        let layers = (materials as u32).max(2);
+        if layers.is_multiple_of(6) {
+            layers + 1
+        } else {
+            layers
+        }
+    }
+
+    /// Whether these hold `model`'s textures, sampled as it samples them.
+    pub fn is_of(&self, model: &model::Model) -> bool {
+        self.sources == Self::sources(model)
+            && model
+                .materials
+                .iter()
+                .all(|material| *material.sampler_settings() == self.sampler_settings)
+    }
+
+    /// Writes the materials' factors, which change without repacking.
+    pub fn write_factors(&self, queue: &wgpu::Queue, model: &model::Model) {
+        queue.write_buffer(
+            &self.factor_buffer,
+            0,
+            bytemuck::cast_slice(&Self::factors(model)),
+        );
+    }
+
+    /// Group 0 of the `PipelineKey::texture_arrays` pipelines.
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
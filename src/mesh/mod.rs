@@ -0,0 +1,81 @@
+use wgpu::util::DeviceExt;
+
+use crate::lod;
+use crate::model::{self, ModelVertex};
+
+pub mod optimize;
+pub mod primitives;
+
+/// Triangles in the standard vertex format, before they are uploaded.
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    pub vertices: Vec<ModelVertex>,
+    /// Counter-clockwise when seen from the front.
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `other`'s triangles to these.
+    pub fn append(&mut self, other: &MeshData) {
+        let base = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&other.vertices);
+        self.indices
+            .extend(other.indices.iter().map(|index| index + base));
+    }
+
+    /// Recomputes tangents and bitangents from the normals and UVs.
+    pub fn compute_tangents(&mut self) {
+        model::compute_tangents(&mut self.vertices, &self.indices);
+    }
+
+    pub fn bounds(&self) -> model::Bounds {
+        model::Bounds::from_points(self.vertices.iter().map(|v| v.position))
+    }
+
+    /// Uploads a mesh drawn with its model's `material`th material, with
+    /// `model::VertexEncoding::Full` vertices.
+    pub fn upload(&self, device: &wgpu::Device, name: &str, material: usize) -> model::Mesh {
+        let vertex_buffer = model::VertexEncoding::Full.create_vertex_buffer(
+            device,
+            &format!("{:?} Vertex Buffer", name),
+            &self.vertices,
+        );
+        let (indices, lods) = lod::generate(&self.vertices, &self.indices);
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
+        });
+        model::Mesh {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: self.indices.len() as u32,
+            material,
+            bounds: self.bounds(),
+            skin_buffer: None,
+            geometry: Some(model::MeshGeometry::new(&self.vertices, &self.indices)),
+            lods,
+        }
+    }
+
+    /// A single-mesh model with `material`, for scenes without model files.
+    pub fn into_model(
+        self,
+        device: &wgpu::Device,
+        name: &str,
+        material: model::Material,
+    ) -> model::Model {
+        model::Model {
+            meshes: vec![self.upload(device, name, 0)],
+            materials: vec![material],
+            skeleton: None,
+            animations: Vec::new(),
+            vertex_encoding: model::VertexEncoding::Full,
+        }
+    }
+}
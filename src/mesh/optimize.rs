@@ -0,0 +1,422 @@
+use cgmath::prelude::*;
+
+use crate::model::ModelVertex;
+
+// The post-transform cache `vertex_cache` orders triangles for, in
+// vertices. GPUs don't all have one this size, but an order that suits it
+// suits theirs too.
+const CACHE_SIZE: usize = 32;
+// The FIFO cache `overdraw` simulates to find where its clusters can start.
+const FIFO_SIZE: u32 = 16;
+// `overdraw` splits a cluster wherever that costs the first part at most
+// this many times the cache misses a triangle of the whole.
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+/// Reorders `indices`' triangles for the vertex cache and then for
+/// overdraw, and `vertices` into the order those triangles first use them,
+/// dropping any they don't. The same triangles are drawn, only in less
+/// time.
+///
+/// Returns where each of the original vertices went, or `u32::MAX` for
+/// those dropped, for `remap_vertices` of whatever else the mesh stores a
+/// vertex.
+pub fn optimize(vertices: &mut Vec<ModelVertex>, indices: &mut Vec<u32>) -> Vec<u32> {
+    *indices = vertex_cache(indices, vertices.len());
+    *indices = overdraw(vertices, indices);
+    let remap = vertex_fetch(indices, vertices.len());
+    *vertices = remap_vertices(vertices, &remap);
+    remap
+}
+
+/// `indices`' triangles in Tom Forsyth's linear-speed order: each one
+/// picked next is, among those sharing a vertex with the last few, the one
+/// whose vertices are most recently used and have the fewest triangles
+/// left, so the vertex shader runs about once a vertex rather than once a
+/// corner.
+pub fn vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    let indices = &indices[..triangle_count * 3];
+
+    // Each vertex's triangles not yet emitted, packed after one another:
+    // the first `remaining[v]` from `starts[v]`.
+    let mut remaining = vec![0u32; vertex_count];
+    for &index in indices {
+        remaining[index as usize] += 1;
+    }
+    let mut starts = Vec::with_capacity(vertex_count);
+    let mut start = 0;
+    for &count in &remaining {
+        starts.push(start);
+        start += count as usize;
+    }
+    let mut adjacency = vec![0u32; indices.len()];
+    let mut filled = starts.clone();
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &index in corners {
+            adjacency[filled[index as usize]] = triangle as u32;
+            filled[index as usize] += 1;
+        }
+    }
+
+    let mut scores = remaining
+        .iter()
+        .map(|&count| vertex_score(None, count))
+        .collect::<Vec<_>>();
+    let mut emitted = vec![false; triangle_count];
+    let mut cache = Vec::<u32>::with_capacity(CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(indices.len());
+    // Where to look for a triangle when none in the cache is left.
+    let mut next = 0;
+    let mut best = (triangle_count > 0).then_some(0);
+    while let Some(triangle) = best {
+        emitted[triangle] = true;
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        output.extend_from_slice(corners);
+        for &index in corners {
+            let v = index as usize;
+            let list = &mut adjacency[starts[v]..starts[v] + remaining[v] as usize];
+            if let Some(i) = list.iter().position(|&t| t as usize == triangle) {
+                list.swap(i, list.len() - 1);
+                remaining[v] -= 1;
+            }
+        }
+
+        let mut touched = corners.to_vec();
+        touched.extend(cache.iter().filter(|index| !corners.contains(index)));
+        for (position, &index) in touched.iter().enumerate() {
+            let v = index as usize;
+            scores[v] = vertex_score((position < CACHE_SIZE).then_some(position), remaining[v]);
+        }
+
+        // Only the triangles of the vertices just scored can score
+        // differently now.
+        best = None;
+        let mut best_score = f32::MIN;
+        for &index in &touched {
+            let v = index as usize;
+            for &t in &adjacency[starts[v]..starts[v] + remaining[v] as usize] {
+                let t = t as usize;
+                let score = indices[t * 3..t * 3 + 3]
+                    .iter()
+                    .map(|&index| scores[index as usize])
+                    .sum::<f32>();
+                if score > best_score {
+                    best = Some(t);
+                    best_score = score;
+                }
+            }
+        }
+        touched.truncate(CACHE_SIZE);
+        cache = touched;
+
+        if best.is_none() {
+            while next < triangle_count && emitted[next] {
+                next += 1;
+            }
+            best = (next < triangle_count).then_some(next);
+        }
+    }
+    output
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining: u32) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache = match cache_position {
+        None => 0.0,
+        // The last triangle's own vertices score a little lower, so it
+        // doesn't always continue into a strip.
+        Some(position) if position < 3 => 0.75,
+        Some(position) => (1.0 - (position - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(1.5),
+    };
+    // Vertices with few triangles left get them out of the way, or
+    // they'd be loaded again for each later.
+    cache + 2.0 * (remaining as f32).powf(-0.5)
+}
+
+/// `indices`' triangles, vertex cache ordered, split into clusters that
+/// keep most of that order, and the clusters sorted with those facing out
+/// from the mesh's center first. Those tend to hide what the rest draw,
+/// so fewer of its fragments get shaded only to be drawn over.
+pub fn overdraw(vertices: &[ModelVertex], indices: &[u32]) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    let indices = &indices[..triangle_count * 3];
+    if triangle_count == 0 {
+        return Vec::new();
+    }
+    let mut boundaries = cluster_starts(indices, vertices.len());
+    boundaries.push(triangle_count);
+
+    let position = |index: u32| cgmath::Vector3::from(vertices[index as usize].position);
+    let mut mesh_centroid = cgmath::Vector3::zero();
+    let mut mesh_area = 0.0;
+    // Each cluster's area-weighted centroid and normal.
+    let clusters = boundaries
+        .windows(2)
+        .map(|range| {
+            let mut centroid = cgmath::Vector3::zero();
+            let mut normal = cgmath::Vector3::zero();
+            let mut area = 0.0;
+            for corners in indices[range[0] * 3..range[1] * 3].chunks_exact(3) {
+                let [p0, p1, p2] = [0, 1, 2].map(|i| position(corners[i]));
+                let cross = (p1 - p0).cross(p2 - p0);
+                let triangle_area = cross.magnitude();
+                centroid += (p0 + p1 + p2) / 3.0 * triangle_area;
+                normal += cross;
+                area += triangle_area;
+            }
+            mesh_centroid += centroid;
+            mesh_area += area;
+            (range[0]..range[1], centroid, normal, area)
+        })
+        .collect::<Vec<_>>();
+    if mesh_area > 0.0 {
+        mesh_centroid /= mesh_area;
+    }
+
+    let mut keyed = clusters
+        .into_iter()
+        .map(|(range, centroid, normal, area)| {
+            let key = if area > 0.0 && normal.magnitude2() > 0.0 {
+                (centroid / area - mesh_centroid).dot(normal.normalize())
+            } else {
+                0.0
+            };
+            (range, key)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    keyed
+        .into_iter()
+        .flat_map(|(range, _)| &indices[range.start * 3..range.end * 3])
+        .copied()
+        .collect()
+}
+
+/// The first triangle of each of `indices`' clusters: wherever a FIFO
+/// cache would have had none of a triangle's vertices, and then wherever a
+/// cluster can be split without more than `OVERDRAW_THRESHOLD` times the
+/// misses a triangle it had.
+fn cluster_starts(indices: &[u32], vertex_count: usize) -> Vec<usize> {
+    let mut cache = FifoCache::new(vertex_count);
+    let mut hard = Vec::new();
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        if cache.misses(corners) == 3 {
+            hard.push(triangle);
+        }
+    }
+    hard.push(indices.len() / 3);
+
+    let mut boundaries = Vec::new();
+    for range in hard.windows(2) {
+        let (start, end) = (range[0], range[1]);
+        cache.clear();
+        let misses = indices[start * 3..end * 3]
+            .chunks_exact(3)
+            .map(|corners| cache.misses(corners))
+            .sum::<u32>();
+        let threshold = OVERDRAW_THRESHOLD * misses as f32 / (end - start) as f32;
+
+        boundaries.push(start);
+        cache.clear();
+        let (mut misses, mut triangles) = (0, 0);
+        for triangle in start..end {
+            misses += cache.misses(&indices[triangle * 3..triangle * 3 + 3]);
+            triangles += 1;
+            if misses as f32 / triangles as f32 <= threshold && triangle + 1 < end {
+                boundaries.push(triangle + 1);
+                cache.clear();
+                (misses, triangles) = (0, 0);
+            }
+        }
+    }
+    boundaries
+}
+
+// When each vertex last entered a simulated FIFO cache, counted in
+// vertices entering it, so it's still there while fewer than its size
+// have entered since.
+struct FifoCache {
+    entered: Vec<u32>,
+    time: u32,
+}
+
+impl FifoCache {
+    fn new(vertex_count: usize) -> Self {
+        Self {
+            entered: vec![0; vertex_count],
+            time: FIFO_SIZE + 1,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.time += FIFO_SIZE + 1;
+    }
+
+    // How many of `corners` weren't in the cache, which they all are now.
+    fn misses(&mut self, corners: &[u32]) -> u32 {
+        let mut misses = 0;
+        for &index in corners {
+            let entered = &mut self.entered[index as usize];
+            if self.time - *entered > FIFO_SIZE {
+                *entered = self.time;
+                self.time += 1;
+                misses += 1;
+            }
+        }
+        misses
+    }
+}
+
+/// Renumbers the vertices in the order `indices` first uses them, so the
+/// vertex buffer is read front to back. Returns where each vertex went, or
+/// `u32::MAX` for those `indices` doesn't use.
+pub fn vertex_fetch(indices: &mut [u32], vertex_count: usize) -> Vec<u32> {
+    let mut remap = vec![u32::MAX; vertex_count];
+    let mut next = 0;
+    for index in indices {
+        let new = &mut remap[*index as usize];
+        if *new == u32::MAX {
+            *new = next;
+            next += 1;
+        }
+        *index = *new;
+    }
+    remap
+}
+
+/// `vertices` moved to where `remap`, from `vertex_fetch`, says.
+pub fn remap_vertices<T: Copy>(vertices: &[T], remap: &[u32]) -> Vec<T> {
+    let mut order = remap
+        .iter()
+        .enumerate()
+        .filter(|&(_, &new)| new != u32::MAX)
+        .map(|(old, &new)| (new, old))
+        .collect::<Vec<_>>();
+    order.sort_unstable();
+    order.into_iter().map(|(_, old)| vertices[old]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A flat grid `size` quads across, two triangles each, in rows.
+    fn grid(size: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let vertex = |x: u32, y: u32| ModelVertex {
+            position: [x as f32, y as f32, 0.0],
+            tex_coords: [0.0; 2],
+            normal: [0.0, 0.0, 1.0],
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        };
+        let vertices = (0..=size)
+            .flat_map(|y| (0..=size).map(move |x| vertex(x, y)))
+            .collect();
+        let index = |x: u32, y: u32| y * (size + 1) + x;
+        let indices = (0..size)
+            .flat_map(|y| (0..size).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                [
+                    [index(x, y), index(x + 1, y), index(x + 1, y + 1)],
+                    [index(x, y), index(x + 1, y + 1), index(x, y + 1)],
+                ]
+            })
+            .flatten()
+            .collect();
+        (vertices, indices)
+    }
+
+    // `indices`' triangles, sorted, so orders compare equal.
+    fn triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles = indices
+            .chunks_exact(3)
+            .map(|corners| [corners[0], corners[1], corners[2]])
+            .collect::<Vec<_>>();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    // Average cache misses a triangle through `FifoCache`.
+    fn acmr(indices: &[u32], vertex_count: usize) -> f32 {
+        let mut cache = FifoCache::new(vertex_count);
+        let misses = indices
+            .chunks_exact(3)
+            .map(|corners| cache.misses(corners))
+            .sum::<u32>();
+        misses as f32 / (indices.len() / 3) as f32
+    }
+
+    #[test]
+    fn reordering_keeps_every_triangle() {
+        let (vertices, indices) = grid(12);
+        let cached = vertex_cache(&indices, vertices.len());
+        assert_eq!(triangles(&cached), triangles(&indices));
+        let overdrawn = overdraw(&vertices, &cached);
+        assert_eq!(triangles(&overdrawn), triangles(&indices));
+    }
+
+    #[test]
+    fn vertex_fetch_renumbers_consistently() {
+        let (vertices, indices) = grid(6);
+        let mut fetched = indices.clone();
+        let remap = vertex_fetch(&mut fetched, vertices.len());
+        for (&old, &new) in indices.iter().zip(&fetched) {
+            assert_eq!(remap[old as usize], new);
+        }
+        // Every vertex is used, so each gets its own new place.
+        let mut places = remap.clone();
+        places.sort_unstable();
+        assert_eq!(places, (0..vertices.len() as u32).collect::<Vec<_>>());
+        // New numbers are handed out in the order of first use.
+        assert_eq!(fetched[..3], [0, 1, 2]);
+
+        let moved = remap_vertices(&vertices, &remap);
+        for (&old, &new) in indices.iter().zip(&fetched) {
+            assert_eq!(
+                moved[new as usize].position,
+                vertices[old as usize].position
+            );
+        }
+    }
+
+    #[test]
+    fn vertex_fetch_drops_only_unused_vertices() {
+        let mut indices = vec![3, 1, 4];
+        let remap = vertex_fetch(&mut indices, 6);
+        assert_eq!(indices, [0, 1, 2]);
+        assert_eq!(remap, [u32::MAX, 1, u32::MAX, 0, 2, u32::MAX]);
+        assert_eq!(
+            remap_vertices(&[10, 11, 12, 13, 14, 15], &remap),
+            [13, 11, 14]
+        );
+    }
+
+    #[test]
+    fn optimize_draws_the_same_surface() {
+        let (mut vertices, mut indices) = grid(8);
+        let positions = |vertices: &[ModelVertex], indices: &[u32]| {
+            let mut triangles = indices
+                .chunks_exact(3)
+                .map(|corners| corners.iter().map(|&i| vertices[i as usize].position))
+                .map(|corners| corners.flatten().map(f32::to_bits).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            triangles.sort_unstable();
+            triangles
+        };
+        let before = positions(&vertices, &indices);
+        let vertex_count = vertices.len();
+        optimize(&mut vertices, &mut indices);
+        assert_eq!(vertices.len(), vertex_count);
+        assert_eq!(positions(&vertices, &indices), before);
+    }
+
+    #[test]
+    fn vertex_cache_does_not_worsen_acmr_on_a_grid() {
+        let (vertices, indices) = grid(32);
+        let before = acmr(&indices, vertices.len());
+        let after = acmr(&vertex_cache(&indices, vertices.len()), vertices.len());
+        assert!(after <= before, "ACMR went from {} to {}", before, after);
+    }
+}
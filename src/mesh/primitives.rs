@@ -0,0 +1,364 @@
+//! Generators for simple shapes, centered on the origin with +Y up.
+//!
+//! Every shape comes with normals, UVs (v running down the texture, as
+//! loaded images expect) and tangents. Curved shapes repeat the vertices
+//! along their UV seam so textures wrap without smearing.
+
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use cgmath::InnerSpace;
+
+use super::MeshData;
+use crate::model::ModelVertex;
+
+fn vertex(
+    position: cgmath::Vector3<f32>,
+    normal: cgmath::Vector3<f32>,
+    uv: [f32; 2],
+) -> ModelVertex {
+    ModelVertex {
+        position: position.into(),
+        tex_coords: uv,
+        normal: normal.into(),
+        tangent: [0.0; 3],
+        bitangent: [0.0; 3],
+    }
+}
+
+/// A flat grid spanning `u` and `v` from `center`, facing `v × u`, with
+/// `u` along the texture's width and `v` down its height.
+fn grid(
+    data: &mut MeshData,
+    center: cgmath::Vector3<f32>,
+    u: cgmath::Vector3<f32>,
+    v: cgmath::Vector3<f32>,
+    subdivisions: u32,
+) {
+    let n = subdivisions.max(1);
+    let normal = v.cross(u).normalize();
+    let base = data.vertices.len() as u32;
+    for j in 0..=n {
+        for i in 0..=n {
+            let (s, t) = (i as f32 / n as f32, j as f32 / n as f32);
+            let position = center + u * (s - 0.5) + v * (t - 0.5);
+            data.vertices.push(vertex(position, normal, [s, t]));
+        }
+    }
+    for j in 0..n {
+        for i in 0..n {
+            let a = base + j * (n + 1) + i;
+            let (b, c) = (a + 1, a + n + 1);
+            let d = c + 1;
+            data.indices.extend_from_slice(&[a, c, b, b, c, d]);
+        }
+    }
+}
+
+/// One point of a `lathe` profile: distance from the Y axis, height, the
+/// normal's outward and upward parts, and the texture's v.
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    normal: [f32; 2],
+    v: f32,
+}
+
+/// Sweeps `profile`, ordered downwards along the outside of the surface,
+/// once around the Y axis in `sectors` steps.
+fn lathe(data: &mut MeshData, profile: &[ProfilePoint], sectors: u32) {
+    let sectors = sectors.max(3);
+    let base = data.vertices.len() as u32;
+    for point in profile {
+        for j in 0..=sectors {
+            let u = j as f32 / sectors as f32;
+            let (sin, cos) = (u * TAU).sin_cos();
+            // Counter-clockwise seen from above, so u grows to the right
+            // seen from outside.
+            let outward = cgmath::Vector3::new(cos, 0.0, -sin);
+            let position = outward * point.radius + cgmath::Vector3::unit_y() * point.y;
+            let normal = (outward * point.normal[0] + cgmath::Vector3::unit_y() * point.normal[1])
+                .normalize();
+            data.vertices.push(vertex(position, normal, [u, point.v]));
+        }
+    }
+    let ring = sectors + 1;
+    for (i, rows) in profile.windows(2).enumerate() {
+        for j in 0..sectors {
+            let a = base + i as u32 * ring + j;
+            let (b, c) = (a + 1, a + ring);
+            let d = c + 1;
+            // Rows on the axis collapse to a point; skip the triangle that
+            // would have no area.
+            if rows[0].radius > f32::EPSILON {
+                data.indices.extend_from_slice(&[a, c, b]);
+            }
+            if rows[1].radius > f32::EPSILON {
+                data.indices.extend_from_slice(&[b, c, d]);
+            }
+        }
+    }
+}
+
+/// A disc at height `y` facing up or down, textured as seen from that side.
+fn disc(data: &mut MeshData, radius: f32, y: f32, up: bool, sectors: u32) {
+    let sectors = sectors.max(3);
+    let normal = if up {
+        cgmath::Vector3::unit_y()
+    } else {
+        -cgmath::Vector3::unit_y()
+    };
+    let uv = |x: f32, z: f32| {
+        let z = if up { z } else { -z };
+        [0.5 + x * 0.5, 0.5 + z * 0.5]
+    };
+    let center = data.vertices.len() as u32;
+    data.vertices
+        .push(vertex(cgmath::Vector3::unit_y() * y, normal, uv(0.0, 0.0)));
+    for j in 0..=sectors {
+        let (sin, cos) = (j as f32 / sectors as f32 * TAU).sin_cos();
+        let position = cgmath::Vector3::new(cos * radius, y, -sin * radius);
+        data.vertices.push(vertex(position, normal, uv(cos, -sin)));
+    }
+    for j in 0..sectors {
+        let (a, b) = (center + 1 + j, center + 2 + j);
+        if up {
+            data.indices.extend_from_slice(&[center, a, b]);
+        } else {
+            data.indices.extend_from_slice(&[center, b, a]);
+        }
+    }
+}
+
+/// A square in the XZ plane facing +Y, split into `subdivisions` cells
+/// along each side.
+pub fn plane(size: f32, subdivisions: u32) -> MeshData {
+    let mut data = MeshData::new();
+    grid(
+        &mut data,
+        cgmath::Vector3::new(0.0, 0.0, 0.0),
+        cgmath::Vector3::unit_x() * size,
+        cgmath::Vector3::unit_z() * size,
+        subdivisions,
+    );
+    data.compute_tangents();
+    data
+}
+
+/// A cube with the whole texture on each face.
+pub fn cube(size: f32) -> MeshData {
+    let (x, y, z) = (
+        cgmath::Vector3::unit_x() * size,
+        cgmath::Vector3::unit_y() * size,
+        cgmath::Vector3::unit_z() * size,
+    );
+    let half = size * 0.5;
+    let mut data = MeshData::new();
+    // (face normal, u, v) with v × u along the normal.
+    for (normal, u, v) in [
+        (cgmath::Vector3::unit_x(), -z, -y),
+        (-cgmath::Vector3::unit_x(), z, -y),
+        (cgmath::Vector3::unit_y(), x, z),
+        (-cgmath::Vector3::unit_y(), x, -z),
+        (cgmath::Vector3::unit_z(), x, -y),
+        (-cgmath::Vector3::unit_z(), -x, -y),
+    ] {
+        grid(&mut data, normal * half, u, v, 1);
+    }
+    data.compute_tangents();
+    data
+}
+
+/// A sphere of `stacks` rings from pole to pole, each of `sectors` quads.
+pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> MeshData {
+    let stacks = stacks.max(2);
+    let profile = (0..=stacks)
+        .map(|i| {
+            let v = i as f32 / stacks as f32;
+            let (sin, cos) = (v * PI).sin_cos();
+            ProfilePoint {
+                radius: radius * sin,
+                y: radius * cos,
+                normal: [sin, cos],
+                v,
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut data = MeshData::new();
+    lathe(&mut data, &profile, sectors);
+    data.compute_tangents();
+    data
+}
+
+/// A subdivided icosahedron: evenly sized triangles without the pinched
+/// poles of `uv_sphere`. Each subdivision quadruples the 20 faces.
+pub fn icosphere(radius: f32, subdivisions: u32) -> MeshData {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut points = [
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ]
+    .map(|p| cgmath::Vector3::from(p).normalize())
+    .to_vec();
+    let mut triangles: Vec<[usize; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // Edges shared by two triangles share their midpoint.
+        let mut midpoints = std::collections::HashMap::new();
+        let mut midpoint = |a: usize, b: usize, points: &mut Vec<cgmath::Vector3<f32>>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push(((points[a] + points[b]) * 0.5).normalize());
+                points.len() - 1
+            })
+        };
+        triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = midpoint(a, b, &mut points);
+                let bc = midpoint(b, c, &mut points);
+                let ca = midpoint(c, a, &mut points);
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // Mapped like `uv_sphere`, with u from the angle around Y.
+    let uv = |p: cgmath::Vector3<f32>| {
+        let u = (-p.z).atan2(p.x) / TAU;
+        [u.rem_euclid(1.0), p.y.clamp(-1.0, 1.0).acos() / PI]
+    };
+    let mut data = MeshData::new();
+    for triangle in triangles {
+        let mut uvs = triangle.map(|i| uv(points[i]));
+        // A triangle across the seam would stretch the whole texture over
+        // itself; move its low side past 1 instead.
+        let (min, max) = uvs.iter().fold((f32::MAX, f32::MIN), |(min, max), uv| {
+            (min.min(uv[0]), max.max(uv[0]))
+        });
+        if max - min > 0.5 {
+            for uv in &mut uvs {
+                if uv[0] < 0.5 {
+                    uv[0] += 1.0;
+                }
+            }
+        }
+        for (i, uv) in triangle.into_iter().zip(uvs) {
+            let normal = points[i];
+            data.indices.push(data.vertices.len() as u32);
+            data.vertices.push(vertex(normal * radius, normal, uv));
+        }
+    }
+    data.compute_tangents();
+    data
+}
+
+/// A capped cylinder along Y.
+pub fn cylinder(radius: f32, height: f32, sectors: u32) -> MeshData {
+    let half = height * 0.5;
+    let mut data = MeshData::new();
+    lathe(
+        &mut data,
+        &[
+            ProfilePoint {
+                radius,
+                y: half,
+                normal: [1.0, 0.0],
+                v: 0.0,
+            },
+            ProfilePoint {
+                radius,
+                y: -half,
+                normal: [1.0, 0.0],
+                v: 1.0,
+            },
+        ],
+        sectors,
+    );
+    disc(&mut data, radius, half, true, sectors);
+    disc(&mut data, radius, -half, false, sectors);
+    data.compute_tangents();
+    data
+}
+
+/// A cone along Y with its tip at the top and a capped base.
+pub fn cone(radius: f32, height: f32, sectors: u32) -> MeshData {
+    let half = height * 0.5;
+    // Perpendicular to the slope.
+    let slope = cgmath::Vector2::new(height, radius).normalize();
+    let mut data = MeshData::new();
+    lathe(
+        &mut data,
+        &[
+            ProfilePoint {
+                radius: 0.0,
+                y: half,
+                normal: slope.into(),
+                v: 0.0,
+            },
+            ProfilePoint {
+                radius,
+                y: -half,
+                normal: slope.into(),
+                v: 1.0,
+            },
+        ],
+        sectors,
+    );
+    disc(&mut data, radius, -half, false, sectors);
+    data.compute_tangents();
+    data
+}
+
+/// A ring around Y: a tube of `minor_radius` swept at `major_radius`, with
+/// `sectors` steps around the ring and `sides` around the tube.
+pub fn torus(major_radius: f32, minor_radius: f32, sectors: u32, sides: u32) -> MeshData {
+    let sides = sides.max(3);
+    // From the top of the tube down its outside, under and back up the
+    // inside.
+    let profile = (0..=sides)
+        .map(|i| {
+            let v = i as f32 / sides as f32;
+            let (sin, cos) = (FRAC_PI_2 - v * TAU).sin_cos();
+            ProfilePoint {
+                radius: major_radius + minor_radius * cos,
+                y: minor_radius * sin,
+                normal: [cos, sin],
+                v,
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut data = MeshData::new();
+    lathe(&mut data, &profile, sectors);
+    data.compute_tangents();
+    data
+}
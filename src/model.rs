@@ -1,9 +1,13 @@
+use crate::animation::{self, SkinVertex};
+use crate::instance::InstanceRaw;
+use crate::material::MaterialKindId;
+use crate::preprocess::ShaderDefs;
 use crate::texture;
+use cgmath::{InnerSpace, Transform};
 use std::ops::Range;
+use wgpu::util::DeviceExt;
 
-pub trait Vertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static>;
-}
+pub use crate::vertex::Vertex;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -11,50 +15,743 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    pub tangent: [f32; 3],
+    pub bitangent: [f32; 3],
 }
 
-impl Vertex for ModelVertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        wgpu::VertexBufferLayout {
-            array_stride: size_of::<ModelVertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-                wgpu::VertexAttribute {
-                    offset: (size_of::<[f32; 3]>() + size_of::<[f32; 2]>()) as wgpu::BufferAddress,
-                    shader_location: 2,
-                    format: wgpu::VertexFormat::Float32x3,
+crate::impl_vertex!(ModelVertex, Vertex {
+    position => 0: Float32x3,
+    tex_coords => 1: Float32x2,
+    normal => 2: Float32x3,
+    tangent => 3: Float32x3,
+    bitangent => 4: Float32x3,
+});
+
+/// A `ModelVertex` in a quarter of the space, for
+/// `VertexEncoding::Quantized`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct QuantizedVertex {
+    /// The position, with the handedness of the tangent frame in w: the
+    /// sign of the bitangent along `normal × tangent`, or 0 where there's
+    /// no tangent.
+    pub position: [half::f16; 4],
+    pub tex_coords: [half::f16; 2],
+    /// The normal, then the tangent, each octahedral-encoded into two
+    /// unorm bytes.
+    pub normal_tangent: [u8; 4],
+}
+
+impl QuantizedVertex {
+    pub fn new(vertex: &ModelVertex) -> Self {
+        let normal = cgmath::Vector3::from(vertex.normal);
+        let tangent = cgmath::Vector3::from(vertex.tangent);
+        let handedness = if tangent.magnitude2() < f32::EPSILON {
+            0.0
+        } else if normal.cross(tangent).dot(vertex.bitangent.into()) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        let [x, y, z] = vertex.position;
+        let [normal_x, normal_y] = octahedral_encode(normal);
+        let [tangent_x, tangent_y] = octahedral_encode(tangent);
+        Self {
+            position: [x, y, z, handedness].map(half::f16::from_f32),
+            tex_coords: vertex.tex_coords.map(half::f16::from_f32),
+            normal_tangent: [normal_x, normal_y, tangent_x, tangent_y],
+        }
+    }
+}
+
+crate::impl_vertex!(QuantizedVertex, Vertex {
+    position => 0: Float16x4,
+    tex_coords => 1: Float16x2,
+    normal_tangent => 2: Unorm8x4,
+});
+
+// `v` folded onto an octahedron and unwrapped into unorm bytes, as
+// include/vertex.wgsl's `unpack_octahedral` reads it back.
+fn octahedral_encode(v: cgmath::Vector3<f32>) -> [u8; 2] {
+    let length = v.x.abs() + v.y.abs() + v.z.abs();
+    if length < f32::EPSILON {
+        return [128; 2];
+    }
+    let p = v / length;
+    let folded = if p.z >= 0.0 {
+        [p.x, p.y]
+    } else {
+        [
+            (1.0 - p.y.abs()) * p.x.signum(),
+            (1.0 - p.x.abs()) * p.y.signum(),
+        ]
+    };
+    folded.map(|c| ((c * 0.5 + 0.5) * 255.0).round() as u8)
+}
+
+/// How a model's meshes store their vertices, picked when it's loaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum VertexEncoding {
+    /// `ModelVertex`, 56 bytes a vertex.
+    #[default]
+    Full,
+    /// `QuantizedVertex`, 16 bytes a vertex. Half floats keep positions
+    /// to about a thousandth of a unit near the model's origin, but only a
+    /// sixteenth a hundred units out, and normals and tangents come back
+    /// within a degree or so.
+    Quantized,
+}
+
+impl VertexEncoding {
+    pub const ALL: [Self; 2] = [Self::Full, Self::Quantized];
+
+    pub fn vertex_size(self) -> wgpu::BufferAddress {
+        match self {
+            Self::Full => size_of::<ModelVertex>() as wgpu::BufferAddress,
+            Self::Quantized => size_of::<QuantizedVertex>() as wgpu::BufferAddress,
+        }
+    }
+
+    pub fn desc(self) -> wgpu::VertexBufferLayout<'static> {
+        match self {
+            Self::Full => ModelVertex::desc(),
+            Self::Quantized => QuantizedVertex::desc(),
+        }
+    }
+
+    /// `defs` plus those of shaders reading this encoding: QUANTIZED for
+    /// `Quantized`, which include/vertex.wgsl unpacks.
+    pub fn shader_defs(self, defs: ShaderDefs) -> ShaderDefs {
+        match self {
+            Self::Full => defs,
+            Self::Quantized => defs.with_flag("QUANTIZED"),
+        }
+    }
+
+    /// A vertex buffer holding `vertices` in this encoding.
+    pub fn create_vertex_buffer(
+        self,
+        device: &wgpu::Device,
+        label: &str,
+        vertices: &[ModelVertex],
+    ) -> wgpu::Buffer {
+        let quantized;
+        let contents = match self {
+            Self::Full => bytemuck::cast_slice(vertices),
+            Self::Quantized => {
+                quantized = vertices
+                    .iter()
+                    .map(QuantizedVertex::new)
+                    .collect::<Vec<_>>();
+                bytemuck::cast_slice(&quantized)
+            }
+        };
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+        })
+    }
+}
+
+impl std::fmt::Display for VertexEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Full => "full",
+            Self::Quantized => "quantized",
+        })
+    }
+}
+
+/// What loading a model does to its meshes beyond reading them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImportOptions {
+    pub vertex_encoding: VertexEncoding,
+    /// Whether to reorder each mesh's triangles and vertices with
+    /// `mesh::optimize`, for the GPU's caches rather than the file's order.
+    pub optimize: bool,
+    /// Whether meshes get coarser levels of detail: those a glTF names
+    /// with `MSFT_lod`, or else ones generated with `lod::generate`.
+    pub lods: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            vertex_encoding: VertexEncoding::default(),
+            optimize: true,
+            lods: true,
+        }
+    }
+}
+
+/// Vertex buffers of the passes that draw models: the vertices in
+/// `encoding` at slot 0, `InstanceRaw` at 1 and, for skinned pipelines,
+/// `SkinVertex` at 2.
+pub fn vertex_buffers(
+    skinned: bool,
+    encoding: VertexEncoding,
+) -> Vec<wgpu::VertexBufferLayout<'static>> {
+    let mut buffers = vec![encoding.desc(), InstanceRaw::desc()];
+    if skinned {
+        buffers.push(SkinVertex::desc());
+    }
+    buffers
+}
+
+/// Fills in `tangent` and `bitangent` from the triangle UVs.
+///
+/// The bitangent follows decreasing `v`, since wgpu's texture origin is the
+/// top-left corner and normal maps store +Y pointing up the image.
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut tangents = vec![cgmath::Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+    let mut bitangents = tangents.clone();
+
+    for triangle in indices.chunks_exact(3) {
+        let [i0, i1, i2] = [0, 1, 2].map(|i| triangle[i] as usize);
+        let pos0 = cgmath::Vector3::from(vertices[i0].position);
+        let pos1 = cgmath::Vector3::from(vertices[i1].position);
+        let pos2 = cgmath::Vector3::from(vertices[i2].position);
+        let uv0 = cgmath::Vector2::from(vertices[i0].tex_coords);
+        let uv1 = cgmath::Vector2::from(vertices[i1].tex_coords);
+        let uv2 = cgmath::Vector2::from(vertices[i2].tex_coords);
+
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        let det = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        for i in [i0, i1, i2] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = cgmath::Vector3::from(vertex.normal);
+        if normal.magnitude2() < f32::EPSILON {
+            continue;
+        }
+        let normal = normal.normalize();
+
+        // Gram-Schmidt against the normal; fall back to any perpendicular
+        // axis where the UVs were degenerate.
+        let mut tangent = tangents[i] - normal * normal.dot(tangents[i]);
+        if tangent.magnitude2() < f32::EPSILON {
+            let axis = if normal.x.abs() < 0.9 {
+                cgmath::Vector3::unit_x()
+            } else {
+                cgmath::Vector3::unit_y()
+            };
+            tangent = axis - normal * normal.dot(axis);
+        }
+        let tangent = tangent.normalize();
+        let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+
+        vertex.tangent = tangent.into();
+        vertex.bitangent = (normal.cross(tangent) * handedness).into();
+    }
+}
+
+pub fn create_material_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    };
+    let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            texture_entry(0),
+            sampler_entry(1),
+            texture_entry(2),
+            sampler_entry(3),
+            texture_entry(4),
+            sampler_entry(5),
+            texture_entry(6),
+            sampler_entry(7),
+            texture_entry(8),
+            sampler_entry(9),
+            wgpu::BindGroupLayoutEntry {
+                binding: 10,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
                 },
-            ],
+                count: None,
+            },
+        ],
+        label: Some("texture_bind_group_layout"),
+    })
+}
+
+/// Texture slots of a metallic-roughness material.
+///
+/// `metallic_roughness` follows glTF: roughness in green, metallic in blue.
+/// `occlusion` is read from red.
+#[derive(Clone)]
+pub struct MaterialTextures {
+    pub diffuse: texture::Texture,
+    pub normal: texture::Texture,
+    pub metallic_roughness: texture::Texture,
+    pub occlusion: texture::Texture,
+    pub emissive: texture::Texture,
+}
+
+impl MaterialTextures {
+    /// Uses `diffuse` as the albedo and neutral textures for every other slot,
+    /// leaving the factors in charge.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        diffuse: texture::Texture,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            diffuse,
+            normal: texture::Texture::flat_normal(device, queue)?,
+            metallic_roughness: texture::Texture::from_color(
+                device,
+                queue,
+                [255; 4],
+                "metallic_roughness",
+            )?,
+            occlusion: texture::Texture::from_color(device, queue, [255; 4], "occlusion")?,
+            emissive: texture::Texture::from_color(device, queue, [255; 4], "emissive")?,
+        })
+    }
+
+    /// Every slot, in the order of their bindings.
+    pub fn slots(&self) -> [&texture::Texture; 5] {
+        [
+            &self.diffuse,
+            &self.normal,
+            &self.metallic_roughness,
+            &self.occlusion,
+            &self.emissive,
+        ]
+    }
+}
+
+/// Scalar factors multiplied with the matching texture slots.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MaterialFactors {
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    /// Scales `emissive`, a color from 0 to 1, past bloom's threshold, so
+    /// lamps and signs glow rather than only showing unlit.
+    pub emissive_strength: f32,
+    pub occlusion_strength: f32,
+    pub normal_scale: f32,
+}
+
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            metallic: 1.0,
+            roughness: 1.0,
+            emissive: [0.0; 3],
+            emissive_strength: 1.0,
+            occlusion_strength: 1.0,
+            normal_scale: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct MaterialUniform {
+    base_color: [f32; 4],
+    emissive: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    lod_bias: f32,
+}
+
+impl MaterialUniform {
+    fn new(factors: MaterialFactors, lod_bias: f32) -> Self {
+        Self {
+            base_color: factors.base_color,
+            emissive: factors.emissive.map(|c| c * factors.emissive_strength),
+            metallic: factors.metallic,
+            roughness: factors.roughness,
+            occlusion_strength: factors.occlusion_strength,
+            normal_scale: factors.normal_scale,
+            lod_bias,
         }
     }
 }
 
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture,
+    pub textures: MaterialTextures,
+    factors: MaterialFactors,
+    factor_buffer: wgpu::Buffer,
+    sampler_override: Option<texture::SamplerSettings>,
+    sampler_settings: texture::SamplerSettings,
+    layout: wgpu::BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
+    /// Picks the pipeline meshes with this material are drawn with; the
+    /// kind's bind group layout must match the one `bind_group` was built
+    /// against.
+    pub kind: MaterialKindId,
+}
+
+impl Material {
+    /// Every slot is sampled with one sampler built from the default
+    /// `SamplerSettings`, not the textures' own; `set_sampler` changes it.
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        textures: MaterialTextures,
+        factors: MaterialFactors,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let sampler_settings = texture::SamplerSettings::default();
+        let factor_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Factors", name)),
+            contents: bytemuck::cast_slice(&[MaterialUniform::new(
+                factors,
+                sampler_settings.lod_bias,
+            )]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = Self::create_bind_group(
+            device,
+            name,
+            &textures,
+            &sampler_settings,
+            &factor_buffer,
+            layout,
+        );
+
+        Self {
+            name: name.to_string(),
+            textures,
+            factors,
+            factor_buffer,
+            sampler_override: None,
+            sampler_settings,
+            layout: layout.clone(),
+            bind_group,
+            kind: MaterialKindId::STANDARD,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        name: &str,
+        textures: &MaterialTextures,
+        sampler_settings: &texture::SamplerSettings,
+        factor_buffer: &wgpu::Buffer,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        let sampler = device.create_sampler(&sampler_settings.descriptor());
+        let slots = textures.slots();
+        let mut entries = Vec::with_capacity(slots.len() * 2 + 1);
+        for (i, texture) in slots.into_iter().enumerate() {
+            entries.push(wgpu::BindGroupEntry {
+                binding: i as u32 * 2,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: i as u32 * 2 + 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            });
+        }
+        entries.push(wgpu::BindGroupEntry {
+            binding: 10,
+            resource: factor_buffer.as_entire_binding(),
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some(name),
+        })
+    }
+
+    pub fn with_kind(mut self, kind: MaterialKindId) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn factors(&self) -> &MaterialFactors {
+        &self.factors
+    }
+
+    pub fn set_factors(&mut self, queue: &wgpu::Queue, factors: MaterialFactors) {
+        self.factors = factors;
+        self.write_uniform(queue);
+    }
+
+    /// The factors as the shaders read them.
+    pub(crate) fn uniform(&self) -> MaterialUniform {
+        MaterialUniform::new(self.factors, self.sampler_settings.lod_bias)
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.factor_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform()]),
+        );
+    }
+
+    /// Samples `textures` from now on, rebuilding the bind group.
+    pub fn set_textures(&mut self, device: &wgpu::Device, textures: MaterialTextures) {
+        self.textures = textures;
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.name,
+            &self.textures,
+            &self.sampler_settings,
+            &self.factor_buffer,
+            &self.layout,
+        );
+    }
+
+    /// Settings this material keeps whatever the scene default is.
+    pub fn sampler_override(&self) -> Option<texture::SamplerSettings> {
+        self.sampler_override
+    }
+
+    /// The settings the material is currently sampled with.
+    pub fn sampler_settings(&self) -> &texture::SamplerSettings {
+        &self.sampler_settings
+    }
+
+    /// Samples with `sampler_override`, or with `default` when there is
+    /// none, rebuilding the bind group if that changes anything.
+    pub fn set_sampler(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sampler_override: Option<texture::SamplerSettings>,
+        default: texture::SamplerSettings,
+    ) {
+        self.sampler_override = sampler_override;
+        let settings = sampler_override.unwrap_or(default);
+        if settings == self.sampler_settings {
+            return;
+        }
+        self.sampler_settings = settings;
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.name,
+            &self.textures,
+            &settings,
+            &self.factor_buffer,
+            &self.layout,
+        );
+        self.write_uniform(queue);
+    }
 }
+
+/// Axis-aligned box around a mesh, in the mesh's own space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bounds {
+    pub min: cgmath::Point3<f32>,
+    pub max: cgmath::Point3<f32>,
+}
+
+impl Bounds {
+    /// The smallest box holding `points`; a point at the origin when there
+    /// are none.
+    pub fn from_points(points: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        let mut points = points.into_iter().map(cgmath::Point3::from);
+        let Some(first) = points.next() else {
+            return Self {
+                min: cgmath::Point3::new(0.0, 0.0, 0.0),
+                max: cgmath::Point3::new(0.0, 0.0, 0.0),
+            };
+        };
+        points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |bounds, p| Self {
+                min: cgmath::Point3::new(
+                    bounds.min.x.min(p.x),
+                    bounds.min.y.min(p.y),
+                    bounds.min.z.min(p.z),
+                ),
+                max: cgmath::Point3::new(
+                    bounds.max.x.max(p.x),
+                    bounds.max.y.max(p.y),
+                    bounds.max.z.max(p.z),
+                ),
+            },
+        )
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds::from_points([
+            self.min.into(),
+            self.max.into(),
+            other.min.into(),
+            other.max.into(),
+        ])
+    }
+
+    pub fn center(&self) -> cgmath::Point3<f32> {
+        cgmath::Point3::new(
+            (self.min.x + self.max.x) * 0.5,
+            (self.min.y + self.max.y) * 0.5,
+            (self.min.z + self.max.z) * 0.5,
+        )
+    }
+
+    /// Radius of the sphere around `center` that holds the box.
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).magnitude() * 0.5
+    }
+
+    /// The axis-aligned box holding this one's corners after `transform`.
+    pub fn transformed(&self, transform: &cgmath::Matrix4<f32>) -> Bounds {
+        Bounds::from_points((0..8).map(|corner| {
+            let point = cgmath::Point3::new(
+                if corner & 1 == 0 {
+                    self.min.x
+                } else {
+                    self.max.x
+                },
+                if corner & 2 == 0 {
+                    self.min.y
+                } else {
+                    self.max.y
+                },
+                if corner & 4 == 0 {
+                    self.min.z
+                } else {
+                    self.max.z
+                },
+            );
+            transform.transform_point(point).into()
+        }))
+    }
+}
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    pub bounds: Bounds,
+    /// The `SkinVertex` buffer, for meshes of a model with a skeleton.
+    pub skin_buffer: Option<wgpu::Buffer>,
+    /// Triangles for picking; None for skinned meshes, whose surface moves
+    /// with the pose.
+    pub geometry: Option<MeshGeometry>,
+    /// Coarser levels of detail, a glTF's own or from `lod::generate`, as
+    /// ranges of the index buffer after the full mesh's `num_elements`.
+    pub lods: Vec<Range<u32>>,
+}
+
+/// A CPU copy of a mesh's triangles, for ray casts against the surface
+/// rather than its bounds.
+#[derive(Debug, Clone, Default)]
+pub struct MeshGeometry {
+    pub positions: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshGeometry {
+    pub fn new(vertices: &[ModelVertex], indices: &[u32]) -> Self {
+        Self {
+            positions: vertices.iter().map(|vertex| vertex.position).collect(),
+            indices: indices.to_vec(),
+        }
+    }
+
+    pub fn triangles(&self) -> impl Iterator<Item = [cgmath::Point3<f32>; 3]> + '_ {
+        self.indices
+            .chunks_exact(3)
+            .map(|triangle| [0, 1, 2].map(|i| self.positions[triangle[i] as usize].into()))
+    }
+}
+
+impl Mesh {
+    /// Binds the vertex buffers at slots 0 and 2 and the index buffer, in
+    /// a pass or a render bundle.
+    pub fn set_buffers<'a>(&'a self, pass: &mut impl wgpu::util::RenderEncoder<'a>) {
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        if let Some(skin_buffer) = &self.skin_buffer {
+            pass.set_vertex_buffer(2, skin_buffer.slice(..));
+        }
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+    }
+
+    /// The indices of level of detail `level`, 0 being the full mesh, or of
+    /// the coarsest level there is.
+    pub fn indices(&self, level: usize) -> Range<u32> {
+        match level.checked_sub(1) {
+            Some(lod) => self
+                .lods
+                .get(lod)
+                .or(self.lods.last())
+                .cloned()
+                .unwrap_or(0..self.num_elements),
+            None => 0..self.num_elements,
+        }
+    }
 }
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    /// Set when the model has skins or animations; every mesh then has a
+    /// `skin_buffer` into its palette.
+    pub skeleton: Option<animation::Skeleton>,
+    pub animations: Vec<animation::AnimationClip>,
+    /// How every mesh's vertex buffer holds its vertices.
+    pub vertex_encoding: VertexEncoding,
+}
+
+impl Model {
+    pub fn is_skinned(&self) -> bool {
+        self.skeleton.is_some()
+    }
+
+    /// Bounds of all meshes together; a point at the origin for an empty
+    /// model.
+    pub fn bounds(&self) -> Bounds {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.bounds)
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or_else(|| Bounds::from_points([]))
+    }
 }
 
 pub trait DrawModel<'a> {
@@ -63,6 +760,7 @@ pub trait DrawModel<'a> {
         mesh: &'a Mesh,
         material: &'a Material,
         camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
     );
 
     fn draw_mesh_instanced(
@@ -71,15 +769,55 @@ pub trait DrawModel<'a> {
         material: &'a Material,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
     );
 
-    fn draw_model(&mut self, model: &'a Model, camera_bind_group: &'a wgpu::BindGroup);
+    /// `draw_mesh_instanced` at level of detail `level`, as `Mesh::indices`
+    /// picks it.
+    fn draw_mesh_lod(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        level: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+
+    /// Draws `mesh` with the `DrawIndexedIndirectArgs` at `indirect_offset`.
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        indirect_buffer: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+
+    fn draw_model(
+        &mut self,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
 
     fn draw_model_instanced(
         &mut self,
         model: &'a Model,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+
+    /// Draws mesh `i` with the `i`th `DrawIndexedIndirectArgs` in
+    /// `indirect_buffer`.
+    fn draw_model_indirect(
+        &mut self,
+        model: &'a Model,
+        indirect_buffer: &'a wgpu::Buffer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
     );
 }
 
@@ -92,8 +830,9 @@ where
         mesh: &'b Mesh,
         material: &'a Material,
         camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
     ) {
-        self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group);
+        self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group, light_bind_group);
     }
 
     fn draw_mesh_instanced(
@@ -102,16 +841,59 @@ where
         material: &'a Material,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.draw_mesh_lod(
+            mesh,
+            material,
+            0,
+            instances,
+            camera_bind_group,
+            light_bind_group,
+        );
+    }
+
+    fn draw_mesh_lod(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        level: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
     ) {
-        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        // Names each draw in graphics debugger captures.
+        self.insert_debug_marker(&mesh.name);
+        mesh.set_buffers(self);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
-        self.draw_indexed(0..mesh.num_elements, 0, instances);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed(mesh.indices(level), 0, instances);
     }
 
-    fn draw_model(&mut self, model: &'b Model, camera_bind_group: &'a wgpu::BindGroup) {
-        self.draw_model_instanced(model, 0..1, camera_bind_group);
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        indirect_buffer: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        mesh.set_buffers(self);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed_indirect(indirect_buffer, indirect_offset);
+    }
+
+    fn draw_model(
+        &mut self,
+        model: &'b Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.draw_model_instanced(model, 0..1, camera_bind_group, light_bind_group);
     }
 
     fn draw_model_instanced(
@@ -119,10 +901,172 @@ where
         model: &'a Model,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
     ) {
         for mesh in &model.meshes {
             let material = &model.materials[mesh.material];
-            self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
+            self.draw_mesh_instanced(
+                mesh,
+                material,
+                instances.clone(),
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
+
+    fn draw_model_indirect(
+        &mut self,
+        model: &'a Model,
+        indirect_buffer: &'a wgpu::Buffer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        let stride = size_of::<wgpu::util::DrawIndexedIndirectArgs>() as wgpu::BufferAddress;
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            self.draw_mesh_indirect(
+                mesh,
+                &model.materials[mesh.material],
+                indirect_buffer,
+                i as wgpu::BufferAddress * stride,
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::*;
+
+    // include/vertex.wgsl's `unpack_octahedral`, from the bytes the
+    // vertex fetch turns into unorm floats.
+    fn octahedral_decode([x, y]: [u8; 2]) -> Vector3<f32> {
+        let e = [x, y].map(|c| c as f32 / 255.0 * 2.0 - 1.0);
+        let mut n = Vector3::new(e[0], e[1], 1.0 - e[0].abs() - e[1].abs());
+        let t = (-n.z).max(0.0);
+        n.x += if n.x >= 0.0 { -t } else { t };
+        n.y += if n.y >= 0.0 { -t } else { t };
+        n.normalize()
+    }
+
+    // Evenly spread over the sphere, plus the axes, where the octahedron
+    // folds.
+    fn directions() -> Vec<Vector3<f32>> {
+        let count = 10_000;
+        let golden = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+        let mut directions = (0..count)
+            .map(|i| {
+                let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+                let radius = (1.0 - y * y).sqrt();
+                let angle = golden * i as f32;
+                Vector3::new(radius * angle.cos(), y, radius * angle.sin())
+            })
+            .collect::<Vec<_>>();
+        for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+            directions.extend([axis, -axis]);
+        }
+        directions
+    }
+
+    fn degrees_between(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+        a.cross(b).magnitude().atan2(a.dot(b)).to_degrees()
+    }
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2], normal: Vector3<f32>) -> ModelVertex {
+        // Any direction across the normal does for a tangent.
+        let across = if normal.x.abs() < 0.9 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        let tangent = normal.cross(across).normalize();
+        ModelVertex {
+            position,
+            tex_coords,
+            normal: normal.into(),
+            tangent: tangent.into(),
+            bitangent: normal.cross(tangent).into(),
+        }
+    }
+
+    #[test]
+    fn normals_and_tangents_come_back_within_a_degree() {
+        for direction in directions() {
+            let vertex = vertex([0.0; 3], [0.0; 2], direction);
+            let [nx, ny, tx, ty] = QuantizedVertex::new(&vertex).normal_tangent;
+            let normal = octahedral_decode([nx, ny]);
+            let tangent = octahedral_decode([tx, ty]);
+            assert!(degrees_between(direction, normal) < 1.0, "{:?}", direction);
+            assert!(degrees_between(vertex.tangent.into(), tangent) < 1.0);
+        }
+        // Only the direction is kept.
+        let direction = Vector3::new(0.3, -0.5, 0.8);
+        assert_eq!(
+            octahedral_encode(direction),
+            octahedral_encode(direction * 7.0)
+        );
+    }
+
+    #[test]
+    fn handedness_follows_the_bitangent() {
+        let mut vertex = vertex([0.0; 3], [0.0; 2], Vector3::unit_z());
+        assert_eq!(QuantizedVertex::new(&vertex).position[3].to_f32(), 1.0);
+        vertex.bitangent = vertex.bitangent.map(|c| -c);
+        assert_eq!(QuantizedVertex::new(&vertex).position[3].to_f32(), -1.0);
+        vertex.tangent = [0.0; 3];
+        assert_eq!(QuantizedVertex::new(&vertex).position[3].to_f32(), 0.0);
+    }
+
+    #[test]
+    fn positions_keep_half_float_precision() {
+        // Half floats have 11 significant bits, so round to within 2^-11
+        // of the value, or 2^-25 among the subnormals near zero.
+        let error = |x: f32| {
+            let vertex = vertex([x, -x, x * 0.5], [0.0; 2], Vector3::unit_z());
+            let position = QuantizedVertex::new(&vertex)
+                .position
+                .map(half::f16::to_f32);
+            (0..3)
+                .map(|i| (position[i] - vertex.position[i]).abs())
+                .fold(0.0, f32::max)
+        };
+        for x in [
+            0.0, 1e-7, 3e-5, 0.001, 0.1234, 0.9999, 1.0, 3.3, 77.7, 1000.1, 65000.0,
+        ] {
+            assert!(
+                error(x) <= (x.abs() * 2f32.powi(-11)).max(2f32.powi(-25)),
+                "{x}"
+            );
+        }
+        assert!(error(1.0) < 0.001);
+        assert!(error(99.99) < 1.0 / 16.0);
+        // The largest half float comes back exactly.
+        assert_eq!(error(65504.0), 0.0);
+    }
+
+    #[test]
+    fn tex_coords_keep_half_float_precision() {
+        let tex_coords = |uv: [f32; 2]| {
+            let vertex = vertex([0.0; 3], uv, Vector3::unit_z());
+            QuantizedVertex::new(&vertex)
+                .tex_coords
+                .map(half::f16::to_f32)
+        };
+        assert_eq!(tex_coords([0.0, 1.0]), [0.0, 1.0]);
+        assert_eq!(tex_coords([0.5, 0.25]), [0.5, 0.25]);
+        for i in 0..=1000 {
+            let u = i as f32 / 1000.0;
+            let [back, _] = tex_coords([u, 0.0]);
+            assert!((back - u).abs() <= 2f32.powi(-12), "{u}");
+        }
+        // Tiling coordinates lose precision as they grow.
+        for u in [-100.3, 12.34, 100.3] {
+            let [back, _] = tex_coords([u, 0.0]);
+            assert!((back - u).abs() <= 1.0 / 32.0, "{u}");
         }
     }
 }
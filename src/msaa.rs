@@ -0,0 +1,224 @@
+use crate::post::taa::Taa;
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+/// Multisampled color, velocity and depth targets the forward paths draw
+/// the opaque scene and the sky into, resolved into the single-sampled ones
+/// every pass after reads.
+///
+/// Color and velocity resolve at the end of the passes drawing them; depth
+/// can't, so `resolve_depth` copies over each pixel's farthest sample.
+pub struct Msaa {
+    color_format: wgpu::TextureFormat,
+    color: wgpu::TextureView,
+    velocity: wgpu::TextureView,
+    depth: wgpu::TextureView,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    resolve_pipeline: wgpu::RenderPipeline,
+    // `resolve_pipeline` for depth drawn with `reverse_z`.
+    reversed_resolve_pipeline: wgpu::RenderPipeline,
+}
+
+impl Msaa {
+    /// Samples a pixel: the one count every adapter has for the HDR,
+    /// velocity and depth formats.
+    pub const SAMPLE_COUNT: u32 = 4;
+
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Msaa::layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: true,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Msaa Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let [resolve_pipeline, reversed_resolve_pipeline] =
+            Self::create_pipelines(device, &pipeline_layout);
+        let [color, velocity, depth] = Self::create_targets(device, color_format, width, height);
+        let bind_group = Self::create_bind_group(device, &layout, &depth);
+        Self {
+            color_format,
+            color,
+            velocity,
+            depth,
+            layout,
+            bind_group,
+            pipeline_layout,
+            resolve_pipeline,
+            reversed_resolve_pipeline,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+    ) -> [wgpu::RenderPipeline; 2] {
+        let shader = crate::post::create_shader(
+            device,
+            "Msaa Shader",
+            &preprocess::builtin_source("msaa.wgsl", &ShaderDefs::new()),
+        );
+        ["fs_resolve_depth", "fs_resolve_reversed_depth"].map(|entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        })
+    }
+
+    /// Compiles the pipelines again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        [self.resolve_pipeline, self.reversed_resolve_pipeline] =
+            Self::create_pipelines(device, &self.pipeline_layout);
+    }
+
+    // The color, velocity and depth targets, in that order.
+    fn create_targets(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> [wgpu::TextureView; 3] {
+        let target = |format, usage, label| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: width.max(1),
+                        height: height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: Self::SAMPLE_COUNT,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor {
+                    label: Some(label),
+                    ..Default::default()
+                })
+        };
+        let attachment = wgpu::TextureUsages::RENDER_ATTACHMENT;
+        [
+            target(color_format, attachment, "Msaa::color"),
+            target(Taa::VELOCITY_FORMAT, attachment, "Msaa::velocity"),
+            target(
+                texture::Texture::DEPTH_FORMAT,
+                attachment | wgpu::TextureUsages::TEXTURE_BINDING,
+                "Msaa::depth",
+            ),
+        ]
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        depth: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Msaa::bind_group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(depth),
+            }],
+        })
+    }
+
+    /// Recreates the targets at `width` x `height`, the size of the ones
+    /// they resolve into.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        [self.color, self.velocity, self.depth] =
+            Self::create_targets(device, self.color_format, width, height);
+        self.bind_group = Self::create_bind_group(device, &self.layout, &self.depth);
+    }
+
+    /// Drawn in place of the HDR target, resolving into it.
+    pub fn color(&self) -> &wgpu::TextureView {
+        &self.color
+    }
+
+    /// Drawn in place of `Taa::velocity`, resolving into it.
+    pub fn velocity(&self) -> &wgpu::TextureView {
+        &self.velocity
+    }
+
+    /// Drawn in place of the depth buffer; see `resolve_depth`.
+    pub fn depth(&self) -> &wgpu::TextureView {
+        &self.depth
+    }
+
+    /// Writes the farthest of each pixel's depth samples into `target`,
+    /// with far at 0 when `reverse_z`.
+    pub fn resolve_depth(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        reverse_z: bool,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Msaa::resolve_depth"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: target,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(if reverse_z {
+            &self.reversed_resolve_pipeline
+        } else {
+            &self.resolve_pipeline
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
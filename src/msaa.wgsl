@@ -0,0 +1,28 @@
+// Resolves the multisampled depth buffer into a single-sampled one, each
+// pixel taking its farthest sample so the depth pyramid built from it
+// never hides what shows at an edge.
+
+// Bound as a plain float texture: GL can't `textureLoad` depth textures.
+@group(0) @binding(0)
+var depth: texture_multisampled_2d<f32>;
+
+fn farthest(position: vec4<f32>, reverse_z: bool) -> f32 {
+    let pixel = vec2<i32>(position.xy);
+    var resolved = textureLoad(depth, pixel, 0).r;
+    for (var i = 1; i < i32(textureNumSamples(depth)); i++) {
+        let other = textureLoad(depth, pixel, i).r;
+        resolved = select(max(resolved, other), min(resolved, other), reverse_z);
+    }
+    return resolved;
+}
+
+@fragment
+fn fs_resolve_depth(in: VertexOutput) -> @builtin(frag_depth) f32 {
+    return farthest(in.clip_position, false);
+}
+
+// `fs_resolve_depth` for a reversed depth buffer, where far is 0.
+@fragment
+fn fs_resolve_reversed_depth(in: VertexOutput) -> @builtin(frag_depth) f32 {
+    return farthest(in.clip_position, true);
+}
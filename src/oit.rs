@@ -0,0 +1,302 @@
+use crate::pipeline_cache::PipelineCache;
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+/// Weighted-blended order-independent transparency (McGuire and Bavoil),
+/// for materials whose surfaces overlap too much to sort.
+///
+/// Their pipelines, built with `create_accumulate_pipeline`, add weighted
+/// premultiplied color to the accumulation target and multiply revealage,
+/// the share of the background left showing, down from one. `composite`
+/// then divides the sums out over the HDR target. The result is only an
+/// approximation: near and far surfaces are told apart by depth weights,
+/// not by drawing order.
+pub struct Oit {
+    accum: texture::Texture,
+    revealage: texture::Texture,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    output_format: wgpu::TextureFormat,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Oit {
+    pub const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+    pub fn new(
+        device: &wgpu::Device,
+        output_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Oit::layout"),
+            entries: &[entry(0), entry(1)],
+        });
+        let (accum, revealage) = Self::create_targets(device, width, height);
+        let bind_group = Self::create_bind_group(device, &layout, &accum, &revealage);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Oit::composite"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, output_format);
+
+        Self {
+            accum,
+            revealage,
+            layout,
+            bind_group,
+            pipeline_layout,
+            output_format,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        output_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader =
+            preprocess::builtin_module(device, "Oit::composite", "oit.wgsl", &ShaderDefs::new());
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Oit::composite"),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_composite"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: output_format,
+                    // color * (1 - revealage) + background * revealage.
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                            dst_factor: wgpu::BlendFactor::SrcAlpha,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Compiles the pipeline again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(device, &self.pipeline_layout, self.output_format);
+    }
+
+    /// A pipeline accumulating materials with `scene_shader`, a module of
+    /// shader.wgsl whose `layout` starts with the material group, from
+    /// meshes laid out as `vertex_buffers`. Depth is tested against the
+    /// opaque scene but never written.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_accumulate_pipeline(
+        device: &wgpu::Device,
+        cache: &PipelineCache,
+        label: &str,
+        layout: &wgpu::PipelineLayout,
+        scene_shader: &wgpu::ShaderModule,
+        vertex_buffers: &[wgpu::VertexBufferLayout],
+        cull_mode: Option<wgpu::Face>,
+        depth_compare: wgpu::CompareFunction,
+    ) -> wgpu::RenderPipeline {
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let multiplicative = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::OneMinusSrc,
+            operation: wgpu::BlendOperation::Add,
+        };
+        cache.render_pipeline(
+            device,
+            &wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: scene_shader,
+                    entry_point: Some("vs_main"),
+                    buffers: vertex_buffers,
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: scene_shader,
+                    entry_point: Some("fs_oit"),
+                    targets: &[
+                        Some(wgpu::ColorTargetState {
+                            format: Self::ACCUM_FORMAT,
+                            blend: Some(wgpu::BlendState {
+                                color: additive,
+                                alpha: additive,
+                            }),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: Self::REVEALAGE_FORMAT,
+                            blend: Some(wgpu::BlendState {
+                                color: multiplicative,
+                                alpha: multiplicative,
+                            }),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        }),
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            },
+        )
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (texture::Texture, texture::Texture) {
+        let target = |format, label| {
+            texture::Texture::create_render_target(device, width, height, format, label)
+        };
+        (
+            target(Self::ACCUM_FORMAT, "Oit::accum"),
+            target(Self::REVEALAGE_FORMAT, "Oit::revealage"),
+        )
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        accum: &texture::Texture,
+        revealage: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Oit::bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&accum.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&revealage.view),
+                },
+            ],
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.accum, self.revealage) = Self::create_targets(device, width, height);
+        self.bind_group =
+            Self::create_bind_group(device, &self.layout, &self.accum, &self.revealage);
+    }
+
+    /// Clears the targets and opens a pass over them, testing against
+    /// `depth_texture`, for the accumulate pipelines to draw in.
+    pub fn begin_pass<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        depth_texture: &texture::Texture,
+    ) -> wgpu::RenderPass<'e> {
+        let attachment = |view, clear| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Oit::accumulate"),
+            color_attachments: &[
+                attachment(&self.accum.view, wgpu::Color::TRANSPARENT),
+                attachment(&self.revealage.view, wgpu::Color::WHITE),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    /// Blends what the accumulate pass gathered over `output`.
+    pub fn composite(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Oit::composite"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
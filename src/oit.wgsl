@@ -0,0 +1,25 @@
+// Resolves the weighted-blended transparency targets of oit.rs over the
+// HDR target.
+@group(0) @binding(0)
+var t_accum: texture_2d<f32>;
+@group(0) @binding(1)
+var t_revealage: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Weighted average color in rgb; alpha is how much of the background still
+// shows, which the blend state uses to fade it.
+@fragment
+fn fs_composite(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let revealage = textureLoad(t_revealage, pixel, 0).r;
+    if revealage >= 1.0 {
+        discard;
+    }
+    let accum = textureLoad(t_accum, pixel, 0);
+    return vec4<f32>(accum.rgb / max(accum.a, 1e-5), revealage);
+}
@@ -0,0 +1,354 @@
+use crate::animation::JointPalette;
+use crate::camera::Camera;
+use crate::instance::InstanceBuffer;
+use crate::model;
+use crate::preprocess::{self, ShaderDefs};
+use crate::uniforms::PerDrawUniforms;
+
+/// How the selection outline looks.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct OutlineSettings {
+    /// Linear color, blended by its alpha.
+    pub color: [f32; 4],
+    /// Thickness in pixels.
+    pub width: f32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 0.5, 0.0, 1.0],
+            width: 3.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    view_proj: [[f32; 4]; 4],
+    color: [f32; 4],
+    offset: [f32; 2],
+    _padding: [f32; 2],
+}
+
+/// Draws an outline around one instance of a model, over the finished
+/// frame and whatever is in front of it.
+///
+/// The instance's meshes are first drawn into a stencil buffer of the
+/// output's size. They are then drawn eight more times, each shifted
+/// `width` pixels in a different direction, only where the stencil is
+/// clear, which dilates their silhouette without the gaps that pushing
+/// vertices out along normals leaves at hard edges. Each outline pixel
+/// marks the stencil too, so it is only blended once.
+pub struct Outline {
+    settings: OutlineSettings,
+    stencil_view: wgpu::TextureView,
+    // One per entry of `OFFSETS`.
+    uniforms: PerDrawUniforms<OutlineUniform>,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader_defs: ShaderDefs,
+    skinned: bool,
+    vertex_encoding: model::VertexEncoding,
+    output_format: wgpu::TextureFormat,
+    mask_pipeline: wgpu::RenderPipeline,
+    outline_pipeline: wgpu::RenderPipeline,
+}
+
+impl Outline {
+    const STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Stencil8;
+    // The mask's shift, then the eight around it.
+    const OFFSETS: [[f32; 2]; 9] = [
+        [0.0, 0.0],
+        [1.0, 0.0],
+        [-1.0, 0.0],
+        [0.0, 1.0],
+        [0.0, -1.0],
+        [
+            std::f32::consts::FRAC_1_SQRT_2,
+            std::f32::consts::FRAC_1_SQRT_2,
+        ],
+        [
+            -std::f32::consts::FRAC_1_SQRT_2,
+            std::f32::consts::FRAC_1_SQRT_2,
+        ],
+        [
+            std::f32::consts::FRAC_1_SQRT_2,
+            -std::f32::consts::FRAC_1_SQRT_2,
+        ],
+        [
+            -std::f32::consts::FRAC_1_SQRT_2,
+            -std::f32::consts::FRAC_1_SQRT_2,
+        ],
+    ];
+
+    /// Skins against `joints`, if it is for a skeleton, reads vertices in
+    /// `vertex_encoding` and draws into `output_format` targets `width` by
+    /// `height` pixels.
+    pub fn new(
+        device: &wgpu::Device,
+        joints: &JointPalette,
+        vertex_encoding: model::VertexEncoding,
+        output_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        // Sized up front, so the bind group never needs rebuilding.
+        let uniforms = PerDrawUniforms::new(
+            device,
+            "Outline Uniform Buffer",
+            Self::OFFSETS.len(),
+            wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        );
+        let entries = uniforms
+            .layout_entry(0)
+            .into_iter()
+            .chain([JointPalette::layout_entry(1)])
+            .collect::<Vec<_>>();
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("outline_bind_group_layout"),
+        });
+        let entries = uniforms
+            .binding()
+            .map(|resource| wgpu::BindGroupEntry {
+                binding: 0,
+                resource,
+            })
+            .into_iter()
+            .chain([wgpu::BindGroupEntry {
+                binding: 1,
+                resource: joints.buffer().as_entire_binding(),
+            }])
+            .collect::<Vec<_>>();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &entries,
+            label: Some("outline_bind_group"),
+        });
+
+        let shader_defs = uniforms.shader_defs(vertex_encoding.shader_defs(joints.shader_defs()));
+        let skinned = joints.is_skinned();
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &uniforms.push_constant_ranges(),
+        });
+        let [mask_pipeline, outline_pipeline] = Self::create_pipelines(
+            device,
+            &pipeline_layout,
+            &shader_defs,
+            skinned,
+            vertex_encoding,
+            output_format,
+        );
+
+        Self {
+            settings: OutlineSettings::default(),
+            stencil_view: Self::create_stencil(device, width, height),
+            uniforms,
+            bind_group,
+            pipeline_layout,
+            shader_defs,
+            skinned,
+            vertex_encoding,
+            output_format,
+            mask_pipeline,
+            outline_pipeline,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        shader_defs: &ShaderDefs,
+        skinned: bool,
+        vertex_encoding: model::VertexEncoding,
+        output_format: wgpu::TextureFormat,
+    ) -> [wgpu::RenderPipeline; 2] {
+        let shader =
+            preprocess::builtin_module(device, "Outline Shader", "outline.wgsl", shader_defs);
+        let create_pipeline = |label, write_mask, compare| {
+            let face = wgpu::StencilFaceState {
+                compare,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op: wgpu::StencilOperation::Replace,
+            };
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &model::vertex_buffers(skinned, vertex_encoding),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: output_format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Self::STENCIL_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState {
+                        front: face,
+                        back: face,
+                        read_mask: 0xff,
+                        write_mask: 0xff,
+                    },
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        [
+            create_pipeline(
+                "Outline Mask Pipeline",
+                wgpu::ColorWrites::empty(),
+                wgpu::CompareFunction::Always,
+            ),
+            create_pipeline(
+                "Outline Pipeline",
+                wgpu::ColorWrites::ALL,
+                wgpu::CompareFunction::NotEqual,
+            ),
+        ]
+    }
+
+    /// Compiles the pipelines again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        [self.mask_pipeline, self.outline_pipeline] = Self::create_pipelines(
+            device,
+            &self.pipeline_layout,
+            &self.shader_defs,
+            self.skinned,
+            self.vertex_encoding,
+            self.output_format,
+        );
+    }
+
+    fn create_stencil(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("outline_stencil"),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::STENCIL_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor {
+                label: Some("outline_stencil_view"),
+                ..Default::default()
+            })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.stencil_view = Self::create_stencil(device, width, height);
+    }
+
+    pub fn settings(&self) -> OutlineSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: OutlineSettings) {
+        self.settings = settings;
+    }
+
+    /// Outlines `instance` of `model` as seen from `camera` onto `output`,
+    /// which is `size` pixels. The uniforms are written through `queue`,
+    /// so record at most one outline per submission.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        camera: &Camera,
+        size: [u32; 2],
+        model: &model::Model,
+        instances: &InstanceBuffer,
+        instance: usize,
+    ) {
+        if instance >= instances.len() {
+            return;
+        }
+        let view_proj = camera.build_view_projection_matrix().into();
+        let [width, height] = size.map(|side| side.max(1) as f32);
+        self.uniforms.clear();
+        for [x, y] in Self::OFFSETS {
+            self.uniforms.push(&OutlineUniform {
+                view_proj,
+                color: self.settings.color,
+                // A pixel is two NDC units over the size.
+                offset: [
+                    x * self.settings.width * 2.0 / width,
+                    y * self.settings.width * 2.0 / height,
+                ],
+                _padding: [0.0; 2],
+            });
+        }
+        let reallocated = self.uniforms.upload(device, queue);
+        debug_assert!(!reallocated, "outline uniforms outgrew their buffer");
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                depth_slice: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.stencil_view,
+                depth_ops: None,
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_stencil_reference(1);
+        pass.set_vertex_buffer(1, instances.slice());
+        let instance = instance as u32;
+        for i in 0..Self::OFFSETS.len() {
+            pass.set_pipeline(if i == 0 {
+                &self.mask_pipeline
+            } else {
+                &self.outline_pipeline
+            });
+            self.uniforms.bind(&mut pass, 0, &self.bind_group, i);
+            for mesh in &model.meshes {
+                mesh.set_buffers(&mut pass);
+                pass.draw_indexed(0..mesh.num_elements, 0, instance..instance + 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// Selection outlines for outline.rs: the selected meshes drawn once into
+// the stencil, then again shifted a few pixels each way around it.
+#include "include/instance.wgsl"
+#define SKIN_GROUP 0
+#define SKIN_BINDING 1
+#include "include/skinning.wgsl"
+
+struct OutlineUniform {
+    view_proj: mat4x4<f32>,
+    color: vec4<f32>,
+    // Screen-space shift in NDC; zero for the stencil mask.
+    offset: vec2<f32>,
+}
+#ifdef PUSH_CONSTANTS
+var<push_constant> outline: OutlineUniform;
+#else
+@group(0) @binding(0)
+var<uniform> outline: OutlineUniform;
+#endif
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+#ifdef SKINNED
+    skin: SkinInput,
+#endif
+) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var position = model.position;
+#ifdef SKINNED
+    position = skin_position(skin_matrix(skin), position);
+#endif
+    let clip = outline.view_proj * model_matrix * vec4<f32>(position, 1.0);
+    return vec4<f32>(clip.xy + outline.offset * clip.w, clip.zw);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return outline.color;
+}
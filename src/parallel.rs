@@ -0,0 +1,105 @@
+use crate::culling::DrawInstances;
+use crate::hdr::HdrPipeline;
+use crate::model;
+use crate::post::taa::Taa;
+use crate::texture;
+
+/// One mesh of the scene model drawn with a material pipeline.
+pub type MeshDraw<'a> = (&'a wgpu::RenderPipeline, usize);
+
+/// Encodes `draws` for the forward pass into render bundles, splitting
+/// them in order across up to `threads` threads, so that executing the
+/// bundles in turn draws what `draws` would one at a time. The web has no
+/// threads to spare, so there it's always one bundle.
+///
+/// `bind_groups` are the camera, lights and shadows at groups 1 to 3;
+/// each mesh binds its own material at group 0. The pass has `samples`
+/// samples a pixel, as the pipelines must.
+pub fn encode_bundles<'a>(
+    device: &wgpu::Device,
+    model: &'a model::Model,
+    instances: DrawInstances<'a>,
+    draws: &[MeshDraw<'a>],
+    bind_groups: [&'a wgpu::BindGroup; 3],
+    threads: usize,
+    samples: u32,
+) -> Vec<wgpu::RenderBundle> {
+    if draws.is_empty() || instances.is_empty() {
+        return Vec::new();
+    }
+    let encode = |draws: &[MeshDraw<'a>]| {
+        encode_bundle(device, model, instances, draws, bind_groups, samples)
+    };
+    #[cfg(target_arch = "wasm32")]
+    let _ = threads;
+    #[cfg(not(target_arch = "wasm32"))]
+    if threads > 1 && draws.len() > 1 {
+        let chunks = draws
+            .chunks(draws.len().div_ceil(threads.min(draws.len())))
+            .collect::<Vec<_>>();
+        return std::thread::scope(|scope| {
+            // This thread takes the first chunk rather than only waiting.
+            let (first, rest) = chunks.split_first().expect("there are draws");
+            let workers = rest
+                .iter()
+                .map(|chunk| scope.spawn(move || encode(chunk)))
+                .collect::<Vec<_>>();
+            let mut bundles = vec![encode(first)];
+            for worker in workers {
+                bundles.push(
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+                );
+            }
+            bundles
+        });
+    }
+    vec![encode(draws)]
+}
+
+/// One per core, or one on the web.
+pub fn available_threads() -> usize {
+    #[cfg(not(target_arch = "wasm32"))]
+    return std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    #[cfg(target_arch = "wasm32")]
+    1
+}
+
+fn encode_bundle<'a>(
+    device: &wgpu::Device,
+    model: &'a model::Model,
+    instances: DrawInstances<'a>,
+    draws: &[MeshDraw<'a>],
+    [camera, lights, shadows]: [&'a wgpu::BindGroup; 3],
+    samples: u32,
+) -> wgpu::RenderBundle {
+    let mut bundle = device.create_render_bundle_encoder(&wgpu::RenderBundleEncoderDescriptor {
+        label: Some("parallel::bundle"),
+        color_formats: &[Some(HdrPipeline::FORMAT), Some(Taa::VELOCITY_FORMAT)],
+        depth_stencil: Some(wgpu::RenderBundleDepthStencil {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_read_only: false,
+            stencil_read_only: true,
+        }),
+        sample_count: samples,
+        multiview: None,
+    });
+    bundle.set_vertex_buffer(1, instances.slice());
+    bundle.set_bind_group(1, camera, &[]);
+    bundle.set_bind_group(2, lights, &[]);
+    bundle.set_bind_group(3, shadows, &[]);
+    let mut bound = None;
+    for &(pipeline, index) in draws {
+        if bound != Some(pipeline) {
+            bundle.set_pipeline(pipeline);
+            bound = Some(pipeline);
+        }
+        let mesh = &model.meshes[index];
+        bundle.set_bind_group(0, &model.materials[mesh.material].bind_group, &[]);
+        instances.draw_mesh(&mut bundle, index, mesh);
+    }
+    bundle.finish(&wgpu::RenderBundleDescriptor {
+        label: Some("parallel::bundle"),
+    })
+}
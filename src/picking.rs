@@ -0,0 +1,750 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use cgmath::prelude::*;
+
+use crate::animation::JointPalette;
+use crate::camera::Camera;
+use crate::ecs::Entity;
+use crate::instance::{InstanceBuffer, InstanceRaw};
+use crate::model::{self, Bounds};
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+use crate::uniforms::PerDrawUniforms;
+
+/// A half-line in world space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ray {
+    pub origin: cgmath::Point3<f32>,
+    /// Unit length, so distances along the ray are in world units.
+    pub direction: cgmath::Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: cgmath::Point3<f32>, direction: cgmath::Vector3<f32>) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    /// The ray through pixel `cursor`, from the top-left of a `size` pixel
+    /// view of `camera`. It starts on the near plane, so orthographic
+    /// cameras get parallel rays.
+    pub fn from_screen(camera: &Camera, cursor: [f32; 2], size: [u32; 2]) -> Option<Self> {
+        let inverse = camera.build_view_projection_matrix().invert()?;
+        let x = cursor[0] / size[0].max(1) as f32 * 2.0 - 1.0;
+        let y = 1.0 - cursor[1] / size[1].max(1) as f32 * 2.0;
+        let unproject = |depth: f32| {
+            let point = inverse * cgmath::Vector4::new(x, y, depth, 1.0);
+            cgmath::Point3::from_homogeneous(point)
+        };
+        // The far plane can unproject to infinity when distant, so the
+        // direction comes from a point partway in.
+        let near = unproject(camera.near_depth());
+        let inside = unproject(0.5);
+        Some(Self::new(near, inside - near))
+    }
+
+    pub fn at(&self, distance: f32) -> cgmath::Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// Distance to where the ray enters `bounds`; zero when it starts
+    /// inside.
+    pub fn intersect_aabb(&self, bounds: &Bounds) -> Option<f32> {
+        let (mut near, mut far) = (0.0f32, f32::INFINITY);
+        for axis in 0..3 {
+            // Infinite for axis-parallel rays, which the comparisons handle.
+            let inverse = 1.0 / self.direction[axis];
+            let a = (bounds.min[axis] - self.origin[axis]) * inverse;
+            let b = (bounds.max[axis] - self.origin[axis]) * inverse;
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    /// Distance to where the ray crosses triangle `abc` from either side.
+    pub fn intersect_triangle(&self, [a, b, c]: [cgmath::Point3<f32>; 3]) -> Option<f32> {
+        // Möller-Trumbore.
+        let ab = b - a;
+        let ac = c - a;
+        let p = self.direction.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let to_origin = self.origin - a;
+        let u = to_origin.dot(p) / det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = to_origin.cross(ab);
+        let v = self.direction.dot(q) / det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = ac.dot(q) / det;
+        (distance >= 0.0).then_some(distance)
+    }
+
+    fn transformed(
+        &self,
+        transform: &cgmath::Matrix4<f32>,
+    ) -> (cgmath::Point3<f32>, cgmath::Vector3<f32>) {
+        (
+            transform.transform_point(self.origin),
+            transform.transform_vector(self.direction),
+        )
+    }
+}
+
+/// How closely `pick` tests meshes.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum PickMode {
+    /// Against each instance's world-space bounds; quick but loose.
+    #[default]
+    Bounds,
+    /// Against the triangles inside the bounds a ray hits. Meshes without
+    /// `geometry` are still tested against their bounds.
+    Triangles,
+    /// Against what the GPU draws, through an `IdPicker`, so skinned
+    /// meshes are exact too. The answer is a frame or more late; `pick`
+    /// itself cannot wait for it and tests triangles instead.
+    IdBuffer,
+}
+
+/// The nearest surface under a ray.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PickHit {
+    pub mesh: usize,
+    pub instance: usize,
+    /// The entity the instance was built from, when the scene comes from
+    /// `State::world`.
+    pub entity: Option<Entity>,
+    pub position: cgmath::Point3<f32>,
+    /// Along the ray, in world units.
+    pub distance: f32,
+}
+
+/// The closest hit of `ray` across every instance of every mesh of
+/// `model`.
+pub fn pick(
+    ray: &Ray,
+    model: &model::Model,
+    instances: &[InstanceRaw],
+    mode: PickMode,
+) -> Option<PickHit> {
+    let meshes = model
+        .meshes
+        .iter()
+        .map(|mesh| (mesh.bounds, mesh.geometry.as_ref()))
+        .collect::<Vec<_>>();
+    pick_meshes(ray, &meshes, instances, mode)
+}
+
+// `pick` over meshes given as their bounds and triangles.
+fn pick_meshes(
+    ray: &Ray,
+    meshes: &[(Bounds, Option<&model::MeshGeometry>)],
+    instances: &[InstanceRaw],
+    mode: PickMode,
+) -> Option<PickHit> {
+    let mut closest: Option<PickHit> = None;
+    for (instance, raw) in instances.iter().enumerate() {
+        let transform = cgmath::Matrix4::from(raw.model);
+        // Degenerate instances cannot be hit.
+        let Some(inverse) = transform.invert() else {
+            continue;
+        };
+        for (mesh_index, (bounds, geometry)) in meshes.iter().enumerate() {
+            let Some(entry) = ray.intersect_aabb(&bounds.transformed(&transform)) else {
+                continue;
+            };
+            if closest.is_some_and(|hit| hit.distance <= entry) {
+                continue;
+            }
+            let distance = match (mode, geometry) {
+                (PickMode::Triangles | PickMode::IdBuffer, Some(geometry)) => {
+                    // An affine map keeps the ray parameter, so distances
+                    // found in mesh space are world distances too.
+                    let (origin, direction) = ray.transformed(&inverse);
+                    let local = Ray { origin, direction };
+                    geometry
+                        .triangles()
+                        .filter_map(|triangle| local.intersect_triangle(triangle))
+                        .min_by(f32::total_cmp)
+                }
+                _ => Some(entry),
+            };
+            if let Some(distance) = distance
+                && closest.is_none_or(|hit| distance < hit.distance)
+            {
+                closest = Some(PickHit {
+                    mesh: mesh_index,
+                    instance,
+                    entity: None,
+                    position: ray.at(distance),
+                    distance,
+                });
+            }
+        }
+    }
+    closest
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PickUniform {
+    view_proj: [[f32; 4]; 4],
+    mesh: u32,
+    instances: u32,
+    _padding: [u32; 2],
+}
+
+/// An ID pass waiting to be read back.
+struct PendingPick {
+    buffer: wgpu::Buffer,
+    mapping: bool,
+    mapped: Arc<AtomicBool>,
+    ray: Ray,
+    ndc: [f32; 2],
+    inverse_view_proj: cgmath::Matrix4<f32>,
+    instances: usize,
+}
+
+/// Picks by drawing the scene model's meshes with an ID per mesh and
+/// instance, reading back the one under the cursor.
+///
+/// The camera is zoomed so the picked pixel covers the whole of a one
+/// texel target, so the pass costs little more than its vertex work. Its
+/// depth is kept alongside the ID to place the hit. Only the model is
+/// drawn; terrain, water and the like neither hit nor occlude.
+///
+/// `request` records the pass; once the encoder is submitted, `map`
+/// starts the readback and `poll` returns the result when it arrives.
+pub struct IdPicker {
+    id_texture: wgpu::Texture,
+    id_view: wgpu::TextureView,
+    depth_texture: wgpu::Texture,
+    depth_view: wgpu::TextureView,
+    depth_stencil_view: wgpu::TextureView,
+    // One per mesh.
+    uniforms: PerDrawUniforms<PickUniform>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    joint_buffer: wgpu::Buffer,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader_defs: ShaderDefs,
+    skinned: bool,
+    vertex_encoding: model::VertexEncoding,
+    pipeline: wgpu::RenderPipeline,
+    pending: Vec<PendingPick>,
+}
+
+impl IdPicker {
+    pub const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+    // Depth bits, since float targets may not be renderable downlevel.
+    const DEPTH_VALUE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+    // The depth follows the ID in the readback buffer, a copy row later.
+    const DEPTH_OFFSET: wgpu::BufferAddress = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as _;
+
+    /// Skins against `joints`, if it is for a skeleton, and reads vertices
+    /// in `vertex_encoding`.
+    pub fn new(
+        device: &wgpu::Device,
+        joints: &JointPalette,
+        vertex_encoding: model::VertexEncoding,
+    ) -> Self {
+        let (id_texture, id_view) = Self::create_target(device, "Pick ID Texture", Self::ID_FORMAT);
+        let (depth_texture, depth_view) =
+            Self::create_target(device, "Pick Depth Texture", Self::DEPTH_VALUE_FORMAT);
+        let (_, depth_stencil_view) =
+            Self::create_target(device, "Pick Depth Buffer", texture::Texture::DEPTH_FORMAT);
+
+        let uniforms =
+            PerDrawUniforms::new(device, "Pick Uniform Buffer", 0, wgpu::ShaderStages::VERTEX);
+        let entries = uniforms
+            .layout_entry(0)
+            .into_iter()
+            .chain([JointPalette::layout_entry(1)])
+            .collect::<Vec<_>>();
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("pick_bind_group_layout"),
+        });
+        let joint_buffer = joints.buffer().clone();
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &uniforms, &joint_buffer);
+
+        let shader_defs = uniforms.shader_defs(vertex_encoding.shader_defs(joints.shader_defs()));
+        let skinned = joints.is_skinned();
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pick Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &uniforms.push_constant_ranges(),
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader_defs,
+            skinned,
+            vertex_encoding,
+        );
+
+        Self {
+            id_texture,
+            id_view,
+            depth_texture,
+            depth_view,
+            depth_stencil_view,
+            uniforms,
+            bind_group_layout,
+            bind_group,
+            joint_buffer,
+            pipeline_layout,
+            shader_defs,
+            skinned,
+            vertex_encoding,
+            pipeline,
+            pending: Vec::new(),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        shader_defs: &ShaderDefs,
+        skinned: bool,
+        vertex_encoding: model::VertexEncoding,
+    ) -> wgpu::RenderPipeline {
+        let shader = preprocess::builtin_module(device, "Pick Shader", "picking.wgsl", shader_defs);
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Pick Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &model::vertex_buffers(skinned, vertex_encoding),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[
+                    Some(Self::ID_FORMAT.into()),
+                    Some(Self::DEPTH_VALUE_FORMAT.into()),
+                ],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                // Double-sided materials can be picked from behind.
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Compiles the pipeline again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader_defs,
+            self.skinned,
+            self.vertex_encoding,
+        );
+    }
+
+    fn create_target(
+        device: &wgpu::Device,
+        label: &str,
+        format: wgpu::TextureFormat,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            ..Default::default()
+        });
+        (texture, view)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniforms: &PerDrawUniforms<PickUniform>,
+        joint_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        let entries = uniforms
+            .binding()
+            .map(|resource| wgpu::BindGroupEntry {
+                binding: 0,
+                resource,
+            })
+            .into_iter()
+            .chain([wgpu::BindGroupEntry {
+                binding: 1,
+                resource: joint_buffer.as_entire_binding(),
+            }])
+            .collect::<Vec<_>>();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some("pick_bind_group"),
+        })
+    }
+
+    /// Records the ID pass for pixel `cursor` of a `size` pixel view of
+    /// `camera`, and the copy out of it. The per-mesh uniforms are written
+    /// through `queue`, so record at most one request per submission.
+    #[allow(clippy::too_many_arguments)]
+    pub fn request(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: &Camera,
+        cursor: [f32; 2],
+        size: [u32; 2],
+        model: &model::Model,
+        instances: &InstanceBuffer,
+    ) {
+        // The pick pass has its own depth buffer, so it keeps to the usual
+        // convention whatever the scene's.
+        let camera = &Camera {
+            reverse_z: false,
+            ..*camera
+        };
+        let Some(ray) = Ray::from_screen(camera, cursor, size) else {
+            return;
+        };
+        let view_proj = camera.build_view_projection_matrix();
+        let Some(inverse_view_proj) = view_proj.invert() else {
+            return;
+        };
+        let [width, height] = size.map(|side| side.max(1) as f32);
+        let ndc = [
+            cursor[0] / width * 2.0 - 1.0,
+            1.0 - cursor[1] / height * 2.0,
+        ];
+        // Moves the pixel's center to the middle of clip space and scales
+        // it up to fill it.
+        let zoom = cgmath::Matrix4::from_nonuniform_scale(width, height, 1.0)
+            * cgmath::Matrix4::from_translation(cgmath::vec3(-ndc[0], -ndc[1], 0.0));
+
+        self.uniforms.clear();
+        for mesh in 0..model.meshes.len() {
+            self.uniforms.push(&PickUniform {
+                view_proj: (zoom * view_proj).into(),
+                mesh: mesh as u32,
+                instances: instances.len() as u32,
+                _padding: [0; 2],
+            });
+        }
+        if self.uniforms.upload(device, queue) {
+            self.bind_group = Self::create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &self.uniforms,
+                &self.joint_buffer,
+            );
+        }
+
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Pick Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &self.id_view,
+                        resolve_target: None,
+                        depth_slice: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &self.depth_view,
+                        resolve_target: None,
+                        depth_slice: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_stencil_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            if !instances.is_empty() {
+                pass.set_pipeline(&self.pipeline);
+                pass.set_vertex_buffer(1, instances.slice());
+                for (i, mesh) in model.meshes.iter().enumerate() {
+                    self.uniforms.bind(&mut pass, 0, &self.bind_group, i);
+                    mesh.set_buffers(&mut pass);
+                    pass.draw_indexed(0..mesh.num_elements, 0, 0..instances.len() as u32);
+                }
+            }
+        }
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pick Readback Buffer"),
+            size: Self::DEPTH_OFFSET + 4,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        for (texture, offset) in [
+            (&self.id_texture, 0),
+            (&self.depth_texture, Self::DEPTH_OFFSET),
+        ] {
+            encoder.copy_texture_to_buffer(
+                texture.as_image_copy(),
+                wgpu::TexelCopyBufferInfo {
+                    buffer: &buffer,
+                    layout: wgpu::TexelCopyBufferLayout {
+                        offset,
+                        bytes_per_row: None,
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        self.pending.push(PendingPick {
+            buffer,
+            mapping: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            ray,
+            ndc,
+            inverse_view_proj,
+            instances: instances.len(),
+        });
+    }
+
+    /// Starts reading back every request submitted since the last call.
+    pub fn map(&mut self) {
+        for pick in self.pending.iter_mut().filter(|pick| !pick.mapping) {
+            pick.mapping = true;
+            let mapped = pick.mapped.clone();
+            pick.buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| match result {
+                    Ok(()) => mapped.store(true, Ordering::Release),
+                    Err(e) => log::error!("Pick readback failed: {}", e),
+                });
+        }
+    }
+
+    /// Whether a request is still waiting on the GPU.
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// The newest result read back since the last call, if any has
+    /// arrived: `Some(None)` when nothing was under the cursor. Older
+    /// results that arrived with it are dropped.
+    pub fn poll(&mut self) -> Option<Option<PickHit>> {
+        let ready = self
+            .pending
+            .iter()
+            .rposition(|pick| pick.mapped.load(Ordering::Acquire))?;
+        let pick = self.pending.drain(..=ready).next_back()?;
+        let (id, depth) = {
+            let data = pick.buffer.slice(..).get_mapped_range();
+            let id = bytemuck::pod_read_unaligned::<u32>(&data[..4]);
+            let offset = Self::DEPTH_OFFSET as usize;
+            let depth = bytemuck::pod_read_unaligned::<f32>(&data[offset..offset + 4]);
+            (id, depth)
+        };
+        pick.buffer.unmap();
+        if id == 0 || pick.instances == 0 {
+            return Some(None);
+        }
+        let index = (id - 1) as usize;
+        let point =
+            pick.inverse_view_proj * cgmath::Vector4::new(pick.ndc[0], pick.ndc[1], depth, 1.0);
+        let position = cgmath::Point3::from_homogeneous(point);
+        Some(Some(PickHit {
+            mesh: index / pick.instances,
+            instance: index % pick.instances,
+            entity: None,
+            position,
+            distance: position.distance(pick.ray.origin),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Projection;
+    use crate::instance::Instance;
+
+    fn camera(reverse_z: bool) -> Camera {
+        Camera {
+            eye: (0.0, 0.0, 5.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 2.0,
+            projection: Projection::Perspective { fovy: 60.0 },
+            znear: 0.1,
+            zfar: 100.0,
+            reverse_z,
+        }
+    }
+
+    // A unit cube's bounds, with its front face, facing +z, as triangles.
+    fn cube() -> (Bounds, model::MeshGeometry) {
+        let bounds = Bounds {
+            min: (-0.5, -0.5, -0.5).into(),
+            max: (0.5, 0.5, 0.5).into(),
+        };
+        let geometry = model::MeshGeometry {
+            positions: vec![
+                [-0.5, -0.5, 0.5],
+                [0.5, -0.5, 0.5],
+                [0.5, 0.5, 0.5],
+                [-0.5, 0.5, 0.5],
+            ],
+            indices: vec![0, 1, 2, 0, 2, 3],
+        };
+        (bounds, geometry)
+    }
+
+    fn at(x: f32, y: f32, z: f32) -> InstanceRaw {
+        Instance::new((x, y, z).into(), cgmath::Quaternion::one()).to_raw()
+    }
+
+    #[test]
+    fn the_centre_ray_looks_along_the_camera() {
+        for reverse_z in [false, true] {
+            let ray = Ray::from_screen(&camera(reverse_z), [100.0, 50.0], [200, 100]).unwrap();
+            assert!((ray.origin - cgmath::Point3::new(0.0, 0.0, 4.9)).magnitude() < 1e-3);
+            assert!((ray.direction - -cgmath::Vector3::unit_z()).magnitude() < 1e-4);
+
+            let (bounds, geometry) = cube();
+            for mode in [PickMode::Bounds, PickMode::Triangles] {
+                let hit = pick_meshes(
+                    &ray,
+                    &[(bounds, Some(&geometry))],
+                    &[at(0.0, 0.0, 0.0)],
+                    mode,
+                )
+                .unwrap();
+                assert!((hit.distance - 4.4).abs() < 1e-3);
+                assert!((hit.position.z - 0.5).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn rays_past_everything_miss() {
+        let (bounds, geometry) = cube();
+        let meshes = [(bounds, Some(&geometry))];
+        let ray = Ray::from_screen(&camera(false), [0.0, 0.0], [200, 100]).unwrap();
+        assert_eq!(
+            pick_meshes(&ray, &meshes, &[at(0.0, 0.0, 0.0)], PickMode::Bounds),
+            None
+        );
+        // Pointing away from the cube.
+        let ray = Ray::new((0.0, 0.0, 5.0).into(), cgmath::Vector3::unit_z());
+        assert_eq!(
+            pick_meshes(&ray, &meshes, &[at(0.0, 0.0, 0.0)], PickMode::Bounds),
+            None
+        );
+        assert_eq!(pick_meshes(&ray, &meshes, &[], PickMode::Bounds), None);
+    }
+
+    #[test]
+    fn the_nearest_hit_wins() {
+        let (bounds, geometry) = cube();
+        let meshes = [(bounds, Some(&geometry))];
+        let instances = [at(0.0, 0.0, -3.0), at(0.0, 0.0, 2.0), at(0.0, 0.0, 0.0)];
+        for reverse_z in [false, true] {
+            let ray = Ray::from_screen(&camera(reverse_z), [100.0, 50.0], [200, 100]).unwrap();
+            for mode in [PickMode::Bounds, PickMode::Triangles] {
+                let hit = pick_meshes(&ray, &meshes, &instances, mode).unwrap();
+                assert_eq!(hit.instance, 1);
+                assert!((hit.position.z - 2.5).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn triangles_see_through_the_empty_parts_of_bounds() {
+        let (bounds, full) = cube();
+        // Only the lower-left half of the front face, in front of a whole
+        // one further back.
+        let half = model::MeshGeometry {
+            positions: vec![[-0.5, -0.5, 0.5], [0.5, -0.5, 0.5], [-0.5, 0.5, 0.5]],
+            indices: vec![0, 1, 2],
+        };
+        let behind = Bounds {
+            min: bounds.min - cgmath::Vector3::unit_z() * 2.0,
+            max: bounds.max - cgmath::Vector3::unit_z() * 2.0,
+        };
+        let full = model::MeshGeometry {
+            positions: full
+                .positions
+                .iter()
+                .map(|&[x, y, z]| [x, y, z - 2.0])
+                .collect(),
+            ..full
+        };
+        let meshes = [(bounds, Some(&half)), (behind, Some(&full))];
+        let instances = [at(0.0, 0.0, 0.0)];
+
+        let ray = Ray::new((0.3, 0.3, 5.0).into(), -cgmath::Vector3::unit_z());
+        let hit = pick_meshes(&ray, &meshes, &instances, PickMode::Bounds).unwrap();
+        assert_eq!(hit.mesh, 0);
+        let hit = pick_meshes(&ray, &meshes, &instances, PickMode::Triangles).unwrap();
+        assert_eq!(hit.mesh, 1);
+        assert!((hit.distance - 6.5).abs() < 1e-4);
+
+        let ray = Ray::new((-0.3, -0.3, 5.0).into(), -cgmath::Vector3::unit_z());
+        let hit = pick_meshes(&ray, &meshes, &instances, PickMode::Triangles).unwrap();
+        assert_eq!(hit.mesh, 0);
+        assert!((hit.distance - 4.5).abs() < 1e-4);
+    }
+}
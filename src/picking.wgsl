@@ -0,0 +1,66 @@
+#include "include/instance.wgsl"
+#define SKIN_GROUP 0
+#define SKIN_BINDING 1
+#include "include/skinning.wgsl"
+
+struct PickUniform {
+    // The camera's, zoomed so the picked pixel fills the target.
+    view_proj: mat4x4<f32>,
+    mesh: u32,
+    instances: u32,
+}
+#ifdef PUSH_CONSTANTS
+var<push_constant> pick: PickUniform;
+#else
+@group(0) @binding(0)
+var<uniform> pick: PickUniform;
+#endif
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32,
+#ifdef SKINNED
+    skin: SkinInput,
+#endif
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var position = model.position;
+#ifdef SKINNED
+    position = skin_position(skin_matrix(skin), position);
+#endif
+    var out: VertexOutput;
+    out.clip_position = pick.view_proj * model_matrix * vec4<f32>(position, 1.0);
+    // Zero is left for nothing drawn.
+    out.id = pick.mesh * pick.instances + instance_index + 1u;
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) id: u32,
+    // Bits of the depth, as float targets are not always renderable.
+    @location(1) depth: u32,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+    out.id = in.id;
+    out.depth = bitcast<u32>(in.clip_position.z);
+    return out;
+}
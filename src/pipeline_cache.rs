@@ -0,0 +1,176 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::preprocess::{ShaderDefs, ShaderLibrary};
+
+/// Everything a render pipeline is built from, by value, so two
+/// descriptors asking for the same pipeline find the same entry.
+///
+/// Modules and layouts compare by handle, which is why they should come
+/// from the same `PipelineCache`. Compilation options are left out; no
+/// pipeline here overrides them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RenderPipelineKey {
+    layout: Option<wgpu::PipelineLayout>,
+    vertex_module: wgpu::ShaderModule,
+    vertex_entry_point: Option<String>,
+    buffers: Vec<(
+        wgpu::BufferAddress,
+        wgpu::VertexStepMode,
+        Vec<wgpu::VertexAttribute>,
+    )>,
+    fragment: Option<(wgpu::ShaderModule, Option<String>)>,
+    targets: Vec<Option<wgpu::ColorTargetState>>,
+    primitive: wgpu::PrimitiveState,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    multisample: wgpu::MultisampleState,
+}
+
+impl RenderPipelineKey {
+    fn new(descriptor: &wgpu::RenderPipelineDescriptor) -> Self {
+        let fragment = descriptor.fragment.as_ref();
+        Self {
+            layout: descriptor.layout.cloned(),
+            vertex_module: descriptor.vertex.module.clone(),
+            vertex_entry_point: descriptor.vertex.entry_point.map(str::to_string),
+            buffers: descriptor
+                .vertex
+                .buffers
+                .iter()
+                .map(|buffer| {
+                    (
+                        buffer.array_stride,
+                        buffer.step_mode,
+                        buffer.attributes.to_vec(),
+                    )
+                })
+                .collect(),
+            fragment: fragment.map(|fragment| {
+                (
+                    fragment.module.clone(),
+                    fragment.entry_point.map(str::to_string),
+                )
+            }),
+            targets: fragment.map_or_else(Vec::new, |fragment| fragment.targets.to_vec()),
+            primitive: descriptor.primitive,
+            depth_stencil: descriptor.depth_stencil.clone(),
+            multisample: descriptor.multisample,
+        }
+    }
+}
+
+/// Bind group layouts, pipeline layouts, shader modules and render
+/// pipelines, each created once per distinct description and handed out
+/// again after that.
+///
+/// Materials, passes and effects asking for identical layouts share one,
+/// and a pipeline seen before, say for a material kind that was drawn
+/// earlier with the same defines, comes back without compiling anything.
+/// Handles are cheap to clone, so entries are returned by value. Lookups
+/// take `&self`, so a cache can be lent out alongside other borrows of its
+/// owner.
+#[derive(Default)]
+pub struct PipelineCache {
+    bind_group_layouts: RefCell<HashMap<Vec<wgpu::BindGroupLayoutEntry>, wgpu::BindGroupLayout>>,
+    pipeline_layouts: RefCell<HashMap<Vec<wgpu::BindGroupLayout>, wgpu::PipelineLayout>>,
+    shader_modules: RefCell<HashMap<(String, ShaderDefs), wgpu::ShaderModule>>,
+    render_pipelines: RefCell<HashMap<RenderPipelineKey, wgpu::RenderPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The layout for `entries`, named `label` by whoever asked first.
+    pub fn bind_group_layout(
+        &self,
+        device: &wgpu::Device,
+        label: &str,
+        entries: &[wgpu::BindGroupLayoutEntry],
+    ) -> wgpu::BindGroupLayout {
+        self.bind_group_layouts
+            .borrow_mut()
+            .entry(entries.to_vec())
+            .or_insert_with(|| {
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some(label),
+                    entries,
+                })
+            })
+            .clone()
+    }
+
+    /// The pipeline layout over `bind_group_layouts`, without push
+    /// constants.
+    pub fn pipeline_layout(
+        &self,
+        device: &wgpu::Device,
+        label: &str,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> wgpu::PipelineLayout {
+        let key = bind_group_layouts
+            .iter()
+            .map(|&layout| layout.clone())
+            .collect();
+        self.pipeline_layouts
+            .borrow_mut()
+            .entry(key)
+            .or_insert_with(|| {
+                device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some(label),
+                    bind_group_layouts,
+                    push_constant_ranges: &[],
+                })
+            })
+            .clone()
+    }
+
+    /// `name` from `shaders`, preprocessed with `defs` and compiled, as
+    /// `ShaderLibrary::create_module` would. Errors are not cached.
+    pub fn shader_module(
+        &self,
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+        label: &str,
+        name: &str,
+        defs: &ShaderDefs,
+    ) -> anyhow::Result<wgpu::ShaderModule> {
+        let key = (name.to_string(), defs.clone());
+        if let Some(module) = self.shader_modules.borrow().get(&key) {
+            return Ok(module.clone());
+        }
+        let module = shaders.create_module(device, label, name, defs)?;
+        self.shader_modules.borrow_mut().insert(key, module.clone());
+        Ok(module)
+    }
+
+    /// The pipeline `descriptor` describes, created unless an identical
+    /// one already was. Its label is only used when it is created.
+    pub fn render_pipeline(
+        &self,
+        device: &wgpu::Device,
+        descriptor: &wgpu::RenderPipelineDescriptor,
+    ) -> wgpu::RenderPipeline {
+        self.render_pipelines
+            .borrow_mut()
+            .entry(RenderPipelineKey::new(descriptor))
+            .or_insert_with(|| {
+                log::debug!("Creating pipeline {:?}", descriptor.label);
+                device.create_render_pipeline(descriptor)
+            })
+            .clone()
+    }
+
+    /// Drops the shader modules and the pipelines built from them, as after
+    /// the sources change. Layouts stay, since they do not depend on them.
+    pub fn clear_shaders(&self) {
+        self.shader_modules.borrow_mut().clear();
+        self.render_pipelines.borrow_mut().clear();
+    }
+
+    /// Render pipelines created so far.
+    pub fn render_pipeline_count(&self) -> usize {
+        self.render_pipelines.borrow().len()
+    }
+}
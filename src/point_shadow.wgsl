@@ -0,0 +1,57 @@
+#include "include/instance.wgsl"
+#define SKIN_GROUP 0
+#define SKIN_BINDING 1
+#include "include/skinning.wgsl"
+
+struct FaceUniform {
+    view_proj: mat4x4<f32>,
+    light_position: vec3<f32>,
+    far: f32,
+}
+#ifdef PUSH_CONSTANTS
+var<push_constant> face: FaceUniform;
+#else
+@group(0) @binding(0)
+var<uniform> face: FaceUniform;
+#endif
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+#ifdef SKINNED
+    skin: SkinInput,
+#endif
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var position = model.position;
+#ifdef SKINNED
+    position = skin_position(skin_matrix(skin), position);
+#endif
+    let world_position = model_matrix * vec4<f32>(position, 1.0);
+
+    var out: VertexOutput;
+    out.world_position = world_position.xyz;
+    out.clip_position = face.view_proj * world_position;
+    return out;
+}
+
+// Store linear distance so every face compares against the same metric.
+@fragment
+fn fs_main(in: VertexOutput) -> @builtin(frag_depth) f32 {
+    return distance(in.world_position, face.light_position) / face.far;
+}
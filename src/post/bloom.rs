@@ -0,0 +1,316 @@
+use wgpu::util::DeviceExt;
+
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+/// Runtime bloom parameters.
+#[derive(Debug, Copy, Clone)]
+pub struct BloomSettings {
+    /// Brightness above which pixels start to bloom.
+    pub threshold: f32,
+    /// Width of the soft transition below `threshold`.
+    pub knee: f32,
+    /// Strength of the blurred result added back to the scene; zero skips
+    /// the effect entirely.
+    pub intensity: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.3,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    _padding: u32,
+}
+
+impl From<BloomSettings> for BloomUniform {
+    fn from(settings: BloomSettings) -> Self {
+        Self {
+            threshold: settings.threshold,
+            knee: settings.knee,
+            intensity: settings.intensity,
+            _padding: 0,
+        }
+    }
+}
+
+/// Threshold, progressive downsample/upsample blur and additive composite
+/// over an HDR target.
+///
+/// Each pyramid level is its own texture so a pass never samples the target
+/// it renders into.
+pub struct Bloom {
+    settings: BloomSettings,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    layout: wgpu::BindGroupLayout,
+    mips: Vec<texture::Texture>,
+    // `[0]` samples the HDR input, `[i + 1]` samples `mips[i]`.
+    bind_groups: Vec<wgpu::BindGroup>,
+    pipeline_layout: wgpu::PipelineLayout,
+    input_format: wgpu::TextureFormat,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+impl Bloom {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const MAX_MIPS: u32 = 6;
+
+    pub fn new(
+        device: &wgpu::Device,
+        input: &wgpu::TextureView,
+        input_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let settings = BloomSettings::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Buffer"),
+            contents: bytemuck::cast_slice(&[BloomUniform::from(settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let [
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+        ] = Self::create_pipelines(device, &pipeline_layout, input_format);
+
+        let mut bloom = Self {
+            settings,
+            uniform_buffer,
+            sampler,
+            layout,
+            pipeline_layout,
+            input_format,
+            mips: Vec::new(),
+            bind_groups: Vec::new(),
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+        };
+        bloom.resize(device, input, width, height);
+        bloom
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        input_format: wgpu::TextureFormat,
+    ) -> [wgpu::RenderPipeline; 4] {
+        let shader = super::create_shader(
+            device,
+            "Bloom Shader",
+            &preprocess::builtin_source("post/bloom.wgsl", &ShaderDefs::new()),
+        );
+        let pipeline = |entry_point, format, blend| {
+            super::create_pipeline(
+                device,
+                entry_point,
+                pipeline_layout,
+                &shader,
+                entry_point,
+                format,
+                blend,
+            )
+        };
+        [
+            pipeline("fs_prefilter", Self::FORMAT, None),
+            pipeline("fs_downsample", Self::FORMAT, None),
+            pipeline("fs_upsample", Self::FORMAT, Some(super::ADDITIVE_BLEND)),
+            pipeline("fs_composite", input_format, Some(super::ADDITIVE_BLEND)),
+        ]
+    }
+
+    /// Compiles the pipelines again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        [
+            self.prefilter_pipeline,
+            self.downsample_pipeline,
+            self.upsample_pipeline,
+            self.composite_pipeline,
+        ] = Self::create_pipelines(device, &self.pipeline_layout, self.input_format);
+    }
+
+    fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bloom::bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Rebuilds the pyramid for an input of `width` x `height`; call after the
+    /// input texture is recreated.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        input: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        let (width, height) = ((width / 2).max(1), (height / 2).max(1));
+        let count = texture::Texture::mip_level_count(width, height).min(Self::MAX_MIPS);
+        self.mips = (0..count)
+            .map(|i| {
+                texture::Texture::create_render_target(
+                    device,
+                    (width >> i).max(1),
+                    (height >> i).max(1),
+                    Self::FORMAT,
+                    "Bloom::mip",
+                )
+            })
+            .collect();
+
+        let mut bind_groups = vec![self.create_bind_group(device, input)];
+        for mip in &self.mips {
+            bind_groups.push(self.create_bind_group(device, &mip.view));
+        }
+        self.bind_groups = bind_groups;
+    }
+
+    /// The downsample chain, the largest first. Each holds its blurred
+    /// level after `render`.
+    pub fn mips(&self) -> &[texture::Texture] {
+        &self.mips
+    }
+
+    pub fn settings(&self) -> BloomSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: BloomSettings) {
+        self.settings = settings;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[BloomUniform::from(settings)]),
+        );
+    }
+
+    /// Blurs the bright parts of `input` and adds them back onto it. `input`
+    /// must be the view the bloom was created or last resized with.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, input: &wgpu::TextureView) {
+        if self.settings.intensity <= 0.0 {
+            return;
+        }
+
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        super::draw_fullscreen(
+            encoder,
+            "Bloom::prefilter",
+            &self.mips[0].view,
+            clear,
+            &self.prefilter_pipeline,
+            &self.bind_groups[0],
+        );
+        for i in 1..self.mips.len() {
+            super::draw_fullscreen(
+                encoder,
+                "Bloom::downsample",
+                &self.mips[i].view,
+                clear,
+                &self.downsample_pipeline,
+                &self.bind_groups[i],
+            );
+        }
+        for i in (1..self.mips.len()).rev() {
+            super::draw_fullscreen(
+                encoder,
+                "Bloom::upsample",
+                &self.mips[i - 1].view,
+                wgpu::LoadOp::Load,
+                &self.upsample_pipeline,
+                &self.bind_groups[i + 1],
+            );
+        }
+        super::draw_fullscreen(
+            encoder,
+            "Bloom::composite",
+            input,
+            wgpu::LoadOp::Load,
+            &self.composite_pipeline,
+            &self.bind_groups[1],
+        );
+    }
+}
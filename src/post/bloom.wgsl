@@ -0,0 +1,77 @@
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+}
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: BloomUniform;
+
+fn tap(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_source, s_source, uv, 0.0).rgb;
+}
+
+// 13-tap downsample from Jimenez, "Next Generation Post Processing in Call
+// of Duty: Advanced Warfare".
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let t = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let a = tap(uv + t * vec2<f32>(-2.0, -2.0));
+    let b = tap(uv + t * vec2<f32>(0.0, -2.0));
+    let c = tap(uv + t * vec2<f32>(2.0, -2.0));
+    let d = tap(uv + t * vec2<f32>(-2.0, 0.0));
+    let e = tap(uv);
+    let f = tap(uv + t * vec2<f32>(2.0, 0.0));
+    let g = tap(uv + t * vec2<f32>(-2.0, 2.0));
+    let h = tap(uv + t * vec2<f32>(0.0, 2.0));
+    let i = tap(uv + t * vec2<f32>(2.0, 2.0));
+    let j = tap(uv + t * vec2<f32>(-1.0, -1.0));
+    let k = tap(uv + t * vec2<f32>(1.0, -1.0));
+    let l = tap(uv + t * vec2<f32>(-1.0, 1.0));
+    let m = tap(uv + t * vec2<f32>(1.0, 1.0));
+
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+}
+
+// 3x3 tent filter.
+fn upsample(uv: vec2<f32>) -> vec3<f32> {
+    let t = 1.0 / vec2<f32>(textureDimensions(t_source));
+    var sum = tap(uv) * 4.0;
+    sum += (tap(uv + vec2<f32>(-t.x, 0.0)) + tap(uv + vec2<f32>(t.x, 0.0))
+        + tap(uv + vec2<f32>(0.0, -t.y)) + tap(uv + vec2<f32>(0.0, t.y))) * 2.0;
+    sum += tap(uv + vec2<f32>(-t.x, -t.y)) + tap(uv + vec2<f32>(t.x, -t.y))
+        + tap(uv + vec2<f32>(-t.x, t.y)) + tap(uv + vec2<f32>(t.x, t.y));
+    return sum / 16.0;
+}
+
+// Soft-knee threshold so highlights fade in rather than pop.
+fn threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    soft = soft * soft / (4.0 * bloom.knee + 1e-4);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 1e-4);
+    return color * contribution;
+}
+
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(threshold(downsample(in.uv)), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(upsample(in.uv), 1.0);
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(upsample(in.uv) * bloom.intensity, 0.0);
+}
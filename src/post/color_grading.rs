@@ -0,0 +1,272 @@
+use wgpu::util::DeviceExt;
+
+use super::{PostContext, PostEffect};
+use crate::preprocess::{self, ShaderDefs};
+
+/// A color lookup table: what each color in a `size` cube becomes, as
+/// RGBA8 texels with red fastest and blue slowest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lut {
+    size: u32,
+    texels: Vec<u8>,
+}
+
+impl Lut {
+    /// Reads a LUT laid out as a strip of `size` square tiles, `size * size`
+    /// wide and `size` tall, like the common 1024 by 32 PNGs: blue picks the
+    /// tile, red the column in it and green the row.
+    pub fn from_strip(strip: &image::RgbaImage) -> anyhow::Result<Self> {
+        let size = strip.height();
+        if size < 2 || strip.width() != size * size {
+            anyhow::bail!(
+                "a LUT strip should be as wide as its height squared, not {}x{}",
+                strip.width(),
+                strip.height()
+            );
+        }
+        let mut texels = Vec::with_capacity((size * size * size * 4) as usize);
+        for blue in 0..size {
+            for green in 0..size {
+                for red in 0..size {
+                    texels.extend_from_slice(&strip.get_pixel(blue * size + red, green).0);
+                }
+            }
+        }
+        Ok(Self { size, texels })
+    }
+
+    /// Decodes an image file holding a strip for `from_strip`.
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        Self::from_strip(&image::load_from_memory(bytes)?.to_rgba8())
+    }
+
+    /// Entries along each side of the cube.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    fn create_view(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::TextureView {
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("ColorGrading::lut"),
+                size: wgpu::Extent3d {
+                    width: self.size,
+                    height: self.size,
+                    depth_or_array_layers: self.size,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                // The entries are sRGB-encoded colors, looked up and
+                // returned as they are.
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &self.texels,
+        );
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ColorGradingSettings {
+    /// How much of the graded color replaces the original.
+    pub intensity: f32,
+}
+
+impl Default for ColorGradingSettings {
+    fn default() -> Self {
+        Self { intensity: 1.0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ColorGradingUniform {
+    intensity: f32,
+    size: f32,
+    srgb_target: u32,
+    _padding: u32,
+}
+
+/// Looks every color up in a `Lut`, for scenes with their own look. Runs
+/// only while it has one.
+pub struct ColorGrading {
+    pub settings: ColorGradingSettings,
+    pub enabled: bool,
+    lut: Option<(Lut, wgpu::TextureView)>,
+    pass: Option<Pass>,
+}
+
+struct Pass {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    srgb_target: bool,
+}
+
+impl ColorGrading {
+    pub fn new(settings: ColorGradingSettings) -> Self {
+        Self {
+            settings,
+            enabled: true,
+            lut: None,
+            pass: None,
+        }
+    }
+
+    pub fn lut(&self) -> Option<&Lut> {
+        self.lut.as_ref().map(|(lut, _)| lut)
+    }
+
+    /// Grades through `lut` from the next frame, or stops grading for None.
+    pub fn set_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lut: Option<Lut>) {
+        self.lut = lut.map(|lut| {
+            let view = lut.create_view(device, queue);
+            (lut, view)
+        });
+    }
+
+    fn uniform(&self, srgb_target: bool) -> ColorGradingUniform {
+        ColorGradingUniform {
+            intensity: self.settings.intensity,
+            size: self.lut().map_or(1, Lut::size) as f32,
+            srgb_target: srgb_target as u32,
+            _padding: 0,
+        }
+    }
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        Self::new(ColorGradingSettings::default())
+    }
+}
+
+impl PostEffect for ColorGrading {
+    fn setup(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        let texture = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ColorGrading"),
+            entries: &[
+                texture(0, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture(3, wgpu::TextureViewDimension::D3),
+            ],
+        });
+        let shader = super::create_shader(
+            device,
+            "ColorGrading",
+            &preprocess::builtin_source("post/color_grading.wgsl", &ShaderDefs::new()),
+        );
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ColorGrading"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = super::create_pipeline(
+            device,
+            "ColorGrading",
+            &pipeline_layout,
+            &shader,
+            "fs_main",
+            format,
+            None,
+        );
+        let srgb_target = format.is_srgb();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ColorGrading"),
+            contents: bytemuck::bytes_of(&self.uniform(srgb_target)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("ColorGrading"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        self.pass = Some(Pass {
+            layout,
+            pipeline,
+            uniform_buffer,
+            sampler,
+            srgb_target,
+        });
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.lut.is_some()
+    }
+
+    fn record(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext) {
+        let (Some(pass), Some((_, lut))) = (&self.pass, &self.lut) else {
+            return;
+        };
+        ctx.queue.write_buffer(
+            &pass.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&self.uniform(pass.srgb_target)),
+        );
+        let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ColorGrading"),
+            layout: &pass.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&ctx.input.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&pass.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: pass.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(lut),
+                },
+            ],
+        });
+        super::draw_fullscreen(
+            encoder,
+            "ColorGrading",
+            ctx.output,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            &pass.pipeline,
+            &bind_group,
+        );
+    }
+}
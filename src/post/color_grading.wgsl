@@ -0,0 +1,36 @@
+struct ColorGradingUniform {
+    intensity: f32,
+    // Entries along each side of the LUT.
+    size: f32,
+    // 1 when the target is sRGB, so colors read back linear and the LUT,
+    // which maps sRGB-encoded colors, needs them encoded.
+    srgb_target: u32,
+}
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
+@group(0) @binding(2)
+var<uniform> grading: ColorGradingUniform;
+@group(0) @binding(3)
+var t_lut: texture_3d<f32>;
+
+#include "include/color.wgsl"
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSampleLevel(t_input, s_input, in.uv, 0.0);
+    var encoded = clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    if (grading.srgb_target != 0u) {
+        encoded = linear_to_srgb(encoded);
+    }
+    // Through the centers of the first and last entries, so black and
+    // white land on them rather than halfway into the clamped edge.
+    let uvw = encoded * (grading.size - 1.0) / grading.size + 0.5 / grading.size;
+    var graded = textureSampleLevel(t_lut, s_input, uvw, 0.0).rgb;
+    graded = mix(encoded, graded, grading.intensity);
+    if (grading.srgb_target != 0u) {
+        graded = srgb_to_linear(graded);
+    }
+    return vec4<f32>(graded, color.a);
+}
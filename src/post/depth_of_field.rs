@@ -0,0 +1,324 @@
+use wgpu::util::DeviceExt;
+
+use super::{PostContext, PostEffect};
+use crate::blur::{Blur, BlurKernel};
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+#[derive(Debug, Copy, Clone)]
+pub struct DepthOfFieldSettings {
+    /// How far from the camera things are sharpest, in world units.
+    pub focus_distance: f32,
+    /// How quickly things blur away from the focus distance: the share of
+    /// `max_radius` something twice as far gets. Zero keeps all in focus.
+    pub aperture: f32,
+    /// The widest blur, in pixels.
+    pub max_radius: f32,
+    /// Focuses on whatever is at the center of the screen each frame,
+    /// rather than at `focus_distance`.
+    pub autofocus: bool,
+}
+
+impl Default for DepthOfFieldSettings {
+    fn default() -> Self {
+        Self {
+            focus_distance: 5.0,
+            aperture: 0.5,
+            max_radius: 8.0,
+            autofocus: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DepthOfFieldUniform {
+    focus_distance: f32,
+    aperture: f32,
+    max_radius: f32,
+    autofocus: u32,
+}
+
+impl From<DepthOfFieldSettings> for DepthOfFieldUniform {
+    fn from(settings: DepthOfFieldSettings) -> Self {
+        Self {
+            focus_distance: settings.focus_distance,
+            aperture: settings.aperture,
+            max_radius: settings.max_radius.max(0.0),
+            autofocus: settings.autofocus as u32,
+        }
+    }
+}
+
+/// Blurs what's nearer or farther than the focus distance by its circle of
+/// confusion, from the scene's depth. Off by default.
+///
+/// The input is copied at half size and blurred once with a `Blur` as wide
+/// as `max_radius`; each pixel then blends from sharp to that blur as its
+/// circle of confusion grows.
+pub struct DepthOfField {
+    pub settings: DepthOfFieldSettings,
+    pub enabled: bool,
+    pass: Option<Pass>,
+}
+
+struct Pass {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    downsample_layout: wgpu::BindGroupLayout,
+    downsample_pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    // Made on the first `record`, which has the queue its kernel needs.
+    blur: Option<Blur>,
+    // The half-size copy, blurred in place, and the blur's scratch; made
+    // by `resize`.
+    targets: Option<(texture::Texture, texture::Texture)>,
+}
+
+impl DepthOfField {
+    pub fn new(settings: DepthOfFieldSettings) -> Self {
+        Self {
+            settings,
+            enabled: false,
+            pass: None,
+        }
+    }
+}
+
+impl DepthOfField {
+    const BLUR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    // A Gaussian about as wide as a disc of `max_radius` pixels, in the
+    // half-size copy's texels.
+    fn kernel(&self) -> BlurKernel {
+        BlurKernel::Gaussian {
+            sigma: self.settings.max_radius.max(0.0) / 4.0,
+        }
+    }
+}
+
+impl Default for DepthOfField {
+    fn default() -> Self {
+        Self::new(DepthOfFieldSettings::default())
+    }
+}
+
+impl PostEffect for DepthOfField {
+    fn setup(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        let texture = |binding, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let uniform = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("DepthOfField"),
+            entries: &[
+                texture(0, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                uniform(2),
+                texture(3, false),
+                uniform(4),
+                texture(5, true),
+            ],
+        });
+        let downsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("DepthOfField::downsample"),
+            entries: &[
+                texture(0, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let shader = super::create_shader(
+            device,
+            "DepthOfField",
+            &preprocess::builtin_source("post/depth_of_field.wgsl", &ShaderDefs::new()),
+        );
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("DepthOfField"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = super::create_pipeline(
+            device,
+            "DepthOfField",
+            &pipeline_layout,
+            &shader,
+            "fs_main",
+            format,
+            None,
+        );
+        let downsample_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("DepthOfField::downsample"),
+                bind_group_layouts: &[&downsample_layout],
+                push_constant_ranges: &[],
+            });
+        let downsample_pipeline = super::create_pipeline(
+            device,
+            "DepthOfField::downsample",
+            &downsample_pipeline_layout,
+            &shader,
+            "fs_downsample",
+            Self::BLUR_FORMAT,
+            None,
+        );
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("DepthOfField"),
+            contents: bytemuck::bytes_of(&DepthOfFieldUniform::from(self.settings)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("DepthOfField"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        self.pass = Some(Pass {
+            layout,
+            pipeline,
+            downsample_layout,
+            downsample_pipeline,
+            uniform_buffer,
+            sampler,
+            blur: None,
+            targets: None,
+        });
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let Some(pass) = &mut self.pass else {
+            return;
+        };
+        let (width, height) = ((width / 2).max(1), (height / 2).max(1));
+        let target = |label| Blur::create_target(device, width, height, Self::BLUR_FORMAT, label);
+        pass.targets = Some((
+            target("DepthOfField::blurred"),
+            target("DepthOfField::scratch"),
+        ));
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn record(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext) {
+        let kernel = self.kernel();
+        let Some(pass) = &mut self.pass else {
+            return;
+        };
+        let Some((blurred, scratch)) = &pass.targets else {
+            return;
+        };
+        ctx.queue.write_buffer(
+            &pass.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&DepthOfFieldUniform::from(self.settings)),
+        );
+        let blur = pass
+            .blur
+            .get_or_insert_with(|| Blur::new(ctx.device, ctx.queue, Self::BLUR_FORMAT, kernel));
+        if blur.kernel() != kernel {
+            blur.set_kernel(ctx.device, ctx.queue, kernel);
+        }
+
+        let downsample = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("DepthOfField::downsample"),
+            layout: &pass.downsample_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&ctx.input.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&pass.sampler),
+                },
+            ],
+        });
+        super::draw_fullscreen(
+            encoder,
+            "DepthOfField::downsample",
+            &blurred.view,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            &pass.downsample_pipeline,
+            &downsample,
+        );
+        let size = blurred.texture.size();
+        blur.run(
+            ctx.device,
+            encoder,
+            &blurred.view,
+            &scratch.view,
+            &blurred.view,
+            [size.width, size.height],
+        );
+
+        let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("DepthOfField"),
+            layout: &pass.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&ctx.input.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&pass.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: pass.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(ctx.depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: ctx.camera.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&blurred.view),
+                },
+            ],
+        });
+        super::draw_fullscreen(
+            encoder,
+            "DepthOfField",
+            ctx.output,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            &pass.pipeline,
+            &bind_group,
+        );
+    }
+}
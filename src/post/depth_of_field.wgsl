@@ -0,0 +1,79 @@
+#include "include/camera.wgsl"
+
+struct DepthOfFieldUniform {
+    focus_distance: f32,
+    aperture: f32,
+    max_radius: f32,
+    // Non-zero to focus on whatever is at the center of the screen instead
+    // of at `focus_distance`.
+    autofocus: u32,
+}
+
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
+@group(0) @binding(2)
+var<uniform> dof: DepthOfFieldUniform;
+@group(0) @binding(3)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(4)
+var<uniform> camera: CameraUniform;
+// The input at half size, blurred by `max_radius`.
+@group(0) @binding(5)
+var t_blurred: texture_2d<f32>;
+
+// How far from the camera the surface at `pixel` is, in world units.
+fn view_distance(pixel: vec2<i32>) -> f32 {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = camera.inv_view_proj * ndc;
+    return distance(world.xyz / world.w, camera.view_pos.xyz);
+}
+
+fn focus_distance() -> f32 {
+    if (dof.autofocus == 0u) {
+        return dof.focus_distance;
+    }
+    // The nearest of a few pixels around the center, so an edge under it
+    // doesn't flicker between what's in front and what's behind.
+    let center = vec2<i32>(textureDimensions(t_depth)) / 2;
+    var nearest = view_distance(center);
+    for (var i = 0; i < 4; i += 1) {
+        let offset = vec2<i32>(select(-2, 2, (i & 1) != 0), select(-2, 2, (i & 2) != 0));
+        nearest = min(nearest, view_distance(center + offset));
+    }
+    return nearest;
+}
+
+// The circle of confusion's radius in pixels, for a surface `distance` away:
+// zero at the focus distance, growing towards `max_radius` either side.
+fn coc(distance: f32, focus: f32) -> f32 {
+    let blur = dof.aperture * abs(distance - focus) / max(distance, 1e-4);
+    return min(blur, 1.0) * dof.max_radius;
+}
+
+// Averages the four input pixels under each of the half-size copy's.
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSampleLevel(t_input, s_input, in.uv, 0.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let color = textureLoad(t_input, pixel, 0);
+    if (dof.max_radius < 0.5) {
+        return color;
+    }
+    let focus = focus_distance();
+    let center_coc = coc(view_distance(pixel), focus);
+
+    // Out of focus by a whole `max_radius` takes all of the blur; anything
+    // less, the share of it its circle covers.
+    let blurred = textureSampleLevel(t_blurred, s_input, in.uv, 0.0).rgb;
+    let amount = clamp(center_coc / dof.max_radius, 0.0, 1.0);
+    return vec4<f32>(mix(color.rgb, blurred, amount), color.a);
+}
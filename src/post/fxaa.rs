@@ -0,0 +1,86 @@
+use super::{FullscreenPass, PostContext, PostEffect};
+use crate::preprocess::{self, ShaderDefs};
+
+#[derive(Debug, Copy, Clone)]
+pub struct FxaaSettings {
+    pub edge_threshold: f32,
+    pub edge_threshold_min: f32,
+    pub span_max: f32,
+}
+
+impl Default for FxaaSettings {
+    fn default() -> Self {
+        Self {
+            edge_threshold: 0.125,
+            edge_threshold_min: 0.0312,
+            span_max: 8.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FxaaUniform {
+    edge_threshold: f32,
+    edge_threshold_min: f32,
+    span_max: f32,
+    _padding: u32,
+}
+
+impl From<FxaaSettings> for FxaaUniform {
+    fn from(settings: FxaaSettings) -> Self {
+        Self {
+            edge_threshold: settings.edge_threshold,
+            edge_threshold_min: settings.edge_threshold_min,
+            span_max: settings.span_max,
+            _padding: 0,
+        }
+    }
+}
+
+/// Fast approximate anti-aliasing on the final image.
+pub struct Fxaa {
+    pub settings: FxaaSettings,
+    pub enabled: bool,
+    pass: Option<FullscreenPass<FxaaUniform>>,
+}
+
+impl Fxaa {
+    pub fn new(settings: FxaaSettings) -> Self {
+        Self {
+            settings,
+            enabled: true,
+            pass: None,
+        }
+    }
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Self::new(FxaaSettings::default())
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn setup(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        self.pass = Some(FullscreenPass::new(
+            device,
+            "Fxaa",
+            &preprocess::builtin_source("post/fxaa.wgsl", &ShaderDefs::new()),
+            format,
+            self.settings.into(),
+        ));
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn record(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext) {
+        let Some(pass) = &self.pass else {
+            return;
+        };
+        pass.write_uniform(ctx.queue, self.settings.into());
+        pass.record(encoder, ctx);
+    }
+}
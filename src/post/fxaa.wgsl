@@ -0,0 +1,60 @@
+struct FxaaUniform {
+    // Minimum local contrast, relative to the brightest neighbour, that
+    // counts as an edge.
+    edge_threshold: f32,
+    // Absolute contrast floor so dark regions are left alone.
+    edge_threshold_min: f32,
+    // Longest blur along an edge, in texels.
+    span_max: f32,
+}
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
+@group(0) @binding(2)
+var<uniform> fxaa: FxaaUniform;
+
+const REDUCE_MUL: f32 = 1.0 / 8.0;
+const REDUCE_MIN: f32 = 1.0 / 128.0;
+
+fn tap(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_input, s_input, uv, 0.0).rgb;
+}
+
+// The input is linear; the square root approximates perceptual luma.
+fn luma(color: vec3<f32>) -> f32 {
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_input));
+    let center = textureSampleLevel(t_input, s_input, in.uv, 0.0);
+    let luma_m = luma(center.rgb);
+    let luma_nw = luma(tap(in.uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = luma(tap(in.uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = luma(tap(in.uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = luma(tap(in.uv + vec2<f32>(1.0, 1.0) * texel));
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+    if luma_max - luma_min < max(fxaa.edge_threshold_min, luma_max * fxaa.edge_threshold) {
+        return center;
+    }
+
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    let rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2<f32>(-fxaa.span_max), vec2<f32>(fxaa.span_max)) * texel;
+
+    let rgb_a = 0.5 * (tap(in.uv + dir * (1.0 / 3.0 - 0.5)) + tap(in.uv + dir * (2.0 / 3.0 - 0.5)));
+    let rgb_b = rgb_a * 0.5 + 0.25 * (tap(in.uv - dir * 0.5) + tap(in.uv + dir * 0.5));
+    let luma_b = luma(rgb_b);
+    if luma_b < luma_min || luma_b > luma_max {
+        return vec4<f32>(rgb_a, center.a);
+    }
+    return vec4<f32>(rgb_b, center.a);
+}
@@ -0,0 +1,431 @@
+use std::any::Any;
+use wgpu::util::DeviceExt;
+
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+pub mod bloom;
+pub mod color_grading;
+pub mod depth_of_field;
+pub mod fxaa;
+pub mod taa;
+pub mod vignette;
+
+pub use color_grading::ColorGrading;
+pub use depth_of_field::DepthOfField;
+pub use fxaa::Fxaa;
+pub use vignette::Vignette;
+
+/// Adds color into the target and leaves its alpha untouched.
+pub(crate) const ADDITIVE_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// Compiles `source` after the shared fullscreen vertex stage, which
+/// provides `VertexOutput` and `vs_main`.
+pub fn create_shader(device: &wgpu::Device, label: &str, source: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(
+            format!(
+                "{}\n{}",
+                preprocess::builtin_source("post/fullscreen.wgsl", &ShaderDefs::new()),
+                source
+            )
+            .into(),
+        ),
+    })
+}
+
+pub fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some(entry_point),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Records a fullscreen draw into `target`; `load` decides whether existing
+/// contents are kept for blending.
+pub fn draw_fullscreen(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    target: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+            depth_slice: None,
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..3, 0..1);
+}
+
+/// What an effect reads from and writes to during `PostEffect::record`.
+pub struct PostContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub input: &'a texture::Texture,
+    pub output: &'a wgpu::TextureView,
+    /// The scene's depth buffer, which `camera` unprojects.
+    pub depth: &'a wgpu::TextureView,
+    pub camera: &'a wgpu::Buffer,
+}
+
+/// A full-screen pass in the `PostStack`.
+///
+/// Effects run after tonemapping, so both input and output are in the
+/// surface format.
+pub trait PostEffect: Any {
+    /// Creates GPU resources; called when the effect joins a stack, and
+    /// again on the new device after `State::recreate_device`.
+    fn setup(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat);
+
+    /// Called after `setup` and whenever the targets change size.
+    fn resize(&mut self, _device: &wgpu::Device, _width: u32, _height: u32) {}
+
+    /// Disabled effects are skipped without breaking the chain.
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn record(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext);
+}
+
+/// Building block for single-pass effects: samples the input at binding 0
+/// with the sampler at 1 and reads a uniform of type `U` at binding 2.
+pub struct FullscreenPass<U: bytemuck::Pod> {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    label: &'static str,
+    _uniform: std::marker::PhantomData<U>,
+}
+
+impl<U: bytemuck::Pod> FullscreenPass<U> {
+    /// `source` is a fragment shader with an `fs_main` entry point; see
+    /// `create_shader`.
+    pub fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        source: &str,
+        format: wgpu::TextureFormat,
+        uniform: U,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let shader = create_shader(device, label, source);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(
+            device,
+            label,
+            &pipeline_layout,
+            &shader,
+            "fs_main",
+            format,
+            None,
+        );
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            layout,
+            pipeline,
+            uniform_buffer,
+            sampler,
+            label,
+            _uniform: std::marker::PhantomData,
+        }
+    }
+
+    pub fn write_uniform(&self, queue: &wgpu::Queue, uniform: U) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    pub fn record(&self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext) {
+        // Inputs alternate between the stack's ping-pong targets, so the
+        // bind group is cheap enough to build per frame.
+        let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(self.label),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&ctx.input.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        draw_fullscreen(
+            encoder,
+            self.label,
+            ctx.output,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            &self.pipeline,
+            &bind_group,
+        );
+    }
+}
+
+/// Ordered chain of `PostEffect`s over two ping-pong targets; the last
+/// enabled effect writes straight to the final output.
+pub struct PostStack {
+    effects: Vec<Box<dyn PostEffect>>,
+    targets: [texture::Texture; 2],
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+}
+
+impl PostStack {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        Self {
+            effects: Vec::new(),
+            targets: Self::create_targets(device, format, width, height),
+            format,
+            width,
+            height,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> [texture::Texture; 2] {
+        std::array::from_fn(|_| {
+            texture::Texture::create_render_target(
+                device,
+                width,
+                height,
+                format,
+                "PostStack::target",
+            )
+        })
+    }
+
+    pub fn push(&mut self, device: &wgpu::Device, mut effect: impl PostEffect) -> usize {
+        effect.setup(device, self.format);
+        effect.resize(device, self.width, self.height);
+        self.effects.push(Box::new(effect));
+        self.effects.len() - 1
+    }
+
+    /// Takes over `old`'s effects in order, setting each up again for
+    /// this stack's device, in place of its own.
+    pub fn carry_over(&mut self, device: &wgpu::Device, old: PostStack) {
+        self.effects.clear();
+        for mut effect in old.effects {
+            effect.setup(device, self.format);
+            effect.resize(device, self.width, self.height);
+            self.effects.push(effect);
+        }
+    }
+
+    /// Sets each effect up again, compiling its pipelines from the current
+    /// shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        for effect in &mut self.effects {
+            effect.setup(device, self.format);
+            effect.resize(device, self.width, self.height);
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Box<dyn PostEffect> {
+        self.effects.remove(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    /// First effect of type `T`.
+    pub fn get<T: PostEffect>(&self) -> Option<&T> {
+        self.effects
+            .iter()
+            .find_map(|effect| (effect.as_ref() as &dyn Any).downcast_ref::<T>())
+    }
+
+    /// First effect of type `T`, for adjusting its settings.
+    pub fn get_mut<T: PostEffect>(&mut self) -> Option<&mut T> {
+        self.effects
+            .iter_mut()
+            .find_map(|effect| (effect.as_mut() as &mut dyn Any).downcast_mut::<T>())
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.targets = Self::create_targets(device, self.format, width, height);
+        for effect in &mut self.effects {
+            effect.resize(device, width, height);
+        }
+    }
+
+    /// Whether any effect will run; when false the scene can skip the
+    /// intermediate target and render to the output directly.
+    pub fn is_active(&self) -> bool {
+        self.effects.iter().any(|effect| effect.enabled())
+    }
+
+    /// Where the stage before the stack should render.
+    pub fn input(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    /// Runs the enabled effects, starting from `input()` and ending in
+    /// `output`, with the scene's `depth` and the `camera` uniform it was
+    /// drawn with.
+    pub fn process(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        camera: &wgpu::Buffer,
+    ) {
+        let count = self
+            .effects
+            .iter()
+            .filter(|effect| effect.enabled())
+            .count();
+        let mut source = 0;
+        for (i, effect) in self
+            .effects
+            .iter_mut()
+            .filter(|effect| effect.enabled())
+            .enumerate()
+        {
+            let target = if i + 1 == count {
+                output
+            } else {
+                &self.targets[1 - source].view
+            };
+            let ctx = PostContext {
+                device,
+                queue,
+                input: &self.targets[source],
+                output: target,
+                depth,
+                camera,
+            };
+            effect.record(encoder, &ctx);
+            source = 1 - source;
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+/// Runtime TAA parameters.
+#[derive(Debug, Copy, Clone)]
+pub struct TaaSettings {
+    pub enabled: bool,
+    /// Weight of the current frame against the accumulated history; lower
+    /// is smoother but slower to react.
+    pub blend: f32,
+}
+
+impl Default for TaaSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            blend: 0.1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaUniform {
+    blend: f32,
+    reset: u32,
+    _padding: [u32; 2],
+}
+
+/// Element `index` of the Halton low-discrepancy sequence in `base`.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Temporal anti-aliasing over the HDR target.
+///
+/// The camera is jittered by a sub-pixel offset every frame, the main pass
+/// writes screen-space velocity into `velocity()`, and `resolve` blends the
+/// reprojected, neighborhood-clamped history into the HDR target before
+/// bloom and tonemapping.
+pub struct Taa {
+    settings: TaaSettings,
+    frame: u32,
+    history_valid: bool,
+    velocity: texture::Texture,
+    // Ping-pong pair: each frame reads one and writes the other.
+    history: [texture::Texture; 2],
+    current: usize,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    // `[i]` reads `history[i]`.
+    bind_groups: Vec<wgpu::BindGroup>,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Taa {
+    pub const VELOCITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+    const JITTER_PHASES: u32 = 8;
+
+    pub fn new(
+        device: &wgpu::Device,
+        camera_buffer: &wgpu::Buffer,
+        input: &texture::Texture,
+        depth_texture: &texture::Texture,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Taa Buffer"),
+            size: size_of::<TaaUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Taa::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let texture_entry = |binding, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Taa::layout"),
+            entries: &[
+                texture_entry(0, true),
+                texture_entry(1, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                texture_entry(3, false),
+                texture_entry(4, false),
+                uniform_entry(5),
+                uniform_entry(6),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Taa Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, input.texture.format());
+
+        let (velocity, history) =
+            Self::create_targets(device, input.texture.format(), width, height);
+        let mut taa = Self {
+            settings: TaaSettings::default(),
+            frame: 0,
+            history_valid: false,
+            velocity,
+            history,
+            current: 0,
+            uniform_buffer,
+            sampler,
+            layout,
+            pipeline_layout,
+            format: input.texture.format(),
+            bind_groups: Vec::new(),
+            pipeline,
+        };
+        taa.bind_groups = taa.create_bind_groups(device, camera_buffer, input, depth_texture);
+        taa
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = super::create_shader(
+            device,
+            "Taa Shader",
+            &preprocess::builtin_source("post/taa.wgsl", &ShaderDefs::new()),
+        );
+        super::create_pipeline(
+            device,
+            "Taa::resolve",
+            pipeline_layout,
+            &shader,
+            "fs_resolve",
+            format,
+            None,
+        )
+    }
+
+    /// Compiles the pipeline again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(device, &self.pipeline_layout, self.format);
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> (texture::Texture, [texture::Texture; 2]) {
+        let target = |format, label| {
+            texture::Texture::create_render_target(device, width, height, format, label)
+        };
+        (
+            target(Self::VELOCITY_FORMAT, "Taa::velocity"),
+            [
+                target(format, "Taa::history"),
+                target(format, "Taa::history"),
+            ],
+        )
+    }
+
+    fn create_bind_groups(
+        &self,
+        device: &wgpu::Device,
+        camera_buffer: &wgpu::Buffer,
+        input: &texture::Texture,
+        depth_texture: &texture::Texture,
+    ) -> Vec<wgpu::BindGroup> {
+        self.history
+            .iter()
+            .map(|history| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Taa::bind_group"),
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&input.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&history.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: wgpu::BindingResource::TextureView(&self.velocity.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 5,
+                            resource: camera_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 6,
+                            resource: self.uniform_buffer.as_entire_binding(),
+                        },
+                    ],
+                })
+            })
+            .collect()
+    }
+
+    /// Recreates the velocity and history targets; call after the input and
+    /// depth textures are recreated. The history restarts from scratch.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        camera_buffer: &wgpu::Buffer,
+        input: &texture::Texture,
+        depth_texture: &texture::Texture,
+        width: u32,
+        height: u32,
+    ) {
+        (self.velocity, self.history) =
+            Self::create_targets(device, input.texture.format(), width, height);
+        self.bind_groups = self.create_bind_groups(device, camera_buffer, input, depth_texture);
+        self.history_valid = false;
+    }
+
+    pub fn settings(&self) -> TaaSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: TaaSettings) {
+        if settings.enabled && !self.settings.enabled {
+            self.history_valid = false;
+        }
+        self.settings = settings;
+    }
+
+    /// Target the main pass writes screen-space velocity into.
+    pub fn velocity(&self) -> &texture::Texture {
+        &self.velocity
+    }
+
+    /// Advances the jitter sequence and returns this frame's NDC offset for
+    /// `CameraUniform::jitter`; zero while disabled.
+    pub fn update(&mut self, queue: &wgpu::Queue, width: u32, height: u32) -> [f32; 2] {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[TaaUniform {
+                blend: self.settings.blend,
+                reset: !self.history_valid as u32,
+                _padding: [0; 2],
+            }]),
+        );
+        if !self.settings.enabled {
+            return [0.0; 2];
+        }
+
+        self.frame = (self.frame + 1) % Self::JITTER_PHASES;
+        // Halton starts at zero for index 0, so skip it.
+        let x = halton(self.frame + 1, 2) - 0.5;
+        let y = halton(self.frame + 1, 3) - 0.5;
+        [
+            2.0 * x / width.max(1) as f32,
+            2.0 * y / height.max(1) as f32,
+        ]
+    }
+
+    /// Blends the history into `input` in place. `input` must be the
+    /// texture the TAA was created or last resized with.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder, input: &texture::Texture) {
+        if !self.settings.enabled {
+            return;
+        }
+
+        let next = 1 - self.current;
+        super::draw_fullscreen(
+            encoder,
+            "Taa::resolve",
+            &self.history[next].view,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            &self.pipeline,
+            &self.bind_groups[self.current],
+        );
+        encoder.copy_texture_to_texture(
+            self.history[next].texture.as_image_copy(),
+            input.texture.as_image_copy(),
+            input.texture.size(),
+        );
+        self.current = next;
+        self.history_valid = true;
+    }
+}
@@ -0,0 +1,71 @@
+#include "include/camera.wgsl"
+
+struct TaaUniform {
+    blend: f32,
+    // Non-zero when the history holds nothing usable yet.
+    reset: u32,
+}
+
+@group(0) @binding(0)
+var t_current: texture_2d<f32>;
+@group(0) @binding(1)
+var t_history: texture_2d<f32>;
+@group(0) @binding(2)
+var s_linear: sampler;
+@group(0) @binding(3)
+var t_velocity: texture_2d<f32>;
+@group(0) @binding(4)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(5)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(6)
+var<uniform> taa: TaaUniform;
+
+// The sky writes no velocity, so reproject its view direction instead.
+fn sky_velocity(uv: vec2<f32>) -> vec2<f32> {
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let point = camera.inv_view_proj * vec4<f32>(ndc, 0.5, 1.0);
+    let direction = point.xyz / point.w - camera.view_pos.xyz;
+    let prev = camera.prev_view_proj * vec4<f32>(direction, 0.0);
+    // Orthographic views keep directions fixed on screen.
+    if abs(prev.w) < 1e-6 {
+        return vec2<f32>(0.0);
+    }
+    let prev_uv = prev.xy / prev.w * vec2<f32>(0.5, -0.5) + 0.5;
+    return uv - prev_uv;
+}
+
+@fragment
+fn fs_resolve(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let current = textureLoad(t_current, pixel, 0);
+    if taa.reset != 0u {
+        return current;
+    }
+
+    // Clamp history to the range of the current 3x3 neighborhood so stale
+    // colors from disoccluded or changed pixels cannot ghost.
+    let size = vec2<i32>(textureDimensions(t_current));
+    var color_min = current.rgb;
+    var color_max = current.rgb;
+    for (var x = -1; x <= 1; x += 1) {
+        for (var y = -1; y <= 1; y += 1) {
+            let neighbor = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            let color = textureLoad(t_current, neighbor, 0).rgb;
+            color_min = min(color_min, color);
+            color_max = max(color_max, color);
+        }
+    }
+
+    var velocity = textureLoad(t_velocity, pixel, 0).xy;
+    if is_far_depth(textureLoad(t_depth, pixel, 0).r) {
+        velocity = sky_velocity(in.uv);
+    }
+    let history_uv = in.uv - velocity;
+    if any(history_uv < vec2<f32>(0.0)) || any(history_uv > vec2<f32>(1.0)) {
+        return current;
+    }
+    let history = textureSampleLevel(t_history, s_linear, history_uv, 0.0).rgb;
+    let clamped = clamp(history, color_min, color_max);
+    return vec4<f32>(mix(clamped, current.rgb, taa.blend), current.a);
+}
@@ -0,0 +1,91 @@
+use super::{FullscreenPass, PostContext, PostEffect};
+use crate::preprocess::{self, ShaderDefs};
+
+#[derive(Debug, Copy, Clone)]
+pub struct VignetteSettings {
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance from the center, 1.0 being a corner, where darkening starts.
+    pub radius: f32,
+    pub softness: f32,
+}
+
+impl Default for VignetteSettings {
+    fn default() -> Self {
+        Self {
+            color: [0.0; 3],
+            intensity: 0.6,
+            radius: 0.5,
+            softness: 0.5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct VignetteUniform {
+    color: [f32; 3],
+    intensity: f32,
+    radius: f32,
+    softness: f32,
+    _padding: [u32; 2],
+}
+
+impl From<VignetteSettings> for VignetteUniform {
+    fn from(settings: VignetteSettings) -> Self {
+        Self {
+            color: settings.color,
+            intensity: settings.intensity,
+            radius: settings.radius,
+            softness: settings.softness,
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// Darkens the image towards its corners.
+pub struct Vignette {
+    pub settings: VignetteSettings,
+    pub enabled: bool,
+    pass: Option<FullscreenPass<VignetteUniform>>,
+}
+
+impl Vignette {
+    pub fn new(settings: VignetteSettings) -> Self {
+        Self {
+            settings,
+            enabled: true,
+            pass: None,
+        }
+    }
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self::new(VignetteSettings::default())
+    }
+}
+
+impl PostEffect for Vignette {
+    fn setup(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        self.pass = Some(FullscreenPass::new(
+            device,
+            "Vignette",
+            &preprocess::builtin_source("post/vignette.wgsl", &ShaderDefs::new()),
+            format,
+            self.settings.into(),
+        ));
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn record(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext) {
+        let Some(pass) = &self.pass else {
+            return;
+        };
+        pass.write_uniform(ctx.queue, self.settings.into());
+        pass.record(encoder, ctx);
+    }
+}
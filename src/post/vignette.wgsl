@@ -0,0 +1,22 @@
+struct VignetteUniform {
+    color: vec3<f32>,
+    intensity: f32,
+    // Distance from the center, 1.0 being a corner, where darkening starts.
+    radius: f32,
+    softness: f32,
+}
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
+@group(0) @binding(2)
+var<uniform> vignette: VignetteUniform;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSampleLevel(t_input, s_input, in.uv, 0.0);
+    let distance = length(in.uv - 0.5) * sqrt(2.0);
+    let falloff = smoothstep(vignette.radius, vignette.radius + vignette.softness, distance);
+    let rgb = mix(color.rgb, vignette.color, falloff * vignette.intensity);
+    return vec4<f32>(rgb, color.a);
+}
@@ -0,0 +1,613 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::LazyLock;
+
+use anyhow::Context;
+
+use crate::{cluster, shadow};
+
+/// Built-in sources, named by their path under `src`.
+const BUILTIN_SOURCES: &[(&str, &str)] = &[
+    ("include/camera.wgsl", include_str!("include/camera.wgsl")),
+    ("include/color.wgsl", include_str!("include/color.wgsl")),
+    ("include/lights.wgsl", include_str!("include/lights.wgsl")),
+    (
+        "include/instance.wgsl",
+        include_str!("include/instance.wgsl"),
+    ),
+    (
+        "include/skinning.wgsl",
+        include_str!("include/skinning.wgsl"),
+    ),
+    ("include/shadows.wgsl", include_str!("include/shadows.wgsl")),
+    ("include/terrain.wgsl", include_str!("include/terrain.wgsl")),
+    ("include/vertex.wgsl", include_str!("include/vertex.wgsl")),
+    ("lighting.wgsl", include_str!("lighting.wgsl")),
+    ("shader.wgsl", include_str!("shader.wgsl")),
+    ("deferred.wgsl", include_str!("deferred.wgsl")),
+    ("decal.wgsl", include_str!("decal.wgsl")),
+    ("cluster.wgsl", include_str!("cluster.wgsl")),
+    ("ssao.wgsl", include_str!("ssao.wgsl")),
+    ("ssr.wgsl", include_str!("ssr.wgsl")),
+    ("volumetric.wgsl", include_str!("volumetric.wgsl")),
+    ("blur.wgsl", include_str!("blur.wgsl")),
+    ("shadow.wgsl", include_str!("shadow.wgsl")),
+    ("point_shadow.wgsl", include_str!("point_shadow.wgsl")),
+    ("shadow_moments.wgsl", include_str!("shadow_moments.wgsl")),
+    ("post/taa.wgsl", include_str!("post/taa.wgsl")),
+    (
+        "post/color_grading.wgsl",
+        include_str!("post/color_grading.wgsl"),
+    ),
+    ("hdr.wgsl", include_str!("hdr.wgsl")),
+    (
+        "post/depth_of_field.wgsl",
+        include_str!("post/depth_of_field.wgsl"),
+    ),
+    ("gpu_culling.wgsl", include_str!("gpu_culling.wgsl")),
+    ("water.wgsl", include_str!("water.wgsl")),
+    ("billboard.wgsl", include_str!("billboard.wgsl")),
+    ("picking.wgsl", include_str!("picking.wgsl")),
+    ("debug_draw.wgsl", include_str!("debug_draw.wgsl")),
+    ("grid.wgsl", include_str!("grid.wgsl")),
+    ("outline.wgsl", include_str!("outline.wgsl")),
+    ("skybox.wgsl", include_str!("skybox.wgsl")),
+    ("sprite.wgsl", include_str!("sprite.wgsl")),
+    ("text.wgsl", include_str!("text.wgsl")),
+    ("oit.wgsl", include_str!("oit.wgsl")),
+    ("hiz.wgsl", include_str!("hiz.wgsl")),
+    ("auto_exposure.wgsl", include_str!("auto_exposure.wgsl")),
+    ("ibl.wgsl", include_str!("ibl.wgsl")),
+    ("probe.wgsl", include_str!("probe.wgsl")),
+    ("material_arrays.wgsl", include_str!("material_arrays.wgsl")),
+    ("debug_blit.wgsl", include_str!("debug_blit.wgsl")),
+    ("msaa.wgsl", include_str!("msaa.wgsl")),
+    ("post/fullscreen.wgsl", include_str!("post/fullscreen.wgsl")),
+    ("post/bloom.wgsl", include_str!("post/bloom.wgsl")),
+    ("post/fxaa.wgsl", include_str!("post/fxaa.wgsl")),
+    ("post/vignette.wgsl", include_str!("post/vignette.wgsl")),
+];
+
+// Built-in sources hot reload read back from disk, which every library
+// made after starts out with in place of the compiled-in ones.
+static RELOADED_SOURCES: LazyLock<parking_lot::RwLock<HashMap<String, String>>> =
+    LazyLock::new(Default::default);
+
+/// A set of `#define`s, ordered so equal sets hash alike and can key a
+/// `PermutationCache`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ShaderDefs(BTreeMap<String, String>);
+
+impl ShaderDefs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines `name` as `value`, which replaces the name wherever it
+    /// appears as a whole word.
+    pub fn with(mut self, name: impl Into<String>, value: impl ToString) -> Self {
+        self.set(name, value);
+        self
+    }
+
+    /// Defines `name` as a `u32` literal, e.g. `63u`.
+    pub fn with_u32(self, name: impl Into<String>, value: u32) -> Self {
+        self.with(name, format!("{}u", value))
+    }
+
+    /// Defines `name` with no value, for `#ifdef`.
+    pub fn with_flag(self, name: impl Into<String>) -> Self {
+        self.with(name, "")
+    }
+
+    pub fn set(&mut self, name: impl Into<String>, value: impl ToString) {
+        self.0.insert(name.into(), value.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.0.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// Named WGSL sources that can `#include` each other.
+///
+/// Supported directives, each on a line of its own:
+///
+/// - `#include "include/camera.wgsl"` pastes a file, named relative to
+///   `src`. Each file is pasted at most once per shader.
+/// - `#define NAME value` and `#define NAME`.
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif`.
+///
+/// Every library starts out with the built-in sources, as last hot
+/// reloaded, and with the limits the Rust side sizes buffers by, such as
+/// `MAX_LIGHTS_PER_CLUSTER` and `CASCADE_COUNT`, already defined.
+pub struct ShaderLibrary {
+    sources: HashMap<String, String>,
+    defs: ShaderDefs,
+}
+
+impl Default for ShaderLibrary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShaderLibrary {
+    pub fn new() -> Self {
+        let mut sources: HashMap<String, String> = BUILTIN_SOURCES
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect();
+        for (name, source) in RELOADED_SOURCES.read().iter() {
+            sources.insert(name.clone(), source.clone());
+        }
+        let defs = ShaderDefs::new()
+            .with_u32("MAX_LIGHTS_PER_CLUSTER", cluster::MAX_LIGHTS_PER_CLUSTER)
+            .with_u32("CASCADE_COUNT", shadow::CASCADE_COUNT as u32);
+        Self { sources, defs }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.sources.get(name).map(String::as_str)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sources.contains_key(name)
+    }
+
+    /// Adds or replaces a source, returning the one it replaced.
+    pub fn insert(&mut self, name: impl Into<String>, source: impl Into<String>) -> Option<String> {
+        self.sources.insert(name.into(), source.into())
+    }
+
+    /// `name` with its includes pasted in, its conditionals resolved and
+    /// `defs` substituted. `defs` take precedence over the library's own.
+    pub fn preprocess(&self, name: &str, defs: &ShaderDefs) -> anyhow::Result<String> {
+        let mut expansion = self.expand(name, defs)?;
+        expansion.output.truncate(expansion.output.trim_end().len());
+        expansion.output.push('\n');
+        Ok(expansion.output)
+    }
+
+    /// Every file `name` pulls in with these `defs`, itself included.
+    pub fn dependencies(&self, name: &str, defs: &ShaderDefs) -> anyhow::Result<Vec<String>> {
+        Ok(self.expand(name, defs)?.included)
+    }
+
+    /// Every source that includes `name`, directly or through another,
+    /// itself included. Includes count whichever branch they're in, as the
+    /// defs that pick one vary between the shaders built from a file.
+    pub fn includers(&self, name: &str) -> Vec<String> {
+        let mut includers = vec![name.to_string()];
+        let mut next = 0;
+        while let Some(included) = includers.get(next).cloned() {
+            next += 1;
+            let directive = format!("\"{}\"", included);
+            let mut direct = self
+                .sources
+                .iter()
+                .filter(|(name, source)| {
+                    !includers.contains(*name)
+                        && source.lines().any(|line| {
+                            line.trim_start()
+                                .strip_prefix("#include")
+                                .is_some_and(|argument| argument.trim() == directive)
+                        })
+                })
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            direct.sort();
+            includers.extend(direct);
+        }
+        includers
+    }
+
+    /// Compiles `name` into a module labelled `label`.
+    pub fn create_module(
+        &self,
+        device: &wgpu::Device,
+        label: &str,
+        name: &str,
+        defs: &ShaderDefs,
+    ) -> anyhow::Result<wgpu::ShaderModule> {
+        let source = self.preprocess(name, defs)?;
+        Ok(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        }))
+    }
+
+    fn expand(&self, name: &str, defs: &ShaderDefs) -> anyhow::Result<Expansion> {
+        let mut expansion = Expansion {
+            defs: self.defs.clone(),
+            output: String::new(),
+            included: Vec::new(),
+        };
+        for (name, value) in defs.iter() {
+            expansion.defs.set(name, value);
+        }
+        self.expand_file(name, &mut expansion)?;
+        Ok(expansion)
+    }
+
+    fn expand_file(&self, name: &str, expansion: &mut Expansion) -> anyhow::Result<()> {
+        if expansion.included.iter().any(|included| included == name) {
+            return Ok(());
+        }
+        expansion.included.push(name.to_string());
+        let source = self
+            .get(name)
+            .with_context(|| format!("No shader source named {:?}", name))?;
+
+        // Whether each enclosing `#ifdef` branch is taken.
+        let mut branches: Vec<bool> = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let at = || format!("{}:{}", name, index + 1);
+            let active = branches.iter().all(|&taken| taken);
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    substitute(line, &expansion.defs, &mut expansion.output);
+                    expansion.output.push('\n');
+                }
+                continue;
+            };
+            let (keyword, argument) = directive
+                .split_once(char::is_whitespace)
+                .map(|(keyword, argument)| (keyword, argument.trim()))
+                .unwrap_or((directive.trim_end(), ""));
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    let defined = expansion.defs.contains(identifier(argument, at)?);
+                    branches.push(defined == (keyword == "ifdef"));
+                }
+                "else" => {
+                    let taken = branches
+                        .last_mut()
+                        .with_context(|| format!("{}: #else without #ifdef", at()))?;
+                    *taken = !*taken;
+                }
+                "endif" => {
+                    branches
+                        .pop()
+                        .with_context(|| format!("{}: #endif without #ifdef", at()))?;
+                }
+                "define" if active => {
+                    let (define, value) = argument
+                        .split_once(char::is_whitespace)
+                        .unwrap_or((argument, ""));
+                    expansion.defs.set(identifier(define, at)?, value.trim());
+                }
+                "include" if active => {
+                    let included = argument
+                        .strip_prefix('"')
+                        .and_then(|rest| rest.strip_suffix('"'))
+                        .with_context(|| format!("{}: expected #include \"file\"", at()))?;
+                    self.expand_file(included, expansion)
+                        .with_context(|| format!("Included from {}", at()))?;
+                }
+                "define" | "include" => {}
+                _ => anyhow::bail!("{}: unknown directive #{}", at(), keyword),
+            }
+        }
+        if !branches.is_empty() {
+            anyhow::bail!("{}: #ifdef without #endif", name);
+        }
+        Ok(())
+    }
+}
+
+struct Expansion {
+    defs: ShaderDefs,
+    output: String,
+    included: Vec<String>,
+}
+
+fn identifier(argument: &str, at: impl Fn() -> String) -> anyhow::Result<&str> {
+    let valid = argument
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && argument
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        anyhow::bail!("{}: expected a name, found {:?}", at(), argument);
+    }
+    Ok(argument)
+}
+
+/// Copies `line` to `output`, replacing whole-word defines that have a value.
+fn substitute(line: &str, defs: &ShaderDefs, output: &mut String) {
+    let mut rest = line;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        match defs.get(word) {
+            Some(value) if !value.is_empty() => output.push_str(value),
+            _ => output.push_str(word),
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+}
+
+/// Compiled variants of one shader or pipeline, keyed by the defines they
+/// were built with, so flipping a define back does not compile it again.
+pub struct PermutationCache<T> {
+    entries: HashMap<ShaderDefs, T>,
+}
+
+impl<T> Default for PermutationCache<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> PermutationCache<T> {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, defs: &ShaderDefs) -> Option<&T> {
+        self.entries.get(defs)
+    }
+
+    /// The variant for `defs`, building it first if it is not cached.
+    pub fn get_or_insert_with(&mut self, defs: &ShaderDefs, build: impl FnOnce() -> T) -> &T {
+        self.entries.entry(defs.clone()).or_insert_with(build)
+    }
+
+    pub fn insert(&mut self, defs: ShaderDefs, value: T) {
+        self.entries.insert(defs, value);
+    }
+
+    /// Drops every variant, e.g. after the sources changed.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Preprocesses one of the built-in sources, which cannot fail short of a
+/// bug in them. Should a hot-reloaded edit break it, the compiled-in
+/// sources are used instead.
+pub(crate) fn builtin_source(name: &str, defs: &ShaderDefs) -> String {
+    ShaderLibrary::new()
+        .preprocess(name, defs)
+        .or_else(|e| {
+            log::error!("{:#}; using the built-in {}", e, name);
+            let mut library = ShaderLibrary::new();
+            for (name, source) in BUILTIN_SOURCES {
+                library.insert(*name, *source);
+            }
+            library.preprocess(name, defs)
+        })
+        .unwrap_or_else(|e| panic!("Built-in shader {} is broken: {:#}", name, e))
+}
+
+/// Replaces the built-in `name` with `source` in the libraries made from
+/// now on, and so in `builtin_source`, returning the source it replaced.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn reload_builtin(name: &str, source: String) -> Option<String> {
+    let previous = ShaderLibrary::new().get(name).map(str::to_string);
+    RELOADED_SOURCES.write().insert(name.to_string(), source);
+    previous
+}
+
+pub(crate) fn builtin_module(
+    device: &wgpu::Device,
+    label: &str,
+    name: &str,
+    defs: &ShaderDefs,
+) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(builtin_source(name, defs).into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library(sources: &[(&str, &str)]) -> ShaderLibrary {
+        let mut library = ShaderLibrary::new();
+        for (name, source) in sources {
+            library.insert(*name, *source);
+        }
+        library
+    }
+
+    #[test]
+    fn nested_ifdefs_take_only_the_enclosed_branches() {
+        let library = library(&[(
+            "test.wgsl",
+            "#ifdef A\n\
+             a\n\
+             #ifndef B\n\
+             a_not_b\n\
+             #else\n\
+             a_and_b\n\
+             #endif\n\
+             #else\n\
+             #ifdef B\n\
+             b_not_a\n\
+             #endif\n\
+             #endif\n",
+        )]);
+        let run = |defs: ShaderDefs| library.preprocess("test.wgsl", &defs).unwrap();
+        assert_eq!(run(ShaderDefs::new()), "\n");
+        assert_eq!(run(ShaderDefs::new().with_flag("A")), "a\na_not_b\n");
+        assert_eq!(
+            run(ShaderDefs::new().with_flag("A").with_flag("B")),
+            "a\na_and_b\n"
+        );
+        assert_eq!(run(ShaderDefs::new().with_flag("B")), "b_not_a\n");
+    }
+
+    #[test]
+    fn unbalanced_conditionals_are_errors() {
+        let library = library(&[
+            ("open.wgsl", "#ifdef A\n"),
+            ("else.wgsl", "#else\n"),
+            ("endif.wgsl", "x\n#endif\n"),
+        ]);
+        let error = |name| {
+            format!(
+                "{:#}",
+                library.preprocess(name, &ShaderDefs::new()).unwrap_err()
+            )
+        };
+        assert_eq!(error("open.wgsl"), "open.wgsl: #ifdef without #endif");
+        assert_eq!(error("else.wgsl"), "else.wgsl:1: #else without #ifdef");
+        assert_eq!(error("endif.wgsl"), "endif.wgsl:2: #endif without #ifdef");
+    }
+
+    #[test]
+    fn defines_substitute_whole_words() {
+        let library = library(&[(
+            "test.wgsl",
+            "#define SIZE 4u\n#define COUNT 2u\nSIZE COUNT SIZED _SIZE\n",
+        )]);
+        let defs = ShaderDefs::new().with_u32("COUNT", 8);
+        // The file's own #define comes after the caller's and replaces it.
+        assert_eq!(
+            library.preprocess("test.wgsl", &defs).unwrap(),
+            "4u 2u SIZED _SIZE\n"
+        );
+    }
+
+    #[test]
+    fn unknown_includes_are_errors_naming_the_line() {
+        let library = library(&[("test.wgsl", "a\n#include \"missing.wgsl\"\n")]);
+        let error = library
+            .preprocess("test.wgsl", &ShaderDefs::new())
+            .unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Included from test.wgsl:2: No shader source named \"missing.wgsl\""
+        );
+    }
+
+    #[test]
+    fn includes_in_untaken_branches_are_skipped() {
+        let library = library(&[(
+            "test.wgsl",
+            "#ifdef A\n#include \"missing.wgsl\"\n#endif\nb\n",
+        )]);
+        assert_eq!(
+            library.preprocess("test.wgsl", &ShaderDefs::new()).unwrap(),
+            "b\n"
+        );
+    }
+
+    #[test]
+    fn include_cycles_paste_each_file_once() {
+        let library = library(&[
+            ("a.wgsl", "#include \"b.wgsl\"\na\n"),
+            ("b.wgsl", "#include \"a.wgsl\"\n#include \"b.wgsl\"\nb\n"),
+        ]);
+        let defs = ShaderDefs::new();
+        assert_eq!(library.preprocess("a.wgsl", &defs).unwrap(), "b\na\n");
+        assert_eq!(
+            library.dependencies("a.wgsl", &defs).unwrap(),
+            ["a.wgsl", "b.wgsl"]
+        );
+    }
+
+    #[test]
+    fn includers_follow_includes_through_every_branch() {
+        let library = library(&[
+            ("test/leaf.wgsl", "leaf\n"),
+            (
+                "test/middle.wgsl",
+                "#ifdef A\n#include \"test/leaf.wgsl\"\n#endif\n",
+            ),
+            ("test/root.wgsl", "#include \"test/middle.wgsl\"\n"),
+            (
+                "test/other.wgsl",
+                "#include \"test/root.wgsl\"\n#include \"test/leaf.wgsl\"\n",
+            ),
+            ("test/unrelated.wgsl", "// #include \"test/leaf.wgsl\"\n"),
+        ]);
+        assert_eq!(
+            library.includers("test/leaf.wgsl"),
+            [
+                "test/leaf.wgsl",
+                "test/middle.wgsl",
+                "test/other.wgsl",
+                "test/root.wgsl"
+            ]
+        );
+        assert_eq!(
+            library.includers("test/unrelated.wgsl"),
+            ["test/unrelated.wgsl"]
+        );
+
+        // The built-in sources, as hot reload maps them to pipelines.
+        let includers = ShaderLibrary::new().includers("include/shadows.wgsl");
+        for name in [
+            "lighting.wgsl",
+            "shader.wgsl",
+            "water.wgsl",
+            "volumetric.wgsl",
+        ] {
+            assert!(
+                includers.iter().any(|includer| includer == name),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn permutation_cache_keys_by_defs_not_by_insertion_order() {
+        let mut cache = PermutationCache::new();
+        let ab = ShaderDefs::new().with_flag("A").with_u32("B", 1);
+        let ba = ShaderDefs::new().with_u32("B", 1).with_flag("A");
+        let mut builds = 0;
+        cache.get_or_insert_with(&ab, || {
+            builds += 1;
+            "ab"
+        });
+        assert_eq!(cache.get_or_insert_with(&ba, || unreachable!()), &"ab");
+        assert_eq!(builds, 1);
+
+        // A different value is a different permutation.
+        let b2 = ShaderDefs::new().with_flag("A").with_u32("B", 2);
+        assert_eq!(cache.get(&b2), None);
+        cache.insert(b2.clone(), "b2");
+        assert_eq!(cache.get(&b2), Some(&"b2"));
+        assert_eq!(cache.get(&ab), Some(&"ab"));
+        assert_eq!(cache.len(), 2);
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
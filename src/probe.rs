@@ -0,0 +1,443 @@
+use wgpu::util::DeviceExt;
+
+use crate::camera::{Camera, Projection};
+use crate::environment::{self, Environment};
+use crate::preprocess::{self, ShaderDefs};
+use crate::render_target::RenderTarget;
+use crate::texture;
+use crate::uniforms::DynamicUniformBuffer;
+
+pub const MAX_REFLECTION_PROBES: usize = 8;
+/// Each face of a capture, before prefiltering down to the environment's
+/// specular size.
+pub(crate) const CAPTURE_SIZE: u32 = 256;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// GL takes any texture of six layers for a cube, never a cube array.
+const MIN_CAPACITY: usize = 2;
+
+/// A reflection probe added to a `State`, in the order they were added.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ReflectionProbeId(pub usize);
+
+/// A point the scene is captured around, for the surfaces near it to
+/// reflect instead of the environment: a room's walls rather than the sky
+/// outside them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ReflectionProbe {
+    pub position: cgmath::Vector3<f32>,
+    /// How far from `position` surfaces reflect the capture.
+    pub radius: f32,
+    /// How far inside `radius` it fades in, from the probes and
+    /// environment around it.
+    pub blend_distance: f32,
+}
+
+impl ReflectionProbe {
+    pub fn new(position: cgmath::Vector3<f32>, radius: f32) -> Self {
+        Self {
+            position,
+            radius,
+            blend_distance: radius * 0.25,
+        }
+    }
+
+    /// Cameras at the probe looking down each cube face, in the faces'
+    /// order, each seeing its face mirrored.
+    pub(crate) fn face_cameras(&self, znear: f32, zfar: f32) -> [Camera; 6] {
+        use cgmath::{EuclideanSpace, Vector3};
+
+        let faces = [
+            (Vector3::unit_x(), Vector3::unit_y()),
+            (-Vector3::unit_x(), Vector3::unit_y()),
+            (Vector3::unit_y(), -Vector3::unit_z()),
+            (-Vector3::unit_y(), Vector3::unit_z()),
+            (Vector3::unit_z(), Vector3::unit_y()),
+            (-Vector3::unit_z(), Vector3::unit_y()),
+        ];
+        let eye = cgmath::Point3::from_vec(self.position);
+        faces.map(|(forward, up)| Camera {
+            eye,
+            target: eye + forward,
+            up,
+            aspect: 1.0,
+            projection: Projection::Perspective { fovy: 90.0 },
+            znear,
+            zfar,
+            // The render target follows the state's.
+            reverse_z: false,
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ProbeRaw {
+    position: [f32; 3],
+    radius: f32,
+    blend_distance: f32,
+    _padding: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ProbeUniform {
+    count: u32,
+    _padding: [u32; 3],
+    probes: [ProbeRaw; MAX_REFLECTION_PROBES],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FaceUniform {
+    face: u32,
+    reverse_z: u32,
+    _padding: [u32; 2],
+}
+
+/// The reflection probes and their captures, prefiltered for roughness
+/// like `Environment::prefiltered` into six layers of one cube array
+/// each.
+///
+/// Lit surfaces pick the probes whose radius they're in by their world
+/// position, nearer ones weighing more, and fall back to the environment
+/// past them. A capture is only as current as its last `State` capture:
+/// it holds the opaque scene and terrain as they were, lit like a
+/// `RenderTarget`, with the environment behind them.
+pub struct ReflectionProbes {
+    probes: Vec<ReflectionProbe>,
+    prefiltered: texture::Texture,
+    uniform_buffer: wgpu::Buffer,
+    // Where each face is resolved before prefiltering.
+    capture: texture::Texture,
+    face_uniforms: DynamicUniformBuffer<FaceUniform>,
+    resolve_layout: wgpu::BindGroupLayout,
+    resolve_pipeline: wgpu::RenderPipeline,
+}
+
+impl ReflectionProbes {
+    pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ReflectionProbes::uniform_buffer"),
+            contents: bytemuck::bytes_of(&<ProbeUniform as bytemuck::Zeroable>::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let capture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("ReflectionProbes::capture"),
+            size: wgpu::Extent3d {
+                width: CAPTURE_SIZE,
+                height: CAPTURE_SIZE,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let capture = texture::Texture {
+            view: capture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("ReflectionProbes::capture"),
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                ..Default::default()
+            }),
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("ReflectionProbes::capture"),
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+            texture: capture,
+        };
+
+        // Each face twice over, the second time for reversed depth.
+        let mut face_uniforms = DynamicUniformBuffer::new(device, "ReflectionProbes::faces", 12);
+        for reverse_z in [false, true] {
+            for face in 0..6 {
+                face_uniforms.push(&FaceUniform {
+                    face,
+                    reverse_z: reverse_z as u32,
+                    _padding: [0; 2],
+                });
+            }
+        }
+        face_uniforms.upload(device, queue);
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty,
+            count: None,
+        };
+        let texture_entry = |binding, view_dimension, filterable| {
+            entry(
+                binding,
+                wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable },
+                    view_dimension,
+                    multisampled: false,
+                },
+            )
+        };
+        let resolve_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ReflectionProbes::resolve_layout"),
+            entries: &[
+                DynamicUniformBuffer::<FaceUniform>::layout_entry(0, wgpu::ShaderStages::FRAGMENT),
+                texture_entry(1, wgpu::TextureViewDimension::D2, false),
+                texture_entry(2, wgpu::TextureViewDimension::D2, false),
+                texture_entry(3, wgpu::TextureViewDimension::Cube, true),
+                entry(
+                    4,
+                    wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                ),
+            ],
+        });
+        let resolve_pipeline = Self::create_resolve_pipeline(device, &resolve_layout);
+
+        Self {
+            probes: Vec::new(),
+            prefiltered: Self::create_prefiltered(device, MIN_CAPACITY),
+            uniform_buffer,
+            capture,
+            face_uniforms,
+            resolve_layout,
+            resolve_pipeline,
+        }
+    }
+
+    fn create_resolve_pipeline(
+        device: &wgpu::Device,
+        resolve_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = preprocess::builtin_module(
+            device,
+            "ReflectionProbes::shader",
+            "probe.wgsl",
+            &ShaderDefs::new(),
+        );
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ReflectionProbes::pipeline_layout"),
+            bind_group_layouts: &[resolve_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("ReflectionProbes::resolve_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(FORMAT.into())],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Compiles the pipeline again from the current shader source.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.resolve_pipeline = Self::create_resolve_pipeline(device, &self.resolve_layout);
+    }
+
+    fn create_prefiltered(device: &wgpu::Device, capacity: usize) -> texture::Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("ReflectionProbes::prefiltered"),
+            size: wgpu::Extent3d {
+                width: environment::PREFILTERED_SIZE,
+                height: environment::PREFILTERED_SIZE,
+                depth_or_array_layers: 6 * capacity as u32,
+            },
+            mip_level_count: environment::PREFILTERED_MIPS,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("ReflectionProbes::prefiltered"),
+            dimension: Some(wgpu::TextureViewDimension::CubeArray),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("ReflectionProbes::prefiltered"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    pub fn probes(&self) -> &[ReflectionProbe] {
+        &self.probes
+    }
+
+    pub fn len(&self) -> usize {
+        self.probes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    pub(crate) fn prefiltered(&self) -> &texture::Texture {
+        &self.prefiltered
+    }
+
+    pub(crate) fn uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+
+    /// Adds `probe`, uncaptured until its layers are prefiltered.
+    pub(crate) fn push(&mut self, probe: ReflectionProbe) -> anyhow::Result<ReflectionProbeId> {
+        if self.probes.len() >= MAX_REFLECTION_PROBES {
+            anyhow::bail!("at most {} reflection probes fit", MAX_REFLECTION_PROBES);
+        }
+        self.probes.push(probe);
+        Ok(ReflectionProbeId(self.probes.len() - 1))
+    }
+
+    pub(crate) fn set(
+        &mut self,
+        id: ReflectionProbeId,
+        probe: ReflectionProbe,
+    ) -> anyhow::Result<()> {
+        let Some(slot) = self.probes.get_mut(id.0) else {
+            anyhow::bail!("there is no reflection probe {}", id.0);
+        };
+        *slot = probe;
+        Ok(())
+    }
+
+    pub(crate) fn clear(&mut self, queue: &wgpu::Queue) {
+        self.probes.clear();
+        self.write_uniform(queue, 0);
+    }
+
+    /// Grows the cube array to hold every probe, doubling it, which loses
+    /// the captures in it. Returns whether it did, and so whether bind
+    /// groups over it need rebuilding and every probe recapturing.
+    pub(crate) fn reserve(&mut self, device: &wgpu::Device) -> bool {
+        let capacity = self.prefiltered.texture.depth_or_array_layers() as usize / 6;
+        if self.probes.len() <= capacity {
+            return false;
+        }
+        let capacity = self
+            .probes
+            .len()
+            .next_power_of_two()
+            .clamp(MIN_CAPACITY, MAX_REFLECTION_PROBES);
+        self.prefiltered = Self::create_prefiltered(device, capacity);
+        true
+    }
+
+    /// Has lighting read the first `count` probes, so one being captured
+    /// sees those before it and not its own stale capture.
+    pub(crate) fn write_uniform(&self, queue: &wgpu::Queue, count: usize) {
+        let mut uniform = <ProbeUniform as bytemuck::Zeroable>::zeroed();
+        uniform.count = count.min(self.probes.len()) as u32;
+        for (raw, probe) in uniform.probes.iter_mut().zip(&self.probes) {
+            *raw = ProbeRaw {
+                position: probe.position.into(),
+                radius: probe.radius,
+                blend_distance: probe.blend_distance,
+                _padding: [0.0; 3],
+            };
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Draws the six `faces` captured with `ReflectionProbe::face_cameras`
+    /// into the capture cube, with `sky` where they saw nothing.
+    pub(crate) fn resolve(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        faces: &[RenderTarget; 6],
+        sky: &texture::Texture,
+    ) {
+        for (face, target) in faces.iter().enumerate() {
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("ReflectionProbes::resolve_bind_group"),
+                layout: &self.resolve_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: self.face_uniforms.binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&target.texture().view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&target.depth().view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&sky.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::Sampler(&sky.sampler),
+                    },
+                ],
+            });
+            let view = self
+                .capture
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("ReflectionProbes::capture_face"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: face as u32,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                });
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("ReflectionProbes::resolve"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.resolve_pipeline);
+            let uniform = face + 6 * target.reverse_z() as usize;
+            pass.set_bind_group(0, &bind_group, &[self.face_uniforms.offset(uniform)]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+
+    /// Prefilters the capture cube, once `resolve` was submitted, into the
+    /// `index`th probe's layers.
+    pub(crate) fn prefilter(&self, device: &wgpu::Device, queue: &wgpu::Queue, index: usize) {
+        Environment::prefilter(
+            device,
+            queue,
+            &self.capture,
+            &self.prefiltered.texture,
+            6 * index as u32,
+        );
+    }
+}
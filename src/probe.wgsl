@@ -0,0 +1,62 @@
+// Turns the six views a reflection probe captured into its cube's faces.
+// A camera looking out of the cube sees each face mirrored, so the views
+// are flipped across; where nothing was drawn, the environment shows.
+
+struct FaceUniform {
+    face: u32,
+    reverse_z: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> face: FaceUniform;
+@group(0) @binding(1)
+var t_color: texture_2d<f32>;
+// Bound as a plain float texture: GL can't `textureLoad` depth textures.
+@group(0) @binding(2)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(3)
+var t_sky: texture_cube<f32>;
+@group(0) @binding(4)
+var s_sky: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Face coordinates in -1..1, with y pointing down the image.
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = vec2<f32>(ndc.x, -ndc.y);
+    return out;
+}
+
+// Matches `face_direction` in ibl.wgsl.
+fn face_direction(index: u32, u: f32, v: f32) -> vec3<f32> {
+    switch index {
+        case 0u: { return normalize(vec3<f32>(1.0, -v, -u)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -v, u)); }
+        case 2u: { return normalize(vec3<f32>(u, 1.0, v)); }
+        case 3u: { return normalize(vec3<f32>(u, -1.0, -v)); }
+        case 4u: { return normalize(vec3<f32>(u, -v, 1.0)); }
+        default: { return normalize(vec3<f32>(-u, -v, -1.0)); }
+    }
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(t_color));
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let mirrored = vec2<i32>(size.x - 1 - pixel.x, pixel.y);
+    let depth = textureLoad(t_depth, mirrored, 0).r;
+    if select(depth >= 1.0, depth <= 0.0, face.reverse_z != 0u) {
+        let direction = face_direction(face.face, in.uv.x, in.uv.y);
+        return vec4<f32>(textureSampleLevel(t_sky, s_sky, direction, 0.0).rgb, 1.0);
+    }
+    return vec4<f32>(textureLoad(t_color, mirrored, 0).rgb, 1.0);
+}
@@ -0,0 +1,212 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// One frame's queries and where their results are read back from.
+struct FrameQueries {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Labels of the sections whose timestamps are in `readback_buffer`,
+    // or None while it is free to record into.
+    labels: Option<Vec<&'static str>>,
+    // Which frame it was, counting those recorded.
+    serial: u64,
+    mapping: bool,
+    mapped: Arc<AtomicBool>,
+}
+
+/// Per-pass GPU durations from timestamp queries.
+///
+/// The frame is cut into sections by `mark` calls inside one encoder: each
+/// lasts from its mark until the next one or `finish`, so wrapping a pass
+/// or a group of them takes one mark before it. Timestamps are written at
+/// the boundaries by the encoder when the device has
+/// `TIMESTAMP_QUERY_INSIDE_ENCODERS`, and otherwise by an empty compute
+/// pass, which needs only `TIMESTAMP_QUERY`. Where the GPU overlaps
+/// neighbouring passes the split between them is approximate.
+///
+/// Every frame records into one of `FRAMES` sets of queries and is read
+/// back without waiting, so results arrive a few frames late; a frame is
+/// left out if all the sets are still in flight.
+pub struct GpuProfiler {
+    frames: Vec<FrameQueries>,
+    // The set being recorded into this frame, if one was free.
+    current: Option<usize>,
+    labels: Vec<&'static str>,
+    recorded: u64,
+    // The serial `timings` are from; sets can finish out of order.
+    shown: u64,
+    inside_encoders: bool,
+    period: f32,
+    timings: Vec<(&'static str, f32)>,
+}
+
+impl GpuProfiler {
+    /// Features `new` can use; request whichever of these the adapter has.
+    /// Only `TIMESTAMP_QUERY` is required.
+    pub const FEATURES: wgpu::Features =
+        wgpu::Features::TIMESTAMP_QUERY.union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+    const FRAMES: usize = 3;
+    const MAX_MARKS: u32 = 32;
+
+    /// None when the device has no timestamp queries.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        let features = device.features();
+        if !features.contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let size = (Self::MAX_MARKS as usize * size_of::<u64>()) as wgpu::BufferAddress;
+        let frames = (0..Self::FRAMES)
+            .map(|_| FrameQueries {
+                query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                    label: Some("GpuProfiler::queries"),
+                    ty: wgpu::QueryType::Timestamp,
+                    count: Self::MAX_MARKS,
+                }),
+                resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("GpuProfiler::resolve"),
+                    size,
+                    usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                }),
+                readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("GpuProfiler::readback"),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                labels: None,
+                serial: 0,
+                mapping: false,
+                mapped: Arc::new(AtomicBool::new(false)),
+            })
+            .collect();
+        Some(Self {
+            frames,
+            current: None,
+            labels: Vec::new(),
+            recorded: 0,
+            shown: 0,
+            inside_encoders: features.contains(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS),
+            period: queue.get_timestamp_period(),
+            timings: Vec::new(),
+        })
+    }
+
+    fn write_timestamp(&self, encoder: &mut wgpu::CommandEncoder, frame: usize, index: u32) {
+        let query_set = &self.frames[frame].query_set;
+        if self.inside_encoders {
+            encoder.write_timestamp(query_set, index);
+        } else {
+            encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("GpuProfiler::mark"),
+                timestamp_writes: Some(wgpu::ComputePassTimestampWrites {
+                    query_set,
+                    beginning_of_pass_write_index: Some(index),
+                    end_of_pass_write_index: None,
+                }),
+            });
+        }
+    }
+
+    /// Ends the current section, if any, and starts one called `label`.
+    /// Sections sharing a label are added up.
+    pub fn mark(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str) {
+        if self.labels.is_empty() {
+            self.current = self.frames.iter().position(|frame| frame.labels.is_none());
+        }
+        let Some(frame) = self.current else {
+            return;
+        };
+        // The last query is reserved for `finish`.
+        if self.labels.len() + 1 >= Self::MAX_MARKS as usize {
+            return;
+        }
+        self.write_timestamp(encoder, frame, self.labels.len() as u32);
+        self.labels.push(label);
+    }
+
+    /// Ends the last section and queues the copy for readback.
+    pub fn finish(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let labels = std::mem::take(&mut self.labels);
+        let Some(frame) = self.current.take() else {
+            return;
+        };
+        if labels.is_empty() {
+            return;
+        }
+        let count = labels.len() as u32 + 1;
+        self.write_timestamp(encoder, frame, count - 1);
+        let queries = &mut self.frames[frame];
+        encoder.resolve_query_set(&queries.query_set, 0..count, &queries.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &queries.resolve_buffer,
+            0,
+            &queries.readback_buffer,
+            0,
+            count as wgpu::BufferAddress * size_of::<u64>() as wgpu::BufferAddress,
+        );
+        self.recorded += 1;
+        queries.labels = Some(labels);
+        queries.serial = self.recorded;
+        queries.mapped.store(false, Ordering::Release);
+    }
+
+    /// Call after submitting the encoder passed to `finish`: starts the
+    /// readback of what it recorded, and collects any that has completed.
+    pub fn after_submit(&mut self, device: &wgpu::Device) {
+        let _ = device.poll(wgpu::PollType::Poll);
+        let period = self.period;
+        for queries in &mut self.frames {
+            let Some(labels) = &queries.labels else {
+                continue;
+            };
+            if queries.mapped.load(Ordering::Acquire) {
+                if queries.serial > self.shown {
+                    self.shown = queries.serial;
+                    let data = queries.readback_buffer.slice(..).get_mapped_range();
+                    let stamps: &[u64] = bytemuck::cast_slice(&data);
+                    self.timings.clear();
+                    for (label, pair) in labels.iter().zip(stamps.windows(2)) {
+                        let ms = pair[1].saturating_sub(pair[0]) as f32 * period / 1_000_000.0;
+                        match self.timings.iter_mut().find(|(name, _)| name == label) {
+                            Some((_, total)) => *total += ms,
+                            None => self.timings.push((label, ms)),
+                        }
+                    }
+                }
+                queries.readback_buffer.unmap();
+                queries.labels = None;
+                queries.mapping = false;
+            } else if !queries.mapping {
+                queries.mapping = true;
+                let mapped = queries.mapped.clone();
+                queries
+                    .readback_buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read, move |result| {
+                        if result.is_ok() {
+                            mapped.store(true, Ordering::Release);
+                        }
+                    });
+            }
+        }
+    }
+
+    /// Milliseconds per section of the most recent completed frame, in the
+    /// order they were first marked.
+    pub fn timings(&self) -> &[(&'static str, f32)] {
+        &self.timings
+    }
+
+    /// Which recorded frame `timings` are from, counting from 1; 0 until
+    /// the first arrives.
+    pub fn timings_frame(&self) -> u64 {
+        self.shown
+    }
+
+    /// The sum of `timings`.
+    pub fn total_ms(&self) -> f32 {
+        self.timings.iter().map(|(_, ms)| ms).sum()
+    }
+}
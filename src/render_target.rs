@@ -0,0 +1,194 @@
+use crate::camera::{Camera, CameraUniform};
+use crate::post::taa::Taa;
+use crate::texture;
+
+/// A render target added to a `State`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RenderTargetId(pub usize);
+
+/// A texture a camera of its own renders the scene into every frame,
+/// before the main view, for in-scene screens such as a security monitor,
+/// a portal or a mirror.
+///
+/// Targets are drawn in the order they were added, so a screen showing one
+/// target can be seen from the ones after it within the same frame, and
+/// from the ones before it a frame late. A target leaves out the meshes
+/// showing itself.
+///
+/// Only the opaque scene and terrain are drawn, forward. None of the main
+/// view's screen-space passes run, so there is no SSAO, TAA, bloom,
+/// transparency or skybox, and point lights are binned by the main view's
+/// clusters, as in the water passes.
+pub struct RenderTarget {
+    /// Its aspect ratio follows the target's size.
+    pub camera: Camera,
+    size: [u32; 2],
+    color: texture::Texture,
+    // What the scene pipelines write velocity to; never read.
+    velocity: texture::Texture,
+    depth: texture::Texture,
+    // White, standing in for SSAO, which only matches the main view.
+    occlusion: texture::Texture,
+    uniform: CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    // Built by the owner of the scene's camera bind group layout, from
+    // `camera_buffer` and `occlusion`.
+    camera_bind_group: Option<wgpu::BindGroup>,
+}
+
+impl RenderTarget {
+    // The main view's clear color, so targets look the same where nothing
+    // is drawn.
+    const CLEAR: wgpu::Color = wgpu::Color {
+        r: 0.1,
+        g: 0.2,
+        b: 0.3,
+        a: 1.0,
+    };
+
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        camera: Camera,
+        size: [u32; 2],
+    ) -> Self {
+        let [width, height] = size.map(|length| length.max(1));
+        let occlusion = texture::Texture::create_render_target(
+            device,
+            width,
+            height,
+            wgpu::TextureFormat::R8Unorm,
+            "RenderTarget::occlusion",
+        );
+        queue.write_texture(
+            occlusion.texture.as_image_copy(),
+            &vec![u8::MAX; (width * height) as usize],
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(width),
+                rows_per_image: Some(height),
+            },
+            occlusion.texture.size(),
+        );
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("RenderTarget::camera_buffer"),
+            size: std::mem::size_of::<CameraUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            camera,
+            size: [width, height],
+            color: texture::Texture::create_render_target(
+                device,
+                width,
+                height,
+                color_format,
+                "RenderTarget::color",
+            ),
+            velocity: texture::Texture::create_render_target(
+                device,
+                width,
+                height,
+                Taa::VELOCITY_FORMAT,
+                "RenderTarget::velocity",
+            ),
+            depth: texture::Texture::create_depth_target(
+                device,
+                width,
+                height,
+                "RenderTarget::depth",
+            ),
+            occlusion,
+            uniform: CameraUniform::new(),
+            camera_buffer,
+            camera_bind_group: None,
+        }
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    /// What the camera saw, in linear HDR.
+    pub fn texture(&self) -> &texture::Texture {
+        &self.color
+    }
+
+    pub(crate) fn depth(&self) -> &texture::Texture {
+        &self.depth
+    }
+
+    pub(crate) fn occlusion(&self) -> &texture::Texture {
+        &self.occlusion
+    }
+
+    /// Whether the last `update` drew with reversed depth.
+    pub(crate) fn reverse_z(&self) -> bool {
+        self.uniform.reverse_z != 0
+    }
+
+    pub(crate) fn camera_buffer(&self) -> &wgpu::Buffer {
+        &self.camera_buffer
+    }
+
+    pub(crate) fn set_camera_bind_group(&mut self, bind_group: wgpu::BindGroup) {
+        self.camera_bind_group = Some(bind_group);
+    }
+
+    pub(crate) fn camera_bind_group(&self) -> Option<&wgpu::BindGroup> {
+        self.camera_bind_group.as_ref()
+    }
+
+    /// Draws with the scene's `reverse_z`, whatever the camera's, as the
+    /// pipelines are shared with it.
+    pub(crate) fn update(&mut self, queue: &wgpu::Queue, reverse_z: bool) {
+        let [width, height] = self.size;
+        self.uniform.update_view_proj(&Camera {
+            aspect: width as f32 / height as f32,
+            reverse_z,
+            ..self.camera
+        });
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    /// A pass drawing the scene into the target, cleared first.
+    pub(crate) fn begin_pass<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+    ) -> wgpu::RenderPass<'e> {
+        let attachment = |view, clear| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("RenderTarget::pass"),
+            color_attachments: &[
+                attachment(&self.color.view, Self::CLEAR),
+                attachment(&self.velocity.view, wgpu::Color::TRANSPARENT),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(texture::Texture::far_depth(self.reverse_z())),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+}
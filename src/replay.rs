@@ -0,0 +1,193 @@
+use winit::event::{MouseButton, MouseScrollDelta, TouchPhase};
+use winit::keyboard::{KeyCode, ModifiersState};
+
+use crate::input::{self, Input};
+
+/// Something the user did that `State` reacts to, in the form a recording
+/// keeps it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InputEvent {
+    Key {
+        code: KeyCode,
+        pressed: bool,
+    },
+    Modifiers(ModifiersState),
+    MouseButton {
+        button: MouseButton,
+        pressed: bool,
+    },
+    /// In physical pixels from the window's top-left.
+    CursorMoved {
+        x: f64,
+        y: f64,
+    },
+    Wheel(MouseScrollDelta),
+    Touch {
+        id: u64,
+        phase: TouchPhase,
+        x: f64,
+        y: f64,
+    },
+}
+
+/// What happened before one update, and how long that frame took.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordedFrame {
+    pub events: Vec<InputEvent>,
+    /// Seconds since the frame before.
+    pub dt: f32,
+}
+
+/// A session's input, frame by frame, to replay with `State::start_replay`.
+///
+/// Replaying feeds each frame's events in and steps time by its recorded
+/// `dt` rather than the clock, so the camera, animations and everything
+/// else simulated come out the same. The renderer draws no random numbers
+/// of its own (SSAO's kernel and noise are fixed-seeded), so there is no
+/// seed to keep. Whatever the debug UI changed is not recorded.
+///
+/// Saved as text, one line per event and a `frame <dt>` line closing each
+/// frame:
+///
+/// ```text
+/// key KeyW down
+/// cursor 412.5 300
+/// frame 0.016
+/// ```
+///
+/// Other lines are `button MouseLeft up`, `modifiers <bits>`,
+/// `wheel lines <x> <y>`, `wheel pixels <x> <y>` and
+/// `touch <id> <started|moved|ended|cancelled> <x> <y>`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputRecording {
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl InputRecording {
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let mut recording = Self::default();
+        let mut events = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let number = |word: &str| -> anyhow::Result<f64> {
+                word.parse()
+                    .map_err(|_| anyhow::anyhow!("line {}: {:?} is not a number", i + 1, word))
+            };
+            let pressed = |word: &str| match word {
+                "down" => Ok(true),
+                "up" => Ok(false),
+                _ => anyhow::bail!("line {}: expected down or up, not {:?}", i + 1, word),
+            };
+            let event = match words.as_slice() {
+                [] => continue,
+                [word, ..] if word.starts_with('#') => continue,
+                ["frame", dt] => {
+                    recording.frames.push(RecordedFrame {
+                        events: std::mem::take(&mut events),
+                        dt: number(dt)? as f32,
+                    });
+                    continue;
+                }
+                ["key", name, state] => InputEvent::Key {
+                    code: input::parse_key(name).ok_or_else(|| {
+                        anyhow::anyhow!("line {}: no known key {:?}", i + 1, name)
+                    })?,
+                    pressed: pressed(state)?,
+                },
+                ["button", name, state] => match Input::parse(name) {
+                    Some(Input::Mouse(button)) => InputEvent::MouseButton {
+                        button,
+                        pressed: pressed(state)?,
+                    },
+                    _ => anyhow::bail!("line {}: no known mouse button {:?}", i + 1, name),
+                },
+                ["modifiers", bits] => InputEvent::Modifiers(ModifiersState::from_bits_truncate(
+                    bits.parse()
+                        .map_err(|_| anyhow::anyhow!("line {}: bad modifiers {:?}", i + 1, bits))?,
+                )),
+                ["cursor", x, y] => InputEvent::CursorMoved {
+                    x: number(x)?,
+                    y: number(y)?,
+                },
+                ["wheel", "lines", x, y] => InputEvent::Wheel(MouseScrollDelta::LineDelta(
+                    number(x)? as f32,
+                    number(y)? as f32,
+                )),
+                ["wheel", "pixels", x, y] => InputEvent::Wheel(MouseScrollDelta::PixelDelta(
+                    winit::dpi::PhysicalPosition::new(number(x)?, number(y)?),
+                )),
+                ["touch", id, phase, x, y] => InputEvent::Touch {
+                    id: id
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("line {}: bad touch id {:?}", i + 1, id))?,
+                    phase: match *phase {
+                        "started" => TouchPhase::Started,
+                        "moved" => TouchPhase::Moved,
+                        "ended" => TouchPhase::Ended,
+                        "cancelled" => TouchPhase::Cancelled,
+                        _ => anyhow::bail!("line {}: no touch phase {:?}", i + 1, phase),
+                    },
+                    x: number(x)?,
+                    y: number(y)?,
+                },
+                _ => anyhow::bail!("line {}: unknown event {:?}", i + 1, line),
+            };
+            events.push(event);
+        }
+        Ok(recording)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Unable to read {}: {}", path.display(), e))?;
+        Self::parse(&source)
+            .map_err(|e| anyhow::anyhow!("Unable to read {}: {:#}", path.display(), e))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    /// The file `parse` reads back as `self`.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let state = |pressed: bool| if pressed { "down" } else { "up" };
+        for frame in &self.frames {
+            for event in &frame.events {
+                let line = match *event {
+                    InputEvent::Key { code, pressed } => {
+                        format!("key {} {}", Input::Key(code), state(pressed))
+                    }
+                    InputEvent::Modifiers(modifiers) => format!("modifiers {}", modifiers.bits()),
+                    InputEvent::MouseButton { button, pressed } => {
+                        format!("button {} {}", Input::Mouse(button), state(pressed))
+                    }
+                    InputEvent::CursorMoved { x, y } => format!("cursor {:?} {:?}", x, y),
+                    InputEvent::Wheel(MouseScrollDelta::LineDelta(x, y)) => {
+                        format!("wheel lines {:?} {:?}", x, y)
+                    }
+                    InputEvent::Wheel(MouseScrollDelta::PixelDelta(position)) => {
+                        format!("wheel pixels {:?} {:?}", position.x, position.y)
+                    }
+                    InputEvent::Touch { id, phase, x, y } => {
+                        let phase = match phase {
+                            TouchPhase::Started => "started",
+                            TouchPhase::Moved => "moved",
+                            TouchPhase::Ended => "ended",
+                            TouchPhase::Cancelled => "cancelled",
+                        };
+                        format!("touch {} {} {:?} {:?}", id, phase, x, y)
+                    }
+                };
+                out.push_str(&line);
+                out.push('\n');
+            }
+            out.push_str(&format!("frame {:?}\n", frame.dt));
+        }
+        out
+    }
+}
@@ -2,18 +2,48 @@ use alloc::format;
 use std::io::{BufReader, Cursor};
 use wgpu::util::DeviceExt;
 
-use crate::{model, texture};
+use crate::color::ColorSpace;
+use crate::mesh::optimize;
+use crate::{atlas, gltf_loader, lod, model, texture};
 
+/// `res/<file_name>` next to the page, matching the native `res` layout.
 #[cfg(target_arch = "wasm32")]
 fn format_url(file_name: &str) -> reqwest::Url {
     let window = web_sys::window().unwrap();
-    let location = window.location();
-    let mut origin = location.origin().unwrap();
-    if !origin.ends_with("learn-wgpu") {
-        origin = format!("{}/learn-wgpu", origin);
-    }
-    let base = reqwest::Url::parse(&format!("{}/", origin,)).unwrap();
-    base.join(file_name).unwrap()
+    let page = window.location().href().unwrap();
+    let base = reqwest::Url::parse(&page).unwrap();
+    base.join("res/").unwrap().join(file_name).unwrap()
+}
+
+// The files under `res` read on this thread while `record_reads` runs.
+#[cfg(not(target_arch = "wasm32"))]
+thread_local! {
+    static READS: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Runs `load`, returning what it made and the files under `res` that it,
+/// or anything it called on this thread, read.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn record_reads<T>(load: impl FnOnce() -> T) -> (T, Vec<String>) {
+    let outer = READS.with(|reads| reads.replace(Some(Vec::new())));
+    let made = load();
+    let files = READS.with(|reads| reads.replace(outer)).unwrap_or_default();
+    (made, files)
+}
+
+// Where `file_name` is, noting the read for `record_reads`.
+#[cfg(not(target_arch = "wasm32"))]
+fn res_path(file_name: &str) -> std::path::PathBuf {
+    READS.with(|reads| {
+        if let Some(reads) = reads.borrow_mut().as_mut()
+            && !reads.iter().any(|read| read == file_name)
+        {
+            reads.push(file_name.to_string());
+        }
+    });
+    std::path::Path::new(env!("OUT_DIR"))
+        .join("res")
+        .join(file_name)
 }
 
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
@@ -23,12 +53,7 @@ pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
         reqwest::get(url).await?.text().await?
     };
     #[cfg(not(target_arch = "wasm32"))]
-    let txt = {
-        let path = std::path::Path::new(env!("OUT_DIR"))
-            .join("res")
-            .join(file_name);
-        std::fs::read_to_string(path)?
-    };
+    let txt = std::fs::read_to_string(res_path(file_name))?;
 
     Ok(txt)
 }
@@ -40,12 +65,7 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
         reqwest::get(url).await?.bytes().await?.to_vec()
     };
     #[cfg(not(target_arch = "wasm32"))]
-    let data = {
-        let path = std::path::Path::new(env!("OUT_DIR"))
-            .join("res")
-            .join(file_name);
-        std::fs::read(path)?
-    };
+    let data = std::fs::read(res_path(file_name))?;
 
     Ok(data)
 }
@@ -54,17 +74,57 @@ pub async fn load_texture(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    load_texture_in(file_name, ColorSpace::Srgb, device, queue).await
+}
+
+pub async fn load_normal_texture(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    load_texture_in(file_name, ColorSpace::Linear, device, queue).await
+}
+
+pub async fn load_texture_in(
+    file_name: &str,
+    color_space: ColorSpace,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
     let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name)
+    texture::Texture::from_bytes_in(device, queue, &data, file_name, color_space)
+}
+
+/// Loads a pre-baked atlas description and the image it names, which is
+/// looked up next to it.
+pub async fn load_texture_atlas(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<atlas::TextureAtlas> {
+    let layout = atlas::AtlasLayout::from_json(&load_string(file_name).await?)?;
+    let image_name = layout
+        .image
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("atlas {} names no image", file_name))?;
+    let data = load_binary(&gltf_loader::resolve_uri(file_name, image_name)).await?;
+    let image = image::load_from_memory(&data)?;
+    atlas::TextureAtlas::new(device, queue, &image, layout, file_name)
 }
 
+/// Loads an OBJ or glTF model, preparing its meshes as `options` asks.
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+    options: model::ImportOptions,
 ) -> anyhow::Result<model::Model> {
+    if file_name.ends_with(".gltf") || file_name.ends_with(".glb") {
+        return gltf_loader::load_gltf(file_name, device, queue, layout, options).await;
+    }
+
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
@@ -86,87 +146,121 @@ pub async fn load_model(
     let mut materials = Vec::new();
     for m in obj_materials? {
         let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
-
-        materials.push(model::Material {
-            name: m.name,
-            diffuse_texture,
-            bind_group,
-        })
+        let mut textures = model::MaterialTextures::new(device, queue, diffuse_texture)?;
+        if !m.normal_texture.is_empty() {
+            textures.normal = load_normal_texture(&m.normal_texture, device, queue).await?;
+        }
+        // tobj leaves the emissive `Ke` and `map_Ke` to unknown_param.
+        let mut emissive = m
+            .unknown_param
+            .get("Ke")
+            .map(|ke| parse_color(ke, file_name))
+            .transpose()?;
+        if let Some(file) = m.unknown_param.get("map_Ke") {
+            textures.emissive = load_texture(file, device, queue).await?;
+            emissive.get_or_insert([1.0; 3]);
+        }
+        // MTL has no metallic-roughness model; map the Phong exponent onto a
+        // roughness that gives a similar highlight size.
+        let mut factors = model::MaterialFactors {
+            metallic: 0.0,
+            roughness: (2.0 / (m.shininess + 2.0)).sqrt(),
+            ..Default::default()
+        };
+        // Ke is unbounded, so anything brighter than 1 goes to the strength.
+        if let Some(emissive) = emissive {
+            let strength = emissive.into_iter().fold(1.0, f32::max);
+            factors.emissive = emissive.map(|c| c / strength);
+            factors.emissive_strength = strength;
+        }
+        materials.push(model::Material::new(
+            device, &m.name, textures, factors, layout,
+        ));
     }
 
     let meshes = models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| {
-                    if m.mesh.normals.is_empty() {
-                        model::ModelVertex {
-                            position: [
-                                m.mesh.positions[i * 3],
-                                m.mesh.positions[i * 3 + 1],
-                                m.mesh.positions[i * 3 + 2],
-                            ],
-                            tex_coords: [
-                                m.mesh.texcoords[i * 2],
-                                1.0 - m.mesh.texcoords[i * 2 + 1],
-                            ],
-                            normal: [0.0, 0.0, 0.0],
-                        }
+            let mut vertices = (0..m.mesh.positions.len() / 3)
+                .map(|i| model::ModelVertex {
+                    position: [
+                        m.mesh.positions[i * 3],
+                        m.mesh.positions[i * 3 + 1],
+                        m.mesh.positions[i * 3 + 2],
+                    ],
+                    tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
+                    normal: if m.mesh.normals.is_empty() {
+                        [0.0, 0.0, 0.0]
                     } else {
-                        model::ModelVertex {
-                            position: [
-                                m.mesh.positions[i * 3],
-                                m.mesh.positions[i * 3 + 1],
-                                m.mesh.positions[i * 3 + 2],
-                            ],
-                            tex_coords: [
-                                m.mesh.texcoords[i * 2],
-                                1.0 - m.mesh.texcoords[i * 2 + 1],
-                            ],
-                            normal: [
-                                m.mesh.normals[i * 3],
-                                m.mesh.normals[i * 3 + 1],
-                                m.mesh.normals[i * 3 + 2],
-                            ],
-                        }
-                    }
+                        [
+                            m.mesh.normals[i * 3],
+                            m.mesh.normals[i * 3 + 1],
+                            m.mesh.normals[i * 3 + 2],
+                        ]
+                    },
+                    tangent: [0.0; 3],
+                    bitangent: [0.0; 3],
                 })
                 .collect::<Vec<_>>();
+            let mut indices = m.mesh.indices.clone();
+            model::compute_tangents(&mut vertices, &indices);
+            if options.optimize {
+                optimize::optimize(&mut vertices, &mut indices);
+            }
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
+            let vertex_buffer = options.vertex_encoding.create_vertex_buffer(
+                device,
+                &format!("{:?} Vertex Buffer", file_name),
+                &vertices,
+            );
+            let (all_indices, lods) = if options.lods {
+                lod::generate(&vertices, &indices)
+            } else {
+                (indices.clone(), Vec::new())
+            };
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
+                contents: bytemuck::cast_slice(&all_indices),
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
             });
 
             model::Mesh {
                 name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
+                num_elements: indices.len() as u32,
                 material: m.mesh.material_id.unwrap_or(0),
+                bounds: model::Bounds::from_points(vertices.iter().map(|v| v.position)),
+                skin_buffer: None,
+                geometry: Some(model::MeshGeometry::new(&vertices, &indices)),
+                lods,
             }
         })
         .collect::<Vec<_>>();
 
-    Ok(model::Model { meshes, materials })
+    Ok(model::Model {
+        meshes,
+        materials,
+        skeleton: None,
+        animations: Vec::new(),
+        vertex_encoding: options.vertex_encoding,
+    })
+}
+
+// An MTL color: `r g b`, or one value for all three.
+fn parse_color(value: &str, file_name: &str) -> anyhow::Result<[f32; 3]> {
+    let channels = value
+        .split_whitespace()
+        .map(str::parse::<f32>)
+        .collect::<Result<Vec<_>, _>>()
+        .ok();
+    match channels.as_deref() {
+        Some(&[r, g, b]) => Ok([r, g, b]),
+        Some(&[grey]) => Ok([grey; 3]),
+        _ => anyhow::bail!(
+            "{}: color {:?} should be one or three numbers",
+            file_name,
+            value
+        ),
+    }
 }
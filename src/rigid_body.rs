@@ -0,0 +1,208 @@
+//! Rigid bodies simulated by rapier, behind the `physics` feature, for
+//! dropping stacks of boxes onto the scene.
+
+use std::collections::HashMap;
+
+use cgmath::{ElementWise, One, Vector3};
+use rapier3d::prelude::*;
+
+use crate::animation::Transform;
+use crate::ecs::{Entity, World};
+use crate::instance::Instance;
+use crate::model::Bounds;
+use crate::state::State;
+
+pub use rapier3d;
+
+/// Rapier's bodies and colliders, stepped with the state's fixed timestep.
+///
+/// Bodies added with `attach` belong to an entity, which carries their
+/// `RigidBodyHandle` and `ColliderHandle` as components, and move its
+/// `Transform`: translation and rotation, leaving the scale alone. Once
+/// the entity is despawned or loses the handle, the body goes too.
+#[derive(Default)]
+pub struct Physics {
+    /// What rapier simulates; bodies added here directly move nothing.
+    pub rapier: PhysicsWorld,
+    entities: HashMap<RigidBodyHandle, Entity>,
+    // Where each body was before the last step, to interpolate from.
+    previous: HashMap<RigidBodyHandle, Pose>,
+}
+
+impl Physics {
+    /// Adds `body` with `collider` where `entity`'s `Transform` is, and
+    /// gives the entity both handles. None if it has no `Transform`.
+    pub fn attach(
+        &mut self,
+        world: &mut World,
+        entity: Entity,
+        body: impl Into<RigidBody>,
+        collider: impl Into<Collider>,
+    ) -> Option<RigidBodyHandle> {
+        let transform = world.get::<Transform>(entity)?;
+        let mut body = body.into();
+        body.set_position(pose(transform), true);
+        let (handle, collider) = self.rapier.insert(body, collider);
+        world.insert(entity, handle);
+        world.insert(entity, collider);
+        self.entities.insert(handle, entity);
+        Some(handle)
+    }
+
+    /// Removes `entity`'s body and its colliders, leaving the entity where
+    /// the body last put it.
+    pub fn detach(&mut self, world: &mut World, entity: Entity) {
+        if let Some(handle) = world.remove::<RigidBodyHandle>(entity) {
+            self.remove(handle);
+        }
+        world.remove::<ColliderHandle>(entity);
+    }
+
+    fn remove(&mut self, handle: RigidBodyHandle) {
+        self.rapier.remove_body(handle);
+        self.entities.remove(&handle);
+        self.previous.remove(&handle);
+    }
+
+    pub fn len(&self) -> usize {
+        self.rapier.bodies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rapier.bodies.is_empty()
+    }
+
+    /// Removes every body, keeping the gravity and integration parameters;
+    /// for after the entities they are attached to have been despawned.
+    pub fn clear(&mut self) {
+        self.rapier = PhysicsWorld {
+            gravity: self.rapier.gravity,
+            integration_parameters: self.rapier.integration_parameters,
+            ..PhysicsWorld::default()
+        };
+        self.entities.clear();
+        self.previous.clear();
+    }
+
+    /// Moves every body on by `dt` seconds.
+    pub fn step(&mut self, dt: f32) {
+        self.previous.clear();
+        self.previous.extend(
+            self.rapier
+                .bodies
+                .iter()
+                .map(|(handle, body)| (handle, *body.position())),
+        );
+        self.rapier.integration_parameters.dt = dt;
+        self.rapier.step();
+    }
+
+    /// Moves each attached entity to where its body is `alpha` of the way
+    /// through the last step, first dropping the bodies whose entity is
+    /// gone. Transforms already there are left unchanged.
+    pub fn sync(&mut self, world: &mut World, alpha: f32) {
+        let detached = self
+            .entities
+            .iter()
+            .filter(|&(handle, &entity)| world.get::<RigidBodyHandle>(entity) != Some(handle))
+            .map(|(&handle, _)| handle)
+            .collect::<Vec<_>>();
+        for handle in detached {
+            self.remove(handle);
+        }
+        for (handle, &entity) in &self.entities {
+            let Some(body) = self.rapier.bodies.get(*handle) else {
+                continue;
+            };
+            let now = body.position();
+            let before = self.previous.get(handle).unwrap_or(now);
+            let t = before.translation.lerp(now.translation, alpha);
+            let r = before.rotation.slerp(now.rotation, alpha);
+            let translation = Vector3::new(t.x, t.y, t.z);
+            let rotation = cgmath::Quaternion::new(r.w, r.x, r.y, r.z);
+            if world.get::<Transform>(entity).is_some_and(|transform| {
+                transform.translation != translation || transform.rotation != rotation
+            }) && let Some(transform) = world.get_mut::<Transform>(entity)
+            {
+                transform.translation = translation;
+                transform.rotation = rotation;
+            }
+        }
+    }
+}
+
+fn pose(transform: &Transform) -> Pose {
+    let Vector3 { x, y, z } = transform.translation;
+    let cgmath::Quaternion {
+        s,
+        v: Vector3 { x: i, y: j, z: k },
+    } = transform.rotation;
+    Pose::from_parts(
+        Vector::new(x, y, z),
+        rapier3d::math::Rotation::from_xyzw(i, j, k, s),
+    )
+}
+
+/// Replaces the scene with a floor and a stack of `count` copies of the
+/// scene model dropped onto it, each a little off the one below. The
+/// boxes are the model's bounds, centered on the instances.
+pub fn drop_stack(state: &mut State, count: usize) {
+    let half_extents = box_half_extents(&state.model_bounds());
+    let floor = Vector3::new(20.0, 0.5, 20.0);
+
+    state.clear_instances();
+    state.physics_mut().clear();
+    let mut add = |position, scale, body: RigidBodyBuilder, extents: Vector3<f32>| {
+        let entity = state.add_instance(Instance {
+            position,
+            rotation: cgmath::Quaternion::one(),
+            scale,
+        });
+        let collider = ColliderBuilder::cuboid(extents.x, extents.y, extents.z);
+        state.add_rigid_body(entity, body, collider);
+    };
+    add(
+        Vector3::new(0.0, -floor.y, 0.0),
+        floor.div_element_wise(half_extents),
+        RigidBodyBuilder::fixed(),
+        floor,
+    );
+    for i in 0..count {
+        let y = half_extents.y * (2.0 * i as f32 + 1.0) * 1.2 + 1.0;
+        let sway = (i as f32 * 2.4).sin() * half_extents.x * 0.3;
+        let position = Vector3::new(sway, y, (i as f32 * 1.7).cos() * half_extents.z * 0.3);
+        add(
+            position,
+            Vector3::new(1.0, 1.0, 1.0),
+            RigidBodyBuilder::dynamic(),
+            half_extents,
+        );
+    }
+}
+
+/// Half the size of the box around `bounds`, at least `MIN_HALF_EXTENT`
+/// along each axis: a flat model still gets a box to stack and to scale
+/// the floor by.
+fn box_half_extents(bounds: &Bounds) -> Vector3<f32> {
+    const MIN_HALF_EXTENT: f32 = 0.01;
+    ((bounds.max - bounds.min) / 2.0).map(|half| half.max(MIN_HALF_EXTENT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_models_get_boxes_with_thickness() {
+        let plane = Bounds::from_points([[-1.0, 0.0, -2.0], [1.0, 0.0, 2.0]]);
+        let half_extents = box_half_extents(&plane);
+        assert_eq!(half_extents.x, 1.0);
+        assert_eq!(half_extents.z, 2.0);
+        assert!(half_extents.y > 0.0);
+        let floor_scale = Vector3::new(20.0, 0.5, 20.0).div_element_wise(half_extents);
+        assert!(floor_scale.y.is_finite());
+
+        let point = box_half_extents(&Bounds::from_points([]));
+        assert!(point.x > 0.0 && point.y > 0.0 && point.z > 0.0);
+    }
+}
@@ -0,0 +1,418 @@
+use cgmath::{InnerSpace, SquareMatrix};
+
+use crate::animation::Transform;
+use crate::ecs::{Entity, World};
+
+/// Handle to a node of a `SceneGraph`; stays valid until the node is
+/// removed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    local: Transform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world: cgmath::Matrix4<f32>,
+    entity: Option<Entity>,
+    // The local transform or parent changed since the world matrix was
+    // last computed.
+    dirty: bool,
+}
+
+impl Node {
+    pub fn local(&self) -> &Transform {
+        &self.local
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// As of the last `SceneGraph::update`.
+    pub fn world(&self) -> cgmath::Matrix4<f32> {
+        self.world
+    }
+
+    /// The entity whose `Transform` this node places, if any.
+    pub fn entity(&self) -> Option<Entity> {
+        self.entity
+    }
+}
+
+/// Nodes with local transforms relative to their parents.
+///
+/// Changing a node only marks it dirty; `update` recomputes the world
+/// matrices of dirty nodes and everything below them, and writes the
+/// results into the `Transform`s of the entities the nodes are attached to.
+/// Entities without a node are left alone, so flat and hierarchical objects
+/// can share a world.
+#[derive(Debug, Clone, Default)]
+pub struct SceneGraph {
+    // Removed nodes leave a hole so the other ids stay valid.
+    nodes: Vec<Option<Node>>,
+    free: Vec<usize>,
+    roots: Vec<NodeId>,
+    dirty: bool,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node under `parent`, or as a root. A `parent` that does not
+    /// exist also makes a root.
+    pub fn add(&mut self, name: &str, local: Transform, parent: Option<NodeId>) -> NodeId {
+        let node = Node {
+            name: name.to_string(),
+            local,
+            parent: None,
+            children: Vec::new(),
+            world: cgmath::Matrix4::identity(),
+            entity: None,
+            dirty: true,
+        };
+        let id = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = Some(node);
+                NodeId(slot)
+            }
+            None => {
+                self.nodes.push(Some(node));
+                NodeId(self.nodes.len() - 1)
+            }
+        };
+        self.roots.push(id);
+        if parent.is_some() {
+            self.set_parent(id, parent);
+        }
+        self.dirty = true;
+        id
+    }
+
+    /// Removes `id` and everything below it. Their entities keep their
+    /// last transforms.
+    pub fn remove(&mut self, id: NodeId) {
+        let Some(node) = self.get(id) else {
+            return;
+        };
+        let parent = node.parent;
+        self.detach(id, parent);
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.nodes[id.0].take() {
+                stack.extend(node.children);
+                self.free.push(id.0);
+            }
+        }
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id.0).and_then(Option::as_ref)
+    }
+
+    fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.nodes.get_mut(id.0).and_then(Option::as_mut)
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes
+            .iter()
+            .position(|node| node.as_ref().is_some_and(|node| node.name == name))
+            .map(NodeId)
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn set_local(&mut self, id: NodeId, local: Transform) {
+        if let Some(node) = self.get_mut(id) {
+            node.local = local;
+            node.dirty = true;
+            self.dirty = true;
+        }
+    }
+
+    /// Places `entity` at the node's world transform from the next
+    /// `update` on; `None` detaches it.
+    pub fn set_entity(&mut self, id: NodeId, entity: Option<Entity>) {
+        if let Some(node) = self.get_mut(id) {
+            node.entity = entity;
+            node.dirty = true;
+            self.dirty = true;
+        }
+    }
+
+    /// Moves `id` under `parent`, or to the roots. Refused, returning
+    /// false, when `parent` is `id` or below it.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> bool {
+        let Some(node) = self.get(id) else {
+            return false;
+        };
+        let old_parent = node.parent;
+        let parent = parent.filter(|&parent| self.get(parent).is_some());
+        let mut ancestor = parent;
+        while let Some(current) = ancestor {
+            if current == id {
+                return false;
+            }
+            ancestor = self.get(current).and_then(|node| node.parent);
+        }
+
+        self.detach(id, old_parent);
+        match parent {
+            Some(parent) => {
+                if let Some(parent) = self.get_mut(parent) {
+                    parent.children.push(id);
+                }
+            }
+            None => self.roots.push(id),
+        }
+        if let Some(node) = self.get_mut(id) {
+            node.parent = parent;
+            node.dirty = true;
+        }
+        self.dirty = true;
+        true
+    }
+
+    // Unlinks `id` from `parent`'s children, or from the roots.
+    fn detach(&mut self, id: NodeId, parent: Option<NodeId>) {
+        let siblings = match parent {
+            Some(parent) => match self.get_mut(parent) {
+                Some(parent) => &mut parent.children,
+                None => return,
+            },
+            None => &mut self.roots,
+        };
+        siblings.retain(|&sibling| sibling != id);
+    }
+
+    /// Recomputes the world matrices of dirty nodes and their descendants
+    /// and writes them into their entities' `Transform`s. Entities that are
+    /// despawned, or have no `Transform`, are skipped.
+    pub fn update(&mut self, entities: &mut World) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        // (node, parent's world matrix if it changed this update)
+        let mut stack = self
+            .roots
+            .iter()
+            .rev()
+            .map(|&root| (root, None))
+            .collect::<Vec<_>>();
+        while let Some((id, parent_world)) = stack.pop() {
+            let Some(node) = self.get(id) else {
+                continue;
+            };
+            let changed = node.dirty || parent_world.is_some();
+            let world = if changed {
+                // An unchanged parent was already up to date.
+                let parent_world = parent_world.or_else(|| {
+                    node.parent
+                        .and_then(|parent| self.get(parent))
+                        .map(|parent| parent.world)
+                });
+                let local = node.local.matrix();
+                Some(parent_world.map_or(local, |parent| parent * local))
+            } else {
+                None
+            };
+
+            let node = self.nodes[id.0].as_mut().unwrap();
+            node.dirty = false;
+            if let Some(world) = world {
+                node.world = world;
+                if let Some(transform) = node
+                    .entity
+                    .and_then(|entity| entities.get_mut::<Transform>(entity))
+                {
+                    *transform = transform_from_matrix(world);
+                }
+            }
+            stack.extend(node.children.iter().rev().map(|&child| (child, world)));
+        }
+    }
+}
+
+/// Splits `m` back into translation, rotation and scale. Exact unless the
+/// hierarchy combines a non-uniform scale with a rotation below it, where
+/// the shear that leaves is dropped.
+fn transform_from_matrix(m: cgmath::Matrix4<f32>) -> Transform {
+    let x = m.x.truncate();
+    let y = m.y.truncate();
+    let z = m.z.truncate();
+    let mut scale = cgmath::Vector3::new(x.magnitude(), y.magnitude(), z.magnitude());
+    // A mirrored basis keeps its handedness in the scale.
+    if x.cross(y).dot(z) < 0.0 {
+        scale.x = -scale.x;
+    }
+    let safe = |s: f32| if s.abs() > f32::EPSILON { s } else { 1.0 };
+    let rotation =
+        cgmath::Matrix3::from_cols(x / safe(scale.x), y / safe(scale.y), z / safe(scale.z));
+    Transform {
+        translation: m.w.truncate(),
+        rotation: cgmath::Quaternion::from(rotation).normalize(),
+        scale,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Rotation3, Vector3};
+
+    use super::*;
+
+    fn at(x: f32, y: f32, z: f32) -> Transform {
+        Transform {
+            translation: Vector3::new(x, y, z),
+            ..Transform::default()
+        }
+    }
+
+    fn position(scene: &SceneGraph, id: NodeId) -> Vector3<f32> {
+        scene.get(id).unwrap().world().w.truncate()
+    }
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn world_applies_the_parent_before_the_child() {
+        let mut scene = SceneGraph::new();
+        let parent = Transform {
+            translation: Vector3::new(1.0, 0.0, 0.0),
+            rotation: cgmath::Quaternion::from_angle_y(Deg(90.0)),
+            scale: Vector3::new(2.0, 2.0, 2.0),
+        };
+        let parent = scene.add("parent", parent, None);
+        let child = scene.add("child", at(0.0, 0.0, 1.0), Some(parent));
+        let grandchild = scene.add("grandchild", at(0.0, 1.0, 0.0), Some(child));
+        scene.update(&mut World::new());
+
+        // The child's offset is scaled and turned by the parent, then moved.
+        assert_near(position(&scene, child), Vector3::new(3.0, 0.0, 0.0));
+        assert_near(position(&scene, grandchild), Vector3::new(3.0, 2.0, 0.0));
+        let expected = scene.get(parent).unwrap().world() * at(0.0, 0.0, 1.0).matrix();
+        assert_eq!(scene.get(child).unwrap().world(), expected);
+    }
+
+    #[test]
+    fn moving_a_parent_moves_its_descendants() {
+        let mut scene = SceneGraph::new();
+        let mut world = World::new();
+        let parent = scene.add("parent", at(1.0, 0.0, 0.0), None);
+        let child = scene.add("child", at(0.0, 1.0, 0.0), Some(parent));
+        let grandchild = scene.add("grandchild", at(0.0, 0.0, 1.0), Some(child));
+        scene.update(&mut world);
+
+        scene.set_local(parent, at(5.0, 0.0, 0.0));
+        assert_near(position(&scene, grandchild), Vector3::new(1.0, 1.0, 1.0));
+        scene.update(&mut world);
+        assert_near(position(&scene, child), Vector3::new(5.0, 1.0, 0.0));
+        assert_near(position(&scene, grandchild), Vector3::new(5.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn reparenting_carries_the_subtree_along() {
+        let mut scene = SceneGraph::new();
+        let mut world = World::new();
+        let a = scene.add("a", at(1.0, 0.0, 0.0), None);
+        let b = scene.add("b", at(0.0, 10.0, 0.0), None);
+        let child = scene.add("child", at(0.0, 0.0, 1.0), Some(a));
+        let grandchild = scene.add("grandchild", at(0.0, 0.0, 1.0), Some(child));
+        scene.update(&mut world);
+        assert_near(position(&scene, grandchild), Vector3::new(1.0, 0.0, 2.0));
+
+        assert!(scene.set_parent(child, Some(b)));
+        assert_eq!(scene.get(a).unwrap().children(), []);
+        assert_eq!(scene.get(b).unwrap().children(), [child]);
+        scene.update(&mut world);
+        assert_near(position(&scene, child), Vector3::new(0.0, 10.0, 1.0));
+        assert_near(position(&scene, grandchild), Vector3::new(0.0, 10.0, 2.0));
+
+        assert!(scene.set_parent(child, None));
+        assert_eq!(scene.roots(), [a, b, child]);
+        scene.update(&mut world);
+        assert_near(position(&scene, grandchild), Vector3::new(0.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn reparenting_under_a_descendant_is_refused() {
+        let mut scene = SceneGraph::new();
+        let parent = scene.add("parent", at(0.0, 0.0, 0.0), None);
+        let child = scene.add("child", at(0.0, 0.0, 0.0), Some(parent));
+        assert!(!scene.set_parent(parent, Some(child)));
+        assert!(!scene.set_parent(parent, Some(parent)));
+        assert_eq!(scene.get(parent).unwrap().parent(), None);
+        assert_eq!(scene.roots(), [parent]);
+    }
+
+    #[test]
+    fn removing_a_node_removes_its_descendants() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add("root", at(0.0, 0.0, 0.0), None);
+        let child = scene.add("child", at(0.0, 0.0, 0.0), Some(root));
+        let grandchild = scene.add("grandchild", at(0.0, 0.0, 0.0), Some(child));
+        let sibling = scene.add("sibling", at(0.0, 0.0, 0.0), Some(root));
+        scene.remove(child);
+
+        assert_eq!(scene.len(), 2);
+        assert!(scene.get(child).is_none());
+        assert!(scene.get(grandchild).is_none());
+        assert_eq!(scene.find("grandchild"), None);
+        assert_eq!(scene.get(root).unwrap().children(), [sibling]);
+        // Adding reuses the freed slots.
+        scene.add("new", at(0.0, 0.0, 0.0), None);
+        assert_eq!(scene.len(), 3);
+    }
+
+    #[test]
+    fn update_places_attached_entities_and_skips_despawned_ones() {
+        let mut scene = SceneGraph::new();
+        let mut world = World::new();
+        let parent_entity = world.spawn();
+        let child_entity = world.spawn();
+        world.insert(parent_entity, Transform::default());
+        world.insert(child_entity, Transform::default());
+        let parent = scene.add("parent", at(1.0, 0.0, 0.0), None);
+        let child = scene.add("child", at(0.0, 2.0, 0.0), Some(parent));
+        scene.set_entity(parent, Some(parent_entity));
+        scene.set_entity(child, Some(child_entity));
+        scene.update(&mut world);
+        let placed = world.get::<Transform>(child_entity).unwrap();
+        assert_near(placed.translation, Vector3::new(1.0, 2.0, 0.0));
+
+        // The child's entity keeps following after its parent's is gone.
+        world.despawn(parent_entity);
+        scene.set_local(parent, at(4.0, 0.0, 0.0));
+        scene.update(&mut world);
+        let placed = world.get::<Transform>(child_entity).unwrap();
+        assert_near(placed.translation, Vector3::new(4.0, 2.0, 0.0));
+
+        // Removed nodes leave their entities where they were.
+        scene.remove(parent);
+        scene.update(&mut world);
+        let placed = world.get::<Transform>(child_entity).unwrap();
+        assert_near(placed.translation, Vector3::new(4.0, 2.0, 0.0));
+    }
+}
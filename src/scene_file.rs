@@ -0,0 +1,487 @@
+use anyhow::Context;
+use serde_json::{Value, json};
+
+use crate::flythrough::CameraKeyframe;
+use crate::instance::Instance;
+use crate::light::{FogSettings, LightUniform};
+use crate::model::{ImportOptions, MaterialFactors, VertexEncoding};
+use crate::shadow::DirectionalLight;
+use crate::state::State;
+
+/// Where the debug UI saves and loads the scene.
+pub const DEFAULT_PATH: &str = "scene.json";
+
+/// What a scene is made of, saved as JSON to set one up without code.
+///
+/// Every part is optional: `apply` leaves what a scene doesn't mention as
+/// it was. The file is an object with these keys:
+///
+/// - `model`: an OBJ or glTF file under `res`.
+/// - `vertices`: how the model stores its vertices, `"full"` by default or
+///   `"quantized"`, as `VertexEncoding` names them.
+/// - `optimize` and `lods`: whether loading the model reorders its meshes
+///   and gives them levels of detail, both true by default.
+/// - `materials`: factors for the model's materials, objects with the
+///   `name` of one and keys like `MaterialFactors`, missing ones taking
+///   their defaults.
+/// - `instances`: objects with a `position`, and optionally a `rotation`
+///   quaternion as `[x, y, z, w]` and a `scale`, defaulting to none and 1.
+/// - `lights`: point lights, objects with a `position`, `color` and
+///   `intensity`.
+/// - `camera`: an object with an `eye`, `target` and `fovy` in degrees.
+/// - `environment`: an object with an `hdr` file under `res`, a `lut`
+///   strip under it to color grade through, `ambient`, `exposure`, and
+///   `fog` and `sun` objects keyed like `FogSettings` and
+///   `DirectionalLight`, any key missing taking its default.
+#[derive(Debug, Clone, Default)]
+pub struct Scene {
+    pub model: Option<String>,
+    /// How `model` is loaded, with the default options when None.
+    pub import: Option<ImportOptions>,
+    /// By material name.
+    pub materials: Option<Vec<(String, MaterialFactors)>>,
+    pub instances: Option<Vec<Instance>>,
+    pub lights: Option<Vec<LightUniform>>,
+    pub camera: Option<CameraKeyframe>,
+    pub environment: Option<String>,
+    pub lut: Option<String>,
+    pub ambient: Option<f32>,
+    pub exposure: Option<f32>,
+    pub fog: Option<FogSettings>,
+    pub sun: Option<DirectionalLight>,
+}
+
+impl Scene {
+    /// Everything `state` shows, with the model and environment it loaded
+    /// from files, if it did.
+    pub fn from_state(state: &State) -> Self {
+        Self {
+            model: state.model_file().map(str::to_string),
+            import: state.model_file().map(|_| state.model_import()),
+            materials: Some(
+                state
+                    .model_materials()
+                    .iter()
+                    .map(|material| (material.name.clone(), *material.factors()))
+                    .collect(),
+            ),
+            instances: Some(state.instances().to_vec()),
+            lights: Some(state.lights().to_vec()),
+            camera: Some(CameraKeyframe::from_camera(state.camera())),
+            environment: state.environment_file().map(str::to_string),
+            lut: state.color_grading_file().map(str::to_string),
+            ambient: Some(state.ambient()),
+            exposure: Some(state.exposure()),
+            fog: Some(state.fog_settings()),
+            sun: Some(*state.directional_light()),
+        }
+    }
+
+    /// Sets `state` up as the scene describes. The files load first, so a
+    /// missing one stops it before anything else changes.
+    pub async fn apply(&self, state: &mut State) -> anyhow::Result<()> {
+        if let Some(file) = &self.model {
+            state
+                .load_model_with(file, self.import.unwrap_or_default())
+                .await
+                .with_context(|| format!("Unable to load model {}", file))?;
+        }
+        if let Some(file) = &self.environment {
+            state
+                .load_environment(file)
+                .await
+                .with_context(|| format!("Unable to load environment {}", file))?;
+        }
+        if let Some(file) = &self.lut {
+            state
+                .load_color_grading_lut(file)
+                .await
+                .with_context(|| format!("Unable to load LUT {}", file))?;
+        }
+        for (name, factors) in self.materials.iter().flatten() {
+            let index = state
+                .model_materials()
+                .iter()
+                .position(|material| material.name == *name);
+            match index {
+                Some(index) => state.set_material_factors(index, *factors)?,
+                None => log::warn!("The model has no material {:?}", name),
+            }
+        }
+        if let Some(instances) = &self.instances {
+            state.clear_instances();
+            state.add_instances(instances.iter().copied());
+        }
+        if let Some(lights) = &self.lights {
+            state.clear_lights();
+            for &light in lights {
+                state.add_light(light);
+            }
+        }
+        if let Some(camera) = &self.camera {
+            camera.apply(state.camera_mut());
+        }
+        if let Some(ambient) = self.ambient {
+            state.set_ambient(ambient);
+        }
+        if let Some(exposure) = self.exposure {
+            state.set_exposure(exposure);
+        }
+        if let Some(fog) = self.fog {
+            state.set_fog_settings(fog);
+        }
+        if let Some(sun) = self.sun {
+            state.set_directional_light(sun);
+        }
+        Ok(())
+    }
+
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let root: Value = serde_json::from_str(source)?;
+        if !root.is_object() {
+            anyhow::bail!("a scene should be an object");
+        }
+        let mut import = None::<ImportOptions>;
+        if let Some(name) = string(&root["vertices"], "vertices")? {
+            import.get_or_insert_default().vertex_encoding = VertexEncoding::ALL
+                .into_iter()
+                .find(|encoding| encoding.to_string() == name)
+                .ok_or_else(|| anyhow::anyhow!("vertices should be full or quantized"))?;
+        }
+        if let Some(optimize) = optional(&root["optimize"], "optimize", boolean)? {
+            import.get_or_insert_default().optimize = optimize;
+        }
+        if let Some(lods) = optional(&root["lods"], "lods", boolean)? {
+            import.get_or_insert_default().lods = lods;
+        }
+        let mut scene = Self {
+            model: string(&root["model"], "model")?,
+            import,
+            ..Self::default()
+        };
+        if let Some(materials) = array(&root["materials"], "materials")? {
+            scene.materials = Some(
+                materials
+                    .iter()
+                    .enumerate()
+                    .map(|(i, material)| {
+                        let key = |key| format!("materials[{}].{}", i, key);
+                        let name = string(&material["name"], &key("name"))?
+                            .ok_or_else(|| anyhow::anyhow!("{} is missing", key("name")))?;
+                        let mut factors = MaterialFactors::default();
+                        if let Some(color) =
+                            optional(&material["base_color"], &key("base_color"), numbers)?
+                        {
+                            factors.base_color = color;
+                        }
+                        if let Some(color) =
+                            optional(&material["emissive"], &key("emissive"), numbers)?
+                        {
+                            factors.emissive = color;
+                        }
+                        let fields: [(&str, &mut f32); 5] = [
+                            ("emissive_strength", &mut factors.emissive_strength),
+                            ("metallic", &mut factors.metallic),
+                            ("roughness", &mut factors.roughness),
+                            ("occlusion_strength", &mut factors.occlusion_strength),
+                            ("normal_scale", &mut factors.normal_scale),
+                        ];
+                        for (field_name, field) in fields {
+                            let value = &material[field_name];
+                            if let Some(value) = optional(value, &key(field_name), number)? {
+                                *field = value;
+                            }
+                        }
+                        Ok((name, factors))
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+            );
+        }
+        if let Some(instances) = array(&root["instances"], "instances")? {
+            scene.instances = Some(
+                instances
+                    .iter()
+                    .enumerate()
+                    .map(|(i, instance)| {
+                        let key = |key| format!("instances[{}].{}", i, key);
+                        let rotation = match &instance["rotation"] {
+                            Value::Null => cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+                            value => {
+                                let [x, y, z, w] = numbers(value, &key("rotation"))?;
+                                cgmath::Quaternion::new(w, x, y, z)
+                            }
+                        };
+                        let scale = match &instance["scale"] {
+                            Value::Null => [1.0; 3],
+                            value => numbers(value, &key("scale"))?,
+                        };
+                        Ok(Instance {
+                            position: numbers(&instance["position"], &key("position"))?.into(),
+                            rotation,
+                            scale: scale.into(),
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+            );
+        }
+        if let Some(lights) = array(&root["lights"], "lights")? {
+            scene.lights = Some(
+                lights
+                    .iter()
+                    .enumerate()
+                    .map(|(i, light)| {
+                        let key = |key| format!("lights[{}].{}", i, key);
+                        Ok(LightUniform::new(
+                            numbers(&light["position"], &key("position"))?,
+                            numbers(&light["color"], &key("color"))?,
+                            number(&light["intensity"], &key("intensity"))?,
+                        ))
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+            );
+        }
+        let camera = &root["camera"];
+        if !camera.is_null() {
+            scene.camera = Some(CameraKeyframe {
+                eye: numbers(&camera["eye"], "camera.eye")?.into(),
+                target: numbers(&camera["target"], "camera.target")?.into(),
+                fovy: number(&camera["fovy"], "camera.fovy")?,
+            });
+        }
+        let environment = &root["environment"];
+        scene.environment = string(&environment["hdr"], "environment.hdr")?;
+        scene.lut = string(&environment["lut"], "environment.lut")?;
+        scene.ambient = optional(&environment["ambient"], "environment.ambient", number)?;
+        scene.exposure = optional(&environment["exposure"], "environment.exposure", number)?;
+        let fog = &environment["fog"];
+        if !fog.is_null() {
+            let mut settings = FogSettings::default();
+            if let Some(enabled) = optional(&fog["enabled"], "environment.fog.enabled", boolean)? {
+                settings.enabled = enabled;
+            }
+            let fields: [(&str, &mut f32); 4] = [
+                ("density", &mut settings.density),
+                ("start", &mut settings.start),
+                ("end", &mut settings.end),
+                ("height_falloff", &mut settings.height_falloff),
+            ];
+            for (key, field) in fields {
+                if let Some(value) =
+                    optional(&fog[key], &format!("environment.fog.{}", key), number)?
+                {
+                    *field = value;
+                }
+            }
+            if let Some(color) = optional(&fog["color"], "environment.fog.color", numbers)? {
+                settings.color = color;
+            }
+            scene.fog = Some(settings);
+        }
+        let sun = &environment["sun"];
+        if !sun.is_null() {
+            let mut light = DirectionalLight::default();
+            if let Some(direction) =
+                optional(&sun["direction"], "environment.sun.direction", numbers)?
+            {
+                light.direction = direction.into();
+            }
+            if let Some(color) = optional(&sun["color"], "environment.sun.color", numbers)? {
+                light.color = color;
+            }
+            let fields: [(&str, &mut f32); 3] = [
+                ("intensity", &mut light.intensity),
+                ("shadow_distance", &mut light.shadow_distance),
+                ("cascade_split_lambda", &mut light.cascade_split_lambda),
+            ];
+            for (key, field) in fields {
+                if let Some(value) =
+                    optional(&sun[key], &format!("environment.sun.{}", key), number)?
+                {
+                    *field = value;
+                }
+            }
+            scene.sun = Some(light);
+        }
+        Ok(scene)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Unable to read {}: {}", path.display(), e))?;
+        Self::parse(&source)
+            .map_err(|e| anyhow::anyhow!("Unable to read {}: {:#}", path.display(), e))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_json())?;
+        Ok(())
+    }
+
+    /// The file `parse` reads back as `self`, leaving out what is None.
+    pub fn to_json(&self) -> String {
+        let vector = |v: &[f32]| Value::Array(v.iter().map(|&v| float(v)).collect());
+        let mut root = serde_json::Map::new();
+        if let Some(model) = &self.model {
+            root.insert("model".into(), json!(model));
+        }
+        if let Some(import) = self.import {
+            root.insert("vertices".into(), json!(import.vertex_encoding.to_string()));
+            root.insert("optimize".into(), json!(import.optimize));
+            root.insert("lods".into(), json!(import.lods));
+        }
+        if let Some(materials) = &self.materials {
+            let materials = materials.iter().map(|(name, factors)| {
+                json!({
+                    "name": name,
+                    "base_color": vector(&factors.base_color),
+                    "metallic": float(factors.metallic),
+                    "roughness": float(factors.roughness),
+                    "emissive": vector(&factors.emissive),
+                    "emissive_strength": float(factors.emissive_strength),
+                    "occlusion_strength": float(factors.occlusion_strength),
+                    "normal_scale": float(factors.normal_scale),
+                })
+            });
+            root.insert("materials".into(), Value::Array(materials.collect()));
+        }
+        if let Some(instances) = &self.instances {
+            let instances = instances.iter().map(|instance| {
+                let rotation = instance.rotation;
+                json!({
+                    "position": vector(&[instance.position.x, instance.position.y, instance.position.z]),
+                    "rotation": vector(&[rotation.v.x, rotation.v.y, rotation.v.z, rotation.s]),
+                    "scale": vector(&[instance.scale.x, instance.scale.y, instance.scale.z]),
+                })
+            });
+            root.insert("instances".into(), Value::Array(instances.collect()));
+        }
+        if let Some(lights) = &self.lights {
+            let lights = lights.iter().map(|light| {
+                json!({
+                    "position": vector(&light.position),
+                    "color": vector(&light.color),
+                    "intensity": float(light.intensity),
+                })
+            });
+            root.insert("lights".into(), Value::Array(lights.collect()));
+        }
+        if let Some(camera) = &self.camera {
+            root.insert(
+                "camera".into(),
+                json!({
+                    "eye": vector(&[camera.eye.x, camera.eye.y, camera.eye.z]),
+                    "target": vector(&[camera.target.x, camera.target.y, camera.target.z]),
+                    "fovy": float(camera.fovy),
+                }),
+            );
+        }
+        let mut environment = serde_json::Map::new();
+        if let Some(hdr) = &self.environment {
+            environment.insert("hdr".into(), json!(hdr));
+        }
+        if let Some(lut) = &self.lut {
+            environment.insert("lut".into(), json!(lut));
+        }
+        if let Some(ambient) = self.ambient {
+            environment.insert("ambient".into(), float(ambient));
+        }
+        if let Some(exposure) = self.exposure {
+            environment.insert("exposure".into(), float(exposure));
+        }
+        if let Some(fog) = &self.fog {
+            environment.insert(
+                "fog".into(),
+                json!({
+                    "enabled": fog.enabled,
+                    "color": vector(&fog.color),
+                    "density": float(fog.density),
+                    "start": float(fog.start),
+                    "end": float(fog.end),
+                    "height_falloff": float(fog.height_falloff),
+                }),
+            );
+        }
+        if let Some(sun) = &self.sun {
+            environment.insert(
+                "sun".into(),
+                json!({
+                    "direction": vector(&[sun.direction.x, sun.direction.y, sun.direction.z]),
+                    "color": vector(&sun.color),
+                    "intensity": float(sun.intensity),
+                    "shadow_distance": float(sun.shadow_distance),
+                    "cascade_split_lambda": float(sun.cascade_split_lambda),
+                }),
+            );
+        }
+        if !environment.is_empty() {
+            root.insert("environment".into(), Value::Object(environment));
+        }
+        let mut out =
+            serde_json::to_string_pretty(&Value::Object(root)).expect("a Value always serializes");
+        out.push('\n');
+        out
+    }
+}
+
+// The shortest decimal reading back as `value`, rather than the f64 it
+// widens to, such as 0.949999988079071 for 0.95.
+fn float(value: f32) -> Value {
+    json!(value.to_string().parse::<f64>().unwrap_or(0.0))
+}
+
+fn number(value: &Value, key: &str) -> anyhow::Result<f32> {
+    value
+        .as_f64()
+        .map(|value| value as f32)
+        .ok_or_else(|| anyhow::anyhow!("{} should be a number", key))
+}
+
+fn numbers<const N: usize>(value: &Value, key: &str) -> anyhow::Result<[f32; N]> {
+    value
+        .as_array()
+        .filter(|array| array.len() == N)
+        .and_then(|array| {
+            let numbers: Option<Vec<f32>> =
+                array.iter().map(|v| v.as_f64().map(|v| v as f32)).collect();
+            numbers?.try_into().ok()
+        })
+        .ok_or_else(|| anyhow::anyhow!("{} should be {} numbers", key, N))
+}
+
+fn boolean(value: &Value, key: &str) -> anyhow::Result<bool> {
+    value
+        .as_bool()
+        .ok_or_else(|| anyhow::anyhow!("{} should be true or false", key))
+}
+
+fn string(value: &Value, key: &str) -> anyhow::Result<Option<String>> {
+    optional(value, key, |value, key| {
+        value
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow::anyhow!("{} should be a file name", key))
+    })
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> anyhow::Result<Option<&'a Vec<Value>>> {
+    optional(value, key, |value, key| {
+        value
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("{} should be an array", key))
+    })
+}
+
+// None for a missing key or null.
+fn optional<'a, T>(
+    value: &'a Value,
+    key: &str,
+    read: impl FnOnce(&'a Value, &str) -> anyhow::Result<T>,
+) -> anyhow::Result<Option<T>> {
+    match value {
+        Value::Null => Ok(None),
+        value => read(value, key).map(Some),
+    }
+}
@@ -0,0 +1,293 @@
+use std::sync::Arc;
+
+use wgpu::util::DeviceExt;
+use winit::window::Window;
+
+use crate::camera::Camera;
+use crate::preprocess::{self, ShaderDefs};
+use crate::render_target::RenderTarget;
+
+/// What a window added with `State::add_window` shows.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WindowContent {
+    /// The scene from a camera of its own, drawn like a `RenderTarget` at
+    /// the window's size.
+    Scene(Camera),
+    /// One of the renderer's intermediate textures, as the main view left
+    /// it this frame.
+    Debug(DebugTexture),
+}
+
+/// A texture a debug window can show.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DebugTexture {
+    /// Base color from the G-buffer. The G-buffer slots are black unless
+    /// the state renders deferred.
+    Albedo,
+    /// World-space normals from the G-buffer.
+    Normal,
+    /// Metallic in red and roughness in green, from the G-buffer.
+    Material,
+    Emissive,
+    /// The main view's depth.
+    Depth,
+    /// SSAO's occlusion.
+    Occlusion,
+    /// A cascade of the directional light's shadow map, nearest first.
+    ShadowCascade(u32),
+    /// A level of bloom's downsample chain, the largest first, as the
+    /// upsample left it.
+    BloomMip(u32),
+}
+
+impl DebugTexture {
+    /// Reads the names `Display` writes: `albedo`, `normal`, `material`,
+    /// `emissive`, `depth`, `occlusion`, or `shadow` or `bloom` and a
+    /// number.
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "albedo" => Self::Albedo,
+            "normal" => Self::Normal,
+            "material" => Self::Material,
+            "emissive" => Self::Emissive,
+            "depth" => Self::Depth,
+            "occlusion" => Self::Occlusion,
+            _ => match name.strip_prefix("shadow") {
+                Some(cascade) => Self::ShadowCascade(cascade.parse().ok()?),
+                None => Self::BloomMip(name.strip_prefix("bloom")?.parse().ok()?),
+            },
+        })
+    }
+}
+
+impl std::fmt::Display for DebugTexture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Albedo => write!(f, "albedo"),
+            Self::Normal => write!(f, "normal"),
+            Self::Material => write!(f, "material"),
+            Self::Emissive => write!(f, "emissive"),
+            Self::Depth => write!(f, "depth"),
+            Self::Occlusion => write!(f, "occlusion"),
+            Self::ShadowCascade(cascade) => write!(f, "shadow{}", cascade),
+            Self::BloomMip(level) => write!(f, "bloom{}", level),
+        }
+    }
+}
+
+/// A window besides the main one, presenting from the same device.
+pub(crate) struct SecondaryWindow {
+    pub window: Arc<Window>,
+    pub surface: wgpu::Surface<'static>,
+    pub config: wgpu::SurfaceConfiguration,
+    pub content: WindowContent,
+    // For `WindowContent::Scene`: the target at the window's size and the
+    // bind group tonemapping it.
+    pub target: Option<(RenderTarget, wgpu::BindGroup)>,
+}
+
+/// How `DebugBlit` reads and shows a texture.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum BlitMode {
+    Color,
+    /// Only the red channel, as gray.
+    Red,
+    /// Octahedral normals, as the G-buffer stores them.
+    Normal,
+    PerspectiveDepth,
+    /// Perspective depth drawn with `reverse_z`.
+    ReversedDepth,
+    /// Linear depth from one layer of an array view, such as a shadow
+    /// cascade.
+    LayerDepth(u32),
+}
+
+impl BlitMode {
+    // Indexed by `index`.
+    const ENTRY_POINTS: [&str; 6] = [
+        "fs_color",
+        "fs_red",
+        "fs_normal",
+        "fs_perspective_depth",
+        "fs_reversed_depth",
+        "fs_layer_depth",
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            Self::Color => 0,
+            Self::Red => 1,
+            Self::Normal => 2,
+            Self::PerspectiveDepth => 3,
+            Self::ReversedDepth => 4,
+            Self::LayerDepth(_) => 5,
+        }
+    }
+}
+
+/// Draws a texture over a window for `WindowContent::Debug`, or over part
+/// of the main view for `State::set_debug_view`.
+pub(crate) struct DebugBlit {
+    layout: wgpu::BindGroupLayout,
+    layer_layout: wgpu::BindGroupLayout,
+    // One per `BlitMode::ENTRY_POINTS`.
+    pipelines: [wgpu::RenderPipeline; 6],
+}
+
+impl DebugBlit {
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Self {
+        let texture = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("DebugBlit::layout"),
+            entries: &[texture(0, wgpu::TextureViewDimension::D2)],
+        });
+        let layer_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("DebugBlit::layer_layout"),
+            entries: &[
+                texture(1, wgpu::TextureViewDimension::D2Array),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = preprocess::builtin_module(
+            device,
+            "Debug Blit Shader",
+            "debug_blit.wgsl",
+            &ShaderDefs::new(),
+        );
+        let pipeline_layout = |layout| {
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Debug Blit Pipeline Layout"),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            })
+        };
+        let texture_pipeline_layout = pipeline_layout(&layout);
+        let layer_pipeline_layout = pipeline_layout(&layer_layout);
+        let pipelines = BlitMode::ENTRY_POINTS.map(|entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(if entry_point == "fs_layer_depth" {
+                    &layer_pipeline_layout
+                } else {
+                    &texture_pipeline_layout
+                }),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: output_format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        });
+
+        Self {
+            layout,
+            layer_layout,
+            pipelines,
+        }
+    }
+
+    /// Shows `texture` over all of `output`, clearing it to black first,
+    /// or over the pixels `rect` gives as a corner and size, keeping the
+    /// rest. Without a texture it only clears.
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        texture: Option<(&wgpu::TextureView, BlitMode)>,
+        rect: Option<([u32; 2], [u32; 2])>,
+    ) {
+        let bind_group = texture.map(|(view, mode)| match mode {
+            BlitMode::LayerDepth(layer) => {
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("DebugBlit::layer"),
+                    contents: bytemuck::cast_slice(&[layer, 0, 0, 0]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("DebugBlit::layer_bind_group"),
+                    layout: &self.layer_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: buffer.as_entire_binding(),
+                        },
+                    ],
+                })
+            }
+            _ => device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("DebugBlit::bind_group"),
+                layout: &self.layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                }],
+            }),
+        });
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("DebugBlit::render"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: match rect {
+                        Some(_) => wgpu::LoadOp::Load,
+                        None => wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    },
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        if let Some(([x, y], [width, height])) = rect {
+            pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+        }
+        if let (Some(bind_group), Some((_, mode))) = (&bind_group, texture) {
+            pass.set_pipeline(&self.pipelines[mode.index()]);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -1,47 +1,326 @@
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
-@group(1) @binding(0)
-var<uniform> camera: CameraUniform;
-
-struct InstanceInput {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-}
-
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-}
+#include "lighting.wgsl"
+#include "include/instance.wgsl"
+#define SKIN_GROUP 1
+#define SKIN_BINDING 9
+#include "include/skinning.wgsl"
+#include "include/vertex.wgsl"
 
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // Must match the SSAO prepass exactly, since the depth it wrote is reused.
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) world_tangent: vec3<f32>,
+    @location(4) world_bitangent: vec3<f32>,
+#ifdef TEXTURE_ARRAYS
+    @location(5) @interpolate(flat) material: u32,
+#endif
+    // The instance's, multiplying the material's base color.
+    @location(6) @interpolate(flat) color: vec4<f32>,
+    @location(7) @interpolate(flat) fade: f32,
 }
 
 @vertex
-fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+fn vs_main(
+    model: ModelVertexInput,
+    instance: InstanceInput,
+#ifdef TEXTURE_ARRAYS
+    @location(14) material: u32,
+#endif
+#ifdef SKINNED
+    skin: SkinInput,
+#endif
+) -> VertexOutput {
     let model_matrix = mat4x4<f32>(
         instance.model_matrix_0,
         instance.model_matrix_1,
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+    let normal_matrix = instance_normal_matrix(instance);
+
+    let attributes = model_attributes(model);
+    var position = attributes.position;
+    var normal = attributes.normal;
+    var tangent = attributes.tangent;
+    var bitangent = attributes.bitangent;
+#ifdef SKINNED
+    let skinning = skin_matrix(skin);
+    position = skin_position(skinning, position);
+    normal = skin_direction(skinning, normal);
+    tangent = skin_direction(skinning, tangent);
+    bitangent = skin_direction(skinning, bitangent);
+#endif
 
     var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.tex_coords = attributes.tex_coords;
+    out.color = instance.color;
+    out.fade = instance_fade(instance);
+#ifdef TEXTURE_ARRAYS
+    out.material = material;
+#endif
+    out.world_normal = normal_matrix * normal;
+    // Tangents lie in the surface, so they follow the model matrix itself.
+    let tangent_matrix = mat3x3<f32>(
+        model_matrix[0].xyz,
+        model_matrix[1].xyz,
+        model_matrix[2].xyz,
+    );
+    out.world_tangent = tangent_matrix * tangent;
+    out.world_bitangent = tangent_matrix * bitangent;
+    let world_position = model_matrix * vec4<f32>(position, 1.0);
+    out.world_position = world_position.xyz;
+    let clip = camera.view_proj * world_position;
+    out.clip_position = vec4<f32>(clip.xy + camera.jitter * clip.w, clip.zw);
     return out;
 }
 
+#ifdef TERRAIN
+#include "include/terrain.wgsl"
+#else
+struct MaterialUniform {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    lod_bias: f32,
+}
+
+#ifdef TEXTURE_ARRAYS
+// Every material of the model, a layer each, picked by the vertex's ID.
+@group(0) @binding(0)
+var t_diffuse: texture_2d_array<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d_array<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+@group(0) @binding(4)
+var t_metallic_roughness: texture_2d_array<f32>;
+@group(0) @binding(5)
+var s_metallic_roughness: sampler;
+@group(0) @binding(6)
+var t_occlusion: texture_2d_array<f32>;
+@group(0) @binding(7)
+var s_occlusion: sampler;
+@group(0) @binding(8)
+var t_emissive: texture_2d_array<f32>;
+@group(0) @binding(9)
+var s_emissive: sampler;
+@group(0) @binding(10)
+var<storage, read> materials: array<MaterialUniform>;
+
+fn material_factors(in: VertexOutput) -> MaterialUniform {
+    return materials[in.material];
+}
+
+fn sample_diffuse(in: VertexOutput) -> vec4<f32> {
+    return textureSampleBias(t_diffuse, s_diffuse, in.tex_coords, in.material, materials[in.material].lod_bias);
+}
+
+fn sample_normal(in: VertexOutput) -> vec4<f32> {
+    return textureSampleBias(t_normal, s_normal, in.tex_coords, in.material, materials[in.material].lod_bias);
+}
+
+fn sample_metallic_roughness(in: VertexOutput) -> vec4<f32> {
+    return textureSampleBias(t_metallic_roughness, s_metallic_roughness, in.tex_coords, in.material, materials[in.material].lod_bias);
+}
+
+fn sample_occlusion(in: VertexOutput) -> vec4<f32> {
+    return textureSampleBias(t_occlusion, s_occlusion, in.tex_coords, in.material, materials[in.material].lod_bias);
+}
+
+fn sample_emissive(in: VertexOutput) -> vec4<f32> {
+    return textureSampleBias(t_emissive, s_emissive, in.tex_coords, in.material, materials[in.material].lod_bias);
+}
+#else
 @group(0) @binding(0)
 var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+@group(0) @binding(4)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(5)
+var s_metallic_roughness: sampler;
+@group(0) @binding(6)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(7)
+var s_occlusion: sampler;
+@group(0) @binding(8)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(9)
+var s_emissive: sampler;
+@group(0) @binding(10)
+var<uniform> material: MaterialUniform;
+
+fn material_factors(in: VertexOutput) -> MaterialUniform {
+    return material;
+}
+
+fn sample_diffuse(in: VertexOutput) -> vec4<f32> {
+    return textureSampleBias(t_diffuse, s_diffuse, in.tex_coords, material.lod_bias);
+}
+
+fn sample_normal(in: VertexOutput) -> vec4<f32> {
+    return textureSampleBias(t_normal, s_normal, in.tex_coords, material.lod_bias);
+}
+
+fn sample_metallic_roughness(in: VertexOutput) -> vec4<f32> {
+    return textureSampleBias(t_metallic_roughness, s_metallic_roughness, in.tex_coords, material.lod_bias);
+}
+
+fn sample_occlusion(in: VertexOutput) -> vec4<f32> {
+    return textureSampleBias(t_occlusion, s_occlusion, in.tex_coords, material.lod_bias);
+}
+
+fn sample_emissive(in: VertexOutput) -> vec4<f32> {
+    return textureSampleBias(t_emissive, s_emissive, in.tex_coords, material.lod_bias);
+}
+#endif
+
+// Falls back to the vertex normal where the mesh has no usable tangents.
+fn perturbed_normal(in: VertexOutput) -> vec3<f32> {
+    var tangent_normal = sample_normal(in).xyz * 2.0 - 1.0;
+    tangent_normal = vec3<f32>(tangent_normal.xy * material_factors(in).normal_scale, tangent_normal.z);
+    let n = normalize(in.world_normal);
+    if dot(in.world_tangent, in.world_tangent) < 1e-8 {
+        return n;
+    }
+    let t = normalize(in.world_tangent - n * dot(n, in.world_tangent));
+    let b = normalize(in.world_bitangent);
+    return normalize(mat3x3<f32>(t, b, n) * tangent_normal);
+}
+
+fn base_color(in: VertexOutput) -> vec4<f32> {
+    return sample_diffuse(in) * material_factors(in).base_color * in.color;
+}
+
+// Everything lighting needs from the material; base color alpha is left to
+// `base_color` since lighting ignores it.
+fn material_surface(in: VertexOutput) -> Surface {
+    let factors = material_factors(in);
+    let metallic_roughness = sample_metallic_roughness(in);
+    let occlusion = sample_occlusion(in).r;
+
+    var surface: Surface;
+    surface.albedo = base_color(in).rgb;
+    surface.normal = perturbed_normal(in);
+    surface.view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    surface.metallic = clamp(metallic_roughness.b * factors.metallic, 0.0, 1.0);
+    // Very low roughness turns point lights into invisible specks.
+    surface.roughness = clamp(metallic_roughness.g * factors.roughness, 0.04, 1.0);
+    surface.f0 = mix(vec3<f32>(0.04), surface.albedo, surface.metallic);
+    surface.occlusion = mix(1.0, occlusion, factors.occlusion_strength);
+    surface.emissive = sample_emissive(in).rgb * factors.emissive;
+    return surface;
+}
+#endif
+
+// Screen-space motion since the previous frame in uv units, for TAA.
+// Only the camera moves between frames, so the world position is enough.
+fn velocity(world_position: vec3<f32>) -> vec2<f32> {
+    let current = camera.view_proj * vec4<f32>(world_position, 1.0);
+    let previous = camera.prev_view_proj * vec4<f32>(world_position, 1.0);
+    return (current.xy / current.w - previous.xy / previous.w) * vec2<f32>(0.5, -0.5);
+}
+
+struct ForwardOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+fn fs_main(in: VertexOutput) -> ForwardOutput {
+    // Set while drawing one side of a water surface.
+    if dot(vec4<f32>(in.world_position, 1.0), camera.clip_plane) < 0.0 {
+        discard;
+    }
+    if lod_dithered(in.clip_position.xy, in.fade) {
+        discard;
+    }
+    let surface = material_surface(in);
+    var out: ForwardOutput;
+#ifdef UNLIT
+    out.color = vec4<f32>(surface.albedo + surface.emissive, base_color(in).a);
+#else
+    let lit = shade(surface, in.world_position, in.clip_position.xy);
+    out.color = vec4<f32>(apply_fog(lit, in.world_position), base_color(in).a);
+#endif
+    out.velocity = velocity(in.world_position);
+    return out;
+}
+
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) revealage: f32,
+}
+
+// Weighted-blended OIT for oit.rs. The weight favors nearby, opaque
+// fragments by window depth, clamped so the half-float sums stay finite.
+@fragment
+fn fs_oit(in: VertexOutput) -> OitOutput {
+    if dot(vec4<f32>(in.world_position, 1.0), camera.clip_plane) < 0.0 {
+        discard;
+    }
+    if lod_dithered(in.clip_position.xy, in.fade) {
+        discard;
+    }
+    let surface = material_surface(in);
+    let alpha = base_color(in).a;
+#ifdef UNLIT
+    let lit = surface.albedo + surface.emissive;
+#else
+    let lit = apply_fog(shade(surface, in.world_position, in.clip_position.xy), in.world_position);
+#endif
+    let z = in.clip_position.z;
+    let weight = clamp(pow(min(1.0, alpha * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - z * 0.9, 3.0), 1e-2, 3e3);
+    var out: OitOutput;
+    out.accum = vec4<f32>(lit * alpha, alpha) * weight;
+    out.revealage = alpha;
+    return out;
+}
+
+// Deferred path: the G-buffer keeps just enough to rebuild a `Surface` in
+// deferred.wgsl, with position recovered from depth.
+struct GBufferOutput {
+    // Albedo in rgb, material occlusion in a.
+    @location(0) albedo: vec4<f32>,
+    // Octahedral-encoded, keeping the targets within 32 bytes per pixel.
+    @location(1) normal: vec2<f32>,
+    // Metallic in r, roughness in g.
+    @location(2) material: vec4<f32>,
+    @location(3) emissive: vec4<f32>,
+    @location(4) velocity: vec2<f32>,
+}
+
+// Maps a unit vector onto the [-1, 1] square.
+fn octahedral_encode(n: vec3<f32>) -> vec2<f32> {
+    let p = n.xy / (abs(n.x) + abs(n.y) + abs(n.z));
+    if n.z >= 0.0 {
+        return p;
+    }
+    return (1.0 - abs(p.yx)) * select(vec2<f32>(-1.0), vec2<f32>(1.0), p >= vec2<f32>(0.0));
+}
+
+@fragment
+fn fs_gbuffer(in: VertexOutput) -> GBufferOutput {
+    if lod_dithered(in.clip_position.xy, in.fade) {
+        discard;
+    }
+    let surface = material_surface(in);
+    var out: GBufferOutput;
+    out.albedo = vec4<f32>(surface.albedo, surface.occlusion);
+    out.normal = octahedral_encode(surface.normal);
+    out.material = vec4<f32>(surface.metallic, surface.roughness, 0.0, 0.0);
+    out.emissive = vec4<f32>(surface.emissive, 0.0);
+    out.velocity = velocity(in.world_position);
+    return out;
+}
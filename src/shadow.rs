@@ -0,0 +1,1076 @@
+use cgmath::{EuclideanSpace, InnerSpace, MetricSpace, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use crate::animation::JointPalette;
+use crate::blur::{Blur, BlurKernel};
+use crate::camera::{Camera, OPENGL_TO_WGPU_MATRIX};
+use crate::instance::InstanceBuffer;
+use crate::light::LightUniform;
+use crate::model;
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+use crate::uniforms::{DynamicUniformBuffer, PerDrawUniforms};
+
+pub const CASCADE_COUNT: usize = 4;
+pub const DEFAULT_MAX_POINT_SHADOWS: usize = 4;
+
+/// How the directional light's cascades are filtered where they are read.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ShadowFilter {
+    /// 3x3 depth-compare taps (PCF) in the depth map.
+    #[default]
+    Pcf,
+    /// One filtered tap of the depth and depth squared, kept at half the
+    /// map's size and blurred with a `Blur` once the cascades are drawn
+    /// (variance shadow maps). Softer, but light can bleed through where
+    /// casters overlap.
+    Variance,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DirectionalLight {
+    /// Direction the light travels in, i.e. from the sun towards the scene.
+    pub direction: cgmath::Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// View distance covered by the cascades; nothing further away is shadowed.
+    pub shadow_distance: f32,
+    /// Blend between uniform (0.0) and logarithmic (1.0) cascade splits.
+    pub cascade_split_lambda: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            direction: cgmath::Vector3::new(-0.5, -1.0, -0.3),
+            color: [1.0, 0.95, 0.9],
+            intensity: 2.5,
+            shadow_distance: 60.0,
+            cascade_split_lambda: 0.75,
+        }
+    }
+}
+
+impl DirectionalLight {
+    fn cascade_splits(&self, camera: &Camera) -> [f32; CASCADE_COUNT + 1] {
+        let near = camera.znear;
+        let far = self.shadow_distance.min(camera.zfar);
+        let mut splits = [near; CASCADE_COUNT + 1];
+        for (i, split) in splits.iter_mut().enumerate().skip(1) {
+            let t = i as f32 / CASCADE_COUNT as f32;
+            let log = near * (far / near).powf(t);
+            let uniform = near + (far - near) * t;
+            *split = self.cascade_split_lambda * log + (1.0 - self.cascade_split_lambda) * uniform;
+        }
+        splits
+    }
+
+    /// Fits an orthographic light frustum around the bounding sphere of one
+    /// camera frustum slice. Using a sphere keeps the size constant while the
+    /// camera rotates, and snapping to whole texels stops edges shimmering.
+    fn build_cascade_matrix(&self, camera: &Camera, near: f32, far: f32) -> cgmath::Matrix4<f32> {
+        let inv_view_proj = (camera.build_projection_matrix(near, far)
+            * camera.build_view_matrix())
+        .invert()
+        .unwrap_or_else(cgmath::Matrix4::identity);
+        let mut corners = [cgmath::Point3::origin(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let ndc = cgmath::Vector4::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 0.0 } else { 1.0 },
+                1.0,
+            );
+            let world = inv_view_proj * ndc;
+            *corner = cgmath::Point3::from_homogeneous(world);
+        }
+
+        let center = cgmath::Point3::centroid(&corners);
+        let radius = corners
+            .iter()
+            .map(|c| c.distance(center))
+            .fold(0.0_f32, f32::max);
+        let radius = (radius * 16.0).ceil() / 16.0;
+
+        let direction = self.direction.normalize();
+        // Pull the light back past the slice so casters outside it still land
+        // in the map.
+        let caster_margin = self.shadow_distance;
+        let eye = center - direction * (radius + caster_margin);
+        // look_at_rh degenerates when looking straight along the up vector.
+        let up = if direction.y.abs() > 0.99 {
+            cgmath::Vector3::unit_z()
+        } else {
+            cgmath::Vector3::unit_y()
+        };
+        let view = cgmath::Matrix4::look_at_rh(eye, center, up);
+        let mut proj = OPENGL_TO_WGPU_MATRIX
+            * cgmath::ortho(
+                -radius,
+                radius,
+                -radius,
+                radius,
+                0.0,
+                2.0 * radius + caster_margin,
+            );
+
+        let texels = ShadowMap::SIZE as f32 * 0.5;
+        let origin = proj * view * cgmath::Vector4::new(0.0, 0.0, 0.0, 1.0);
+        proj.w.x += ((origin.x * texels).round() - origin.x * texels) / texels;
+        proj.w.y += ((origin.y * texels).round() - origin.y * texels) / texels;
+
+        proj * view
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowUniform {
+    pub cascades: [[[f32; 4]; 4]; CASCADE_COUNT],
+    /// Far edge of each cascade in view-space depth.
+    pub splits: [f32; CASCADE_COUNT],
+    pub direction: [f32; 3],
+    pub intensity: f32,
+    pub color: [f32; 3],
+    pub debug_cascades: u32,
+    /// Non-zero to read the cascades as `ShadowFilter::Variance`.
+    pub variance: u32,
+    pub _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointShadowUniform {
+    count: u32,
+    far: f32,
+    bias: f32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowPassUniform {
+    view_proj: [[f32; 4]; 4],
+    // Only read by the point light passes; the cascade passes bind just
+    // the matrix.
+    light_position: [f32; 3],
+    far: f32,
+}
+
+/// One depth-only render target: a cascade or a cube face.
+struct ShadowView {
+    // Names the view and its pass, so captures tell the layers apart.
+    label: String,
+    view: wgpu::TextureView,
+}
+
+impl ShadowView {
+    fn new(texture: &wgpu::Texture, layer: u32, label: String) -> Self {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(&label),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        });
+        Self { label, view }
+    }
+
+    fn begin_pass<'a>(&'a self, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&self.label),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MomentsUniform {
+    cascade: u32,
+    _padding: [u32; 3],
+}
+
+/// What `ShadowFilter::Variance` turns the cascades into and blurs them
+/// with.
+///
+/// Each cascade's moments are written to `unblurred` and blurred from
+/// there into its layer, which is only ever written as storage: GL can't
+/// sample a view of any layer but the first, nor `textureLoad` a depth
+/// texture, so the cascades are read as unfilterable floats.
+struct Moments {
+    // One view per cascade, written by `blur`.
+    layers: Vec<wgpu::TextureView>,
+    unblurred: texture::Texture,
+    scratch: texture::Texture,
+    blur: Blur,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::ComputePipeline,
+    // Each cascade's index, at its dynamic offset.
+    uniforms: DynamicUniformBuffer<MomentsUniform>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Moments {
+    const SIZE: u32 = ShadowMap::SIZE / 2;
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const BLUR: BlurKernel = BlurKernel::Gaussian { sigma: 1.5 };
+    const WORKGROUP_SIZE: u32 = 8;
+
+    /// The array `ShadowMap::bind_group` reads the moments from, `size`
+    /// texels wide: a single one while the cascades are filtered with PCF.
+    fn create_texture(device: &wgpu::Device, size: u32) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow_moments"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: CASCADE_COUNT as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("shadow_moments_view"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        (texture, view)
+    }
+
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        depth: &wgpu::TextureView,
+        texture: &wgpu::Texture,
+    ) -> Self {
+        let layers = (0..CASCADE_COUNT as u32)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("shadow_moments_layer"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let unblurred = Blur::create_target(
+            device,
+            Self::SIZE,
+            Self::SIZE,
+            Self::FORMAT,
+            "shadow_moments_unblurred",
+        );
+
+        let mut uniforms =
+            DynamicUniformBuffer::new(device, "shadow_moments_uniforms", CASCADE_COUNT);
+        for cascade in 0..CASCADE_COUNT as u32 {
+            uniforms.push(&MomentsUniform {
+                cascade,
+                _padding: [0; 3],
+            });
+        }
+        uniforms.upload(device, queue);
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shadow_moments_layout"),
+            entries: &[
+                DynamicUniformBuffer::<MomentsUniform>::layout_entry(
+                    0,
+                    wgpu::ShaderStages::COMPUTE,
+                ),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: Self::FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_moments_bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&unblurred.view),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Moments Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout);
+
+        Self {
+            layers,
+            unblurred,
+            scratch: Blur::create_target(
+                device,
+                Self::SIZE,
+                Self::SIZE,
+                Self::FORMAT,
+                "shadow_moments_scratch",
+            ),
+            blur: Blur::new(device, queue, Self::FORMAT, Self::BLUR),
+            pipeline_layout,
+            pipeline,
+            uniforms,
+            bind_group,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+    ) -> wgpu::ComputePipeline {
+        let shader = preprocess::builtin_module(
+            device,
+            "Shadow Moments Shader",
+            "shadow_moments.wgsl",
+            &preprocess::ShaderDefs::new(),
+        );
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Shadow Moments Pipeline"),
+            layout: Some(pipeline_layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        })
+    }
+
+    fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(device, &self.pipeline_layout);
+        self.blur.rebuild_pipelines(device);
+    }
+
+    /// Turns the cascades `ShadowMap::render` just drew into moments and
+    /// blurs each into its layer.
+    fn render(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let groups = Self::SIZE.div_ceil(Self::WORKGROUP_SIZE);
+        for (cascade, layer) in self.layers.iter().enumerate() {
+            {
+                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("Shadow Moments"),
+                    timestamp_writes: None,
+                });
+                pass.set_pipeline(&self.pipeline);
+                pass.set_bind_group(0, &self.bind_group, &[self.uniforms.offset(cascade)]);
+                pass.dispatch_workgroups(groups, groups, 1);
+            }
+            self.blur.run(
+                device,
+                encoder,
+                &self.unblurred.view,
+                &self.scratch.view,
+                layer,
+                [Self::SIZE; 2],
+            );
+        }
+    }
+}
+
+/// Cube-map depth shadows for the first `max_lights` point lights.
+struct PointShadows {
+    max_lights: usize,
+    active_lights: usize,
+    view: wgpu::TextureView,
+    faces: Vec<ShadowView>,
+}
+
+impl PointShadows {
+    const SIZE: u32 = 512;
+    const NEAR: f32 = 0.05;
+    const FAR: f32 = 50.0;
+
+    fn new(device: &wgpu::Device, max_lights: usize) -> Self {
+        // Keep at least one cube allocated so the binding stays valid.
+        let cubes = max_lights.max(1) as u32;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("point_shadow_map"),
+            size: wgpu::Extent3d {
+                width: Self::SIZE,
+                height: Self::SIZE,
+                depth_or_array_layers: cubes * 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture::Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("point_shadow_map_view"),
+            dimension: Some(wgpu::TextureViewDimension::CubeArray),
+            ..Default::default()
+        });
+        let faces = (0..max_lights as u32 * 6)
+            .map(|layer| {
+                let label = format!("Point Shadow {} Face {}", layer / 6, layer % 6);
+                ShadowView::new(&texture, layer, label)
+            })
+            .collect();
+
+        Self {
+            max_lights,
+            active_lights: 0,
+            view,
+            faces,
+        }
+    }
+
+    /// View-projection for each cube face, in wgpu's +X, -X, +Y, -Y, +Z, -Z
+    /// layer order. wgpu samples cube faces with a mirrored x axis relative
+    /// to a right-handed look-at, hence the flip and the disabled culling.
+    fn face_matrices(position: cgmath::Point3<f32>) -> [cgmath::Matrix4<f32>; 6] {
+        let flip_x = cgmath::Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0);
+        let proj = flip_x
+            * OPENGL_TO_WGPU_MATRIX
+            * cgmath::perspective(cgmath::Deg(90.0), 1.0, Self::NEAR, Self::FAR);
+        let faces = [
+            (cgmath::Vector3::unit_x(), cgmath::Vector3::unit_y()),
+            (-cgmath::Vector3::unit_x(), cgmath::Vector3::unit_y()),
+            (cgmath::Vector3::unit_y(), -cgmath::Vector3::unit_z()),
+            (-cgmath::Vector3::unit_y(), cgmath::Vector3::unit_z()),
+            (cgmath::Vector3::unit_z(), cgmath::Vector3::unit_y()),
+            (-cgmath::Vector3::unit_z(), cgmath::Vector3::unit_y()),
+        ];
+        faces.map(|(forward, up)| {
+            proj * cgmath::Matrix4::look_at_rh(position, position + forward, up)
+        })
+    }
+
+    /// Pushes the uniforms of each active light's six faces in turn.
+    fn update(
+        &mut self,
+        uniforms: &mut PerDrawUniforms<ShadowPassUniform>,
+        lights: &[LightUniform],
+    ) {
+        self.active_lights = lights.len().min(self.max_lights);
+        for light in &lights[..self.active_lights] {
+            let position = cgmath::Point3::from(light.position);
+            for matrix in Self::face_matrices(position) {
+                uniforms.push(&ShadowPassUniform {
+                    view_proj: matrix.into(),
+                    light_position: light.position,
+                    far: Self::FAR,
+                });
+            }
+        }
+    }
+}
+
+pub struct ShadowMap {
+    pub texture: texture::Texture,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    pub debug_cascades: bool,
+    uniform: ShadowUniform,
+    uniform_buffer: wgpu::Buffer,
+    point_uniform_buffer: wgpu::Buffer,
+    pass_bind_group_layout: wgpu::BindGroupLayout,
+    joint_buffer: wgpu::Buffer,
+    // The light matrix of every pass, cascades first, then point faces.
+    pass_uniforms: PerDrawUniforms<ShadowPassUniform>,
+    pass_bind_group: wgpu::BindGroup,
+    cascades: Vec<ShadowView>,
+    point_shadows: PointShadows,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader_defs: ShaderDefs,
+    skinned: bool,
+    vertex_encoding: model::VertexEncoding,
+    pipeline: wgpu::RenderPipeline,
+    point_pipeline: wgpu::RenderPipeline,
+    filter: ShadowFilter,
+    // Bound whatever the filter, so the layout stays the same.
+    moments_texture: wgpu::Texture,
+    moments_view: wgpu::TextureView,
+    moments_sampler: wgpu::Sampler,
+    // Only while the filter is `ShadowFilter::Variance`.
+    moments: Option<Moments>,
+}
+
+impl ShadowMap {
+    pub const SIZE: u32 = 2048;
+
+    /// The depth passes skin against `joints`, if it is for a skeleton,
+    /// and read vertices in `vertex_encoding`.
+    pub fn new(
+        device: &wgpu::Device,
+        max_point_shadows: usize,
+        joints: &JointPalette,
+        vertex_encoding: model::VertexEncoding,
+    ) -> Self {
+        let texture = Self::create_texture(device);
+        let uniform = bytemuck::Zeroable::zeroed();
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX
+                | wgpu::ShaderStages::FRAGMENT
+                | wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        // The depth passes can't see the shadow texture they are writing to,
+        // so they get a layout with just their pass's matrix and the joint
+        // palette.
+        let pass_uniforms = PerDrawUniforms::new(
+            device,
+            "Shadow Pass Uniform Buffer",
+            CASCADE_COUNT + max_point_shadows * 6,
+            wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        );
+        let entries = pass_uniforms
+            .layout_entry(0)
+            .into_iter()
+            .chain([JointPalette::layout_entry(1)])
+            .collect::<Vec<_>>();
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &entries,
+                label: Some("shadow_pass_bind_group_layout"),
+            });
+        let joint_buffer = joints.buffer().clone();
+        let pass_bind_group = Self::create_pass_bind_group(
+            device,
+            &pass_bind_group_layout,
+            &pass_uniforms,
+            &joint_buffer,
+        );
+        let cascades = (0..CASCADE_COUNT as u32)
+            .map(|layer| {
+                ShadowView::new(&texture.texture, layer, format!("Shadow Cascade {}", layer))
+            })
+            .collect();
+        let point_shadows = PointShadows::new(device, max_point_shadows);
+        let point_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Point Shadow Uniform Buffer"),
+            size: size_of::<PointShadowUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Seen by compute too, for `Volumetrics` to march the cascades.
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform_entry,
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    ..uniform_entry
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::CubeArray,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("shadow_bind_group_layout"),
+        });
+        let (moments_texture, moments_view) = Moments::create_texture(device, 1);
+        let moments_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow_moments_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniform_buffer,
+            &texture,
+            &point_uniform_buffer,
+            &point_shadows,
+            (&moments_view, &moments_sampler),
+        );
+
+        let shader_defs =
+            pass_uniforms.shader_defs(vertex_encoding.shader_defs(joints.shader_defs()));
+        let skinned = joints.is_skinned();
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&pass_bind_group_layout],
+            push_constant_ranges: &pass_uniforms.push_constant_ranges(),
+        });
+        let [pipeline, point_pipeline] = Self::create_pipelines(
+            device,
+            &pipeline_layout,
+            &shader_defs,
+            skinned,
+            vertex_encoding,
+        );
+
+        Self {
+            texture,
+            bind_group_layout,
+            bind_group,
+            debug_cascades: false,
+            uniform,
+            uniform_buffer,
+            point_uniform_buffer,
+            pass_bind_group_layout,
+            joint_buffer,
+            pass_uniforms,
+            pass_bind_group,
+            cascades,
+            point_shadows,
+            pipeline_layout,
+            shader_defs,
+            skinned,
+            vertex_encoding,
+            pipeline,
+            point_pipeline,
+            filter: ShadowFilter::Pcf,
+            moments_texture,
+            moments_view,
+            moments_sampler,
+            moments: None,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        defs: &ShaderDefs,
+        skinned: bool,
+        vertex_encoding: model::VertexEncoding,
+    ) -> [wgpu::RenderPipeline; 2] {
+        let vertex_buffers = model::vertex_buffers(skinned, vertex_encoding);
+        let shader = preprocess::builtin_module(device, "Shadow Shader", "shadow.wgsl", defs);
+        let point_shader =
+            preprocess::builtin_module(device, "Point Shadow Shader", "point_shadow.wgsl", defs);
+        [
+            Self::create_pipeline(
+                device,
+                pipeline_layout,
+                &shader,
+                "Shadow Pipeline",
+                &vertex_buffers,
+                false,
+                Some(wgpu::Face::Back),
+            ),
+            Self::create_pipeline(
+                device,
+                pipeline_layout,
+                &point_shader,
+                "Point Shadow Pipeline",
+                &vertex_buffers,
+                true,
+                None,
+            ),
+        ]
+    }
+
+    /// Compiles the pipelines again from the current shader sources,
+    /// along with the variance shadow maps' prefilter.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        [self.pipeline, self.point_pipeline] = Self::create_pipelines(
+            device,
+            &self.pipeline_layout,
+            &self.shader_defs,
+            self.skinned,
+            self.vertex_encoding,
+        );
+        if let Some(moments) = &mut self.moments {
+            moments.rebuild_pipelines(device);
+        }
+    }
+
+    fn create_pass_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniforms: &PerDrawUniforms<ShadowPassUniform>,
+        joint_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        let entries = uniforms
+            .binding()
+            .map(|resource| wgpu::BindGroupEntry {
+                binding: 0,
+                resource,
+            })
+            .into_iter()
+            .chain([wgpu::BindGroupEntry {
+                binding: 1,
+                resource: joint_buffer.as_entire_binding(),
+            }])
+            .collect::<Vec<_>>();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some("shadow_pass_bind_group"),
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        texture: &texture::Texture,
+        point_uniform_buffer: &wgpu::Buffer,
+        point_shadows: &PointShadows,
+        (moments_view, moments_sampler): (&wgpu::TextureView, &wgpu::Sampler),
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: point_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&point_shadows.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(moments_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(moments_sampler),
+                },
+            ],
+            label: Some("shadow_bind_group"),
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        label: &str,
+        vertex_buffers: &[wgpu::VertexBufferLayout],
+        writes_depth: bool,
+        cull_mode: Option<wgpu::Face>,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: vertex_buffers,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            // Point shadows write linear distance from the fragment shader.
+            fragment: writes_depth.then(|| wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: if writes_depth {
+                    wgpu::DepthBiasState::default()
+                } else {
+                    wgpu::DepthBiasState {
+                        constant: 2,
+                        slope_scale: 2.0,
+                        clamp: 0.0,
+                    }
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn max_point_shadows(&self) -> usize {
+        self.point_shadows.max_lights
+    }
+
+    /// Reallocates the cube-map array for a new number of shadow-casting
+    /// point lights. The bind group layout is unchanged, so pipelines built
+    /// against it stay valid; the pass uniforms grow on the next `update`.
+    pub fn set_max_point_shadows(&mut self, device: &wgpu::Device, max_lights: usize) {
+        self.point_shadows = PointShadows::new(device, max_lights);
+        self.rebind(device);
+    }
+
+    pub fn filter(&self) -> ShadowFilter {
+        self.filter
+    }
+
+    /// Switches how the cascades are read, allocating the moments for
+    /// `ShadowFilter::Variance` or freeing them for `Pcf`. Takes effect
+    /// with the next `update`, and like `set_max_point_shadows` keeps the
+    /// bind group layout.
+    pub fn set_filter(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, filter: ShadowFilter) {
+        if filter == self.filter {
+            return;
+        }
+        self.filter = filter;
+        let size = match filter {
+            ShadowFilter::Pcf => 1,
+            ShadowFilter::Variance => Moments::SIZE,
+        };
+        (self.moments_texture, self.moments_view) = Moments::create_texture(device, size);
+        self.moments = (filter == ShadowFilter::Variance)
+            .then(|| Moments::new(device, queue, &self.texture.view, &self.moments_texture));
+        self.rebind(device);
+    }
+
+    fn rebind(&mut self, device: &wgpu::Device) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            &self.texture,
+            &self.point_uniform_buffer,
+            &self.point_shadows,
+            (&self.moments_view, &self.moments_sampler),
+        );
+    }
+
+    fn create_texture(device: &wgpu::Device) -> texture::Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow_map"),
+            size: wgpu::Extent3d {
+                width: Self::SIZE,
+                height: Self::SIZE,
+                depth_or_array_layers: CASCADE_COUNT as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture::Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("shadow_map_view"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        light: &DirectionalLight,
+        camera: &Camera,
+        point_lights: &[LightUniform],
+    ) {
+        let splits = light.cascade_splits(camera);
+        self.pass_uniforms.clear();
+        for i in 0..CASCADE_COUNT {
+            let matrix = light.build_cascade_matrix(camera, splits[i], splits[i + 1]);
+            self.uniform.cascades[i] = matrix.into();
+            self.uniform.splits[i] = splits[i + 1];
+            self.pass_uniforms.push(&ShadowPassUniform {
+                view_proj: matrix.into(),
+                ..bytemuck::Zeroable::zeroed()
+            });
+        }
+        self.uniform.direction = light.direction.normalize().into();
+        self.uniform.intensity = light.intensity;
+        self.uniform.color = light.color;
+        self.uniform.debug_cascades = self.debug_cascades as u32;
+        self.uniform.variance = (self.filter == ShadowFilter::Variance) as u32;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+
+        self.point_shadows
+            .update(&mut self.pass_uniforms, point_lights);
+        if self.pass_uniforms.upload(device, queue) {
+            self.pass_bind_group = Self::create_pass_bind_group(
+                device,
+                &self.pass_bind_group_layout,
+                &self.pass_uniforms,
+                &self.joint_buffer,
+            );
+        }
+        queue.write_buffer(
+            &self.point_uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[PointShadowUniform {
+                count: self.point_shadows.active_lights as u32,
+                far: PointShadows::FAR,
+                bias: 0.005,
+                _padding: 0,
+            }]),
+        );
+    }
+
+    /// Number of depth passes `render` records: one per cascade and per
+    /// active point light cube face.
+    pub fn pass_count(&self) -> usize {
+        self.cascades.len() + self.point_shadows.active_lights * 6
+    }
+
+    /// Renders the depth of `model` into every cascade and every active
+    /// point light cube face, then the cascades' moments if the filter
+    /// reads them.
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        instances: &InstanceBuffer,
+    ) {
+        let point_faces = &self.point_shadows.faces[..self.point_shadows.active_lights * 6];
+        let passes = self
+            .cascades
+            .iter()
+            .map(|view| (view, &self.pipeline))
+            .chain(point_faces.iter().map(|view| (view, &self.point_pipeline)));
+
+        // Passes are in the order `update` pushed their uniforms.
+        for (i, (view, pipeline)) in passes.enumerate() {
+            let mut shadow_pass = view.begin_pass(encoder);
+            if instances.is_empty() {
+                continue;
+            }
+            shadow_pass.set_pipeline(pipeline);
+            self.pass_uniforms
+                .bind(&mut shadow_pass, 0, &self.pass_bind_group, i);
+            shadow_pass.set_vertex_buffer(1, instances.slice());
+            for mesh in &model.meshes {
+                mesh.set_buffers(&mut shadow_pass);
+                shadow_pass.draw_indexed(0..mesh.num_elements, 0, 0..instances.len() as u32);
+            }
+        }
+        if let Some(moments) = &self.moments {
+            moments.render(device, encoder);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+#include "include/instance.wgsl"
+#define SKIN_GROUP 0
+#define SKIN_BINDING 1
+#include "include/skinning.wgsl"
+
+struct CascadeUniform {
+    light_view_proj: mat4x4<f32>,
+}
+#ifdef PUSH_CONSTANTS
+var<push_constant> cascade: CascadeUniform;
+#else
+@group(0) @binding(0)
+var<uniform> cascade: CascadeUniform;
+#endif
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+#ifdef SKINNED
+    skin: SkinInput,
+#endif
+) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var position = model.position;
+#ifdef SKINNED
+    position = skin_position(skin_matrix(skin), position);
+#endif
+    return cascade.light_view_proj * model_matrix * vec4<f32>(position, 1.0);
+}
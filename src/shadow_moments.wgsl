@@ -0,0 +1,30 @@
+// Turns one cascade of the shadow map into the depth and depth squared
+// `ShadowFilter::Variance` reads, at half size: each texel averages a 2x2
+// block of the cascade's, so the moments are already a little filtered
+// before `Blur` spreads them.
+
+struct MomentsUniform {
+    cascade: u32,
+}
+@group(0) @binding(0)
+var<uniform> moments_uniform: MomentsUniform;
+// The cascades' depth, read as unfilterable floats.
+@group(0) @binding(1)
+var t_depth: texture_2d_array<f32>;
+@group(0) @binding(2)
+var output: texture_storage_2d<rgba16float, write>;
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    var moments = vec2<f32>(0.0);
+    for (var i = 0u; i < 4u; i += 1u) {
+        let texel = id.xy * 2u + vec2<u32>(i & 1u, i >> 1u);
+        let depth = textureLoad(t_depth, texel, moments_uniform.cascade, 0).r;
+        moments += vec2<f32>(depth, depth * depth);
+    }
+    textureStore(output, id.xy, vec4<f32>(moments * 0.25, 0.0, 1.0));
+}
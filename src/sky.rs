@@ -0,0 +1,289 @@
+use cgmath::InnerSpace;
+
+use crate::shadow::DirectionalLight;
+use crate::skybox::{self, Skybox};
+use crate::texture;
+
+// Texels along each side of the baked cube faces.
+const CUBE_SIZE: u32 = 64;
+// How far the sun moves, in hours, or the turbidity changes before the
+// environment is baked again.
+const REBAKE_HOURS: f32 = 0.2;
+const REBAKE_TURBIDITY: f32 = 0.1;
+// Brings the model's luminance, in kcd/m², to the scene's: a clear noon
+// zenith comes out about as bright as the default gradient's.
+const LUMINANCE_SCALE: f32 = 0.08;
+// How far south of overhead the sun passes at noon.
+const NOON_TILT: cgmath::Deg<f32> = cgmath::Deg(30.0);
+const SUN_INTENSITY: f32 = 2.5;
+const MOON_INTENSITY: f32 = 0.08;
+const MOON_COLOR: [f32; 3] = [0.6, 0.7, 1.0];
+const NIGHT_SKY: [f32; 3] = [0.004, 0.006, 0.015];
+const GROUND: [f32; 3] = [0.2, 0.18, 0.16];
+
+/// The time of day and the air the sky is drawn through.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SkySettings {
+    /// Whether the sky drives the environment, the directional light and
+    /// the fog's color. Off leaves them to be set by hand.
+    pub enabled: bool,
+    /// Hours since midnight: the sun rises in +X at 6 and sets in -X at 18.
+    pub time_of_day: f32,
+    /// Hours that pass each second; zero holds the time.
+    pub speed: f32,
+    /// Haze in the air, from 2 for a clear day to about 10 for a murky one.
+    pub turbidity: f32,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            time_of_day: 10.0,
+            speed: 0.0,
+            turbidity: 2.5,
+        }
+    }
+}
+
+/// Preetham, Shirley and Smits' analytic daylight model: the sky's color in
+/// every direction for a sun position and turbidity, which `bake` renders
+/// into a cube for the skybox and image-based lighting. Also where the sun
+/// is and what light it gives, or the moon once it has set.
+#[derive(Debug, Clone)]
+pub struct Sky {
+    settings: SkySettings,
+    // The time of day and turbidity the environment was last baked for.
+    baked: Option<(f32, f32)>,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self::new(SkySettings::default())
+    }
+}
+
+impl Sky {
+    pub fn new(settings: SkySettings) -> Self {
+        Self {
+            settings,
+            baked: None,
+        }
+    }
+
+    pub fn settings(&self) -> SkySettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: SkySettings) {
+        self.settings = SkySettings {
+            time_of_day: settings.time_of_day.rem_euclid(24.0),
+            turbidity: settings.turbidity.clamp(1.7, 10.0),
+            ..settings
+        };
+        if !settings.enabled {
+            self.baked = None;
+        }
+    }
+
+    /// Moves the time of day on by `delta_time` seconds.
+    pub fn advance(&mut self, delta_time: f32) {
+        self.settings.time_of_day =
+            (self.settings.time_of_day + self.settings.speed * delta_time).rem_euclid(24.0);
+    }
+
+    /// Unit vector towards the sun, below the horizon at night.
+    pub fn sun_direction(&self) -> cgmath::Vector3<f32> {
+        let angle = (self.settings.time_of_day - 6.0) / 12.0 * std::f32::consts::PI;
+        let tilt = cgmath::Rad::from(NOON_TILT).0;
+        cgmath::Vector3::new(
+            angle.cos(),
+            angle.sin() * tilt.cos(),
+            angle.sin() * tilt.sin(),
+        )
+    }
+
+    /// Points `light` from the sun, tinted and dimmed by the air it comes
+    /// through, or from the moon while the sun is down. Both fade out at
+    /// the horizon, so the swap between them doesn't show.
+    pub fn apply_to_light(&self, light: &mut DirectionalLight) {
+        let sun = self.sun_direction();
+        if sun.y >= 0.0 {
+            let transmittance = self.sun_transmittance(sun.y);
+            let peak = transmittance.iter().copied().fold(f32::EPSILON, f32::max);
+            light.direction = -sun;
+            light.color = transmittance.map(|t| t / peak);
+            light.intensity = SUN_INTENSITY * peak * smoothstep(0.0, 0.08, sun.y);
+        } else {
+            light.direction = sun;
+            light.color = MOON_COLOR;
+            light.intensity = MOON_INTENSITY * smoothstep(0.0, 0.08, -sun.y);
+        }
+    }
+
+    /// The sky just above the horizon, averaged all the way around, for fog
+    /// to fade into.
+    pub fn horizon_color(&self) -> [f32; 3] {
+        const SAMPLES: usize = 8;
+        let sun = self.sun_direction();
+        let mut sum = [0.0; 3];
+        for i in 0..SAMPLES {
+            let azimuth = i as f32 / SAMPLES as f32 * std::f32::consts::TAU;
+            let direction = cgmath::Vector3::new(azimuth.cos(), 0.05, azimuth.sin()).normalize();
+            let color = self.radiance(direction, sun);
+            for c in 0..3 {
+                sum[c] += color[c] / SAMPLES as f32;
+            }
+        }
+        sum
+    }
+
+    /// Whether the sun or the haze has moved far enough from where `bake`
+    /// last drew them that the environment should be baked again.
+    pub fn needs_bake(&self) -> bool {
+        self.baked.is_none_or(|(time, turbidity)| {
+            // Around the clock, so midnight's wrap doesn't count as a day.
+            let hours = (self.settings.time_of_day - time).rem_euclid(24.0);
+            hours.min(24.0 - hours) >= REBAKE_HOURS
+                || (self.settings.turbidity - turbidity).abs() >= REBAKE_TURBIDITY
+        })
+    }
+
+    /// Renders the sky into a cube, for `Environment::new` and
+    /// `Skybox::new`.
+    pub fn bake(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+        self.baked = Some((self.settings.time_of_day, self.settings.turbidity));
+        let sun = self.sun_direction();
+        let faces = skybox::bake_cube_faces(CUBE_SIZE, |direction| self.radiance(direction, sun));
+        texture::Texture::create_cube(
+            device,
+            queue,
+            CUBE_SIZE,
+            Skybox::HDR_FORMAT,
+            &faces.each_ref().map(|face| bytemuck::cast_slice(face)),
+            "sky",
+        )
+    }
+
+    // What reaches the eye looking along `direction`: the Perez sky above
+    // the horizon and a ground lit by it below, both fading into a night
+    // sky as the sun sets, plus a glow around the sun.
+    fn radiance(&self, direction: cgmath::Vector3<f32>, sun: cgmath::Vector3<f32>) -> [f32; 3] {
+        let day = smoothstep(-0.2, 0.05, sun.y);
+        // The model only holds for a sun above the horizon, so at dusk it
+        // keeps drawing one on it, dimmed by `day`.
+        let sun = cgmath::Vector3::new(sun.x, sun.y.max(0.01), sun.z).normalize();
+        let turbidity = self.settings.turbidity;
+        let theta_sun = sun.y.acos();
+        let horizon = cgmath::Vector3::new(direction.x, 0.0, direction.z);
+        let above = if direction.y >= 0.0 || horizon.magnitude2() < 1e-6 {
+            direction
+        } else {
+            horizon.normalize()
+        };
+        let cos_theta = above.y.max(0.01);
+        let gamma = above.dot(sun).clamp(-1.0, 1.0).acos();
+
+        let zenith = zenith_xy_luminance(turbidity, theta_sun);
+        let coefficients = perez_coefficients(turbidity);
+        let xy_luminance: [f32; 3] = std::array::from_fn(|i| {
+            let [a, b, c, d, e] = coefficients[i];
+            let perez = |cos_theta: f32, gamma: f32| {
+                (1.0 + a * (b / cos_theta).exp())
+                    * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+            };
+            zenith[i] * perez(cos_theta, gamma) / perez(1.0, theta_sun)
+        });
+        let sky = xy_luminance_to_rgb(xy_luminance);
+
+        let mut color = std::array::from_fn(|i| sky[i] * day + NIGHT_SKY[i]);
+        if direction.y < 0.0 {
+            let lit = smoothstep(0.0, 0.2, sun.y) * day;
+            let t = (-direction.y).sqrt();
+            color = std::array::from_fn(|i| {
+                let ground = GROUND[i] * (lit * 0.5 + 0.02);
+                color[i] + (ground - color[i]) * t
+            });
+        } else if day > 0.0 {
+            let transmittance = self.sun_transmittance(sun.y);
+            let glow = 2.0 * day * ((direction.dot(sun) - 1.0) * 400.0).exp();
+            for i in 0..3 {
+                color[i] += transmittance[i] * glow;
+            }
+        }
+        color
+    }
+
+    // What share of each of red, green and blue sunlight gets through the
+    // air to a sun `elevation`, the sine of its height above the horizon,
+    // relative to a sun overhead. Kasten and Young's air mass, with the
+    // extinction of Rayleigh scattering plus a haze growing with turbidity.
+    fn sun_transmittance(&self, elevation: f32) -> [f32; 3] {
+        const RAYLEIGH: [f32; 3] = [0.1, 0.2, 0.4];
+        let degrees = cgmath::Deg::from(cgmath::Rad(elevation.clamp(0.0, 1.0).asin())).0;
+        let air_mass = 1.0 / (elevation.max(0.0) + 0.50572 * (degrees + 6.07995).powf(-1.6364));
+        let haze = 0.02 * self.settings.turbidity;
+        RAYLEIGH.map(|k| (-(k + haze) * (air_mass - 1.0)).exp())
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// The zenith's chromaticity x and y and its luminance, in kcd/m², for the
+// sun `theta_sun` radians from it.
+fn zenith_xy_luminance(turbidity: f32, theta_sun: f32) -> [f32; 3] {
+    let t = turbidity;
+    let (t2, s, s2, s3) = (t * t, theta_sun, theta_sun * theta_sun, theta_sun.powi(3));
+    let chi = (4.0 / 9.0 - t / 120.0) * (std::f32::consts::PI - 2.0 * theta_sun);
+    let luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+    let x = t2 * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s)
+        + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s + 0.00394)
+        + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s + 0.25886);
+    let y = t2 * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s)
+        + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s + 0.00516)
+        + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s + 0.26688);
+    [x, y, luminance]
+}
+
+// The Perez function's A to E for x, y and luminance.
+fn perez_coefficients(t: f32) -> [[f32; 5]; 3] {
+    [
+        [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ],
+        [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ],
+        [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ],
+    ]
+}
+
+// Linear sRGB from CIE xyY, with the luminance brought to the scene's.
+fn xy_luminance_to_rgb([x, y, luminance]: [f32; 3]) -> [f32; 3] {
+    let luminance = luminance * LUMINANCE_SCALE;
+    let y = y.max(1e-4);
+    let (cx, cy, cz) = (x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+    [
+        3.2406 * cx - 1.5372 * cy - 0.4986 * cz,
+        -0.9689 * cx + 1.8758 * cy + 0.0415 * cz,
+        0.0557 * cx - 0.2040 * cy + 1.0570 * cz,
+    ]
+    .map(|c| c.max(0.0))
+}
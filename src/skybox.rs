@@ -0,0 +1,360 @@
+use cgmath::{InnerSpace, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use crate::camera::Camera;
+use crate::preprocess::{self, ShaderDefs};
+use crate::{resources, texture};
+
+/// Direction through texel (`u`, `v`) of a cube face, both in -1..1 with `v`
+/// pointing down, following wgpu's +X, -X, +Y, -Y, +Z, -Z face order.
+pub fn cube_face_direction(face: usize, u: f32, v: f32) -> cgmath::Vector3<f32> {
+    let direction = match face {
+        0 => cgmath::Vector3::new(1.0, -v, -u),
+        1 => cgmath::Vector3::new(-1.0, -v, u),
+        2 => cgmath::Vector3::new(u, 1.0, v),
+        3 => cgmath::Vector3::new(u, -1.0, -v),
+        4 => cgmath::Vector3::new(u, -v, 1.0),
+        _ => cgmath::Vector3::new(-u, -v, -1.0),
+    };
+    direction.normalize()
+}
+
+/// Fills six `Rgba16Float` faces by evaluating `color` for every texel
+/// direction.
+pub fn bake_cube_faces(
+    size: u32,
+    color: impl Fn(cgmath::Vector3<f32>) -> [f32; 3],
+) -> [Vec<half::f16>; 6] {
+    std::array::from_fn(|face| {
+        let mut pixels = Vec::with_capacity((size * size * 4) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let u = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                let v = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                let [r, g, b] = color(cube_face_direction(face, u, v));
+                pixels.extend([r, g, b, 1.0].map(half::f16::from_f32));
+            }
+        }
+        pixels
+    })
+}
+
+fn sample_equirectangular(image: &image::Rgb32FImage, direction: cgmath::Vector3<f32>) -> [f32; 3] {
+    use std::f32::consts::PI;
+
+    let u = 0.5 + direction.z.atan2(direction.x) / (2.0 * PI);
+    let v = 0.5 - direction.y.clamp(-1.0, 1.0).asin() / PI;
+    let x = (u * image.width() as f32 - 0.5).rem_euclid(image.width() as f32);
+    let y = (v * image.height() as f32 - 0.5).clamp(0.0, (image.height() - 1) as f32);
+
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let x1 = (x0 + 1) % image.width();
+    let y1 = (y0 + 1).min(image.height() - 1);
+    let (fx, fy) = (x.fract(), y.fract());
+    let texel = |x, y| image.get_pixel(x, y).0;
+    let lerp = |a: [f32; 3], b: [f32; 3], t: f32| std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t);
+    lerp(
+        lerp(texel(x0, y0), texel(x1, y0), fx),
+        lerp(texel(x0, y1), texel(x1, y1), fx),
+        fy,
+    )
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyboxUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    reverse_z: u32,
+    _padding: [u32; 3],
+}
+
+pub struct Skybox {
+    pub texture: texture::Texture,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    sample_count: u32,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Skybox {
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    pub fn new(
+        device: &wgpu::Device,
+        texture: texture::Texture,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Buffer"),
+            contents: bytemuck::cast_slice(&[SkyboxUniform {
+                inv_view_proj: cgmath::Matrix4::identity().into(),
+                reverse_z: 0,
+                _padding: [0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("skybox_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, color_format, 1);
+
+        Self {
+            texture,
+            uniform_buffer,
+            bind_group,
+            pipeline_layout,
+            color_format,
+            sample_count: 1,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader =
+            preprocess::builtin_module(device, "Skybox Shader", "skybox.wgsl", &ShaderDefs::new());
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Drawn after opaque geometry; only fills pixels still at the
+            // cleared far depth, which is 0 or 1 depending on the camera's
+            // `reverse_z`, so it's matched exactly.
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Equal,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Compiles the pipeline again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            self.color_format,
+            self.sample_count,
+        );
+    }
+
+    /// Rebuilds the pipeline for targets with `sample_count` samples a
+    /// pixel, as with `Msaa`'s.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        if sample_count != self.sample_count {
+            self.sample_count = sample_count;
+            self.rebuild_pipelines(device);
+        }
+    }
+
+    /// Vertical gradient from `ground` through `horizon` to `zenith`, used
+    /// when no environment has been loaded.
+    pub fn gradient(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let texture = Self::gradient_cube(device, queue, zenith, horizon, ground);
+        Self::new(device, texture, color_format)
+    }
+
+    pub fn gradient_cube(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    ) -> texture::Texture {
+        const SIZE: u32 = 32;
+        let faces = bake_cube_faces(SIZE, |direction| {
+            let (from, to, t) = if direction.y >= 0.0 {
+                (horizon, zenith, direction.y.powf(0.5))
+            } else {
+                (horizon, ground, (-direction.y).powf(0.5))
+            };
+            std::array::from_fn(|i| from[i] + (to[i] - from[i]) * t)
+        });
+        texture::Texture::create_cube(
+            device,
+            queue,
+            SIZE,
+            Self::HDR_FORMAT,
+            &faces.each_ref().map(|face| bytemuck::cast_slice(face)),
+            "skybox_gradient",
+        )
+    }
+
+    /// Loads six square LDR images in +X, -X, +Y, -Y, +Z, -Z order.
+    pub async fn load_faces(
+        file_names: [&str; 6],
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<Self> {
+        let mut faces = Vec::with_capacity(6);
+        for file_name in file_names {
+            let data = resources::load_binary(file_name).await?;
+            faces.push(image::load_from_memory(&data)?.to_rgba8());
+        }
+        let size = faces[0].width();
+        if faces
+            .iter()
+            .any(|f| f.width() != size || f.height() != size)
+        {
+            anyhow::bail!("skybox faces must be square and equally sized");
+        }
+
+        let texture = texture::Texture::create_cube(
+            device,
+            queue,
+            size,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            &std::array::from_fn(|i| faces[i].as_raw().as_slice()),
+            "skybox_faces",
+        );
+        Ok(Self::new(device, texture, color_format))
+    }
+
+    /// Loads an equirectangular (lat-long) image, typically a `.hdr`, and
+    /// resamples it into cube faces of `face_size` texels.
+    pub async fn load_equirectangular(
+        file_name: &str,
+        face_size: u32,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<Self> {
+        let data = resources::load_binary(file_name).await?;
+        let image = image::load_from_memory(&data)?.to_rgb32f();
+        let texture = Self::cube_from_equirectangular(device, queue, &image, face_size, file_name);
+        Ok(Self::new(device, texture, color_format))
+    }
+
+    pub fn cube_from_equirectangular(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &image::Rgb32FImage,
+        face_size: u32,
+        label: &str,
+    ) -> texture::Texture {
+        let faces = bake_cube_faces(face_size, |direction| {
+            sample_equirectangular(image, direction)
+        });
+        texture::Texture::create_cube(
+            device,
+            queue,
+            face_size,
+            Self::HDR_FORMAT,
+            &faces.each_ref().map(|face| bytemuck::cast_slice(face)),
+            label,
+        )
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera) {
+        let mut view = camera.build_view_matrix();
+        view.w = cgmath::Vector4::unit_w();
+        let view_proj = camera.build_projection_matrix(camera.znear, camera.zfar) * view;
+        let inv_view_proj = view_proj.invert().unwrap_or_else(cgmath::Matrix4::identity);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[SkyboxUniform {
+                inv_view_proj: inv_view_proj.into(),
+                reverse_z: camera.reverse_z as u32,
+                _padding: [0; 3],
+            }]),
+        );
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
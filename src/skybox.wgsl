@@ -0,0 +1,41 @@
+struct SkyboxUniform {
+    // Inverse of projection * view with the view translation removed.
+    inv_view_proj: mat4x4<f32>,
+    reverse_z: u32,
+}
+@group(0) @binding(0)
+var<uniform> skybox: SkyboxUniform;
+@group(0) @binding(1)
+var t_sky: texture_cube<f32>;
+@group(0) @binding(2)
+var s_sky: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// A single triangle covering the screen, pinned to the far plane.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    let far = select(1.0, 0.0, skybox.reverse_z != 0u);
+    out.clip_position = vec4<f32>(ndc, far, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Unprojecting two depths works for perspective and orthographic alike.
+    // The far plane itself is avoided since with a huge far/near ratio it
+    // unprojects to infinity in f32.
+    let near_depth = select(0.0, 1.0, skybox.reverse_z != 0u);
+    let near = skybox.inv_view_proj * vec4<f32>(in.ndc, near_depth, 1.0);
+    let far = skybox.inv_view_proj * vec4<f32>(in.ndc, 0.5, 1.0);
+    let direction = far.xyz / far.w - near.xyz / near.w;
+    return textureSample(t_sky, s_sky, direction);
+}
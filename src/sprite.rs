@@ -0,0 +1,420 @@
+use std::ops::Range;
+
+use wgpu::util::DeviceExt;
+
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+use crate::vertex::Vertex;
+
+/// A texture or atlas registered with a `SpriteRenderer`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SpriteTextureId(pub usize);
+
+impl SpriteTextureId {
+    /// Plain white, for solid rectangles colored by `Sprite::color`.
+    pub const WHITE: Self = Self(0);
+}
+
+/// A textured quad queued for one frame, in physical pixels with the
+/// origin at the top-left of the output.
+#[derive(Debug, Copy, Clone)]
+pub struct Sprite {
+    /// Where `origin` lands on screen.
+    pub position: [f32; 2],
+    /// Unscaled width and height.
+    pub size: [f32; 2],
+    pub scale: [f32; 2],
+    /// Clockwise on screen, in radians, about `origin`.
+    pub rotation: f32,
+    /// Pivot for position, rotation and scale as a fraction of `size`;
+    /// `[0.0, 0.0]` is the top-left corner.
+    pub origin: [f32; 2],
+    /// Part of the texture shown, as `[u0, v0, u1, v1]`; swapping a pair
+    /// flips the sprite.
+    pub uv_rect: [f32; 4],
+    /// Linear RGBA, multiplying the texture.
+    pub color: [f32; 4],
+    pub texture: SpriteTextureId,
+    /// Higher layers draw over lower ones; within a layer, sprites draw in
+    /// the order they were queued.
+    pub layer: i32,
+}
+
+impl Sprite {
+    pub fn new(texture: SpriteTextureId, position: [f32; 2], size: [f32; 2]) -> Self {
+        Self {
+            position,
+            size,
+            scale: [1.0, 1.0],
+            rotation: 0.0,
+            origin: [0.0, 0.0],
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+            color: [1.0; 4],
+            texture,
+            layer: 0,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: [f32; 2]) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_origin(mut self, origin: [f32; 2]) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn with_uv_rect(mut self, uv_rect: [f32; 4]) -> Self {
+        self.uv_rect = uv_rect;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    /// The four corners, top-left, top-right, bottom-left, bottom-right.
+    fn vertices(&self) -> [SpriteVertex; 4] {
+        let (sin, cos) = self.rotation.sin_cos();
+        let [u0, v0, u1, v1] = self.uv_rect;
+        [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]].map(|[x, y]: [f32; 2]| {
+            let local = [
+                (x - self.origin[0]) * self.size[0] * self.scale[0],
+                (y - self.origin[1]) * self.size[1] * self.scale[1],
+            ];
+            // y points down, so this turns clockwise on screen.
+            SpriteVertex {
+                position: [
+                    self.position[0] + local[0] * cos - local[1] * sin,
+                    self.position[1] + local[0] * sin + local[1] * cos,
+                ],
+                uv: [u0 + (u1 - u0) * x, v0 + (v1 - v0) * y],
+                color: self.color,
+            }
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpriteVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+crate::impl_vertex!(SpriteVertex, Vertex {
+    position => 0: Float32x2,
+    uv => 1: Float32x2,
+    color => 2: Float32x4,
+});
+
+/// Screen-space 2D sprites, for overlays and simple 2D games.
+///
+/// Like `TextRenderer`, sprites are queued during the frame and drawn, then
+/// forgotten, by `render`, which runs after post-processing and beneath the
+/// text. Their corners are transformed on the CPU into one vertex buffer,
+/// and each run of consecutive sprites sharing a texture is one draw, so
+/// packing sprites into an atlas keeps the draw count down.
+pub struct SpriteRenderer {
+    sprites: Vec<Sprite>,
+    textures: Vec<(wgpu::BindGroup, [u32; 2])>,
+    texture_layout: wgpu::BindGroupLayout,
+    vertices: Vec<SpriteVertex>,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    // Quads the buffers hold before they have to grow.
+    capacity: usize,
+    batches: Vec<(SpriteTextureId, Range<u32>)>,
+    projection_buffer: wgpu::Buffer,
+    projection_bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    output_format: wgpu::TextureFormat,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl SpriteRenderer {
+    const INITIAL_CAPACITY: usize = 256;
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        output_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<Self> {
+        let projection_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("sprite_projection_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let projection_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sprite Projection Buffer"),
+            contents: bytemuck::cast_slice(&Self::projection(1, 1)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let projection_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &projection_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: projection_buffer.as_entire_binding(),
+            }],
+            label: Some("sprite_projection_bind_group"),
+        });
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("sprite_texture_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sprite Pipeline Layout"),
+            bind_group_layouts: &[&projection_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, output_format);
+
+        let (vertex_buffer, index_buffer) = Self::create_buffers(device, Self::INITIAL_CAPACITY);
+        let mut renderer = Self {
+            sprites: Vec::new(),
+            textures: Vec::new(),
+            texture_layout,
+            vertices: Vec::new(),
+            vertex_buffer,
+            index_buffer,
+            capacity: Self::INITIAL_CAPACITY,
+            batches: Vec::new(),
+            projection_buffer,
+            projection_bind_group,
+            pipeline_layout,
+            output_format,
+            pipeline,
+        };
+        let white = texture::Texture::from_color(device, queue, [255; 4], "sprite_white")?;
+        renderer.add_texture(device, &white);
+        Ok(renderer)
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        output_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader =
+            preprocess::builtin_module(device, "Sprite Shader", "sprite.wgsl", &ShaderDefs::new());
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sprite Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[SpriteVertex::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: output_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            // Mirrored sprites wind the other way, so nothing is culled.
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Compiles the pipeline again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(device, &self.pipeline_layout, self.output_format);
+    }
+
+    /// Maps pixels, y down from the top-left, onto a `width` by `height`
+    /// output.
+    fn projection(width: u32, height: u32) -> [[f32; 4]; 4] {
+        cgmath::ortho(
+            0.0,
+            width.max(1) as f32,
+            height.max(1) as f32,
+            0.0,
+            -1.0,
+            1.0,
+        )
+        .into()
+    }
+
+    fn create_buffers(device: &wgpu::Device, capacity: usize) -> (wgpu::Buffer, wgpu::Buffer) {
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sprite Vertex Buffer"),
+            size: (capacity * 4 * size_of::<SpriteVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // Fixed two triangles per quad, so only the vertices change.
+        let indices = (0..capacity as u32)
+            .flat_map(|quad| [0, 1, 2, 2, 1, 3].map(|i| quad * 4 + i))
+            .collect::<Vec<_>>();
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sprite Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        (vertex_buffer, index_buffer)
+    }
+
+    /// Makes `texture` available to sprites. Straight alpha is expected.
+    pub fn add_texture(
+        &mut self,
+        device: &wgpu::Device,
+        texture: &texture::Texture,
+    ) -> SpriteTextureId {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("sprite_texture_bind_group"),
+        });
+        let size = texture.texture.size();
+        self.textures.push((bind_group, [size.width, size.height]));
+        SpriteTextureId(self.textures.len() - 1)
+    }
+
+    /// Width and height of `texture` in texels, for turning atlas regions
+    /// into `Sprite::uv_rect`s.
+    pub fn texture_size(&self, texture: SpriteTextureId) -> Option<[u32; 2]> {
+        self.textures.get(texture.0).map(|(_, size)| *size)
+    }
+
+    /// Draws `sprite` on the next `render`.
+    pub fn queue(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite);
+    }
+
+    /// Sprites queued since the last `render`.
+    pub fn queued(&self) -> &[Sprite] {
+        &self.sprites
+    }
+
+    /// Draws and clears the queued sprites over `output`, which is `width`
+    /// by `height` pixels. Sprites with a texture that was never added are
+    /// dropped.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.batches.clear();
+        if self.sprites.is_empty() {
+            return;
+        }
+        let textures = self.textures.len();
+        self.sprites.retain(|sprite| sprite.texture.0 < textures);
+        // Stable, so queue order holds within a layer.
+        self.sprites.sort_by_key(|sprite| sprite.layer);
+        self.vertices.clear();
+        for (i, sprite) in self.sprites.drain(..).enumerate() {
+            self.vertices.extend(sprite.vertices());
+            let indices = i as u32 * 6..(i as u32 + 1) * 6;
+            match self.batches.last_mut() {
+                Some((texture, range)) if *texture == sprite.texture => range.end = indices.end,
+                _ => self.batches.push((sprite.texture, indices)),
+            }
+        }
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        let quads = self.vertices.len() / 4;
+        if quads > self.capacity {
+            self.capacity = quads.next_power_of_two();
+            (self.vertex_buffer, self.index_buffer) = Self::create_buffers(device, self.capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+        queue.write_buffer(
+            &self.projection_buffer,
+            0,
+            bytemuck::cast_slice(&Self::projection(width, height)),
+        );
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Sprite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.projection_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        for (texture, indices) in &self.batches {
+            pass.set_bind_group(1, &self.textures[texture.0].0, &[]);
+            pass.draw_indexed(indices.clone(), 0, 0..1);
+        }
+    }
+
+    /// Draw calls the last `render` made.
+    pub fn draw_calls(&self) -> usize {
+        self.batches.len()
+    }
+}
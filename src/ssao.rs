@@ -0,0 +1,570 @@
+use crate::animation::JointPalette;
+use crate::blur::{Blur, BlurKernel};
+use crate::camera::Camera;
+use crate::culling::DrawInstances;
+use crate::model;
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+pub const MAX_SAMPLES: u32 = 64;
+const NOISE_SIZE: u32 = 4;
+
+/// Tuning for the screen-space ambient occlusion pass.
+#[derive(Debug, Copy, Clone)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    /// View-space radius of the sampling hemisphere.
+    pub radius: f32,
+    /// Depth offset that keeps flat surfaces from occluding themselves.
+    pub bias: f32,
+    /// Kernel samples per pixel, at most `MAX_SAMPLES`.
+    pub sample_count: u32,
+    /// Exponent applied to the result; higher darkens creases further.
+    pub intensity: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            radius: 0.5,
+            bias: 0.025,
+            sample_count: 32,
+            intensity: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    proj: [[f32; 4]; 4],
+    inv_proj: [[f32; 4]; 4],
+    kernel: [[f32; 4]; MAX_SAMPLES as usize],
+    radius: f32,
+    bias: f32,
+    sample_count: u32,
+    intensity: f32,
+    reverse_z: u32,
+    _padding: [u32; 3],
+}
+
+// Small xorshift generator; the kernel and noise only need to look random
+// and stay the same between runs.
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+/// Hemisphere samples along +z, denser towards the origin.
+fn create_kernel() -> [[f32; 4]; MAX_SAMPLES as usize] {
+    use cgmath::InnerSpace;
+
+    let mut rng = Rng(0x9e37_79b9);
+    std::array::from_fn(|i| {
+        let sample =
+            cgmath::Vector3::new(rng.next() * 2.0 - 1.0, rng.next() * 2.0 - 1.0, rng.next());
+        let sample = sample.normalize() * rng.next();
+        let t = i as f32 / MAX_SAMPLES as f32;
+        let sample = sample * (0.1 + 0.9 * t * t);
+        [sample.x, sample.y, sample.z, 0.0]
+    })
+}
+
+/// Depth/normal prepass, hemisphere occlusion and a blur, producing an AO
+/// texture the main pass reads as `occlusion()`.
+///
+/// The prepass fills the scene depth buffer, so the main pass loads it
+/// instead of clearing while SSAO is enabled.
+pub struct Ssao {
+    settings: SsaoSettings,
+    kernel: [[f32; 4]; MAX_SAMPLES as usize],
+    uniform_buffer: wgpu::Buffer,
+    camera_layout: wgpu::BindGroupLayout,
+    camera_bind_group: wgpu::BindGroup,
+    noise: texture::Texture,
+    normal: texture::Texture,
+    raw: texture::Texture,
+    scratch: texture::Texture,
+    occlusion: texture::Texture,
+    ssao_layout: wgpu::BindGroupLayout,
+    ssao_bind_group: wgpu::BindGroup,
+    shader_defs: ShaderDefs,
+    skinned: bool,
+    vertex_encoding: model::VertexEncoding,
+    reverse_z: bool,
+    prepass_pipeline: wgpu::RenderPipeline,
+    // `prepass_pipeline` for a reversed depth buffer.
+    reverse_z_prepass_pipeline: wgpu::RenderPipeline,
+    ssao_pipeline: wgpu::RenderPipeline,
+    blur: Blur,
+}
+
+impl Ssao {
+    const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    // Four channels where one would do, as `Blur` can't store to R8Unorm.
+    const OCCLUSION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    // Wide enough to smooth out the 4x4 noise tile.
+    const BLUR: BlurKernel = BlurKernel::Gaussian { sigma: 2.0 };
+
+    /// The prepass skins against `joints`, if it is for a skeleton, and
+    /// reads vertices in `vertex_encoding`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera_buffer: &wgpu::Buffer,
+        joints: &JointPalette,
+        vertex_encoding: model::VertexEncoding,
+        depth_texture: &texture::Texture,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let settings = SsaoSettings::default();
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Ssao Buffer"),
+            size: size_of::<SsaoUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut rng = Rng(0x2545_f491);
+        // Random rotations in the tangent plane, tiled across the screen.
+        let noise_image = image::RgbaImage::from_fn(NOISE_SIZE, NOISE_SIZE, |_, _| {
+            image::Rgba([
+                (rng.next() * 255.0) as u8,
+                (rng.next() * 255.0) as u8,
+                128,
+                255,
+            ])
+        });
+        let noise = texture::Texture::linear_from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(noise_image),
+            Some("Ssao::noise"),
+        )
+        .expect("noise texture is always valid");
+
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ssao::camera_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                JointPalette::layout_entry(1),
+            ],
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ssao::camera_bind_group"),
+            layout: &camera_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: joints.buffer().as_entire_binding(),
+                },
+            ],
+        });
+
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let unfilterable = wgpu::TextureSampleType::Float { filterable: false };
+        let ssao_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ssao::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1, unfilterable),
+                texture_entry(2, unfilterable),
+                texture_entry(3, unfilterable),
+            ],
+        });
+
+        let shader_defs = vertex_encoding.shader_defs(joints.shader_defs());
+        let skinned = joints.is_skinned();
+        let [prepass_pipeline, reverse_z_prepass_pipeline, ssao_pipeline] = Self::create_pipelines(
+            device,
+            &camera_layout,
+            &ssao_layout,
+            &shader_defs,
+            skinned,
+            vertex_encoding,
+        );
+        let blur = Blur::new(device, queue, Self::OCCLUSION_FORMAT, Self::BLUR);
+
+        let (normal, raw, scratch, occlusion) = Self::create_targets(device, width, height);
+        let ssao_bind_group = Self::create_bind_group(
+            device,
+            &ssao_layout,
+            &uniform_buffer,
+            depth_texture,
+            &normal,
+            &noise,
+        );
+
+        Self {
+            settings,
+            kernel: create_kernel(),
+            uniform_buffer,
+            camera_layout,
+            camera_bind_group,
+            noise,
+            normal,
+            raw,
+            scratch,
+            occlusion,
+            ssao_layout,
+            ssao_bind_group,
+            shader_defs,
+            skinned,
+            vertex_encoding,
+            reverse_z: false,
+            prepass_pipeline,
+            reverse_z_prepass_pipeline,
+            ssao_pipeline,
+            blur,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        ssao_layout: &wgpu::BindGroupLayout,
+        shader_defs: &ShaderDefs,
+        skinned: bool,
+        vertex_encoding: model::VertexEncoding,
+    ) -> [wgpu::RenderPipeline; 3] {
+        let shader = preprocess::builtin_module(device, "Ssao Shader", "ssao.wgsl", shader_defs);
+        // The prepasses test depth with `depth_compare`; the rest draw none.
+        let pipeline = |label,
+                        layout: &wgpu::BindGroupLayout,
+                        vertex,
+                        fragment,
+                        format,
+                        depth_compare: Option<wgpu::CompareFunction>| {
+            let depth = depth_compare.is_some();
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            let buffers = if depth {
+                model::vertex_buffers(skinned, vertex_encoding)
+            } else {
+                Vec::new()
+            };
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some(vertex),
+                    buffers: &buffers,
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(fragment),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: depth.then_some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: depth_compare.map(|depth_compare| wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        [
+            pipeline(
+                "Ssao Prepass Pipeline",
+                camera_layout,
+                "vs_prepass",
+                "fs_prepass",
+                Self::NORMAL_FORMAT,
+                Some(wgpu::CompareFunction::Less),
+            ),
+            pipeline(
+                "Ssao Reverse Z Prepass Pipeline",
+                camera_layout,
+                "vs_prepass",
+                "fs_prepass",
+                Self::NORMAL_FORMAT,
+                Some(wgpu::CompareFunction::Greater),
+            ),
+            pipeline(
+                "Ssao Pipeline",
+                ssao_layout,
+                "vs_fullscreen",
+                "fs_ssao",
+                Self::OCCLUSION_FORMAT,
+                None,
+            ),
+        ]
+    }
+
+    /// Compiles the pipelines again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        [
+            self.prepass_pipeline,
+            self.reverse_z_prepass_pipeline,
+            self.ssao_pipeline,
+        ] = Self::create_pipelines(
+            device,
+            &self.camera_layout,
+            &self.ssao_layout,
+            &self.shader_defs,
+            self.skinned,
+            self.vertex_encoding,
+        );
+        self.blur.rebuild_pipelines(device);
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (
+        texture::Texture,
+        texture::Texture,
+        texture::Texture,
+        texture::Texture,
+    ) {
+        let target = |format, label| {
+            texture::Texture::create_render_target(device, width, height, format, label)
+        };
+        let blur_target =
+            |label| Blur::create_target(device, width, height, Self::OCCLUSION_FORMAT, label);
+        (
+            target(Self::NORMAL_FORMAT, "Ssao::normal"),
+            target(Self::OCCLUSION_FORMAT, "Ssao::raw"),
+            blur_target("Ssao::scratch"),
+            blur_target("Ssao::occlusion"),
+        )
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        ssao_layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        depth_texture: &texture::Texture,
+        normal: &texture::Texture,
+        noise: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ssao::bind_group"),
+            layout: ssao_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&noise.view),
+                },
+            ],
+        })
+    }
+
+    /// Recreates the screen-sized targets; `depth_texture` must be the new
+    /// scene depth buffer.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        depth_texture: &texture::Texture,
+        width: u32,
+        height: u32,
+    ) {
+        (self.normal, self.raw, self.scratch, self.occlusion) =
+            Self::create_targets(device, width, height);
+        self.ssao_bind_group = Self::create_bind_group(
+            device,
+            &self.ssao_layout,
+            &self.uniform_buffer,
+            depth_texture,
+            &self.normal,
+            &self.noise,
+        );
+    }
+
+    pub fn settings(&self) -> SsaoSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: SsaoSettings) {
+        self.settings = settings;
+    }
+
+    /// Blurred ambient occlusion, 1.0 meaning unoccluded.
+    pub fn occlusion(&self) -> &texture::Texture {
+        &self.occlusion
+    }
+
+    /// Whether the main pass should keep the depth written by the prepass.
+    pub fn writes_depth(&self) -> bool {
+        self.settings.enabled
+    }
+
+    /// Clears and tests depth in the prepass as for a camera with
+    /// `reverse_z`.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera) {
+        use cgmath::SquareMatrix;
+
+        let proj = camera.build_projection_matrix(camera.znear, camera.zfar);
+        let inv_proj = proj.invert().unwrap_or_else(cgmath::Matrix4::identity);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[SsaoUniform {
+                proj: proj.into(),
+                inv_proj: inv_proj.into(),
+                kernel: self.kernel,
+                radius: self.settings.radius,
+                bias: self.settings.bias,
+                sample_count: self.settings.sample_count.min(MAX_SAMPLES),
+                intensity: self.settings.intensity,
+                reverse_z: camera.reverse_z as u32,
+                _padding: [0; 3],
+            }]),
+        );
+    }
+
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        instances: DrawInstances,
+        depth_texture: &texture::Texture,
+    ) {
+        let color_attachment = |view, clear| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })
+        };
+        if !self.settings.enabled {
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Ssao::clear"),
+                color_attachments: &[color_attachment(&self.occlusion.view, wgpu::Color::WHITE)],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            return;
+        }
+
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Ssao::prepass"),
+                color_attachments: &[color_attachment(&self.normal.view, wgpu::Color::BLACK)],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(texture::Texture::far_depth(self.reverse_z)),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            if !instances.is_empty() {
+                pass.set_pipeline(if self.reverse_z {
+                    &self.reverse_z_prepass_pipeline
+                } else {
+                    &self.prepass_pipeline
+                });
+                pass.set_bind_group(0, &self.camera_bind_group, &[]);
+                pass.set_vertex_buffer(1, instances.slice());
+                instances.draw_meshes(&mut pass, model);
+            }
+        }
+
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Ssao::ssao"),
+                color_attachments: &[color_attachment(&self.raw.view, wgpu::Color::WHITE)],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.ssao_pipeline);
+            pass.set_bind_group(0, &self.ssao_bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        let size = self.occlusion.texture.size();
+        self.blur.run(
+            device,
+            encoder,
+            &self.raw.view,
+            &self.scratch.view,
+            &self.occlusion.view,
+            [size.width, size.height],
+        );
+    }
+}
@@ -0,0 +1,142 @@
+#include "include/camera.wgsl"
+#include "include/instance.wgsl"
+#define SKIN_GROUP 0
+#define SKIN_BINDING 1
+#include "include/skinning.wgsl"
+#include "include/vertex.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct PrepassOutput {
+    @builtin(position) @invariant clip_position: vec4<f32>,
+    @location(0) view_normal: vec3<f32>,
+    // Dithered as the main pass does, so the depth it reuses has the same
+    // holes.
+    @location(1) @interpolate(flat) fade: f32,
+}
+
+// Depth + view-space normal prepass; the main pass reuses the depth.
+@vertex
+fn vs_prepass(
+    model: ModelVertexInput,
+    instance: InstanceInput,
+#ifdef SKINNED
+    skin: SkinInput,
+#endif
+) -> PrepassOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = instance_normal_matrix(instance);
+    let view_rotation = mat3x3<f32>(camera.view[0].xyz, camera.view[1].xyz, camera.view[2].xyz);
+
+    let attributes = model_attributes(model);
+    var position = attributes.position;
+    var normal = attributes.normal;
+#ifdef SKINNED
+    let skinning = skin_matrix(skin);
+    position = skin_position(skinning, position);
+    normal = skin_direction(skinning, normal);
+#endif
+
+    var out: PrepassOutput;
+    // Same operations as vs_main in shader.wgsl, so depth matches exactly.
+    let clip = camera.view_proj * (model_matrix * vec4<f32>(position, 1.0));
+    out.clip_position = vec4<f32>(clip.xy + camera.jitter * clip.w, clip.zw);
+    out.view_normal = view_rotation * normal_matrix * normal;
+    out.fade = instance_fade(instance);
+    return out;
+}
+
+@fragment
+fn fs_prepass(in: PrepassOutput) -> @location(0) vec4<f32> {
+    if lod_dithered(in.clip_position.xy, in.fade) {
+        discard;
+    }
+    return vec4<f32>(normalize(in.view_normal), 1.0);
+}
+
+const MAX_SAMPLES: u32 = 64u;
+
+struct SsaoUniform {
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    kernel: array<vec4<f32>, 64>,
+    radius: f32,
+    bias: f32,
+    sample_count: u32,
+    intensity: f32,
+    reverse_z: u32,
+}
+@group(0) @binding(0)
+var<uniform> ssao: SsaoUniform;
+@group(0) @binding(1)
+// Bound as a plain float texture: GL can't `textureLoad` depth textures.
+var t_depth: texture_2d<f32>;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var t_noise: texture_2d<f32>;
+
+struct FullscreenOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: FullscreenOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+fn view_position(pixel: vec2<i32>, size: vec2<f32>) -> vec3<f32> {
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let position = ssao.inv_proj * ndc;
+    return position.xyz / position.w;
+}
+
+// Hemisphere kernel sampling around the view-space normal.
+@fragment
+fn fs_ssao(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let dims = textureDimensions(t_depth);
+    let size = vec2<f32>(dims);
+    let pixel = vec2<i32>(in.clip_position.xy);
+    // `is_far_depth` reads `camera`, which isn't bound here.
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    if select(depth >= 1.0, depth <= 0.0, ssao.reverse_z != 0u) {
+        return vec4<f32>(1.0);
+    }
+
+    let origin = view_position(pixel, size);
+    let normal = normalize(textureLoad(t_normal, pixel, 0).xyz);
+    let noise_size = vec2<i32>(textureDimensions(t_noise));
+    let random = textureLoad(t_noise, pixel % noise_size, 0).xyz * 2.0 - 1.0;
+    let tangent = normalize(random - normal * dot(random, normal));
+    let tbn = mat3x3<f32>(tangent, cross(normal, tangent), normal);
+
+    let count = min(ssao.sample_count, MAX_SAMPLES);
+    var occlusion = 0.0;
+    for (var i = 0u; i < count; i += 1u) {
+        let sample_position = origin + tbn * ssao.kernel[i].xyz * ssao.radius;
+        let clip = ssao.proj * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if any(uv < vec2<f32>(0.0)) || any(uv >= vec2<f32>(1.0)) {
+            continue;
+        }
+        let scene = view_position(vec2<i32>(uv * size), size);
+        // View space looks down -z, so nearer surfaces have larger z.
+        let range = smoothstep(0.0, 1.0, ssao.radius / max(abs(origin.z - scene.z), 1e-4));
+        occlusion += select(0.0, 1.0, scene.z >= sample_position.z + ssao.bias) * range;
+    }
+    let ao = 1.0 - occlusion / f32(max(count, 1u));
+    return vec4<f32>(pow(ao, ssao.intensity));
+}
@@ -0,0 +1,341 @@
+use wgpu::util::DeviceExt;
+
+use crate::camera::CameraUniform;
+use crate::hdr::HdrPipeline;
+use crate::post;
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+/// Tuning for the screen-space reflections.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SsrSettings {
+    pub enabled: bool,
+    /// How far a reflected ray travels before it falls back to the
+    /// environment, in world units.
+    pub max_distance: f32,
+    /// Steps along that distance; more find thinner things.
+    pub steps: u32,
+    /// How far behind a surface a ray can be and still count as hitting
+    /// it, in world units.
+    pub thickness: f32,
+    /// Surfaces rougher than this reflect only the environment, which
+    /// blurs its reflections to suit and a single ray can't.
+    pub max_roughness: f32,
+    /// Share of the screen's width, in from each edge, over which the
+    /// reflections fade into the environment's.
+    pub edge_fade: f32,
+}
+
+impl Default for SsrSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_distance: 20.0,
+            steps: 48,
+            thickness: 0.5,
+            max_roughness: 0.6,
+            edge_fade: 0.1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsrUniform {
+    enabled: u32,
+    steps: u32,
+    max_distance: f32,
+    thickness: f32,
+    max_roughness: f32,
+    edge_fade: f32,
+    _padding: [u32; 2],
+    // Copied from the camera by `store`, so left alone by `set_settings`.
+    eye: [f32; 4],
+}
+
+impl From<SsrSettings> for SsrUniform {
+    fn from(settings: SsrSettings) -> Self {
+        Self {
+            enabled: settings.enabled as u32,
+            steps: settings.steps.max(1),
+            max_distance: settings.max_distance,
+            thickness: settings.thickness,
+            max_roughness: settings.max_roughness,
+            edge_fade: settings.edge_fade.max(1e-4),
+            _padding: [0; 2],
+            eye: [0.0; 4],
+        }
+    }
+}
+
+/// What a camera bind group gives lighting.wgsl to reflect: a frame's
+/// color and world positions, and the settings to march them with.
+#[derive(Clone, Copy)]
+pub struct SsrBindings<'a> {
+    pub color: &'a wgpu::TextureView,
+    pub position: &'a wgpu::TextureView,
+    pub uniform: &'a wgpu::Buffer,
+}
+
+/// Reflections of what's on screen, found by marching each reflected ray
+/// through the previous frame, reprojected. Where a ray finds nothing, or
+/// leaves the screen, the environment map's reflection shows instead.
+///
+/// The main view keeps its lit opaque scene each frame with `store` for
+/// the next to reflect; the other views bind `disabled` and reflect only
+/// the environment.
+pub struct ScreenSpaceReflections {
+    settings: SsrSettings,
+    uniform_buffer: wgpu::Buffer,
+    disabled_buffer: wgpu::Buffer,
+    color: texture::Texture,
+    position: texture::Texture,
+    empty: [texture::Texture; 2],
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ScreenSpaceReflections {
+    // Not Rgba32Float, which downlevel adapters can't render to.
+    const POSITION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const EYE_OFFSET: wgpu::BufferAddress = std::mem::offset_of!(SsrUniform, eye) as _;
+
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let settings = SsrSettings::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ssr::uniform_buffer"),
+            contents: bytemuck::bytes_of(&SsrUniform::from(settings)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let disabled_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ssr::disabled_buffer"),
+            contents: bytemuck::bytes_of(&SsrUniform::from(SsrSettings {
+                enabled: false,
+                ..settings
+            })),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let (color, position) = Self::create_targets(device, width, height);
+        let (empty_color, empty_position) = Self::create_targets(device, 1, 1);
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ssr::layout"),
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline = Self::create_pipeline(device, &layout);
+
+        Self {
+            settings,
+            uniform_buffer,
+            disabled_buffer,
+            color,
+            position,
+            empty: [empty_color, empty_position],
+            layout,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = post::create_shader(
+            device,
+            "Ssr::shader",
+            &preprocess::builtin_source("ssr.wgsl", &ShaderDefs::new()),
+        );
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ssr::pipeline_layout"),
+            bind_group_layouts: &[layout],
+            push_constant_ranges: &[],
+        });
+        let target = |format| {
+            Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+        };
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Ssr::pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_history"),
+                targets: &[target(HdrPipeline::FORMAT), target(Self::POSITION_FORMAT)],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Compiles the pipeline again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(device, &self.layout);
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (texture::Texture, texture::Texture) {
+        (
+            texture::Texture::create_render_target(
+                device,
+                width,
+                height,
+                HdrPipeline::FORMAT,
+                "Ssr::color",
+            ),
+            texture::Texture::create_render_target(
+                device,
+                width,
+                height,
+                Self::POSITION_FORMAT,
+                "Ssr::position",
+            ),
+        )
+    }
+
+    pub fn settings(&self) -> SsrSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: SsrSettings) {
+        self.settings = settings;
+        let uniform = SsrUniform::from(settings);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            &bytemuck::bytes_of(&uniform)[..Self::EYE_OFFSET as usize],
+        );
+    }
+
+    /// Recreates the history, which starts out reflecting nothing; rebind
+    /// the main camera group after.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.color, self.position) = Self::create_targets(device, width, height);
+    }
+
+    /// For the main view's camera group.
+    pub fn bindings(&self) -> SsrBindings<'_> {
+        SsrBindings {
+            color: &self.color.view,
+            position: &self.position.view,
+            uniform: &self.uniform_buffer,
+        }
+    }
+
+    /// For every other view's camera group.
+    pub fn disabled(&self) -> SsrBindings<'_> {
+        SsrBindings {
+            color: &self.empty[0].view,
+            position: &self.empty[1].view,
+            uniform: &self.disabled_buffer,
+        }
+    }
+
+    /// Keeps the main view's `color`, lit from `depth` as seen through
+    /// `camera_buffer` (a `CameraUniform` with `COPY_SRC`), for the next
+    /// frame to reflect.
+    pub fn store(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        color: &wgpu::TextureView,
+        depth: &texture::Texture,
+        camera_buffer: &wgpu::Buffer,
+    ) {
+        if !self.settings.enabled {
+            return;
+        }
+        // The HDR and depth targets are replaced on resize, so the bind
+        // group is built per frame like the post stack's.
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ssr::bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(color),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&depth.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let attachment = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })
+        };
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Ssr::store"),
+            color_attachments: &[
+                attachment(&self.color.view),
+                attachment(&self.position.view),
+            ],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+        // In the encoder rather than the queue, so the frame's own draws
+        // before this still see the last frame's eye.
+        encoder.copy_buffer_to_buffer(
+            camera_buffer,
+            std::mem::offset_of!(CameraUniform, view_position) as _,
+            &self.uniform_buffer,
+            Self::EYE_OFFSET,
+            Some(size_of::<[f32; 4]>() as _),
+        );
+    }
+}
@@ -0,0 +1,34 @@
+#include "include/camera.wgsl"
+
+// Keeps what the next frame's reflections march against: the lit scene,
+// and the world position behind each of its pixels.
+@group(0) @binding(0)
+var t_color: texture_2d<f32>;
+@group(0) @binding(1)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> camera: CameraUniform;
+
+struct HistoryOutput {
+    @location(0) color: vec4<f32>,
+    // Relative to the eye, which keeps half floats precise up close, with
+    // zero in w where there's only sky.
+    @location(1) position: vec4<f32>,
+}
+
+@fragment
+fn fs_history(in: VertexOutput) -> HistoryOutput {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    var out: HistoryOutput;
+    out.color = textureLoad(t_color, pixel, 0);
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    if is_far_depth(depth) {
+        out.position = vec4<f32>(0.0);
+        return out;
+    }
+    // Depth was rasterized with the TAA jitter, so take it back out.
+    let ndc_xy = vec2<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0) - camera.jitter;
+    let world = camera.inv_view_proj * vec4<f32>(ndc_xy, depth, 1.0);
+    out.position = vec4<f32>(world.xyz / world.w - camera.view_pos.xyz, 1.0);
+    return out;
+}
@@ -0,0 +1,88 @@
+use wgpu::util::StagingBelt;
+
+/// Per-frame buffer updates recorded as copies out of reused staging
+/// memory, rather than handed to `queue.write_buffer` one at a time.
+///
+/// `write_buffer` on the queue allocates fresh staging space for every
+/// call, which shows up as stalls once large buffers such as the instances
+/// are rewritten each frame. Here writes instead land in mapped chunks of a
+/// `StagingBelt`, and the copies out of them go into an encoder of their
+/// own. The frame submits that encoder, from `finish`, ahead of its render
+/// commands, then calls `recall` so the chunks can be mapped for reuse
+/// once the GPU is done with them.
+///
+/// Writes through here are ordered after every `queue.write_buffer` of the
+/// same submission, so a buffer should be written through one or the
+/// other.
+pub struct UploadBelt {
+    belt: StagingBelt,
+    encoder: Option<wgpu::CommandEncoder>,
+    // Bytes written since the last `finish`.
+    pending_bytes: u64,
+    // Bytes carried by the last `finish`.
+    frame_bytes: u64,
+}
+
+impl UploadBelt {
+    /// Writes bigger than this get a chunk of their own, which is kept for
+    /// reuse as well.
+    const CHUNK_SIZE: wgpu::BufferAddress = 1 << 20;
+
+    pub fn new() -> Self {
+        Self {
+            belt: StagingBelt::new(Self::CHUNK_SIZE),
+            encoder: None,
+            pending_bytes: 0,
+            frame_bytes: 0,
+        }
+    }
+
+    /// Copies `data` into `target` at `offset` when the frame is
+    /// submitted. Like `queue.write_buffer`, the size and offset must be
+    /// multiples of `wgpu::COPY_BUFFER_ALIGNMENT`.
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        target: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        data: &[u8],
+    ) {
+        let Some(size) = wgpu::BufferSize::new(data.len() as _) else {
+            return;
+        };
+        let encoder = self.encoder.get_or_insert_with(|| {
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Upload Encoder"),
+            })
+        });
+        self.belt
+            .write_buffer(encoder, target, offset, size, device)
+            .copy_from_slice(data);
+        self.pending_bytes += data.len() as u64;
+    }
+
+    /// Closes the staged writes, returning the commands that perform them,
+    /// if there were any. Submit them before anything reading the targets.
+    pub fn finish(&mut self) -> Option<wgpu::CommandBuffer> {
+        self.belt.finish();
+        self.frame_bytes = std::mem::take(&mut self.pending_bytes);
+        self.encoder.take().map(|encoder| encoder.finish())
+    }
+
+    /// Returns the chunks `finish` closed for reuse. Call after submitting
+    /// what it returned.
+    pub fn recall(&mut self) {
+        self.belt.recall();
+    }
+
+    /// Bytes uploaded by the last submitted frame.
+    pub fn frame_bytes(&self) -> u64 {
+        self.frame_bytes
+    }
+}
+
+impl Default for UploadBelt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
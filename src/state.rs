@@ -0,0 +1,5885 @@
+use cgmath::prelude::*;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use wgpu::util::DeviceExt;
+use winit::{
+    event::{MouseButton, MouseScrollDelta, TouchPhase, WindowEvent},
+    keyboard::{KeyCode, ModifiersState},
+    monitor::VideoModeHandle,
+    window::{Fullscreen, Window, WindowId},
+};
+
+use crate::adapter::AdapterOptions;
+use crate::animation::{AnimationClip, AnimationPlayer, JointPalette, Transform};
+use crate::assets::{AssetHandle, Assets};
+use crate::atlas::{TextureAtlas, TextureAtlasBuilder};
+use crate::auto_exposure::{AutoExposure, AutoExposureSettings};
+use crate::billboard::{BillboardRenderer, BillboardTextureId};
+use crate::bookmarks::{self, BookmarkTransition, CameraBookmarks};
+use crate::camera::{Camera, CameraController, CameraUniform, Projection};
+use crate::camera_effects::CameraEffects;
+use crate::capture::{self, FrameCapture};
+use crate::cluster::LightClusters;
+use crate::color;
+use crate::config::KeyBindings;
+use crate::culling::{self, DrawInstances, Frustum, SortedDraw};
+use crate::debug_draw::{DebugDraw, DebugShapes};
+use crate::debug_ui::{DebugOverlay, DebugUi};
+use crate::decal::{Decal, DecalTextureId, Decals};
+use crate::deferred::{Deferred, DeferredPipelines, RenderPath};
+use crate::ecs::{ActiveCamera, Entity, Material, Mesh, World};
+use crate::editor::{EditorSettings, Gizmo, GizmoDrag};
+use crate::environment::Environment;
+use crate::flythrough::{CameraKeyframe, CameraPath};
+use crate::frame_trace::{FrameTrace, Phase};
+use crate::gpu_culling::GpuCulling;
+use crate::grid::{Grid, GridSettings};
+use crate::hdr::{self, ColorView, HdrPipeline, Tonemap};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hot_reload::{self, AssetWatcher, ShaderWatcher};
+use crate::input::{self, Axis, GamepadState, Input, InputMap, TouchGestures};
+use crate::instance::{Instance, InstanceBuffer};
+use crate::light::{FogSettings, LightBuffer, LightUniform};
+use crate::lod::{LodInstances, LodSettings};
+use crate::material::{
+    MaterialKind, MaterialKindId, MaterialPass, MaterialRegistry, PipelineContext, PipelineKey,
+};
+use crate::material_arrays::MaterialArrays;
+use crate::model::DrawModel;
+use crate::msaa::Msaa;
+use crate::oit::Oit;
+use crate::outline::{Outline, OutlineSettings};
+use crate::parallel;
+use crate::picking::{self, IdPicker, PickHit, PickMode, Ray};
+use crate::pipeline_cache::PipelineCache;
+use crate::post::bloom::{Bloom, BloomSettings};
+use crate::post::color_grading::Lut;
+use crate::post::taa::{Taa, TaaSettings};
+use crate::post::{ColorGrading, DepthOfField, Fxaa, PostEffect, PostStack};
+use crate::preprocess::{self, PermutationCache, ShaderDefs, ShaderLibrary};
+use crate::probe::{self, ReflectionProbe, ReflectionProbeId, ReflectionProbes};
+use crate::profiler::GpuProfiler;
+use crate::render_target::{RenderTarget, RenderTargetId};
+use crate::replay::{InputEvent, InputRecording, RecordedFrame};
+#[cfg(feature = "physics")]
+use crate::rigid_body::Physics;
+use crate::scene::SceneGraph;
+use crate::secondary_window::{BlitMode, DebugBlit, DebugTexture, SecondaryWindow, WindowContent};
+use crate::shadow::{self, DirectionalLight, ShadowFilter, ShadowMap};
+use crate::sky::{Sky, SkySettings};
+use crate::skybox::Skybox;
+use crate::sprite::{Sprite, SpriteRenderer, SpriteTextureId};
+use crate::ssao::{Ssao, SsaoSettings};
+use crate::ssr::{ScreenSpaceReflections, SsrBindings, SsrSettings};
+use crate::staging::UploadBelt;
+use crate::static_bundles::{BundleKey, StaticBundles};
+use crate::terrain::{Heightmap, Splatting, Terrain, TerrainMaterial, TerrainSettings};
+use crate::text::{TextRenderer, TextSection};
+use crate::timing::{FixedTimestep, FrameTiming};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::video::VideoCapture;
+use crate::viewport::{DebugView, View, ViewportRect};
+use crate::volumetric::{VolumetricSettings, Volumetrics};
+use crate::water::{Water, WaterPass, WaterSettings, WaterTextures};
+use crate::{camera, compressed, debug_ui, light, model, resources, texture};
+
+// This will store the state of our game
+pub struct State {
+    output: Output,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    // What the device was created on, again for `recreate_device`.
+    adapter_options: AdapterOptions,
+    adapter_info: wgpu::AdapterInfo,
+    // Set once the device is lost, from wgpu's callbacks.
+    device_lost: Arc<AtomicBool>,
+    frame_trace: FrameTrace,
+    config: wgpu::SurfaceConfiguration,
+    is_surface_configured: bool,
+    materials: MaterialRegistry,
+    deferred: Option<Deferred>,
+    shaders: ShaderLibrary,
+    pipeline_cache: PipelineCache,
+    scene_defs: ShaderDefs,
+    scene_pipelines: PermutationCache<ScenePipelines>,
+    diffuse_material: model::Material,
+    sampler_settings: texture::SamplerSettings,
+    // Where the camera is now, where the simulation's newest step left it
+    // and where the step before did, and what the last update drew between
+    // the two steps. `camera` only differs from `stepped_camera` when
+    // changed from outside the simulation, which is not interpolated.
+    camera: Camera,
+    stepped_camera: Camera,
+    previous_camera: Camera,
+    view_camera: Camera,
+    timestep: FixedTimestep,
+    camera_path: CameraPath,
+    // Seconds into `camera_path` while flying it.
+    flythrough: Option<f32>,
+    camera_bookmarks: CameraBookmarks,
+    // A bookmark being eased to, in place of the controller.
+    bookmark_transition: Option<BookmarkTransition>,
+    camera_uniform: CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group: wgpu::BindGroup,
+    camera_controller: CameraController,
+    // Over `view_camera`, for drawing only.
+    camera_effects: CameraEffects,
+    // What `sync_world` last built from the world's `Mesh` entities, and
+    // those of them that cast shadows.
+    instances: InstanceBuffer,
+    shadow_casters: InstanceBuffer,
+    // Places the entities attached to its nodes each update.
+    scene: SceneGraph,
+    world: World,
+    // `world.tick()` as of the last `sync_world`, and as of the camera
+    // last being taken from or written back to the active camera entity.
+    world_tick: u64,
+    camera_tick: u64,
+    // The entity behind each instance, and the other way around.
+    instance_entities: Vec<Entity>,
+    instance_slots: HashMap<Entity, InstanceSlot>,
+    // Last cursor position over the window, in physical pixels.
+    cursor: Option<[f32; 2]>,
+    modifiers: ModifiersState,
+    key_bindings: KeyBindings,
+    input_map: InputMap,
+    touches: TouchGestures,
+    // The session so far and the events since its last frame, while
+    // recording.
+    recording: Option<(InputRecording, Vec<InputEvent>)>,
+    // What is being replayed and the next frame of it.
+    replay: Option<(InputRecording, usize)>,
+    exit_requested: bool,
+    // What `toggle_fullscreen` switches to from windowed.
+    fullscreen: Fullscreen,
+    selection: Option<PickHit>,
+    pick_mode: PickMode,
+    editor: EditorSettings,
+    // The gizmo handle the left button is holding.
+    gizmo_drag: Option<GizmoDrag>,
+    id_picker: IdPicker,
+    outline: Outline,
+    // Where the next frame's ID pass reads, for `PickMode::IdBuffer`.
+    pick_request: Option<[f32; 2]>,
+    gpu_culling: GpuCulling,
+    cpu_culling: bool,
+    lods: LodInstances,
+    parallel_encoding: bool,
+    static_bundles: bool,
+    bundle_cache: StaticBundles,
+    texture_arrays: bool,
+    texture_array_layout: wgpu::BindGroupLayout,
+    // Packed while `texture_arrays` is on, and again as the materials change.
+    material_arrays: Option<MaterialArrays>,
+    // Per mesh of `obj_model`, whether any instance of it is in view.
+    visible_meshes: Vec<bool>,
+    // Rebuilt every update, as the camera and instances move.
+    transparent_draws: Vec<SortedDraw>,
+    depth_texture: texture::Texture,
+    // What the forward paths draw the opaque scene and sky into while
+    // MSAA is on.
+    msaa: Option<Msaa>,
+    ssao: Ssao,
+    ssr: ScreenSpaceReflections,
+    hdr: HdrPipeline,
+    taa: Taa,
+    oit: Oit,
+    billboards: BillboardRenderer,
+    decals: Decals,
+    debug_draw: DebugDraw,
+    debug_shapes: DebugShapes,
+    grid: Grid,
+    bloom: Bloom,
+    auto_exposure: AutoExposure,
+    volumetrics: Volumetrics,
+    post: PostStack,
+    obj_model: model::Model,
+    // The files under `res` the scene model and environment came from, if
+    // they came from one, and the options the model was loaded with.
+    model_file: Option<String>,
+    model_import: model::ImportOptions,
+    environment_file: Option<String>,
+    // The LUT image under `res` the color grading was loaded from.
+    color_grading_file: Option<String>,
+    terrain: Option<Terrain>,
+    water: Option<Water>,
+    render_targets: Vec<RenderTarget>,
+    // Scene model materials showing a render target, as `(material, target)`.
+    screens: Vec<(usize, RenderTargetId)>,
+    // Each with a target at its rectangle's size and the bind group
+    // tonemapping it.
+    views: Vec<(View, RenderTarget, wgpu::BindGroup)>,
+    windows: Vec<SecondaryWindow>,
+    debug_view: Option<DebugView>,
+    assets: Assets,
+    // What `assets` finished during the last update.
+    loaded_assets: Vec<AssetHandle>,
+    // Built with the first window or debug view that needs it.
+    debug_blit: Option<DebugBlit>,
+    // What the terrain and water were built from, beyond what they keep
+    // themselves, for `recreate_device`.
+    terrain_splatting: Option<Splatting>,
+    water_textures: Option<WaterTextures>,
+    // Likewise the skybox's files and the images uploaded for sprites,
+    // billboards and decals, in the order their ids were handed out.
+    skybox_files: Option<SkyboxFiles>,
+    sprite_images: Vec<image::DynamicImage>,
+    billboard_images: Vec<image::DynamicImage>,
+    decal_images: Vec<(image::DynamicImage, Option<image::DynamicImage>)>,
+    animation: AnimationPlayer,
+    joint_palette: JointPalette,
+    last_update: Option<web_time::Instant>,
+    // Seconds every update steps by, in place of the time since the last.
+    fixed_frame_time: Option<f32>,
+    lights: LightBuffer,
+    clusters: LightClusters,
+    light_bind_group_layout: wgpu::BindGroupLayout,
+    directional_light: DirectionalLight,
+    shadow_map: ShadowMap,
+    environment: Environment,
+    reflection_probes: ReflectionProbes,
+    skybox: Skybox,
+    sky: Sky,
+    sprites: SpriteRenderer,
+    text: TextRenderer,
+    debug_ui: DebugOverlay,
+    timing: FrameTiming,
+    // Camera, instance and light updates, submitted ahead of each frame.
+    uploads: UploadBelt,
+    capture_request: Option<PathBuf>,
+    captures: Vec<(FrameCapture, PathBuf)>,
+    debugger_capture_request: bool,
+    // Whether `section` has a debug group open on the frame's encoder.
+    section_open: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    shader_watcher: Option<ShaderWatcher>,
+    #[cfg(not(target_arch = "wasm32"))]
+    asset_watcher: Option<AssetWatcher>,
+    #[cfg(not(target_arch = "wasm32"))]
+    video: Option<VideoCapture>,
+    #[cfg(feature = "physics")]
+    physics: Physics,
+}
+
+// What `State::load_skybox` or `load_skybox_equirectangular` loaded.
+enum SkyboxFiles {
+    Faces([String; 6]),
+    Equirectangular(String, u32),
+}
+
+// What `State::reload_shaders` rebuilds the pipelines of.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ShaderUser {
+    Scene,
+    Ssao,
+    Ssr,
+    Hdr,
+    Taa,
+    Bloom,
+    AutoExposure,
+    Volumetrics,
+    Post,
+    Oit,
+    Clusters,
+    Shadows,
+    ReflectionProbes,
+    Skybox,
+    Sprites,
+    Text,
+    GpuCulling,
+    IdPicker,
+    Outline,
+    Billboards,
+    Decals,
+    DebugDraw,
+    Grid,
+    Water,
+    Environment,
+    MaterialArrays,
+    DebugBlit,
+    Msaa,
+}
+
+// The files each `ShaderUser` compiles, not counting those they include.
+// `post::create_shader` puts post/fullscreen.wgsl in front of its source,
+// which the deferred lighting pass is compiled by too.
+#[cfg(not(target_arch = "wasm32"))]
+const SHADER_USERS: &[(&str, ShaderUser)] = &[
+    ("shader.wgsl", ShaderUser::Scene),
+    ("deferred.wgsl", ShaderUser::Scene),
+    ("post/fullscreen.wgsl", ShaderUser::Scene),
+    ("ssao.wgsl", ShaderUser::Ssao),
+    ("blur.wgsl", ShaderUser::Ssao),
+    ("ssr.wgsl", ShaderUser::Ssr),
+    ("post/fullscreen.wgsl", ShaderUser::Ssr),
+    ("hdr.wgsl", ShaderUser::Hdr),
+    ("post/taa.wgsl", ShaderUser::Taa),
+    ("post/fullscreen.wgsl", ShaderUser::Taa),
+    ("post/bloom.wgsl", ShaderUser::Bloom),
+    ("post/fullscreen.wgsl", ShaderUser::Bloom),
+    ("auto_exposure.wgsl", ShaderUser::AutoExposure),
+    ("volumetric.wgsl", ShaderUser::Volumetrics),
+    ("post/fullscreen.wgsl", ShaderUser::Volumetrics),
+    ("post/color_grading.wgsl", ShaderUser::Post),
+    ("post/depth_of_field.wgsl", ShaderUser::Post),
+    ("post/fxaa.wgsl", ShaderUser::Post),
+    ("post/vignette.wgsl", ShaderUser::Post),
+    ("post/fullscreen.wgsl", ShaderUser::Post),
+    ("blur.wgsl", ShaderUser::Post),
+    ("oit.wgsl", ShaderUser::Oit),
+    ("cluster.wgsl", ShaderUser::Clusters),
+    ("shadow.wgsl", ShaderUser::Shadows),
+    ("point_shadow.wgsl", ShaderUser::Shadows),
+    ("shadow_moments.wgsl", ShaderUser::Shadows),
+    ("blur.wgsl", ShaderUser::Shadows),
+    ("probe.wgsl", ShaderUser::ReflectionProbes),
+    ("skybox.wgsl", ShaderUser::Skybox),
+    ("sprite.wgsl", ShaderUser::Sprites),
+    ("text.wgsl", ShaderUser::Text),
+    ("gpu_culling.wgsl", ShaderUser::GpuCulling),
+    ("hiz.wgsl", ShaderUser::GpuCulling),
+    ("picking.wgsl", ShaderUser::IdPicker),
+    ("outline.wgsl", ShaderUser::Outline),
+    ("billboard.wgsl", ShaderUser::Billboards),
+    ("decal.wgsl", ShaderUser::Decals),
+    ("debug_draw.wgsl", ShaderUser::DebugDraw),
+    ("grid.wgsl", ShaderUser::Grid),
+    ("water.wgsl", ShaderUser::Water),
+    ("ibl.wgsl", ShaderUser::Environment),
+    ("material_arrays.wgsl", ShaderUser::MaterialArrays),
+    ("debug_blit.wgsl", ShaderUser::DebugBlit),
+    ("msaa.wgsl", ShaderUser::Msaa),
+    ("post/fullscreen.wgsl", ShaderUser::Msaa),
+];
+
+// Where an entity's instance lives in the instance buffers.
+struct InstanceSlot {
+    instance: usize,
+    shadow_caster: Option<usize>,
+}
+
+/// Pipelines compiled from one permutation of the scene shaders.
+#[derive(Clone)]
+struct ScenePipelines {
+    materials: HashMap<PipelineKey, wgpu::RenderPipeline>,
+    deferred: Option<DeferredPipelines>,
+}
+
+/// Where frames end up: a window's swapchain, or a texture kept around so
+/// headless runs can read it back.
+enum Output {
+    Window {
+        window: Arc<Window>,
+        surface: wgpu::Surface<'static>,
+        // What `set_present_mode` can pick from.
+        present_modes: Vec<wgpu::PresentMode>,
+        // Kept for the surfaces of `add_window`.
+        instance: wgpu::Instance,
+        adapter: wgpu::Adapter,
+    },
+    Offscreen {
+        texture: wgpu::Texture,
+    },
+}
+
+impl Output {
+    fn window(&self) -> Option<&Arc<Window>> {
+        match self {
+            Output::Window { window, .. } => Some(window),
+            Output::Offscreen { .. } => None,
+        }
+    }
+}
+
+// The sky gradient every state starts out with.
+fn default_environment(device: &wgpu::Device, queue: &wgpu::Queue) -> Environment {
+    Environment::gradient(
+        device,
+        queue,
+        [0.25, 0.45, 0.85],
+        [0.75, 0.8, 0.9],
+        [0.2, 0.18, 0.16],
+    )
+}
+
+fn create_offscreen_texture(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen Output"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: config.usage,
+        view_formats: &[],
+    })
+}
+
+async fn request_device(
+    adapter: &wgpu::Adapter,
+    adapter_options: &AdapterOptions,
+) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    // Lights live in storage buffers and are binned by a compute pass,
+    // culled instances are drawn indirectly and vertex shaders read joint
+    // palettes from storage.
+    let flags = adapter.get_downlevel_capabilities().flags;
+    if !flags.contains(
+        wgpu::DownlevelFlags::COMPUTE_SHADERS
+            | wgpu::DownlevelFlags::INDIRECT_EXECUTION
+            | wgpu::DownlevelFlags::VERTEX_STORAGE,
+    ) {
+        anyhow::bail!(
+            "{:?} has no compute shaders or storage buffers (WebGL2?); a WebGPU, Vulkan, \
+             Metal, DX12 or GL 4.3 adapter is required",
+            adapter.get_info().backend
+        );
+    }
+    // Timestamps only feed the stats overlay, compressed textures can be
+    // decoded instead, culled draws can go one mesh at a time and per-draw
+    // values can sit in uniform buffers, so take all four when offered.
+    let mut features = adapter.features()
+        & (GpuProfiler::FEATURES
+            | compressed::FEATURES
+            | wgpu::Features::MULTI_DRAW_INDIRECT_COUNT
+            | wgpu::Features::PUSH_CONSTANTS);
+    // GL emulates push constants with plain uniforms, and reads the types
+    // of those seen by both stages from the wrong shader.
+    if adapter.get_info().backend == wgpu::Backend::Gl {
+        features.remove(wgpu::Features::PUSH_CONSTANTS);
+    }
+    let max_push_constant_size = if features.contains(wgpu::Features::PUSH_CONSTANTS) {
+        adapter.limits().max_push_constant_size
+    } else {
+        0
+    };
+    Ok(adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            label: Some("wgpu_test"),
+            required_features: features,
+            experimental_features: wgpu::ExperimentalFeatures::disabled(),
+            required_limits: wgpu::Limits {
+                max_push_constant_size,
+                ..Default::default()
+            },
+            memory_hints: Default::default(),
+            trace: adapter_options.device_trace()?,
+        })
+        .await?)
+}
+
+/// A flag that is set once `device` is lost, whether the driver dropped it
+/// or an allocation ran out of memory.
+///
+/// Every call on a lost device fails, so errors raised after that are only
+/// warned about. Any others are logged as errors rather than panicking as
+/// they do without a handler; those raised during a frame are caught by
+/// its `FrameTrace` spans first.
+fn watch_device_loss(device: &wgpu::Device) -> Arc<AtomicBool> {
+    let lost = Arc::new(AtomicBool::new(false));
+    let flag = lost.clone();
+    device.set_device_lost_callback(move |reason, message| {
+        // Destroyed is the device being dropped on purpose.
+        if reason != wgpu::DeviceLostReason::Destroyed {
+            log::error!("Device lost: {}", message);
+            flag.store(true, Ordering::Release);
+        }
+    });
+    let flag = lost.clone();
+    device.on_uncaptured_error(Arc::new(move |error| match error {
+        wgpu::Error::OutOfMemory { .. } => {
+            log::error!("{}", error);
+            flag.store(true, Ordering::Release);
+        }
+        _ if flag.load(Ordering::Acquire) => log::warn!("On the lost device: {}", error),
+        _ => log::error!("wgpu error: {}", error),
+    }));
+    lost
+}
+
+const NUM_INSTANCES_PER_ROW: u32 = 10;
+
+impl State {
+    // We don't need this to be async right now,
+    // but we will in the next tutorial
+    pub async fn new(window: Arc<Window>) -> anyhow::Result<Self> {
+        Self::with_render_path(window, RenderPath::default()).await
+    }
+
+    pub async fn with_render_path(
+        window: Arc<Window>,
+        render_path: RenderPath,
+    ) -> anyhow::Result<Self> {
+        Self::with_adapter(window, render_path, AdapterOptions::default()).await
+    }
+
+    /// Renders to `window` on the backend and GPU `adapter_options` pick.
+    pub async fn with_adapter(
+        window: Arc<Window>,
+        render_path: RenderPath,
+        adapter_options: AdapterOptions,
+    ) -> anyhow::Result<Self> {
+        let size = window.inner_size();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: adapter_options.window_backends(),
+            ..Default::default()
+        });
+        // Prefers WebGPU and falls back to WebGL2 where the browser has no
+        // `navigator.gpu`.
+        #[cfg(target_arch = "wasm32")]
+        let instance = wgpu::util::new_instance_with_webgpu_detection(&wgpu::InstanceDescriptor {
+            backends: adapter_options.window_backends(),
+            ..Default::default()
+        })
+        .await;
+        let surface = instance.create_surface(window.clone())?;
+
+        let adapter = adapter_options
+            .request_adapter(&instance, Some(&surface))
+            .await?;
+
+        let (device, queue) = request_device(&adapter, &adapter_options).await?;
+        let surface_caps = surface.get_capabilities(&adapter);
+        // Canvases only offer linear formats, so those are drawn through
+        // an sRGB view.
+        let (surface_format, view_format) = color::surface_format(&surface_caps.formats);
+        log::info!(
+            "Surface format {:?}, drawn as {:?}",
+            surface_format,
+            view_format.unwrap_or(surface_format)
+        );
+
+        // Copying out of the swapchain is what `capture_frame` reads.
+        let surface_usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC);
+        let config = wgpu::SurfaceConfiguration {
+            usage: surface_usage,
+            format: surface_format,
+            width: size.width,
+            height: size.height,
+            present_mode: surface_caps.present_modes[0],
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: view_format.into_iter().collect(),
+            desired_maximum_frame_latency: 2,
+        };
+
+        let adapter_info = adapter.get_info();
+        Self::build(
+            device,
+            queue,
+            config,
+            Output::Window {
+                window,
+                surface,
+                present_modes: surface_caps.present_modes,
+                instance,
+                adapter,
+            },
+            render_path,
+            adapter_options,
+            adapter_info,
+        )
+        .await
+    }
+
+    /// A state with no window that renders `width` by `height` frames into
+    /// an offscreen texture; read them back with `render_to_image`.
+    pub async fn headless(
+        width: u32,
+        height: u32,
+        render_path: RenderPath,
+    ) -> anyhow::Result<Self> {
+        Self::headless_with_adapter(width, height, render_path, AdapterOptions::default()).await
+    }
+
+    /// `headless` on the backend and GPU `adapter_options` pick.
+    pub async fn headless_with_adapter(
+        width: u32,
+        height: u32,
+        render_path: RenderPath,
+        adapter_options: AdapterOptions,
+    ) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: adapter_options.headless_backends(),
+            ..Default::default()
+        });
+        let adapter = adapter_options.request_adapter(&instance, None).await?;
+        let (device, queue) = request_device(&adapter, &adapter_options).await?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        let texture = create_offscreen_texture(&device, &config);
+        let mut state = Self::build(
+            device,
+            queue,
+            config,
+            Output::Offscreen { texture },
+            render_path,
+            adapter_options,
+            adapter.get_info(),
+        )
+        .await?;
+        state.is_surface_configured = true;
+        Ok(state)
+    }
+
+    async fn build(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        output: Output,
+        render_path: RenderPath,
+        adapter_options: AdapterOptions,
+        adapter_info: wgpu::AdapterInfo,
+    ) -> anyhow::Result<Self> {
+        const SPACE_BETWEEN: f32 = 3.0;
+        let instances = (0..NUM_INSTANCES_PER_ROW)
+            .flat_map(|z| {
+                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
+                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
+                    let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
+
+                    let position = cgmath::Vector3 { x, y: 0.0, z };
+                    let rotation = if position.is_zero() {
+                        cgmath::Quaternion::from_axis_angle(
+                            cgmath::Vector3::unit_z(),
+                            cgmath::Deg(0.0),
+                        )
+                    } else {
+                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
+                    };
+                    Instance::new(position, rotation)
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut world = World::new();
+        for instance in instances {
+            let entity = world.spawn();
+            world.insert(entity, Mesh::default());
+            world.insert(entity, Transform::from(instance));
+        }
+        // Filled from the world by `sync_world`.
+        let instances = InstanceBuffer::new(&device, Vec::new());
+        let shadow_casters = InstanceBuffer::new(&device, Vec::new());
+        let gpu_culling = GpuCulling::new(&device, true);
+        let lods = LodInstances::new(&device);
+
+        let diffuse_bytes = include_bytes!("happy-tree.png");
+        let diffuse_texture =
+            texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "happy-tree.png").unwrap();
+        let texture_bind_group_layout = model::create_material_bind_group_layout(&device);
+        let diffuse_material = model::Material::new(
+            &device,
+            "diffuse_bind_group",
+            model::MaterialTextures::new(&device, &queue, diffuse_texture).unwrap(),
+            model::MaterialFactors::default(),
+            &texture_bind_group_layout,
+        );
+        let obj_model = resources::load_model(
+            "cube.obj",
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+            model::ImportOptions::default(),
+        )
+        .await
+        .unwrap();
+        let assets = Assets::new(&device, &queue, &texture_bind_group_layout)?;
+        let joint_palette = JointPalette::new(&device, obj_model.skeleton.as_ref());
+        let mut animation = AnimationPlayer::new();
+        if !obj_model.animations.is_empty() {
+            animation.play(0);
+        }
+
+        let camera = Camera {
+            eye: (0.0, 1.0, 2.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: config.width as f32 / config.height as f32,
+            projection: Projection::Perspective { fovy: 45.0 },
+            znear: 0.01,
+            zfar: 100000.0,
+            reverse_z: false,
+        };
+        let camera_entity = world.spawn();
+        world.insert(camera_entity, camera);
+        world.insert(camera_entity, ActiveCamera);
+        let mut camera_uniform = camera::CameraUniform::new();
+        camera_uniform.update_view_proj(&camera);
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            // Copied from by `ScreenSpaceReflections::store`.
+            usage: wgpu::BufferUsages::UNIFORM
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let cube_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        // The environment shares the camera group since every other group
+        // slot is taken.
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    cube_entry(1),
+                    cube_entry(2),
+                    sampler_entry(3),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    sampler_entry(5),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    JointPalette::layout_entry(9),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 11,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 12,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 13,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::CubeArray,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 14,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("camera_bind_group_layout"),
+            });
+        let environment = default_environment(&device, &queue);
+        let reflection_probes = ReflectionProbes::new(&device, &queue);
+
+        let camera_controller = CameraController::new(0.1);
+
+        let light_bind_group_layout = light::create_bind_group_layout(&device);
+        let lights = LightBuffer::new(&device, &light_bind_group_layout, 1.0);
+        let light = world.spawn();
+        world.insert(
+            light,
+            LightUniform::new([2.0, 2.0, 2.0], [1.0, 1.0, 1.0], 30.0),
+        );
+        let clusters = LightClusters::new(
+            &device,
+            &light_bind_group_layout,
+            render_path == RenderPath::ForwardPlus,
+        );
+
+        let directional_light = DirectionalLight::default();
+        let id_picker = IdPicker::new(&device, &joint_palette, obj_model.vertex_encoding);
+        let shadow_map = ShadowMap::new(
+            &device,
+            shadow::DEFAULT_MAX_POINT_SHADOWS,
+            &joint_palette,
+            obj_model.vertex_encoding,
+        );
+
+        let depth_texture =
+            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+        let ssao = Ssao::new(
+            &device,
+            &queue,
+            &camera_buffer,
+            &joint_palette,
+            obj_model.vertex_encoding,
+            &depth_texture,
+            config.width,
+            config.height,
+        );
+        let ssr = ScreenSpaceReflections::new(&device, config.width, config.height);
+        let camera_bind_group = create_camera_bind_group(
+            &device,
+            &camera_bind_group_layout,
+            &camera_buffer,
+            &environment,
+            &reflection_probes,
+            &ssao.occlusion().view,
+            ssr.bindings(),
+            &clusters,
+            &joint_palette,
+        );
+        let hdr = HdrPipeline::new(&device, &config);
+        let taa = Taa::new(
+            &device,
+            &camera_buffer,
+            hdr.texture(),
+            &depth_texture,
+            config.width,
+            config.height,
+        );
+        let oit = Oit::new(&device, hdr.format(), config.width, config.height);
+        let output_format = hdr::output_format(&config);
+        let mut post = PostStack::new(&device, output_format, config.width, config.height);
+        // Blurred before grading, like a lens would, and graded before
+        // FXAA, which finds edges by the final luma.
+        post.push(&device, DepthOfField::default());
+        post.push(&device, ColorGrading::default());
+        post.push(&device, Fxaa::default());
+        let bloom = Bloom::new(
+            &device,
+            hdr.view(),
+            hdr.format(),
+            config.width,
+            config.height,
+        );
+        let auto_exposure = AutoExposure::new(&device);
+        let volumetrics = Volumetrics::new(
+            &device,
+            &shadow_map.bind_group_layout,
+            config.width,
+            config.height,
+        );
+
+        let shaders = ShaderLibrary::new();
+        let pipeline_cache = PipelineCache::new();
+        let scene_defs = joint_palette.shader_defs().with_flag("ENABLE_SHADOWS");
+        let mut materials = MaterialRegistry::new(&texture_bind_group_layout);
+        let texture_array_layout = MaterialArrays::create_bind_group_layout(&device);
+        let material_pipelines = create_material_pipelines(
+            &materials,
+            &pipeline_keys(
+                &materials,
+                &obj_model,
+                &scene_defs,
+                render_path == RenderPath::Deferred,
+                false,
+            ),
+            &PipelineContext {
+                device: &device,
+                shaders: &shaders,
+                cache: &pipeline_cache,
+                scene_defs: &scene_defs,
+                scene_bind_group_layouts: [
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                    &shadow_map.bind_group_layout,
+                ],
+                texture_array_layout: &texture_array_layout,
+                color_format: hdr.format(),
+            },
+        )?;
+        materials.set_pipelines(material_pipelines.clone());
+        let billboards = BillboardRenderer::new(
+            &device,
+            &queue,
+            &shaders,
+            &camera_bind_group_layout,
+            hdr.format(),
+        )?;
+        let decals = Decals::new(&device, &queue, &shaders, &depth_texture, &camera_buffer)?;
+        let debug_draw =
+            DebugDraw::new(&device, &shaders, &camera_bind_group_layout, hdr.format())?;
+        let grid = Grid::new(&device, &shaders, &camera_bind_group_layout, hdr.format())?;
+
+        let deferred = if render_path == RenderPath::Deferred {
+            let lighting_source = shaders.preprocess("deferred.wgsl", &scene_defs)?;
+            Some(Deferred::new(
+                &device,
+                &lighting_source,
+                [
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                    &shadow_map.bind_group_layout,
+                ],
+                &depth_texture,
+                hdr.format(),
+                config.width,
+                config.height,
+            ))
+        } else {
+            None
+        };
+        let mut scene_pipelines = PermutationCache::new();
+        scene_pipelines.insert(
+            scene_defs.clone(),
+            ScenePipelines {
+                materials: material_pipelines,
+                deferred: deferred
+                    .as_ref()
+                    .map(|deferred| deferred.pipelines().clone()),
+            },
+        );
+
+        let skybox = Skybox::new(&device, environment.cube.clone(), hdr.format());
+
+        let sprites = SpriteRenderer::new(&device, &queue, output_format)?;
+        let outline = Outline::new(
+            &device,
+            &joint_palette,
+            obj_model.vertex_encoding,
+            output_format,
+            config.width,
+            config.height,
+        );
+        let text = TextRenderer::new(&device, output_format, TextRenderer::default_font());
+        let debug_ui =
+            DebugOverlay::new(&device, output.window().map(|w| w.as_ref()), output_format);
+        let timing = FrameTiming::new(GpuProfiler::new(&device, &queue));
+        // On by default while developing; the sources are only around then.
+        #[cfg(not(target_arch = "wasm32"))]
+        let shader_watcher = cfg!(debug_assertions)
+            .then(|| {
+                ShaderWatcher::new()
+                    .inspect_err(|e| log::debug!("Shader hot reloading is off: {}", e))
+                    .ok()
+            })
+            .flatten();
+        #[cfg(not(target_arch = "wasm32"))]
+        let asset_watcher = cfg!(debug_assertions)
+            .then(|| {
+                AssetWatcher::new()
+                    .inspect_err(|e| log::debug!("Asset hot reloading is off: {}", e))
+                    .ok()
+            })
+            .flatten();
+
+        let mut state = Self {
+            output,
+            adapter_options,
+            adapter_info,
+            device_lost: watch_device_loss(&device),
+            frame_trace: FrameTrace::new(&device),
+            device,
+            queue,
+            config,
+            is_surface_configured: false,
+            materials,
+            deferred,
+            shaders,
+            pipeline_cache,
+            scene_defs,
+            scene_pipelines,
+            diffuse_material,
+            sampler_settings: texture::SamplerSettings::default(),
+            camera,
+            stepped_camera: camera,
+            previous_camera: camera,
+            view_camera: camera,
+            timestep: FixedTimestep::default(),
+            camera_path: CameraPath::default(),
+            flythrough: None,
+            camera_bookmarks: CameraBookmarks::default(),
+            bookmark_transition: None,
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group_layout,
+            camera_bind_group,
+            camera_controller,
+            camera_effects: CameraEffects::new(),
+            instances,
+            shadow_casters,
+            scene: SceneGraph::new(),
+            camera_tick: world.tick(),
+            world,
+            world_tick: 0,
+            instance_entities: Vec::new(),
+            instance_slots: HashMap::new(),
+            cursor: None,
+            modifiers: ModifiersState::empty(),
+            key_bindings: KeyBindings::default(),
+            input_map: InputMap::default(),
+            touches: TouchGestures::default(),
+            recording: None,
+            replay: None,
+            exit_requested: false,
+            fullscreen: Fullscreen::Borderless(None),
+            selection: None,
+            pick_mode: PickMode::default(),
+            editor: EditorSettings::default(),
+            gizmo_drag: None,
+            id_picker,
+            outline,
+            pick_request: None,
+            gpu_culling,
+            cpu_culling: true,
+            parallel_encoding: false,
+            static_bundles: false,
+            bundle_cache: StaticBundles::new(),
+            texture_arrays: false,
+            texture_array_layout,
+            material_arrays: None,
+            lods,
+            visible_meshes: Vec::new(),
+            transparent_draws: Vec::new(),
+            depth_texture,
+            msaa: None,
+            ssao,
+            ssr,
+            hdr,
+            taa,
+            oit,
+            billboards,
+            decals,
+            debug_draw,
+            debug_shapes: DebugShapes::default(),
+            grid,
+            bloom,
+            auto_exposure,
+            volumetrics,
+            post,
+            obj_model,
+            model_file: Some("cube.obj".to_string()),
+            model_import: model::ImportOptions::default(),
+            environment_file: None,
+            color_grading_file: None,
+            terrain: None,
+            water: None,
+            render_targets: Vec::new(),
+            screens: Vec::new(),
+            views: Vec::new(),
+            windows: Vec::new(),
+            debug_view: None,
+            assets,
+            loaded_assets: Vec::new(),
+            debug_blit: None,
+            terrain_splatting: None,
+            water_textures: None,
+            skybox_files: None,
+            sprite_images: Vec::new(),
+            billboard_images: Vec::new(),
+            decal_images: Vec::new(),
+            animation,
+            joint_palette,
+            last_update: None,
+            fixed_frame_time: None,
+            lights,
+            clusters,
+            light_bind_group_layout,
+            directional_light,
+            shadow_map,
+            environment,
+            reflection_probes,
+            skybox,
+            sky: Sky::default(),
+            sprites,
+            text,
+            debug_ui,
+            timing,
+            uploads: UploadBelt::new(),
+            capture_request: None,
+            captures: Vec::new(),
+            debugger_capture_request: false,
+            section_open: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            shader_watcher,
+            #[cfg(not(target_arch = "wasm32"))]
+            asset_watcher,
+            #[cfg(not(target_arch = "wasm32"))]
+            video: None,
+            #[cfg(feature = "physics")]
+            physics: Physics::default(),
+        };
+        state.sync_world();
+        Ok(state)
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.config.width = width;
+            self.config.height = height;
+            match &mut self.output {
+                Output::Window { surface, .. } => surface.configure(&self.device, &self.config),
+                Output::Offscreen { texture } => {
+                    *texture = create_offscreen_texture(&self.device, &self.config);
+                }
+            }
+            self.is_surface_configured = true;
+            self.depth_texture =
+                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            if let Some(msaa) = &mut self.msaa {
+                msaa.resize(&self.device, width, height);
+            }
+            self.ssao
+                .resize(&self.device, &self.depth_texture, width, height);
+            if let Some(deferred) = &mut self.deferred {
+                deferred.resize(&self.device, &self.depth_texture, width, height);
+            }
+            self.decals
+                .resize(&self.device, &self.depth_texture, &self.camera_buffer);
+            self.outline.resize(&self.device, width, height);
+            self.ssr.resize(&self.device, width, height);
+            self.volumetrics.resize(&self.device, width, height);
+            self.camera_bind_group = create_camera_bind_group(
+                &self.device,
+                &self.camera_bind_group_layout,
+                &self.camera_buffer,
+                &self.environment,
+                &self.reflection_probes,
+                &self.ssao.occlusion().view,
+                self.ssr.bindings(),
+                &self.clusters,
+                &self.joint_palette,
+            );
+            self.hdr.resize(&self.device, width, height);
+            self.taa.resize(
+                &self.device,
+                &self.camera_buffer,
+                self.hdr.texture(),
+                &self.depth_texture,
+                width,
+                height,
+            );
+            self.oit.resize(&self.device, width, height);
+            if let Some(water) = &mut self.water {
+                water.resize(&self.device, &self.queue, width, height);
+                self.bind_water_cameras();
+            }
+            self.bind_render_targets();
+            self.fit_views(true);
+            self.fit_windows(true);
+            self.bloom
+                .resize(&self.device, self.hdr.view(), width, height);
+            self.post.resize(&self.device, width, height);
+        }
+    }
+
+    /// Which keys trigger the shortcuts `handle_key` knows.
+    pub fn key_bindings(&self) -> KeyBindings {
+        self.key_bindings
+    }
+
+    pub fn set_key_bindings(&mut self, key_bindings: KeyBindings) {
+        self.key_bindings = key_bindings;
+    }
+
+    /// Which inputs drive the camera controller's actions.
+    pub fn input_map(&self) -> &InputMap {
+        &self.input_map
+    }
+
+    /// Anything held under the old map is let go.
+    pub fn set_input_map(&mut self, input_map: InputMap) {
+        self.input_map = input_map;
+        self.camera_controller.release_all();
+    }
+
+    /// Whether the device has been lost, as after a driver reset, so that
+    /// nothing renders until `recreate_device`.
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Acquire)
+    }
+
+    /// The GPU and backend the device was created on.
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    /// Each frame's phases and the validation errors raised in them.
+    pub fn frame_trace(&self) -> &FrameTrace {
+        &self.frame_trace
+    }
+
+    /// Builds the state again on a new device, for the same window or
+    /// headless size, after `is_device_lost` or when the surface runs out
+    /// of memory.
+    ///
+    /// What the old state holds on the CPU carries over: the camera and
+    /// controller, instances, lights, world, scene graph, animation,
+    /// selection, terrain, water, render targets and the materials showing
+    /// them, views, other windows, scene defines, debug panels, the font,
+    /// the post effects, which are set up again on the new device, and the
+    /// settings with a getter here. The model, environment and skybox are
+    /// loaded again from the files they came from, keeping the kinds,
+    /// factors and samplers set on the model's materials, and sprite,
+    /// billboard and decal textures are uploaded again under the same ids.
+    /// Models, environments and skyboxes set directly rather than from a
+    /// file only live on the GPU and are back to how `new` leaves them;
+    /// set them again afterwards.
+    pub async fn recreate_device(self) -> anyhow::Result<Self> {
+        let render_path = self.render_path();
+        let adapter_options = self.adapter_options.clone();
+        let mut state = match &self.output {
+            Output::Window { window, .. } => {
+                Self::with_adapter(window.clone(), render_path, adapter_options).await?
+            }
+            Output::Offscreen { .. } => {
+                let (width, height) = self.size();
+                Self::headless_with_adapter(width, height, render_path, adapter_options).await?
+            }
+        };
+        let configured = self.is_surface_configured;
+        let (width, height) = self.size();
+        state.carry_over(self).await?;
+        // Only now that the old swapchain is gone, as some platforms allow
+        // one per window.
+        if configured {
+            state.resize(width, height);
+        }
+        Ok(state)
+    }
+
+    // Takes what `recreate_device` keeps from `old`, dropping the rest.
+    async fn carry_over(&mut self, mut old: State) -> anyhow::Result<()> {
+        // First, as a model rebuilds the passes drawing it.
+        if let Some(file_name) = &old.model_file
+            && (old.model_file != self.model_file || old.model_import != self.model_import)
+        {
+            self.load_model_with(file_name, old.model_import).await?;
+        }
+        if let Some(file_name) = &old.environment_file {
+            self.load_environment(file_name).await?;
+        }
+        match &old.skybox_files {
+            Some(SkyboxFiles::Faces(file_names)) => {
+                self.load_skybox(file_names.each_ref().map(String::as_str))
+                    .await?;
+            }
+            Some(SkyboxFiles::Equirectangular(file_name, face_size)) => {
+                self.load_skybox_equirectangular(file_name, *face_size)
+                    .await?;
+            }
+            None => {}
+        }
+        self.text.set_font(old.text.font().clone());
+        for image in &old.sprite_images {
+            self.add_sprite_texture(image)?;
+        }
+        for image in &old.billboard_images {
+            self.add_billboard_texture(image)?;
+        }
+        for (albedo, normal) in &old.decal_images {
+            self.add_decal_texture(albedo, normal.as_ref())?;
+        }
+        let msaa_samples = old.msaa_samples();
+        self.frame_trace.carry_over(old.frame_trace);
+        self.camera = old.camera;
+        self.stepped_camera = old.stepped_camera;
+        self.previous_camera = old.previous_camera;
+        self.view_camera = old.view_camera;
+        self.timestep = old.timestep;
+        self.fixed_frame_time = old.fixed_frame_time;
+        self.camera_path = old.camera_path;
+        self.flythrough = old.flythrough;
+        self.camera_bookmarks = old.camera_bookmarks;
+        self.bookmark_transition = old.bookmark_transition;
+        self.camera_controller = old.camera_controller;
+        self.camera_effects = old.camera_effects;
+        self.scene = old.scene;
+        self.world = old.world;
+        // Builds everything from the old world afresh.
+        self.world_tick = 0;
+        self.camera_tick = old.camera_tick;
+        self.cursor = old.cursor;
+        self.modifiers = old.modifiers;
+        self.key_bindings = old.key_bindings;
+        self.input_map = old.input_map;
+        self.recording = old.recording;
+        self.replay = old.replay;
+        self.fullscreen = old.fullscreen;
+        self.selection = old.selection;
+        self.pick_mode = old.pick_mode;
+        self.editor = old.editor;
+        self.cpu_culling = old.cpu_culling;
+        self.parallel_encoding = old.parallel_encoding;
+        self.static_bundles = old.static_bundles;
+        self.animation = old.animation;
+        self.directional_light = old.directional_light;
+        self.set_sky_settings(old.sky.settings());
+        self.debug_shapes = old.debug_shapes;
+        self.debug_ui.visible = old.debug_ui.visible;
+        self.debug_ui.stats_visible = old.debug_ui.stats_visible;
+        self.debug_ui.panels = old.debug_ui.panels;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.shader_watcher = old.shader_watcher;
+            self.asset_watcher = old.asset_watcher;
+            self.video = old.video;
+        }
+        #[cfg(feature = "physics")]
+        {
+            self.physics = old.physics;
+        }
+
+        self.set_ambient(old.lights.ambient());
+        self.set_fog_settings(old.lights.fog());
+        self.set_max_point_shadows(old.shadow_map.max_point_shadows());
+        self.set_shadow_filter(old.shadow_map.filter());
+        self.set_gpu_culling_enabled(old.gpu_culling.enabled());
+        self.set_occlusion_culling_enabled(old.gpu_culling.occlusion_enabled());
+        self.set_texture_arrays_enabled(old.texture_arrays)?;
+        self.set_msaa_samples(msaa_samples)?;
+        self.set_lod_settings(old.lods.settings());
+        self.set_sampler_settings(old.sampler_settings);
+        self.set_exposure(old.hdr.exposure());
+        self.set_tonemap(old.hdr.tonemap());
+        self.set_color_view(old.hdr.color_view());
+        self.set_taa_settings(old.taa.settings());
+        self.set_bloom_settings(old.bloom.settings());
+        self.set_auto_exposure_settings(old.auto_exposure.settings());
+        self.set_volumetric_settings(old.volumetrics.settings());
+        let lut = old
+            .post
+            .get::<ColorGrading>()
+            .and_then(|grading| grading.lut().cloned());
+        self.post.carry_over(&self.device, old.post);
+        // The LUT's texture is the one effect resource made outside `setup`.
+        if lut.is_some() {
+            self.set_color_grading_lut(lut);
+            self.color_grading_file = old.color_grading_file.clone();
+        }
+        self.set_ssao_settings(old.ssao.settings());
+        self.set_ssr_settings(old.ssr.settings());
+        self.set_outline_settings(old.outline.settings());
+        self.set_grid_settings(old.grid.settings());
+        self.set_scene_defines(old.scene_defs.clone());
+        self.set_reverse_z(old.scene_defs.contains("REVERSE_Z"));
+        if self.present_modes().contains(&old.config.present_mode) {
+            self.config.present_mode = old.config.present_mode;
+        }
+
+        if let (Some(terrain), Some(splatting)) = (&old.terrain, &old.terrain_splatting) {
+            self.set_terrain(terrain.heightmap().clone(), splatting, *terrain.settings())?;
+        }
+        if let (Some(water), Some(textures)) = (&old.water, &old.water_textures) {
+            self.set_water(textures, water.settings())?;
+        }
+        // After the terrain, which registers its material kind.
+        if old.model_file.is_some() {
+            for (index, material) in old.obj_model.materials.iter().enumerate() {
+                if material.kind != self.obj_model.materials[index].kind
+                    && let Err(e) = self.set_material_kind(index, material.kind)
+                {
+                    log::warn!("Unable to restore material {}'s kind: {:#}", index, e);
+                }
+                self.set_material_factors(index, *material.factors())?;
+                self.set_material_sampler(index, material.sampler_override())?;
+            }
+        }
+        for target in &old.render_targets {
+            self.add_render_target(target.camera, target.size())?;
+        }
+        for &(material, target) in &old.screens {
+            self.set_material_render_target(material, target)?;
+        }
+        for (view, ..) in &old.views {
+            self.add_view(*view)?;
+        }
+        for probe in old.reflection_probes.probes() {
+            self.reflection_probes.push(*probe)?;
+        }
+        self.capture_reflection_probes()?;
+        // The old surfaces go first, as some platforms allow one per window.
+        let windows: Vec<_> = old
+            .windows
+            .drain(..)
+            .map(|window| (window.window, window.content))
+            .collect();
+        for (window, content) in windows {
+            self.add_window(window, content)?;
+        }
+        self.set_debug_view(old.debug_view);
+        self.assets.reload(&old.assets);
+        self.sync_world();
+        Ok(())
+    }
+
+    /// How frames reach the window, which starts out as whatever the
+    /// surface prefers.
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    /// Which of `Fifo`, `Mailbox` and `Immediate` the surface supports, in
+    /// that order. `Fifo` waits for vertical sync; the others do not, and
+    /// `Immediate` may tear. Empty for headless states.
+    pub fn present_modes(&self) -> Vec<wgpu::PresentMode> {
+        let Output::Window { present_modes, .. } = &self.output else {
+            return Vec::new();
+        };
+        [
+            wgpu::PresentMode::Fifo,
+            wgpu::PresentMode::Mailbox,
+            wgpu::PresentMode::Immediate,
+        ]
+        .into_iter()
+        .filter(|mode| present_modes.contains(mode))
+        .collect()
+    }
+
+    /// Reconfigures the surface to present with `mode`, one of
+    /// `present_modes`.
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) -> anyhow::Result<()> {
+        if !self.present_modes().contains(&mode) {
+            anyhow::bail!("The surface does not support {:?}", mode);
+        }
+        self.config.present_mode = mode;
+        if let Output::Window { surface, .. } = &self.output
+            && self.is_surface_configured
+        {
+            surface.configure(&self.device, &self.config);
+        }
+        Ok(())
+    }
+
+    /// Switches to the mode after the current one in `present_modes`,
+    /// wrapping around; bound to V.
+    pub fn cycle_present_mode(&mut self) {
+        let modes = self.present_modes();
+        let Some(next) = modes
+            .iter()
+            .position(|&mode| mode == self.config.present_mode)
+            .map_or(modes.first(), |i| modes.get((i + 1) % modes.len()))
+            .copied()
+        else {
+            return;
+        };
+        match self.set_present_mode(next) {
+            Ok(()) => log::info!("Presenting with {:?}", next),
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    /// The window's fullscreen mode; None while it is windowed, and for
+    /// headless states.
+    pub fn fullscreen(&self) -> Option<Fullscreen> {
+        self.window()?.fullscreen()
+    }
+
+    /// Makes the window borderless fullscreen, exclusive fullscreen in one
+    /// of `video_modes`, or windowed with None. The surface and the
+    /// targets sized like it follow at the next render. Does nothing for
+    /// headless states.
+    pub fn set_fullscreen(&mut self, fullscreen: Option<Fullscreen>) {
+        let Some(window) = self.output.window() else {
+            return;
+        };
+        if let Some(fullscreen) = &fullscreen {
+            self.fullscreen = fullscreen.clone();
+        }
+        window.set_fullscreen(fullscreen);
+    }
+
+    /// Switches between windowed and the last fullscreen mode set, which
+    /// starts out borderless on the window's monitor; bound to Alt+Enter.
+    pub fn toggle_fullscreen(&mut self) {
+        let fullscreen = match self.fullscreen() {
+            Some(_) => None,
+            None => Some(self.fullscreen.clone()),
+        };
+        self.set_fullscreen(fullscreen);
+    }
+
+    /// The video modes of the monitor the window is on, for exclusive
+    /// fullscreen, largest and then fastest first. Empty for headless
+    /// states and where the platform lists none.
+    pub fn video_modes(&self) -> Vec<VideoModeHandle> {
+        let Some(monitor) = self.window().and_then(|window| window.current_monitor()) else {
+            return Vec::new();
+        };
+        let mut modes = monitor.video_modes().collect::<Vec<_>>();
+        modes.sort_by_key(|mode| {
+            let size = mode.size();
+            std::cmp::Reverse((
+                size.width * size.height,
+                mode.refresh_rate_millihertz(),
+                mode.bit_depth(),
+            ))
+        });
+        modes
+    }
+
+    /// None for headless states.
+    pub fn window(&self) -> Option<&Arc<Window>> {
+        self.output.window()
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.config.width, self.config.height)
+    }
+
+    pub fn render_path(&self) -> RenderPath {
+        if self.deferred.is_some() {
+            RenderPath::Deferred
+        } else if self.clusters.enabled() {
+            RenderPath::ForwardPlus
+        } else {
+            RenderPath::Forward
+        }
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    pub fn camera_controller(&self) -> &CameraController {
+        &self.camera_controller
+    }
+
+    pub fn camera_controller_mut(&mut self) -> &mut CameraController {
+        &mut self.camera_controller
+    }
+
+    /// Shake, recoil and field of view changes drawn over the camera.
+    pub fn camera_effects(&self) -> &CameraEffects {
+        &self.camera_effects
+    }
+
+    pub fn camera_effects_mut(&mut self) -> &mut CameraEffects {
+        &mut self.camera_effects
+    }
+
+    /// The instances drawn, one per `Mesh` entity with a `Transform`, as of
+    /// the last update.
+    pub fn instances(&self) -> &[Instance] {
+        self.instances.instances()
+    }
+
+    /// The entity the instance at `index` was built from.
+    pub fn instance_entity(&self, index: usize) -> Option<Entity> {
+        self.instance_entities.get(index).copied()
+    }
+
+    /// Spawns a `Mesh` entity at `instance`, drawn from the next update.
+    pub fn add_instance(&mut self, instance: Instance) -> Entity {
+        let entity = self.world.spawn();
+        self.world.insert(entity, Mesh::default());
+        self.world.insert(entity, Transform::from(instance));
+        entity
+    }
+
+    pub fn add_instances(&mut self, instances: impl IntoIterator<Item = Instance>) -> Vec<Entity> {
+        instances
+            .into_iter()
+            .map(|instance| self.add_instance(instance))
+            .collect()
+    }
+
+    /// Moves `entity`'s `Transform` to `instance`.
+    pub fn update_instance(&mut self, entity: Entity, instance: Instance) {
+        if let Some(transform) = self.world.get_mut::<Transform>(entity) {
+            *transform = Transform::from(instance);
+        }
+    }
+
+    /// Despawns every `Mesh` entity.
+    pub fn clear_instances(&mut self) {
+        let entities = self
+            .world
+            .query::<Mesh>()
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+        for entity in entities {
+            self.world.despawn(entity);
+        }
+    }
+
+    /// The nearest instance under `cursor`, in physical pixels from the
+    /// window's top-left, as drawn by the last update.
+    pub fn pick(&self, cursor: [f32; 2], mode: PickMode) -> Option<PickHit> {
+        let ray = self.cursor_ray(cursor)?;
+        let mut hit = picking::pick(&ray, &self.obj_model, self.instances.raw(), mode)?;
+        hit.entity = self.instance_entity(hit.instance);
+        Some(hit)
+    }
+
+    /// Reads back the instance under `cursor` from an ID pass in the next
+    /// render, making it the selection once it arrives. Replaces any
+    /// request not rendered yet.
+    pub fn request_pick(&mut self, cursor: [f32; 2]) {
+        self.pick_request = Some(cursor);
+    }
+
+    /// Whether a `request_pick` has yet to change the selection.
+    pub fn pick_pending(&self) -> bool {
+        self.pick_request.is_some() || self.id_picker.is_pending()
+    }
+
+    /// What the last left click picked.
+    pub fn selection(&self) -> Option<PickHit> {
+        self.selection
+    }
+
+    pub fn set_selection(&mut self, selection: Option<PickHit>) {
+        self.selection = selection;
+    }
+
+    /// How the selected instance is outlined.
+    pub fn outline_settings(&self) -> OutlineSettings {
+        self.outline.settings()
+    }
+
+    pub fn set_outline_settings(&mut self, settings: OutlineSettings) {
+        self.outline.set_settings(settings);
+    }
+
+    /// How clicks pick; `PickMode::Bounds` by default.
+    pub fn pick_mode(&self) -> PickMode {
+        self.pick_mode
+    }
+
+    pub fn set_pick_mode(&mut self, mode: PickMode) {
+        self.pick_mode = mode;
+    }
+
+    /// Whether the selection shows a gizmo to move it with; toggled with
+    /// Tab, and the gizmo switched with Shift+Tab.
+    pub fn editor_settings(&self) -> EditorSettings {
+        self.editor
+    }
+
+    pub fn set_editor_settings(&mut self, settings: EditorSettings) {
+        if settings != self.editor {
+            self.gizmo_drag = None;
+        }
+        self.editor = settings;
+    }
+
+    /// Whether a gizmo handle is being dragged, which keeps the cursor
+    /// from turning the camera.
+    pub fn is_dragging_gizmo(&self) -> bool {
+        self.gizmo_drag.is_some()
+    }
+
+    /// The selected instance, where it is now.
+    pub fn selected_instance(&self) -> Option<Instance> {
+        let selection = self.selection?;
+        self.instances.instances().get(selection.instance).copied()
+    }
+
+    /// Moves the selected entity's `Transform` to `instance`. Entities a
+    /// scene graph node places are moved back by the node when it next
+    /// changes.
+    pub fn update_selected_instance(&mut self, instance: Instance) {
+        if let Some(entity) = self.selection.and_then(|selection| selection.entity) {
+            self.update_instance(entity, instance);
+        }
+    }
+
+    /// The index among `model_materials` of the selected mesh's material.
+    pub fn selected_material(&self) -> Option<usize> {
+        let selection = self.selection?;
+        let mesh = self.obj_model.meshes.get(selection.mesh)?;
+        Some(mesh.material)
+    }
+
+    // The gizmo on the selected instance, while editing.
+    fn gizmo(&self) -> Option<Gizmo> {
+        if !self.editor.enabled {
+            return None;
+        }
+        let instance = self.selected_instance()?;
+        let center = cgmath::Point3::from_vec(instance.position);
+        let transform = cgmath::Matrix4::from(instance.to_raw().model);
+        let radius = self
+            .obj_model
+            .meshes
+            .iter()
+            .map(|mesh| mesh.bounds.transformed(&transform))
+            .map(|bounds| (bounds.center() - center).magnitude() + bounds.radius())
+            .fold(0.0, f32::max);
+        Some(Gizmo::new(
+            self.editor.mode,
+            center,
+            radius,
+            self.view_camera.eye,
+        ))
+    }
+
+    // The ray through `cursor` picking follows.
+    fn cursor_ray(&self, cursor: [f32; 2]) -> Option<Ray> {
+        Ray::from_screen(
+            &self.view_camera,
+            cursor,
+            [self.config.width, self.config.height],
+        )
+    }
+
+    /// Nodes that position entities hierarchically; attach one with
+    /// `SceneGraph::set_entity`.
+    pub fn scene(&self) -> &SceneGraph {
+        &self.scene
+    }
+
+    pub fn scene_mut(&mut self) -> &mut SceneGraph {
+        &mut self.scene
+    }
+
+    /// Entities the scene is built from, starting with `new`'s default
+    /// scene. Each entity with a `Mesh` and a `Transform` is an instance,
+    /// tinted by any `Material`; each with a `LightUniform` is a light; a
+    /// `Camera` marked `ActiveCamera` is the one rendered from and moved by
+    /// the controller; and a `Decal` with a `Transform` is pressed onto the
+    /// deferred path's G-buffer.
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    fn active_camera(&self) -> Option<(Entity, Camera)> {
+        self.world
+            .query2::<Camera, ActiveCamera>()
+            .map(|(entity, camera, _)| (entity, *camera))
+            .next()
+    }
+
+    /// Brings the instances, shadow casters and lights up to date with the
+    /// world. Entities that gained or lost a `Mesh` or `Transform`, or
+    /// started or stopped casting shadows, rebuild the instances, following
+    /// the selection to its entity's new one; otherwise only those whose
+    /// `Transform` or `Material` changed are written.
+    fn sync_world(&mut self) {
+        let tick = self.world_tick;
+        let rebuild = self.world.added_or_removed_since::<Mesh>(tick)
+            || self.world.added_or_removed_since::<Transform>(tick)
+            || self.world.changed::<Mesh>(tick).any(|(entity, mesh)| {
+                let slot = self.instance_slots.get(&entity);
+                slot.is_some_and(|slot| {
+                    slot.shadow_caster.is_some() != mesh.is_some_and(|mesh| mesh.cast_shadows)
+                })
+            });
+        if rebuild {
+            self.rebuild_instances();
+        } else {
+            for (entity, transform) in self.world.changed::<Transform>(tick) {
+                let (Some(slot), Some(transform)) = (self.instance_slots.get(&entity), transform)
+                else {
+                    continue;
+                };
+                let instance = Instance::from(*transform);
+                self.instances.set(slot.instance, instance);
+                if let Some(index) = slot.shadow_caster {
+                    self.shadow_casters.set(index, instance);
+                }
+            }
+            for (entity, material) in self.world.changed::<Material>(tick) {
+                if let Some(slot) = self.instance_slots.get(&entity) {
+                    let color = material.map_or([1.0; 4], |material| material.base_color);
+                    self.instances.set_color(slot.instance, color);
+                }
+            }
+        }
+        if self.world.changed_since::<LightUniform>(tick) {
+            self.lights.clear();
+            for (_, light) in self.world.query::<LightUniform>() {
+                self.lights.push(*light);
+            }
+        }
+        let decal_moved = || {
+            self.world
+                .changed::<Transform>(tick)
+                .any(|(entity, _)| self.world.has::<Decal>(entity))
+        };
+        if self.world.changed_since::<Decal>(tick) || decal_moved() {
+            let decals = self
+                .world
+                .query2::<Decal, Transform>()
+                .map(|(_, decal, transform)| (decal, transform));
+            self.decals.upload(&self.device, &self.queue, decals);
+        }
+        self.world_tick = self.world.tick();
+    }
+
+    // Every instance and shadow caster afresh, in the world's order.
+    fn rebuild_instances(&mut self) {
+        self.instances.clear();
+        self.shadow_casters.clear();
+        self.instance_entities.clear();
+        self.instance_slots.clear();
+        for (entity, mesh, transform) in self.world.query2::<Mesh, Transform>() {
+            let instance = Instance::from(*transform);
+            let index = self.instances.push(instance);
+            if let Some(material) = self.world.get::<Material>(entity) {
+                self.instances.set_color(index, material.base_color);
+            }
+            let shadow_caster = mesh
+                .cast_shadows
+                .then(|| self.shadow_casters.push(instance));
+            self.instance_entities.push(entity);
+            self.instance_slots.insert(
+                entity,
+                InstanceSlot {
+                    instance: index,
+                    shadow_caster,
+                },
+            );
+        }
+        if let Some(selection) = &mut self.selection {
+            match selection
+                .entity
+                .and_then(|entity| self.instance_slots.get(&entity))
+            {
+                Some(slot) => selection.instance = slot.instance,
+                None => self.selection = None,
+            }
+        }
+    }
+
+    pub fn gpu_culling_enabled(&self) -> bool {
+        self.gpu_culling.enabled()
+    }
+
+    /// Culls instances outside the view on the GPU before the SSAO prepass
+    /// and the scene pass draw them. On by default.
+    pub fn set_gpu_culling_enabled(&mut self, enabled: bool) {
+        self.gpu_culling.set_enabled(enabled);
+    }
+
+    pub fn occlusion_culling_enabled(&self) -> bool {
+        self.gpu_culling.occlusion_enabled()
+    }
+
+    /// Has GPU culling also skip instances hidden behind what the last
+    /// frame drew. Off by default, as what comes into view can show up a
+    /// frame late.
+    pub fn set_occlusion_culling_enabled(&mut self, enabled: bool) {
+        self.gpu_culling.set_occlusion_enabled(enabled);
+    }
+
+    pub fn cpu_culling_enabled(&self) -> bool {
+        self.cpu_culling
+    }
+
+    /// Skips the SSAO prepass and scene pass draws of meshes that no
+    /// instance places inside the view. On by default.
+    pub fn set_cpu_culling_enabled(&mut self, enabled: bool) {
+        self.cpu_culling = enabled;
+    }
+
+    pub fn parallel_encoding_enabled(&self) -> bool {
+        self.parallel_encoding
+    }
+
+    /// Encodes the forward path's opaque draws into render bundles across
+    /// worker threads, one per core, rather than all into the pass on this
+    /// one. Off by default, as bundles cost more than they save in light
+    /// scenes, and ignored on the web, which has no threads to spare.
+    pub fn set_parallel_encoding_enabled(&mut self, enabled: bool) {
+        self.parallel_encoding = enabled;
+    }
+
+    pub fn static_bundles_enabled(&self) -> bool {
+        self.static_bundles
+    }
+
+    /// Records the forward path's opaque draws into render bundles once
+    /// and replays them every frame until the draws, what they're drawn
+    /// with or the instances change, rather than encoding them anew. Off
+    /// by default, as with CPU culling on a moving camera re-records them
+    /// often; LODs, which move instances every update, bypass it.
+    pub fn set_static_bundles_enabled(&mut self, enabled: bool) {
+        self.static_bundles = enabled;
+        if !enabled {
+            self.bundle_cache.clear();
+        }
+    }
+
+    /// How many times `static_bundles_enabled` has recorded the bundles.
+    pub fn static_bundle_recordings(&self) -> u64 {
+        self.bundle_cache.recordings()
+    }
+
+    pub fn texture_arrays_enabled(&self) -> bool {
+        self.texture_arrays
+    }
+
+    /// Packs the scene model's material textures into `MaterialArrays`,
+    /// so batched GPU culling draws the opaque meshes of each material
+    /// kind in one multi-draw rather than one per material, building the
+    /// pipelines that read them first. Off by default, as packing
+    /// stretches every texture to the largest of its slot; it also drops
+    /// back to the per-material draws when the materials can't share
+    /// arrays, or the adapter can't batch.
+    pub fn set_texture_arrays_enabled(&mut self, enabled: bool) -> anyhow::Result<()> {
+        if enabled && !self.texture_arrays {
+            let keys = pipeline_keys(
+                &self.materials,
+                &self.obj_model,
+                &self.scene_defs,
+                self.deferred.is_some(),
+                true,
+            );
+            for key in keys.into_iter().filter(|key| key.texture_arrays) {
+                self.build_pipeline(key)?;
+            }
+        }
+        if !enabled {
+            self.material_arrays = None;
+        }
+        self.texture_arrays = enabled;
+        Ok(())
+    }
+
+    pub fn lod_settings(&self) -> LodSettings {
+        self.lods.settings()
+    }
+
+    /// Draws instances of the scene model that are small on screen, or far
+    /// away, with coarser meshes, in place of `GpuCulling` for the passes
+    /// it would cull. Off by default.
+    pub fn set_lod_settings(&mut self, settings: LodSettings) {
+        self.lods.set_settings(settings);
+    }
+
+    /// How many instances the last update put at each level of detail,
+    /// starting with the full meshes, those crossfading counted at both.
+    /// Empty while levels of detail are off.
+    pub fn lod_counts(&self) -> Vec<usize> {
+        if !self.lods.is_enabled() {
+            return Vec::new();
+        }
+        self.lods.ranges().iter().map(|range| range.len()).collect()
+    }
+
+    /// Clips of the scene model, which start with the first one playing.
+    pub fn animations(&self) -> &[AnimationClip] {
+        &self.obj_model.animations
+    }
+
+    pub fn animation_player(&self) -> &AnimationPlayer {
+        &self.animation
+    }
+
+    pub fn animation_player_mut(&mut self) -> &mut AnimationPlayer {
+        &mut self.animation
+    }
+
+    /// Moves the simulation on by one step: the camera controller, or the
+    /// flythrough or bookmark transition while one plays, and the animation
+    /// clock.
+    fn fixed_update(&mut self) {
+        self.previous_camera = self.camera;
+        match self.flythrough {
+            Some(time) => {
+                self.camera_controller.stop();
+                let time = time + self.timestep.step();
+                if let Some(keyframe) = self.camera_path.sample(time) {
+                    keyframe.apply(&mut self.camera);
+                }
+                let duration = self.camera_path.duration();
+                self.flythrough = if self.camera_path.looping {
+                    Some(time % duration.max(f32::EPSILON))
+                } else {
+                    (time < duration).then_some(time)
+                };
+            }
+            None => match &mut self.bookmark_transition {
+                Some(transition) => {
+                    self.camera_controller.stop();
+                    let (keyframe, running) = transition.advance(self.timestep.step());
+                    keyframe.apply(&mut self.camera);
+                    if !running {
+                        self.bookmark_transition = None;
+                    }
+                }
+                None => self.camera_controller.update_camera(&mut self.camera),
+            },
+        }
+        self.stepped_camera = self.camera;
+        if self.obj_model.skeleton.is_some() {
+            self.animation
+                .advance(self.timestep.step(), &self.obj_model.animations);
+        }
+        #[cfg(feature = "physics")]
+        self.physics.step(self.timestep.step());
+    }
+
+    /// Rigid bodies stepped with the fixed timestep, moving the entities
+    /// they are attached to.
+    #[cfg(feature = "physics")]
+    pub fn physics(&self) -> &Physics {
+        &self.physics
+    }
+
+    #[cfg(feature = "physics")]
+    pub fn physics_mut(&mut self) -> &mut Physics {
+        &mut self.physics
+    }
+
+    /// Attaches a rapier body with `collider` to `entity`, starting where
+    /// its `Transform` is; see `Physics::attach`.
+    #[cfg(feature = "physics")]
+    pub fn add_rigid_body(
+        &mut self,
+        entity: Entity,
+        body: impl Into<rapier3d::prelude::RigidBody>,
+        collider: impl Into<rapier3d::prelude::Collider>,
+    ) -> Option<rapier3d::prelude::RigidBodyHandle> {
+        self.physics.attach(&mut self.world, entity, body, collider)
+    }
+
+    /// Takes `entity`'s body away, leaving it where the body last put it.
+    #[cfg(feature = "physics")]
+    pub fn remove_rigid_body(&mut self, entity: Entity) {
+        self.physics.detach(&mut self.world, entity);
+    }
+
+    /// Uploads the pose the animation has left the skeleton in.
+    fn update_pose(&mut self) {
+        let Some(skeleton) = &self.obj_model.skeleton else {
+            return;
+        };
+        let pose = self.animation.pose(skeleton, &self.obj_model.animations);
+        self.joint_palette
+            .write(&self.queue, &skeleton.palette(&pose));
+    }
+
+    /// The lights, one per entity with a `LightUniform`, as of the last
+    /// update.
+    pub fn lights(&self) -> &[LightUniform] {
+        self.lights.lights()
+    }
+
+    /// Spawns an entity with `light`, shining from the next update.
+    pub fn add_light(&mut self, light: LightUniform) -> Entity {
+        let entity = self.world.spawn();
+        self.world.insert(entity, light);
+        entity
+    }
+
+    pub fn update_light(&mut self, entity: Entity, light: LightUniform) {
+        if let Some(component) = self.world.get_mut::<LightUniform>(entity) {
+            *component = light;
+        }
+    }
+
+    /// Takes `entity`'s light, leaving the entity.
+    pub fn remove_light(&mut self, entity: Entity) -> Option<LightUniform> {
+        self.world.remove::<LightUniform>(entity)
+    }
+
+    /// Takes the light from every entity with one.
+    pub fn clear_lights(&mut self) {
+        let entities = self
+            .world
+            .query::<LightUniform>()
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+        for entity in entities {
+            self.world.remove::<LightUniform>(entity);
+        }
+    }
+
+    /// Scales the image-based ambient lighting from the environment.
+    pub fn ambient(&self) -> f32 {
+        self.lights.ambient()
+    }
+
+    pub fn set_ambient(&mut self, ambient: f32) {
+        self.lights.set_ambient(ambient);
+    }
+
+    pub fn fog_settings(&self) -> FogSettings {
+        self.lights.fog()
+    }
+
+    pub fn set_fog_settings(&mut self, settings: FogSettings) {
+        self.lights.set_fog(settings);
+    }
+
+    pub fn directional_light(&self) -> &DirectionalLight {
+        &self.directional_light
+    }
+
+    pub fn set_directional_light(&mut self, light: DirectionalLight) {
+        self.directional_light = light;
+    }
+
+    pub fn sky_settings(&self) -> SkySettings {
+        self.sky.settings()
+    }
+
+    /// While enabled, the sky takes over the environment, the directional
+    /// light and the fog's color from the next update. Disabling it puts
+    /// back the default environment and sun, and leaves the fog as the sky
+    /// last tinted it.
+    pub fn set_sky_settings(&mut self, settings: SkySettings) {
+        let was_enabled = self.sky.settings().enabled;
+        self.sky.set_settings(settings);
+        if was_enabled && !settings.enabled {
+            self.set_environment(default_environment(&self.device, &self.queue));
+            let sun = DirectionalLight::default();
+            self.directional_light.direction = sun.direction;
+            self.directional_light.color = sun.color;
+            self.directional_light.intensity = sun.intensity;
+        }
+    }
+
+    // Moves the sky's time of day on and couples the light, the fog and,
+    // once the sun has moved far enough, the environment to it.
+    fn update_sky(&mut self, dt: f32) {
+        if !self.sky.settings().enabled {
+            return;
+        }
+        self.sky.advance(dt);
+        self.sky.apply_to_light(&mut self.directional_light);
+        let mut fog = self.lights.fog();
+        let color = self.sky.horizon_color();
+        if fog.color != color {
+            fog.color = color;
+            self.lights.set_fog(fog);
+        }
+        if self.sky.needs_bake() {
+            let cube = self.sky.bake(&self.device, &self.queue);
+            self.set_environment(Environment::new(&self.device, &self.queue, cube));
+        }
+    }
+
+    /// Only the first `max` point lights cast shadows.
+    pub fn set_max_point_shadows(&mut self, max: usize) {
+        self.shadow_map.set_max_point_shadows(&self.device, max);
+    }
+
+    pub fn shadow_filter(&self) -> ShadowFilter {
+        self.shadow_map.filter()
+    }
+
+    /// Filters the directional light's shadows with PCF or, prefiltered
+    /// with a `Blur`, as variance shadow maps.
+    pub fn set_shadow_filter(&mut self, filter: ShadowFilter) {
+        self.shadow_map
+            .set_filter(&self.device, &self.queue, filter);
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// Switches the image-based lighting and the skybox to `environment`.
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.set_skybox(Skybox::new(
+            &self.device,
+            environment.cube.clone(),
+            self.hdr.format(),
+        ));
+        self.environment = environment;
+        self.environment_file = None;
+        self.rebind_cameras();
+    }
+
+    /// Loads an equirectangular HDR image as the environment.
+    pub async fn load_environment(&mut self, file_name: &str) -> anyhow::Result<()> {
+        let environment = Environment::from_hdr(file_name, &self.device, &self.queue).await?;
+        self.set_environment(environment);
+        self.environment_file = Some(file_name.to_string());
+        Ok(())
+    }
+
+    /// The HDR image under `res` the environment was loaded from. None for
+    /// the built-in one and those passed to `set_environment`.
+    pub fn environment_file(&self) -> Option<&str> {
+        self.environment_file.as_deref()
+    }
+
+    // Rebuilds every camera bind group after the environment or joint
+    // palette they bind was replaced.
+    fn rebind_cameras(&mut self) {
+        self.camera_bind_group = create_camera_bind_group(
+            &self.device,
+            &self.camera_bind_group_layout,
+            &self.camera_buffer,
+            &self.environment,
+            &self.reflection_probes,
+            &self.ssao.occlusion().view,
+            self.ssr.bindings(),
+            &self.clusters,
+            &self.joint_palette,
+        );
+        self.bind_water_cameras();
+        self.bind_render_targets();
+        self.fit_views(true);
+        self.fit_windows(true);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.hdr.exposure()
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.hdr.set_exposure(&self.queue, exposure);
+    }
+
+    pub fn tonemap(&self) -> Tonemap {
+        self.hdr.tonemap()
+    }
+
+    pub fn set_tonemap(&mut self, tonemap: Tonemap) {
+        self.hdr.set_tonemap(&self.queue, tonemap);
+    }
+
+    pub fn color_view(&self) -> ColorView {
+        self.hdr.color_view()
+    }
+
+    /// Shows the frame without tonemapping, or with the sRGB encode undone
+    /// or doubled, to tell which conversion a look comes from.
+    pub fn set_color_view(&mut self, color_view: ColorView) {
+        self.hdr.set_color_view(&self.queue, color_view);
+    }
+
+    /// The format the final passes draw into: always sRGB unless the
+    /// surface offered no 8-bit format.
+    pub fn output_format(&self) -> wgpu::TextureFormat {
+        hdr::output_format(&self.config)
+    }
+
+    pub fn taa_settings(&self) -> TaaSettings {
+        self.taa.settings()
+    }
+
+    pub fn set_taa_settings(&mut self, settings: TaaSettings) {
+        self.taa.set_settings(settings);
+    }
+
+    pub fn bloom_settings(&self) -> BloomSettings {
+        self.bloom.settings()
+    }
+
+    pub fn set_bloom_settings(&mut self, settings: BloomSettings) {
+        self.bloom.set_settings(&self.queue, settings);
+    }
+
+    pub fn auto_exposure_settings(&self) -> AutoExposureSettings {
+        self.auto_exposure.settings()
+    }
+
+    /// Off by default, as it changes what every frame looks like with what
+    /// came before, which comparisons between frames don't want. Turning it
+    /// off goes back to `exposure` alone.
+    pub fn set_auto_exposure_settings(&mut self, settings: AutoExposureSettings) {
+        if !settings.enabled {
+            self.hdr.reset_adapted_exposure(&self.queue);
+        }
+        self.auto_exposure.set_settings(settings);
+    }
+
+    /// Grades the image through the LUT strip `file_name` under `res`, as
+    /// `Lut::from_strip` reads it, in place of any graded through before.
+    pub async fn load_color_grading_lut(&mut self, file_name: &str) -> anyhow::Result<()> {
+        let lut = Lut::from_bytes(&resources::load_binary(file_name).await?)?;
+        self.set_color_grading_lut(Some(lut));
+        self.color_grading_file = Some(file_name.to_string());
+        Ok(())
+    }
+
+    /// Grades the image through `lut`, or stops grading it for None.
+    pub fn set_color_grading_lut(&mut self, lut: Option<Lut>) {
+        if self.post.get::<ColorGrading>().is_none() {
+            self.post.push(&self.device, ColorGrading::default());
+        }
+        if let Some(grading) = self.post.get_mut::<ColorGrading>() {
+            grading.set_lut(&self.device, &self.queue, lut);
+        }
+        self.color_grading_file = None;
+    }
+
+    /// The LUT image under `res` the color grading was loaded from. None
+    /// without grading and for LUTs passed to `set_color_grading_lut`.
+    pub fn color_grading_file(&self) -> Option<&str> {
+        self.color_grading_file.as_deref()
+    }
+
+    /// Appends `effect` to the post-processing chain, which runs after
+    /// tonemapping.
+    pub fn add_post_effect(&mut self, effect: impl PostEffect) -> usize {
+        self.post.push(&self.device, effect)
+    }
+
+    pub fn remove_post_effect(&mut self, index: usize) -> Box<dyn PostEffect> {
+        self.post.remove(index)
+    }
+
+    pub fn post_effect_mut<T: PostEffect>(&mut self) -> Option<&mut T> {
+        self.post.get_mut::<T>()
+    }
+
+    pub fn ssr_settings(&self) -> SsrSettings {
+        self.ssr.settings()
+    }
+
+    pub fn set_ssr_settings(&mut self, settings: SsrSettings) {
+        self.ssr.set_settings(&self.queue, settings);
+    }
+
+    pub fn volumetric_settings(&self) -> VolumetricSettings {
+        self.volumetrics.settings()
+    }
+
+    pub fn set_volumetric_settings(&mut self, settings: VolumetricSettings) {
+        self.volumetrics.set_settings(settings);
+    }
+
+    pub fn ssao_settings(&self) -> SsaoSettings {
+        self.ssao.settings()
+    }
+
+    pub fn set_ssao_settings(&mut self, settings: SsaoSettings) {
+        self.ssao.set_settings(settings);
+    }
+
+    pub fn set_skybox(&mut self, mut skybox: Skybox) {
+        skybox.set_sample_count(&self.device, self.msaa_samples());
+        self.skybox = skybox;
+        self.skybox_files = None;
+    }
+
+    /// Replaces the skybox with six LDR faces in +X, -X, +Y, -Y, +Z, -Z order.
+    pub async fn load_skybox(&mut self, file_names: [&str; 6]) -> anyhow::Result<()> {
+        self.set_skybox(
+            Skybox::load_faces(file_names, &self.device, &self.queue, self.hdr.format()).await?,
+        );
+        self.skybox_files = Some(SkyboxFiles::Faces(file_names.map(str::to_string)));
+        Ok(())
+    }
+
+    /// Replaces the skybox with an equirectangular image such as an `.hdr`.
+    pub async fn load_skybox_equirectangular(
+        &mut self,
+        file_name: &str,
+        face_size: u32,
+    ) -> anyhow::Result<()> {
+        self.set_skybox(
+            Skybox::load_equirectangular(
+                file_name,
+                face_size,
+                &self.device,
+                &self.queue,
+                self.hdr.format(),
+            )
+            .await?,
+        );
+        self.skybox_files = Some(SkyboxFiles::Equirectangular(
+            file_name.to_string(),
+            face_size,
+        ));
+        Ok(())
+    }
+
+    /// Adds a section to the debug window, toggled with F1.
+    /// Draws `section` over the finished frame; queued sections last one
+    /// frame.
+    pub fn queue_text(&mut self, section: TextSection) {
+        self.text.queue(section);
+    }
+
+    /// Queues `section` with its position taken as an offset from where
+    /// `position` lands on screen. Nothing is drawn for points behind the
+    /// camera.
+    pub fn queue_world_text(&mut self, position: cgmath::Point3<f32>, mut section: TextSection) {
+        let view_proj = cgmath::Matrix4::from(self.camera_uniform.view_proj);
+        let clip = view_proj * position.to_homogeneous();
+        if clip.w <= 0.0 {
+            return;
+        }
+        let x = (clip.x / clip.w * 0.5 + 0.5) * self.config.width as f32;
+        let y = (0.5 - clip.y / clip.w * 0.5) * self.config.height as f32;
+        section.position = [section.position[0] + x, section.position[1] + y];
+        self.text.queue(section);
+    }
+
+    /// Draws `sprite` over the finished frame, beneath any text; queued
+    /// sprites last one frame.
+    pub fn queue_sprite(&mut self, sprite: Sprite) {
+        self.sprites.queue(sprite);
+    }
+
+    pub fn sprites(&self) -> &SpriteRenderer {
+        &self.sprites
+    }
+
+    pub fn sprites_mut(&mut self) -> &mut SpriteRenderer {
+        &mut self.sprites
+    }
+
+    /// Uploads `image`, a single sprite or an atlas, for sprites to use.
+    pub fn add_sprite_texture(
+        &mut self,
+        image: &image::DynamicImage,
+    ) -> anyhow::Result<SpriteTextureId> {
+        let texture =
+            texture::Texture::from_image(&self.device, &self.queue, image, Some("sprite_texture"))?;
+        self.sprite_images.push(image.clone());
+        Ok(self.sprites.add_texture(&self.device, &texture))
+    }
+
+    /// Packs and uploads the images added to `builder`.
+    pub fn build_texture_atlas(
+        &self,
+        builder: TextureAtlasBuilder,
+    ) -> anyhow::Result<TextureAtlas> {
+        builder.build(&self.device, &self.queue, "texture_atlas")
+    }
+
+    /// Loads a pre-baked atlas description and its image from `res`.
+    pub async fn load_texture_atlas(&self, file_name: &str) -> anyhow::Result<TextureAtlas> {
+        resources::load_texture_atlas(file_name, &self.device, &self.queue).await
+    }
+
+    /// Makes `atlas` available to sprites, which pick their part of it with
+    /// `TextureAtlas::uv_rect`.
+    pub fn add_sprite_atlas(&mut self, atlas: &TextureAtlas) -> SpriteTextureId {
+        self.sprite_images.push(atlas.image().clone());
+        self.sprites.add_texture(&self.device, atlas.texture())
+    }
+
+    pub fn text(&self) -> &TextRenderer {
+        &self.text
+    }
+
+    pub fn text_mut(&mut self) -> &mut TextRenderer {
+        &mut self.text
+    }
+
+    pub async fn load_font(&mut self, file_name: &str) -> anyhow::Result<()> {
+        let data = resources::load_binary(file_name).await?;
+        self.text.set_font(ab_glyph::FontArc::try_from_vec(data)?);
+        Ok(())
+    }
+
+    /// Saves the next rendered frame, overlays included, to `path` as a PNG.
+    /// The file is written in the background a frame or two later.
+    pub fn capture_frame(&mut self, path: impl Into<PathBuf>) {
+        self.capture_request = Some(path.into());
+    }
+
+    /// Writes out every frame from the next one on, stepping time by the
+    /// capture's fixed frame time instead of the clock, until
+    /// `stop_video_capture`. This takes precedence over a replay's frame
+    /// times, though its input is still fed in frame by frame.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_video_capture(&mut self, video: VideoCapture) {
+        self.stop_video_capture();
+        self.video = Some(video);
+    }
+
+    /// Blocks until the GPU has finished everything submitted so far.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn wait_for_gpu(&self) {
+        if let Err(e) = self.device.poll(wgpu::PollType::wait_indefinitely()) {
+            log::warn!("Unable to wait for the GPU: {}", e);
+        }
+    }
+
+    /// Seconds every update steps the simulation by, if fixed.
+    pub fn fixed_frame_time(&self) -> Option<f32> {
+        self.fixed_frame_time
+    }
+
+    /// Steps every update by `seconds` rather than the time since the last,
+    /// so that runs see the same frames however fast they render, as for a
+    /// benchmark. A video capture's own frame time comes first.
+    pub fn set_fixed_frame_time(&mut self, seconds: Option<f32>) {
+        self.fixed_frame_time = seconds;
+    }
+
+    /// Finishes the video, logging how it went.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn stop_video_capture(&mut self) {
+        if let Some(video) = self.video.take() {
+            match video.finish() {
+                Ok(frames) => log::info!("Wrote {} video frames", frames),
+                Err(e) => log::error!("Unable to finish video: {:#}", e),
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn is_capturing_video(&self) -> bool {
+        self.video.is_some()
+    }
+
+    /// Has an attached graphics debugger, such as RenderDoc, capture the
+    /// next rendered frame. Without one this does nothing.
+    pub fn request_debugger_capture(&mut self) {
+        self.debugger_capture_request = true;
+    }
+
+    /// Renders a frame and blocks until it has been read back. Only
+    /// headless states keep their output around to read.
+    pub fn render_to_image(&mut self) -> anyhow::Result<image::RgbaImage> {
+        let Output::Offscreen { texture } = &self.output else {
+            anyhow::bail!("render_to_image needs a headless State");
+        };
+        let texture = texture.clone();
+        self.render()?;
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        let capture = FrameCapture::new(&self.device, &mut encoder, &texture)?;
+        self.queue.submit(std::iter::once(encoder.finish()));
+        capture.wait(&self.device)
+    }
+
+    fn finish_captures(&mut self) {
+        for (capture, _) in &mut self.captures {
+            capture.map();
+        }
+        let _ = self.device.poll(wgpu::PollType::Poll);
+        let (ready, pending) = std::mem::take(&mut self.captures)
+            .into_iter()
+            .partition(|(capture, _)| capture.is_ready());
+        self.captures = pending;
+        for (capture, path) in ready {
+            match capture.into_image() {
+                Ok(image) => capture::save_png(image, path),
+                Err(e) => log::error!("Unable to capture {}: {:#}", path.display(), e),
+            }
+        }
+    }
+
+    pub fn add_debug_ui(&mut self, panel: impl DebugUi + 'static) {
+        self.debug_ui.panels.push(Box::new(panel));
+    }
+
+    pub fn debug_ui_visible(&self) -> bool {
+        self.debug_ui.visible
+    }
+
+    pub fn set_debug_ui_visible(&mut self, visible: bool) {
+        self.debug_ui.visible = visible;
+    }
+
+    pub fn frame_timing(&self) -> &FrameTiming {
+        &self.timing
+    }
+
+    pub fn frame_timing_mut(&mut self) -> &mut FrameTiming {
+        &mut self.timing
+    }
+
+    /// Keyframes for `play_camera_path` to fly through.
+    pub fn camera_path(&self) -> &CameraPath {
+        &self.camera_path
+    }
+
+    pub fn camera_path_mut(&mut self) -> &mut CameraPath {
+        &mut self.camera_path
+    }
+
+    /// Stops any flythrough of the old path.
+    pub fn set_camera_path(&mut self, camera_path: CameraPath) {
+        self.camera_path = camera_path;
+        self.flythrough = None;
+    }
+
+    /// Adds where the camera is now to the end of the camera path.
+    pub fn add_camera_keyframe(&mut self) {
+        self.camera_path
+            .keyframes
+            .push(CameraKeyframe::from_camera(&self.camera));
+    }
+
+    /// Flies the camera along the camera path from its first keyframe,
+    /// in place of the controller, until it ends, if it doesn't loop, or
+    /// `stop_camera_path`.
+    pub fn play_camera_path(&mut self) {
+        if let Some(keyframe) = self.camera_path.sample(0.0) {
+            keyframe.apply(&mut self.camera);
+            self.flythrough = Some(0.0);
+            self.bookmark_transition = None;
+        }
+    }
+
+    pub fn stop_camera_path(&mut self) {
+        self.flythrough = None;
+    }
+
+    pub fn is_playing_camera_path(&self) -> bool {
+        self.flythrough.is_some()
+    }
+
+    /// Camera poses saved to slots 1 to 9, to come back to with
+    /// `recall_camera_bookmark`.
+    pub fn camera_bookmarks(&self) -> &CameraBookmarks {
+        &self.camera_bookmarks
+    }
+
+    pub fn camera_bookmarks_mut(&mut self) -> &mut CameraBookmarks {
+        &mut self.camera_bookmarks
+    }
+
+    pub fn set_camera_bookmarks(&mut self, camera_bookmarks: CameraBookmarks) {
+        self.camera_bookmarks = camera_bookmarks;
+    }
+
+    /// Saves where the camera is now to `slot`, then, off the web, writes
+    /// every bookmark to `bookmarks::DEFAULT_PATH` so they last between
+    /// runs.
+    pub fn save_camera_bookmark(&mut self, slot: usize) {
+        self.camera_bookmarks
+            .set(slot, CameraKeyframe::from_camera(&self.camera));
+        log::info!("Saved camera bookmark {}", slot);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Err(e) = self.camera_bookmarks.save(bookmarks::DEFAULT_PATH) {
+            log::error!("Unable to save {}: {:#}", bookmarks::DEFAULT_PATH, e);
+        }
+    }
+
+    /// Moves the camera to the pose saved in `slot`, stopping any
+    /// flythrough: eased there over the bookmarks' `transition_seconds` if
+    /// `smooth`, or at once. False, and nothing done, if the slot is empty.
+    pub fn recall_camera_bookmark(&mut self, slot: usize, smooth: bool) -> bool {
+        let Some(keyframe) = self.camera_bookmarks.get(slot) else {
+            return false;
+        };
+        self.flythrough = None;
+        self.camera_controller.stop();
+        let seconds = self.camera_bookmarks.transition_seconds;
+        if smooth && seconds > 0.0 {
+            let from = CameraKeyframe::from_camera(&self.camera);
+            self.bookmark_transition = Some(BookmarkTransition::new(from, keyframe, seconds));
+        } else {
+            keyframe.apply(&mut self.camera);
+            self.bookmark_transition = None;
+        }
+        true
+    }
+
+    pub fn is_recalling_camera_bookmark(&self) -> bool {
+        self.bookmark_transition.is_some()
+    }
+
+    /// The fixed rate the camera controller and animations step at,
+    /// whatever the frame rate.
+    pub fn fixed_timestep(&self) -> &FixedTimestep {
+        &self.timestep
+    }
+
+    pub fn fixed_timestep_mut(&mut self) -> &mut FixedTimestep {
+        &mut self.timestep
+    }
+
+    pub fn stats_visible(&self) -> bool {
+        self.debug_ui.stats_visible
+    }
+
+    pub fn set_stats_visible(&mut self, visible: bool) {
+        self.debug_ui.stats_visible = visible;
+    }
+
+    /// Gives the debug UI first look at `event`; true if it was consumed.
+    pub(crate) fn handle_window_event(&mut self, event: &WindowEvent) -> bool {
+        if let WindowEvent::ModifiersChanged(modifiers) = event {
+            self.handle_input_event(InputEvent::Modifiers(modifiers.state()));
+        }
+        match self.output.window() {
+            Some(window) => self.debug_ui.on_window_event(window, event),
+            None => false,
+        }
+    }
+
+    fn run_debug_ui(&mut self) -> egui::FullOutput {
+        let input = self.debug_ui.take_input(
+            self.output.window().map(|w| w.as_ref()),
+            [self.config.width, self.config.height],
+        );
+        // Taken out so panels can borrow all of `self`; any added while
+        // running go after the existing ones.
+        let mut panels = std::mem::take(&mut self.debug_ui.panels);
+        let frame = self.debug_ui.context().run(input, |ctx| {
+            if self.debug_ui.stats_visible {
+                debug_ui::stats_overlay(ctx, &self.timing);
+            }
+            if self.debug_ui.visible {
+                egui::Window::new("Debug").show(ctx, |ui| {
+                    for panel in &mut panels {
+                        panel.ui(ui, self);
+                    }
+                });
+            }
+        });
+        panels.append(&mut self.debug_ui.panels);
+        self.debug_ui.panels = panels;
+        frame
+    }
+
+    /// Defines the forward, G-buffer and deferred lighting shaders are
+    /// compiled with.
+    pub fn scene_defines(&self) -> &ShaderDefs {
+        &self.scene_defs
+    }
+
+    /// Switches the scene shaders to the permutation for `defs`, compiling
+    /// it unless it was used before. Keeps the current one, logging why,
+    /// if the sources fail to preprocess.
+    ///
+    /// SKINNED follows the model, whose meshes either all have skin
+    /// buffers or none do, whatever `defs` says.
+    pub fn set_scene_defines(&mut self, mut defs: ShaderDefs) {
+        if self.joint_palette.is_skinned() {
+            defs.set("SKINNED", "");
+        } else {
+            defs.remove("SKINNED");
+        }
+        if defs == self.scene_defs {
+            return;
+        }
+        let pipelines = match self.scene_pipelines.get(&defs) {
+            Some(pipelines) => pipelines.clone(),
+            None => match self.build_scene_pipelines(&defs) {
+                Ok(pipelines) => {
+                    self.scene_pipelines.insert(defs.clone(), pipelines.clone());
+                    pipelines
+                }
+                Err(e) => {
+                    log::error!("Unable to build the scene shaders: {:#}", e);
+                    return;
+                }
+            },
+        };
+        self.use_scene_pipelines(pipelines);
+        self.scene_defs = defs;
+    }
+
+    pub fn shadows_enabled(&self) -> bool {
+        self.scene_defs.contains("ENABLE_SHADOWS")
+    }
+
+    /// Turning shadows off skips the shadow passes and compiles the scene
+    /// shaders without ENABLE_SHADOWS, so they never sample the maps.
+    pub fn set_shadows_enabled(&mut self, enabled: bool) {
+        let mut defs = self.scene_defs.clone();
+        if enabled {
+            defs.set("ENABLE_SHADOWS", "");
+        } else {
+            defs.remove("ENABLE_SHADOWS");
+        }
+        self.set_scene_defines(defs);
+    }
+
+    pub fn reverse_z(&self) -> bool {
+        self.scene_defs.contains("REVERSE_Z")
+    }
+
+    /// Reversed depth puts the near plane at 1 and the far plane at 0, so
+    /// float precision, densest near 0, goes to distant surfaces instead
+    /// of being spent right in front of the camera. Rebuilds the scene
+    /// pipelines with REVERSE_Z, which tests depth the other way round.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        let mut defs = self.scene_defs.clone();
+        if reverse_z {
+            defs.set("REVERSE_Z", "");
+        } else {
+            defs.remove("REVERSE_Z");
+        }
+        self.set_scene_defines(defs);
+        for camera in [
+            &mut self.camera,
+            &mut self.stepped_camera,
+            &mut self.previous_camera,
+            &mut self.view_camera,
+        ] {
+            camera.reverse_z = reverse_z;
+        }
+        self.billboards.set_reverse_z(reverse_z);
+        self.debug_draw.set_reverse_z(reverse_z);
+        self.grid.set_reverse_z(reverse_z);
+        self.ssao.set_reverse_z(reverse_z);
+        if let Some(water) = &mut self.water {
+            water.set_reverse_z(reverse_z);
+        }
+    }
+
+    /// Samples a pixel of the opaque scene and the sky: 1 without MSAA.
+    pub fn msaa_samples(&self) -> u32 {
+        if self.msaa.is_some() {
+            Msaa::SAMPLE_COUNT
+        } else {
+            1
+        }
+    }
+
+    /// Whether `set_msaa_samples` can turn MSAA on: on the forward paths,
+    /// off GL.
+    pub fn msaa_available(&self) -> bool {
+        // wgpu's GL backend creates sampled multisampled textures with the
+        // single-sampled target, leaving the depth `Msaa::resolve_depth`
+        // reads incomplete.
+        self.deferred.is_none() && self.adapter_info.backend != wgpu::Backend::Gl
+    }
+
+    /// Multisamples the opaque scene and the sky with `samples` samples a
+    /// pixel, 1 or `Msaa::SAMPLE_COUNT`, resolving them for the passes
+    /// after. Only the forward paths can, as the G-buffer holds one surface
+    /// a pixel, and not on GL. Rebuilds the scene pipelines, and leaves MSAA
+    /// as it was if they fail to build.
+    pub fn set_msaa_samples(&mut self, samples: u32) -> anyhow::Result<()> {
+        if samples == self.msaa_samples() {
+            return Ok(());
+        }
+        if samples != 1 && samples != Msaa::SAMPLE_COUNT {
+            anyhow::bail!(
+                "MSAA takes 1 or {} samples, not {}",
+                Msaa::SAMPLE_COUNT,
+                samples
+            );
+        }
+        if samples > 1 && self.deferred.is_some() {
+            anyhow::bail!("the deferred path has no MSAA");
+        }
+        if samples > 1 && !self.msaa_available() {
+            anyhow::bail!("MSAA can't resolve depth on GL");
+        }
+        let msaa = (samples > 1).then(|| {
+            Msaa::new(
+                &self.device,
+                self.hdr.format(),
+                self.config.width,
+                self.config.height,
+            )
+        });
+        let previous = std::mem::replace(&mut self.msaa, msaa);
+        let pipelines = match self.build_scene_pipelines(&self.scene_defs) {
+            Ok(pipelines) => pipelines,
+            Err(e) => {
+                self.msaa = previous;
+                return Err(e);
+            }
+        };
+        // Other permutations were built for the old sample count.
+        self.scene_pipelines.clear();
+        self.scene_pipelines
+            .insert(self.scene_defs.clone(), pipelines.clone());
+        self.use_scene_pipelines(pipelines);
+        self.skybox.set_sample_count(&self.device, samples);
+        Ok(())
+    }
+
+    /// The material kinds meshes can be drawn with; register more with
+    /// `MaterialRegistry::register` and switch materials to them with
+    /// `set_material_kind`.
+    pub fn materials(&self) -> &MaterialRegistry {
+        &self.materials
+    }
+
+    pub fn materials_mut(&mut self) -> &mut MaterialRegistry {
+        &mut self.materials
+    }
+
+    /// The layouts, modules and pipelines the material pipelines are
+    /// built from.
+    pub fn pipeline_cache(&self) -> &PipelineCache {
+        &self.pipeline_cache
+    }
+
+    /// Textures and models loading in the background, with placeholders
+    /// until they arrive. Handles stay valid across `recreate_device`,
+    /// which loads everything again.
+    pub fn assets(&self) -> &Assets {
+        &self.assets
+    }
+
+    pub fn assets_mut(&mut self) -> &mut Assets {
+        &mut self.assets
+    }
+
+    /// The loads that finished, or failed, during the last `update`, first
+    /// loads and hot reloads alike, for rebuilding whatever used them.
+    pub fn loaded_assets(&self) -> &[AssetHandle] {
+        &self.loaded_assets
+    }
+
+    /// The scene model's bounds around its origin.
+    pub fn model_bounds(&self) -> model::Bounds {
+        self.obj_model.bounds()
+    }
+
+    /// The scene model's materials, in the order its meshes refer to them.
+    pub fn model_materials(&self) -> &[model::Material] {
+        &self.obj_model.materials
+    }
+
+    /// Draws `model` at every instance in place of the scene model,
+    /// keeping the old one if the pipelines for its materials fail to
+    /// build. Its skeleton drives the joints and its first animation
+    /// plays. The selection and the materials showing render targets are
+    /// cleared, as they referred to the old model's meshes and materials.
+    pub fn set_model(&mut self, mut model: model::Model) -> anyhow::Result<()> {
+        for material in &mut model.materials {
+            material.set_sampler(&self.device, &self.queue, None, self.sampler_settings);
+        }
+        let joint_palette = JointPalette::new(&self.device, model.skeleton.as_ref());
+        let mut defs = self.scene_defs.clone();
+        if joint_palette.is_skinned() {
+            defs.set("SKINNED", "");
+        } else {
+            defs.remove("SKINNED");
+        }
+        let previous = std::mem::replace(&mut self.obj_model, model);
+        let pipelines = match self.build_scene_pipelines(&defs) {
+            Ok(pipelines) => pipelines,
+            Err(e) => {
+                self.obj_model = previous;
+                return Err(e);
+            }
+        };
+        // The other permutations were built for the old materials.
+        self.scene_pipelines.clear();
+        self.scene_pipelines.insert(defs.clone(), pipelines.clone());
+        self.use_scene_pipelines(pipelines);
+        self.scene_defs = defs;
+        self.joint_palette = joint_palette;
+        self.rebuild_model_passes();
+        self.rebind_cameras();
+        self.animation = AnimationPlayer::new();
+        if !self.obj_model.animations.is_empty() {
+            self.animation.play(0);
+        }
+        self.screens.clear();
+        self.selection = None;
+        self.model_file = None;
+        Ok(())
+    }
+
+    /// Loads an OBJ or glTF model from `res` as the scene model.
+    pub async fn load_model(&mut self, file_name: &str) -> anyhow::Result<()> {
+        self.load_model_with(file_name, model::ImportOptions::default())
+            .await
+    }
+
+    /// `load_model`, preparing the model's meshes as `options` asks.
+    pub async fn load_model_with(
+        &mut self,
+        file_name: &str,
+        options: model::ImportOptions,
+    ) -> anyhow::Result<()> {
+        let layout = self
+            .materials
+            .get(MaterialKindId::STANDARD)
+            .expect("registered by new")
+            .bind_group_layout();
+        let model =
+            resources::load_model(file_name, &self.device, &self.queue, layout, options).await?;
+        self.set_model(model)?;
+        self.model_file = Some(file_name.to_string());
+        self.model_import = options;
+        Ok(())
+    }
+
+    /// The options the scene model was loaded from `model_file` with.
+    pub fn model_import(&self) -> model::ImportOptions {
+        self.model_import
+    }
+
+    // The passes drawing the scene model outside its material pipelines
+    // skin against its palette and read its vertex layout, so each model
+    // gets its own, with the settings carried over.
+    fn rebuild_model_passes(&mut self) {
+        let joints = &self.joint_palette;
+        let vertex_encoding = self.obj_model.vertex_encoding;
+        let (width, height) = self.size();
+        self.id_picker = IdPicker::new(&self.device, joints, vertex_encoding);
+        let filter = self.shadow_map.filter();
+        self.shadow_map = ShadowMap::new(
+            &self.device,
+            self.shadow_map.max_point_shadows(),
+            joints,
+            vertex_encoding,
+        );
+        self.shadow_map
+            .set_filter(&self.device, &self.queue, filter);
+        let ssao_settings = self.ssao.settings();
+        self.ssao = Ssao::new(
+            &self.device,
+            &self.queue,
+            &self.camera_buffer,
+            joints,
+            vertex_encoding,
+            &self.depth_texture,
+            width,
+            height,
+        );
+        self.ssao.set_settings(ssao_settings);
+        let outline_settings = self.outline.settings();
+        self.outline = Outline::new(
+            &self.device,
+            joints,
+            vertex_encoding,
+            hdr::output_format(&self.config),
+            width,
+            height,
+        );
+        self.outline.set_settings(outline_settings);
+    }
+
+    /// The file under `res` the scene model was loaded from. None for
+    /// models passed to `set_model`.
+    pub fn model_file(&self) -> Option<&str> {
+        self.model_file.as_deref()
+    }
+
+    pub fn terrain(&self) -> Option<&Terrain> {
+        self.terrain.as_ref()
+    }
+
+    /// Replaces the terrain with one built from `heightmap`, registering
+    /// `TerrainMaterial` and compiling its pipeline the first time.
+    pub fn set_terrain(
+        &mut self,
+        heightmap: Heightmap,
+        splatting: &Splatting,
+        settings: TerrainSettings,
+    ) -> anyhow::Result<()> {
+        let material = match self.materials.find(TerrainMaterial::NAME) {
+            Some(material) => material,
+            None => self.materials.register(TerrainMaterial::new(&self.device)),
+        };
+        let layout = self
+            .materials
+            .get(material)
+            .map(|kind| kind.bind_group_layout())
+            .unwrap();
+        let terrain = Terrain::new(
+            &self.device,
+            &self.queue,
+            heightmap,
+            splatting,
+            settings,
+            material,
+            layout,
+        )?;
+        self.build_pipeline(terrain.pipeline_key())?;
+        self.terrain = Some(terrain);
+        self.terrain_splatting = Some(splatting.clone());
+        Ok(())
+    }
+
+    /// Builds terrain from a heightmap image, splatted by height and slope
+    /// with `Splatting::from_heightmap`.
+    pub async fn load_terrain(
+        &mut self,
+        file_name: &str,
+        settings: TerrainSettings,
+    ) -> anyhow::Result<()> {
+        let heightmap = Heightmap::load(file_name).await?;
+        let splatting = Splatting::from_heightmap(&heightmap, &settings);
+        self.set_terrain(heightmap, &splatting, settings)
+    }
+
+    pub fn remove_terrain(&mut self) -> Option<Terrain> {
+        self.terrain_splatting = None;
+        self.terrain.take()
+    }
+
+    pub fn billboards(&self) -> &BillboardRenderer {
+        &self.billboards
+    }
+
+    pub fn billboards_mut(&mut self) -> &mut BillboardRenderer {
+        &mut self.billboards
+    }
+
+    /// Lines drawn over the next frame, then cleared.
+    pub fn debug_draw(&self) -> &DebugDraw {
+        &self.debug_draw
+    }
+
+    pub fn debug_draw_mut(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    /// What the debug lines outline on their own each frame.
+    pub fn debug_shapes(&self) -> DebugShapes {
+        self.debug_shapes
+    }
+
+    pub fn set_debug_shapes(&mut self, shapes: DebugShapes) {
+        self.debug_shapes = shapes;
+    }
+
+    /// The ground grid, with the axis helper at the origin; toggled with G.
+    pub fn grid_settings(&self) -> GridSettings {
+        self.grid.settings()
+    }
+
+    pub fn set_grid_settings(&mut self, settings: GridSettings) {
+        self.grid.set_settings(&self.queue, settings);
+    }
+
+    fn draw_debug_shapes(&mut self) {
+        if self.grid.is_enabled() {
+            let length = self.grid.settings().cell_size;
+            self.debug_draw.axes(&cgmath::Matrix4::identity(), length);
+        }
+        if self.debug_shapes.bounds {
+            for raw in self.instances.raw() {
+                let transform = cgmath::Matrix4::from(raw.model);
+                for mesh in &self.obj_model.meshes {
+                    self.debug_draw
+                        .wire_aabb(&mesh.bounds.transformed(&transform), [0.0, 1.0, 0.0, 1.0]);
+                }
+            }
+        }
+        if self.debug_shapes.lights {
+            for light in self.lights.lights() {
+                let [r, g, b] = light.color;
+                self.debug_draw
+                    .wire_sphere(light.position.into(), 0.25, [r, g, b, 1.0]);
+            }
+        }
+        if let Some(gizmo) = self.gizmo() {
+            let highlighted = match &self.gizmo_drag {
+                Some(drag) => Some(drag.axis()),
+                None => self
+                    .cursor
+                    .and_then(|cursor| self.cursor_ray(cursor))
+                    .and_then(|ray| gizmo.handle(&ray)),
+            };
+            gizmo.draw(&mut self.debug_draw, highlighted);
+        }
+    }
+
+    /// Uploads `image` for billboards to use.
+    pub fn add_billboard_texture(
+        &mut self,
+        image: &image::DynamicImage,
+    ) -> anyhow::Result<BillboardTextureId> {
+        let texture = texture::Texture::from_image(
+            &self.device,
+            &self.queue,
+            image,
+            Some("billboard_texture"),
+        )?;
+        self.billboard_images.push(image.clone());
+        Ok(self.billboards.add_texture(&self.device, &texture))
+    }
+
+    /// Makes `albedo`, and the tangent-space `normal` map if given,
+    /// available to `Decal`s.
+    pub fn add_decal_texture(
+        &mut self,
+        albedo_image: &image::DynamicImage,
+        normal_image: Option<&image::DynamicImage>,
+    ) -> anyhow::Result<DecalTextureId> {
+        let albedo = texture::Texture::from_image(
+            &self.device,
+            &self.queue,
+            albedo_image,
+            Some("decal_albedo"),
+        )?;
+        let normal = normal_image
+            .map(|normal| {
+                texture::Texture::normal_from_image(
+                    &self.device,
+                    &self.queue,
+                    normal,
+                    Some("decal_normal"),
+                )
+            })
+            .transpose()?;
+        self.decal_images
+            .push((albedo_image.clone(), normal_image.cloned()));
+        Ok(self
+            .decals
+            .add_texture(&self.device, &albedo, normal.as_ref()))
+    }
+
+    pub fn water(&self) -> Option<&Water> {
+        self.water.as_ref()
+    }
+
+    pub fn water_mut(&mut self) -> Option<&mut Water> {
+        self.water.as_mut()
+    }
+
+    /// Replaces the water surface, compiling forward pipelines for the
+    /// reflection and refraction passes where the deferred path lacks them.
+    pub fn set_water(
+        &mut self,
+        textures: &WaterTextures,
+        settings: WaterSettings,
+    ) -> anyhow::Result<()> {
+        let mut water = Water::new(
+            &self.device,
+            &self.queue,
+            &self.shaders,
+            textures,
+            settings,
+            [
+                &self.camera_bind_group_layout,
+                &self.light_bind_group_layout,
+                &self.shadow_map.bind_group_layout,
+            ],
+            self.hdr.format(),
+            self.config.width,
+            self.config.height,
+        )?;
+        water.set_reverse_z(self.reverse_z());
+        for key in opaque_forward_keys(&self.materials, &self.obj_model, &self.scene_defs) {
+            self.build_pipeline(key)?;
+        }
+        self.water = Some(water);
+        self.water_textures = Some(textures.clone());
+        self.bind_water_cameras();
+        Ok(())
+    }
+
+    pub fn remove_water(&mut self) -> Option<Water> {
+        self.water_textures = None;
+        self.water.take()
+    }
+
+    // The water passes see the scene through camera bind groups of their
+    // own, which go stale with the main one.
+    fn bind_water_cameras(&mut self) {
+        let Some(water) = &self.water else {
+            return;
+        };
+        let bind_groups = WaterPass::ALL.map(|pass| {
+            create_camera_bind_group(
+                &self.device,
+                &self.camera_bind_group_layout,
+                water.camera_buffer(pass),
+                &self.environment,
+                &self.reflection_probes,
+                &water.occlusion().view,
+                self.ssr.disabled(),
+                &self.clusters,
+                &self.joint_palette,
+            )
+        });
+        if let Some(water) = &mut self.water {
+            water.set_camera_bind_groups(bind_groups);
+        }
+    }
+
+    pub fn render_target(&self, id: RenderTargetId) -> Option<&RenderTarget> {
+        self.render_targets.get(id.0)
+    }
+
+    /// The target `id`, to move its camera.
+    pub fn render_target_mut(&mut self, id: RenderTargetId) -> Option<&mut RenderTarget> {
+        self.render_targets.get_mut(id.0)
+    }
+
+    /// Renders the scene from `camera` into a `size` texture every frame,
+    /// after the targets added before it and before the main view,
+    /// compiling the forward pipelines it needs first.
+    pub fn add_render_target(
+        &mut self,
+        camera: Camera,
+        size: [u32; 2],
+    ) -> anyhow::Result<RenderTargetId> {
+        for key in opaque_forward_keys(&self.materials, &self.obj_model, &self.scene_defs) {
+            self.build_pipeline(key)?;
+        }
+        let mut target =
+            RenderTarget::new(&self.device, &self.queue, self.hdr.format(), camera, size);
+        self.bind_render_target(&mut target);
+        self.render_targets.push(target);
+        Ok(RenderTargetId(self.render_targets.len() - 1))
+    }
+
+    /// Shows what `target` sees on the scene model's `material`th material,
+    /// as its emissive texture at full strength so it glows like a screen.
+    pub fn set_material_render_target(
+        &mut self,
+        material: usize,
+        target: RenderTargetId,
+    ) -> anyhow::Result<()> {
+        let Some(render_target) = self.render_targets.get(target.0) else {
+            anyhow::bail!("there is no render target {}", target.0);
+        };
+        let Some(model_material) = self.obj_model.materials.get_mut(material) else {
+            anyhow::bail!("the model has no material {}", material);
+        };
+        let textures = model::MaterialTextures {
+            emissive: render_target.texture().clone(),
+            ..model_material.textures.clone()
+        };
+        model_material.set_textures(&self.device, textures);
+        let factors = model::MaterialFactors {
+            emissive: [1.0; 3],
+            ..*model_material.factors()
+        };
+        model_material.set_factors(&self.queue, factors);
+        self.screens.retain(|&(other, _)| other != material);
+        self.screens.push((material, target));
+        Ok(())
+    }
+
+    fn bind_render_target(&self, target: &mut RenderTarget) {
+        target.set_camera_bind_group(create_camera_bind_group(
+            &self.device,
+            &self.camera_bind_group_layout,
+            target.camera_buffer(),
+            &self.environment,
+            &self.reflection_probes,
+            &target.occlusion().view,
+            self.ssr.disabled(),
+            &self.clusters,
+            &self.joint_palette,
+        ));
+    }
+
+    // Like the water's, the targets' camera bind groups go stale with the
+    // main one.
+    fn bind_render_targets(&mut self) {
+        let mut targets = std::mem::take(&mut self.render_targets);
+        for target in &mut targets {
+            self.bind_render_target(target);
+        }
+        self.render_targets = targets;
+    }
+
+    /// Draws the opaque scene into each render target in order, leaving out
+    /// the meshes showing the target itself. Nothing is culled, since the
+    /// culling follows the main camera.
+    fn render_target_passes(&self, encoder: &mut wgpu::CommandEncoder) {
+        let opaque = self.meshes_of_kind(|kind| !kind.is_transparent());
+        for (i, target) in self.render_targets.iter().enumerate() {
+            let Some(camera_bind_group) = target.camera_bind_group() else {
+                continue;
+            };
+            let in_view = self
+                .obj_model
+                .meshes
+                .iter()
+                .zip(&opaque)
+                .map(|(mesh, &opaque)| {
+                    opaque && !self.screens.contains(&(mesh.material, RenderTargetId(i)))
+                })
+                .collect();
+            let draws = self.material_draws_of(in_view, false, MaterialPass::Forward, 1);
+            let mut render_pass = target.begin_pass(encoder);
+            self.draw_materials_from(&mut render_pass, &draws, None, camera_bind_group);
+            self.draw_terrain_from(&mut render_pass, camera_bind_group, false, 1);
+        }
+    }
+
+    pub fn reflection_probes(&self) -> &[ReflectionProbe] {
+        self.reflection_probes.probes()
+    }
+
+    /// Places `probe` and captures the scene around it, compiling the
+    /// forward pipelines it needs first. Surfaces within its radius reflect
+    /// the capture, which only changes with `capture_reflection_probes`.
+    pub fn add_reflection_probe(
+        &mut self,
+        probe: ReflectionProbe,
+    ) -> anyhow::Result<ReflectionProbeId> {
+        let id = self.reflection_probes.push(probe)?;
+        if self.reflection_probes.reserve(&self.device) {
+            self.capture_reflection_probes()?;
+        } else {
+            self.capture_reflection_probe(id.0)?;
+        }
+        Ok(id)
+    }
+
+    /// Moves or resizes the probe `id`, and captures it again.
+    pub fn set_reflection_probe(
+        &mut self,
+        id: ReflectionProbeId,
+        probe: ReflectionProbe,
+    ) -> anyhow::Result<()> {
+        self.reflection_probes.set(id, probe)?;
+        self.capture_reflection_probe(id.0)
+    }
+
+    pub fn clear_reflection_probes(&mut self) {
+        self.reflection_probes.clear(&self.queue);
+    }
+
+    /// Captures every probe again, in order, for when the scene around
+    /// them changed. Each sees the ones before it as just captured, and
+    /// none after it.
+    pub fn capture_reflection_probes(&mut self) -> anyhow::Result<()> {
+        if self.reflection_probes.reserve(&self.device) {
+            self.rebind_cameras();
+        }
+        for index in 0..self.reflection_probes.len() {
+            self.capture_reflection_probe(index)?;
+        }
+        Ok(())
+    }
+
+    /// Draws the opaque scene around the `index`th probe like a
+    /// `RenderTarget` would, once per cube face, and prefilters it into
+    /// the probe's layers.
+    fn capture_reflection_probe(&mut self, index: usize) -> anyhow::Result<()> {
+        // Probes are captured at load time too, before any `update` has
+        // uploaded the instances the world grew.
+        self.sync_world();
+        self.instances.upload(&self.device, &mut self.uploads);
+        self.shadow_casters.upload(&self.device, &mut self.uploads);
+        if let Some(uploads) = self.uploads.finish() {
+            self.queue.submit(std::iter::once(uploads));
+            self.uploads.recall();
+        }
+        for key in opaque_forward_keys(&self.materials, &self.obj_model, &self.scene_defs) {
+            self.build_pipeline(key)?;
+        }
+        let probe = self.reflection_probes.probes()[index];
+        let faces = probe
+            .face_cameras(self.camera.znear, self.camera.zfar)
+            .map(|camera| {
+                let mut target = RenderTarget::new(
+                    &self.device,
+                    &self.queue,
+                    self.hdr.format(),
+                    camera,
+                    [probe::CAPTURE_SIZE; 2],
+                );
+                self.bind_render_target(&mut target);
+                target.update(&self.queue, self.reverse_z());
+                target
+            });
+        self.reflection_probes.write_uniform(&self.queue, index);
+
+        let draws = self.material_draws_of(
+            self.meshes_of_kind(|kind| !kind.is_transparent()),
+            false,
+            MaterialPass::Forward,
+            1,
+        );
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Reflection Probe Encoder"),
+            });
+        for target in &faces {
+            let Some(camera_bind_group) = target.camera_bind_group() else {
+                continue;
+            };
+            let mut render_pass = target.begin_pass(&mut encoder);
+            self.draw_materials_from(&mut render_pass, &draws, None, camera_bind_group);
+            self.draw_terrain_from(&mut render_pass, camera_bind_group, false, 1);
+        }
+        self.reflection_probes
+            .resolve(&self.device, &mut encoder, &faces, &self.environment.cube);
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.reflection_probes
+            .prefilter(&self.device, &self.queue, index);
+        self.reflection_probes
+            .write_uniform(&self.queue, self.reflection_probes.len());
+        Ok(())
+    }
+
+    /// Views drawn over the main one, in order.
+    pub fn views(&self) -> impl Iterator<Item = &View> {
+        self.views.iter().map(|(view, ..)| view)
+    }
+
+    /// The `index`th view, to move its camera or rectangle.
+    pub fn view_mut(&mut self, index: usize) -> Option<&mut View> {
+        self.views.get_mut(index).map(|(view, ..)| view)
+    }
+
+    /// Draws `view` over the main view and the ones added before it,
+    /// compiling the forward pipelines it needs first. Returns its index.
+    ///
+    /// Each view renders like a `RenderTarget` at its rectangle's size, with
+    /// the grid as well, and is tonemapped into its rectangle after the main
+    /// view's post effects.
+    pub fn add_view(&mut self, view: View) -> anyhow::Result<usize> {
+        for key in opaque_forward_keys(&self.materials, &self.obj_model, &self.scene_defs) {
+            self.build_pipeline(key)?;
+        }
+        let (target, tonemap) = self.view_target(&view);
+        self.views.push((view, target, tonemap));
+        Ok(self.views.len() - 1)
+    }
+
+    pub fn remove_view(&mut self, index: usize) -> Option<View> {
+        (index < self.views.len()).then(|| self.views.remove(index).0)
+    }
+
+    fn view_target(&self, view: &View) -> (RenderTarget, wgpu::BindGroup) {
+        let (_, size) = view.rect.pixels(self.size().into());
+        let camera = view.camera.unwrap_or(self.view_camera);
+        let mut target =
+            RenderTarget::new(&self.device, &self.queue, self.hdr.format(), camera, size);
+        self.bind_render_target(&mut target);
+        let tonemap = self.hdr.bind_texture(&self.device, target.texture());
+        (target, tonemap)
+    }
+
+    // Rebuilds the targets of views whose rectangles changed size, or of
+    // all of them when their bind groups went stale with the main ones.
+    fn fit_views(&mut self, all: bool) {
+        for i in 0..self.views.len() {
+            let (view, target, _) = &self.views[i];
+            let (_, size) = view.rect.pixels(self.size().into());
+            if all || target.size() != size {
+                let (target, tonemap) = self.view_target(view);
+                self.views[i].1 = target;
+                self.views[i].2 = tonemap;
+            }
+        }
+    }
+
+    /// Draws the opaque scene from each view into its own target and
+    /// tonemaps it into its rectangle of `output`. Nothing is culled, since
+    /// the culling follows the main camera.
+    fn render_views(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        if self.views.is_empty() {
+            return;
+        }
+        let draws = self.material_draws_of(
+            self.meshes_of_kind(|kind| !kind.is_transparent()),
+            false,
+            MaterialPass::Forward,
+            1,
+        );
+        for (view, target, tonemap_bind_group) in &self.views {
+            let Some(camera_bind_group) = target.camera_bind_group() else {
+                continue;
+            };
+            {
+                let mut render_pass = target.begin_pass(encoder);
+                self.draw_materials_from(&mut render_pass, &draws, None, camera_bind_group);
+                self.draw_terrain_from(&mut render_pass, camera_bind_group, false, 1);
+                if self.grid.is_enabled() {
+                    self.grid.render(&mut render_pass, camera_bind_group);
+                }
+            }
+            let (origin, size) = view.rect.pixels(self.size().into());
+            self.hdr
+                .composite(encoder, tonemap_bind_group, output, origin, size);
+        }
+    }
+
+    /// Presents to `window` as well, from the same device, showing
+    /// `content`. Its surface takes the main window's format.
+    pub fn add_window(
+        &mut self,
+        window: Arc<Window>,
+        content: WindowContent,
+    ) -> anyhow::Result<()> {
+        let Output::Window {
+            instance, adapter, ..
+        } = &self.output
+        else {
+            anyhow::bail!("Headless states have no windows to add to");
+        };
+        let surface = instance.create_surface(window.clone())?;
+        let caps = surface.get_capabilities(adapter);
+        if !caps.formats.contains(&self.config.format) {
+            anyhow::bail!(
+                "The window cannot show {:?}, the main window's format",
+                self.config.format
+            );
+        }
+        // Waiting on another window's vsync would hold back the main one.
+        let present_mode = [wgpu::PresentMode::Mailbox, wgpu::PresentMode::Immediate]
+            .into_iter()
+            .find(|mode| caps.present_modes.contains(mode))
+            .unwrap_or(wgpu::PresentMode::Fifo);
+        let size = window.inner_size();
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode,
+            alpha_mode: caps.alpha_modes[0],
+            ..self.config.clone()
+        };
+        surface.configure(&self.device, &config);
+        let mut window = SecondaryWindow {
+            window,
+            surface,
+            config,
+            content: WindowContent::Debug(DebugTexture::Depth),
+            target: None,
+        };
+        self.prepare_window_content(&mut window, content)?;
+        self.windows.push(window);
+        Ok(())
+    }
+
+    /// Stops presenting to the window `id`, handing it back.
+    pub fn remove_window(&mut self, id: WindowId) -> Option<Arc<Window>> {
+        let index = self
+            .windows
+            .iter()
+            .position(|window| window.window.id() == id)?;
+        Some(self.windows.remove(index).window)
+    }
+
+    /// What the window `id` shows, if it was added.
+    pub fn window_content(&self, id: WindowId) -> Option<WindowContent> {
+        self.windows
+            .iter()
+            .find(|window| window.window.id() == id)
+            .map(|window| window.content)
+    }
+
+    /// Shows `content` in the window `id` from the next frame on.
+    pub fn set_window_content(
+        &mut self,
+        id: WindowId,
+        content: WindowContent,
+    ) -> anyhow::Result<()> {
+        let Some(index) = self
+            .windows
+            .iter()
+            .position(|window| window.window.id() == id)
+        else {
+            anyhow::bail!("The window was never added");
+        };
+        let mut window = self.windows.remove(index);
+        let prepared = self.prepare_window_content(&mut window, content);
+        self.windows.insert(index, window);
+        prepared
+    }
+
+    /// Reconfigures the window `id`'s surface after it was resized.
+    pub fn resize_window(&mut self, id: WindowId, width: u32, height: u32) {
+        let Some(window) = self
+            .windows
+            .iter_mut()
+            .find(|window| window.window.id() == id)
+        else {
+            return;
+        };
+        if width > 0 && height > 0 {
+            window.config.width = width;
+            window.config.height = height;
+            window.surface.configure(&self.device, &window.config);
+        }
+    }
+
+    // Compiles what `content` needs, leaving `window` as it was if that
+    // fails.
+    fn prepare_window_content(
+        &mut self,
+        window: &mut SecondaryWindow,
+        content: WindowContent,
+    ) -> anyhow::Result<()> {
+        match content {
+            WindowContent::Scene(camera) => {
+                for key in opaque_forward_keys(&self.materials, &self.obj_model, &self.scene_defs) {
+                    self.build_pipeline(key)?;
+                }
+                window.target = Some(self.window_target(camera, &window.config));
+            }
+            WindowContent::Debug(_) => {
+                window.target = None;
+                self.build_debug_blit();
+            }
+        }
+        window.content = content;
+        Ok(())
+    }
+
+    fn build_debug_blit(&mut self) {
+        if self.debug_blit.is_none() {
+            self.debug_blit = Some(DebugBlit::new(
+                &self.device,
+                hdr::output_format(&self.config),
+            ));
+        }
+    }
+
+    fn window_target(
+        &self,
+        camera: Camera,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> (RenderTarget, wgpu::BindGroup) {
+        let mut target = RenderTarget::new(
+            &self.device,
+            &self.queue,
+            self.hdr.format(),
+            camera,
+            [config.width, config.height],
+        );
+        self.bind_render_target(&mut target);
+        let tonemap = self.hdr.bind_texture(&self.device, target.texture());
+        (target, tonemap)
+    }
+
+    // Like `fit_views`, for the targets of windows showing the scene.
+    fn fit_windows(&mut self, all: bool) {
+        for i in 0..self.windows.len() {
+            let window = &self.windows[i];
+            let WindowContent::Scene(camera) = window.content else {
+                continue;
+            };
+            let size = [window.config.width, window.config.height];
+            let stale = window
+                .target
+                .as_ref()
+                .is_none_or(|(target, _)| target.size() != size);
+            if all || stale {
+                self.windows[i].target = Some(self.window_target(camera, &window.config));
+            }
+        }
+    }
+
+    /// Takes this frame's texture from each other window, reconfiguring
+    /// the surfaces that went stale, and draws into the ones it got.
+    fn render_windows(&mut self, encoder: &mut wgpu::CommandEncoder) -> Vec<wgpu::SurfaceTexture> {
+        let mut frames = Vec::new();
+        for (i, window) in self.windows.iter_mut().enumerate() {
+            let size = window.window.inner_size();
+            if size.width == 0 || size.height == 0 {
+                continue;
+            }
+            if [size.width, size.height] != [window.config.width, window.config.height] {
+                window.config.width = size.width;
+                window.config.height = size.height;
+                window.surface.configure(&self.device, &window.config);
+            }
+            match window.surface.get_current_texture() {
+                Ok(frame) => frames.push((i, frame)),
+                Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                    window.surface.configure(&self.device, &window.config);
+                }
+                Err(e) => log::warn!("Skipping a frame of another window: {}", e),
+            }
+        }
+        self.fit_windows(false);
+        for (i, frame) in &frames {
+            let view = frame.texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("window_view"),
+                format: Some(hdr::output_format(&self.config)),
+                ..Default::default()
+            });
+            self.render_window(encoder, &self.windows[*i], &view);
+        }
+        frames.into_iter().map(|(_, frame)| frame).collect()
+    }
+
+    /// The texture drawn over the main view, if any.
+    pub fn debug_view(&self) -> Option<DebugView> {
+        self.debug_view
+    }
+
+    /// Draws `view`'s texture over its rectangle of the main view, after
+    /// the other views and before the UI, or stops drawing one.
+    pub fn set_debug_view(&mut self, view: Option<DebugView>) {
+        if view.is_some() {
+            self.build_debug_blit();
+        }
+        self.debug_view = view;
+    }
+
+    /// The textures this state has to show, in the order the debug view
+    /// key steps through them: the G-buffer when rendering deferred, then
+    /// depth, occlusion, each shadow cascade and each bloom level.
+    pub fn debug_textures(&self) -> Vec<DebugTexture> {
+        let mut textures = Vec::new();
+        if self.deferred.is_some() {
+            textures.extend([
+                DebugTexture::Albedo,
+                DebugTexture::Normal,
+                DebugTexture::Material,
+                DebugTexture::Emissive,
+            ]);
+        }
+        textures.extend([DebugTexture::Depth, DebugTexture::Occlusion]);
+        let cascades = self.shadow_map.texture.texture.depth_or_array_layers();
+        textures.extend((0..cascades).map(DebugTexture::ShadowCascade));
+        let levels = self.bloom.mips().len() as u32;
+        textures.extend((0..levels).map(DebugTexture::BloomMip));
+        textures
+    }
+
+    /// Shows the next of `debug_textures` in the debug view's rectangle,
+    /// fullscreen at first, and none after the last.
+    pub fn cycle_debug_view(&mut self) {
+        let textures = self.debug_textures();
+        let next = match self.debug_view {
+            None => textures.first(),
+            Some(view) => textures
+                .iter()
+                .skip_while(|&&texture| texture != view.texture)
+                .nth(1),
+        };
+        let rect = self.debug_view.map_or(ViewportRect::FULL, |view| view.rect);
+        self.set_debug_view(next.map(|&texture| DebugView { texture, rect }));
+    }
+
+    fn render_window(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        window: &SecondaryWindow,
+        output: &wgpu::TextureView,
+    ) {
+        let texture = match window.content {
+            WindowContent::Scene(_) => {
+                let Some((target, tonemap)) = &window.target else {
+                    return;
+                };
+                let Some(camera_bind_group) = target.camera_bind_group() else {
+                    return;
+                };
+                let draws = self.material_draws_of(
+                    self.meshes_of_kind(|kind| !kind.is_transparent()),
+                    false,
+                    MaterialPass::Forward,
+                    1,
+                );
+                {
+                    let mut render_pass = target.begin_pass(encoder);
+                    self.draw_materials_from(&mut render_pass, &draws, None, camera_bind_group);
+                    self.draw_terrain_from(&mut render_pass, camera_bind_group, false, 1);
+                    if self.grid.is_enabled() {
+                        self.grid.render(&mut render_pass, camera_bind_group);
+                    }
+                }
+                self.hdr
+                    .composite(encoder, tonemap, output, [0, 0], target.size());
+                return;
+            }
+            WindowContent::Debug(texture) => texture,
+        };
+        let source = self.debug_source(texture);
+        if let Some(blit) = &self.debug_blit {
+            blit.render(
+                &self.device,
+                encoder,
+                output,
+                source.as_ref().map(|(view, mode)| (view, *mode)),
+                None,
+            );
+        }
+    }
+
+    fn render_debug_view(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        debug_view: DebugView,
+    ) {
+        let Some(blit) = &self.debug_blit else {
+            return;
+        };
+        let source = self.debug_source(debug_view.texture);
+        let rect = (debug_view.rect != ViewportRect::FULL)
+            .then(|| debug_view.rect.pixels(self.size().into()));
+        blit.render(
+            &self.device,
+            encoder,
+            output,
+            source.as_ref().map(|(view, mode)| (view, *mode)),
+            rect,
+        );
+    }
+
+    /// The view of `texture` a debug window or view reads, and how. None
+    /// for the G-buffer on the forward paths and for cascades or bloom
+    /// levels past the last.
+    fn debug_source(&self, texture: DebugTexture) -> Option<(wgpu::TextureView, BlitMode)> {
+        let gbuffer = |slot: usize| {
+            self.deferred
+                .as_ref()
+                .map(|deferred| deferred.gbuffer()[slot].view.clone())
+        };
+        match texture {
+            DebugTexture::Albedo => gbuffer(0).map(|view| (view, BlitMode::Color)),
+            DebugTexture::Normal => gbuffer(1).map(|view| (view, BlitMode::Normal)),
+            DebugTexture::Material => gbuffer(2).map(|view| (view, BlitMode::Color)),
+            DebugTexture::Emissive => gbuffer(3).map(|view| (view, BlitMode::Color)),
+            DebugTexture::Depth => Some((
+                self.depth_texture.view.clone(),
+                if self.reverse_z() {
+                    BlitMode::ReversedDepth
+                } else {
+                    BlitMode::PerspectiveDepth
+                },
+            )),
+            DebugTexture::Occlusion => Some((self.ssao.occlusion().view.clone(), BlitMode::Red)),
+            DebugTexture::ShadowCascade(cascade) => {
+                let shadows = &self.shadow_map.texture;
+                (cascade < shadows.texture.depth_or_array_layers())
+                    .then(|| (shadows.view.clone(), BlitMode::LayerDepth(cascade)))
+            }
+            DebugTexture::BloomMip(level) => self
+                .bloom
+                .mips()
+                .get(level as usize)
+                .map(|mip| (mip.view.clone(), BlitMode::Color)),
+        }
+    }
+
+    // The water, render targets, views and windows showing the scene draw
+    // the opaque scene forward with cameras of their own, whatever the
+    // render path.
+    fn draws_opaque_forward(&self) -> bool {
+        self.water.is_some()
+            || !self.render_targets.is_empty()
+            || !self.views.is_empty()
+            || self
+                .windows
+                .iter()
+                .any(|window| matches!(window.content, WindowContent::Scene(_)))
+    }
+
+    /// Draws the scene model's `material`th material as `kind`, compiling
+    /// the pipelines it needs first. Leaves the material as it was if they
+    /// fail to build.
+    pub fn set_material_kind(
+        &mut self,
+        material: usize,
+        kind: MaterialKindId,
+    ) -> anyhow::Result<()> {
+        if material >= self.obj_model.materials.len() {
+            anyhow::bail!("the model has no material {}", material);
+        }
+        let skinned = self.scene_defs.contains("SKINNED");
+        let key = PipelineKey {
+            material: kind,
+            skinned,
+            vertex_encoding: self.obj_model.vertex_encoding,
+            pass: self.materials.pass(kind, self.deferred.is_some()),
+            texture_arrays: false,
+            samples: 1,
+        };
+        self.build_pipeline(key)?;
+        let opaque = self
+            .materials
+            .get(kind)
+            .is_some_and(|kind| !kind.is_transparent());
+        let arrays = self
+            .materials
+            .get(kind)
+            .is_some_and(|kind| kind.texture_arrays());
+        if self.texture_arrays && opaque && arrays {
+            self.build_pipeline(PipelineKey {
+                texture_arrays: true,
+                ..key
+            })?;
+        }
+        if self.draws_opaque_forward() && opaque {
+            self.build_pipeline(PipelineKey {
+                material: kind,
+                skinned,
+                vertex_encoding: self.obj_model.vertex_encoding,
+                pass: MaterialPass::Forward,
+                texture_arrays: false,
+                samples: 1,
+            })?;
+        }
+        self.obj_model.materials[material].kind = kind;
+        Ok(())
+    }
+
+    /// Sets the factors of the scene model's `material`th material, such as
+    /// the base color alpha a transparent kind blends by.
+    pub fn set_material_factors(
+        &mut self,
+        material: usize,
+        factors: model::MaterialFactors,
+    ) -> anyhow::Result<()> {
+        let Some(material) = self.obj_model.materials.get_mut(material) else {
+            anyhow::bail!("the model has no material {}", material);
+        };
+        material.set_factors(&self.queue, factors);
+        Ok(())
+    }
+
+    /// Sampler options for every material that has none of its own.
+    pub fn sampler_settings(&self) -> texture::SamplerSettings {
+        self.sampler_settings
+    }
+
+    pub fn set_sampler_settings(&mut self, settings: texture::SamplerSettings) {
+        self.sampler_settings = settings;
+        for material in self
+            .obj_model
+            .materials
+            .iter_mut()
+            .chain(std::iter::once(&mut self.diffuse_material))
+        {
+            let sampler_override = material.sampler_override();
+            material.set_sampler(&self.device, &self.queue, sampler_override, settings);
+        }
+    }
+
+    /// Gives `material` its own sampler options, or puts it back on the
+    /// scene default with `None`.
+    pub fn set_material_sampler(
+        &mut self,
+        material: usize,
+        settings: Option<texture::SamplerSettings>,
+    ) -> anyhow::Result<()> {
+        let Some(material) = self.obj_model.materials.get_mut(material) else {
+            anyhow::bail!("the model has no material {}", material);
+        };
+        material.set_sampler(&self.device, &self.queue, settings, self.sampler_settings);
+        Ok(())
+    }
+
+    /// Builds the pipeline for `key` with the current scene defines unless
+    /// it is already there, as the scene pipelines start to need it.
+    fn build_pipeline(&mut self, key: PipelineKey) -> anyhow::Result<()> {
+        for key in [Some(key), self.multisampled(key)].into_iter().flatten() {
+            if self.materials.pipeline(&key).is_none() {
+                let pipeline = self
+                    .materials
+                    .create_pipeline(&key, &self.pipeline_context(&self.scene_defs))?;
+                self.materials.insert_pipeline(key, pipeline);
+            }
+        }
+        // Other permutations were built without it.
+        self.scene_pipelines.clear();
+        self.scene_pipelines.insert(
+            self.scene_defs.clone(),
+            ScenePipelines {
+                materials: self.materials.pipelines().clone(),
+                deferred: self
+                    .deferred
+                    .as_ref()
+                    .map(|deferred| deferred.pipelines().clone()),
+            },
+        );
+        Ok(())
+    }
+
+    /// The variant of `key` the forward paths' opaque pass draws with while
+    /// MSAA is on, if it needs one.
+    fn multisampled(&self, key: PipelineKey) -> Option<PipelineKey> {
+        let opaque = self
+            .materials
+            .get(key.material)
+            .is_some_and(|kind| !kind.is_transparent());
+        (self.msaa.is_some() && key.pass == MaterialPass::Forward && opaque).then_some(
+            PipelineKey {
+                samples: Msaa::SAMPLE_COUNT,
+                ..key
+            },
+        )
+    }
+
+    fn pipeline_context<'a>(&'a self, defs: &'a ShaderDefs) -> PipelineContext<'a> {
+        PipelineContext {
+            device: &self.device,
+            shaders: &self.shaders,
+            cache: &self.pipeline_cache,
+            scene_defs: defs,
+            scene_bind_group_layouts: [
+                &self.camera_bind_group_layout,
+                &self.light_bind_group_layout,
+                &self.shadow_map.bind_group_layout,
+            ],
+            texture_array_layout: &self.texture_array_layout,
+            color_format: self.hdr.format(),
+        }
+    }
+
+    fn build_scene_pipelines(&self, defs: &ShaderDefs) -> anyhow::Result<ScenePipelines> {
+        let mut keys = pipeline_keys(
+            &self.materials,
+            &self.obj_model,
+            defs,
+            self.deferred.is_some(),
+            self.texture_arrays,
+        );
+        keys.extend(self.terrain.as_ref().map(Terrain::pipeline_key));
+        if self.draws_opaque_forward() {
+            for key in opaque_forward_keys(&self.materials, &self.obj_model, defs) {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+        let multisampled = keys
+            .iter()
+            .filter_map(|&key| self.multisampled(key))
+            .collect::<Vec<_>>();
+        keys.extend(multisampled);
+        let materials =
+            create_material_pipelines(&self.materials, &keys, &self.pipeline_context(defs))?;
+        let deferred = match &self.deferred {
+            Some(deferred) => Some(deferred.create_pipelines(
+                &self.device,
+                &self.shaders.preprocess("deferred.wgsl", defs)?,
+            )),
+            None => None,
+        };
+        Ok(ScenePipelines {
+            materials,
+            deferred,
+        })
+    }
+
+    fn use_scene_pipelines(&mut self, pipelines: ScenePipelines) {
+        self.materials.set_pipelines(pipelines.materials);
+        if let (Some(deferred), Some(pipelines)) = (&mut self.deferred, pipelines.deferred) {
+            deferred.set_pipelines(pipelines);
+        }
+    }
+
+    /// Starts watching the WGSL sources, as debug builds do from the start.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn enable_shader_hot_reload(&mut self) -> anyhow::Result<()> {
+        if self.shader_watcher.is_none() {
+            self.shader_watcher = Some(ShaderWatcher::new()?);
+        }
+        Ok(())
+    }
+
+    /// Starts watching `res`, as debug builds do from the start, to load
+    /// the assets that read an edited file again.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn enable_asset_hot_reload(&mut self) -> anyhow::Result<()> {
+        if self.asset_watcher.is_none() {
+            self.asset_watcher = Some(AssetWatcher::new()?);
+        }
+        Ok(())
+    }
+
+    /// Rebuilds every pipeline built from a WGSL file that changed, or
+    /// from one including it. Should the edit not compile, the error is
+    /// logged and the pipelines are built from the previous sources again.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_shaders(&mut self) {
+        let Some(watcher) = &self.shader_watcher else {
+            return;
+        };
+        let changed = watcher.changed();
+        if changed.is_empty() {
+            return;
+        }
+        let mut users = Vec::new();
+        // Sources replaced so far, restored if the new ones do not build.
+        let mut previous = Vec::new();
+        for name in changed {
+            let source = match watcher.read(&name) {
+                Ok(source) => source,
+                Err(e) => {
+                    log::error!("Unable to read {}: {}", name, e);
+                    continue;
+                }
+            };
+            let includers = self.shaders.includers(&name);
+            let mut built = false;
+            for (file, user) in SHADER_USERS {
+                if includers.iter().any(|includer| includer == file) {
+                    built = true;
+                    if !users.contains(user) {
+                        users.push(*user);
+                    }
+                }
+            }
+            if !built {
+                log::warn!("{} changed, but no pipeline is built from it", name);
+            }
+            let old = self.shaders.insert(name.clone(), source.clone());
+            let old_builtin = preprocess::reload_builtin(&name, source);
+            previous.push((name, old, old_builtin));
+        }
+        if users.is_empty() {
+            return;
+        }
+
+        let failed = self.rebuild_shader_users(&users);
+        if failed.is_empty() {
+            log::info!("Reloaded the shaders of {:?}", users);
+            return;
+        }
+        for (name, old, old_builtin) in previous {
+            if let Some(old) = old {
+                self.shaders.insert(name.clone(), old);
+            }
+            if let Some(old_builtin) = old_builtin {
+                preprocess::reload_builtin(&name, old_builtin);
+            }
+        }
+        // Those that did build are rebuilt too, so that nothing runs
+        // shaders the library no longer holds.
+        let failed = self.rebuild_shader_users(&users);
+        if !failed.is_empty() {
+            log::error!("Unable to rebuild {:?} from the previous shaders", failed);
+        }
+    }
+
+    // Rebuilds the pipelines of each of `users` from the current sources,
+    // returning those that failed to compile.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn rebuild_shader_users(&mut self, users: &[ShaderUser]) -> Vec<ShaderUser> {
+        let device = self.device.clone();
+        let mut failed = Vec::new();
+        for &user in users {
+            let label = format!("{:?} shaders", user);
+            match hot_reload::try_build(&device, &label, || self.rebuild_shader_user(user)) {
+                Some(Ok(())) => {}
+                Some(Err(e)) => {
+                    log::error!("Keeping the previous {}: {:#}", label, e);
+                    failed.push(user);
+                }
+                None => failed.push(user),
+            }
+        }
+        failed
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn rebuild_shader_user(&mut self, user: ShaderUser) -> anyhow::Result<()> {
+        let device = &self.device;
+        match user {
+            ShaderUser::Scene => {
+                // Cached modules were compiled from the old sources.
+                self.pipeline_cache.clear_shaders();
+                let pipelines = self.build_scene_pipelines(&self.scene_defs)?;
+                // Other permutations were built from the old sources.
+                self.scene_pipelines.clear();
+                self.scene_pipelines
+                    .insert(self.scene_defs.clone(), pipelines.clone());
+                self.use_scene_pipelines(pipelines);
+            }
+            ShaderUser::Ssao => self.ssao.rebuild_pipelines(device),
+            ShaderUser::Ssr => self.ssr.rebuild_pipelines(device),
+            ShaderUser::Hdr => self.hdr.rebuild_pipelines(device),
+            ShaderUser::Taa => self.taa.rebuild_pipelines(device),
+            ShaderUser::Bloom => self.bloom.rebuild_pipelines(device),
+            ShaderUser::AutoExposure => self.auto_exposure.rebuild_pipelines(device),
+            ShaderUser::Volumetrics => self.volumetrics.rebuild_pipelines(device),
+            ShaderUser::Post => self.post.rebuild_pipelines(device),
+            ShaderUser::Oit => self.oit.rebuild_pipelines(device),
+            ShaderUser::Clusters => self.clusters.rebuild_pipelines(device),
+            ShaderUser::Shadows => self.shadow_map.rebuild_pipelines(device),
+            ShaderUser::ReflectionProbes => self.reflection_probes.rebuild_pipelines(device),
+            ShaderUser::Skybox => self.skybox.rebuild_pipelines(device),
+            ShaderUser::Sprites => self.sprites.rebuild_pipelines(device),
+            ShaderUser::Text => self.text.rebuild_pipelines(device),
+            ShaderUser::GpuCulling => self.gpu_culling.rebuild_pipelines(device),
+            ShaderUser::IdPicker => self.id_picker.rebuild_pipelines(device),
+            ShaderUser::Outline => self.outline.rebuild_pipelines(device),
+            ShaderUser::Billboards => self.billboards.rebuild_pipelines(device, &self.shaders)?,
+            ShaderUser::Decals => self.decals.rebuild_pipelines(device, &self.shaders)?,
+            ShaderUser::DebugDraw => self.debug_draw.rebuild_pipelines(device, &self.shaders)?,
+            ShaderUser::Grid => self.grid.rebuild_pipelines(device, &self.shaders)?,
+            ShaderUser::Water => {
+                if let Some(water) = &mut self.water {
+                    water.rebuild_pipelines(device, &self.shaders)?;
+                }
+            }
+            ShaderUser::Environment => {
+                let cube = self.environment.cube.clone();
+                self.environment = Environment::new(device, &self.queue, cube);
+                self.rebind_cameras();
+            }
+            // Packed again, with the new shader, by the next update.
+            ShaderUser::MaterialArrays => self.material_arrays = None,
+            ShaderUser::DebugBlit => {
+                if self.debug_blit.take().is_some() {
+                    self.build_debug_blit();
+                }
+            }
+            ShaderUser::Msaa => {
+                if let Some(msaa) = &mut self.msaa {
+                    msaa.rebuild_pipelines(device);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update(&mut self) {
+        self.frame_trace.begin_frame();
+        let _span = self.frame_trace.span(Phase::Update);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.reload_shaders();
+            if let Some(watcher) = &self.asset_watcher {
+                let changed = watcher.changed();
+                if !changed.is_empty() {
+                    self.assets.reload_changed(&changed);
+                }
+            }
+        }
+        self.loaded_assets = self.assets.poll();
+        // First, so that replayed input arrives where live input would.
+        let dt = self.frame_dt();
+        // Only when changed, so that `camera_mut` moves it in between.
+        let camera_changed = self.world.changed_since::<Camera>(self.camera_tick)
+            || self.world.changed_since::<ActiveCamera>(self.camera_tick);
+        let active_camera = self.active_camera();
+        if camera_changed && let Some((_, camera)) = active_camera {
+            // The aspect ratio and depth convention follow the output,
+            // whatever the entity has.
+            self.camera = Camera {
+                aspect: self.camera.aspect,
+                reverse_z: self.camera.reverse_z,
+                ..camera
+            };
+        }
+        if self.camera != self.stepped_camera {
+            self.previous_camera = self.camera;
+            self.stepped_camera = self.camera;
+        }
+        for _ in 0..self.timestep.advance(dt) {
+            self.fixed_update();
+        }
+        // Moves each body's entity to where the body is between the last
+        // two steps, as the camera is drawn.
+        #[cfg(feature = "physics")]
+        self.physics.sync(&mut self.world, self.timestep.alpha());
+        if let Some((entity, camera)) = active_camera
+            && camera != self.camera
+            && let Some(component) = self.world.get_mut::<Camera>(entity)
+        {
+            *component = self.camera;
+        }
+        self.camera_tick = self.world.tick();
+        self.camera_effects.update(dt);
+        self.view_camera = self.camera_effects.apply(
+            &self
+                .previous_camera
+                .lerp(&self.camera, self.timestep.alpha()),
+        );
+        self.camera_uniform.update_view_proj(&self.view_camera);
+        self.camera_uniform.jitter =
+            self.taa
+                .update(&self.queue, self.config.width, self.config.height);
+        self.uploads.write(
+            &self.device,
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+        self.scene.update(&mut self.world);
+        self.sync_world();
+        self.instances.upload(&self.device, &mut self.uploads);
+        self.shadow_casters.upload(&self.device, &mut self.uploads);
+        self.update_pose();
+        if let Some(water) = &mut self.water {
+            water.update(&self.queue, &self.view_camera, dt);
+        }
+        self.auto_exposure.update(&self.queue, dt);
+        self.update_sky(dt);
+        self.volumetrics.update(&self.queue, self.shadows_enabled());
+        self.fit_views(false);
+        let reverse_z = self.reverse_z();
+        for (view, target, _) in &mut self.views {
+            target.camera = view.camera.unwrap_or(self.view_camera);
+            target.update(&self.queue, reverse_z);
+        }
+        for target in &mut self.render_targets {
+            target.update(&self.queue, reverse_z);
+        }
+        for window in &mut self.windows {
+            if let (WindowContent::Scene(camera), Some((target, _))) =
+                (window.content, &mut window.target)
+            {
+                target.camera = camera;
+                target.update(&self.queue, reverse_z);
+            }
+        }
+        self.gpu_culling.update(
+            &self.device,
+            &self.queue,
+            &self.view_camera,
+            &self.obj_model,
+            &self.instances,
+        );
+        if self.texture_arrays {
+            match &self.material_arrays {
+                Some(arrays) if arrays.is_of(&self.obj_model) => {
+                    arrays.write_factors(&self.queue, &self.obj_model);
+                }
+                _ => {
+                    self.material_arrays = MaterialArrays::new(
+                        &self.device,
+                        &self.queue,
+                        &self.texture_array_layout,
+                        &self.obj_model,
+                    );
+                }
+            }
+        }
+        self.lods.update(
+            &self.device,
+            &mut self.uploads,
+            &self.view_camera,
+            self.config.height,
+            &self.obj_model,
+            self.instances.raw(),
+        );
+        self.visible_meshes = if self.cpu_culling {
+            culling::visible_meshes(
+                &Frustum::from_view_proj(&self.view_camera),
+                &self.obj_model,
+                self.instances.raw(),
+            )
+        } else {
+            vec![true; self.obj_model.meshes.len()]
+        };
+        let frustum = self
+            .cpu_culling
+            .then(|| Frustum::from_view_proj(&self.view_camera));
+        self.transparent_draws = culling::back_to_front(
+            self.view_camera.eye,
+            frustum.as_ref(),
+            &self.obj_model,
+            self.instances.raw(),
+            &self.meshes_of_kind(|kind| kind.is_transparent() && !kind.order_independent()),
+        );
+        if let Some(terrain) = &mut self.terrain {
+            terrain.cull(frustum.as_ref());
+        }
+        self.lights.upload(
+            &self.device,
+            &mut self.uploads,
+            &self.light_bind_group_layout,
+        );
+        self.skybox.update(&self.queue, &self.view_camera);
+        self.billboards
+            .upload(&self.device, &self.queue, self.view_camera.eye);
+        self.ssao.update(&self.queue, &self.view_camera);
+        self.clusters.update(
+            &self.queue,
+            &self.view_camera,
+            self.config.width,
+            self.config.height,
+        );
+        self.shadow_map.update(
+            &self.device,
+            &self.queue,
+            &self.directional_light,
+            &self.view_camera,
+            self.lights.lights(),
+        );
+    }
+
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        if let Some(window) = self.output.window() {
+            window.request_redraw();
+        }
+
+        // Switching fullscreen modes does not always send a resize first.
+        if let Some(size) = self.output.window().map(|window| window.inner_size())
+            && (size.width, size.height) != self.size()
+        {
+            self.resize(size.width, size.height);
+        }
+
+        if !self.is_surface_configured {
+            return Ok(());
+        }
+
+        let encode = self.frame_trace.span(Phase::Encode);
+        let debug_frame =
+            (self.debug_ui.visible || self.debug_ui.stats_visible).then(|| self.run_debug_ui());
+
+        let (frame, output_texture) = match &self.output {
+            Output::Window { surface, .. } => {
+                let frame = surface.get_current_texture()?;
+                let texture = frame.texture.clone();
+                (Some(frame), texture)
+            }
+            Output::Offscreen { texture } => (None, texture.clone()),
+        };
+        let debugger_capture = std::mem::take(&mut self.debugger_capture_request);
+        if debugger_capture {
+            // SAFETY: no capture is in progress, and the one started here
+            // is stopped before this function returns.
+            unsafe { self.device.start_graphics_debugger_capture() };
+        }
+        let view = output_texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("output_view"),
+            format: Some(hdr::output_format(&self.config)),
+            ..Default::default()
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+        self.section(&mut encoder, "shadows");
+        if self.shadows_enabled() {
+            self.shadow_map.render(
+                &self.device,
+                &mut encoder,
+                &self.obj_model,
+                &self.shadow_casters,
+            );
+        }
+        self.section(&mut encoder, "clusters");
+        self.clusters
+            .dispatch(&mut encoder, self.lights.bind_group());
+        self.section(&mut encoder, "culling");
+        self.gpu_culling.dispatch(&mut encoder);
+        self.section(&mut encoder, "reflect");
+        self.render_water_passes(&mut encoder);
+        if !self.render_targets.is_empty() {
+            self.section(&mut encoder, "targets");
+            self.render_target_passes(&mut encoder);
+        }
+        self.section(&mut encoder, "ssao");
+        // Transparent meshes would hide what is behind them.
+        let opaque_meshes = self.meshes_in_view(|kind| !kind.is_transparent());
+        self.ssao.render(
+            &self.device,
+            &mut encoder,
+            &self.obj_model,
+            DrawInstances::new(&self.instances, Some(&self.gpu_culling))
+                .with_lods(&self.lods)
+                .with_visible_meshes(&opaque_meshes),
+            &self.depth_texture,
+        );
+        // The SSAO prepass already laid down depth.
+        let depth_load = if self.ssao.writes_depth() {
+            wgpu::LoadOp::Load
+        } else {
+            wgpu::LoadOp::Clear(self.camera.far_depth())
+        };
+        self.section(&mut encoder, "opaque");
+        if let Some(deferred) = &self.deferred {
+            let gbuffer_draws = self.material_draws(MaterialPass::GBuffer, |_| true);
+            let gbuffer_draws = gbuffer_draws
+                .iter()
+                .map(|(pipeline, meshes)| {
+                    self.with_texture_arrays(
+                        pipeline,
+                        DrawInstances::new(&self.instances, Some(&self.gpu_culling))
+                            .with_lods(&self.lods)
+                            .with_visible_meshes(meshes),
+                    )
+                })
+                .collect::<Vec<_>>();
+            deferred.render(
+                &mut encoder,
+                &self.obj_model,
+                &gbuffer_draws,
+                &self.depth_texture,
+                depth_load,
+                &self.decals,
+                [
+                    &self.camera_bind_group,
+                    self.lights.bind_group(),
+                    &self.shadow_map.bind_group,
+                ],
+                &self.taa.velocity().view,
+                self.hdr.view(),
+            );
+            // Materials the G-buffer cannot hold, over the lit result.
+            let draws = self.material_draws(MaterialPass::Forward, |kind| !kind.is_transparent());
+            if !draws.is_empty() || self.terrain.is_some() {
+                let mut render_pass = self.begin_overlay_pass(&mut encoder, "Forward Pass");
+                self.draw_materials(&mut render_pass, &draws);
+                self.draw_terrain(&mut render_pass, 1);
+            }
+        } else {
+            let samples = self.msaa_samples();
+            let draws = self.material_draws_of(
+                self.meshes_in_view(|kind| !kind.is_transparent()),
+                false,
+                MaterialPass::Forward,
+                samples,
+            );
+            // With MSAA the velocity resolves after this pass, the color
+            // once the sky is drawn too and the depth by `resolve_depth`.
+            let (color, velocity, velocity_resolve, depth, depth_load) = match &self.msaa {
+                Some(msaa) => (
+                    msaa.color(),
+                    msaa.velocity(),
+                    Some(&self.taa.velocity().view),
+                    msaa.depth(),
+                    // The SSAO prepass's depth has a single sample.
+                    wgpu::LoadOp::Clear(self.camera.far_depth()),
+                ),
+                None => (
+                    self.hdr.view(),
+                    &self.taa.velocity().view,
+                    None,
+                    &self.depth_texture.view,
+                    depth_load,
+                ),
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: color,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color {
+                                r: 0.1,
+                                g: 0.2,
+                                b: 0.3,
+                                a: 1.0,
+                            }),
+                            store: wgpu::StoreOp::Store,
+                        },
+                        depth_slice: None,
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: velocity,
+                        resolve_target: velocity_resolve,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                        depth_slice: None,
+                    }),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: depth_load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            self.draw_opaque_materials(&mut render_pass, &draws);
+            self.draw_terrain(&mut render_pass, samples);
+        }
+        if let Some(msaa) = &self.msaa {
+            msaa.resolve_depth(&mut encoder, &self.depth_texture.view, self.reverse_z());
+        }
+        self.section(&mut encoder, "hiz");
+        self.gpu_culling.build_depth_pyramid(
+            &self.device,
+            &mut encoder,
+            &self.depth_texture,
+            &self.view_camera,
+        );
+        // Drawn on its own so it fits behind either path without writing
+        // velocity; TAA reprojects sky pixels from depth instead.
+        self.section(&mut encoder, "skybox");
+        {
+            // With MSAA, over the multisampled scene, so its edges against
+            // the sky resolve too.
+            let (color, resolve_target, depth) = match &self.msaa {
+                Some(msaa) => (msaa.color(), Some(self.hdr.view()), msaa.depth()),
+                None => (self.hdr.view(), None, &self.depth_texture.view),
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Skybox Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: color,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            self.skybox.render(&mut render_pass);
+        }
+        if self.water.is_some() {
+            self.section(&mut encoder, "water");
+        }
+        if let Some(water) = &self.water {
+            let mut render_pass = self.begin_overlay_pass(&mut encoder, "Water Pass");
+            water.render(
+                &mut render_pass,
+                [
+                    &self.camera_bind_group,
+                    self.lights.bind_group(),
+                    &self.shadow_map.bind_group,
+                ],
+            );
+        }
+        if self.ssr.settings().enabled {
+            self.section(&mut encoder, "ssr");
+            self.ssr.store(
+                &self.device,
+                &mut encoder,
+                self.hdr.view(),
+                &self.depth_texture,
+                &self.camera_buffer,
+            );
+        }
+        if self.volumetrics.settings().enabled {
+            self.section(&mut encoder, "volume");
+            self.volumetrics.render(
+                &self.device,
+                &mut encoder,
+                self.hdr.view(),
+                &self.depth_texture,
+                &self.camera_buffer,
+                &self.shadow_map.bind_group,
+            );
+        }
+        if self.grid.is_enabled() {
+            self.section(&mut encoder, "grid");
+            let mut render_pass = self.begin_overlay_pass(&mut encoder, "Grid Pass");
+            self.grid.render(&mut render_pass, &self.camera_bind_group);
+        }
+        // Sorted surfaces blend over the order-independent ones after, so
+        // where the two overlap the sorted ones always look in front.
+        self.section(&mut encoder, "oit");
+        let draws = self.material_draws(MaterialPass::WeightedBlended, |kind| {
+            kind.order_independent()
+        });
+        if !draws.is_empty() {
+            {
+                let mut render_pass = self.oit.begin_pass(&mut encoder, &self.depth_texture);
+                self.draw_materials(&mut render_pass, &draws);
+            }
+            self.oit.composite(&mut encoder, self.hdr.view());
+        }
+        if !self.transparent_draws.is_empty() {
+            self.section(&mut encoder, "sorted");
+            let mut render_pass = self.begin_overlay_pass(&mut encoder, "Transparent Pass");
+            self.draw_sorted(&mut render_pass, &self.transparent_draws);
+        }
+        if self.billboards.draw_calls() > 0 {
+            self.section(&mut encoder, "bboard");
+            let mut render_pass = self.begin_overlay_pass(&mut encoder, "Billboard Pass");
+            self.billboards
+                .render(&mut render_pass, &self.camera_bind_group);
+        }
+        self.draw_debug_shapes();
+        self.debug_draw.upload(&self.device, &self.queue);
+        if self.debug_draw.draw_calls() > 0 {
+            self.section(&mut encoder, "debug");
+            let mut render_pass = self.begin_overlay_pass(&mut encoder, "Debug Draw Pass");
+            self.debug_draw
+                .render(&mut render_pass, &self.camera_bind_group);
+        }
+        self.section(&mut encoder, "taa");
+        self.taa.resolve(&mut encoder, self.hdr.texture());
+        self.section(&mut encoder, "bloom");
+        self.bloom.render(&mut encoder, self.hdr.view());
+        self.section(&mut encoder, "exposure");
+        self.auto_exposure.render(
+            &self.device,
+            &mut encoder,
+            self.hdr.view(),
+            self.hdr.adapted_exposure(),
+            [self.config.width, self.config.height],
+        );
+        self.section(&mut encoder, "post");
+        if self.post.is_active() {
+            self.hdr.process(&mut encoder, self.post.input());
+            self.post.process(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &view,
+                &self.depth_texture.view,
+                &self.camera_buffer,
+            );
+        } else {
+            self.hdr.process(&mut encoder, &view);
+        }
+        if let Some(selection) = self.selection {
+            self.section(&mut encoder, "outline");
+            self.outline.render(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &view,
+                &self.view_camera,
+                [self.config.width, self.config.height],
+                &self.obj_model,
+                &self.instances,
+                selection.instance,
+            );
+        }
+        if !self.views.is_empty() {
+            self.section(&mut encoder, "views");
+            self.render_views(&mut encoder, &view);
+        }
+        if let Some(debug_view) = self.debug_view {
+            self.section(&mut encoder, "inspect");
+            self.render_debug_view(&mut encoder, &view, debug_view);
+        }
+        self.section(&mut encoder, "ui");
+        self.sprites.render(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &view,
+            self.config.width,
+            self.config.height,
+        );
+        self.text.render(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &view,
+            self.config.width,
+            self.config.height,
+        );
+        if let Some(frame) = debug_frame {
+            self.debug_ui.render(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                self.output.window().map(|w| w.as_ref()),
+                &view,
+                [self.config.width, self.config.height],
+                frame,
+            );
+        }
+
+        if let Some(cursor) = self.pick_request.take() {
+            self.section(&mut encoder, "picking");
+            self.id_picker.request(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &self.view_camera,
+                cursor,
+                [self.config.width, self.config.height],
+                &self.obj_model,
+                &self.instances,
+            );
+        }
+        if let Some(path) = self.capture_request.take() {
+            match FrameCapture::new(&self.device, &mut encoder, &output_texture) {
+                Ok(capture) => self.captures.push((capture, path)),
+                Err(e) => log::error!("Unable to capture frame: {}", e),
+            }
+        }
+        let window_frames = if self.windows.is_empty() {
+            Vec::new()
+        } else {
+            self.section(&mut encoder, "windows");
+            self.render_windows(&mut encoder)
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let video_frame = match &self.video {
+            Some(_) => match FrameCapture::new(&self.device, &mut encoder, &output_texture) {
+                Ok(capture) => Some(capture),
+                Err(e) => {
+                    log::error!("Unable to capture video frame: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        self.end_section(&mut encoder);
+        if let Some(gpu) = self.timing.gpu_mut() {
+            gpu.finish(&mut encoder);
+        }
+
+        let uploads = self.uploads.finish();
+        let commands = encoder.finish();
+        drop(encode);
+        let submit = self.frame_trace.span(Phase::Submit);
+        self.queue.submit(uploads.into_iter().chain([commands]));
+        drop(submit);
+        self.uploads.recall();
+        self.timing.set_upload_bytes(self.uploads.frame_bytes());
+        let present = self.frame_trace.span(Phase::Present);
+        if let Some(frame) = frame {
+            frame.present();
+        }
+        for frame in window_frames {
+            frame.present();
+        }
+        drop(present);
+        #[cfg(not(target_arch = "wasm32"))]
+        self.frame_trace.flush();
+        if debugger_capture {
+            // SAFETY: paired with the start above.
+            unsafe { self.device.stop_graphics_debugger_capture() };
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(capture) = video_frame
+            && let Some(video) = &mut self.video
+        {
+            let written = capture
+                .wait(&self.device)
+                .and_then(|image| video.write(image));
+            if let Err(e) = written {
+                log::error!("Stopping video capture: {:#}", e);
+                self.stop_video_capture();
+            }
+        }
+        if let Some(gpu) = self.timing.gpu_mut() {
+            gpu.after_submit(&self.device);
+        }
+        self.timing.set_draw_calls(
+            self.mesh_draw_calls()
+                + (self.billboards.draw_calls()
+                    + self.debug_draw.draw_calls()
+                    + self.sprites.draw_calls()) as u32,
+        );
+        self.timing.set_triangles(self.mesh_triangles());
+        self.debug_draw.clear();
+        self.finish_captures();
+        self.finish_picks();
+
+        Ok(())
+    }
+
+    /// Per mesh of the scene model, whether it is in view and its material
+    /// kind passes `include`.
+    fn meshes_in_view(&self, include: impl Fn(&dyn MaterialKind) -> bool) -> Vec<bool> {
+        self.meshes_of_kind(include)
+            .into_iter()
+            .enumerate()
+            .map(|(i, of_kind)| of_kind && self.visible_meshes.get(i).copied().unwrap_or(true))
+            .collect()
+    }
+
+    /// `meshes_in_view` from anywhere, for passes with cameras of their own.
+    fn meshes_of_kind(&self, include: impl Fn(&dyn MaterialKind) -> bool) -> Vec<bool> {
+        self.obj_model
+            .meshes
+            .iter()
+            .map(|mesh| {
+                self.materials
+                    .get(self.obj_model.materials[mesh.material].kind)
+                    .is_some_and(&include)
+            })
+            .collect()
+    }
+
+    /// The pipelines `pass` draws the scene model with, each with the
+    /// meshes in view whose material kind passes `include`. Kinds without a
+    /// built pipeline are left out.
+    fn material_draws(
+        &self,
+        pass: MaterialPass,
+        include: impl Fn(&dyn MaterialKind) -> bool,
+    ) -> Vec<(&wgpu::RenderPipeline, Vec<bool>)> {
+        self.material_draws_of(
+            self.meshes_in_view(include),
+            self.deferred.is_some(),
+            pass,
+            1,
+        )
+    }
+
+    /// `material_draws` for the meshes in `in_view`, as drawn on the
+    /// forward or deferred path into targets with `samples` samples a
+    /// pixel.
+    fn material_draws_of(
+        &self,
+        in_view: Vec<bool>,
+        deferred_path: bool,
+        pass: MaterialPass,
+        samples: u32,
+    ) -> Vec<(&wgpu::RenderPipeline, Vec<bool>)> {
+        let skinned = self.scene_defs.contains("SKINNED");
+        let mut draws: Vec<(PipelineKey, Vec<bool>)> = Vec::new();
+        for (i, mesh) in self.obj_model.meshes.iter().enumerate() {
+            let kind = self.obj_model.materials[mesh.material].kind;
+            if !in_view[i] || self.materials.pass(kind, deferred_path) != pass {
+                continue;
+            }
+            let key = PipelineKey {
+                material: kind,
+                skinned,
+                vertex_encoding: self.obj_model.vertex_encoding,
+                pass,
+                texture_arrays: false,
+                samples,
+            };
+            match draws.iter_mut().find(|(other, _)| *other == key) {
+                Some((_, meshes)) => meshes[i] = true,
+                None => {
+                    let mut meshes = vec![false; in_view.len()];
+                    meshes[i] = true;
+                    draws.push((key, meshes));
+                }
+            }
+        }
+        draws
+            .into_iter()
+            .filter_map(|(key, meshes)| Some((self.materials.pipeline(&key)?, meshes)))
+            .collect()
+    }
+
+    /// Draws the scene model's meshes with forward material pipelines.
+    fn draw_materials<'p>(
+        &'p self,
+        render_pass: &mut wgpu::RenderPass<'p>,
+        draws: &'p [(&wgpu::RenderPipeline, Vec<bool>)],
+    ) {
+        self.draw_materials_from(
+            render_pass,
+            draws,
+            Some((&self.gpu_culling, &self.lods)),
+            &self.camera_bind_group,
+        );
+    }
+
+    /// `draw_materials`, or with parallel encoding or static bundles, the
+    /// same draws from render bundles, encoded across threads or replayed.
+    fn draw_opaque_materials<'p>(
+        &'p self,
+        render_pass: &mut wgpu::RenderPass<'p>,
+        draws: &'p [(&wgpu::RenderPipeline, Vec<bool>)],
+    ) {
+        let parallel_encoding = self.parallel_encoding && !cfg!(target_arch = "wasm32");
+        if !parallel_encoding && !self.static_bundles {
+            self.draw_materials(render_pass, draws);
+            return;
+        }
+        let instances =
+            DrawInstances::new(&self.instances, Some(&self.gpu_culling)).with_lods(&self.lods);
+        let draws = draws
+            .iter()
+            .flat_map(|(pipeline, meshes)| {
+                meshes
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &visible)| visible)
+                    .map(move |(i, _)| (*pipeline, i))
+            })
+            .collect::<Vec<_>>();
+        let bind_groups = [
+            &self.camera_bind_group,
+            self.lights.bind_group(),
+            &self.shadow_map.bind_group,
+        ];
+        let threads = if parallel_encoding {
+            parallel::available_threads()
+        } else {
+            1
+        };
+        let encode = || {
+            parallel::encode_bundles(
+                &self.device,
+                &self.obj_model,
+                instances,
+                &draws,
+                bind_groups,
+                threads,
+                self.msaa_samples(),
+            )
+        };
+        let key = BundleKey::new(&self.obj_model, instances.source(), &draws, bind_groups)
+            .filter(|_| self.static_bundles);
+        match key {
+            Some(key) => self.bundle_cache.replay(render_pass, key, encode),
+            None => render_pass.execute_bundles(&encode()),
+        }
+    }
+
+    /// `draw_materials` from the camera in `camera_bind_group`, drawing
+    /// the instances `culling` kept, those of the view camera, or, without
+    /// it, all of them.
+    fn draw_materials_from<'p>(
+        &'p self,
+        render_pass: &mut wgpu::RenderPass<'p>,
+        draws: &'p [(&wgpu::RenderPipeline, Vec<bool>)],
+        culling: Option<(&'p GpuCulling, &'p LodInstances)>,
+        camera_bind_group: &'p wgpu::BindGroup,
+    ) {
+        for (pipeline, meshes) in draws {
+            let mut instances =
+                DrawInstances::new(&self.instances, culling.map(|(gpu_culling, _)| gpu_culling))
+                    .with_visible_meshes(meshes);
+            if let Some((_, lods)) = culling {
+                instances = instances.with_lods(lods);
+            }
+            if instances.is_empty() {
+                continue;
+            }
+            let (pipeline, instances) = self.with_texture_arrays(pipeline, instances);
+            render_pass.set_vertex_buffer(1, instances.slice());
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &self.diffuse_material.bind_group, &[]);
+            render_pass.set_bind_group(1, camera_bind_group, &[]);
+            render_pass.set_bind_group(2, self.lights.bind_group(), &[]);
+            render_pass.set_bind_group(3, &self.shadow_map.bind_group, &[]);
+            instances.draw_model(
+                render_pass,
+                &self.obj_model,
+                camera_bind_group,
+                self.lights.bind_group(),
+            );
+        }
+    }
+
+    /// `pipeline`'s `PipelineKey::texture_arrays` variant, with `instances`
+    /// reading every material from the packed arrays, when there are both
+    /// and the draws are batched; otherwise both as they are.
+    fn with_texture_arrays<'p>(
+        &'p self,
+        pipeline: &'p wgpu::RenderPipeline,
+        instances: DrawInstances<'p>,
+    ) -> (&'p wgpu::RenderPipeline, DrawInstances<'p>) {
+        let arrays = self
+            .material_arrays
+            .as_ref()
+            .filter(|_| instances.is_batched());
+        match arrays.zip(self.materials.texture_array_pipeline(pipeline)) {
+            Some((arrays, pipeline)) => (pipeline, instances.with_texture_arrays(arrays)),
+            None => (pipeline, instances),
+        }
+    }
+
+    /// Draws `draws` one instance at a time in their order, switching
+    /// pipelines only between meshes of different kinds.
+    fn draw_sorted<'p>(&'p self, render_pass: &mut wgpu::RenderPass<'p>, draws: &[SortedDraw]) {
+        let skinned = self.scene_defs.contains("SKINNED");
+        let mut bound = None;
+        render_pass.set_vertex_buffer(1, self.instances.slice());
+        render_pass.set_bind_group(3, &self.shadow_map.bind_group, &[]);
+        for draw in draws {
+            let mesh = &self.obj_model.meshes[draw.mesh];
+            let material = &self.obj_model.materials[mesh.material];
+            let key = PipelineKey {
+                material: material.kind,
+                skinned,
+                vertex_encoding: self.obj_model.vertex_encoding,
+                pass: MaterialPass::Forward,
+                texture_arrays: false,
+                samples: 1,
+            };
+            if bound != Some(key) {
+                let Some(pipeline) = self.materials.pipeline(&key) else {
+                    continue;
+                };
+                render_pass.set_pipeline(pipeline);
+                bound = Some(key);
+            }
+            render_pass.draw_mesh_instanced(
+                mesh,
+                material,
+                draw.instance..draw.instance + 1,
+                &self.camera_bind_group,
+                self.lights.bind_group(),
+            );
+        }
+    }
+
+    /// Draws the terrain from the main camera, multisampled in the forward
+    /// paths' opaque pass while MSAA is on.
+    fn draw_terrain<'p>(&'p self, render_pass: &mut wgpu::RenderPass<'p>, samples: u32) {
+        self.draw_terrain_from(render_pass, &self.camera_bind_group, true, samples);
+    }
+
+    fn draw_terrain_from<'p>(
+        &'p self,
+        render_pass: &mut wgpu::RenderPass<'p>,
+        camera_bind_group: &'p wgpu::BindGroup,
+        culled: bool,
+        samples: u32,
+    ) {
+        let Some(terrain) = &self.terrain else {
+            return;
+        };
+        let key = PipelineKey {
+            samples,
+            ..terrain.pipeline_key()
+        };
+        let Some(pipeline) = self.materials.pipeline(&key) else {
+            return;
+        };
+        terrain.draw(
+            render_pass,
+            pipeline,
+            [
+                camera_bind_group,
+                self.lights.bind_group(),
+                &self.shadow_map.bind_group,
+            ],
+            culled,
+        );
+    }
+
+    /// Draws the opaque scene forward into the water's reflection and
+    /// refraction targets. Nothing is culled, since the culling follows the
+    /// main camera.
+    fn render_water_passes(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(water) = &self.water else {
+            return;
+        };
+        let draws = self.material_draws_of(
+            self.meshes_of_kind(|kind| !kind.is_transparent()),
+            false,
+            MaterialPass::Forward,
+            1,
+        );
+        for pass in WaterPass::ALL {
+            let Some(camera_bind_group) = water.camera_bind_group(pass) else {
+                continue;
+            };
+            let mut render_pass = water.begin_pass(encoder, pass);
+            self.draw_materials_from(&mut render_pass, &draws, None, camera_bind_group);
+            self.draw_terrain_from(&mut render_pass, camera_bind_group, false, 1);
+        }
+    }
+
+    /// A pass drawing forward materials over the HDR, velocity and depth
+    /// targets as they are.
+    fn begin_overlay_pass<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        label: &str,
+    ) -> wgpu::RenderPass<'e> {
+        let attachment = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[
+                attachment(self.hdr.view()),
+                attachment(&self.taa.velocity().view),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    /// Ends the frame's current section and starts one called `label`: a
+    /// debug group in graphics debugger captures, and a timed span in the
+    /// GPU profiler.
+    fn section(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str) {
+        self.end_section(encoder);
+        encoder.push_debug_group(label);
+        self.section_open = true;
+        if let Some(gpu) = self.timing.gpu_mut() {
+            gpu.mark(encoder, label);
+        }
+    }
+
+    fn end_section(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if std::mem::take(&mut self.section_open) {
+            encoder.pop_debug_group();
+        }
+    }
+
+    /// One draw per mesh in each shadow pass, plus one per visible opaque
+    /// mesh in the main or G-buffer pass and in the SSAO prepass, one per
+    /// sorted transparent instance, one per visible order-independent mesh
+    /// and one per visible terrain chunk. Each water
+    /// pass adds one per opaque mesh and terrain chunk.
+    fn mesh_draw_calls(&self) -> u32 {
+        let count = |meshes: Vec<bool>| meshes.into_iter().filter(|&visible| visible).count();
+        let mut terrain = self.terrain.as_ref().map_or(0, Terrain::visible_chunks);
+        let mut water = 0;
+        if self.water.is_some() {
+            let chunks = self
+                .terrain
+                .as_ref()
+                .map_or(0, |terrain| terrain.chunks().len());
+            terrain += WaterPass::ALL.len() * chunks;
+            if !self.instances.is_empty() {
+                water = WaterPass::ALL.len()
+                    * count(self.meshes_of_kind(|kind| !kind.is_transparent()));
+            }
+        }
+        if self.instances.is_empty() {
+            return terrain as u32;
+        }
+        let shadow_passes = if self.shadows_enabled() {
+            self.shadow_map.pass_count()
+        } else {
+            0
+        };
+        let visible = count(self.meshes_in_view(|kind| !kind.is_transparent()))
+            + self.transparent_draws.len()
+            + count(self.meshes_in_view(|kind| kind.is_transparent() && kind.order_independent()));
+        let prepass = if self.ssao.writes_depth() {
+            count(self.meshes_in_view(|kind| !kind.is_transparent()))
+        } else {
+            0
+        };
+        (shadow_passes * self.obj_model.meshes.len() + visible + prepass + water + terrain) as u32
+    }
+
+    // Triangles of the scene model's meshes the main view drew, counting
+    // every instance at full detail.
+    fn mesh_triangles(&self) -> u64 {
+        let per_instance: u64 = self
+            .meshes_in_view(|_| true)
+            .into_iter()
+            .zip(&self.obj_model.meshes)
+            .filter(|(visible, _)| *visible)
+            .map(|(_, mesh)| mesh.num_elements as u64 / 3)
+            .sum();
+        per_instance * self.instances.len() as u64
+    }
+
+    /// Starts recording input, along with each frame's length, for
+    /// `stop_recording` to hand back. Any recording so far is dropped.
+    pub fn start_recording(&mut self) {
+        self.recording = Some((InputRecording::default(), Vec::new()));
+    }
+
+    /// The input since `start_recording`, up to the last update.
+    pub fn stop_recording(&mut self) -> Option<InputRecording> {
+        self.recording.take().map(|(recording, _)| recording)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Plays `recording` back from the next update on, one recorded frame
+    /// per update. Live input is ignored until it runs out.
+    pub fn start_replay(&mut self, recording: InputRecording) {
+        self.camera_controller.release_all();
+        self.replay = Some((recording, 0));
+    }
+
+    pub fn stop_replay(&mut self) {
+        self.replay = None;
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Whether something, such as the exit key, has asked for the
+    /// application to close.
+    pub fn exit_requested(&self) -> bool {
+        self.exit_requested
+    }
+
+    /// Reacts to `event` from the user, and records it while recording.
+    /// Ignored while replaying.
+    pub(crate) fn handle_input_event(&mut self, event: InputEvent) {
+        if self.replay.is_some() {
+            return;
+        }
+        if let Some((_, events)) = &mut self.recording {
+            // Keys that cannot be bound do nothing, and could not be named.
+            let nameable = match event {
+                InputEvent::Key { code, .. } => input::parse_key(&format!("{:?}", code)).is_some(),
+                _ => true,
+            };
+            if nameable {
+                events.push(event);
+            }
+        }
+        self.apply_input_event(event);
+    }
+
+    fn apply_input_event(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::Key { code, pressed } => self.handle_key(code, pressed),
+            InputEvent::Modifiers(modifiers) => self.modifiers = modifiers,
+            InputEvent::MouseButton { button, pressed } => {
+                self.handle_mouse_button(button, pressed)
+            }
+            InputEvent::CursorMoved { x, y } => self.handle_cursor_moved(x, y),
+            InputEvent::Wheel(delta) => self.handle_wheel(delta),
+            InputEvent::Touch { id, phase, x, y } => self.handle_touch(id, phase, x, y),
+        }
+    }
+
+    /// This frame's length: a video capture's frame time while one runs,
+    /// else the recorded one while replaying, and otherwise the time since
+    /// the last update. Replays feed in the events recorded with the
+    /// frame, and recordings keep the length returned.
+    fn frame_dt(&mut self) -> f32 {
+        let now = web_time::Instant::now();
+        let elapsed = self
+            .last_update
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        self.last_update = Some(now);
+        #[cfg(not(target_arch = "wasm32"))]
+        let fixed = self
+            .video
+            .as_ref()
+            .map(VideoCapture::frame_time)
+            .or(self.fixed_frame_time);
+        #[cfg(target_arch = "wasm32")]
+        let fixed = self.fixed_frame_time;
+        let elapsed = fixed.unwrap_or(elapsed);
+
+        if let Some((recording, next)) = &mut self.replay {
+            match recording.frames.get(*next) {
+                Some(frame) => {
+                    let frame = frame.clone();
+                    *next += 1;
+                    for event in frame.events {
+                        self.apply_input_event(event);
+                    }
+                    return fixed.unwrap_or(frame.dt);
+                }
+                None => {
+                    log::info!("Replay finished after {} frames", recording.frames.len());
+                    self.replay = None;
+                }
+            }
+        }
+        if let Some((recording, events)) = &mut self.recording {
+            recording.frames.push(RecordedFrame {
+                events: std::mem::take(events),
+                dt: elapsed,
+            });
+        }
+        elapsed
+    }
+
+    fn handle_cursor_moved(&mut self, x: f64, y: f64) {
+        let cursor = [x as f32, y as f32];
+        let last = self.cursor.replace(cursor);
+        if let Some(drag) = self.gizmo_drag {
+            if let Some(instance) = self.cursor_ray(cursor).and_then(|ray| drag.update(&ray)) {
+                self.update_selected_instance(instance);
+            }
+            return;
+        }
+        if let Some([last_x, last_y]) = last {
+            for (axis, updates) in Axis::cursor([cursor[0] - last_x, cursor[1] - last_y]) {
+                self.handle_axis(axis, updates);
+            }
+        }
+    }
+
+    /// Feeds the camera controller where a gamepad's sticks and triggers
+    /// are. Call it each frame, before `update`, while one is connected,
+    /// and once with the default when it goes away.
+    pub fn set_gamepad(&mut self, gamepad: GamepadState) {
+        self.camera_controller.set_gamepad(gamepad);
+    }
+
+    fn handle_mouse_button(&mut self, button: MouseButton, is_pressed: bool) {
+        if button == MouseButton::Left {
+            // A press on a gizmo handle drags it instead of picking.
+            if !is_pressed && self.gizmo_drag.take().is_some() {
+                return;
+            }
+            if is_pressed {
+                self.gizmo_drag = self.grab_gizmo();
+                if self.gizmo_drag.is_some() {
+                    return;
+                }
+                self.handle_click();
+            }
+        }
+        self.handle_input(Input::Mouse(button), is_pressed);
+    }
+
+    fn handle_wheel(&mut self, delta: MouseScrollDelta) {
+        for (axis, updates) in Axis::wheel(delta) {
+            self.handle_axis(axis, updates);
+        }
+    }
+
+    fn handle_touch(&mut self, id: u64, phase: TouchPhase, x: f64, y: f64) {
+        for (action, updates) in self.touches.handle(id, phase, [x as f32, y as f32]) {
+            self.camera_controller.nudge(action, updates);
+        }
+    }
+
+    fn handle_input(&mut self, input: Input, is_pressed: bool) {
+        for action in self.input_map.actions(input) {
+            self.camera_controller.handle_action(action, is_pressed);
+        }
+    }
+
+    fn handle_axis(&mut self, axis: Axis, updates: f32) {
+        if updates == 0.0 {
+            return;
+        }
+        for action in self.input_map.actions(Input::Axis(axis)) {
+            self.camera_controller.nudge(action, updates);
+        }
+    }
+
+    fn handle_click(&mut self) {
+        let Some(cursor) = self.cursor else {
+            return;
+        };
+        if self.pick_mode == PickMode::IdBuffer {
+            self.request_pick(cursor);
+        } else {
+            self.select(self.pick(cursor, self.pick_mode));
+        }
+    }
+
+    fn grab_gizmo(&self) -> Option<GizmoDrag> {
+        let gizmo = self.gizmo()?;
+        let ray = self.cursor_ray(self.cursor?)?;
+        let axis = gizmo.handle(&ray)?;
+        gizmo.drag(axis, &ray, self.selected_instance()?)
+    }
+
+    fn select(&mut self, selection: Option<PickHit>) {
+        self.selection = selection;
+        match &self.selection {
+            Some(hit) => log::info!(
+                "Picked mesh {} instance {} at {:?}",
+                hit.mesh,
+                hit.instance,
+                hit.position
+            ),
+            None => log::info!("Picked nothing"),
+        }
+    }
+
+    /// Selects what the newest ID pass read back, if it has arrived.
+    fn finish_picks(&mut self) {
+        self.id_picker.map();
+        let _ = self.device.poll(wgpu::PollType::Poll);
+        if let Some(mut hit) = self.id_picker.poll() {
+            if let Some(hit) = &mut hit {
+                hit.entity = self.instance_entity(hit.instance);
+            }
+            self.select(hit);
+        }
+    }
+
+    fn handle_key(&mut self, code: KeyCode, is_pressed: bool) {
+        // Ctrl and a digit saves a bookmark; the digit alone eases back to
+        // it, and with Shift jumps there. Empty slots leave the digit to the
+        // input map.
+        if is_pressed && let Some(slot) = bookmark_slot(code) {
+            if self.modifiers.control_key() {
+                self.save_camera_bookmark(slot);
+                return;
+            }
+            if self.recall_camera_bookmark(slot, !self.modifiers.shift_key()) {
+                return;
+            }
+        }
+        let keys = self.key_bindings;
+        match (code, is_pressed) {
+            (code, true) if code == keys.exit => self.exit_requested = true,
+            (code, true) if code == keys.projection => self.camera.toggle_projection(),
+            (code, true) if code == keys.debug_ui => self.debug_ui.visible = !self.debug_ui.visible,
+            (code, true) if code == keys.screenshot => {
+                let seconds = web_time::SystemTime::now()
+                    .duration_since(web_time::UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_secs());
+                self.capture_frame(format!("screenshot-{}.png", seconds));
+            }
+            (code, true) if code == keys.debugger_capture => self.request_debugger_capture(),
+            (code, true) if code == keys.present_mode => self.cycle_present_mode(),
+            (code, true) if code == keys.fullscreen && self.modifiers.alt_key() => {
+                self.toggle_fullscreen()
+            }
+            (code, true) if code == keys.keyframe => self.add_camera_keyframe(),
+            (code, true) if code == keys.flythrough => {
+                if self.is_playing_camera_path() {
+                    self.stop_camera_path();
+                } else {
+                    self.play_camera_path();
+                }
+            }
+            (code, true) if code == keys.stats => {
+                self.debug_ui.stats_visible = !self.debug_ui.stats_visible;
+            }
+            (code, true) if code == keys.grid => {
+                let mut grid = self.grid_settings();
+                grid.enabled = !grid.enabled;
+                self.set_grid_settings(grid);
+            }
+            (code, true) if code == keys.cascades => {
+                self.shadow_map.debug_cascades = !self.shadow_map.debug_cascades;
+            }
+            (code, true) if code == keys.debug_view && self.modifiers.shift_key() => {
+                if let Some(view) = &mut self.debug_view {
+                    view.rect = if view.rect == ViewportRect::FULL {
+                        ViewportRect::INSET
+                    } else {
+                        ViewportRect::FULL
+                    };
+                }
+            }
+            (code, true) if code == keys.debug_view => self.cycle_debug_view(),
+            (code, true) if code == keys.editor && self.modifiers.shift_key() => {
+                self.editor.mode = self.editor.mode.next();
+                self.gizmo_drag = None;
+            }
+            (code, true) if code == keys.editor => self.set_editor_settings(EditorSettings {
+                enabled: !self.editor.enabled,
+                ..self.editor
+            }),
+            _ => self.handle_input(Input::Key(code), is_pressed),
+        }
+    }
+}
+
+// The bookmark slot a digit key stands for.
+fn bookmark_slot(code: KeyCode) -> Option<usize> {
+    const DIGITS: [KeyCode; bookmarks::SLOTS] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    DIGITS
+        .iter()
+        .position(|&digit| digit == code)
+        .map(|i| i + 1)
+}
+
+// Views other than the main one pass `ScreenSpaceReflections::disabled`,
+// having no previous frame of their own kept.
+#[allow(clippy::too_many_arguments)]
+fn create_camera_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    camera_buffer: &wgpu::Buffer,
+    environment: &Environment,
+    reflection_probes: &ReflectionProbes,
+    occlusion: &wgpu::TextureView,
+    reflections: SsrBindings,
+    clusters: &LightClusters,
+    joints: &JointPalette,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&environment.irradiance.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&environment.prefiltered.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&environment.prefiltered.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&environment.brdf_lut.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::Sampler(&environment.brdf_lut.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureView(occlusion),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: clusters.uniform_buffer().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: clusters.cluster_buffer().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: joints.buffer().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: wgpu::BindingResource::TextureView(reflections.color),
+            },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: wgpu::BindingResource::TextureView(reflections.position),
+            },
+            wgpu::BindGroupEntry {
+                binding: 12,
+                resource: reflections.uniform.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 13,
+                resource: wgpu::BindingResource::TextureView(&reflection_probes.prefiltered().view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 14,
+                resource: reflection_probes.uniform_buffer().as_entire_binding(),
+            },
+        ],
+        label: Some("camera_bind_group"),
+    })
+}
+
+/// The pipelines the materials of `model` need on the forward or deferred
+/// path with `defs`, and with `texture_arrays`, the variants of the opaque
+/// ones that read from `MaterialArrays`.
+fn pipeline_keys(
+    materials: &MaterialRegistry,
+    model: &model::Model,
+    defs: &ShaderDefs,
+    deferred_path: bool,
+    texture_arrays: bool,
+) -> Vec<PipelineKey> {
+    let mut keys = Vec::new();
+    for material in &model.materials {
+        let key = PipelineKey {
+            material: material.kind,
+            skinned: defs.contains("SKINNED"),
+            vertex_encoding: model.vertex_encoding,
+            pass: materials.pass(material.kind, deferred_path),
+            texture_arrays: false,
+            samples: 1,
+        };
+        let arrays = texture_arrays
+            && materials
+                .get(material.kind)
+                .is_some_and(|kind| kind.texture_arrays() && !kind.is_transparent());
+        let variant = arrays.then_some(PipelineKey {
+            texture_arrays: true,
+            ..key
+        });
+        for key in [Some(key), variant].into_iter().flatten() {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+    keys
+}
+
+/// The forward pipelines the water passes draw the opaque materials of
+/// `model` with, whichever path the scene is on.
+/// The forward pipelines passes with cameras of their own, such as the
+/// water's, render targets and views, draw the opaque scene with.
+fn opaque_forward_keys(
+    materials: &MaterialRegistry,
+    model: &model::Model,
+    defs: &ShaderDefs,
+) -> Vec<PipelineKey> {
+    pipeline_keys(materials, model, defs, false, false)
+        .into_iter()
+        .filter(|key| {
+            materials
+                .get(key.material)
+                .is_some_and(|kind| !kind.is_transparent())
+        })
+        .collect()
+}
+
+fn create_material_pipelines(
+    materials: &MaterialRegistry,
+    keys: &[PipelineKey],
+    context: &PipelineContext,
+) -> anyhow::Result<HashMap<PipelineKey, wgpu::RenderPipeline>> {
+    keys.iter()
+        .map(|key| Ok((*key, materials.create_pipeline(key, context)?)))
+        .collect()
+}
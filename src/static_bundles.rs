@@ -0,0 +1,88 @@
+use std::cell::{Cell, RefCell};
+
+use crate::culling::InstanceSource;
+use crate::model;
+use crate::parallel::MeshDraw;
+
+/// Everything recorded bundles were recorded from: while it stays the
+/// same, replaying them draws what recording them again would.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleKey {
+    // Each draw's pipeline and the mesh's vertex buffer and material.
+    draws: Vec<(wgpu::RenderPipeline, wgpu::Buffer, wgpu::BindGroup)>,
+    bind_groups: [wgpu::BindGroup; 3],
+    instances: InstanceSource,
+}
+
+impl BundleKey {
+    /// The key for `parallel::encode_bundles` of `draws`, or None when
+    /// the instances change every frame, so there's nothing to replay.
+    pub fn new(
+        model: &model::Model,
+        instances: Option<InstanceSource>,
+        draws: &[MeshDraw<'_>],
+        bind_groups: [&wgpu::BindGroup; 3],
+    ) -> Option<Self> {
+        Some(Self {
+            draws: draws
+                .iter()
+                .map(|&(pipeline, index)| {
+                    let mesh = &model.meshes[index];
+                    (
+                        pipeline.clone(),
+                        mesh.vertex_buffer.clone(),
+                        model.materials[mesh.material].bind_group.clone(),
+                    )
+                })
+                .collect(),
+            bind_groups: bind_groups.map(Clone::clone),
+            instances: instances?,
+        })
+    }
+}
+
+/// Render bundles of the forward pass's opaque draws, recorded once and
+/// replayed every frame until their `BundleKey` changes, which skips
+/// encoding the draws again while the scene holds still.
+///
+/// Recorded from `&self`, as it is while the pass borrows the state.
+#[derive(Default)]
+pub struct StaticBundles {
+    recorded: RefCell<Option<(BundleKey, Vec<wgpu::RenderBundle>)>>,
+    recordings: Cell<u64>,
+}
+
+impl StaticBundles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Executes the bundles recorded for `key` in `pass`, running `record`
+    /// for them first unless the last ones were recorded for it too.
+    pub fn replay(
+        &self,
+        pass: &mut wgpu::RenderPass<'_>,
+        key: BundleKey,
+        record: impl FnOnce() -> Vec<wgpu::RenderBundle>,
+    ) {
+        let mut recorded = self.recorded.borrow_mut();
+        if recorded.as_ref().is_none_or(|(last, _)| *last != key) {
+            *recorded = Some((key, record()));
+            self.recordings.set(self.recordings.get() + 1);
+        }
+        if let Some((_, bundles)) = recorded.as_ref() {
+            pass.execute_bundles(bundles);
+        }
+    }
+
+    /// Drops the recorded bundles, so they don't hold on to what they drew
+    /// with while nothing replays them.
+    pub fn clear(&self) {
+        self.recorded.replace(None);
+    }
+
+    /// How many times the bundles have been recorded.
+    pub fn recordings(&self) -> u64 {
+        self.recordings.get()
+    }
+}
@@ -0,0 +1,538 @@
+use cgmath::InnerSpace;
+use wgpu::util::DeviceExt;
+
+use crate::culling::Frustum;
+use crate::instance::Instance;
+use crate::material::{MaterialKind, MaterialKindId, MaterialPass, PipelineKey};
+use crate::mesh::MeshData;
+use crate::model::{self, ModelVertex};
+use crate::preprocess::ShaderDefs;
+use crate::{resources, texture};
+
+/// How a heightmap is turned into terrain.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TerrainSettings {
+    /// World units between neighbouring heightmap samples.
+    pub spacing: f32,
+    /// Height of a sample of 1.0; heights start at 0.0.
+    pub height_scale: f32,
+    /// Quads along each side of a chunk, the unit chunks are culled in.
+    pub chunk_size: u32,
+    /// How many times the splat layers repeat across the terrain.
+    pub tiling: f32,
+    pub layer_roughness: [f32; 4],
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            spacing: 1.0,
+            height_scale: 32.0,
+            chunk_size: 32,
+            tiling: 64.0,
+            layer_roughness: [0.9, 0.85, 0.7, 0.5],
+        }
+    }
+}
+
+/// A grid of heights from 0.0 to 1.0, row by row along +Z.
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    width: u32,
+    depth: u32,
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    /// `heights` holds `width * depth` samples, each row along +X.
+    pub fn new(width: u32, depth: u32, heights: Vec<f32>) -> anyhow::Result<Self> {
+        if width < 2 || depth < 2 {
+            anyhow::bail!(
+                "a heightmap needs at least 2x2 samples, not {}x{}",
+                width,
+                depth
+            );
+        }
+        if heights.len() != (width * depth) as usize {
+            anyhow::bail!(
+                "a {}x{} heightmap needs {} samples, not {}",
+                width,
+                depth,
+                width * depth,
+                heights.len()
+            );
+        }
+        Ok(Self {
+            width,
+            depth,
+            heights,
+        })
+    }
+
+    /// Samples `height(x, z)` for every grid point.
+    pub fn from_fn(
+        width: u32,
+        depth: u32,
+        height: impl Fn(u32, u32) -> f32,
+    ) -> anyhow::Result<Self> {
+        let heights = (0..depth)
+            .flat_map(|z| (0..width).map(move |x| (x, z)))
+            .map(|(x, z)| height(x, z))
+            .collect();
+        Self::new(width, depth, heights)
+    }
+
+    /// Uses the image's luminance, at 16 bits so smooth slopes do not step.
+    pub fn from_image(image: &image::DynamicImage) -> anyhow::Result<Self> {
+        let luma = image.to_luma16();
+        let heights = luma
+            .pixels()
+            .map(|pixel| pixel.0[0] as f32 / u16::MAX as f32)
+            .collect();
+        Self::new(luma.width(), luma.height(), heights)
+    }
+
+    pub async fn load(file_name: &str) -> anyhow::Result<Self> {
+        let data = resources::load_binary(file_name).await?;
+        Self::from_image(&image::load_from_memory(&data)?)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// The sample at (`x`, `z`), clamped to the edges.
+    pub fn get(&self, x: i64, z: i64) -> f32 {
+        let x = x.clamp(0, self.width as i64 - 1) as usize;
+        let z = z.clamp(0, self.depth as i64 - 1) as usize;
+        self.heights[z * self.width as usize + x]
+    }
+
+    // World-space normal at a sample, from the slopes to its neighbours.
+    fn normal(&self, x: i64, z: i64, settings: &TerrainSettings) -> cgmath::Vector3<f32> {
+        let scale = settings.height_scale / (2.0 * settings.spacing);
+        let dx = (self.get(x + 1, z) - self.get(x - 1, z)) * scale;
+        let dz = (self.get(x, z + 1) - self.get(x, z - 1)) * scale;
+        cgmath::Vector3::new(-dx, 1.0, -dz).normalize()
+    }
+}
+
+/// What the terrain shader blends: four layers tiled over the terrain,
+/// weighted by the channels of a splat map stretched over all of it.
+#[derive(Clone)]
+pub struct Splatting {
+    /// Weights for layers 0 to 3 in r, g, b and a; they need not sum to one.
+    pub weights: image::RgbaImage,
+    pub layers: [image::DynamicImage; 4],
+}
+
+impl Splatting {
+    /// Sand, grass, rock and snow in flat colors, placed by height and
+    /// slope: sand at the bottom, rock wherever it is steep and snow near
+    /// the top.
+    pub fn from_heightmap(heightmap: &Heightmap, settings: &TerrainSettings) -> Self {
+        fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+            let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+            t * t * (3.0 - 2.0 * t)
+        }
+
+        let weights = image::RgbaImage::from_fn(heightmap.width, heightmap.depth, |x, z| {
+            let height = heightmap.get(x as i64, z as i64);
+            let slope = 1.0 - heightmap.normal(x as i64, z as i64, settings).y;
+            let rock = smoothstep(0.15, 0.3, slope);
+            let snow = smoothstep(0.65, 0.8, height) * (1.0 - rock);
+            let sand = (1.0 - smoothstep(0.04, 0.1, height)) * (1.0 - rock);
+            let grass = (1.0 - rock - snow - sand).max(0.0);
+            image::Rgba([sand, grass, rock, snow].map(|w| (w * 255.0).round() as u8))
+        });
+        let color = |rgb: [u8; 3]| {
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                1,
+                1,
+                image::Rgba([rgb[0], rgb[1], rgb[2], 255]),
+            ))
+        };
+        Self {
+            weights,
+            layers: [
+                color([194, 178, 128]),
+                color([86, 125, 70]),
+                color([110, 105, 100]),
+                color([240, 240, 245]),
+            ],
+        }
+    }
+
+    pub async fn load(weights: &str, layers: [&str; 4]) -> anyhow::Result<Self> {
+        let data = resources::load_binary(weights).await?;
+        let weights = image::load_from_memory(&data)?.to_rgba8();
+        let mut images = Vec::with_capacity(4);
+        for file_name in layers {
+            let data = resources::load_binary(file_name).await?;
+            images.push(image::load_from_memory(&data)?);
+        }
+        Ok(Self {
+            weights,
+            layers: images.try_into().unwrap(),
+        })
+    }
+}
+
+/// The material kind terrain is drawn with: shader.wgsl's surface swapped
+/// for include/terrain.wgsl's splatting, drawn forward on both paths.
+pub struct TerrainMaterial {
+    layout: wgpu::BindGroupLayout,
+}
+
+impl TerrainMaterial {
+    pub const NAME: &'static str = "terrain";
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture(0),
+                sampler(1),
+                texture(2),
+                texture(3),
+                texture(4),
+                texture(5),
+                sampler(6),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("terrain_bind_group_layout"),
+        });
+        Self { layout }
+    }
+}
+
+impl MaterialKind for TerrainMaterial {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    fn shader_defs(&self) -> ShaderDefs {
+        ShaderDefs::new().with_flag("TERRAIN")
+    }
+
+    // Chunks are drawn by `Terrain::draw`, which the G-buffer pass does not
+    // call.
+    fn deferred(&self) -> bool {
+        false
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TerrainUniform {
+    layer_roughness: [f32; 4],
+    tiling: f32,
+    _padding: [f32; 3],
+}
+
+/// A heightmap built into chunks centered on the origin, each culled
+/// against the camera on its own.
+///
+/// Chunks receive shadows but cast none, and are left out of the SSAO
+/// prepass.
+pub struct Terrain {
+    heightmap: Heightmap,
+    settings: TerrainSettings,
+    material: MaterialKindId,
+    chunks: Vec<model::Mesh>,
+    visible: Vec<bool>,
+    // A single identity instance for the scene's vertex layout.
+    instance_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Terrain {
+    /// `layout` is the bind group layout of `material`, a
+    /// `TerrainMaterial` registered with the scene's `MaterialRegistry`.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        heightmap: Heightmap,
+        splatting: &Splatting,
+        settings: TerrainSettings,
+        material: MaterialKindId,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let chunk_size = settings.chunk_size.max(1);
+        let mut chunks = Vec::new();
+        for z in (0..heightmap.depth - 1).step_by(chunk_size as usize) {
+            for x in (0..heightmap.width - 1).step_by(chunk_size as usize) {
+                let x1 = (x + chunk_size).min(heightmap.width - 1);
+                let z1 = (z + chunk_size).min(heightmap.depth - 1);
+                let data = build_chunk(&heightmap, &settings, [x, z], [x1, z1]);
+                chunks.push(data.upload(device, &format!("Terrain Chunk {},{}", x, z), 0));
+            }
+        }
+
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Instance Buffer"),
+            contents: bytemuck::cast_slice(&[Instance::new(
+                cgmath::Vector3::new(0.0, 0.0, 0.0),
+                cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            )
+            .to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Buffer"),
+            contents: bytemuck::cast_slice(&[TerrainUniform {
+                layer_roughness: settings.layer_roughness,
+                tiling: settings.tiling,
+                _padding: [0.0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let splat = texture::Texture::linear_from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(splatting.weights.clone()),
+            Some("terrain_splat"),
+        )?;
+        // Stretched over the terrain once, so its edges must not wrap.
+        let splat_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("terrain_splat_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let mut layers = Vec::with_capacity(4);
+        for (i, layer) in splatting.layers.iter().enumerate() {
+            let label = format!("terrain_layer_{}", i);
+            layers.push(texture::Texture::from_image(
+                device,
+                queue,
+                layer,
+                Some(&label),
+            )?);
+        }
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&splat.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&splat_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&layers[0].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&layers[1].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&layers[2].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&layers[3].view),
+                },
+                // Repeating and trilinear, like every material texture.
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&layers[0].sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("terrain_bind_group"),
+        });
+
+        Ok(Self {
+            heightmap,
+            settings,
+            material,
+            visible: vec![true; chunks.len()],
+            chunks,
+            instance_buffer,
+            bind_group,
+        })
+    }
+
+    pub fn heightmap(&self) -> &Heightmap {
+        &self.heightmap
+    }
+
+    pub fn settings(&self) -> &TerrainSettings {
+        &self.settings
+    }
+
+    pub fn material(&self) -> MaterialKindId {
+        self.material
+    }
+
+    /// The forward pipeline chunks are drawn with.
+    pub fn pipeline_key(&self) -> PipelineKey {
+        PipelineKey {
+            material: self.material,
+            skinned: false,
+            vertex_encoding: model::VertexEncoding::Full,
+            pass: MaterialPass::Forward,
+            texture_arrays: false,
+            samples: 1,
+        }
+    }
+
+    pub fn chunks(&self) -> &[model::Mesh] {
+        &self.chunks
+    }
+
+    /// World-space size along X and Z.
+    pub fn extent(&self) -> (f32, f32) {
+        (
+            (self.heightmap.width - 1) as f32 * self.settings.spacing,
+            (self.heightmap.depth - 1) as f32 * self.settings.spacing,
+        )
+    }
+
+    /// Height of the surface above (`x`, `z`), following the same triangles
+    /// as the chunks. Points off the terrain get the height of its edge.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let (width, depth) = self.extent();
+        let gx = ((x + width * 0.5) / self.settings.spacing)
+            .clamp(0.0, (self.heightmap.width - 1) as f32);
+        let gz = ((z + depth * 0.5) / self.settings.spacing)
+            .clamp(0.0, (self.heightmap.depth - 1) as f32);
+        let (x0, z0) = (gx.floor() as i64, gz.floor() as i64);
+        let (fx, fz) = (gx - x0 as f32, gz - z0 as f32);
+        let h = |dx, dz| self.heightmap.get(x0 + dx, z0 + dz);
+        // Each quad is split along its (1, 0)-(0, 1) diagonal.
+        let height = if fx + fz <= 1.0 {
+            h(0, 0) + fx * (h(1, 0) - h(0, 0)) + fz * (h(0, 1) - h(0, 0))
+        } else {
+            h(1, 1) + (1.0 - fx) * (h(0, 1) - h(1, 1)) + (1.0 - fz) * (h(1, 0) - h(1, 1))
+        };
+        height * self.settings.height_scale
+    }
+
+    /// Marks the chunks inside `frustum` for drawing, or all of them with
+    /// `None`.
+    pub fn cull(&mut self, frustum: Option<&Frustum>) {
+        for (visible, chunk) in self.visible.iter_mut().zip(&self.chunks) {
+            *visible = frustum.is_none_or(|frustum| frustum.intersects_aabb(&chunk.bounds));
+        }
+    }
+
+    pub fn visible_chunks(&self) -> usize {
+        self.visible.iter().filter(|&&visible| visible).count()
+    }
+
+    /// Draws the chunks with `pipeline`, built for `pipeline_key`, and the
+    /// camera, light and shadow groups. `culled` leaves out those `cull`
+    /// rejected, for passes seen from the camera it was given.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipeline: &'a wgpu::RenderPipeline,
+        scene_bind_groups: [&'a wgpu::BindGroup; 3],
+        culled: bool,
+    ) {
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        for (i, bind_group) in scene_bind_groups.into_iter().enumerate() {
+            render_pass.set_bind_group(i as u32 + 1, bind_group, &[]);
+        }
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for (chunk, &visible) in self.chunks.iter().zip(&self.visible) {
+            if culled && !visible {
+                continue;
+            }
+            chunk.set_buffers(render_pass);
+            render_pass.draw_indexed(0..chunk.num_elements, 0, 0..1);
+        }
+    }
+}
+
+/// The quads between samples `from` and `to`, sharing their edge vertices
+/// and normals with the neighbouring chunks so no seams show.
+fn build_chunk(
+    heightmap: &Heightmap,
+    settings: &TerrainSettings,
+    from: [u32; 2],
+    to: [u32; 2],
+) -> MeshData {
+    let half_width = (heightmap.width - 1) as f32 * 0.5;
+    let half_depth = (heightmap.depth - 1) as f32 * 0.5;
+    let mut data = MeshData::new();
+    for z in from[1]..=to[1] {
+        for x in from[0]..=to[0] {
+            let (xi, zi) = (x as i64, z as i64);
+            let normal = heightmap.normal(xi, zi, settings);
+            // Along +X and +Z, the directions u and v grow in.
+            let tangent = cgmath::Vector3::new(normal.y, -normal.x, 0.0).normalize();
+            let bitangent = tangent.cross(normal).normalize();
+            data.vertices.push(ModelVertex {
+                position: [
+                    (x as f32 - half_width) * settings.spacing,
+                    heightmap.get(xi, zi) * settings.height_scale,
+                    (z as f32 - half_depth) * settings.spacing,
+                ],
+                tex_coords: [
+                    x as f32 / (heightmap.width - 1) as f32,
+                    z as f32 / (heightmap.depth - 1) as f32,
+                ],
+                normal: normal.into(),
+                tangent: tangent.into(),
+                bitangent: bitangent.into(),
+            });
+        }
+    }
+    let row = to[0] - from[0] + 1;
+    for z in 0..to[1] - from[1] {
+        for x in 0..to[0] - from[0] {
+            let a = z * row + x;
+            let (b, c) = (a + 1, a + row);
+            let d = c + 1;
+            data.indices.extend_from_slice(&[a, c, b, b, c, d]);
+        }
+    }
+    data
+}
@@ -0,0 +1,540 @@
+use std::collections::HashMap;
+
+use ab_glyph::{Font, FontArc, GlyphId, PxScale, ScaleFont};
+use wgpu::util::DeviceExt;
+
+use crate::preprocess::{self, ShaderDefs};
+use crate::vertex::Vertex;
+
+/// A run of text queued for one frame.
+#[derive(Debug, Clone)]
+pub struct TextSection {
+    pub text: String,
+    /// Top-left corner of the first line, in physical pixels.
+    pub position: [f32; 2],
+    /// Line height in pixels.
+    pub scale: f32,
+    /// Linear RGBA.
+    pub color: [f32; 4],
+    /// Lines wrap at word boundaries once they would pass this width.
+    pub max_width: Option<f32>,
+}
+
+impl TextSection {
+    pub fn new(text: impl Into<String>, position: [f32; 2]) -> Self {
+        Self {
+            text: text.into(),
+            position,
+            scale: 16.0,
+            color: [1.0; 4],
+            max_width: None,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_max_width(mut self, max_width: f32) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+}
+
+/// A glyph placed by `layout`: its id and the pen position on the baseline.
+#[derive(Debug, Copy, Clone)]
+struct PlacedGlyph {
+    id: GlyphId,
+    x: f32,
+    baseline: f32,
+}
+
+/// Lays `section` out into glyph positions and returns them with the size
+/// of the block they cover.
+fn layout(font: &FontArc, section: &TextSection) -> (Vec<PlacedGlyph>, [f32; 2]) {
+    let font = font.as_scaled(PxScale::from(section.scale));
+    let line_height = font.height() + font.line_gap();
+    let [left, top] = section.position;
+    let mut glyphs = Vec::new();
+    let mut width: f32 = 0.0;
+    let mut baseline = top + font.ascent();
+
+    for (line_index, line) in section.text.lines().enumerate() {
+        if line_index > 0 {
+            baseline += line_height;
+        }
+        let mut x = 0.0;
+        let mut previous: Option<GlyphId> = None;
+        for (word_index, word) in line.split(' ').enumerate() {
+            // A space joins words on the same line; wrapping replaces it.
+            let space = font.glyph_id(' ');
+            let word_width: f32 = word.chars().map(|c| font.h_advance(font.glyph_id(c))).sum();
+            if word_index > 0 {
+                let space_width = font.h_advance(space);
+                let wraps = section
+                    .max_width
+                    .is_some_and(|max| x > 0.0 && x + space_width + word_width > max);
+                if wraps {
+                    width = width.max(x);
+                    x = 0.0;
+                    baseline += line_height;
+                    previous = None;
+                } else {
+                    x += space_width;
+                    previous = Some(space);
+                }
+            }
+            for c in word.chars() {
+                let id = font.glyph_id(c);
+                if let Some(previous) = previous {
+                    x += font.kern(previous, id);
+                }
+                glyphs.push(PlacedGlyph {
+                    id,
+                    x: left + x,
+                    baseline,
+                });
+                x += font.h_advance(id);
+                previous = Some(id);
+            }
+        }
+        width = width.max(x);
+    }
+    let height = baseline - font.descent() - top;
+    (glyphs, [width, height.max(0.0)])
+}
+
+/// Where a rasterized glyph lives in the atlas.
+#[derive(Debug, Copy, Clone)]
+struct AtlasEntry {
+    uv: [f32; 4],
+    // Bounds relative to the pen position on the baseline, in pixels.
+    offset: [f32; 2],
+    size: [f32; 2],
+}
+
+/// Shelf-packed single-channel coverage atlas.
+struct GlyphAtlas {
+    texture: wgpu::Texture,
+    // Keyed by glyph and quarter-pixel scale; None for glyphs with no
+    // outline, such as spaces.
+    entries: HashMap<(GlyphId, u32), Option<AtlasEntry>>,
+    cursor: [u32; 2],
+    shelf_height: u32,
+}
+
+impl GlyphAtlas {
+    const SIZE: u32 = 1024;
+    const PADDING: u32 = 1;
+
+    fn new(device: &wgpu::Device) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("GlyphAtlas"),
+            size: wgpu::Extent3d {
+                width: Self::SIZE,
+                height: Self::SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        Self {
+            texture,
+            entries: HashMap::new(),
+            cursor: [0; 2],
+            shelf_height: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.cursor = [0; 2];
+        self.shelf_height = 0;
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> Option<[u32; 2]> {
+        let padded = [width + Self::PADDING, height + Self::PADDING];
+        if self.cursor[0] + padded[0] > Self::SIZE {
+            self.cursor = [0, self.cursor[1] + self.shelf_height];
+            self.shelf_height = 0;
+        }
+        if self.cursor[1] + padded[1] > Self::SIZE || padded[0] > Self::SIZE {
+            return None;
+        }
+        let origin = self.cursor;
+        self.cursor[0] += padded[0];
+        self.shelf_height = self.shelf_height.max(padded[1]);
+        Some(origin)
+    }
+
+    /// Looks up or rasterizes `id` at `scale`. Err when the atlas is full.
+    fn get(
+        &mut self,
+        queue: &wgpu::Queue,
+        font: &FontArc,
+        id: GlyphId,
+        scale: f32,
+    ) -> Result<Option<AtlasEntry>, ()> {
+        let key = (id, (scale * 4.0).round() as u32);
+        if let Some(entry) = self.entries.get(&key) {
+            return Ok(*entry);
+        }
+        let glyph = id.with_scale_and_position(scale, ab_glyph::point(0.0, 0.0));
+        let Some(outline) = font.outline_glyph(glyph) else {
+            self.entries.insert(key, None);
+            return Ok(None);
+        };
+        let bounds = outline.px_bounds();
+        let width = bounds.width() as u32;
+        let height = bounds.height() as u32;
+        if width == 0 || height == 0 {
+            self.entries.insert(key, None);
+            return Ok(None);
+        }
+        let origin = self.allocate(width, height).ok_or(())?;
+
+        let mut coverage = vec![0u8; (width * height) as usize];
+        outline.draw(|x, y, c| {
+            if x < width && y < height {
+                coverage[(y * width + x) as usize] = (c.clamp(0.0, 1.0) * 255.0) as u8;
+            }
+        });
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: origin[0],
+                    y: origin[1],
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &coverage,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(width),
+                rows_per_image: Some(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let size = Self::SIZE as f32;
+        let entry = AtlasEntry {
+            uv: [
+                origin[0] as f32 / size,
+                origin[1] as f32 / size,
+                (origin[0] + width) as f32 / size,
+                (origin[1] + height) as f32 / size,
+            ],
+            offset: [bounds.min.x, bounds.min.y],
+            size: [width as f32, height as f32],
+        };
+        self.entries.insert(key, Some(entry));
+        Ok(Some(entry))
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlyphInstance {
+    // Pixel position and size.
+    rect: [f32; 4],
+    uv: [f32; 4],
+    color: [f32; 4],
+}
+
+crate::impl_vertex!(GlyphInstance, Instance {
+    rect => 0: Float32x4,
+    uv => 1: Float32x4,
+    color => 2: Float32x4,
+});
+
+/// Screen-space text drawn from a glyph atlas.
+///
+/// Sections are queued during the frame with `queue` and drawn, then
+/// forgotten, by `render`. Glyphs are rasterized on first use; when the
+/// atlas fills up it is cleared and refilled with the current frame's text.
+pub struct TextRenderer {
+    font: FontArc,
+    atlas: GlyphAtlas,
+    sections: Vec<TextSection>,
+    instances: Vec<GlyphInstance>,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    screen_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    output_format: wgpu::TextureFormat,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl TextRenderer {
+    const INITIAL_CAPACITY: usize = 256;
+
+    /// The monospace font used until `set_font` is called.
+    pub fn default_font() -> FontArc {
+        FontArc::try_from_slice(epaint_default_fonts::HACK_REGULAR).expect("bundled font is valid")
+    }
+
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat, font: FontArc) -> Self {
+        let atlas = GlyphAtlas::new(device);
+        let atlas_view = atlas.texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("text_atlas_view"),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("text_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let screen_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Text Screen Buffer"),
+            contents: bytemuck::cast_slice(&[[1.0f32; 4]]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let instance_buffer = Self::create_instance_buffer(device, Self::INITIAL_CAPACITY);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("text_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: screen_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("text_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, output_format);
+
+        Self {
+            font,
+            atlas,
+            sections: Vec::new(),
+            instances: Vec::new(),
+            instance_buffer,
+            instance_capacity: Self::INITIAL_CAPACITY,
+            screen_buffer,
+            bind_group,
+            pipeline_layout,
+            output_format,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        output_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader =
+            preprocess::builtin_module(device, "Text Shader", "text.wgsl", &ShaderDefs::new());
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Text Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[GlyphInstance::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: output_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Compiles the pipeline again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(device, &self.pipeline_layout, self.output_format);
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Text Instance Buffer"),
+            size: (capacity * size_of::<GlyphInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn font(&self) -> &FontArc {
+        &self.font
+    }
+
+    /// Replaces the font; cached glyphs are discarded.
+    pub fn set_font(&mut self, font: FontArc) {
+        self.font = font;
+        self.atlas.clear();
+    }
+
+    /// Width and height `section` would cover, in pixels.
+    pub fn measure(&self, section: &TextSection) -> [f32; 2] {
+        layout(&self.font, section).1
+    }
+
+    /// Draws `section` on the next `render`.
+    pub fn queue(&mut self, section: TextSection) {
+        self.sections.push(section);
+    }
+
+    fn build_instances(&mut self, queue: &wgpu::Queue) -> Result<(), ()> {
+        self.instances.clear();
+        for section in &self.sections {
+            let (glyphs, _) = layout(&self.font, section);
+            for glyph in glyphs {
+                let Some(entry) = self.atlas.get(queue, &self.font, glyph.id, section.scale)?
+                else {
+                    continue;
+                };
+                // Snap to whole pixels so the atlas texels map one to one.
+                let x = glyph.x.round() + entry.offset[0];
+                let y = glyph.baseline.round() + entry.offset[1];
+                self.instances.push(GlyphInstance {
+                    rect: [x, y, entry.size[0], entry.size[1]],
+                    uv: entry.uv,
+                    color: section.color,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws and clears the queued sections over `output`, which is
+    /// `width` by `height` pixels.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        if self.sections.is_empty() {
+            return;
+        }
+        if self.build_instances(queue).is_err() {
+            // Start over with only this frame's glyphs; anything that still
+            // does not fit is dropped.
+            self.atlas.clear();
+            if self.build_instances(queue).is_err() {
+                log::warn!("Text does not fit in the glyph atlas");
+            }
+        }
+        self.sections.clear();
+        if self.instances.is_empty() {
+            return;
+        }
+
+        if self.instances.len() > self.instance_capacity {
+            self.instance_capacity = self.instances.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.instance_capacity);
+        }
+        queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&self.instances),
+        );
+        queue.write_buffer(
+            &self.screen_buffer,
+            0,
+            bytemuck::cast_slice(&[[width.max(1) as f32, height.max(1) as f32, 0.0, 0.0]]),
+        );
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Text Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        pass.draw(0..4, 0..self.instances.len() as u32);
+    }
+}
@@ -1,6 +1,63 @@
 use anyhow::*;
 use image::GenericImageView;
 
+use crate::color::{self, ColorSpace};
+use crate::compressed::CompressedImage;
+
+/// How materials filter and address their textures.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SamplerSettings {
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    /// Highest anisotropy, 1 to 16. Only applies while every filter is
+    /// linear.
+    pub anisotropy_clamp: u16,
+    /// Added to the mip level the GPU picks; negative sharpens. Samplers
+    /// have no bias of their own, so the material shaders apply it.
+    pub lod_bias: f32,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            anisotropy_clamp: 1,
+            lod_bias: 0.0,
+        }
+    }
+}
+
+impl SamplerSettings {
+    pub fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        let linear = [self.mag_filter, self.min_filter, self.mipmap_filter]
+            .iter()
+            .all(|filter| *filter == wgpu::FilterMode::Linear);
+        wgpu::SamplerDescriptor {
+            label: Some("material_sampler"),
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            anisotropy_clamp: if linear {
+                self.anisotropy_clamp.clamp(1, 16)
+            } else {
+                1
+            },
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Texture {
     #[allow(unused)]
     pub texture: wgpu::Texture,
@@ -11,14 +68,43 @@ pub struct Texture {
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
+    /// The far plane's depth, which depth buffers drawn with `reverse_z`
+    /// are cleared to.
+    pub fn far_depth(reverse_z: bool) -> f32 {
+        if reverse_z { 0.0 } else { 1.0 }
+    }
+
+    /// `compare` for a depth test against a buffer drawn with `reverse_z`,
+    /// where nearer is greater: `Less` turns into `Greater` and so on.
+    pub fn depth_compare(compare: wgpu::CompareFunction, reverse_z: bool) -> wgpu::CompareFunction {
+        use wgpu::CompareFunction as C;
+        match compare {
+            C::Less if reverse_z => C::Greater,
+            C::LessEqual if reverse_z => C::GreaterEqual,
+            C::Greater if reverse_z => C::Less,
+            C::GreaterEqual if reverse_z => C::LessEqual,
+            compare => compare,
+        }
+    }
+
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         label: &str,
+    ) -> Self {
+        Self::create_depth_target(device, config.width, config.height, label)
+    }
+
+    /// A depth texture for an offscreen pass of its own size.
+    pub fn create_depth_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width.max(1),
-            height: config.height.max(1),
+            width: width.max(1),
+            height: height.max(1),
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
@@ -33,8 +119,12 @@ impl Texture {
         };
         let texture = device.create_texture(&desc);
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            ..Default::default()
+        });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
@@ -54,13 +144,89 @@ impl Texture {
         }
     }
 
+    /// Color target that later passes can sample from or copy.
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Decodes a color image, such as an albedo or emissive map.
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
     ) -> Result<Self> {
+        Self::from_bytes_in(device, queue, bytes, label, ColorSpace::Srgb)
+    }
+
+    /// Decodes an image file or KTX2/DDS container in `color_space`. A
+    /// container's own format wins where it names one.
+    pub fn from_bytes_in(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        color_space: ColorSpace,
+    ) -> Result<Self> {
+        if CompressedImage::is_container(bytes) {
+            let image = CompressedImage::from_bytes(bytes, color_space == ColorSpace::Srgb)?;
+            return Self::from_compressed(device, queue, &image, Some(label));
+        }
         let img = image::load_from_memory(bytes)?;
+        Self::from_image_in(device, queue, &img, Some(label), color_space)
+    }
+
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+    ) -> Result<Self> {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
         Self::from_image(device, queue, &img, Some(label))
     }
 
@@ -70,8 +236,214 @@ impl Texture {
         img: &image::DynamicImage,
         label: Option<&str>,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
+        Self::from_image_with_sampler(device, queue, img, label, &Self::default_sampler())
+    }
+
+    pub fn from_image_in(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        color_space: ColorSpace,
+    ) -> Result<Self> {
+        Self::upload_image(
+            device,
+            queue,
+            img,
+            label,
+            color_space.rgba8_format(),
+            &Self::default_sampler(),
+        )
+    }
+
+    pub fn normal_from_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        Self::from_bytes_in(device, queue, bytes, label, ColorSpace::Linear)
+    }
+
+    /// Uploads the stored mip chain as it is when `device` can sample its
+    /// format, and otherwise decodes it to RGBA8 first, generating mips if
+    /// the file had none.
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &CompressedImage,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let sampler = Self::default_sampler();
+        if image.is_supported(device) {
+            let levels = image.levels.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            return Ok(Self::upload_levels(
+                device,
+                queue,
+                image.format,
+                (image.width, image.height),
+                &levels,
+                label,
+                &sampler,
+            ));
+        }
+
+        log::warn!(
+            "{:?} is not supported here; decoding {} to RGBA8",
+            image.format,
+            label.unwrap_or("texture")
+        );
+        let decoded = image.decode_rgba8()?;
+        let format = image.fallback_format();
+        if let [level] = decoded.as_slice() {
+            let img = image::DynamicImage::ImageRgba8(level.clone());
+            return Self::upload_image(device, queue, &img, label, format, &sampler);
+        }
+        let levels = decoded
+            .iter()
+            .map(|level| level.as_raw().as_slice())
+            .collect::<Vec<_>>();
+        Ok(Self::upload_levels(
+            device,
+            queue,
+            format,
+            (image.width, image.height),
+            &levels,
+            label,
+            &sampler,
+        ))
+    }
+
+    // Writes prepared mip levels, largest first, each tightly packed rows
+    // of `format` blocks.
+    fn upload_levels(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        (width, height): (u32, u32),
+        levels: &[&[u8]],
+        label: Option<&str>,
+        sampler: &wgpu::SamplerDescriptor,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: levels.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None).unwrap_or(4);
+        for (mip_level, data) in levels.iter().enumerate() {
+            let level_size = size
+                .mip_level_size(mip_level as u32, wgpu::TextureDimension::D2)
+                .physical_size(format);
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                data,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(level_size.width / block_width * block_size),
+                    rows_per_image: Some(level_size.height / block_height),
+                },
+                level_size,
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(sampler);
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Normal maps hold vectors rather than colors, so they are uploaded
+    /// without the sRGB curve.
+    pub fn normal_from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        Self::linear_from_image(device, queue, img, label)
+    }
+
+    /// Uploads non-color data such as metallic-roughness or occlusion maps.
+    pub fn linear_from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_image_in(device, queue, img, label, ColorSpace::Linear)
+    }
+
+    /// 1x1 tangent-space normal pointing straight out of the surface.
+    pub fn flat_normal(device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Self> {
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba([128, 128, 255, 255]),
+        ));
+        Self::normal_from_image(device, queue, &img, Some("flat_normal"))
+    }
+
+    /// Trilinear, repeating sampler used for material textures.
+    pub fn default_sampler() -> wgpu::SamplerDescriptor<'static> {
+        SamplerSettings::default().descriptor()
+    }
+
+    pub fn mip_level_count(width: u32, height: u32) -> u32 {
+        u32::BITS - width.max(height).max(1).leading_zeros()
+    }
+
+    /// Uploads `img` with a full mip chain generated on the CPU.
+    pub fn from_image_with_sampler(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        sampler: &wgpu::SamplerDescriptor,
+    ) -> Result<Self> {
+        Self::upload_image(
+            device,
+            queue,
+            img,
+            label,
+            ColorSpace::Srgb.rgba8_format(),
+            sampler,
+        )
+    }
+
+    fn upload_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
+        sampler: &wgpu::SamplerDescriptor,
+    ) -> Result<Self> {
         let dimensions = img.dimensions();
+        let mip_level_count = Self::mip_level_count(dimensions.0, dimensions.1);
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -81,45 +453,163 @@ impl Texture {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
 
-        queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+        // sRGB texels are averaged in linear space, or each mip comes out
+        // darker than the last.
+        let srgb = format.is_srgb();
+        let mut level = img.to_rgba8();
+        let mut linear = srgb.then(|| decode_srgb(&level));
+        for mip_level in 0..mip_level_count {
+            if mip_level > 0 {
+                let width = (level.width() / 2).max(1);
+                let height = (level.height() / 2).max(1);
+                let filter = image::imageops::FilterType::Triangle;
+                match &mut linear {
+                    Some(linear) => {
+                        *linear = image::imageops::resize(linear, width, height, filter);
+                        level = encode_srgb(linear);
+                    }
+                    None => level = image::imageops::resize(&level, width, height, filter),
+                }
+            }
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                &level,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * level.width()),
+                    rows_per_image: Some(level.height()),
+                },
+                wgpu::Extent3d {
+                    width: level.width(),
+                    height: level.height(),
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(sampler);
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    /// Creates a cube texture from six tightly packed faces in +X, -X, +Y,
+    /// -Y, +Z, -Z order.
+    pub fn create_cube(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: u32,
+        format: wgpu::TextureFormat,
+        faces: &[&[u8]; 6],
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
             },
-            size,
-        );
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bytes_per_pixel = format.block_copy_size(None).unwrap_or(4);
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                face,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_pixel * size),
+                    rows_per_image: Some(size),
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
             ..Default::default()
         });
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
-        })
+        }
     }
 }
+
+fn decode_srgb(image: &image::RgbaImage) -> image::Rgba32FImage {
+    image::Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0.map(|c| c as f32 / 255.0);
+        image::Rgba([
+            color::srgb_to_linear(r),
+            color::srgb_to_linear(g),
+            color::srgb_to_linear(b),
+            a,
+        ])
+    })
+}
+
+fn encode_srgb(image: &image::Rgba32FImage) -> image::RgbaImage {
+    let quantize = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    image::RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        image::Rgba([
+            quantize(color::linear_to_srgb(r)),
+            quantize(color::linear_to_srgb(g)),
+            quantize(color::linear_to_srgb(b)),
+            quantize(a),
+        ])
+    })
+}
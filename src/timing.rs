@@ -0,0 +1,195 @@
+use std::collections::VecDeque;
+
+use web_time::Instant;
+
+use crate::profiler::GpuProfiler;
+
+/// Rolling frame statistics for the stats overlay.
+///
+/// The main loop calls `begin_frame` before updating and `end_frame` after
+/// rendering; everything here is averaged over the last `HISTORY` frames.
+pub struct FrameTiming {
+    last_begin: Option<Instant>,
+    begin: Instant,
+    // Milliseconds, newest last.
+    frame_times: VecDeque<f32>,
+    cpu_times: VecDeque<f32>,
+    draw_calls: u32,
+    triangles: u64,
+    upload_bytes: u64,
+    gpu: Option<GpuProfiler>,
+}
+
+impl FrameTiming {
+    const HISTORY: usize = 120;
+
+    pub fn new(gpu: Option<GpuProfiler>) -> Self {
+        Self {
+            last_begin: None,
+            begin: Instant::now(),
+            frame_times: VecDeque::with_capacity(Self::HISTORY),
+            cpu_times: VecDeque::with_capacity(Self::HISTORY),
+            draw_calls: 0,
+            triangles: 0,
+            upload_bytes: 0,
+            gpu,
+        }
+    }
+
+    fn push(samples: &mut VecDeque<f32>, value: f32) {
+        if samples.len() == Self::HISTORY {
+            samples.pop_front();
+        }
+        samples.push_back(value);
+    }
+
+    fn average(samples: &VecDeque<f32>) -> f32 {
+        if samples.is_empty() {
+            0.0
+        } else {
+            samples.iter().sum::<f32>() / samples.len() as f32
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last_begin {
+            Self::push(&mut self.frame_times, (now - last).as_secs_f32() * 1000.0);
+        }
+        self.last_begin = Some(now);
+        self.begin = now;
+    }
+
+    pub fn end_frame(&mut self) {
+        Self::push(
+            &mut self.cpu_times,
+            self.begin.elapsed().as_secs_f32() * 1000.0,
+        );
+    }
+
+    pub fn fps(&self) -> f32 {
+        let frame_time = self.frame_time_ms();
+        if frame_time > 0.0 {
+            1000.0 / frame_time
+        } else {
+            0.0
+        }
+    }
+
+    /// Average time between frames.
+    pub fn frame_time_ms(&self) -> f32 {
+        Self::average(&self.frame_times)
+    }
+
+    /// Average time spent in update and render, excluding waits between
+    /// frames.
+    pub fn cpu_time_ms(&self) -> f32 {
+        Self::average(&self.cpu_times)
+    }
+
+    /// Frame times in milliseconds, oldest first.
+    pub fn frame_times(&self) -> impl ExactSizeIterator<Item = f32> + '_ {
+        self.frame_times.iter().copied()
+    }
+
+    /// Mesh draw calls issued by the last frame.
+    pub fn draw_calls(&self) -> u32 {
+        self.draw_calls
+    }
+
+    pub(crate) fn set_draw_calls(&mut self, draw_calls: u32) {
+        self.draw_calls = draw_calls;
+    }
+
+    /// Triangles of the scene model's meshes in the last frame's main
+    /// view, counting every instance at full detail, whatever per-instance
+    /// culling and levels of detail left out.
+    pub fn triangles(&self) -> u64 {
+        self.triangles
+    }
+
+    pub(crate) fn set_triangles(&mut self, triangles: u64) {
+        self.triangles = triangles;
+    }
+
+    /// Bytes the last frame staged for per-frame buffer updates.
+    pub fn upload_bytes(&self) -> u64 {
+        self.upload_bytes
+    }
+
+    pub(crate) fn set_upload_bytes(&mut self, upload_bytes: u64) {
+        self.upload_bytes = upload_bytes;
+    }
+
+    /// None when the device has no timestamp queries.
+    pub fn gpu(&self) -> Option<&GpuProfiler> {
+        self.gpu.as_ref()
+    }
+
+    pub(crate) fn gpu_mut(&mut self) -> Option<&mut GpuProfiler> {
+        self.gpu.as_mut()
+    }
+}
+
+/// Paces a simulation at a fixed rate, however often frames come.
+///
+/// Each frame, `advance` takes the frame's length and says how many whole steps of `step` seconds are
+/// due, and `alpha` how far towards the next one the frame is, so state
+/// drawn between steps can be interpolated from the last two.
+pub struct FixedTimestep {
+    step: f32,
+    // Seconds passed but not yet stepped.
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    /// Steps per second unless set otherwise.
+    pub const DEFAULT_RATE: f32 = 60.0;
+    // The most time one frame hands the simulation, so that a stall such
+    // as a breakpoint or a dragged window doesn't run seconds of steps at
+    // once afterwards.
+    const MAX_FRAME: f32 = 0.25;
+
+    pub fn new(rate: f32) -> Self {
+        Self {
+            step: 1.0 / rate,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Seconds per step.
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    /// Steps per second.
+    pub fn rate(&self) -> f32 {
+        1.0 / self.step
+    }
+
+    pub fn set_rate(&mut self, rate: f32) {
+        self.step = 1.0 / rate.max(1.0);
+        self.accumulator = self.accumulator.min(self.step);
+    }
+
+    /// Takes in a frame `dt` seconds long and returns how many steps are
+    /// due.
+    pub fn advance(&mut self, dt: f32) -> u32 {
+        self.accumulator += dt.clamp(0.0, Self::MAX_FRAME);
+        let steps = (self.accumulator / self.step) as u32;
+        self.accumulator -= steps as f32 * self.step;
+        steps
+    }
+
+    /// How far from the last step towards the next the time that
+    /// `advance` has seen is, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step).clamp(0.0, 1.0)
+    }
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_RATE)
+    }
+}
@@ -0,0 +1,239 @@
+use std::marker::PhantomData;
+
+use crate::preprocess::ShaderDefs;
+
+/// Per-draw uniforms of one type packed into a single buffer, each at the
+/// device's `min_uniform_buffer_offset_alignment`.
+///
+/// A pass binds one bind group over it and picks each draw's value with a
+/// dynamic offset, instead of building a buffer and bind group per object.
+/// Values are pushed on the CPU each frame and written in one go by
+/// `upload`, which grows the buffer when they outnumber its capacity; bind
+/// groups over it must then be rebuilt.
+pub struct DynamicUniformBuffer<T> {
+    buffer: wgpu::Buffer,
+    label: String,
+    stride: wgpu::BufferAddress,
+    capacity: usize,
+    staged: Vec<u8>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> DynamicUniformBuffer<T> {
+    const MIN_CAPACITY: usize = 16;
+
+    pub fn new(device: &wgpu::Device, label: &str, capacity: usize) -> Self {
+        let stride = (size_of::<T>() as wgpu::BufferAddress)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as _);
+        let capacity = capacity.max(Self::MIN_CAPACITY).next_power_of_two();
+        Self {
+            buffer: Self::create_buffer(device, label, stride, capacity),
+            label: label.to_string(),
+            stride,
+            capacity,
+            staged: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        label: &str,
+        stride: wgpu::BufferAddress,
+        capacity: usize,
+    ) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: stride * capacity as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Layout entry for one value with a dynamic offset.
+    pub fn layout_entry(
+        binding: u32,
+        visibility: wgpu::ShaderStages,
+    ) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: wgpu::BufferSize::new(size_of::<T>() as _),
+            },
+            count: None,
+        }
+    }
+
+    /// The resource for a bind group entry made with `layout_entry`.
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(size_of::<T>() as _),
+        })
+    }
+
+    /// Values pushed since the last clear.
+    pub fn len(&self) -> usize {
+        self.staged.len() / self.stride as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.staged.clear();
+    }
+
+    /// Stages `value`, returning the dynamic offset it will be bound at.
+    pub fn push(&mut self, value: &T) -> u32 {
+        let offset = self.staged.len();
+        self.staged.resize(offset + self.stride as usize, 0);
+        self.staged[offset..offset + size_of::<T>()].copy_from_slice(bytemuck::bytes_of(value));
+        offset as u32
+    }
+
+    /// The dynamic offset of the `index`th value pushed.
+    pub fn offset(&self, index: usize) -> u32 {
+        (index as wgpu::BufferAddress * self.stride) as u32
+    }
+
+    /// Writes the staged values, first reallocating if they no longer fit.
+    /// Returns whether it did, and so whether bind groups over the buffer
+    /// need rebuilding.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        let len = self.len();
+        let reallocated = len > self.capacity;
+        if reallocated {
+            self.capacity = len.next_power_of_two();
+            self.buffer = Self::create_buffer(device, &self.label, self.stride, self.capacity);
+        }
+        if !self.staged.is_empty() {
+            queue.write_buffer(&self.buffer, 0, &self.staged);
+        }
+        reallocated
+    }
+}
+
+/// Per-draw values of one type, set with push constants on devices with
+/// `Features::PUSH_CONSTANTS` room for them, and otherwise bound from a
+/// `DynamicUniformBuffer` with each draw's dynamic offset.
+///
+/// Shaders declare the value twice behind `#ifdef PUSH_CONSTANTS`: as a
+/// `var<push_constant>`, or else as a uniform at the binding given to
+/// `layout_entry`. `shader_defs` sets the flag to match.
+pub struct PerDrawUniforms<T> {
+    stages: wgpu::ShaderStages,
+    // None when pushing constants, which are set from `values`.
+    buffer: Option<DynamicUniformBuffer<T>>,
+    values: Vec<T>,
+}
+
+impl<T: bytemuck::Pod> PerDrawUniforms<T> {
+    /// Values read in `stages`; `label` and `capacity` are for the
+    /// fallback buffer.
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        capacity: usize,
+        stages: wgpu::ShaderStages,
+    ) -> Self {
+        let push_constants = device.features().contains(wgpu::Features::PUSH_CONSTANTS)
+            && device.limits().max_push_constant_size as usize >= size_of::<T>();
+        Self {
+            stages,
+            buffer: (!push_constants).then(|| DynamicUniformBuffer::new(device, label, capacity)),
+            values: Vec::new(),
+        }
+    }
+
+    pub fn uses_push_constants(&self) -> bool {
+        self.buffer.is_none()
+    }
+
+    /// `defs` with `PUSH_CONSTANTS` set when pushing constants.
+    pub fn shader_defs(&self, defs: ShaderDefs) -> ShaderDefs {
+        if self.uses_push_constants() {
+            defs.with_flag("PUSH_CONSTANTS")
+        } else {
+            defs
+        }
+    }
+
+    /// Layout entry for the fallback buffer, which push constants skip.
+    pub fn layout_entry(&self, binding: u32) -> Option<wgpu::BindGroupLayoutEntry> {
+        self.buffer
+            .as_ref()
+            .map(|_| DynamicUniformBuffer::<T>::layout_entry(binding, self.stages))
+    }
+
+    /// The resource for a bind group entry made with `layout_entry`.
+    pub fn binding(&self) -> Option<wgpu::BindingResource<'_>> {
+        self.buffer.as_ref().map(DynamicUniformBuffer::binding)
+    }
+
+    /// The pipeline layout's push constant ranges: one value's worth, or
+    /// none with the fallback buffer.
+    pub fn push_constant_ranges(&self) -> Vec<wgpu::PushConstantRange> {
+        if !self.uses_push_constants() {
+            return Vec::new();
+        }
+        vec![wgpu::PushConstantRange {
+            stages: self.stages,
+            range: 0..size_of::<T>() as u32,
+        }]
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        if let Some(buffer) = &mut self.buffer {
+            buffer.clear();
+        }
+    }
+
+    /// Stages `value` for the next draw, made the `len`th before it.
+    pub fn push(&mut self, value: &T) {
+        self.values.push(*value);
+        if let Some(buffer) = &mut self.buffer {
+            buffer.push(value);
+        }
+    }
+
+    /// Writes the fallback buffer, as `DynamicUniformBuffer::upload`;
+    /// push constants have nothing to write and never reallocate.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        self.buffer
+            .as_mut()
+            .is_some_and(|buffer| buffer.upload(device, queue))
+    }
+
+    /// Binds `bind_group` at `group` for a draw of the `index`th value
+    /// pushed, at its dynamic offset or as the push constants.
+    pub fn bind(
+        &self,
+        pass: &mut wgpu::RenderPass<'_>,
+        group: u32,
+        bind_group: &wgpu::BindGroup,
+        index: usize,
+    ) {
+        match &self.buffer {
+            Some(buffer) => pass.set_bind_group(group, bind_group, &[buffer.offset(index)]),
+            None => {
+                pass.set_bind_group(group, bind_group, &[]);
+                pass.set_push_constants(self.stages, 0, bytemuck::bytes_of(&self.values[index]));
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Vertex buffer layouts worked out from the structs they describe.
+//!
+//! `impl_vertex!` implements `Vertex` for a `#[repr(C)]` struct from a list
+//! of its fields, each with the shader location and format it's read as.
+//! Offsets come from `offset_of!` and the stride from `size_of`, so adding,
+//! removing or reordering a field can't leave a stale offset behind, and a
+//! format that doesn't fill its field exactly fails to compile. A matrix
+//! field spans a range of locations, one column each:
+//!
+//! ```ignore
+//! impl_vertex!(InstanceRaw, Instance {
+//!     model => 5..=8: Float32x4,
+//!     normal => 9..=11: Float32x4,
+//! });
+//! ```
+
+pub trait Vertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static>;
+}
+
+/// One field `impl_vertex!` lists, as `attributes` spreads it over
+/// locations.
+#[doc(hidden)]
+#[derive(Debug, Copy, Clone)]
+pub struct Field {
+    pub offset: usize,
+    pub size: usize,
+    pub location: u32,
+    pub end_location: u32,
+    pub format: wgpu::VertexFormat,
+}
+
+/// How many locations `fields` take up between them.
+#[doc(hidden)]
+pub const fn location_count(fields: &[Field]) -> usize {
+    let mut count = 0;
+    let mut i = 0;
+    while i < fields.len() {
+        count += (fields[i].end_location - fields[i].location + 1) as usize;
+        i += 1;
+    }
+    count
+}
+
+/// An attribute for each location of each of `fields`, which must take up
+/// `N` of them. Panics, failing the build, where a field's formats don't
+/// add up to its size.
+#[doc(hidden)]
+pub const fn attributes<const N: usize>(fields: &[Field]) -> [wgpu::VertexAttribute; N] {
+    let mut attributes = [wgpu::VertexAttribute {
+        format: wgpu::VertexFormat::Float32,
+        offset: 0,
+        shader_location: 0,
+    }; N];
+    let mut index = 0;
+    let mut i = 0;
+    while i < fields.len() {
+        let field = fields[i];
+        if field.end_location < field.location {
+            panic!("a vertex field's locations run backwards");
+        }
+        let count = (field.end_location - field.location + 1) as u64;
+        if field.format.size() * count != field.size as u64 {
+            panic!("a vertex field's format doesn't fill it");
+        }
+        let mut column = 0;
+        while column < count {
+            attributes[index] = wgpu::VertexAttribute {
+                format: field.format,
+                offset: field.offset as u64 + column * field.format.size(),
+                shader_location: field.location + column as u32,
+            };
+            index += 1;
+            column += 1;
+        }
+        i += 1;
+    }
+    attributes
+}
+
+/// The size of the field `_field` projects out of a `T`.
+#[doc(hidden)]
+pub const fn field_size<T, F>(_field: fn(&T) -> &F) -> usize {
+    size_of::<F>()
+}
+
+/// Implements `Vertex` for `$ty`, stepped per `Vertex` or `Instance`, from
+/// its fields' locations and formats; see the module docs.
+#[macro_export]
+macro_rules! impl_vertex {
+    (@end $location:literal) => { $location };
+    (@end $location:literal $end:literal) => { $end };
+    ($ty:ty, $step:ident {
+        $($field:ident => $location:literal $(..= $end:literal)?: $format:ident),* $(,)?
+    }) => {
+        impl $crate::vertex::Vertex for $ty {
+            fn desc() -> wgpu::VertexBufferLayout<'static> {
+                const FIELDS: &[$crate::vertex::Field] = &[$(
+                    $crate::vertex::Field {
+                        offset: std::mem::offset_of!($ty, $field),
+                        size: $crate::vertex::field_size(|vertex: &$ty| &vertex.$field),
+                        location: $location,
+                        end_location: $crate::impl_vertex!(@end $location $($end)?),
+                        format: wgpu::VertexFormat::$format,
+                    },
+                )*];
+                const ATTRIBUTES: [wgpu::VertexAttribute; $crate::vertex::location_count(FIELDS)] =
+                    $crate::vertex::attributes(FIELDS);
+                wgpu::VertexBufferLayout {
+                    array_stride: size_of::<$ty>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::$step,
+                    attributes: &ATTRIBUTES,
+                }
+            }
+        }
+    };
+}
@@ -0,0 +1,149 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+/// Where a `VideoCapture` writes its frames.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VideoOutput {
+    /// `frame-NNNNN.png` files in a directory, created if missing.
+    Png(PathBuf),
+    /// A video file, encoded by an `ffmpeg` on the PATH that is fed raw
+    /// frames through its standard input.
+    Ffmpeg(PathBuf),
+}
+
+impl VideoOutput {
+    // Containers that mean `path` is a video rather than a directory.
+    const VIDEO_EXTENSIONS: [&str; 5] = ["mp4", "mkv", "webm", "mov", "avi"];
+
+    /// A video file for paths ending in a container's extension, such as
+    /// `.mp4`, and otherwise a directory of PNGs.
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let is_video = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                Self::VIDEO_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+            });
+        if is_video {
+            Self::Ffmpeg(path)
+        } else {
+            Self::Png(path)
+        }
+    }
+}
+
+/// Writes out every frame rendered while it runs.
+///
+/// While `State` has one, each update steps time by exactly `1 / fps`
+/// however long the frame took to draw, and each frame is read back
+/// before the next starts. Rendering slows to whatever that costs, but the
+/// output plays back smoothly at `fps`.
+pub struct VideoCapture {
+    output: VideoOutput,
+    fps: u32,
+    frames: u32,
+    // Started on the first frame, once its size is known.
+    ffmpeg: Option<(Child, [u32; 2])>,
+}
+
+impl VideoCapture {
+    pub fn new(output: VideoOutput, fps: u32) -> anyhow::Result<Self> {
+        if fps == 0 {
+            anyhow::bail!("Video needs at least one frame per second");
+        }
+        if let VideoOutput::Png(directory) = &output {
+            std::fs::create_dir_all(directory)?;
+        }
+        Ok(Self {
+            output,
+            fps,
+            frames: 0,
+            ffmpeg: None,
+        })
+    }
+
+    pub fn fps(&self) -> u32 {
+        self.fps
+    }
+
+    /// Seconds each frame steps time by.
+    pub fn frame_time(&self) -> f32 {
+        1.0 / self.fps as f32
+    }
+
+    /// Frames written so far.
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub(crate) fn write(&mut self, image: image::RgbaImage) -> anyhow::Result<()> {
+        match &self.output {
+            VideoOutput::Png(directory) => {
+                image.save(directory.join(format!("frame-{:05}.png", self.frames)))?;
+            }
+            VideoOutput::Ffmpeg(path) => {
+                let size = [image.width(), image.height()];
+                if self.ffmpeg.is_none() {
+                    self.ffmpeg = Some((spawn_ffmpeg(path, size, self.fps)?, size));
+                }
+                let (ffmpeg, started) = self.ffmpeg.as_mut().expect("started above");
+                if *started != size {
+                    anyhow::bail!(
+                        "Output resized from {}x{} to {}x{} mid-video",
+                        started[0],
+                        started[1],
+                        size[0],
+                        size[1]
+                    );
+                }
+                let Some(stdin) = &mut ffmpeg.stdin else {
+                    anyhow::bail!("ffmpeg closed its input");
+                };
+                stdin.write_all(image.as_raw())?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Closes the output, waiting for ffmpeg to finish encoding, and
+    /// returns how many frames were written.
+    pub fn finish(mut self) -> anyhow::Result<u32> {
+        if let Some((mut ffmpeg, _)) = self.ffmpeg.take() {
+            drop(ffmpeg.stdin.take());
+            let status = ffmpeg.wait()?;
+            if !status.success() {
+                anyhow::bail!("ffmpeg failed: {}", status);
+            }
+        }
+        Ok(self.frames)
+    }
+}
+
+fn spawn_ffmpeg(path: &std::path::Path, size: [u32; 2], fps: u32) -> anyhow::Result<Child> {
+    Command::new("ffmpeg")
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgba",
+        ])
+        .args(["-s", &format!("{}x{}", size[0], size[1])])
+        .args(["-r", &fps.to_string(), "-i", "-"])
+        // Most encoders need even sizes for 4:2:0 chroma.
+        .args([
+            "-vf",
+            "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+            "-pix_fmt",
+            "yuv420p",
+        ])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Unable to start ffmpeg: {}", e))
+}
@@ -0,0 +1,65 @@
+use crate::camera::Camera;
+use crate::secondary_window::DebugTexture;
+
+/// Part of the output, in fractions of its size from the top-left corner.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewportRect {
+    pub const FULL: Self = Self::new(0.0, 0.0, 1.0, 1.0);
+    pub const LEFT_HALF: Self = Self::new(0.0, 0.0, 0.5, 1.0);
+    pub const RIGHT_HALF: Self = Self::new(0.5, 0.0, 0.5, 1.0);
+    /// A third of the output in the bottom-right corner, for
+    /// picture-in-picture.
+    pub const INSET: Self = Self::new(0.65, 0.65, 1.0 / 3.0, 1.0 / 3.0);
+
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The top-left corner and size in pixels of this part of an output
+    /// `size` big, kept inside it and at least a pixel wide and high.
+    pub fn pixels(&self, size: [u32; 2]) -> ([u32; 2], [u32; 2]) {
+        let axis = |start: f32, length: f32, total: u32| {
+            let total = total.max(1);
+            let start = ((start.clamp(0.0, 1.0) * total as f32) as u32).min(total - 1);
+            let length = ((length * total as f32).round() as u32).clamp(1, total - start);
+            (start, length)
+        };
+        let (x, width) = axis(self.x, self.width, size[0]);
+        let (y, height) = axis(self.y, self.height, size[1]);
+        ([x, y], [width, height])
+    }
+}
+
+/// A camera drawn into a rectangle of the output over the main view, such
+/// as a top-down minimap or one player's half of a split screen.
+///
+/// The camera's aspect ratio follows the rectangle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct View {
+    /// None draws from the main camera, as for the first player's half of
+    /// a split screen covering the main view.
+    pub camera: Option<Camera>,
+    pub rect: ViewportRect,
+}
+
+/// One of the renderer's intermediate textures drawn over the main view,
+/// to see what the passes before it produced.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DebugView {
+    pub texture: DebugTexture,
+    /// `ViewportRect::FULL` to replace the main view, or a part of it such
+    /// as `ViewportRect::INSET` for picture-in-picture.
+    pub rect: ViewportRect,
+}
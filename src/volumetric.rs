@@ -0,0 +1,344 @@
+use wgpu::util::DeviceExt;
+
+use crate::hdr::HdrPipeline;
+use crate::post;
+use crate::preprocess::{self, ShaderDefs};
+use crate::texture;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Tuning for the directional light's scattering.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VolumetricSettings {
+    pub enabled: bool,
+    /// How much light the air scatters and absorbs per world unit.
+    pub density: f32,
+    /// From -1, scattering light back towards the sun, through 0, evenly,
+    /// to 1, onwards: shafts brighten looking towards the sun for positive
+    /// values.
+    pub anisotropy: f32,
+    /// How far from the eye the air reaches, in world units.
+    pub max_distance: f32,
+    /// Samples along each pixel's ray.
+    pub steps: u32,
+    /// Scales the scattered light, leaving the dimming of the scene behind
+    /// it alone.
+    pub intensity: f32,
+}
+
+impl Default for VolumetricSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            density: 0.02,
+            anisotropy: 0.6,
+            max_distance: 60.0,
+            steps: 32,
+            intensity: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct VolumetricUniform {
+    density: f32,
+    anisotropy: f32,
+    max_distance: f32,
+    intensity: f32,
+    steps: u32,
+    shadows: u32,
+    frame: u32,
+    _padding: u32,
+}
+
+/// Light shafts: each pixel's view ray marched through the directional
+/// light's shadow cascades in a compute pass, and the light the air along
+/// it scatters towards the eye blended over the HDR target before TAA,
+/// which smooths the march's dithering.
+pub struct Volumetrics {
+    settings: VolumetricSettings,
+    frame: u32,
+    uniform_buffer: wgpu::Buffer,
+    scattering: texture::Texture,
+    scatter_layout: wgpu::BindGroupLayout,
+    composite_layout: wgpu::BindGroupLayout,
+    scatter_pipeline_layout: wgpu::PipelineLayout,
+    composite_pipeline_layout: wgpu::PipelineLayout,
+    scatter_pipeline: wgpu::ComputePipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+impl Volumetrics {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// `shadow_layout` is `ShadowMap::bind_group_layout`, whose bind group
+    /// `render` marches.
+    pub fn new(
+        device: &wgpu::Device,
+        shadow_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let settings = VolumetricSettings::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Volumetrics::uniform_buffer"),
+            contents: bytemuck::bytes_of(&Self::uniform(settings, false, 0)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let scattering = Self::create_scattering(device, width, height);
+
+        let depth_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let scatter_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Volumetrics::scatter_layout"),
+            entries: &[
+                depth_entry(0, wgpu::ShaderStages::COMPUTE),
+                uniform_entry(1),
+                uniform_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: Self::FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let composite_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Volumetrics::composite_layout"),
+            entries: &[depth_entry(4, wgpu::ShaderStages::FRAGMENT)],
+        });
+
+        let scatter_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Volumetrics::scatter_pipeline_layout"),
+                bind_group_layouts: &[&scatter_layout, shadow_layout],
+                push_constant_ranges: &[],
+            });
+        let composite_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Volumetrics::composite_pipeline_layout"),
+                bind_group_layouts: &[&composite_layout],
+                push_constant_ranges: &[],
+            });
+        let (scatter_pipeline, composite_pipeline) =
+            Self::create_pipelines(device, &scatter_pipeline_layout, &composite_pipeline_layout);
+
+        Self {
+            settings,
+            frame: 0,
+            uniform_buffer,
+            scattering,
+            scatter_layout,
+            composite_layout,
+            scatter_pipeline_layout,
+            composite_pipeline_layout,
+            scatter_pipeline,
+            composite_pipeline,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        scatter_pipeline_layout: &wgpu::PipelineLayout,
+        composite_pipeline_layout: &wgpu::PipelineLayout,
+    ) -> (wgpu::ComputePipeline, wgpu::RenderPipeline) {
+        let shader = post::create_shader(
+            device,
+            "Volumetrics::shader",
+            &preprocess::builtin_source("volumetric.wgsl", &ShaderDefs::new()),
+        );
+        let scatter_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Volumetrics::scatter_pipeline"),
+            layout: Some(scatter_pipeline_layout),
+            module: &shader,
+            entry_point: Some("cs_scatter"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+        let composite_pipeline = post::create_pipeline(
+            device,
+            "Volumetrics::composite_pipeline",
+            composite_pipeline_layout,
+            &shader,
+            "fs_composite",
+            HdrPipeline::FORMAT,
+            Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::SrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            }),
+        );
+        (scatter_pipeline, composite_pipeline)
+    }
+
+    /// Compiles the pipelines again from the current shader sources.
+    pub(crate) fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        (self.scatter_pipeline, self.composite_pipeline) = Self::create_pipelines(
+            device,
+            &self.scatter_pipeline_layout,
+            &self.composite_pipeline_layout,
+        );
+    }
+
+    fn create_scattering(device: &wgpu::Device, width: u32, height: u32) -> texture::Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Volumetrics::scattering"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    fn uniform(settings: VolumetricSettings, shadows: bool, frame: u32) -> VolumetricUniform {
+        VolumetricUniform {
+            density: settings.density.max(0.0),
+            anisotropy: settings.anisotropy.clamp(-0.99, 0.99),
+            max_distance: settings.max_distance,
+            intensity: settings.intensity,
+            steps: settings.steps.max(1),
+            shadows: shadows as u32,
+            frame,
+            _padding: 0,
+        }
+    }
+
+    pub fn settings(&self) -> VolumetricSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: VolumetricSettings) {
+        self.settings = settings;
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.scattering = Self::create_scattering(device, width, height);
+    }
+
+    /// Moves the march's dithering on for the next `render`, which marches
+    /// the shadow maps only if `shadows` says the scene has them.
+    pub fn update(&mut self, queue: &wgpu::Queue, shadows: bool) {
+        self.frame = self.frame.wrapping_add(1);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&Self::uniform(self.settings, shadows, self.frame)),
+        );
+    }
+
+    /// Scatters light through the air in front of `depth`, as seen through
+    /// `camera_buffer`, over `hdr`, when enabled.
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr: &wgpu::TextureView,
+        depth: &texture::Texture,
+        camera_buffer: &wgpu::Buffer,
+        shadow_bind_group: &wgpu::BindGroup,
+    ) {
+        if !self.settings.enabled {
+            return;
+        }
+        // The depth target is replaced on resize, so the bind groups are
+        // built per frame.
+        let scatter_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Volumetrics::scatter_bind_group"),
+            layout: &self.scatter_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&depth.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.scattering.view),
+                },
+            ],
+        });
+        let composite_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Volumetrics::composite_bind_group"),
+            layout: &self.composite_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&self.scattering.view),
+            }],
+        });
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Volumetrics::scatter"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.scatter_pipeline);
+            pass.set_bind_group(0, &scatter_bind_group, &[]);
+            pass.set_bind_group(1, shadow_bind_group, &[]);
+            let size = self.scattering.texture.size();
+            pass.dispatch_workgroups(
+                size.width.div_ceil(WORKGROUP_SIZE),
+                size.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+        post::draw_fullscreen(
+            encoder,
+            "Volumetrics::composite",
+            hdr,
+            wgpu::LoadOp::Load,
+            &self.composite_pipeline,
+            &composite_bind_group,
+        );
+    }
+}
@@ -0,0 +1,110 @@
+#include "include/camera.wgsl"
+#define SHADOW_GROUP 1
+#include "include/shadows.wgsl"
+
+// Sunlight scattered towards the eye by the air between it and each pixel,
+// marched through the shadow cascades so shadowed air stays dark and light
+// shafts show between.
+
+struct VolumetricUniform {
+    density: f32,
+    anisotropy: f32,
+    max_distance: f32,
+    intensity: f32,
+    steps: u32,
+    // Zero when the scene is drawn without shadows, whose maps then hold
+    // nothing.
+    shadows: u32,
+    // Varies where along each step the march samples, for TAA to average.
+    frame: u32,
+}
+
+@group(0) @binding(0)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(2)
+var<uniform> volume: VolumetricUniform;
+// In-scattered light in rgb and what share of the scene behind shows
+// through in a.
+@group(0) @binding(3)
+var t_scattering: texture_storage_2d<rgba16float, write>;
+
+const PI: f32 = 3.14159265359;
+
+// Henyey-Greenstein: how much of the light is scattered `cos_theta` off
+// its way, forwards for positive `g`.
+fn phase(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (4.0 * PI * pow(max(1.0 + g2 - 2.0 * g * cos_theta, 1e-4), 1.5));
+}
+
+// Jimenez's interleaved gradient noise, which TAA averages out quickly.
+fn noise(pixel: vec2<f32>) -> f32 {
+    let shifted = pixel + 5.588238 * f32(volume.frame % 64u);
+    return fract(52.9829189 * fract(dot(shifted, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+fn lit(world_position: vec3<f32>) -> f32 {
+    if volume.shadows == 0u {
+        return 1.0;
+    }
+    let view_depth = -(camera.view * vec4<f32>(world_position, 1.0)).z;
+    let cascade = cascade_index(view_depth);
+    if cascade >= CASCADE_COUNT {
+        return 1.0;
+    }
+    return sample_cascade_once(cascade, world_position);
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_scatter(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(t_scattering);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let pixel = vec2<i32>(id.xy);
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    // Depth was rasterized with the TAA jitter, so take it back out.
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0) - camera.jitter;
+    // Sky pixels are marched as far as the air reaches, towards a point
+    // short of the far plane, which unprojects to infinity.
+    let world = camera.inv_view_proj * vec4<f32>(ndc, select(depth, 0.5, is_far_depth(depth)), 1.0);
+    let to_surface = world.xyz / world.w - camera.view_pos.xyz;
+    var reach = volume.max_distance;
+    if !is_far_depth(depth) {
+        reach = min(length(to_surface), reach);
+    }
+    let direction = normalize(to_surface);
+
+    let step_length = reach / f32(volume.steps);
+    let sun_dir = -normalize(shadow.direction);
+    let scattered = phase(dot(direction, sun_dir), volume.anisotropy);
+    let step_transmittance = exp(-volume.density * step_length);
+    var transmittance = 1.0;
+    var light = 0.0;
+    var t = step_length * noise(vec2<f32>(id.xy));
+    for (var i = 0u; i < volume.steps; i += 1u) {
+        let position = camera.view_pos.xyz + direction * t;
+        // What the step scatters in, less what it absorbs before the eye,
+        // integrated over the step rather than taken at its start.
+        light += transmittance * lit(position) * (1.0 - step_transmittance);
+        transmittance *= step_transmittance;
+        t += step_length;
+    }
+    let color = shadow.color * shadow.intensity * volume.intensity * scattered;
+    textureStore(t_scattering, pixel, vec4<f32>(color * light, transmittance));
+}
+
+// The scattering again, sampled for `fs_composite`, whose layout has just
+// this.
+@group(0) @binding(4)
+var t_composite: texture_2d<f32>;
+
+// Blended as color * a + rgb, so the scene dims behind the air it shows
+// through.
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureLoad(t_composite, vec2<i32>(in.clip_position.xy), 0);
+}
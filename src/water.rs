@@ -0,0 +1,641 @@
+use std::f32::consts::TAU;
+
+use wgpu::util::DeviceExt;
+
+use crate::camera::{Camera, CameraUniform};
+use crate::post::taa::Taa;
+use crate::preprocess::{ShaderDefs, ShaderLibrary};
+use crate::{resources, texture};
+
+/// Keeps a sliver of the other side in each pass, so the distorted edges
+/// of the surface do not sample what was clipped away.
+const CLIP_MARGIN: f32 = 0.1;
+
+/// Tuning for a `Water` surface.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WaterSettings {
+    pub height: f32,
+    /// Middle of the surface in X and Z.
+    pub center: [f32; 2],
+    pub size: [f32; 2],
+    /// World units covered by one repeat of the DuDv and normal maps.
+    pub texture_scale: f32,
+    /// How far the ripples push reflections and refractions, in uv units.
+    pub wave_strength: f32,
+    /// Map repeats per second the ripples scroll by.
+    pub wave_speed: f32,
+    /// What deep water fades to.
+    pub color: [f32; 3],
+    /// World units of water the refraction fades through by half.
+    pub clarity: f32,
+    pub specular: f32,
+    pub shininess: f32,
+    /// Size of the reflection and refraction targets relative to the
+    /// output; takes effect on the next resize.
+    pub resolution_scale: f32,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            height: 0.0,
+            center: [0.0, 0.0],
+            size: [100.0, 100.0],
+            texture_scale: 8.0,
+            wave_strength: 0.02,
+            wave_speed: 0.03,
+            color: [0.02, 0.12, 0.16],
+            clarity: 2.0,
+            specular: 0.6,
+            shininess: 64.0,
+            resolution_scale: 0.5,
+        }
+    }
+}
+
+/// The tiling maps rippling the surface: a DuDv map, offsets in 0..1
+/// around 0.5, and a tangent-space normal map of the same waves.
+#[derive(Clone)]
+pub struct WaterTextures {
+    pub dudv: image::DynamicImage,
+    pub normal: image::DynamicImage,
+}
+
+impl WaterTextures {
+    /// A few crossing sine waves, for when no maps are at hand.
+    pub fn procedural(size: u32) -> Self {
+        // Whole wave counts per repeat, so the maps tile.
+        const WAVES: [([f32; 2], f32, f32); 4] = [
+            ([3.0, 1.0], 0.0, 1.0),
+            ([-2.0, 4.0], 1.3, 0.7),
+            ([5.0, -3.0], 2.1, 0.4),
+            ([1.0, 7.0], 4.2, 0.3),
+        ];
+        let slope = |u: f32, v: f32| {
+            WAVES
+                .iter()
+                .fold([0.0f32; 2], |[du, dv], &([ku, kv], phase, amplitude)| {
+                    let c = (TAU * (ku * u + kv * v) + phase).cos() * amplitude;
+                    [du + c * ku, dv + c * kv]
+                })
+        };
+        let max = WAVES
+            .iter()
+            .map(|&([ku, kv], _, amplitude)| (ku.abs() + kv.abs()) * amplitude)
+            .sum::<f32>();
+        let to_unorm = |x: f32| ((x * 0.5 + 0.5).clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        let mut dudv = image::RgbaImage::new(size, size);
+        let mut normal = image::RgbaImage::new(size, size);
+        for y in 0..size {
+            for x in 0..size {
+                let [du, dv] = slope(x as f32 / size as f32, y as f32 / size as f32);
+                let (du, dv) = (du / max, dv / max);
+                dudv.put_pixel(x, y, image::Rgba([to_unorm(du), to_unorm(dv), 0, 255]));
+                let length = (du * du + dv * dv + 1.0).sqrt();
+                normal.put_pixel(
+                    x,
+                    y,
+                    image::Rgba([
+                        to_unorm(-du / length),
+                        to_unorm(-dv / length),
+                        to_unorm(1.0 / length),
+                        255,
+                    ]),
+                );
+            }
+        }
+        Self {
+            dudv: image::DynamicImage::ImageRgba8(dudv),
+            normal: image::DynamicImage::ImageRgba8(normal),
+        }
+    }
+
+    pub async fn load(dudv: &str, normal: &str) -> anyhow::Result<Self> {
+        let dudv = image::load_from_memory(&resources::load_binary(dudv).await?)?;
+        let normal = image::load_from_memory(&resources::load_binary(normal).await?)?;
+        Ok(Self { dudv, normal })
+    }
+}
+
+/// The two offscreen views of the scene the surface is made of.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WaterPass {
+    /// Seen from the camera mirrored under the surface, keeping only what
+    /// is above it.
+    Reflection,
+    /// Seen from the camera itself, keeping only what is under the surface.
+    Refraction,
+}
+
+impl WaterPass {
+    pub const ALL: [Self; 2] = [Self::Reflection, Self::Refraction];
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct WaterUniform {
+    color: [f32; 3],
+    clarity: f32,
+    center: [f32; 2],
+    size: [f32; 2],
+    height: f32,
+    texture_scale: f32,
+    wave_strength: f32,
+    move_factor: f32,
+    specular: f32,
+    shininess: f32,
+    _padding: [f32; 2],
+}
+
+struct Targets {
+    reflection: texture::Texture,
+    reflection_depth: texture::Texture,
+    refraction: texture::Texture,
+    refraction_depth: texture::Texture,
+    // What the scene pipelines write velocity to; never read.
+    velocity: texture::Texture,
+    // White, standing in for SSAO, which only matches the main view.
+    occlusion: texture::Texture,
+}
+
+/// A flat, rectangular water surface.
+///
+/// Every frame the opaque scene is drawn twice more into targets of its
+/// own: reflected in the surface and clipped to what is above it, and as
+/// seen through it and clipped to what is below. The surface then blends
+/// the two by Fresnel, reflecting the environment where the reflection
+/// pass drew nothing.
+pub struct Water {
+    settings: WaterSettings,
+    targets: Targets,
+    move_factor: f32,
+    cameras: [(CameraUniform, wgpu::Buffer); 2],
+    // Built by the owner of the scene's camera bind group layout, from
+    // `camera_buffer` and `occlusion`.
+    camera_bind_groups: Option<[wgpu::BindGroup; 2]>,
+    uniform_buffer: wgpu::Buffer,
+    dudv: texture::Texture,
+    normal: texture::Texture,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    reverse_z: bool,
+    pipeline: wgpu::RenderPipeline,
+    // `pipeline` for a reversed depth buffer.
+    reverse_z_pipeline: wgpu::RenderPipeline,
+}
+
+impl Water {
+    /// `scene_bind_group_layouts` are the camera, light and shadow groups
+    /// of the scene shaders; `width` and `height` the output's.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shaders: &ShaderLibrary,
+        textures: &WaterTextures,
+        settings: WaterSettings,
+        scene_bind_group_layouts: [&wgpu::BindGroupLayout; 3],
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let targets = Self::create_targets(device, queue, &settings, color_format, width, height);
+        let cameras = WaterPass::ALL.map(|pass| {
+            let uniform = CameraUniform::new();
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(match pass {
+                    WaterPass::Reflection => "Water::reflection_camera",
+                    WaterPass::Refraction => "Water::refraction_camera",
+                }),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+            (uniform, buffer)
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water::uniform"),
+            contents: bytemuck::cast_slice(&[Self::uniform(&settings, 0.0)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let dudv = texture::Texture::linear_from_image(
+            device,
+            queue,
+            &textures.dudv,
+            Some("Water::dudv"),
+        )?;
+        let normal = texture::Texture::normal_from_image(
+            device,
+            queue,
+            &textures.normal,
+            Some("Water::normal"),
+        )?;
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty,
+            count: None,
+        };
+        let color = wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        };
+        let sampler = wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering);
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Water::layout"),
+            entries: &[
+                entry(
+                    0,
+                    wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                ),
+                entry(1, color),
+                entry(2, color),
+                entry(
+                    3,
+                    wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                ),
+                entry(4, sampler),
+                entry(5, color),
+                entry(6, color),
+                entry(7, sampler),
+            ],
+        });
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniform_buffer,
+            &targets,
+            &dudv,
+            &normal,
+        );
+
+        let [camera_layout, light_layout, shadow_layout] = scene_bind_group_layouts;
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Water::pipeline_layout"),
+            bind_group_layouts: &[
+                &bind_group_layout,
+                camera_layout,
+                light_layout,
+                shadow_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let [pipeline, reverse_z_pipeline] =
+            Self::create_pipelines(device, shaders, &pipeline_layout, color_format)?;
+
+        Ok(Self {
+            settings,
+            targets,
+            move_factor: 0.0,
+            cameras,
+            camera_bind_groups: None,
+            uniform_buffer,
+            dudv,
+            normal,
+            bind_group_layout,
+            bind_group,
+            pipeline_layout,
+            color_format,
+            reverse_z: false,
+            pipeline,
+            reverse_z_pipeline,
+        })
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+        pipeline_layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<[wgpu::RenderPipeline; 2]> {
+        let shader =
+            shaders.create_module(device, "Water::shader", "water.wgsl", &ShaderDefs::new())?;
+        let create_pipeline = |label, depth_compare| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[
+                        Some(wgpu::ColorTargetState {
+                            format: color_format,
+                            blend: Some(wgpu::BlendState::REPLACE),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: Taa::VELOCITY_FORMAT,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL,
+                        }),
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                // Seen from below as well as above.
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        Ok([
+            create_pipeline("Water::pipeline", wgpu::CompareFunction::LessEqual),
+            create_pipeline(
+                "Water::reverse_z_pipeline",
+                wgpu::CompareFunction::GreaterEqual,
+            ),
+        ])
+    }
+
+    /// Compiles the pipelines again from `shaders`' current sources.
+    pub(crate) fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+    ) -> anyhow::Result<()> {
+        [self.pipeline, self.reverse_z_pipeline] =
+            Self::create_pipelines(device, shaders, &self.pipeline_layout, self.color_format)?;
+        Ok(())
+    }
+
+    fn uniform(settings: &WaterSettings, move_factor: f32) -> WaterUniform {
+        WaterUniform {
+            color: settings.color,
+            clarity: settings.clarity.max(1e-3),
+            center: settings.center,
+            size: settings.size,
+            height: settings.height,
+            texture_scale: settings.texture_scale,
+            wave_strength: settings.wave_strength,
+            move_factor,
+            specular: settings.specular,
+            shininess: settings.shininess,
+            _padding: [0.0; 2],
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: &WaterSettings,
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Targets {
+        let scale = settings.resolution_scale.clamp(0.05, 1.0);
+        let width = ((width as f32 * scale) as u32).max(1);
+        let height = ((height as f32 * scale) as u32).max(1);
+        let color = |label| {
+            texture::Texture::create_render_target(device, width, height, color_format, label)
+        };
+        let depth = |label| texture::Texture::create_depth_target(device, width, height, label);
+        let occlusion = texture::Texture::create_render_target(
+            device,
+            width,
+            height,
+            wgpu::TextureFormat::R8Unorm,
+            "Water::occlusion",
+        );
+        queue.write_texture(
+            occlusion.texture.as_image_copy(),
+            &vec![u8::MAX; (width * height) as usize],
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(width),
+                rows_per_image: Some(height),
+            },
+            occlusion.texture.size(),
+        );
+        Targets {
+            reflection: color("Water::reflection"),
+            reflection_depth: depth("Water::reflection_depth"),
+            refraction: color("Water::refraction"),
+            refraction_depth: depth("Water::refraction_depth"),
+            velocity: texture::Texture::create_render_target(
+                device,
+                width,
+                height,
+                Taa::VELOCITY_FORMAT,
+                "Water::velocity",
+            ),
+            occlusion,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        targets: &Targets,
+        dudv: &texture::Texture,
+        normal: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Water::bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&targets.reflection.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&targets.refraction.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&targets.refraction_depth.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&targets.reflection.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&dudv.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
+                },
+                // Repeating, so the maps tile across the surface.
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Sampler(&dudv.sampler),
+                },
+            ],
+        })
+    }
+
+    pub fn settings(&self) -> WaterSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: WaterSettings) {
+        self.settings = settings;
+    }
+
+    /// Recreates the pass targets for an output of `width` by `height`.
+    /// The camera bind groups refer to the old ones and need replacing.
+    pub fn resize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        let color_format = self.targets.reflection.texture.format();
+        self.targets =
+            Self::create_targets(device, queue, &self.settings, color_format, width, height);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            &self.targets,
+            &self.dudv,
+            &self.normal,
+        );
+        self.camera_bind_groups = None;
+    }
+
+    /// Clears and tests depth as for a camera with `reverse_z`.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+    }
+
+    /// The `CameraUniform` `pass` is drawn with, for its camera bind group.
+    pub fn camera_buffer(&self, pass: WaterPass) -> &wgpu::Buffer {
+        &self.cameras[pass as usize].1
+    }
+
+    /// Stands in for the SSAO texture in the passes' camera bind groups.
+    pub fn occlusion(&self) -> &texture::Texture {
+        &self.targets.occlusion
+    }
+
+    /// The scene's camera bind group layout, bound to `camera_buffer` and
+    /// `occlusion`, for the reflection and refraction passes.
+    pub fn set_camera_bind_groups(&mut self, bind_groups: [wgpu::BindGroup; 2]) {
+        self.camera_bind_groups = Some(bind_groups);
+    }
+
+    pub fn camera_bind_group(&self, pass: WaterPass) -> Option<&wgpu::BindGroup> {
+        Some(&self.camera_bind_groups.as_ref()?[pass as usize])
+    }
+
+    /// Follows `camera` with the pass cameras and scrolls the ripples by
+    /// `dt` seconds.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, dt: f32) {
+        let height = self.settings.height;
+        let mirror = |point: cgmath::Point3<f32>| {
+            cgmath::Point3::new(point.x, 2.0 * height - point.y, point.z)
+        };
+        let reflected = Camera {
+            eye: mirror(camera.eye),
+            target: mirror(camera.target),
+            ..*camera
+        };
+        let passes = [
+            (reflected, [0.0, 1.0, 0.0, CLIP_MARGIN - height]),
+            (*camera, [0.0, -1.0, 0.0, height + CLIP_MARGIN]),
+        ];
+        for ((uniform, buffer), (camera, clip_plane)) in self.cameras.iter_mut().zip(passes) {
+            uniform.update_view_proj(&camera);
+            uniform.clip_plane = clip_plane;
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[*uniform]));
+        }
+
+        self.move_factor = (self.move_factor + self.settings.wave_speed * dt).fract();
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[Self::uniform(&self.settings, self.move_factor)]),
+        );
+    }
+
+    /// A pass drawing the opaque scene into `pass`'s targets, over a clear
+    /// color with zero alpha so the surface can tell where nothing was.
+    pub fn begin_pass<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        pass: WaterPass,
+    ) -> wgpu::RenderPass<'e> {
+        let (color, depth) = match pass {
+            WaterPass::Reflection => (&self.targets.reflection, &self.targets.reflection_depth),
+            WaterPass::Refraction => (&self.targets.refraction, &self.targets.refraction_depth),
+        };
+        let attachment = |view, clear| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(match pass {
+                WaterPass::Reflection => "Water::reflection",
+                WaterPass::Refraction => "Water::refraction",
+            }),
+            color_attachments: &[
+                attachment(&color.view, wgpu::Color::TRANSPARENT),
+                attachment(&self.targets.velocity.view, wgpu::Color::TRANSPARENT),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(texture::Texture::far_depth(self.reverse_z)),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    /// Draws the surface into a pass over the HDR, velocity and depth
+    /// targets, after the passes of this frame.
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        scene_bind_groups: [&'a wgpu::BindGroup; 3],
+    ) {
+        render_pass.set_pipeline(if self.reverse_z {
+            &self.reverse_z_pipeline
+        } else {
+            &self.pipeline
+        });
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        for (i, bind_group) in scene_bind_groups.into_iter().enumerate() {
+            render_pass.set_bind_group(i as u32 + 1, bind_group, &[]);
+        }
+        render_pass.draw(0..6, 0..1);
+    }
+}
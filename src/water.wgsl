@@ -0,0 +1,121 @@
+// Water surface for water.rs: the reflection and refraction passes blended
+// by Fresnel, rippled by a scrolling DuDv map.
+#include "lighting.wgsl"
+
+// Matches `WaterUniform` in water.rs.
+struct WaterUniform {
+    color: vec3<f32>,
+    clarity: f32,
+    center: vec2<f32>,
+    size: vec2<f32>,
+    height: f32,
+    texture_scale: f32,
+    wave_strength: f32,
+    move_factor: f32,
+    specular: f32,
+    shininess: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> water: WaterUniform;
+@group(0) @binding(1)
+var t_reflection: texture_2d<f32>;
+@group(0) @binding(2)
+var t_refraction: texture_2d<f32>;
+@group(0) @binding(3)
+var t_refraction_depth: texture_2d<f32>;
+@group(0) @binding(4)
+var s_target: sampler;
+@group(0) @binding(5)
+var t_dudv: texture_2d<f32>;
+@group(0) @binding(6)
+var t_water_normal: texture_2d<f32>;
+@group(0) @binding(7)
+var s_water: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    // Without the TAA jitter, as the reflection and refraction were drawn.
+    @location(1) clip: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let xz = water.center + (corners[index] - 0.5) * water.size;
+    let world_position = vec4<f32>(xz.x, water.height, xz.y, 1.0);
+
+    var out: VertexOutput;
+    out.world_position = world_position.xyz;
+    out.clip = camera.view_proj * world_position;
+    out.clip_position = vec4<f32>(out.clip.xy + camera.jitter * out.clip.w, out.clip.zw);
+    return out;
+}
+
+// As in shader.wgsl.
+fn velocity(world_position: vec3<f32>) -> vec2<f32> {
+    let current = camera.view_proj * vec4<f32>(world_position, 1.0);
+    let previous = camera.prev_view_proj * vec4<f32>(world_position, 1.0);
+    return (current.xy / current.w - previous.xy / previous.w) * vec2<f32>(0.5, -0.5);
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let ndc = in.clip.xy / in.clip.w;
+    let refraction_uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    // The mirrored camera sees the reflection upside down.
+    let reflection_uv = vec2<f32>(refraction_uv.x, 1.0 - refraction_uv.y);
+
+    // How much water the view ray crosses before the refracted scene.
+    let size = vec2<f32>(textureDimensions(t_refraction_depth));
+    let texel = clamp(vec2<i32>(refraction_uv * size), vec2<i32>(0), vec2<i32>(size) - 1);
+    let depth = textureLoad(t_refraction_depth, texel, 0).r;
+    let behind = camera.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    let thickness = distance(behind.xyz / behind.w, in.world_position);
+    // Shallow water ripples less, hiding the edges of the distortion.
+    let shore = saturate(thickness);
+
+    let uv = in.world_position.xz / water.texture_scale;
+    var distorted = textureSample(t_dudv, s_water, vec2<f32>(uv.x + water.move_factor, uv.y)).rg * 0.1;
+    distorted = uv + vec2<f32>(distorted.x, distorted.y + water.move_factor);
+    let distortion = (textureSample(t_dudv, s_water, distorted).rg * 2.0 - 1.0) * water.wave_strength * shore;
+
+    let map = textureSample(t_water_normal, s_water, distorted);
+    // Flattened towards +Y, so the ripples stay gentle.
+    let normal = normalize(vec3<f32>(map.r * 2.0 - 1.0, map.b * 3.0, map.g * 2.0 - 1.0));
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+
+    var reflection = textureSample(t_reflection, s_target, clamp(reflection_uv + distortion, vec2<f32>(0.001), vec2<f32>(0.999)));
+    // Nothing was drawn there; the sky shows instead.
+    if reflection.a < 0.5 {
+        let direction = reflect(-view_dir, normal);
+        reflection = vec4<f32>(textureSampleLevel(t_prefiltered, s_environment, direction, 0.0).rgb, 1.0);
+    }
+    let refraction = textureSample(t_refraction, s_target, clamp(refraction_uv + distortion, vec2<f32>(0.001), vec2<f32>(0.999)));
+    let murk = 1.0 - exp2(-thickness / water.clarity);
+    let below = mix(refraction.rgb, water.color, murk);
+
+    // Schlick's approximation with water's 2% reflectance head-on.
+    let fresnel = 0.02 + 0.98 * pow(1.0 - saturate(dot(view_dir, normal)), 5.0);
+    let sun = reflect(normalize(shadow.direction), normal);
+    let highlight = pow(saturate(dot(sun, view_dir)), water.shininess) * water.specular * shore;
+
+    var out: FragmentOutput;
+    let lit = mix(below, reflection.rgb, fresnel) + shadow.color * shadow.intensity * highlight;
+    out.color = vec4<f32>(apply_fog(lit, in.world_position), 1.0);
+    out.velocity = velocity(in.world_position);
+    return out;
+}
@@ -0,0 +1,175 @@
+//! Renders fixed scenes headlessly and compares them with the PNGs in
+//! `tests/golden`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to write new references after an intended
+//! change. On a mismatch the actual image and a difference image are
+//! written to `target/golden` next to the report. Machines without any
+//! usable adapter skip the comparison. Native only, as `State::headless`
+//! is blocked on with `pollster`.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use wgpu_test::light::LightUniform;
+use wgpu_test::{RenderPath, State};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 192;
+
+/// Channel difference, out of 255, below which a pixel counts as matching.
+/// Leaves room for rounding differences between drivers.
+const PIXEL_TOLERANCE: u8 = 24;
+/// Share of pixels allowed to exceed `PIXEL_TOLERANCE`.
+const MAX_MISMATCHED: f64 = 0.005;
+
+// Devices are created one at a time; several GL contexts at once are not
+// reliable on software drivers.
+static GPU: Mutex<()> = Mutex::new(());
+
+struct Comparison {
+    mismatched: f64,
+    mean_error: f64,
+    diff: image::RgbaImage,
+}
+
+fn compare(expected: &image::RgbaImage, actual: &image::RgbaImage) -> Comparison {
+    let mut diff = image::RgbaImage::new(actual.width(), actual.height());
+    let mut mismatched = 0usize;
+    let mut total_error = 0u64;
+    for ((a, b), out) in expected
+        .pixels()
+        .zip(actual.pixels())
+        .zip(diff.pixels_mut())
+    {
+        let error = (0..3).map(|i| a[i].abs_diff(b[i])).max().unwrap_or(0);
+        total_error += error as u64;
+        if error > PIXEL_TOLERANCE {
+            mismatched += 1;
+            *out = image::Rgba([255, 0, 255, 255]);
+        } else {
+            // Matching pixels are kept as a faint copy for orientation.
+            let gray = (b[0] as u16 + b[1] as u16 + b[2] as u16) / 12;
+            *out = image::Rgba([gray as u8, gray as u8, gray as u8, 255]);
+        }
+    }
+    let pixels = (actual.width() * actual.height()).max(1) as f64;
+    Comparison {
+        mismatched: mismatched as f64 / pixels,
+        mean_error: total_error as f64 / pixels,
+        diff,
+    }
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.png", name))
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("target")
+        .join("golden")
+}
+
+/// Renders a scene set up by `setup` and checks it against `<name>.png`.
+fn check(name: &str, render_path: RenderPath, setup: impl FnOnce(&mut State)) {
+    let _gpu = GPU.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut state = match pollster::block_on(State::headless(WIDTH, HEIGHT, render_path)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("skipping golden test {}: no adapter ({})", name, e);
+            return;
+        }
+    };
+    // A fixed view, so moving the default camera does not invalidate
+    // every reference.
+    {
+        let camera = state.camera_mut();
+        camera.eye = (0.0, 12.0, 25.0).into();
+        camera.target = (0.0, 0.0, 0.0).into();
+    }
+    setup(&mut state);
+    state.update();
+    let actual = state.render_to_image().expect("headless render");
+
+    let path = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        actual.save(&path).unwrap();
+        return;
+    }
+    let expected = match image::open(&path) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => panic!(
+            "missing golden image {} ({}); run with UPDATE_GOLDEN=1 to create it",
+            path.display(),
+            e
+        ),
+    };
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "{}: golden image has a different size",
+        name
+    );
+
+    let comparison = compare(&expected, &actual);
+    if comparison.mismatched > MAX_MISMATCHED {
+        let dir = output_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let actual_path = dir.join(format!("{}-actual.png", name));
+        let diff_path = dir.join(format!("{}-diff.png", name));
+        actual.save(&actual_path).unwrap();
+        comparison.diff.save(&diff_path).unwrap();
+        panic!(
+            "{}: {:.2}% of pixels differ by more than {} (mean error {:.2}); \
+             see {} and {}",
+            name,
+            comparison.mismatched * 100.0,
+            PIXEL_TOLERANCE,
+            comparison.mean_error,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+#[test]
+fn forward_default_scene() {
+    check("forward", RenderPath::Forward, |_| {});
+}
+
+#[test]
+fn deferred_default_scene() {
+    check("deferred", RenderPath::Deferred, |_| {});
+}
+
+#[test]
+fn forward_plus_point_lights() {
+    check("forward_plus_lights", RenderPath::ForwardPlus, |state| {
+        let colors = [[1.0, 0.2, 0.1], [0.1, 1.0, 0.2], [0.2, 0.3, 1.0]];
+        for (i, color) in colors.into_iter().enumerate() {
+            let x = (i as f32 - 1.0) * 8.0;
+            state.add_light(LightUniform::new([x, 2.0, 4.0], color, 20.0));
+        }
+    });
+}
+
+#[test]
+fn effects_disabled() {
+    check("no_effects", RenderPath::Forward, |state| {
+        let mut taa = state.taa_settings();
+        taa.enabled = false;
+        state.set_taa_settings(taa);
+        let mut bloom = state.bloom_settings();
+        bloom.intensity = 0.0;
+        state.set_bloom_settings(bloom);
+        let mut ssao = state.ssao_settings();
+        ssao.enabled = false;
+        state.set_ssao_settings(ssao);
+    });
+}
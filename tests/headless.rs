@@ -0,0 +1,92 @@
+//! Drives `State::headless` through what a loader does before the first
+//! frame. Machines without any usable adapter skip these. Native only, as
+//! `State::headless` is blocked on with `pollster`.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use wgpu_test::msaa::Msaa;
+use wgpu_test::post::DepthOfField;
+use wgpu_test::probe::ReflectionProbe;
+use wgpu_test::shadow::ShadowFilter;
+use wgpu_test::{RenderPath, State};
+
+fn headless() -> Option<State> {
+    match pollster::block_on(State::headless(256, 192, RenderPath::Forward)) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            eprintln!("skipping: no adapter ({})", e);
+            None
+        }
+    }
+}
+
+#[test]
+fn reflection_probe_captures_before_the_first_update() {
+    let Some(mut state) = headless() else {
+        return;
+    };
+    state
+        .add_reflection_probe(ReflectionProbe::new((0.0, 2.0, 0.0).into(), 10.0))
+        .unwrap();
+    state.update();
+    state.render_to_image().unwrap();
+}
+
+#[test]
+fn depth_of_field_blurs_without_validation_errors() {
+    let Some(mut state) = headless() else {
+        return;
+    };
+    let dof = state.post_effect_mut::<DepthOfField>().unwrap();
+    dof.enabled = true;
+    dof.settings.focus_distance = 1.0;
+    state.update();
+    state.render_to_image().unwrap();
+}
+
+#[test]
+fn variance_shadows_prefilter_without_validation_errors() {
+    let Some(mut state) = headless() else {
+        return;
+    };
+    // Set before the first frame, as loading a saved setting would.
+    state.set_shadow_filter(ShadowFilter::Variance);
+    state.update();
+    let variance = state.render_to_image().unwrap();
+    state.set_shadow_filter(ShadowFilter::Pcf);
+    state.update();
+    let pcf = state.render_to_image().unwrap();
+    // Only the shadows' edges differ; a frame lost to an invalid pass
+    // doesn't come close.
+    let difference = mean(&variance).abs_diff(mean(&pcf));
+    assert!(difference < 8, "frames differ by {} on average", difference);
+}
+
+#[test]
+fn msaa_resolves_without_validation_errors() {
+    let Some(mut state) = headless() else {
+        return;
+    };
+    if state.adapter_info().backend == wgpu::Backend::Gl {
+        assert!(state.set_msaa_samples(Msaa::SAMPLE_COUNT).is_err());
+        return;
+    }
+    state.update();
+    let single = state.render_to_image().unwrap();
+    state.set_msaa_samples(Msaa::SAMPLE_COUNT).unwrap();
+    state.set_reverse_z(true);
+    state.update();
+    let multisampled = state.render_to_image().unwrap();
+    // Only the edges differ, as with the shadow filters above.
+    let difference = mean(&single).abs_diff(mean(&multisampled));
+    assert!(difference < 8, "frames differ by {} on average", difference);
+}
+
+fn mean(image: &image::RgbaImage) -> u8 {
+    let sum: u64 = image
+        .pixels()
+        .flat_map(|p| &p.0[..3])
+        .map(|&c| c as u64)
+        .sum();
+    (sum / (image.width() as u64 * image.height() as u64 * 3)) as u8
+}